@@ -1,7 +0,0 @@
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::configure()
-        .build_server(false)
-        .build_client(true)
-        .compile_protos(&["../proto/murmure.proto"], &["../proto"])?;
-    Ok(())
-}
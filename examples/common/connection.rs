@@ -0,0 +1,233 @@
+//! Connection flags and error messages shared by the example clients
+//!
+//! tonic's defaults never give up: a connect to a firewalled host hangs until
+//! the OS does (minutes), and a request waits forever for a stuck server. The
+//! examples set a connect timeout, a per-request deadline and keepalive, and
+//! turn the resulting errors into something that says what to check.
+
+use murmure_client::{ClientError, ClientOptions, MurmureClient};
+use std::error::Error;
+use std::io;
+use std::time::Duration;
+use tonic::Code;
+
+/// Where and how to connect.
+pub struct Connection {
+    pub server: String,
+    /// Time allowed to establish the connection
+    pub connect_timeout: Duration,
+    /// Deadline of one transcription (`None` = no deadline)
+    pub timeout: Option<Duration>,
+    /// Keepalive interval on an idle connection (`None` = off)
+    pub keepalive: Option<Duration>,
+}
+
+impl Connection {
+    /// Read `--server`, `--connect-timeout` (default 5 s), `--timeout`
+    /// (default 120 s) and `--keepalive-secs` (default 30 s). `0` turns the
+    /// last two off.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let value = |flag: &str| {
+            args.iter()
+                .position(|a| a == flag)
+                .and_then(|i| args.get(i + 1))
+        };
+        let seconds = |flag: &str, default: f32| -> Result<f32, String> {
+            match value(flag) {
+                Some(secs) => match secs.parse::<f32>() {
+                    Ok(secs) if secs >= 0.0 && secs.is_finite() => Ok(secs),
+                    _ => Err(format!("Invalid {} '{}'", flag, secs)),
+                },
+                None => Ok(default),
+            }
+        };
+        let optional = |secs: f32| (secs > 0.0).then(|| Duration::from_secs_f32(secs));
+
+        let connect_timeout = seconds("--connect-timeout", 5.0)?;
+        if connect_timeout == 0.0 {
+            return Err("--connect-timeout must be greater than 0".to_string());
+        }
+        Ok(Self {
+            server: value("--server")
+                .cloned()
+                .unwrap_or_else(|| "http://localhost:50051".to_string()),
+            connect_timeout: Duration::from_secs_f32(connect_timeout),
+            timeout: optional(seconds("--timeout", 120.0)?),
+            keepalive: optional(seconds("--keepalive-secs", 30.0)?),
+        })
+    }
+
+    /// Client options with these settings, `--timeout` as the deadline of
+    /// every request.
+    pub fn options(&self) -> ClientOptions {
+        ClientOptions {
+            connect_timeout: self.connect_timeout,
+            request_timeout: self.timeout,
+            keepalive: self.keepalive,
+            ..ClientOptions::new(self.server.clone())
+        }
+    }
+
+    /// Connect with `options`, explaining a failure.
+    pub async fn connect(&self, options: ClientOptions) -> Result<MurmureClient, String> {
+        MurmureClient::connect(options)
+            .await
+            .map_err(|e| self.explain(&e))
+    }
+
+    /// Say what went wrong and what to check: nothing listening, no answer
+    /// at all, or a server that took longer than `--timeout`.
+    pub fn explain(&self, error: &ClientError) -> String {
+        match error {
+            ClientError::Status(status) if is_deadline(status) => format!(
+                "Deadline exceeded during transcription: no result within {}. Raise --timeout (0 = no deadline) for long audio or a busy server.",
+                describe(self.timeout)
+            ),
+            _ => match io_error_kind(error) {
+                Some(io::ErrorKind::ConnectionRefused) => format!(
+                    "Connection refused by {}: is murmure-server running and listening on that port?",
+                    self.server
+                ),
+                Some(io::ErrorKind::TimedOut) => format!(
+                    "Timed out connecting to {} after {}: check the address and that no firewall drops the traffic (--connect-timeout).",
+                    self.server,
+                    describe(Some(self.connect_timeout))
+                ),
+                _ => error.to_string(),
+            },
+        }
+    }
+}
+
+/// Whether a request failed on its deadline. tonic reports its own timer as
+/// `CANCELLED` "Timeout expired"; the server reports `DEADLINE_EXCEEDED`.
+fn is_deadline(status: &tonic::Status) -> bool {
+    status.code() == Code::DeadlineExceeded
+        || (status.code() == Code::Cancelled && status.message().contains("Timeout expired"))
+}
+
+/// Kind of the I/O error at the root of a connection failure, if any.
+fn io_error_kind(error: &ClientError) -> Option<io::ErrorKind> {
+    let mut source: Option<&(dyn Error + 'static)> = Some(error);
+    while let Some(error) = source {
+        if let Some(io_error) = error.downcast_ref::<io::Error>() {
+            return Some(io_error.kind());
+        }
+        source = error.source();
+    }
+    None
+}
+
+fn describe(duration: Option<Duration>) -> String {
+    match duration {
+        Some(duration) => format!("{:.0}s", duration.as_secs_f32()),
+        None => "the time allowed".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn defaults_and_flags() {
+        let connection = Connection::from_args(&[]).unwrap();
+        assert_eq!(connection.server, "http://localhost:50051");
+        assert_eq!(connection.connect_timeout, Duration::from_secs(5));
+        assert_eq!(connection.timeout, Some(Duration::from_secs(120)));
+        assert_eq!(connection.keepalive, Some(Duration::from_secs(30)));
+
+        let connection = Connection::from_args(&args(
+            "--server http://host:1 --connect-timeout 0.5 --timeout 0 --keepalive-secs 0",
+        ))
+        .unwrap();
+        assert_eq!(connection.server, "http://host:1");
+        assert_eq!(connection.connect_timeout, Duration::from_millis(500));
+        assert_eq!(connection.timeout, None);
+        assert_eq!(connection.keepalive, None);
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        for line in [
+            "--timeout soon",
+            "--timeout -1",
+            "--keepalive-secs inf",
+            "--connect-timeout 0",
+        ] {
+            assert!(Connection::from_args(&args(line)).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn explains_deadlines() {
+        let connection = Connection::from_args(&args("--timeout 3")).unwrap();
+        for status in [
+            tonic::Status::deadline_exceeded("too slow"),
+            tonic::Status::cancelled("Timeout expired"),
+        ] {
+            let message = connection.explain(&status.into());
+            assert!(message.starts_with("Deadline exceeded"), "{}", message);
+            assert!(message.contains("3s"), "{}", message);
+        }
+        let message = connection.explain(&tonic::Status::cancelled("bye").into());
+        assert!(!message.starts_with("Deadline exceeded"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn explains_refused_connections() {
+        // A port nothing listens on anymore
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let connection =
+            Connection::from_args(&args(&format!("--server http://{}", addr))).unwrap();
+        let error = connection
+            .connect(connection.options())
+            .await
+            .err()
+            .unwrap();
+        assert!(error.starts_with("Connection refused"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_unreachable_hosts_in_time() {
+        // A listener that never accepts, its backlog filled: further SYNs go
+        // unanswered, as with a firewall that drops them
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(0).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut backlog = Vec::new();
+        while let Ok(Ok(stream)) = tokio::time::timeout(
+            Duration::from_millis(100),
+            tokio::net::TcpStream::connect(addr),
+        )
+        .await
+        {
+            backlog.push(stream);
+        }
+
+        let connection = Connection::from_args(&args(&format!(
+            "--server http://{} --connect-timeout 0.5",
+            addr
+        )))
+        .unwrap();
+        let started = Instant::now();
+        let error = connection
+            .connect(connection.options())
+            .await
+            .err()
+            .unwrap();
+        let elapsed = started.elapsed();
+
+        assert!(elapsed < Duration::from_secs(2), "took {:?}", elapsed);
+        assert!(error.starts_with("Timed out connecting"), "{}", error);
+    }
+}
@@ -0,0 +1,16 @@
+//! Engine metadata attached by the server to transcription responses
+
+use tonic::metadata::{KeyAndValueRef, MetadataMap};
+
+/// Print the `x-murmure-*` response metadata: server version, engine, model
+/// name and modification time, and dictionary revision.
+pub fn print_engine_metadata(metadata: &MetadataMap) {
+    println!("ℹ️  Server metadata:");
+    for entry in metadata.iter() {
+        if let KeyAndValueRef::Ascii(key, value) = entry {
+            if let Some(name) = key.as_str().strip_prefix("x-murmure-") {
+                println!("   {}: {}", name, value.to_str().unwrap_or("?"));
+            }
+        }
+    }
+}
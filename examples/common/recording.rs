@@ -0,0 +1,107 @@
+//! WAV recording shared by the microphone examples
+//!
+//! The cpal callback thread and the recording code both need the WAV writer.
+//! It is kept in an `Option` behind the mutex so the recording code can take it
+//! out and finalize it as soon as the stream is dropped, however long the
+//! callback thread takes to let go of its handle.
+
+use hound::{WavSpec, WavWriter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// WAV writer shared between the audio callback and the recording code.
+#[derive(Clone)]
+pub struct SharedWavWriter {
+    inner: Arc<Mutex<Option<WavWriter<BufWriter<File>>>>>,
+}
+
+impl SharedWavWriter {
+    pub fn create(path: &Path, spec: WavSpec) -> hound::Result<Self> {
+        let writer = WavWriter::new(BufWriter::new(File::create(path)?), spec)?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(Some(writer))),
+        })
+    }
+
+    /// Append samples; they are dropped once the file has been finalized.
+    pub fn write_samples(&self, samples: impl IntoIterator<Item = i16>) {
+        if let Some(writer) = self.inner.lock().unwrap().as_mut() {
+            for sample in samples {
+                let _ = writer.write_sample(sample);
+            }
+        }
+    }
+
+    /// Take the writer back and finalize the file.
+    ///
+    /// Call after dropping the stream. The lock waits for a callback that is
+    /// still writing, and callbacks that run later find the writer gone.
+    pub fn finalize(&self) -> hound::Result<()> {
+        match self.inner.lock().unwrap().take() {
+            Some(writer) => writer.finalize(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    const BLOCK: usize = 160;
+
+    fn spec() -> WavSpec {
+        WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        }
+    }
+
+    /// Start and stop recordings in a loop, with a callback thread that keeps
+    /// writing after the stream is stopped as cpal's may.
+    #[test]
+    fn finalizes_while_the_callback_still_writes() {
+        let dir = std::env::temp_dir().join(format!("murmure-recording-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for round in 0..200 {
+            let path = dir.join(format!("{}.wav", round));
+            let writer = SharedWavWriter::create(&path, spec()).unwrap();
+            let stopped = Arc::new(AtomicBool::new(false));
+            let callback = thread::spawn({
+                let writer = writer.clone();
+                let stopped = stopped.clone();
+                move || loop {
+                    let last = stopped.load(Ordering::SeqCst);
+                    writer.write_samples(std::iter::repeat(1000).take(BLOCK));
+                    if last {
+                        break;
+                    }
+                    thread::yield_now();
+                }
+            });
+
+            thread::sleep(Duration::from_micros(round % 7 * 100));
+            stopped.store(true, Ordering::SeqCst);
+            // No fixed sleep: the writer is taken back right away
+            writer.finalize().unwrap();
+            callback.join().unwrap();
+            assert!(writer.finalize().is_ok(), "finalizing twice is harmless");
+
+            let reader = hound::WavReader::open(&path).unwrap();
+            assert_eq!(reader.len() as usize % BLOCK, 0, "round {}", round);
+            assert!(reader
+                .into_samples::<i16>()
+                .all(|sample| sample.unwrap() == 1000));
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
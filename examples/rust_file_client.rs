@@ -22,35 +22,73 @@
 //!
 //! # Try streaming mode
 //! cargo run --example rust_file_client -- audio.wav --stream
+//!
+//! # Upload a long recording in chunks without loading it into memory
+//! cargo run --example rust_file_client -- meeting.wav --large
+//!
+//! # Write SubRip subtitles to meeting.srt
+//! cargo run --example rust_file_client -- meeting.wav --format srt
+//!
+//! # Interactive session: transcribe the same file again and again
+//! cargo run --example rust_file_client -- --repl
 //! ```
 //!
 //! Options:
-//! - Audio file path (required)
+//! - Audio file path (required, except with `--repl`)
 //! - `--server <address>` - Server address (default: http://localhost:50051)
+//! - `--connect-timeout <secs>` - Give up connecting after this long (default: 5)
+//! - `--timeout <secs>` - Deadline of each transcription, 0 = none (default: 120)
+//! - `--keepalive-secs <secs>` - Keepalive ping interval, 0 = off (default: 30)
 //! - `--no-dictionary` - Disable dictionary corrections
 //! - `--stream` - Use streaming RPC instead of file-based
+//! - `--segment` - Split the transcript at long pauses (file-based mode)
+//! - `--show-corrections` - Print the words replaced by the dictionary (file-based mode)
+//! - `--large` - Upload the file in chunks with TranscribeLargeFile and show upload progress
+//! - `--verbose` - Print the server version, model and dictionary revision sent with the response
+//! - `--api-key <key>` - Sent as `x-api-key` metadata (selects a rate limit override)
+//! - `--model <name>` - Model to transcribe with (file-based and large modes; default: server default)
+//! - `--format <text|json|srt|vtt>` - Output format (file-based mode). JSON and subtitles
+//!   are written next to the audio file, e.g. `audio.srt`
+//! - `--repl` - Keep the connection open and read commands at a prompt:
+//!   `load <path>`, `transcribe [--no-dictionary] [--segment] [--model <name>]`,
+//!   `info` and `quit`. The audio file, if given, is loaded up front. Ctrl+C
+//!   cancels the running transcription instead of exiting.
 
-use std::path::PathBuf;
+use murmure_client::proto as murmure;
+use murmure_client::{ClientError, ClientOptions, MurmureClient, TranscriptionResult};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::Request;
 
-// Include generated proto code from build script
-pub mod murmure {
-    include!(concat!(env!("OUT_DIR"), "/murmure.rs"));
-}
+#[path = "common/connection.rs"]
+mod connection;
+#[path = "common/metadata.rs"]
+mod metadata;
 
-use murmure::transcription_service_client::TranscriptionServiceClient;
-use murmure::{TranscribeFileRequest, TranscribeStreamRequest};
+use connection::Connection;
+
+use murmure::{
+    GetUploadProgressRequest, OutputFormat, StageTimings, StreamConfig, TranscribeFileRequest,
+    TranscribeLargeFileRequest, UploadStart,
+};
+
+/// Chunk size for TranscribeLargeFile uploads
+const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
+    let use_repl = args.contains(&"--repl".to_string());
 
-    if args.len() < 2 {
+    if args.len() < 2 || (!use_repl && args[1].starts_with("--")) {
         eprintln!(
-            "Usage: {} <audio_file> [--server <address>] [--no-dictionary] [--stream]",
+            "Usage: {} <audio_file> [--server <address>] [--no-dictionary] [--stream] [--segment] [--show-corrections] [--large] [--api-key <key>] [--model <name>] [--format <text|json|srt|vtt>] [--connect-timeout <secs>] [--timeout <secs>] [--keepalive-secs <secs>]",
             args[0]
         );
+        eprintln!("       {} [<audio_file>] --repl [--server <address>] [--api-key <key>] [--model <name>]", args[0]);
         eprintln!(
             "Example: {} audio.wav --server http://localhost:50051",
             args[0]
@@ -58,35 +96,117 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         std::process::exit(1);
     }
 
-    let audio_file = PathBuf::from(&args[1]);
-    if !audio_file.exists() {
+    let audio_file = PathBuf::from(if args[1].starts_with("--") {
+        ""
+    } else {
+        &args[1]
+    });
+    if !use_repl && !audio_file.exists() {
         eprintln!("Error: Audio file not found: {}", audio_file.display());
         std::process::exit(1);
     }
 
-    let server_address = args
-        .iter()
-        .position(|a| a == "--server")
-        .and_then(|i| args.get(i + 1))
-        .unwrap_or(&"http://localhost:50051".to_string())
-        .clone();
+    let connection = Connection::from_args(&args)?;
 
     let use_dictionary = !args.contains(&"--no-dictionary".to_string());
     let use_streaming = args.contains(&"--stream".to_string());
+    let segment_on_silence = args.contains(&"--segment".to_string());
+    let show_corrections = args.contains(&"--show-corrections".to_string());
+    let use_large_upload = args.contains(&"--large".to_string());
+    let verbose = args.contains(&"--verbose".to_string());
+    let api_key = args
+        .iter()
+        .position(|a| a == "--api-key")
+        .and_then(|i| args.get(i + 1))
+        .cloned();
+    let model = args
+        .iter()
+        .position(|a| a == "--model")
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+        .unwrap_or_default();
+    let output_format = match args
+        .iter()
+        .position(|a| a == "--format")
+        .and_then(|i| args.get(i + 1))
+        .map(|f| f.to_ascii_lowercase())
+        .as_deref()
+    {
+        None | Some("text") => OutputFormat::Text,
+        Some("json") => OutputFormat::Json,
+        Some("srt") => OutputFormat::Srt,
+        Some("vtt") => OutputFormat::Vtt,
+        Some(other) => {
+            eprintln!(
+                "Error: unknown format '{}', expected text, json, srt or vtt",
+                other
+            );
+            std::process::exit(1);
+        }
+    };
+
+    if use_repl {
+        println!("📡 Connecting to {}...", connection.server);
+        let client = connection
+            .connect(ClientOptions {
+                api_key,
+                ..connection.options()
+            })
+            .await?;
+        println!("✅ Connected to server");
+
+        let mut repl = Repl {
+            client,
+            audio: None,
+            use_dictionary,
+            segment_on_silence,
+            model,
+        };
+        if !audio_file.as_os_str().is_empty() {
+            repl.load(&audio_file);
+        }
+        return repl.run().await;
+    }
 
     println!("📁 Murmure File Transcription Client");
     println!("Audio file: {}", audio_file.display());
-    println!("Server: {}", server_address);
+    println!("Server: {}", connection.server);
     println!("Use dictionary: {}", use_dictionary);
     println!(
         "Mode: {}\n",
-        if use_streaming {
+        if use_large_upload {
+            "Large file upload"
+        } else if use_streaming {
             "Streaming"
         } else {
             "File-based"
         }
     );
 
+    if use_large_upload {
+        println!("📡 Connecting to server...");
+        let mut client = connection
+            .connect(ClientOptions {
+                api_key: api_key.clone(),
+                ..connection.options()
+            })
+            .await?;
+        println!("✅ Connected to server");
+
+        let transcription = transcribe_large_file(
+            &mut client,
+            &audio_file,
+            use_dictionary,
+            segment_on_silence,
+            &model,
+            verbose,
+        )
+        .await
+        .map_err(|e| explain(&connection, e))?;
+        print_file_response(transcription, show_corrections);
+        return Ok(());
+    }
+
     // Read audio file
     println!("📖 Reading audio file...");
     let audio_data = std::fs::read(&audio_file)?;
@@ -94,120 +214,443 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Connect to server
     println!("📡 Connecting to server...");
-    let mut client = TranscriptionServiceClient::connect(server_address.clone()).await?;
+    let mut client = connection
+        .connect(ClientOptions {
+            api_key,
+            ..connection.options()
+        })
+        .await?;
     println!("✅ Connected to server");
 
     // Transcribe
     if use_streaming {
-        transcribe_stream(&mut client, &audio_data).await?;
+        transcribe_stream(&mut client, audio_data, verbose)
+            .await
+            .map_err(|e| explain(&connection, e))?;
     } else {
-        transcribe_file(&mut client, &audio_data, use_dictionary).await?;
+        let transcription = transcribe_file(
+            &mut client,
+            &audio_data,
+            use_dictionary,
+            segment_on_silence,
+            &model,
+            output_format,
+            verbose,
+        )
+        .await
+        .map_err(|e| explain(&connection, e))?;
+        if output_format == OutputFormat::Text {
+            print_file_response(transcription, show_corrections);
+        } else {
+            save_formatted_response(transcription, output_format, &audio_file)?;
+        }
     }
 
     Ok(())
 }
 
+/// Replace a client error with an explanation of what to check.
+fn explain(
+    connection: &Connection,
+    error: Box<dyn std::error::Error>,
+) -> Box<dyn std::error::Error> {
+    match error.downcast_ref::<ClientError>() {
+        Some(client_error) => connection.explain(client_error).into(),
+        None => error,
+    }
+}
+
 async fn transcribe_file(
-    client: &mut TranscriptionServiceClient<tonic::transport::Channel>,
+    client: &mut MurmureClient,
     audio_data: &[u8],
     use_dictionary: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
+    segment_on_silence: bool,
+    model: &str,
+    output_format: OutputFormat,
+    verbose: bool,
+) -> Result<TranscriptionResult, Box<dyn std::error::Error>> {
     println!("🔊 Sending audio for transcription (file-based)...");
 
-    let request = Request::new(TranscribeFileRequest {
+    let request = TranscribeFileRequest {
         audio_data: audio_data.to_vec(),
         use_dictionary,
-    });
+        segment_on_silence,
+        output_format: output_format.into(),
+        model: model.to_string(),
+        ..Default::default()
+    };
 
-    let response = client.transcribe_file(request).await?;
-    let transcription = response.into_inner();
-
-    if transcription.success {
-        println!("\n📝 Transcription:");
-        println!("{}", transcription.text);
-    } else {
-        eprintln!("\n❌ Transcription failed: {}", transcription.error);
-        std::process::exit(1);
+    let transcription = client.transcribe_file(request).await?;
+    if verbose {
+        metadata::print_engine_metadata(&transcription.metadata);
     }
-
-    Ok(())
+    Ok(transcription)
 }
 
-async fn transcribe_stream(
-    client: &mut TranscriptionServiceClient<tonic::transport::Channel>,
-    audio_data: &[u8],
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("🔊 Sending audio for transcription (streaming)...");
-
+/// Upload the file chunk by chunk with TranscribeLargeFile, printing upload
+/// progress while it runs. Only one chunk is held in memory at a time.
+async fn transcribe_large_file(
+    client: &mut MurmureClient,
+    audio_file: &Path,
+    use_dictionary: bool,
+    segment_on_silence: bool,
+    model: &str,
+    verbose: bool,
+) -> Result<TranscriptionResult, Box<dyn std::error::Error>> {
+    use murmure::transcribe_large_file_request::RequestType;
     use tokio::sync::mpsc;
 
-    // Split audio into chunks
-    let chunk_size = 8192;
-    let chunks: Vec<Vec<u8>> = audio_data
-        .chunks(chunk_size)
-        .map(|chunk| chunk.to_vec())
-        .collect();
+    let mut file = tokio::fs::File::open(audio_file).await?;
+    let total_bytes = file.metadata().await?.len();
+    let upload_id = format!("rust-file-client-{}", std::process::id());
+    println!(
+        "🔊 Uploading {} bytes in {} KiB chunks...",
+        total_bytes,
+        UPLOAD_CHUNK_SIZE / 1024
+    );
 
-    // Create channel for request stream
-    let (tx, rx) = mpsc::channel(128);
+    let (tx, rx) = mpsc::channel(4);
+    tx.send(TranscribeLargeFileRequest {
+        request_type: Some(RequestType::Start(UploadStart {
+            upload_id: upload_id.clone(),
+            total_bytes,
+            options: Some(StreamConfig {
+                use_dictionary: Some(use_dictionary),
+                segment_on_silence,
+                model: model.to_string(),
+                ..Default::default()
+            }),
+        })),
+    })
+    .await?;
 
-    // Spawn task to send chunks
+    // Read the file lazily; the bounded channel keeps at most a few chunks in flight
     tokio::spawn(async move {
-        for chunk in chunks {
-            let request = TranscribeStreamRequest {
-                request_type: Some(murmure::transcribe_stream_request::RequestType::AudioChunk(
-                    chunk,
-                )),
+        loop {
+            let mut chunk = vec![0u8; UPLOAD_CHUNK_SIZE];
+            let read = match file.read(&mut chunk).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    eprintln!("❌ Failed to read audio file: {}", e);
+                    return;
+                }
+            };
+            chunk.truncate(read);
+            let request = TranscribeLargeFileRequest {
+                request_type: Some(RequestType::Chunk(chunk)),
             };
             if tx.send(request).await.is_err() {
-                break;
+                return;
             }
         }
-        // Send end of stream
         let _ = tx
-            .send(TranscribeStreamRequest {
-                request_type: Some(
-                    murmure::transcribe_stream_request::RequestType::EndOfStream(true),
-                ),
+            .send(TranscribeLargeFileRequest {
+                request_type: Some(RequestType::Commit(true)),
             })
             .await;
     });
 
-    let request = Request::new(ReceiverStream::new(rx));
-    let mut response_stream = client.transcribe_stream(request).await?.into_inner();
+    // Poll progress on a second handle while the upload runs
+    let mut progress_client = client.clone();
+    let progress_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(500));
+        loop {
+            interval.tick().await;
+            let request = GetUploadProgressRequest {
+                upload_id: upload_id.clone(),
+            };
+            let Ok(progress) = progress_client.grpc().get_upload_progress(request).await else {
+                continue;
+            };
+            let progress = progress.into_inner();
+            if progress.transcribing {
+                println!("⏳ Upload complete, transcribing...");
+                break;
+            }
+            if progress.total_bytes > 0 {
+                println!(
+                    "📤 {:>5.1}% ({} / {} bytes)",
+                    progress.received_bytes as f64 * 100.0 / progress.total_bytes as f64,
+                    progress.received_bytes,
+                    progress.total_bytes
+                );
+            }
+        }
+    });
+
+    let response = client
+        .grpc()
+        .transcribe_large_file(Request::new(ReceiverStream::new(rx)))
+        .await;
+    progress_task.abort();
+
+    let transcription = TranscriptionResult::from_file_response(response?)?;
+    if verbose {
+        metadata::print_engine_metadata(&transcription.metadata);
+    }
+    Ok(transcription)
+}
+
+fn print_file_response(transcription: TranscriptionResult, show_corrections: bool) {
+    println!("\n📝 Transcription:");
+    println!("{}", transcription.text);
+    if !transcription.segments.is_empty() {
+        println!("\n🧩 Segments:");
+        for segment in &transcription.segments {
+            println!(
+                "   [{:>7.2}s - {:>7.2}s] {}",
+                segment.start, segment.end, segment.text
+            );
+        }
+    }
+    if show_corrections {
+        if transcription.corrections.is_empty() {
+            println!("\n📖 No dictionary corrections");
+        } else {
+            println!("\n📖 Dictionary corrections:");
+            for correction in &transcription.corrections {
+                println!(
+                    "   @{:<5} {:?} → {:?}",
+                    correction.offset, correction.original, correction.replacement
+                );
+            }
+        }
+    }
+    print_timing(
+        transcription.audio_duration_secs,
+        transcription.processing_time_ms,
+        transcription.realtime_factor,
+    );
+}
+
+/// Write a JSON or subtitle response next to the audio file, using the
+/// format name as extension.
+fn save_formatted_response(
+    transcription: TranscriptionResult,
+    format: OutputFormat,
+    audio_file: &Path,
+) -> std::io::Result<()> {
+    let extension = match format {
+        OutputFormat::Json => "json",
+        OutputFormat::Srt => "srt",
+        OutputFormat::Vtt => "vtt",
+        OutputFormat::Text => "txt",
+    };
+    let output = audio_file.with_extension(extension);
+    std::fs::write(&output, &transcription.text)?;
+    println!("\n💾 Saved {} output to {}", extension, output.display());
+    print_timing(
+        transcription.audio_duration_secs,
+        transcription.processing_time_ms,
+        transcription.realtime_factor,
+    );
+    Ok(())
+}
 
+async fn transcribe_stream(
+    client: &mut MurmureClient,
+    audio_data: Vec<u8>,
+    verbose: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("🔊 Sending audio for transcription (streaming)...");
     println!("📡 Streaming audio chunks...");
 
-    let mut final_text: Option<String> = None;
+    let chunks = client.wav_chunks(audio_data.into());
+    let transcription = client
+        .transcribe_stream_with(chunks, None, |text, segment| {
+            if let Some(segment) = segment {
+                println!(
+                    "📝 Partial [{:.2}s - {:.2}s]: {}",
+                    segment.start, segment.end, text
+                );
+            } else if !text.is_empty() {
+                println!("📝 Partial: {}", text);
+            }
+        })
+        .await?;
+    if verbose {
+        metadata::print_engine_metadata(&transcription.metadata);
+    }
+
+    println!("\n📝 Final Transcription:");
+    println!("{}", transcription.text);
+    print_timing(
+        transcription.audio_duration_secs,
+        transcription.processing_time_ms,
+        transcription.realtime_factor,
+    );
+
+    Ok(())
+}
+
+/// Interactive session on a single connection, for transcribing the same file
+/// many times with different options.
+struct Repl {
+    client: MurmureClient,
+    /// Path and content of the loaded audio file
+    audio: Option<(PathBuf, Vec<u8>)>,
+    use_dictionary: bool,
+    segment_on_silence: bool,
+    model: String,
+}
+
+impl Repl {
+    async fn run(mut self) -> Result<(), Box<dyn std::error::Error>> {
+        use tokio::io::AsyncBufReadExt;
 
-    while let Some(response) = response_stream.message().await? {
-        match response.response_type {
-            Some(murmure::transcribe_stream_response::ResponseType::PartialText(text)) => {
-                if !text.is_empty() {
-                    println!("📝 Partial: {}", text);
+        println!(
+            "Commands: load <path>, transcribe [--no-dictionary] [--segment] [--model <name>], info, quit"
+        );
+        let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            print!("murmure> ");
+            std::io::stdout().flush()?;
+
+            let line = tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => line,
+                    None => break,
+                },
+                _ = tokio::signal::ctrl_c() => {
+                    println!("\n(type quit to exit)");
+                    continue;
+                }
+            };
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => {}
+                ["quit" | "exit"] => break,
+                ["load", path] => self.load(Path::new(path)),
+                ["load", ..] => eprintln!("❌ Usage: load <path>"),
+                ["transcribe", flags @ ..] => match self.transcribe_request(flags) {
+                    Ok(request) => self.transcribe(request).await,
+                    Err(e) => eprintln!("❌ {}", e),
+                },
+                ["info"] => self.info().await,
+                ["dict", ..] => {
+                    eprintln!("❌ The server has no dictionary RPCs yet; edit MURMURE_DICTIONARY and restart it")
                 }
+                [other, ..] => eprintln!("❌ Unknown command '{}'", other),
             }
-            Some(murmure::transcribe_stream_response::ResponseType::FinalText(text)) => {
-                final_text = Some(text);
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, path: &Path) {
+        match std::fs::read(path) {
+            Ok(data) => {
+                println!("✅ Loaded {} ({} bytes)", path.display(), data.len());
+                self.audio = Some((path.to_path_buf(), data));
             }
-            Some(murmure::transcribe_stream_response::ResponseType::Error(err)) => {
-                eprintln!("❌ Error: {}", err);
-                std::process::exit(1);
+            Err(e) => eprintln!("❌ Cannot read {}: {}", path.display(), e),
+        }
+    }
+
+    /// Build a TranscribeFile request for the loaded file from the session
+    /// defaults and the flags given to `transcribe`.
+    fn transcribe_request(&self, flags: &[&str]) -> Result<TranscribeFileRequest, String> {
+        let Some((_, audio_data)) = &self.audio else {
+            return Err("No audio file loaded, use load <path> first".to_string());
+        };
+
+        let mut request = TranscribeFileRequest {
+            audio_data: audio_data.clone(),
+            use_dictionary: self.use_dictionary,
+            segment_on_silence: self.segment_on_silence,
+            model: self.model.clone(),
+            include_timing: true,
+            ..Default::default()
+        };
+        let mut flags = flags.iter();
+        while let Some(flag) = flags.next() {
+            match *flag {
+                "--no-dictionary" => request.use_dictionary = false,
+                "--segment" => request.segment_on_silence = true,
+                "--model" => {
+                    request.model = flags
+                        .next()
+                        .ok_or("--model needs a model name")?
+                        .to_string()
+                }
+                "--language" => {
+                    return Err(
+                        "--language is not supported, the model decides the language".to_string(),
+                    )
+                }
+                other => return Err(format!("Unknown option '{}'", other)),
             }
-            None => {}
         }
+        Ok(request)
+    }
 
-        if response.is_final {
-            break;
+    /// Send the request, or drop it (cancelling the RPC) on Ctrl+C.
+    async fn transcribe(&mut self, request: TranscribeFileRequest) {
+        let started = Instant::now();
+        tokio::select! {
+            result = self.client.transcribe_file(request) => match result {
+                Ok(transcription) => {
+                    let timing = transcription.timing;
+                    print_file_response(transcription, true);
+                    if let Some(timing) = &timing {
+                        print_stage_timings(timing);
+                    }
+                    println!("   round trip {} ms", started.elapsed().as_millis());
+                }
+                Err(e) => eprintln!("❌ {}", e),
+            },
+            _ = tokio::signal::ctrl_c() => println!("\n⏹️  Cancelled"),
         }
     }
 
-    if let Some(text) = final_text {
-        println!("\n📝 Final Transcription:");
-        println!("{}", text);
-    } else {
-        eprintln!("\n⚠️  No final transcription received");
+    async fn info(&mut self) {
+        match self.client.server_info().await {
+            Ok(info) => {
+                println!("   version: {}", info.version);
+                println!(
+                    "   execution provider: {} (requested {})",
+                    info.execution_provider, info.requested_execution_provider
+                );
+                println!("   engine panics: {}", info.engine_panics);
+                println!("   idempotent replays: {}", info.idempotent_replays);
+                if let Some(inference) = &info.inference_defaults {
+                    println!(
+                        "   decoding: blank penalty {}, {} symbols per step, max tokens {}",
+                        inference.blank_penalty(),
+                        inference.max_symbols_per_step(),
+                        inference.max_tokens()
+                    );
+                }
+            }
+            Err(e) => eprintln!("❌ {}", e),
+        }
+        match &self.audio {
+            Some((path, data)) => println!("   loaded: {} ({} bytes)", path.display(), data.len()),
+            None => println!("   loaded: nothing"),
+        }
     }
+}
 
-    Ok(())
+fn print_stage_timings(timing: &StageTimings) {
+    println!(
+        "   decode {:.1} ms, resample {:.1} ms, preprocess {:.1} ms, vad {:.1} ms, inference {:.1} ms, dictionary {:.1} ms, post-process {:.1} ms, total {:.1} ms",
+        timing.decode_ms,
+        timing.resample_ms,
+        timing.preprocess_ms,
+        timing.vad_ms,
+        timing.inference_ms,
+        timing.dictionary_ms,
+        timing.post_process_ms,
+        timing.total_ms
+    );
+}
+
+fn print_timing(audio_duration_secs: f32, processing_time_ms: u64, realtime_factor: f32) {
+    println!(
+        "\n⏱️  {:.1}s audio in {:.1}s ({:.1}x realtime)",
+        audio_duration_secs,
+        processing_time_ms as f64 / 1000.0,
+        realtime_factor
+    );
 }
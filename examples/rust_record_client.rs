@@ -19,35 +19,34 @@
 //!
 //! Options:
 //! - `--server <address>` - Server address (default: http://localhost:50051)
+//! - `--connect-timeout <secs>` - Give up connecting after this long (default: 5)
+//! - `--timeout <secs>` - Deadline of the transcription, 0 = none (default: 120)
+//! - `--keepalive-secs <secs>` - Keepalive ping interval, 0 = off (default: 30)
 //! - `--duration <seconds>` - Recording duration (default: 5)
+//! - `--verbose` - Print the server version, model and dictionary revision sent with the response
 //!
 //! See ../docs/examples/README_RUST_CLIENT.md for detailed documentation.
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use hound::{WavSpec, WavWriter};
-use std::fs::File;
-use std::io::BufWriter;
+use hound::WavSpec;
+use murmure_client::ClientError;
 use std::sync::Arc;
 use std::time::Duration;
-use tonic::Request;
 
-// Include generated proto code from build script
-pub mod murmure {
-    include!(concat!(env!("OUT_DIR"), "/murmure.rs"));
-}
+#[path = "common/connection.rs"]
+mod connection;
+#[path = "common/metadata.rs"]
+mod metadata;
+#[path = "common/recording.rs"]
+mod recording;
 
-use murmure::transcription_service_client::TranscriptionServiceClient;
-use murmure::TranscribeFileRequest;
+use connection::Connection;
+use recording::SharedWavWriter;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
-    let server_address = args
-        .iter()
-        .position(|a| a == "--server")
-        .and_then(|i| args.get(i + 1))
-        .unwrap_or(&"http://localhost:50051".to_string())
-        .clone();
+    let connection = Connection::from_args(&args)?;
 
     let duration_secs = args
         .iter()
@@ -57,7 +56,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .unwrap_or(5);
 
     println!("🎤 Murmure Audio Recording Client");
-    println!("Server: {}", server_address);
+    println!("Server: {}", connection.server);
     println!("Recording duration: {} seconds", duration_secs);
     println!("Press Ctrl+C to stop early\n");
 
@@ -68,36 +67,41 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Connect to server
     println!("📡 Connecting to server...");
-    let mut client = TranscriptionServiceClient::connect(server_address).await?;
+    let mut client = connection.connect(connection.options()).await?;
     println!("✅ Connected to server");
 
     // Transcribe
     println!("🔊 Sending audio for transcription...");
-    let request = Request::new(TranscribeFileRequest {
-        audio_data,
-        use_dictionary: true,
-    });
-
-    let response = client.transcribe_file(request).await?;
-    let transcription = response.into_inner();
-
-    if transcription.success {
-        println!("\n📝 Transcription:");
-        if transcription.text.is_empty() {
-            println!("(Empty transcription - audio may be too short, silent, or unrecognized)");
-            println!("\n💡 Possible reasons:");
-            println!("   - Audio was too quiet or silent");
-            println!("   - Audio format mismatch");
-            println!("   - Server processed but found no speech");
-            println!("   - Try speaking louder or checking microphone levels");
-        } else {
-            println!("{}", transcription.text);
+    match client.transcribe_wav_bytes(&audio_data).await {
+        Ok(transcription) => {
+            if args.iter().any(|a| a == "--verbose") {
+                metadata::print_engine_metadata(&transcription.metadata);
+            }
+            println!("\n📝 Transcription:");
+            if transcription.text.is_empty() {
+                println!("(Empty transcription - audio may be too short, silent, or unrecognized)");
+                println!("\n💡 Possible reasons:");
+                println!("   - Audio was too quiet or silent");
+                println!("   - Audio format mismatch");
+                println!("   - Server processed but found no speech");
+                println!("   - Try speaking louder or checking microphone levels");
+            } else {
+                println!("{}", transcription.text);
+            }
+            println!(
+                "\n⏱️  {:.1}s audio in {:.1}s ({:.1}x realtime)",
+                transcription.audio_duration_secs,
+                transcription.processing_time_ms as f64 / 1000.0,
+                transcription.realtime_factor
+            );
         }
-    } else {
-        eprintln!("\n❌ Transcription failed: {}", transcription.error);
-        if transcription.error.is_empty() {
-            eprintln!("   (No error message provided by server)");
+        Err(ClientError::Transcription(error)) => {
+            eprintln!("\n❌ Transcription failed: {}", error);
+            if error.is_empty() {
+                eprintln!("   (No error message provided by server)");
+            }
         }
+        Err(e) => return Err(connection.explain(&e).into()),
     }
 
     Ok(())
@@ -149,15 +153,13 @@ fn record_audio(duration_secs: u64) -> Result<Vec<u8>, Box<dyn std::error::Error
         sample_format: hound::SampleFormat::Int,
     };
 
-    let file = File::create(&temp_file)?;
-    let writer = WavWriter::new(BufWriter::new(file), spec)?;
-    let writer_arc = Arc::new(std::sync::Mutex::new(writer));
+    let writer = SharedWavWriter::create(&temp_file, spec)?;
 
     println!("   Testing microphone access...");
     let result = match config.sample_format() {
-        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, writer_arc.clone()),
-        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, writer_arc.clone()),
-        cpal::SampleFormat::I32 => build_stream::<i32>(&device, &config, writer_arc.clone()),
+        cpal::SampleFormat::F32 => build_stream::<f32>(&device, &config, writer.clone()),
+        cpal::SampleFormat::I16 => build_stream::<i16>(&device, &config, writer.clone()),
+        cpal::SampleFormat::I32 => build_stream::<i32>(&device, &config, writer.clone()),
         _ => return Err("Unsupported sample format".into()),
     };
 
@@ -233,18 +235,11 @@ fn record_audio(duration_secs: u64) -> Result<Vec<u8>, Box<dyn std::error::Error
         final_stats.0, final_stats.1
     );
 
+    drop(final_stats);
     drop(stream);
 
     // Finalize WAV file
-    {
-        let mut writer = writer_arc.lock().unwrap();
-        writer.flush()?;
-        drop(writer); // Explicitly drop to get ownership back
-    }
-
-    // Get the writer back and finalize
-    let writer = Arc::try_unwrap(writer_arc).map_err(|_| "Failed to unwrap Arc")?;
-    writer.into_inner().unwrap().finalize()?;
+    writer.finalize()?;
 
     // Read WAV file into memory
     let audio_data = std::fs::read(&temp_file)?;
@@ -300,14 +295,13 @@ fn record_audio(duration_secs: u64) -> Result<Vec<u8>, Box<dyn std::error::Error
     Ok(audio_data)
 }
 
-type WavWriterType = WavWriter<BufWriter<File>>;
 type StreamResult =
     Result<(cpal::Stream, Arc<std::sync::Mutex<(usize, i16)>>), Box<dyn std::error::Error>>;
 
 fn build_stream<T>(
     device: &cpal::Device,
     config: &cpal::SupportedStreamConfig,
-    writer: Arc<std::sync::Mutex<WavWriterType>>,
+    writer: SharedWavWriter,
 ) -> StreamResult
 where
     T: cpal::Sample + cpal::SizedSample + Send + 'static,
@@ -322,11 +316,10 @@ where
     let stream = device.build_input_stream(
         &config.clone().into(),
         move |data: &[T], _: &cpal::InputCallbackInfo| {
-            let mut writer = writer.lock().unwrap();
             let mut stats = stats_clone.lock().unwrap();
             stats.0 += data.len() / channels;
 
-            for frame in data.chunks_exact(channels) {
+            writer.write_samples(data.chunks_exact(channels).map(|frame| {
                 let sample = if channels == 1 {
                     frame[0].to_sample::<f32>()
                 } else {
@@ -341,8 +334,8 @@ where
                 if amplitude > stats.1 {
                     stats.1 = amplitude;
                 }
-                let _ = writer.write_sample(sample_i16);
-            }
+                sample_i16
+            }));
         },
         |err| eprintln!("Stream error: {}", err),
         None,
@@ -19,33 +19,58 @@
 //!
 //! Controls:
 //! - Press SPACE to start/stop recording (toggle)
+//! - Press P to pause/resume the current recording
 //! - Press Ctrl+C to exit
 //!
 //! Options:
 //! - `--server <address>` - Server address (default: http://localhost:50051)
+//! - `--connect-timeout <secs>` - Give up connecting after this long (default: 5)
+//! - `--timeout <secs>` - Deadline of each recording's transcript, 0 = none (default: 120)
+//! - `--keepalive-secs <secs>` - Keepalive ping interval, 0 = off (default: 30)
+//! - `--chunk-size <bytes>` - Size of each streamed audio chunk (default: 16384)
+//! - `--realtime` - Send audio at its natural rate instead of as fast as possible
+//! - `--max-in-flight <n>` - Chunks queued ahead of the network (default: 8)
+//! - `--min-duration <secs>` - Discard shorter recordings locally (default: 0.5)
+//! - `--max-duration <secs>` - Stop and transcribe at this length, 0 = no limit (default: 300)
+//! - `--pre-roll-ms <ms>` - Audio kept from before SPACE is pressed (default: 500)
+//! - `--verbose` - Print the server version, model and dictionary revision when the session opens
+//!
+//! While a recording is paused, the microphone audio is dropped and the
+//! session stream is paused too; the paused time doesn't count towards
+//! `--min-duration` or `--max-duration`.
+//!
+//! Pressing Ctrl+C while a recording is being sent stops sending but still
+//! ends the stream, so the server returns a transcript of what it received.
+//!
+//! The session stream stays open between recordings, so `--timeout` applies
+//! to each recording, from the end of its audio to its transcript, rather
+//! than to the connection.
 
-use std::fs::File;
-use std::io::{self, BufWriter, Write};
+use std::collections::VecDeque;
+use std::io::{self, Cursor, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{SampleFormat, SupportedStreamConfig};
 use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
 use hound::{WavSpec, WavWriter};
+use murmure_client::proto as murmure;
+use murmure_client::{ClientOptions, MurmureClient};
 use tokio::sync::mpsc;
-use tokio::task::JoinHandle;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::Request;
+use tonic::Status;
 
-// Include generated proto code from build script
-pub mod murmure {
-    include!(concat!(env!("OUT_DIR"), "/murmure.rs"));
-}
+#[path = "common/connection.rs"]
+mod connection;
+#[path = "common/metadata.rs"]
+mod metadata;
+
+use connection::Connection;
 
-use murmure::transcription_service_client::TranscriptionServiceClient;
 use murmure::{TranscribeStreamRequest, TranscribeStreamResponse};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -60,62 +85,96 @@ struct AudioConfig {
     config: SupportedStreamConfig,
 }
 
+/// How recorded audio is streamed to the server.
+struct StreamingOptions {
+    chunk_size: usize,
+    /// Pace chunks at the audio's byte rate
+    realtime: bool,
+    /// Capacity of the request channel; sending waits when it is full
+    max_in_flight: usize,
+    /// Print the engine metadata the server sends when the session opens
+    verbose: bool,
+}
+
+/// Limits applied to each recording.
+struct RecordingOptions {
+    /// Shorter recordings are discarded without contacting the server
+    min_duration: Duration,
+    /// Recordings are stopped and transcribed when they reach this length
+    max_duration: Option<Duration>,
+    /// Audio captured before SPACE is pressed and prepended to the recording
+    pre_roll: Duration,
+}
+
 struct RecordingState {
-    is_recording: bool,
     count: usize,
-    stop_flag: Option<Arc<AtomicBool>>,
-    handle: Option<JoinHandle<SendResult<Vec<u8>>>>,
+    /// When the current recording started (None = not recording)
+    started: Option<Instant>,
+    /// When the current recording was paused (None = not paused)
+    paused_since: Option<Instant>,
+    /// Time the current recording spent paused before `paused_since`
+    paused_for: Duration,
+    options: RecordingOptions,
 }
 
 impl RecordingState {
-    fn new() -> Self {
+    fn new(options: RecordingOptions) -> Self {
         Self {
-            is_recording: false,
             count: 0,
-            stop_flag: None,
-            handle: None,
+            started: None,
+            paused_since: None,
+            paused_for: Duration::ZERO,
+            options,
         }
     }
 
-    fn start(&mut self, device: &cpal::Device, config: &SupportedStreamConfig) {
-        self.count += 1;
-        self.is_recording = true;
+    fn is_recording(&self) -> bool {
+        self.started.is_some()
+    }
 
-        let stop_flag = Arc::new(AtomicBool::new(false));
-        self.stop_flag = Some(stop_flag.clone());
+    fn is_paused(&self) -> bool {
+        self.paused_since.is_some()
+    }
 
-        let device_clone = device.clone();
-        let config_clone = config.clone();
+    /// Time since SPACE was pressed, not counting the pre-roll or pauses.
+    fn elapsed(&self) -> Option<Duration> {
+        let paused = self.paused_for + self.paused_since.map_or(Duration::ZERO, |p| p.elapsed());
+        self.started
+            .map(|started| started.elapsed().saturating_sub(paused))
+    }
 
-        self.handle = Some(tokio::spawn(async move {
-            tokio::task::spawn_blocking(move || {
-                record_audio(&device_clone, &config_clone, stop_flag)
-            })
-            .await
-            .map_err(|e| {
-                Box::new(io::Error::other(format!("Task error: {}", e)))
-                    as Box<dyn std::error::Error + Send + Sync>
-            })?
-        }));
+    fn reached_max_duration(&self) -> bool {
+        match (self.elapsed(), self.options.max_duration) {
+            (Some(elapsed), Some(max)) => elapsed >= max,
+            _ => false,
+        }
     }
 
-    async fn stop(&mut self) -> Option<SendResult<Vec<u8>>> {
-        self.is_recording = false;
+    fn start(&mut self, recorder: &Recorder) {
+        self.count += 1;
+        self.started = Some(Instant::now());
+        self.paused_since = None;
+        self.paused_for = Duration::ZERO;
+        recorder.start();
+    }
 
-        if let Some(flag) = self.stop_flag.take() {
-            flag.store(true, Ordering::Relaxed);
+    /// Pause the recording, or resume it when paused; returns whether it is
+    /// now paused.
+    fn toggle_pause(&mut self, recorder: &Recorder) -> bool {
+        match self.paused_since.take() {
+            Some(paused_since) => self.paused_for += paused_since.elapsed(),
+            None => self.paused_since = Some(Instant::now()),
         }
+        recorder.set_paused(self.is_paused());
+        self.is_paused()
+    }
 
-        if let Some(handle) = self.handle.take() {
-            let result = match handle.await {
-                Ok(inner_result) => inner_result,
-                Err(e) => Err(Box::new(io::Error::other(format!("Join error: {}", e)))
-                    as Box<dyn std::error::Error + Send + Sync>),
-            };
-            Some(result)
-        } else {
-            None
-        }
+    /// Stop recording, returning the samples and how long it ran.
+    fn stop(&mut self, recorder: &Recorder) -> (Vec<i16>, Duration) {
+        let elapsed = self.elapsed().unwrap_or_default();
+        self.started = None;
+        self.paused_since = None;
+        (recorder.stop(), elapsed)
     }
 }
 
@@ -125,56 +184,84 @@ impl RecordingState {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let server_address = parse_server_address();
-    print_welcome(&server_address);
+    let args: Vec<String> = std::env::args().collect();
+    let connection = Connection::from_args(&args)?;
+    let streaming = parse_streaming_options()?;
+    let recording = parse_recording_options()?;
+    print_welcome(&connection.server);
 
     let audio_config = setup_audio()?;
-    let mut client = connect_to_server(&server_address).await?;
+    let recorder = Recorder::open(&audio_config, recording.pre_roll)?;
+    let mut client = connect_to_server(&connection).await?;
+    let mut session = Session::open(&mut client, &streaming, connection.timeout).await?;
 
     print_instructions();
 
     enable_raw_mode()?;
     let shutdown_flag = setup_shutdown_handler();
 
-    let result = run_recording_loop(&mut client, &audio_config, shutdown_flag).await;
+    let mut state = RecordingState::new(recording);
+    let result = run_recording_loop(&mut session, &recorder, &mut state, shutdown_flag).await;
 
     disable_raw_mode()?;
     result
 }
 
 async fn run_recording_loop(
-    client: &mut TranscriptionServiceClient<tonic::transport::Channel>,
-    audio_config: &AudioConfig,
+    session: &mut Session,
+    recorder: &Recorder,
+    recording_state: &mut RecordingState,
     shutdown_flag: Arc<AtomicBool>,
 ) -> Result<()> {
     let mut conversation_text = String::new();
-    let mut recording_state = RecordingState::new();
 
     loop {
         if shutdown_flag.load(Ordering::Relaxed) {
-            handle_shutdown(&mut recording_state, &conversation_text).await?;
+            handle_shutdown(recording_state, recorder, session, &conversation_text).await?;
             break;
         }
 
+        if recording_state.reached_max_duration() {
+            disable_raw_mode()?;
+            println!("\n   ⏱️  Maximum recording duration reached");
+            stop_and_transcribe(
+                recording_state,
+                recorder,
+                session,
+                &shutdown_flag,
+                &mut conversation_text,
+            )
+            .await?;
+            enable_raw_mode()?;
+            continue;
+        }
+
         if event::poll(Duration::from_millis(50))? {
             if let Event::Key(key_event) = event::read()? {
                 if should_exit(&key_event) {
-                    handle_shutdown(&mut recording_state, &conversation_text).await?;
+                    handle_shutdown(recording_state, recorder, session, &conversation_text).await?;
                     break;
                 }
 
                 match key_event.code {
                     KeyCode::Char(' ') if key_event.kind == KeyEventKind::Press => {
                         handle_space_press(
-                            &mut recording_state,
-                            audio_config,
-                            client,
+                            recording_state,
+                            recorder,
+                            session,
+                            &shutdown_flag,
                             &mut conversation_text,
                         )
                         .await?;
                     }
+                    KeyCode::Char('p') | KeyCode::Char('P')
+                        if key_event.kind == KeyEventKind::Press =>
+                    {
+                        handle_pause_press(recording_state, recorder, session).await?;
+                    }
                     KeyCode::Esc => {
-                        handle_shutdown(&mut recording_state, &conversation_text).await?;
+                        handle_shutdown(recording_state, recorder, session, &conversation_text)
+                            .await?;
                         break;
                     }
                     _ => {}
@@ -188,63 +275,100 @@ async fn run_recording_loop(
 
 async fn handle_space_press(
     state: &mut RecordingState,
-    audio_config: &AudioConfig,
-    client: &mut TranscriptionServiceClient<tonic::transport::Channel>,
+    recorder: &Recorder,
+    session: &mut Session,
+    shutdown_flag: &Arc<AtomicBool>,
     conversation_text: &mut String,
 ) -> Result<()> {
     disable_raw_mode()?;
 
-    if !state.is_recording {
-        start_recording(state, audio_config)?;
+    if !state.is_recording() {
+        start_recording(state, recorder)?;
     } else {
-        stop_and_transcribe(state, client, conversation_text).await?;
+        stop_and_transcribe(state, recorder, session, shutdown_flag, conversation_text).await?;
     }
 
     enable_raw_mode()?;
     Ok(())
 }
 
-fn start_recording(state: &mut RecordingState, audio_config: &AudioConfig) -> Result<()> {
+async fn handle_pause_press(
+    state: &mut RecordingState,
+    recorder: &Recorder,
+    session: &Session,
+) -> Result<()> {
+    if !state.is_recording() {
+        return Ok(());
+    }
+    disable_raw_mode()?;
+
+    let paused = state.toggle_pause(recorder);
+    if paused {
+        println!("\n   ⏸️  Paused (press P to resume, SPACE to stop)");
+    } else {
+        println!("\n   ▶️  Resumed");
+    }
+    io::stdout().flush()?;
+    if let Err(e) = session.set_paused(paused).await {
+        println!("   ❌ {}", e);
+    }
+
+    enable_raw_mode()?;
+    Ok(())
+}
+
+fn start_recording(state: &mut RecordingState, recorder: &Recorder) -> Result<()> {
     println!(
         "\n🎙️  Recording #{} started (press SPACE again to stop)...",
         state.count + 1
     );
     io::stdout().flush()?;
-    state.start(&audio_config.device, &audio_config.config);
+    state.start(recorder);
     Ok(())
 }
 
 async fn stop_and_transcribe(
     state: &mut RecordingState,
-    client: &mut TranscriptionServiceClient<tonic::transport::Channel>,
+    recorder: &Recorder,
+    session: &mut Session,
+    shutdown_flag: &Arc<AtomicBool>,
     conversation_text: &mut String,
 ) -> Result<()> {
     println!("\n   ⏹️  Stopping recording...");
     io::stdout().flush()?;
 
-    let audio_result = state.stop().await;
+    let was_paused = state.is_paused();
+    let (samples, elapsed) = state.stop(recorder);
+    // The server drops audio while paused, so resume before sending any
+    if was_paused {
+        session.set_paused(false).await?;
+    }
+
+    if elapsed < state.options.min_duration {
+        println!(
+            "⚠️  Recording discarded ({:.1}s, shorter than --min-duration {:.1}s)\n",
+            elapsed.as_secs_f32(),
+            state.options.min_duration.as_secs_f32()
+        );
+        return Ok(());
+    }
+    if samples.is_empty() {
+        println!("⚠️  No audio recorded (too short or silent)\n");
+        return Ok(());
+    }
 
-    let audio_data = match audio_result {
-        Some(Ok(data)) => data,
-        Some(Err(e)) => {
+    let audio_data = match encode_wav(&samples, recorder.sample_rate) {
+        Ok(data) => data,
+        Err(e) => {
             eprintln!("\n❌ Recording error: {}", e);
             return Ok(());
         }
-        None => {
-            eprintln!("\n❌ No recording handle found");
-            return Ok(());
-        }
     };
 
-    if audio_data.is_empty() {
-        println!("⚠️  No audio recorded (too short or silent)\n");
-        return Ok(());
-    }
-
-    print!("   📤 Sending to server for transcription...");
+    println!("   📤 Sending to server for transcription...");
     io::stdout().flush()?;
 
-    match transcribe_audio(client, audio_data).await {
+    match session.transcribe(audio_data, shutdown_flag).await {
         Ok(text) if !text.trim().is_empty() => {
             println!("\r   ✅ Transcription #{}: {}\n", state.count, text);
             conversation_text.push_str(&text);
@@ -262,11 +386,17 @@ async fn stop_and_transcribe(
     Ok(())
 }
 
-async fn handle_shutdown(state: &mut RecordingState, conversation_text: &str) -> Result<()> {
-    if state.is_recording {
+async fn handle_shutdown(
+    state: &mut RecordingState,
+    recorder: &Recorder,
+    session: &Session,
+    conversation_text: &str,
+) -> Result<()> {
+    if state.is_recording() {
         println!("\n🛑 Stopping recording...");
-        state.stop().await;
+        state.stop(recorder);
     }
+    session.close().await;
 
     println!("\n📝 Conversation transcript:\n{}", conversation_text);
     Ok(())
@@ -276,13 +406,72 @@ async fn handle_shutdown(state: &mut RecordingState, conversation_text: &str) ->
 // Setup and Initialization
 // ============================================================================
 
-fn parse_server_address() -> String {
+fn parse_streaming_options() -> Result<StreamingOptions> {
     let args: Vec<String> = std::env::args().collect();
-    args.iter()
-        .position(|a| a == "--server")
-        .and_then(|i| args.get(i + 1))
-        .cloned()
-        .unwrap_or_else(|| "http://localhost:50051".to_string())
+    let value = |flag: &str| {
+        args.iter()
+            .position(|a| a == flag)
+            .and_then(|i| args.get(i + 1))
+    };
+
+    let chunk_size = match value("--chunk-size") {
+        Some(size) => size
+            .parse()
+            .map_err(|_| format!("Invalid --chunk-size '{}'", size))?,
+        None => 16384,
+    };
+    let max_in_flight = match value("--max-in-flight") {
+        Some(count) => count
+            .parse()
+            .map_err(|_| format!("Invalid --max-in-flight '{}'", count))?,
+        None => 8,
+    };
+    if chunk_size == 0 || max_in_flight == 0 {
+        return Err("--chunk-size and --max-in-flight must be at least 1".into());
+    }
+
+    Ok(StreamingOptions {
+        chunk_size,
+        realtime: args.iter().any(|a| a == "--realtime"),
+        max_in_flight,
+        verbose: args.iter().any(|a| a == "--verbose"),
+    })
+}
+
+fn parse_recording_options() -> Result<RecordingOptions> {
+    let args: Vec<String> = std::env::args().collect();
+    let value = |flag: &str| {
+        args.iter()
+            .position(|a| a == flag)
+            .and_then(|i| args.get(i + 1))
+    };
+    let seconds = |flag: &str, default: f32| -> Result<f32> {
+        match value(flag) {
+            Some(secs) => match secs.parse::<f32>() {
+                Ok(secs) if secs >= 0.0 && secs.is_finite() => Ok(secs),
+                _ => Err(format!("Invalid {} '{}'", flag, secs).into()),
+            },
+            None => Ok(default),
+        }
+    };
+
+    let min_duration = seconds("--min-duration", 0.5)?;
+    let max_duration = seconds("--max-duration", 300.0)?;
+    if max_duration > 0.0 && max_duration < min_duration {
+        return Err("--max-duration must not be shorter than --min-duration".into());
+    }
+    let pre_roll_ms = match value("--pre-roll-ms") {
+        Some(ms) => ms
+            .parse()
+            .map_err(|_| format!("Invalid --pre-roll-ms '{}'", ms))?,
+        None => 500,
+    };
+
+    Ok(RecordingOptions {
+        min_duration: Duration::from_secs_f32(min_duration),
+        max_duration: (max_duration > 0.0).then(|| Duration::from_secs_f32(max_duration)),
+        pre_roll: Duration::from_millis(pre_roll_ms),
+    })
 }
 
 fn print_welcome(server_address: &str) {
@@ -294,6 +483,7 @@ fn print_instructions() {
     println!("🎤 Toggle Recording Mode");
     println!("   Press SPACE to start recording");
     println!("   Press SPACE again to stop and transcribe");
+    println!("   Press P to pause/resume a recording");
     println!("   Press Ctrl+C to exit\n");
 }
 
@@ -325,11 +515,15 @@ fn setup_audio() -> Result<AudioConfig> {
     Ok(AudioConfig { device, config })
 }
 
-async fn connect_to_server(
-    address: &str,
-) -> Result<TranscriptionServiceClient<tonic::transport::Channel>> {
+async fn connect_to_server(connection: &Connection) -> Result<MurmureClient> {
     println!("📡 Connecting to server...");
-    let client = TranscriptionServiceClient::connect(address.to_string()).await?;
+    // No deadline on the connection: the session stream stays open between
+    // recordings and each transcript gets its own deadline
+    let options = ClientOptions {
+        request_timeout: None,
+        ..connection.options()
+    };
+    let client = connection.connect(options).await?;
     println!("✅ Connected to server\n");
     Ok(client)
 }
@@ -358,64 +552,114 @@ fn should_exit(key_event: &crossterm::event::KeyEvent) -> bool {
 // Audio Recording
 // ============================================================================
 
-fn record_audio(
-    device: &cpal::Device,
-    config: &SupportedStreamConfig,
-    stop_flag: Arc<AtomicBool>,
-) -> SendResult<Vec<u8>> {
-    let temp_file = create_temp_wav_file()?;
-    let spec = create_wav_spec(config);
-
-    let writer = WavWriter::new(BufWriter::new(File::create(&temp_file)?), spec)?;
-    let writer_arc = Arc::new(Mutex::new(writer));
+/// Microphone samples shared with the cpal callback.
+struct Capture {
+    /// Most recent audio while idle, at most `pre_roll_len` samples
+    pre_roll: VecDeque<i16>,
+    pre_roll_len: usize,
+    /// Samples of the recording in progress
+    recording: Option<Vec<i16>>,
+    /// Drop samples instead of adding them to the recording
+    paused: bool,
+}
 
-    let stream = create_audio_stream(device, config, writer_arc.clone())?;
-    stream
-        .play()
-        .map_err(|e| format!("❌ Failed to start recording: {}", e))?;
+impl Capture {
+    fn push(&mut self, samples: impl Iterator<Item = i16>) {
+        match &mut self.recording {
+            Some(_) if self.paused => {}
+            Some(recording) => recording.extend(samples),
+            None if self.pre_roll_len > 0 => {
+                for sample in samples {
+                    if self.pre_roll.len() == self.pre_roll_len {
+                        self.pre_roll.pop_front();
+                    }
+                    self.pre_roll.push_back(sample);
+                }
+            }
+            None => {}
+        }
+    }
+}
 
-    wait_for_stop_signal(&stop_flag);
-    drop(stream);
-    std::thread::sleep(Duration::from_millis(200));
+/// Input stream opened once at startup and kept running, so the pre-roll
+/// buffer always holds the audio just before SPACE is pressed.
+struct Recorder {
+    _stream: cpal::Stream,
+    capture: Arc<Mutex<Capture>>,
+    sample_rate: u32,
+}
 
-    finalize_wav_file(writer_arc)?;
+impl Recorder {
+    fn open(audio_config: &AudioConfig, pre_roll: Duration) -> Result<Self> {
+        let sample_rate = audio_config.config.sample_rate().0;
+        let pre_roll_len = (pre_roll.as_secs_f64() * sample_rate as f64) as usize;
+        let capture = Arc::new(Mutex::new(Capture {
+            pre_roll: VecDeque::with_capacity(pre_roll_len),
+            pre_roll_len,
+            recording: None,
+            paused: false,
+        }));
 
-    let audio_data = std::fs::read(&temp_file)?;
-    let _ = std::fs::remove_file(&temp_file);
+        let stream =
+            create_audio_stream(&audio_config.device, &audio_config.config, capture.clone())
+                .map_err(|e| e.to_string())?;
+        stream
+            .play()
+            .map_err(|e| format!("❌ Failed to start recording: {}", e))?;
+
+        Ok(Self {
+            _stream: stream,
+            capture,
+            sample_rate,
+        })
+    }
 
-    Ok(audio_data)
-}
+    /// Start a recording, beginning with the pre-roll audio.
+    fn start(&self) {
+        let mut capture = self.capture.lock().unwrap();
+        let pre_roll: Vec<i16> = capture.pre_roll.drain(..).collect();
+        capture.recording = Some(pre_roll);
+    }
 
-fn create_temp_wav_file() -> SendResult<std::path::PathBuf> {
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)?
-        .as_secs();
+    fn set_paused(&self, paused: bool) {
+        self.capture.lock().unwrap().paused = paused;
+    }
 
-    Ok(std::env::temp_dir().join(format!(
-        "murmure-record-{}-{}.wav",
-        std::process::id(),
-        timestamp
-    )))
+    fn stop(&self) -> Vec<i16> {
+        let mut capture = self.capture.lock().unwrap();
+        capture.paused = false;
+        capture.recording.take().unwrap_or_default()
+    }
 }
 
-fn create_wav_spec(config: &SupportedStreamConfig) -> WavSpec {
-    WavSpec {
+/// Encode mono 16-bit samples as an in-memory WAV file.
+fn encode_wav(samples: &[i16], sample_rate: u32) -> SendResult<Vec<u8>> {
+    let spec = WavSpec {
         channels: 1,
-        sample_rate: config.sample_rate().0,
+        sample_rate,
         bits_per_sample: 16,
         sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut cursor = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut cursor, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
     }
+    writer.finalize()?;
+
+    Ok(cursor.into_inner())
 }
 
 fn create_audio_stream(
     device: &cpal::Device,
     config: &SupportedStreamConfig,
-    writer: Arc<Mutex<WavWriter<BufWriter<File>>>>,
+    capture: Arc<Mutex<Capture>>,
 ) -> SendResult<cpal::Stream> {
     match config.sample_format() {
-        SampleFormat::F32 => build_stream::<f32>(device, config, writer),
-        SampleFormat::I16 => build_stream::<i16>(device, config, writer),
-        SampleFormat::I32 => build_stream::<i32>(device, config, writer),
+        SampleFormat::F32 => build_stream::<f32>(device, config, capture),
+        SampleFormat::I16 => build_stream::<i16>(device, config, capture),
+        SampleFormat::I32 => build_stream::<i32>(device, config, capture),
         _ => Err("Unsupported sample format".into()),
     }
 }
@@ -423,7 +667,7 @@ fn create_audio_stream(
 fn build_stream<T>(
     device: &cpal::Device,
     config: &SupportedStreamConfig,
-    writer: Arc<Mutex<WavWriter<BufWriter<File>>>>,
+    capture: Arc<Mutex<Capture>>,
 ) -> SendResult<cpal::Stream>
 where
     T: cpal::Sample + cpal::SizedSample + Send + 'static,
@@ -434,7 +678,7 @@ where
     let stream = device.build_input_stream(
         &config.clone().into(),
         move |data: &[T], _: &cpal::InputCallbackInfo| {
-            process_audio_data(data, channels, &writer);
+            process_audio_data(data, channels, &capture);
         },
         |err| eprintln!("Stream error: {}", err),
         None,
@@ -443,89 +687,261 @@ where
     Ok(stream)
 }
 
-fn process_audio_data<T>(
-    data: &[T],
-    channels: usize,
-    writer: &Arc<Mutex<WavWriter<BufWriter<File>>>>,
-) where
+fn process_audio_data<T>(data: &[T], channels: usize, capture: &Mutex<Capture>)
+where
     T: cpal::Sample,
     f32: cpal::FromSample<T>,
 {
-    let mut writer = writer.lock().unwrap();
+    capture
+        .lock()
+        .unwrap()
+        .push(data.chunks_exact(channels).map(|frame| {
+            let sample = if channels == 1 {
+                frame[0].to_sample::<f32>()
+            } else {
+                frame.iter().map(|&s| s.to_sample::<f32>()).sum::<f32>() / channels as f32
+            };
 
-    for frame in data.chunks_exact(channels) {
-        let sample = if channels == 1 {
-            frame[0].to_sample::<f32>()
-        } else {
-            frame.iter().map(|&s| s.to_sample::<f32>()).sum::<f32>() / channels as f32
-        };
+            (sample * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+        }));
+}
 
-        let sample_i16 = (sample * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+// ============================================================================
+// Transcription
+// ============================================================================
 
-        let _ = writer.write_sample(sample_i16);
-    }
+/// Final result of one utterance.
+struct UtteranceResult {
+    /// Utterance the server attributed the result to (None for stream errors)
+    utterance_id: Option<u32>,
+    text: std::result::Result<String, String>,
 }
 
-fn wait_for_stop_signal(stop_flag: &Arc<AtomicBool>) {
-    while !stop_flag.load(Ordering::Relaxed) {
-        std::thread::sleep(Duration::from_millis(100));
-    }
+/// One TranscribeStream call kept open for the whole conversation.
+///
+/// Each recording is sent as an utterance followed by an UtteranceBoundary;
+/// the server answers every boundary with a final text tagged with the
+/// utterance's index, in order.
+struct Session {
+    requests: mpsc::Sender<TranscribeStreamRequest>,
+    results: mpsc::Receiver<UtteranceResult>,
+    chunk_size: usize,
+    realtime: bool,
+    next_utterance: u32,
+    /// Longest wait for a transcript once its audio is sent
+    deadline: Option<Duration>,
 }
 
-fn finalize_wav_file(writer_arc: Arc<Mutex<WavWriter<BufWriter<File>>>>) -> SendResult<()> {
-    {
-        let mut writer = writer_arc.lock().unwrap();
-        writer.flush()?;
+impl Session {
+    async fn open(
+        client: &mut MurmureClient,
+        streaming: &StreamingOptions,
+        deadline: Option<Duration>,
+    ) -> Result<Self> {
+        let (requests, request_rx) = mpsc::channel(streaming.max_in_flight);
+        let response = client
+            .grpc()
+            .transcribe_stream(Request::new(ReceiverStream::new(request_rx)))
+            .await?;
+        if streaming.verbose {
+            metadata::print_engine_metadata(response.metadata());
+        }
+        let mut responses = response.into_inner();
+
+        let (result_tx, results) = mpsc::channel(16);
+        tokio::spawn(async move {
+            read_responses(&mut responses, result_tx).await;
+        });
+
+        Ok(Self {
+            requests,
+            results,
+            chunk_size: streaming.chunk_size,
+            realtime: streaming.realtime,
+            next_utterance: 0,
+            deadline,
+        })
     }
 
-    let writer = Arc::try_unwrap(writer_arc).map_err(|_| "Failed to unwrap Arc")?;
-
-    writer.into_inner().unwrap().finalize()?;
-    Ok(())
-}
-
-// ============================================================================
-// Transcription
-// ============================================================================
+    /// Send one recording as the next utterance and wait for its transcript.
+    async fn transcribe(
+        &mut self,
+        audio_data: Vec<u8>,
+        shutdown_flag: &AtomicBool,
+    ) -> Result<String> {
+        let utterance_id = self.next_utterance;
+        self.next_utterance += 1;
+
+        send_audio_chunks(
+            &self.requests,
+            audio_data,
+            self.chunk_size,
+            self.realtime,
+            shutdown_flag,
+        )
+        .await;
+        // Sent even after an interruption so the server transcribes what it has
+        self.requests
+            .send(TranscribeStreamRequest {
+                request_type: Some(
+                    murmure::transcribe_stream_request::RequestType::UtteranceBoundary(
+                        murmure::UtteranceBoundary {},
+                    ),
+                ),
+            })
+            .await
+            .map_err(|_| "Session stream closed")?;
+
+        loop {
+            let result = match self.deadline {
+                Some(deadline) => tokio::time::timeout(deadline, self.results.recv())
+                    .await
+                    .map_err(|_| {
+                        Status::deadline_exceeded(format!(
+                            "Deadline exceeded during transcription: no transcript within {:.0}s. Raise --timeout (0 = no deadline) for long recordings or a busy server.",
+                            deadline.as_secs_f32()
+                        ))
+                    })?,
+                None => self.results.recv().await,
+            }
+            .ok_or("Session stream closed by the server")?;
+            match result.utterance_id {
+                // Late transcript of a recording whose deadline ran out
+                Some(id) if id < utterance_id => continue,
+                Some(id) if id != utterance_id => {
+                    return Err(format!(
+                        "Out-of-order result: expected utterance {}, got {}",
+                        utterance_id, id
+                    )
+                    .into())
+                }
+                _ => return result.text.map_err(Into::into),
+            }
+        }
+    }
 
-async fn transcribe_audio(
-    client: &mut TranscriptionServiceClient<tonic::transport::Channel>,
-    audio_data: Vec<u8>,
-) -> Result<String> {
-    let request_stream = create_transcription_stream(audio_data);
-    let mut response_stream = client
-        .transcribe_stream(Request::new(request_stream))
-        .await?
-        .into_inner();
+    /// Pause or resume the session stream, in step with the recording.
+    async fn set_paused(&self, paused: bool) -> Result<()> {
+        let request_type = if paused {
+            murmure::transcribe_stream_request::RequestType::Pause(murmure::StreamPause {})
+        } else {
+            murmure::transcribe_stream_request::RequestType::Resume(murmure::StreamResume {})
+        };
+        self.requests
+            .send(TranscribeStreamRequest {
+                request_type: Some(request_type),
+            })
+            .await
+            .map_err(|_| "Session stream closed".into())
+    }
 
-    process_transcription_responses(&mut response_stream).await
+    async fn close(&self) {
+        send_end_of_stream(&self.requests).await;
+    }
 }
 
-fn create_transcription_stream(audio_data: Vec<u8>) -> ReceiverStream<TranscribeStreamRequest> {
-    let (chunk_tx, chunk_rx) = mpsc::channel(128);
+/// Forward the final result of every utterance, in the order received.
+async fn read_responses(
+    stream: &mut tonic::Streaming<TranscribeStreamResponse>,
+    results: mpsc::Sender<UtteranceResult>,
+) {
+    loop {
+        let response = match stream.message().await {
+            Ok(Some(response)) => response,
+            Ok(None) => return,
+            Err(status) => {
+                let _ = results
+                    .send(UtteranceResult {
+                        utterance_id: None,
+                        text: Err(format!("Stream error: {}", status.message())),
+                    })
+                    .await;
+                return;
+            }
+        };
 
-    tokio::spawn(async move {
-        send_audio_chunks(&chunk_tx, audio_data).await;
-        send_end_of_stream(&chunk_tx).await;
-    });
+        let text = match response.response_type {
+            Some(murmure::transcribe_stream_response::ResponseType::FinalText(text)) => {
+                println!(
+                    "\r   ⏱️  {:.1}s audio in {:.1}s ({:.1}x realtime)",
+                    response.audio_duration_secs,
+                    response.processing_time_ms as f64 / 1000.0,
+                    response.realtime_factor
+                );
+                Ok(text)
+            }
+            Some(murmure::transcribe_stream_response::ResponseType::Error(err)) => {
+                Err(format!("Server error: {}", err))
+            }
+            // Heartbeats only keep the stream alive through proxies
+            Some(murmure::transcribe_stream_response::ResponseType::Heartbeat(_)) => continue,
+            _ => continue,
+        };
 
-    ReceiverStream::new(chunk_rx)
+        let result = UtteranceResult {
+            utterance_id: Some(response.utterance_id),
+            text,
+        };
+        if results.send(result).await.is_err() {
+            return;
+        }
+    }
 }
 
-async fn send_audio_chunks(tx: &mpsc::Sender<TranscribeStreamRequest>, audio_data: Vec<u8>) {
-    const CHUNK_SIZE: usize = 16384; // 16KB chunks
+async fn send_audio_chunks(
+    tx: &mpsc::Sender<TranscribeStreamRequest>,
+    audio_data: Vec<u8>,
+    chunk_size: usize,
+    realtime: bool,
+    shutdown_flag: &AtomicBool,
+) {
+    let total = audio_data.len();
+    let byte_rate = wav_byte_rate(&audio_data).filter(|_| realtime);
+    let start = tokio::time::Instant::now();
+    let mut sent = 0;
+
+    for chunk in audio_data.chunks(chunk_size) {
+        if shutdown_flag.load(Ordering::Relaxed) {
+            println!("\n   ⚠️  Interrupted, requesting a partial transcript...");
+            return;
+        }
+
+        // Hold each chunk until the audio before it would have finished playing
+        if let Some(byte_rate) = byte_rate {
+            let due = start + Duration::from_secs_f64(sent as f64 / byte_rate as f64);
+            tokio::time::sleep_until(due).await;
+        }
 
-    for chunk in audio_data.chunks(CHUNK_SIZE) {
         let request = TranscribeStreamRequest {
             request_type: Some(murmure::transcribe_stream_request::RequestType::AudioChunk(
                 chunk.to_vec(),
             )),
         };
 
+        // Waits while max_in_flight chunks are still queued
         if tx.send(request).await.is_err() {
             return;
         }
+
+        sent += chunk.len();
+        print!(
+            "\r   📤 Sent {} / {} bytes ({:.0}%)",
+            sent,
+            total,
+            sent as f64 * 100.0 / total as f64
+        );
+        let _ = io::stdout().flush();
+    }
+    println!();
+}
+
+/// Byte rate from the `fmt ` header of a canonical WAV file.
+fn wav_byte_rate(audio_data: &[u8]) -> Option<u32> {
+    if audio_data.len() < 32 || &audio_data[0..4] != b"RIFF" || &audio_data[12..16] != b"fmt " {
+        return None;
     }
+    let byte_rate = u32::from_le_bytes(audio_data[28..32].try_into().ok()?);
+    (byte_rate > 0).then_some(byte_rate)
 }
 
 async fn send_end_of_stream(tx: &mpsc::Sender<TranscribeStreamRequest>) {
@@ -535,29 +951,3 @@ async fn send_end_of_stream(tx: &mpsc::Sender<TranscribeStreamRequest>) {
         })
         .await;
 }
-
-async fn process_transcription_responses(
-    stream: &mut tonic::Streaming<TranscribeStreamResponse>,
-) -> Result<String> {
-    let mut final_text = String::new();
-
-    while let Some(result) = stream.message().await.transpose() {
-        let response = result?;
-
-        match response.response_type {
-            Some(murmure::transcribe_stream_response::ResponseType::FinalText(text)) => {
-                final_text = text;
-            }
-            Some(murmure::transcribe_stream_response::ResponseType::Error(err)) => {
-                return Err(format!("Server error: {}", err).into());
-            }
-            _ => {}
-        }
-
-        if response.is_final {
-            break;
-        }
-    }
-
-    Ok(final_text)
-}
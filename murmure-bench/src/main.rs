@@ -0,0 +1,365 @@
+//! Latency and throughput benchmark for the Murmure speech-to-text engine
+//!
+//! Loads the engine in-process (no gRPC) with the same environment variables
+//! as the server, transcribes every WAV in a fixture directory and reports
+//! latency percentiles, realtime factor, throughput and peak memory for each
+//! concurrency level, along with the time and memory the model took to load.
+//!
+//! ## Usage
+//!
+//! ```bash
+//! export MURMURE_MODEL_PATH=resources/parakeet-tdt-0.6b-v3-int8
+//! cargo run --release -p murmure-bench -- --fixtures tests/voices --iterations 10
+//!
+//! # Machine-readable results for CI
+//! cargo run --release -p murmure-bench -- --json bench.json
+//! ```
+//!
+//! Options:
+//! - `--fixtures <dir>` - Directory of WAV files (default: tests/voices)
+//! - `--iterations <n>` - Passes over the fixtures per concurrency level (default: 5)
+//! - `--warmup <n>` - Passes run before measuring, excluded from stats (default: 1)
+//! - `--concurrency <list>` - Comma-separated worker counts (default: 1,2,4)
+//! - `--json <path>` - Also write the results as JSON (`-` for stdout)
+//!
+//! The JSON report also holds each fixture's transcript, so two runs can be
+//! checked for identical output, e.g. with `MURMURE_MMAP_MODEL=true` and
+//! `false`.
+
+use anyhow::{Context, Result};
+use murmure_stt::config::ServerConfig;
+use murmure_stt::dictionary::Dictionary;
+use murmure_stt::model::{ExecutionProvider, Model, ModelPrecision};
+use murmure_stt::transcription::{TranscriptionOptions, TranscriptionService};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct BenchArgs {
+    fixtures: PathBuf,
+    iterations: usize,
+    warmup: usize,
+    concurrency: Vec<usize>,
+    json: Option<String>,
+}
+
+struct Fixture {
+    name: String,
+    audio: Vec<u8>,
+}
+
+/// Measurements for one transcription.
+struct Sample {
+    latency: Duration,
+    audio_duration_secs: f32,
+}
+
+#[derive(Serialize)]
+struct LevelReport {
+    concurrency: usize,
+    requests: usize,
+    p50_latency_ms: f64,
+    p95_latency_ms: f64,
+    /// Seconds of audio per second of latency, averaged over requests
+    mean_realtime_factor: f64,
+    requests_per_sec: f64,
+    /// Seconds of audio transcribed per wall-clock second
+    audio_secs_per_sec: f64,
+    /// Process peak resident set size so far, in MiB (Linux only)
+    peak_rss_mb: Option<f64>,
+}
+
+/// A fixture's transcript from the pass before measuring.
+#[derive(Serialize)]
+struct FixtureTranscript {
+    fixture: String,
+    text: String,
+}
+
+#[derive(Serialize)]
+struct BenchReport {
+    version: &'static str,
+    execution_provider: Option<ExecutionProvider>,
+    model_precision: ModelPrecision,
+    mmap_model: bool,
+    /// Time to load the model, in milliseconds
+    model_load_ms: Option<u128>,
+    /// Resident memory added by loading the model, in MiB (Linux only)
+    model_resident_delta_mb: Option<f64>,
+    fixtures: Vec<String>,
+    transcripts: Vec<FixtureTranscript>,
+    iterations: usize,
+    warmup: usize,
+    levels: Vec<LevelReport>,
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+
+    let config = Arc::new(ServerConfig::from_env()?);
+    let fixtures = load_fixtures(&args.fixtures)?;
+    eprintln!(
+        "Loaded {} fixture(s) from {}",
+        fixtures.len(),
+        args.fixtures.display()
+    );
+
+    let model = Arc::new(Model::new((*config).clone()));
+    let status = model.availability();
+    if !status.is_available() {
+        anyhow::bail!("Model is not available.\n{}", status);
+    }
+    let dictionary = (!config.dictionary.is_empty())
+        .then(|| Arc::new(Dictionary::new(config.dictionary.clone())));
+    let service = Arc::new(TranscriptionService::new(
+        model,
+        dictionary,
+        config.clone(),
+        Vec::new(),
+    )?);
+    service.warm_up()?;
+    let load_stats = service.get_model().load_stats();
+    if let Some(stats) = load_stats {
+        eprintln!(
+            "Model loaded in {} ms (mmap {})",
+            stats.load_time.as_millis(),
+            if config.mmap_model { "on" } else { "off" }
+        );
+    }
+
+    let options = TranscriptionOptions::from_config(&config);
+    let mut transcripts = Vec::with_capacity(fixtures.len());
+    for fixture in &fixtures {
+        let transcript = service.transcribe_audio_bytes_with_options(&fixture.audio, &options)?;
+        transcripts.push(FixtureTranscript {
+            fixture: fixture.name.clone(),
+            text: transcript.text,
+        });
+    }
+    for pass in 0..args.warmup {
+        eprintln!("Warm-up pass {}/{}", pass + 1, args.warmup);
+        for fixture in &fixtures {
+            service.transcribe_audio_bytes_with_options(&fixture.audio, &options)?;
+        }
+    }
+
+    let mut levels = Vec::with_capacity(args.concurrency.len());
+    for &workers in &args.concurrency {
+        eprintln!("Measuring with {} worker(s)...", workers);
+        let (samples, wall) = run_level(&service, &options, &fixtures, args.iterations, workers)?;
+        let level = summarize(workers, &samples, wall);
+        print_level(&level);
+        levels.push(level);
+    }
+
+    if let Some(target) = &args.json {
+        let report = BenchReport {
+            version: env!("CARGO_PKG_VERSION"),
+            execution_provider: service.execution_provider(),
+            model_precision: service.get_model().precision(),
+            mmap_model: config.mmap_model,
+            model_load_ms: load_stats.map(|stats| stats.load_time.as_millis()),
+            model_resident_delta_mb: load_stats
+                .and_then(|stats| stats.resident_delta_bytes)
+                .map(|bytes| bytes as f64 / (1024.0 * 1024.0)),
+            fixtures: fixtures.iter().map(|f| f.name.clone()).collect(),
+            transcripts,
+            iterations: args.iterations,
+            warmup: args.warmup,
+            levels,
+        };
+        let json = serde_json::to_string_pretty(&report)?;
+        if target == "-" {
+            println!("{}", json);
+        } else {
+            std::fs::write(target, json).with_context(|| format!("Failed to write {}", target))?;
+            eprintln!("Results written to {}", target);
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_args() -> Result<BenchArgs> {
+    let args: Vec<String> = std::env::args().collect();
+    let value = |flag: &str| {
+        args.iter()
+            .position(|a| a == flag)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+
+    let concurrency = match value("--concurrency") {
+        Some(list) => list
+            .split(',')
+            .map(|n| n.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()
+            .context("--concurrency must be a comma-separated list of numbers")?,
+        None => vec![1, 2, 4],
+    };
+    if concurrency.contains(&0) {
+        anyhow::bail!("--concurrency values must be at least 1");
+    }
+
+    let iterations = match value("--iterations") {
+        Some(n) => n.parse().context("--iterations must be a number")?,
+        None => 5,
+    };
+    if iterations == 0 {
+        anyhow::bail!("--iterations must be at least 1");
+    }
+
+    Ok(BenchArgs {
+        fixtures: value("--fixtures")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("tests/voices")),
+        iterations,
+        warmup: match value("--warmup") {
+            Some(n) => n.parse().context("--warmup must be a number")?,
+            None => 1,
+        },
+        concurrency,
+        json: value("--json"),
+    })
+}
+
+/// Read every `.wav` file in `dir`, sorted by name.
+fn load_fixtures(dir: &Path) -> Result<Vec<Fixture>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read fixture directory {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("wav"))
+        })
+        .collect();
+    paths.sort();
+
+    if paths.is_empty() {
+        anyhow::bail!("No .wav fixtures found in {}", dir.display());
+    }
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let audio = std::fs::read(&path)
+                .with_context(|| format!("Failed to read fixture {}", path.display()))?;
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            Ok(Fixture { name, audio })
+        })
+        .collect()
+}
+
+/// Transcribe `iterations` passes over the fixtures with `workers` threads
+/// pulling from a shared queue. Returns per-request samples and wall time.
+fn run_level(
+    service: &TranscriptionService,
+    options: &TranscriptionOptions,
+    fixtures: &[Fixture],
+    iterations: usize,
+    workers: usize,
+) -> Result<(Vec<Sample>, Duration)> {
+    let total = fixtures.len() * iterations;
+    let next = AtomicUsize::new(0);
+    let samples = Mutex::new(Vec::with_capacity(total));
+
+    let start = Instant::now();
+    std::thread::scope(|scope| -> Result<()> {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= total {
+                            return Ok(());
+                        }
+                        let fixture = &fixtures[index % fixtures.len()];
+                        let request_start = Instant::now();
+                        let transcript =
+                            service.transcribe_audio_bytes_with_options(&fixture.audio, options)?;
+                        samples.lock().unwrap().push(Sample {
+                            latency: request_start.elapsed(),
+                            audio_duration_secs: transcript.audio_duration_secs,
+                        });
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("Benchmark worker panicked"))??;
+        }
+        Ok(())
+    })?;
+    let wall = start.elapsed();
+
+    Ok((samples.into_inner().unwrap(), wall))
+}
+
+fn summarize(concurrency: usize, samples: &[Sample], wall: Duration) -> LevelReport {
+    let mut latencies: Vec<f64> = samples
+        .iter()
+        .map(|s| s.latency.as_secs_f64() * 1000.0)
+        .collect();
+    latencies.sort_by(f64::total_cmp);
+
+    let mean_realtime_factor = samples
+        .iter()
+        .map(|s| s.audio_duration_secs as f64 / s.latency.as_secs_f64().max(f64::EPSILON))
+        .sum::<f64>()
+        / samples.len().max(1) as f64;
+    let audio_secs: f64 = samples.iter().map(|s| s.audio_duration_secs as f64).sum();
+    let wall_secs = wall.as_secs_f64().max(f64::EPSILON);
+
+    LevelReport {
+        concurrency,
+        requests: samples.len(),
+        p50_latency_ms: percentile(&latencies, 50.0),
+        p95_latency_ms: percentile(&latencies, 95.0),
+        mean_realtime_factor,
+        requests_per_sec: samples.len() as f64 / wall_secs,
+        audio_secs_per_sec: audio_secs / wall_secs,
+        peak_rss_mb: peak_rss_mb(),
+    }
+}
+
+/// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[f64], pct: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Peak resident set size of this process, from `/proc/self/status`.
+fn peak_rss_mb() -> Option<f64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: f64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb / 1024.0)
+}
+
+fn print_level(level: &LevelReport) {
+    println!(
+        "concurrency {:>2}: {:>4} requests | p50 {:>8.1} ms | p95 {:>8.1} ms | {:>6.1}x realtime | {:>6.2} req/s | {:>7.1} audio s/s | peak RSS {}",
+        level.concurrency,
+        level.requests,
+        level.p50_latency_ms,
+        level.p95_latency_ms,
+        level.mean_realtime_factor,
+        level.requests_per_sec,
+        level.audio_secs_per_sec,
+        level
+            .peak_rss_mb
+            .map(|mb| format!("{:.0} MiB", mb))
+            .unwrap_or_else(|| "n/a".to_string())
+    );
+}
@@ -0,0 +1,250 @@
+// One API over the in-process engine and the gRPC server
+//
+// `Murmure` tries the backends its policy allows, in the policy's order, and
+// moves on to the next one when a backend is unavailable: the server can't be
+// reached (connection refused, timed out, UNAVAILABLE), or the local engine
+// failed to load or stayed busy. Any other failure (invalid audio, a rejected
+// API key) is the answer for that audio and is returned as is, since the
+// other backend would fail the same way or hide a misconfiguration.
+//
+// The local backend needs the `local` feature, which links murmure-stt and
+// the ONNX runtime. Without it the local variants don't exist and the local
+// policies only ever find the server.
+
+use crate::{ClientError, ClientOptions, MurmureClient, TranscriptionResult};
+
+#[cfg(feature = "local")]
+use murmure_stt::{Transcript, TranscriptionOptions, TranscriptionService};
+
+/// Which backends a [`Murmure`] uses, and in which order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BackendPolicy {
+    /// The local engine when it is loaded, the server otherwise
+    PreferLocal,
+    /// The server, the local engine when the server can't be reached
+    #[default]
+    PreferRemote,
+    /// Only the local engine
+    LocalOnly,
+    /// Only the server
+    RemoteOnly,
+}
+
+impl BackendPolicy {
+    fn order(self) -> &'static [BackendKind] {
+        match self {
+            Self::PreferLocal => &[BackendKind::Local, BackendKind::Remote],
+            Self::PreferRemote => &[BackendKind::Remote, BackendKind::Local],
+            Self::LocalOnly => &[BackendKind::Local],
+            Self::RemoteOnly => &[BackendKind::Remote],
+        }
+    }
+}
+
+/// Where a transcription ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Local,
+    Remote,
+}
+
+/// A single way of transcribing audio.
+#[derive(Clone)]
+pub enum TranscriptionBackend {
+    /// The engine running in this process
+    #[cfg(feature = "local")]
+    Local(TranscriptionService),
+    /// A connection to a Murmure server
+    Remote(MurmureClient),
+}
+
+impl TranscriptionBackend {
+    pub fn kind(&self) -> BackendKind {
+        match self {
+            #[cfg(feature = "local")]
+            Self::Local(_) => BackendKind::Local,
+            Self::Remote(_) => BackendKind::Remote,
+        }
+    }
+
+    /// Transcribe a complete WAV file with the defaults of the engine's
+    /// configuration or of the server, and the dictionary enabled.
+    pub async fn transcribe_wav_bytes(
+        &mut self,
+        wav: &[u8],
+    ) -> Result<TranscriptionResult, ClientError> {
+        match self {
+            #[cfg(feature = "local")]
+            Self::Local(service) => {
+                let options = TranscriptionOptions::from_config(service.get_config());
+                let transcript = service
+                    .transcribe_audio_bytes_async(wav.to_vec(), options)
+                    .await?;
+                Ok(from_transcript(transcript))
+            }
+            Self::Remote(client) => client.transcribe_wav_bytes(wav).await,
+        }
+    }
+}
+
+/// Transcription through whichever backend the policy allows and is
+/// available.
+///
+/// ```no_run
+/// use murmure_client::{BackendPolicy, ClientOptions, Murmure};
+///
+/// # async fn run(wav: Vec<u8>) -> Result<(), murmure_client::ClientError> {
+/// let mut murmure = Murmure::new(BackendPolicy::PreferRemote)
+///     .with_remote(ClientOptions::new("http://localhost:50051"));
+/// let result = murmure.transcribe_wav_bytes(&wav).await?;
+/// println!("{} ({:?})", result.text, murmure.last_backend());
+/// # Ok(())
+/// # }
+/// ```
+pub struct Murmure {
+    policy: BackendPolicy,
+    #[cfg(feature = "local")]
+    local: Option<TranscriptionService>,
+    remote_options: Option<ClientOptions>,
+    /// Connected on first use, and again on later calls while connecting fails
+    remote: Option<MurmureClient>,
+    last_backend: Option<BackendKind>,
+}
+
+impl Murmure {
+    /// A facade with no backend yet; add them with [`Self::with_remote`]
+    /// and `with_local`.
+    pub fn new(policy: BackendPolicy) -> Self {
+        Self {
+            policy,
+            #[cfg(feature = "local")]
+            local: None,
+            remote_options: None,
+            remote: None,
+            last_backend: None,
+        }
+    }
+
+    /// Use the server at `options.endpoint`. Nothing is connected until the
+    /// first transcription that tries it.
+    pub fn with_remote(mut self, options: ClientOptions) -> Self {
+        self.remote_options = Some(options);
+        self.remote = None;
+        self
+    }
+
+    /// Use an already connected client.
+    pub fn with_client(mut self, client: MurmureClient) -> Self {
+        self.remote = Some(client);
+        self
+    }
+
+    /// Use the in-process engine. Build the service when the model is
+    /// present (`TranscriptionError::ModelNotFound` otherwise) and leave the
+    /// local backend out when it isn't.
+    #[cfg(feature = "local")]
+    pub fn with_local(mut self, service: TranscriptionService) -> Self {
+        self.local = Some(service);
+        self
+    }
+
+    pub fn policy(&self) -> BackendPolicy {
+        self.policy
+    }
+
+    /// Backend of the last successful transcription.
+    pub fn last_backend(&self) -> Option<BackendKind> {
+        self.last_backend
+    }
+
+    /// Transcribe a complete WAV file, failing over to the next backend of
+    /// the policy when one is unavailable. The error of the last backend
+    /// tried is returned when none succeeds, [`ClientError::NoBackend`] when
+    /// the policy allows none that is configured.
+    pub async fn transcribe_wav_bytes(
+        &mut self,
+        wav: &[u8],
+    ) -> Result<TranscriptionResult, ClientError> {
+        let mut last_error = None;
+        for &kind in self.policy.order() {
+            let mut backend = match self.backend(kind).await {
+                Ok(Some(backend)) => backend,
+                Ok(None) => continue,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            match backend.transcribe_wav_bytes(wav).await {
+                Err(e) if e.is_unavailable() => last_error = Some(e),
+                result => {
+                    if result.is_ok() {
+                        self.last_backend = Some(kind);
+                    }
+                    return result;
+                }
+            }
+        }
+        Err(last_error.unwrap_or(ClientError::NoBackend))
+    }
+
+    /// The backend of `kind`, connecting to the server if it isn't yet.
+    /// `None` when it isn't configured.
+    async fn backend(
+        &mut self,
+        kind: BackendKind,
+    ) -> Result<Option<TranscriptionBackend>, ClientError> {
+        match kind {
+            #[cfg(feature = "local")]
+            BackendKind::Local => Ok(self.local.clone().map(TranscriptionBackend::Local)),
+            #[cfg(not(feature = "local"))]
+            BackendKind::Local => Ok(None),
+            BackendKind::Remote => {
+                if self.remote.is_none() {
+                    let Some(options) = &self.remote_options else {
+                        return Ok(None);
+                    };
+                    self.remote = Some(MurmureClient::connect(options.clone()).await?);
+                }
+                Ok(self.remote.clone().map(TranscriptionBackend::Remote))
+            }
+        }
+    }
+}
+
+/// The same result the server would have returned for the transcript, with
+/// no response metadata.
+#[cfg(feature = "local")]
+fn from_transcript(transcript: Transcript) -> TranscriptionResult {
+    use crate::proto::{Correction, Segment};
+
+    let realtime_factor = transcript.realtime_factor();
+    TranscriptionResult {
+        segments: transcript
+            .segments
+            .into_iter()
+            .map(|segment| Segment {
+                start: segment.start,
+                end: segment.end,
+                text: segment.text,
+                channel: segment.channel.map(|channel| channel as i32),
+            })
+            .collect(),
+        corrections: transcript
+            .corrections
+            .into_iter()
+            .map(|correction| Correction {
+                original: correction.original,
+                replacement: correction.replacement,
+                offset: correction.offset as u32,
+            })
+            .collect(),
+        text: transcript.text,
+        audio_duration_secs: transcript.audio_duration_secs,
+        processing_time_ms: transcript.processing_time_ms,
+        realtime_factor,
+        // The server only returns stage timings when asked to
+        timing: None,
+        metadata: Default::default(),
+    }
+}
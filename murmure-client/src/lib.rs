@@ -0,0 +1,550 @@
+//! Rust client for the Murmure gRPC server
+//!
+//! Embeds the generated stubs and wraps them with connection options (TLS,
+//! API key, compression, timeouts) and helpers for the request building and
+//! stream plumbing every consumer would otherwise write by hand.
+//!
+//! ```no_run
+//! use murmure_client::{ClientOptions, MurmureClient};
+//!
+//! # async fn run(wav: Vec<u8>) -> Result<(), murmure_client::ClientError> {
+//! let options = ClientOptions::new("http://localhost:50051");
+//! let mut client = MurmureClient::connect(options).await?;
+//!
+//! let result = client.transcribe_wav_bytes(&wav).await?;
+//! println!("{}", result.text);
+//!
+//! // Same audio through TranscribeStream, in chunks of `options.chunk_size`
+//! let chunks = client.wav_chunks(wav.into());
+//! let result = client.transcribe_stream(chunks).await?;
+//! println!("{}", result.text);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The generated messages and the raw stub are available in [`proto`] and
+//! through [`MurmureClient::grpc`] for anything the helpers don't cover
+//! (session streams, large file uploads, history).
+//!
+//! ## Local fallback
+//!
+//! [`Murmure`] transcribes with the in-process engine or the server,
+//! whichever its [`BackendPolicy`] prefers and is available, and fails over
+//! to the other when the server can't be reached or the engine can't be
+//! used. Results are the same [`TranscriptionResult`] either way. The local
+//! engine needs the `local` feature, which pulls in murmure-stt and the ONNX
+//! runtime.
+//!
+//! ## Compatibility
+//!
+//! The client is built from `proto/murmure.proto` of the same release and
+//! shares the server's version number. Within a major version the proto only
+//! grows: new fields and RPCs get new numbers, existing fields are never
+//! renumbered, retyped or removed. A client therefore works against any
+//! server of the same major version. Fields an older server doesn't know are
+//! ignored by it, fields it doesn't send read as their defaults, and RPCs it
+//! lacks fail with `UNIMPLEMENTED`.
+//!
+//! The server has no speech synthesis yet, so there is no synthesis helper.
+
+mod backend;
+
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::codec::CompressionEncoding;
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tonic::service::interceptor::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint};
+use tonic::{Request, Response, Status};
+
+/// Code generated from `proto/murmure.proto`
+pub use murmure_proto as proto;
+
+pub use backend::{BackendKind, BackendPolicy, Murmure, TranscriptionBackend};
+
+use proto::transcribe_stream_request::RequestType;
+use proto::transcribe_stream_response::ResponseType;
+use proto::transcription_service_client::TranscriptionServiceClient;
+use proto::{
+    CancelJobRequest, CorrectTextRequest, CorrectTextResponse, Correction, GetJobResultRequest,
+    GetJobStatusRequest, GetServerInfoRequest, GetServerInfoResponse, GetUsageRequest,
+    GetUsageResponse, JobStatus, ListModelsRequest, ListModelsResponse, Segment, StageTimings,
+    StreamConfig, SubmitTranscriptionJobRequest, SubmitTranscriptionJobResponse,
+    TranscribeFileRequest, TranscribeFileResponse, TranscribeStreamRequest, TranscribeUrlRequest,
+};
+
+/// Metadata key the server identifies API keys by
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Channel type of the raw stub returned by [`MurmureClient::grpc`]
+pub type Channel = InterceptedService<tonic::transport::Channel, ApiKeyInterceptor>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    /// The endpoint is invalid or the connection could not be established
+    #[error("connection failed: {0}")]
+    Transport(#[from] tonic::transport::Error),
+    /// The server rejected the request
+    #[error("request failed: {0}")]
+    Status(Box<Status>),
+    /// The server accepted the request but could not transcribe the audio
+    #[error("transcription failed: {0}")]
+    Transcription(String),
+    /// The API key can't be sent as gRPC metadata
+    #[error("API key contains characters not allowed in gRPC metadata")]
+    InvalidApiKey,
+    /// The in-process engine could not transcribe the audio
+    #[cfg(feature = "local")]
+    #[error("local transcription failed: {0}")]
+    Local(#[from] murmure_stt::TranscriptionError),
+    /// The [`Murmure`] policy allows no backend that is configured
+    #[error("no transcription backend available for the policy")]
+    NoBackend,
+}
+
+impl ClientError {
+    /// Whether the backend couldn't be reached or used at all, as opposed to
+    /// failing on this request. [`Murmure`] fails over on these.
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            Self::Transport(_) => true,
+            Self::Status(status) => status.code() == tonic::Code::Unavailable,
+            #[cfg(feature = "local")]
+            Self::Local(error) => matches!(
+                error,
+                murmure_stt::TranscriptionError::EngineLoad(_)
+                    | murmure_stt::TranscriptionError::EngineUnavailable(_)
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        Self::Status(Box::new(status))
+    }
+}
+
+/// How to reach the server.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Server address; `https://` enables TLS
+    pub endpoint: String,
+    /// Sent as `x-api-key` metadata with every request
+    pub api_key: Option<String>,
+    /// PEM root certificate trusted in addition to the system roots (enables TLS)
+    pub ca_certificate: Option<Vec<u8>>,
+    /// Name the server certificate is checked against (default: endpoint host)
+    pub tls_domain: Option<String>,
+    /// Compress requests with gzip and accept gzip responses
+    pub compression: bool,
+    /// Time allowed to establish the connection
+    pub connect_timeout: Duration,
+    /// Deadline of each request, streams included (`None` = no deadline)
+    pub request_timeout: Option<Duration>,
+    /// Interval of TCP keepalive probes and HTTP/2 pings, which detect a
+    /// dead connection while no request is running (`None` = off)
+    pub keepalive: Option<Duration>,
+    /// Size of the chunks produced by [`MurmureClient::wav_chunks`]
+    pub chunk_size: usize,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:50051".to_string(),
+            api_key: None,
+            ca_certificate: None,
+            tls_domain: None,
+            compression: false,
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: None,
+            keepalive: None,
+            chunk_size: 8192,
+        }
+    }
+}
+
+impl ClientOptions {
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Adds the API key to every request.
+#[derive(Clone)]
+pub struct ApiKeyInterceptor {
+    api_key: Option<AsciiMetadataValue>,
+}
+
+impl Interceptor for ApiKeyInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(api_key) = &self.api_key {
+            request
+                .metadata_mut()
+                .insert(API_KEY_HEADER, api_key.clone());
+        }
+        Ok(request)
+    }
+}
+
+/// Transcript and timing of a successful transcription.
+#[derive(Debug, Clone, Default)]
+pub struct TranscriptionResult {
+    /// Transcribed text (rendered in the requested format for TranscribeFile)
+    pub text: String,
+    /// Pause-delimited segments, when segmentation was enabled
+    pub segments: Vec<Segment>,
+    /// Dictionary corrections applied to the text
+    pub corrections: Vec<Correction>,
+    /// Duration of the decoded audio in seconds
+    pub audio_duration_secs: f32,
+    /// Time spent in the transcription engine, in milliseconds
+    pub processing_time_ms: u64,
+    /// Audio duration divided by processing time
+    pub realtime_factor: f32,
+    /// Time spent in each server pipeline stage, when `include_timing` was set
+    pub timing: Option<StageTimings>,
+    /// Response metadata: server version, model, dictionary revision
+    pub metadata: MetadataMap,
+}
+
+impl TranscriptionResult {
+    /// Convert a TranscribeFile or TranscribeLargeFile response, turning an
+    /// unsuccessful one into [`ClientError::Transcription`].
+    pub fn from_file_response(
+        response: Response<TranscribeFileResponse>,
+    ) -> Result<Self, ClientError> {
+        let metadata = response.metadata().clone();
+        let response = response.into_inner();
+        if !response.success {
+            return Err(ClientError::Transcription(response.error));
+        }
+        Ok(Self {
+            text: response.text,
+            segments: response.segments,
+            corrections: response.corrections,
+            audio_duration_secs: response.audio_duration_secs,
+            processing_time_ms: response.processing_time_ms,
+            realtime_factor: response.realtime_factor,
+            timing: response.timing,
+            metadata,
+        })
+    }
+}
+
+/// Connection to a Murmure server.
+#[derive(Clone)]
+pub struct MurmureClient {
+    inner: TranscriptionServiceClient<Channel>,
+    chunk_size: usize,
+}
+
+impl MurmureClient {
+    pub async fn connect(options: ClientOptions) -> Result<Self, ClientError> {
+        let mut endpoint = Endpoint::from_shared(options.endpoint.clone())?
+            .connect_timeout(options.connect_timeout);
+        if let Some(timeout) = options.request_timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(interval) = options.keepalive {
+            endpoint = endpoint
+                .tcp_keepalive(Some(interval))
+                .http2_keep_alive_interval(interval)
+                .keep_alive_while_idle(true);
+        }
+        if options.endpoint.starts_with("https://") || options.ca_certificate.is_some() {
+            let mut tls = ClientTlsConfig::new().with_native_roots();
+            if let Some(pem) = &options.ca_certificate {
+                tls = tls.ca_certificate(Certificate::from_pem(pem));
+            }
+            if let Some(domain) = &options.tls_domain {
+                tls = tls.domain_name(domain.clone());
+            }
+            endpoint = endpoint.tls_config(tls)?;
+        }
+
+        let api_key = options
+            .api_key
+            .as_deref()
+            .map(|key| key.parse().map_err(|_| ClientError::InvalidApiKey))
+            .transpose()?;
+        let channel = endpoint.connect().await?;
+
+        let mut inner =
+            TranscriptionServiceClient::with_interceptor(channel, ApiKeyInterceptor { api_key });
+        if options.compression {
+            inner = inner
+                .send_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Gzip);
+        }
+
+        Ok(Self {
+            inner,
+            chunk_size: options.chunk_size.max(1),
+        })
+    }
+
+    /// The generated stub, for RPCs and options the helpers don't cover.
+    pub fn grpc(&mut self) -> &mut TranscriptionServiceClient<Channel> {
+        &mut self.inner
+    }
+
+    /// Transcribe a complete WAV file with the server defaults and the
+    /// dictionary enabled.
+    pub async fn transcribe_wav_bytes(
+        &mut self,
+        wav: &[u8],
+    ) -> Result<TranscriptionResult, ClientError> {
+        self.transcribe_file(TranscribeFileRequest {
+            audio_data: wav.to_vec(),
+            use_dictionary: true,
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Send a TranscribeFile request with explicit options.
+    pub async fn transcribe_file(
+        &mut self,
+        request: TranscribeFileRequest,
+    ) -> Result<TranscriptionResult, ClientError> {
+        let response = self.inner.transcribe_file(request).await?;
+        TranscriptionResult::from_file_response(response)
+    }
+
+    /// Split a WAV payload into chunks of the configured size for
+    /// [`Self::transcribe_stream`].
+    pub fn wav_chunks(&self, wav: Bytes) -> impl Stream<Item = Bytes> + Send + 'static {
+        let chunk_size = self.chunk_size;
+        let len = wav.len();
+        tokio_stream::iter(
+            (0..len)
+                .step_by(chunk_size)
+                .map(move |start| wav.slice(start..(start + chunk_size).min(len))),
+        )
+    }
+
+    /// Stream WAV audio through TranscribeStream as a single utterance.
+    pub async fn transcribe_stream<S>(
+        &mut self,
+        audio: S,
+    ) -> Result<TranscriptionResult, ClientError>
+    where
+        S: Stream<Item = Bytes> + Send + 'static,
+    {
+        self.transcribe_stream_with(audio, None, |_, _| {}).await
+    }
+
+    /// Stream WAV audio through TranscribeStream as a single utterance, with
+    /// optional stream options. `on_partial` is called with each partial
+    /// transcript as it arrives, along with its segment when segmentation is
+    /// enabled.
+    pub async fn transcribe_stream_with<S, F>(
+        &mut self,
+        audio: S,
+        config: Option<StreamConfig>,
+        mut on_partial: F,
+    ) -> Result<TranscriptionResult, ClientError>
+    where
+        S: Stream<Item = Bytes> + Send + 'static,
+        F: FnMut(&str, Option<&Segment>),
+    {
+        let (tx, rx) = mpsc::channel(32);
+        tokio::spawn(async move {
+            if let Some(config) = config {
+                let request = TranscribeStreamRequest {
+                    request_type: Some(RequestType::Config(config)),
+                };
+                if tx.send(request).await.is_err() {
+                    return;
+                }
+            }
+            tokio::pin!(audio);
+            while let Some(chunk) = audio.next().await {
+                let request = TranscribeStreamRequest {
+                    request_type: Some(RequestType::AudioChunk(chunk.to_vec())),
+                };
+                if tx.send(request).await.is_err() {
+                    return;
+                }
+            }
+            let _ = tx
+                .send(TranscribeStreamRequest {
+                    request_type: Some(RequestType::EndOfStream(true)),
+                })
+                .await;
+        });
+
+        let response = self
+            .inner
+            .transcribe_stream(Request::new(ReceiverStream::new(rx)))
+            .await?;
+        let mut result = TranscriptionResult {
+            metadata: response.metadata().clone(),
+            ..Default::default()
+        };
+        let mut responses = response.into_inner();
+
+        while let Some(response) = responses.message().await? {
+            match response.response_type {
+                Some(ResponseType::PartialText(text)) => {
+                    on_partial(&text, response.segment.as_ref());
+                    result.segments.extend(response.segment);
+                }
+                Some(ResponseType::FinalText(text)) => {
+                    result.text = text;
+                    result.corrections = response.corrections;
+                    result.audio_duration_secs = response.audio_duration_secs;
+                    result.processing_time_ms = response.processing_time_ms;
+                    result.realtime_factor = response.realtime_factor;
+                    result.timing = response.timing;
+                    return Ok(result);
+                }
+                Some(ResponseType::Error(error)) => {
+                    return Err(ClientError::Transcription(error));
+                }
+                // Keepalive while the server waits for audio or transcribes
+                Some(ResponseType::Heartbeat(_)) | None => {}
+                // A single utterance has nothing to resume
+                Some(ResponseType::Session(_)) => {}
+            }
+        }
+
+        Err(ClientError::Transcription(
+            "stream ended without a final transcript".to_string(),
+        ))
+    }
+
+    pub async fn server_info(&mut self) -> Result<GetServerInfoResponse, ClientError> {
+        Ok(self
+            .inner
+            .get_server_info(GetServerInfoRequest {})
+            .await?
+            .into_inner())
+    }
+
+    pub async fn list_models(&mut self) -> Result<ListModelsResponse, ClientError> {
+        Ok(self
+            .inner
+            .list_models(ListModelsRequest {})
+            .await?
+            .into_inner())
+    }
+
+    /// Run text transcribed elsewhere through the server's dictionary
+    /// correction, or through `words` when it isn't empty. A `language`
+    /// (`fr`, `en`, ...) selects the server's dictionary and cc-rules for
+    /// it; empty uses the default ones.
+    pub async fn correct_text(
+        &mut self,
+        text: impl Into<String>,
+        words: Vec<String>,
+        language: &str,
+    ) -> Result<CorrectTextResponse, ClientError> {
+        Ok(self
+            .inner
+            .correct_text(CorrectTextRequest {
+                text: text.into(),
+                words,
+                language: language.to_string(),
+            })
+            .await?
+            .into_inner())
+    }
+
+    /// Have the server fetch a WAV file from `url` and transcribe it. The URL
+    /// must be under one of the server's MURMURE_URL_ALLOWLIST prefixes.
+    pub async fn transcribe_url(
+        &mut self,
+        url: &str,
+        options: Option<StreamConfig>,
+    ) -> Result<TranscriptionResult, ClientError> {
+        let response = self
+            .inner
+            .transcribe_url(TranscribeUrlRequest {
+                url: url.to_string(),
+                options,
+            })
+            .await?;
+        TranscriptionResult::from_file_response(response)
+    }
+
+    /// Queue a WAV file for background transcription. Poll the returned
+    /// `job_id` with [`Self::job_status`], then fetch the transcript with
+    /// [`Self::job_result`].
+    pub async fn submit_job(
+        &mut self,
+        wav: Vec<u8>,
+        options: Option<StreamConfig>,
+    ) -> Result<SubmitTranscriptionJobResponse, ClientError> {
+        Ok(self
+            .inner
+            .submit_transcription_job(SubmitTranscriptionJobRequest {
+                audio_data: wav,
+                options,
+            })
+            .await?
+            .into_inner())
+    }
+
+    pub async fn job_status(&mut self, job_id: &str) -> Result<JobStatus, ClientError> {
+        Ok(self
+            .inner
+            .get_job_status(GetJobStatusRequest {
+                job_id: job_id.to_string(),
+            })
+            .await?
+            .into_inner())
+    }
+
+    /// Transcript of a finished job; fails while it is queued or running.
+    pub async fn job_result(&mut self, job_id: &str) -> Result<TranscriptionResult, ClientError> {
+        let response = self
+            .inner
+            .get_job_result(GetJobResultRequest {
+                job_id: job_id.to_string(),
+            })
+            .await?;
+        TranscriptionResult::from_file_response(response)
+    }
+
+    pub async fn cancel_job(&mut self, job_id: &str) -> Result<JobStatus, ClientError> {
+        Ok(self
+            .inner
+            .cancel_job(CancelJobRequest {
+                job_id: job_id.to_string(),
+            })
+            .await?
+            .into_inner())
+    }
+
+    /// Audio transcribed between two times in milliseconds since the Unix
+    /// epoch (0 = no bound). The server reports the caller's own usage, or
+    /// that of every client (`client` only when not empty) for a request
+    /// carrying its admin token.
+    pub async fn usage(
+        &mut self,
+        from_unix_ms: u64,
+        to_unix_ms: u64,
+        client: &str,
+    ) -> Result<GetUsageResponse, ClientError> {
+        Ok(self
+            .inner
+            .get_usage(GetUsageRequest {
+                from_unix_ms,
+                to_unix_ms,
+                client: client.to_string(),
+            })
+            .await?
+            .into_inner())
+    }
+}
@@ -0,0 +1,8 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Only generate the sides the enabled features ask for
+    tonic_build::configure()
+        .build_server(std::env::var_os("CARGO_FEATURE_SERVER").is_some())
+        .build_client(std::env::var_os("CARGO_FEATURE_CLIENT").is_some())
+        .compile_protos(&["../proto/murmure.proto"], &["../proto"])?;
+    Ok(())
+}
@@ -0,0 +1,8 @@
+//! Code generated from `proto/murmure.proto`
+//!
+//! The server and the Rust client both depend on this crate, so the schema is
+//! compiled in one place and both always agree on it. The `client` feature
+//! (on by default) generates `transcription_service_client`, the `server`
+//! feature `transcription_service_server`.
+
+include!(concat!(env!("OUT_DIR"), "/murmure.rs"));
@@ -0,0 +1,381 @@
+// Startup checks, shown as a table by `--check` and as the banner of `serve`
+//
+// Each component the server needs is checked on its own, so a deployment
+// image can be verified in CI without loading the model or serving: the
+// configuration, the model files, the cc-rules, the writable directories and
+// the ports. Nothing is left behind except the directories that would be
+// created at startup anyway.
+
+use serde_json::{json, Value};
+use std::fmt;
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+
+use murmure_stt::config::ServerConfig;
+use murmure_stt::model::Model;
+use murmure_stt::tempfiles;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    /// Works, but probably not as intended
+    Warn,
+    /// The server can't start, or can't serve requests, like this
+    Fail,
+    /// Not configured, or not part of this build
+    Skip,
+}
+
+impl CheckStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Warn => "warn",
+            Self::Fail => "fail",
+            Self::Skip => "skip",
+        }
+    }
+}
+
+/// Outcome of checking one component.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub component: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(component: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            component: component.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    /// `component  status  detail`, the component padded to `width`.
+    fn row(&self, width: usize) -> String {
+        format!(
+            "{:<width$}  {:<4}  {}",
+            self.component,
+            self.status.as_str(),
+            self.detail,
+            width = width
+        )
+    }
+}
+
+/// Results of all checks, in the order they ran.
+#[derive(Debug, Clone, Default)]
+pub struct CheckReport {
+    pub checks: Vec<Check>,
+}
+
+impl CheckReport {
+    /// Check everything `config` needs. When the configuration has errors,
+    /// the other components are still checked, so one run shows every
+    /// problem.
+    pub fn run(config: &ServerConfig) -> Self {
+        let mut checks = vec![check_config(config)];
+        checks.extend(check_models(config));
+        checks.push(check_cc_rules(config));
+        checks.push(Check::new(
+            "tts",
+            CheckStatus::Skip,
+            "no synthesis engine in this build",
+        ));
+        checks.push(check_temp_dir(config));
+        for (component, dir) in [
+            ("salvage_dir", config.salvage_dir.as_deref()),
+            ("job_dir", config.job_dir.as_deref()),
+            (
+                "usage_file",
+                config
+                    .usage_file
+                    .as_deref()
+                    .and_then(Path::parent)
+                    .filter(|dir| !dir.as_os_str().is_empty()),
+            ),
+        ] {
+            if let Some(dir) = dir {
+                checks.push(check_dir(component, dir));
+            }
+        }
+        if config.grpc_listeners.is_empty() {
+            checks.push(check_port("grpc_port", config.grpc_port));
+        } else {
+            for listener in &config.grpc_listeners {
+                let component = format!("grpc:{}", listener.addr);
+                checks.push(check_addr(&component, listener.addr));
+            }
+        }
+        if let Some(addr) = config.ws_addr() {
+            checks.push(check_addr("ws_port", addr));
+        }
+        Self { checks }
+    }
+
+    /// The configuration could not be read at all; nothing else was checked.
+    pub fn unreadable_config(error: &anyhow::Error) -> Self {
+        Self {
+            checks: vec![Check::new(
+                "config",
+                CheckStatus::Fail,
+                format!("{:#}", error),
+            )],
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failed().next().is_none()
+    }
+
+    /// Components that failed their check.
+    pub fn failed(&self) -> impl Iterator<Item = &str> {
+        self.checks
+            .iter()
+            .filter(|check| check.status == CheckStatus::Fail)
+            .map(|check| check.component.as_str())
+    }
+
+    /// Log one line per component, at the level its status calls for.
+    pub fn log(&self) {
+        let width = self.component_width();
+        for check in &self.checks {
+            let line = check.row(width);
+            match check.status {
+                CheckStatus::Fail => tracing::error!("{}", line),
+                CheckStatus::Warn => tracing::warn!("{}", line),
+                CheckStatus::Ok | CheckStatus::Skip => tracing::info!("{}", line),
+            }
+        }
+    }
+
+    fn component_width(&self) -> usize {
+        self.checks
+            .iter()
+            .map(|check| check.component.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// `{"ok": bool, "checks": [{"component", "status", "detail"}]}`
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ok": self.is_ok(),
+            "checks": self
+                .checks
+                .iter()
+                .map(|check| json!({
+                    "component": check.component,
+                    "status": check.status.as_str(),
+                    "detail": check.detail,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// One line per component: `component  status  detail`.
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.component_width();
+        for check in &self.checks {
+            writeln!(f, "{}", check.row(width))?;
+        }
+        Ok(())
+    }
+}
+
+fn check_config(config: &ServerConfig) -> Check {
+    let report = config.validate();
+    if !report.is_ok() {
+        Check::new("config", CheckStatus::Fail, report.errors.join("; "))
+    } else if !report.warnings.is_empty() {
+        Check::new("config", CheckStatus::Warn, report.warnings.join("; "))
+    } else {
+        Check::new("config", CheckStatus::Ok, "valid")
+    }
+}
+
+/// The default model, or each named model.
+fn check_models(config: &ServerConfig) -> Vec<Check> {
+    let models = if config.models.is_empty() {
+        vec![Model::new(config.clone())]
+    } else {
+        config
+            .models
+            .iter()
+            .map(|spec| Model::from_spec(config.clone(), spec.clone()))
+            .collect()
+    };
+    let named = !config.models.is_empty();
+    models
+        .iter()
+        .map(|model| {
+            let component = if named {
+                format!("model:{}", model.name())
+            } else {
+                "model".to_string()
+            };
+            let status = model.availability();
+            let outcome = if status.is_available() {
+                CheckStatus::Ok
+            } else {
+                CheckStatus::Fail
+            };
+            Check::new(component, outcome, status.summary())
+        })
+        .collect()
+}
+
+fn check_cc_rules(config: &ServerConfig) -> Check {
+    let needed = !config.dictionary.is_empty() || !config.dictionaries.is_empty();
+    match config.get_cc_rules_path() {
+        Ok(path) => Check::new("cc_rules", CheckStatus::Ok, path.display().to_string()),
+        Err(e) if !needed => Check::new(
+            "cc_rules",
+            CheckStatus::Skip,
+            format!("not needed without a dictionary ({})", e),
+        ),
+        Err(e) if config.allow_missing_cc_rules => Check::new(
+            "cc_rules",
+            CheckStatus::Warn,
+            format!("{}, dictionary corrections will be skipped", e),
+        ),
+        Err(e) => Check::new("cc_rules", CheckStatus::Fail, e.to_string()),
+    }
+}
+
+/// Writable, and with at least MURMURE_MIN_FREE_DISK_MB free.
+fn check_temp_dir(config: &ServerConfig) -> Check {
+    let check = check_dir("temp_dir", &config.temp_dir);
+    if check.status != CheckStatus::Ok {
+        return check;
+    }
+    match tempfiles::available_space(&config.temp_dir) {
+        Ok(free) if free < config.min_free_disk_bytes() => Check::new(
+            "temp_dir",
+            CheckStatus::Warn,
+            format!(
+                "{} has {} MB free, below MURMURE_MIN_FREE_DISK_MB ({} MB)",
+                config.temp_dir.display(),
+                free / (1024 * 1024),
+                config.min_free_disk_mb
+            ),
+        ),
+        Ok(free) => Check::new(
+            "temp_dir",
+            CheckStatus::Ok,
+            format!(
+                "{} writable, {} MB free",
+                config.temp_dir.display(),
+                free / (1024 * 1024)
+            ),
+        ),
+        Err(e) => Check::new(
+            "temp_dir",
+            CheckStatus::Warn,
+            format!(
+                "{} writable, free space unknown: {}",
+                config.temp_dir.display(),
+                e
+            ),
+        ),
+    }
+}
+
+/// A file can be created in `dir`, creating the directory if needed.
+fn check_dir(component: &str, dir: &Path) -> Check {
+    if dir.exists() && !dir.is_dir() {
+        return Check::new(
+            component,
+            CheckStatus::Fail,
+            format!("{} is not a directory", dir.display()),
+        );
+    }
+    match tempfiles::check_writable(dir) {
+        Ok(()) => Check::new(
+            component,
+            CheckStatus::Ok,
+            format!("{} writable", dir.display()),
+        ),
+        Err(e) => Check::new(component, CheckStatus::Fail, e.to_string()),
+    }
+}
+
+/// Nothing is listening on `addr` yet.
+fn check_addr(component: &str, addr: SocketAddr) -> Check {
+    match TcpListener::bind(addr) {
+        Ok(_) => Check::new(component, CheckStatus::Ok, format!("{} is free", addr)),
+        Err(e) => Check::new(
+            component,
+            CheckStatus::Fail,
+            format!("can't listen on {}: {}", addr, e),
+        ),
+    }
+}
+
+/// Nothing is listening on `port` yet.
+fn check_port(component: &str, port: u16) -> Check {
+    match TcpListener::bind(("0.0.0.0", port)) {
+        Ok(_) => Check::new(component, CheckStatus::Ok, format!("{} is free", port)),
+        Err(e) => Check::new(
+            component,
+            CheckStatus::Fail,
+            format!("can't listen on {}: {}", port, e),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prints_an_aligned_table() {
+        let report = CheckReport {
+            checks: vec![
+                Check::new("config", CheckStatus::Ok, "valid"),
+                Check::new("temp_dir", CheckStatus::Fail, "/tmp/x is not a directory"),
+            ],
+        };
+        assert_eq!(
+            report.to_string(),
+            "config    ok    valid\ntemp_dir  fail  /tmp/x is not a directory\n"
+        );
+        assert!(!report.is_ok());
+        assert_eq!(report.failed().collect::<Vec<_>>(), ["temp_dir"]);
+        assert_eq!(report.to_json()["checks"][1]["status"], "fail");
+    }
+
+    #[test]
+    fn cc_rules_matter_only_with_a_dictionary() {
+        let missing = ServerConfig {
+            cc_rules_path: Some("/nonexistent/cc-rules".into()),
+            ..ServerConfig::default()
+        };
+        assert_eq!(check_cc_rules(&missing).status, CheckStatus::Skip);
+
+        let dictionary = ServerConfig {
+            dictionary: vec!["Murmure".to_string()],
+            ..missing
+        };
+        assert_eq!(check_cc_rules(&dictionary).status, CheckStatus::Fail);
+        let allowed = ServerConfig {
+            allow_missing_cc_rules: true,
+            ..dictionary
+        };
+        assert_eq!(check_cc_rules(&allowed).status, CheckStatus::Warn);
+    }
+
+    #[test]
+    fn ports_in_use_fail() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = taken.local_addr().unwrap();
+        assert_eq!(check_addr("ws_port", addr).status, CheckStatus::Fail);
+        drop(taken);
+        assert_eq!(check_addr("ws_port", addr).status, CheckStatus::Ok);
+    }
+}
@@ -0,0 +1,371 @@
+// Command line of the murmure-server binary
+//
+// `serve` (the default) runs the gRPC server. `transcribe` loads the model
+// in-process, transcribes one file and prints the result, for scripts on the
+// machine that holds the model. `--check` runs the startup checks and exits
+// without serving, for CI of deployment images. Exit codes: 0 on success, 1
+// when the command (or a check) fails, 2 on a usage error.
+
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use murmure_stt::config::ServerConfig;
+use murmure_stt::dictionary::Dictionary;
+use murmure_stt::formats::OutputFormat;
+use murmure_stt::model::Model;
+use murmure_stt::transcription::{TranscriptionOptions, TranscriptionService};
+
+use crate::checks::CheckReport;
+use crate::server::grpc::to_file_response;
+use crate::server::murmure::{self, TranscribeFileResponse};
+
+pub const USAGE: &str = "Usage:
+  murmure-server [serve]
+  murmure-server --check [--json]
+  murmure-server transcribe <file.wav> [--json] [--no-dictionary] [--language <code>] [--two-pass]";
+
+pub enum Command {
+    /// Run the gRPC server
+    Serve,
+    /// Run the startup checks and exit
+    Check {
+        /// Print the report as JSON instead of a table
+        json: bool,
+    },
+    Transcribe {
+        file: PathBuf,
+        /// Print the TranscribeFile response fields as JSON instead of the text
+        json: bool,
+        use_dictionary: bool,
+        /// Language of the audio, selecting its dictionary
+        language: Option<String>,
+        /// Retry a low-confidence transcript with heavier preprocessing
+        two_pass: bool,
+    },
+}
+
+impl Command {
+    /// Parse the arguments after the program name.
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let Some((command, rest)) = args.split_first() else {
+            return Ok(Self::Serve);
+        };
+        match command.as_str() {
+            "serve" if rest.is_empty() => Ok(Self::Serve),
+            "serve" => Err(format!("serve takes no arguments, got '{}'", rest[0])),
+            "--check" => match rest {
+                [] => Ok(Self::Check { json: false }),
+                [flag] if flag == "--json" => Ok(Self::Check { json: true }),
+                [extra, ..] => Err(format!("unexpected argument '{}'", extra)),
+            },
+            "transcribe" => {
+                let mut file = None;
+                let mut json = false;
+                let mut use_dictionary = true;
+                let mut language = None;
+                let mut two_pass = false;
+                let mut args = rest.iter();
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--json" => json = true,
+                        "--no-dictionary" => use_dictionary = false,
+                        "--two-pass" => two_pass = true,
+                        "--language" => {
+                            language = Some(args.next().ok_or("--language needs a code")?.clone())
+                        }
+                        flag if flag.starts_with("--") => {
+                            return Err(format!("unknown option '{}'", flag))
+                        }
+                        path if file.is_none() => file = Some(PathBuf::from(path)),
+                        extra => return Err(format!("unexpected argument '{}'", extra)),
+                    }
+                }
+                Ok(Self::Transcribe {
+                    file: file.ok_or("transcribe needs a WAV file")?,
+                    json,
+                    use_dictionary,
+                    language,
+                    two_pass,
+                })
+            }
+            "-h" | "--help" | "help" => Err(String::new()),
+            other => Err(format!("unknown command '{}'", other)),
+        }
+    }
+}
+
+/// Transcribe `file` with the configured model, without starting the server.
+pub async fn transcribe(
+    file: PathBuf,
+    json: bool,
+    use_dictionary: bool,
+    language: Option<String>,
+    two_pass: bool,
+) -> anyhow::Result<()> {
+    let config = ServerConfig::from_env()?;
+    let report = config.validate();
+    if !report.is_ok() {
+        anyhow::bail!("{}", report);
+    }
+    let config = Arc::new(config);
+
+    let model = Arc::new(Model::new((*config).clone()));
+    let status = model.availability();
+    if !status.is_available() {
+        anyhow::bail!("Model is not available.\n{}", status);
+    }
+    let dictionary = (!config.dictionary.is_empty())
+        .then(|| Arc::new(Dictionary::new(config.dictionary.clone())));
+    let service = TranscriptionService::new(model, dictionary, Arc::clone(&config), Vec::new())?;
+
+    let output = transcribe_with(&service, &file, json, use_dictionary, language, two_pass).await?;
+    println!("{}", output);
+    Ok(())
+}
+
+/// Transcribe `file` with `service` and return what `transcribe` prints:
+/// the text, or the response fields as JSON.
+async fn transcribe_with(
+    service: &TranscriptionService,
+    file: &Path,
+    json: bool,
+    use_dictionary: bool,
+    language: Option<String>,
+    two_pass: bool,
+) -> anyhow::Result<String> {
+    let audio = std::fs::read(file)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", file.display(), e))?;
+    let mut options = TranscriptionOptions::from_config(service.get_config());
+    options.use_dictionary = use_dictionary;
+    options.language = language;
+    options.two_pass = two_pass;
+    options.include_stats = json;
+    options.include_timing = json;
+    let include_timing = options.include_timing;
+    let transcript = service.transcribe_audio_bytes_async(audio, options).await?;
+
+    let response = to_file_response(transcript, OutputFormat::Text, include_timing)
+        .map_err(|status| anyhow::anyhow!("{}", status.message()))?;
+    if json {
+        Ok(serde_json::to_string_pretty(&response_json(&response))?)
+    } else {
+        Ok(response.text)
+    }
+}
+
+/// Check the configuration and everything it points to, print the report
+/// and fail when a component failed.
+pub fn check(json: bool) -> anyhow::Result<()> {
+    let report = match ServerConfig::from_env() {
+        Ok(config) => CheckReport::run(&config),
+        Err(e) => CheckReport::unreadable_config(&e),
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report.to_json())?);
+    } else {
+        print!("{}", report);
+    }
+    if !report.is_ok() {
+        anyhow::bail!(
+            "check failed: {}",
+            report.failed().collect::<Vec<_>>().join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// The response fields under their proto names.
+fn response_json(response: &TranscribeFileResponse) -> Value {
+    let corrections = |corrections: &[murmure::Correction]| -> Vec<Value> {
+        corrections
+            .iter()
+            .map(|c| json!({"original": c.original, "replacement": c.replacement, "offset": c.offset}))
+            .collect()
+    };
+    json!({
+        "text": response.text,
+        "success": response.success,
+        "audio_duration_secs": response.audio_duration_secs,
+        "processing_time_ms": response.processing_time_ms,
+        "realtime_factor": response.realtime_factor,
+        "segments": response
+            .segments
+            .iter()
+            .map(|s| json!({"start": s.start, "end": s.end, "text": s.text, "channel": s.channel}))
+            .collect::<Vec<_>>(),
+        "corrections": corrections(&response.corrections),
+        "channels": response
+            .channels
+            .iter()
+            .map(|c| json!({"channel": c.channel, "text": c.text, "corrections": corrections(&c.corrections)}))
+            .collect::<Vec<_>>(),
+        "stats": response.stats.as_ref().map(|s| json!({
+            "word_count": s.word_count,
+            "words_per_minute": s.words_per_minute,
+            "voiced_secs": s.voiced_secs,
+            "mean_rms": s.mean_rms,
+            "max_rms": s.max_rms,
+            "silence_percent": s.silence_percent,
+        })),
+        "timing": response.timing.as_ref().map(|t| json!({
+            "decode_ms": t.decode_ms,
+            "resample_ms": t.resample_ms,
+            "preprocess_ms": t.preprocess_ms,
+            "vad_ms": t.vad_ms,
+            "inference_ms": t.inference_ms,
+            "dictionary_ms": t.dictionary_ms,
+            "post_process_ms": t.post_process_ms,
+            "serialization_ms": t.serialization_ms,
+            "total_ms": t.total_ms,
+        })),
+        "format": response.format().as_str_name(),
+        "input_sample_rate": response.input_sample_rate,
+        "detected_command": response.detected_command.as_ref().map(|c| json!({
+            "phrase": c.phrase,
+            "action": c.action,
+            "score": c.score,
+        })),
+        "detected_language": response.detected_language.as_ref().map(|l| json!({
+            "language": l.language,
+            "confidence": l.confidence,
+        })),
+        "two_pass": response.two_pass.as_ref().map(|p| json!({
+            "first_confidence": p.first_confidence,
+            "second_confidence": p.second_confidence,
+            "second_pass_won": p.second_pass_won,
+        })),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Command::parse(&args)
+    }
+
+    #[test]
+    fn serve_is_the_default() {
+        assert!(matches!(parse(&[]), Ok(Command::Serve)));
+        assert!(matches!(parse(&["serve"]), Ok(Command::Serve)));
+        assert!(parse(&["serve", "extra"]).is_err());
+    }
+
+    #[test]
+    fn check_takes_an_optional_json_flag() {
+        assert!(matches!(
+            parse(&["--check"]),
+            Ok(Command::Check { json: false })
+        ));
+        assert!(matches!(
+            parse(&["--check", "--json"]),
+            Ok(Command::Check { json: true })
+        ));
+        assert!(parse(&["--check", "--verbose"]).is_err());
+    }
+
+    #[test]
+    fn transcribe_reads_the_file_and_flags() {
+        let Ok(Command::Transcribe {
+            file,
+            json,
+            use_dictionary,
+            language,
+            two_pass,
+        }) = parse(&[
+            "transcribe",
+            "clip.wav",
+            "--json",
+            "--no-dictionary",
+            "--language",
+            "fr",
+            "--two-pass",
+        ])
+        else {
+            panic!("transcribe did not parse");
+        };
+        assert_eq!(file, PathBuf::from("clip.wav"));
+        assert!(json && !use_dictionary && two_pass);
+        assert_eq!(language.as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert!(parse(&["transcribe"]).is_err());
+        assert!(parse(&["transcribe", "a.wav", "b.wav"]).is_err());
+        assert!(parse(&["transcribe", "a.wav", "--language"]).is_err());
+        assert!(parse(&["transcribe", "a.wav", "--fast"]).is_err());
+        assert_eq!(
+            parse(&["synthesize", "hello", "--out", "out.wav"]).err(),
+            Some("unknown command 'synthesize'".to_string())
+        );
+    }
+
+    /// A one-second tone in a temp directory, and a service transcribing
+    /// everything as `text`.
+    fn fixture(text: &str) -> (tempfile::TempDir, PathBuf, TranscriptionService) {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("clip.wav");
+        let samples: Vec<f32> = (0..16_000)
+            .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 16_000.0).sin() * 0.3)
+            .collect();
+        std::fs::write(
+            &file,
+            murmure_stt::dsp::write_wav_bytes(&samples, 16_000).unwrap(),
+        )
+        .unwrap();
+        let engine = murmure_stt::mock::MockEngine::new(text);
+        let service = TranscriptionService::builder()
+            .engine(move || engine.clone())
+            .build()
+            .unwrap();
+        (dir, file, service)
+    }
+
+    #[tokio::test]
+    async fn transcribe_prints_the_text() {
+        let (_dir, file, service) = fixture("hello world");
+        let output = transcribe_with(&service, &file, false, true, None, false)
+            .await
+            .unwrap();
+        assert_eq!(output, "hello world");
+    }
+
+    #[tokio::test]
+    async fn transcribe_prints_the_response_fields_as_json() {
+        let (_dir, file, service) = fixture("hello world");
+        let output = transcribe_with(&service, &file, true, true, None, true)
+            .await
+            .unwrap();
+        let response: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(response["text"], "hello world");
+        assert_eq!(response["success"], true);
+        assert!((response["audio_duration_secs"].as_f64().unwrap() - 1.0).abs() < 0.01);
+        assert_eq!(response["input_sample_rate"], 16_000);
+        assert_eq!(response["format"], "TEXT");
+        assert!(response["segments"].is_array());
+        // Asked for by --json, and by --two-pass
+        assert_eq!(response["stats"]["word_count"], 2);
+        assert!(response["timing"]["total_ms"].as_f64().unwrap() >= 0.0);
+        assert!(response["two_pass"].is_object());
+    }
+
+    #[tokio::test]
+    async fn transcribe_names_a_missing_file() {
+        let (dir, _file, service) = fixture("unused");
+        let missing = dir.path().join("missing.wav");
+        let error = transcribe_with(&service, &missing, false, true, None, false)
+            .await
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with(&format!("Failed to read {}", missing.display())),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,7 @@
+//! The Murmure gRPC server, built into the `murmure-server` binary
+//!
+//! Exposed as a library so the integration tests in `tests/` can serve the
+//! real gRPC service on an ephemeral port, with a mock engine in place of the
+//! model (see `murmure_stt::mock`).
+
+pub mod server;
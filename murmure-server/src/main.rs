@@ -1,54 +1,110 @@
 use std::sync::Arc;
-use tonic::transport::Server;
-use tracing::{error, info};
+use std::time::Duration;
+use tracing::{error, info, warn};
 
 use murmure_stt::config::ServerConfig;
 use murmure_stt::dictionary::Dictionary;
 use murmure_stt::model::Model;
+use murmure_stt::tempfiles;
 use murmure_stt::transcription::TranscriptionService;
+use murmure_stt::DictionaryStatus;
 
-mod server;
+mod checks;
+mod cli;
+mod readiness;
 
-use server::murmure;
-use server::TranscriptionServiceImpl;
+use checks::CheckReport;
+use cli::Command;
+use readiness::ReadyFile;
+
+use murmure_server::server;
+use server::{GrpcListeners, RateLimiter, TranscriptionServiceImpl, WebSocketBridge};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Print to stderr immediately to ensure we see output even if logging fails
-    eprintln!("[DEBUG] Starting Murmure server...");
-
-    // Initialize logging - ensure output goes to stdout
-    tracing_subscriber::fmt()
-        .with_writer(std::io::stdout)
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
-        .init();
-
-    info!("Starting Murmure gRPC Server...");
-
-    // Load configuration
-    let config = match ServerConfig::from_env() {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("[ERROR] Failed to load configuration: {}", e);
-            return Err(e);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match Command::parse(&args) {
+        Ok(command) => command,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("Error: {}", message);
+            }
+            eprintln!("{}", cli::USAGE);
+            std::process::exit(2);
         }
     };
-    let config = Arc::new(config);
-    info!("Configuration loaded: gRPC port = {}", config.grpc_port);
-
-    // Initialize model
-    eprintln!("[DEBUG] Checking model availability...");
-    let model = Arc::new(Model::new((*config).clone()));
-    if !model.is_available() {
-        eprintln!("[ERROR] Model is not available. Ensure MURMURE_MODEL_PATH is set correctly.");
-        error!("Model is not available. Please ensure MURMURE_MODEL_PATH is set correctly.");
-        anyhow::bail!("Model not available");
+
+    match command {
+        Command::Serve => serve().await,
+        Command::Check { json } => {
+            init_logging(false);
+            cli::check(json)
+        }
+        Command::Transcribe {
+            file,
+            json,
+            use_dictionary,
+            language,
+            two_pass,
+        } => {
+            // stdout carries the transcript only
+            init_logging(false);
+            cli::transcribe(file, json, use_dictionary, language, two_pass).await
+        }
+    }
+}
+
+/// Log to stdout at `info` for the server, to stderr at `warn` for the
+/// one-shot commands; RUST_LOG overrides the level either way.
+fn init_logging(server: bool) {
+    let filter = tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        tracing_subscriber::EnvFilter::new(if server { "info" } else { "warn" })
+    });
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if server {
+        builder.with_writer(std::io::stdout).init();
+    } else {
+        builder.with_writer(std::io::stderr).init();
+    }
+}
+
+async fn serve() -> anyhow::Result<()> {
+    init_logging(true);
+    info!(
+        "Starting Murmure gRPC Server {}...",
+        env!("CARGO_PKG_VERSION")
+    );
+
+    let config = ServerConfig::from_env().map_err(|e| {
+        error!("Failed to load configuration: {:#}", e);
+        e
+    })?;
+
+    // A model still being copied into a mounted volume gets a chance to
+    // appear before it is checked
+    let model = Arc::new(Model::new(config.clone()));
+    if !model.is_available() && config.model_wait_secs > 0 {
+        info!(
+            "Model not available yet, waiting up to {}s (MURMURE_MODEL_WAIT_SECS)",
+            config.model_wait_secs
+        );
+        readiness::wait_for_model(&model, Duration::from_secs(config.model_wait_secs)).await;
+    }
+
+    // Same checks as --check, logged as the startup banner
+    let checks = CheckReport::run(&config);
+    checks.log();
+    if !checks.is_ok() {
+        let status = model.availability();
+        if !status.is_available() {
+            error!("{}", status);
+        }
+        anyhow::bail!(
+            "Startup checks failed: {}",
+            checks.failed().collect::<Vec<_>>().join(", ")
+        );
     }
-    eprintln!("[DEBUG] Model is available");
-    info!("Model initialized");
+    let config = Arc::new(config);
 
     // Initialize dictionary (optional)
     let dictionary = if !config.dictionary.is_empty() {
@@ -56,31 +112,89 @@ async fn main() -> anyhow::Result<()> {
     } else {
         None
     };
-    if dictionary.is_some() {
-        info!(
-            "Custom dictionary loaded with {} words",
-            config.dictionary.len()
-        );
-    }
 
     // Create transcription service
     let transcription_service = Arc::new(
-        TranscriptionService::new(model, dictionary, config.clone())
+        TranscriptionService::new(model, dictionary, config.clone(), Vec::new())
             .map_err(|e| anyhow::anyhow!("Failed to initialize transcription service: {}", e))?,
     );
+    transcription_service
+        .warm_up()
+        .map_err(|e| anyhow::anyhow!("Failed to warm up transcription engine: {}", e))?;
+    match transcription_service.execution_provider() {
+        Some(active) if active != config.execution_provider => warn!(
+            "Requested execution provider '{}' is unavailable, running on '{}' instead",
+            config.execution_provider, active
+        ),
+        Some(active) => info!("Engine running on the '{}' execution provider", active),
+        None => {}
+    }
+    if config.models.len() > 1 {
+        info!(
+            "{} models configured, default '{}', up to {} loaded at once",
+            config.models.len(),
+            transcription_service.get_model().name(),
+            config.max_loaded_models
+        );
+    }
+    match transcription_service.dictionary_status() {
+        DictionaryStatus::Active => info!(
+            "Custom dictionary loaded with {} words",
+            config.dictionary.len()
+        ),
+        DictionaryStatus::Degraded => warn!(
+            "Custom dictionary of {} words loaded but the cc-rules directory was not found, \
+             transcripts will not be corrected (MURMURE_ALLOW_MISSING_CC_RULES)",
+            config.dictionary.len()
+        ),
+        DictionaryStatus::Off => {}
+    }
+    if !config.dictionaries.is_empty() {
+        info!(
+            "Language-scoped dictionaries: {}",
+            transcription_service
+                .dictionary_languages()
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
     info!("Transcription service ready");
 
+    // Clear temp files left by killed processes, then keep sweeping
+    match tempfiles::sweep(&config.temp_dir, tempfiles::STALE_AFTER, true) {
+        Ok(0) => {}
+        Ok(removed) => info!(
+            "Removed {} stale temp file(s) from {}",
+            removed,
+            config.temp_dir.display()
+        ),
+        Err(e) => warn!(
+            "Failed to sweep temp directory {}: {}",
+            config.temp_dir.display(),
+            e
+        ),
+    }
+    spawn_temp_sweeper(config.temp_dir.clone());
+
     // Create gRPC service
-    let grpc_service = TranscriptionServiceImpl::new(transcription_service);
+    let limiter = Arc::new(RateLimiter::from_config(&config));
+    let usage = Arc::clone(limiter.usage());
+    usage.spawn_flusher();
+    let grpc_service =
+        TranscriptionServiceImpl::new(Arc::clone(&transcription_service), Arc::clone(&limiter));
 
-    // Create gRPC server
-    eprintln!("[DEBUG] Creating gRPC server...");
-    let addr = format!("0.0.0.0:{}", config.grpc_port).parse()?;
-    eprintln!("[DEBUG] gRPC server will listen on {}", addr);
-    info!("gRPC server listening on {}", addr);
+    if let Some(ws_addr) = config.ws_addr() {
+        let bridge = WebSocketBridge::new(transcription_service, Arc::clone(&limiter));
+        info!("WebSocket bridge listening on {}", ws_addr);
+        tokio::spawn(async move {
+            if let Err(e) = bridge.serve(ws_addr).await {
+                error!("WebSocket bridge failed: {}", e);
+            }
+        });
+    }
 
-    eprintln!("[DEBUG] About to start server, binding to {}", addr);
-    info!("Starting server on {}", addr);
+    // Bind every gRPC listener before serving on any
+    let listeners = GrpcListeners::bind(&config).await?;
 
     // Create shutdown signal receiver
     // Note: In Docker (PID 1), signals must be handled explicitly
@@ -96,7 +210,6 @@ async fn main() -> anyhow::Result<()> {
         let mut sigint = signal(SignalKind::interrupt())
             .expect("Failed to create SIGINT handler - cannot run server without signal handling");
 
-        eprintln!("[DEBUG] Signal handlers installed, waiting for shutdown signal...");
         info!("Server is ready and listening for requests");
 
         // Wait for either signal - this will block until one is received
@@ -104,61 +217,90 @@ async fn main() -> anyhow::Result<()> {
             result = sigint.recv() => {
                 match result {
                     Some(_) => {
-                        eprintln!("[DEBUG] SIGINT received");
                         info!("SIGINT received, shutting down gracefully");
                     }
-                    None => {
-                        eprintln!("[WARN] SIGINT stream ended unexpectedly");
-                    }
+                    None => warn!("SIGINT stream ended unexpectedly"),
                 }
             }
             result = sigterm.recv() => {
                 match result {
                     Some(_) => {
-                        eprintln!("[DEBUG] SIGTERM received");
                         info!("SIGTERM received, shutting down gracefully");
                     }
-                    None => {
-                        eprintln!("[WARN] SIGTERM stream ended unexpectedly");
-                    }
+                    None => warn!("SIGTERM stream ended unexpectedly"),
                 }
             }
         }
+    };
+
+    // Ctrl+Break is what service wrappers and `taskkill`-style tools send to
+    // console programs, Ctrl+C what a user types
+    #[cfg(windows)]
+    let shutdown = async {
+        use tokio::signal::windows::{ctrl_break, ctrl_c};
 
-        eprintln!("[DEBUG] Shutdown signal processed, server will stop");
+        let mut ctrl_c = ctrl_c()
+            .expect("Failed to create Ctrl+C handler - cannot run server without signal handling");
+        let mut ctrl_break = ctrl_break().expect(
+            "Failed to create Ctrl+Break handler - cannot run server without signal handling",
+        );
+
+        info!("Server is ready and listening for requests");
+
+        tokio::select! {
+            _ = ctrl_c.recv() => info!("Ctrl+C received, shutting down gracefully"),
+            _ = ctrl_break.recv() => info!("Ctrl+Break received, shutting down gracefully"),
+        }
     };
 
-    #[cfg(not(unix))]
+    #[cfg(not(any(unix, windows)))]
     let shutdown = async {
-        eprintln!("[DEBUG] Setting up Ctrl+C handler...");
         info!("Server is ready and listening for requests");
-        signal::ctrl_c()
+        tokio::signal::ctrl_c()
             .await
             .expect("Failed to listen for shutdown signal");
-        eprintln!("[DEBUG] Ctrl+C received");
         info!("Shutdown signal received");
     };
 
-    eprintln!("[DEBUG] Building server...");
-    let server = Server::builder().add_service(
-        murmure::transcription_service_server::TranscriptionServiceServer::new(grpc_service),
-    );
-
-    eprintln!("[DEBUG] Starting server with shutdown handler...");
+    // Signal readiness only once the engine is loaded and warmed; the guard
+    // removes the file when serve returns, whether shutdown was clean or not.
+    let _ready_file = match config.ready_file.as_deref() {
+        Some(path) => Some(ReadyFile::create(path)?),
+        None => None,
+    };
 
-    // Start the server - this will block until shutdown signal is received
-    match server.serve_with_shutdown(addr, shutdown).await {
-        Ok(_) => {
-            eprintln!("[DEBUG] Server exited normally");
-            info!("Server shut down");
-        }
-        Err(e) => {
-            eprintln!("[ERROR] Server error: {}", e);
-            error!("Server error: {}", e);
-            return Err(anyhow::anyhow!("Server failed: {}", e));
-        }
+    // Serve until the shutdown signal, or until a listener fails, which
+    // stops the others too
+    let served = listeners
+        .serve(server::grpc_service(grpc_service, limiter), shutdown)
+        .await;
+    if let Err(e) = usage.flush() {
+        warn!("Failed to save usage: {}", e);
     }
+    served?;
 
     info!("Server shut down");
     Ok(())
 }
+
+/// Periodically remove temp files abandoned by other server processes.
+fn spawn_temp_sweeper(temp_dir: std::path::PathBuf) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tempfiles::SWEEP_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let dir = temp_dir.clone();
+            match tokio::task::spawn_blocking(move || {
+                tempfiles::sweep(&dir, tempfiles::STALE_AFTER, false)
+            })
+            .await
+            {
+                Ok(Ok(0)) => {}
+                Ok(Ok(removed)) => info!("Removed {} stale temp file(s)", removed),
+                Ok(Err(e)) => warn!("Failed to sweep temp directory: {}", e),
+                Err(e) => warn!("Temp directory sweep failed: {}", e),
+            }
+        }
+    });
+}
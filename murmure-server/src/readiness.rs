@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use murmure_stt::model::Model;
+
+const MODEL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Poll until the model becomes available or `timeout` elapses.
+///
+/// Useful when the model is still being copied into a mounted volume while the
+/// container starts. Returns `true` as soon as the model is found.
+pub async fn wait_for_model(model: &Model, timeout: Duration) -> bool {
+    let start = Instant::now();
+    let mut attempt = 0u64;
+
+    loop {
+        if model.is_available() {
+            if attempt > 0 {
+                info!(
+                    "Model became available after {:.1}s",
+                    start.elapsed().as_secs_f32()
+                );
+            }
+            return true;
+        }
+
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            return false;
+        }
+
+        attempt += 1;
+        warn!(
+            "Model not available yet ({}), retrying ({}s elapsed, waiting up to {}s)",
+            model.availability().summary(),
+            elapsed.as_secs(),
+            timeout.as_secs()
+        );
+        tokio::time::sleep(MODEL_POLL_INTERVAL.min(timeout - elapsed)).await;
+    }
+}
+
+/// Readiness marker file, removed again when dropped.
+///
+/// Orchestrators can probe for the file's existence (e.g. `test -f`) to know
+/// the engine is loaded and the server is about to accept traffic.
+pub struct ReadyFile {
+    path: PathBuf,
+}
+
+impl ReadyFile {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        std::fs::write(path, format!("{}\n", std::process::id()))
+            .map_err(|e| anyhow::anyhow!("Failed to write ready file {}: {}", path.display(), e))?;
+        info!("Ready file written to {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for ReadyFile {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => info!("Ready file {} removed", self.path.display()),
+            Err(e) => warn!("Failed to remove ready file {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use murmure_stt::config::ServerConfig;
+    use murmure_stt::mock::MockEngine;
+
+    fn missing_model() -> Model {
+        Model::new(ServerConfig {
+            model_path: Some(PathBuf::from("/nonexistent/murmure-model")),
+            ..ServerConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_a_missing_model() {
+        let started = Instant::now();
+        assert!(!wait_for_model(&missing_model(), Duration::from_millis(50)).await);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert!(!wait_for_model(&missing_model(), Duration::ZERO).await);
+    }
+
+    #[tokio::test]
+    async fn returns_at_once_for_an_available_model() {
+        let model = missing_model().with_engine(|| MockEngine::new("ready"));
+        let started = Instant::now();
+        assert!(wait_for_model(&model, Duration::from_secs(10)).await);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn ready_file_lives_as_long_as_its_guard() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready");
+        let guard = ReadyFile::create(&path).unwrap();
+        let pid = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(pid.trim(), std::process::id().to_string());
+        drop(guard);
+        assert!(!path.exists());
+
+        assert!(ReadyFile::create(&dir.path().join("missing/ready")).is_err());
+    }
+}
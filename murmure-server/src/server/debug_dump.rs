@@ -0,0 +1,397 @@
+// Debug dumps of what the engine was given, for investigating bad transcripts
+//
+// With MURMURE_DEBUG_DUMP_DIR set, a TranscribeFile request carrying the
+// MURMURE_DEBUG_DUMP_TOKEN secret in `x-murmure-debug-dump` gets a directory
+// of its own, named after a new request id and returned in the response's
+// `x-murmure-debug-dump` metadata:
+//
+//     payload.wav     the audio as sent
+//     resampled.wav   decoded and resampled to 16 kHz, before preprocessing
+//     speech.wav      what the engine was given, without the silences VAD
+//                     left out when segmenting
+//     pipeline.json   input properties, stage timings, the engine's raw text
+//                     and the final text
+//
+// In SEPARATE channel mode the WAVs are per channel (`speech-0.wav`, ...).
+// Requests without the header are never dumped. Dumps hold raw audio, so the
+// directory has a size cap, oldest dumps evicted first, and dumps older than
+// the retention period are deleted when another is written and at startup.
+
+use murmure_stt::config::ServerConfig;
+use murmure_stt::{dsp, DebugCapture, Transcript};
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tonic::metadata::MetadataMap;
+use tonic::Status;
+
+use super::session::new_session_id;
+
+/// Metadata key of the secret on requests, and of the dump's directory name
+/// on responses
+pub const DEBUG_DUMP_HEADER: &str = "x-murmure-debug-dump";
+
+const DIR_PREFIX: &str = "dump-";
+
+/// Suffix of a dump still being written
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Room taken by `pipeline.json`, counted before it is written
+const PIPELINE_JSON_ALLOWANCE: u64 = 64 * 1024;
+
+pub struct DebugDumps {
+    dir: PathBuf,
+    token: String,
+    max_bytes: u64,
+    /// `None` keeps dumps until they are evicted
+    retention: Option<Duration>,
+}
+
+impl DebugDumps {
+    /// `None` unless both `MURMURE_DEBUG_DUMP_DIR` and
+    /// `MURMURE_DEBUG_DUMP_TOKEN` are set.
+    pub fn from_config(config: &ServerConfig) -> Option<Self> {
+        let dumps = Self {
+            dir: config.debug_dump_dir.clone()?,
+            token: config.debug_dump_token.clone()?,
+            max_bytes: config.debug_dump_max_bytes,
+            retention: (config.debug_dump_retention_days > 0)
+                .then(|| Duration::from_secs(config.debug_dump_retention_days * 24 * 3600)),
+        };
+        if let Err(e) = dumps.evict(0, true) {
+            tracing::warn!("Failed to sweep debug dump directory: {}", e);
+        }
+        Some(dumps)
+    }
+
+    /// Whether the request asks to be dumped. A header that doesn't hold the
+    /// secret is rejected rather than ignored, so a typo doesn't go unnoticed.
+    pub fn requested(&self, metadata: &MetadataMap) -> Result<bool, Status> {
+        let Some(value) = metadata.get(DEBUG_DUMP_HEADER) else {
+            return Ok(false);
+        };
+        if constant_time_eq(value.as_bytes(), self.token.as_bytes()) {
+            Ok(true)
+        } else {
+            Err(Status::permission_denied(format!(
+                "{} does not match MURMURE_DEBUG_DUMP_TOKEN",
+                DEBUG_DUMP_HEADER
+            )))
+        }
+    }
+
+    /// Write the dump of a transcribed request and return its directory
+    /// name. Returns `None` when the transcript has no capture, or the dump
+    /// alone would exceed the size cap.
+    pub fn save(
+        &self,
+        payload: &[u8],
+        transcript: &Transcript,
+        model: &str,
+    ) -> io::Result<Option<String>> {
+        let Some(capture) = &transcript.debug else {
+            return Ok(None);
+        };
+        let size = dump_size(payload, capture);
+        if size > self.max_bytes {
+            tracing::warn!(
+                "Not dumping request: {} bytes exceed MURMURE_DEBUG_DUMP_MAX_BYTES ({})",
+                size,
+                self.max_bytes
+            );
+            return Ok(None);
+        }
+        fs::create_dir_all(&self.dir)?;
+        self.evict(size, false)?;
+
+        let request_id = new_session_id();
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = format!("{}{}-{}", DIR_PREFIX, created.as_millis(), request_id);
+        let partial = self.dir.join(format!("{}{}", name, PARTIAL_SUFFIX));
+        fs::create_dir(&partial)?;
+        let written = write_dump(&partial, payload, transcript, capture, |pipeline| {
+            pipeline["request_id"] = json!(request_id);
+            pipeline["created_unix_secs"] = json!(created.as_secs());
+            pipeline["model"] = json!(model);
+        })
+        .and_then(|()| fs::rename(&partial, self.dir.join(&name)));
+        if let Err(e) = written {
+            let _ = fs::remove_dir_all(&partial);
+            return Err(e);
+        }
+        tracing::info!("Dumped request {} to {}", request_id, self.dir.display());
+        Ok(Some(name))
+    }
+
+    /// Delete dumps past the retention period, then the oldest ones until
+    /// `incoming` more bytes fit under the cap. Dumps still being written are
+    /// left alone, unless `startup` says they were cut short by a restart.
+    fn evict(&self, incoming: u64, startup: bool) -> io::Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut dumps = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !name.starts_with(DIR_PREFIX) || (name.ends_with(PARTIAL_SUFFIX) && !startup) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+                dumps.push((modified, entry.path(), dir_size(&entry.path())?));
+            }
+        }
+        dumps.sort();
+
+        let mut total: u64 = dumps.iter().map(|(_, _, len)| len).sum();
+        for (modified, path, len) in dumps {
+            let expired = path.to_string_lossy().ends_with(PARTIAL_SUFFIX)
+                || self
+                    .retention
+                    .is_some_and(|retention| modified.elapsed().unwrap_or_default() >= retention);
+            if !expired && total + incoming <= self.max_bytes {
+                continue;
+            }
+            fs::remove_dir_all(&path)?;
+            tracing::info!("Removed debug dump {}", path.display());
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+fn write_dump(
+    dir: &Path,
+    payload: &[u8],
+    transcript: &Transcript,
+    capture: &DebugCapture,
+    describe: impl FnOnce(&mut serde_json::Value),
+) -> io::Result<()> {
+    fs::write(dir.join("payload.wav"), payload)?;
+    let single = capture.speech.len() == 1;
+    for (stage, channels) in [
+        ("resampled", &capture.resampled),
+        ("speech", &capture.speech),
+    ] {
+        for (index, samples) in channels.iter().enumerate() {
+            let name = if single {
+                format!("{}.wav", stage)
+            } else {
+                format!("{}-{}.wav", stage, index)
+            };
+            let wav =
+                dsp::write_wav_bytes(samples, capture.sample_rate).map_err(io::Error::other)?;
+            fs::write(dir.join(name), wav)?;
+        }
+    }
+
+    let ms = |elapsed: Duration| elapsed.as_secs_f64() * 1000.0;
+    let timing = &transcript.timing;
+    let input = &transcript.input;
+    let mut pipeline = json!({
+        "input": {
+            "sample_rate": input.sample_rate,
+            "channels": input.channels,
+            "bits_per_sample": input.bits_per_sample,
+            "duration_secs": input.duration_secs,
+            "peak": input.peak,
+        },
+        "timing_ms": {
+            "decode": ms(timing.decode),
+            "resample": ms(timing.resample),
+            "preprocess": ms(timing.preprocess),
+            "vad": ms(timing.vad),
+            "inference": ms(timing.inference),
+            "dictionary": ms(timing.dictionary),
+            "post_process": ms(timing.post_process),
+            "total": ms(timing.total),
+        },
+        "raw_text": capture.raw_text,
+        "text": transcript.text,
+    });
+    describe(&mut pipeline);
+    let pipeline = serde_json::to_vec_pretty(&pipeline).map_err(io::Error::other)?;
+    fs::write(dir.join("pipeline.json"), pipeline)
+}
+
+/// Bytes a dump will take: the payload, two 16-bit WAVs per channel and the
+/// JSON.
+fn dump_size(payload: &[u8], capture: &DebugCapture) -> u64 {
+    let wav = |samples: &Vec<f32>| 44 + samples.len() as u64 * 2;
+    payload.len() as u64
+        + capture.resampled.iter().map(wav).sum::<u64>()
+        + capture.speech.iter().map(wav).sum::<u64>()
+        + PIPELINE_JSON_ALLOWANCE
+}
+
+fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(dir)? {
+        total += entry?.metadata()?.len();
+    }
+    Ok(total)
+}
+
+/// Compare secrets without stopping at the first difference.
+pub(super) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dumps(dir: &Path, max_bytes: u64, retention: Option<Duration>) -> DebugDumps {
+        DebugDumps {
+            dir: dir.to_path_buf(),
+            token: "secret".to_string(),
+            max_bytes,
+            retention,
+        }
+    }
+
+    /// A transcript of one second of 16 kHz audio, captured.
+    fn transcript() -> Transcript {
+        Transcript {
+            text: "Hello world.".to_string(),
+            debug: Some(DebugCapture {
+                sample_rate: 16_000,
+                resampled: vec![vec![0.1; 16_000]],
+                speech: vec![vec![0.1; 12_000]],
+                raw_text: vec![vec!["hello world".to_string()]],
+            }),
+            ..Transcript::default()
+        }
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn writes_each_artifact() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = dumps(dir.path(), 10 << 20, None)
+            .save(b"payload", &transcript(), "parakeet")
+            .unwrap()
+            .unwrap();
+        assert!(name.starts_with(DIR_PREFIX));
+
+        let dump = dir.path().join(&name);
+        assert_eq!(
+            names(&dump),
+            [
+                "payload.wav",
+                "pipeline.json",
+                "resampled.wav",
+                "speech.wav"
+            ]
+        );
+        assert_eq!(fs::read(dump.join("payload.wav")).unwrap(), b"payload");
+        let speech = dsp::read_wav_bytes(&fs::read(dump.join("speech.wav")).unwrap()).unwrap();
+        assert_eq!(speech.samples.len(), 12_000);
+        let pipeline: serde_json::Value =
+            serde_json::from_slice(&fs::read(dump.join("pipeline.json")).unwrap()).unwrap();
+        assert_eq!(pipeline["raw_text"], json!([["hello world"]]));
+        assert_eq!(pipeline["text"], "Hello world.");
+        assert_eq!(pipeline["model"], "parakeet");
+        assert!(name.ends_with(pipeline["request_id"].as_str().unwrap()));
+    }
+
+    #[test]
+    fn skips_transcripts_without_a_capture_or_over_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let uncaptured = Transcript::default();
+        assert_eq!(
+            dumps(dir.path(), 10 << 20, None)
+                .save(b"payload", &uncaptured, "parakeet")
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            dumps(dir.path(), 1024, None)
+                .save(b"payload", &transcript(), "parakeet")
+                .unwrap(),
+            None
+        );
+        assert!(names(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn evicts_the_oldest_dumps_to_stay_under_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dumps(dir.path(), 10 << 20, None)
+            .save(b"payload", &transcript(), "m")
+            .unwrap()
+            .unwrap();
+        // Room for one dump on disk and the next one's estimate, not two
+        let written = dir_size(&dir.path().join(&first)).unwrap();
+        let estimate = dump_size(b"payload", transcript().debug.as_ref().unwrap());
+        let dumps = dumps(dir.path(), written * 3 / 2 + estimate, None);
+        std::thread::sleep(Duration::from_millis(20));
+        let second = dumps.save(b"payload", &transcript(), "m").unwrap().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let third = dumps.save(b"payload", &transcript(), "m").unwrap().unwrap();
+
+        let left = names(dir.path());
+        assert!(!left.contains(&first), "{:?}", left);
+        assert!(
+            left.contains(&second) && left.contains(&third),
+            "{:?}",
+            left
+        );
+    }
+
+    #[test]
+    fn deletes_expired_and_interrupted_dumps() {
+        let dir = tempfile::tempdir().unwrap();
+        let name = dumps(dir.path(), 10 << 20, None)
+            .save(b"payload", &transcript(), "m")
+            .unwrap()
+            .unwrap();
+        let partial = dir
+            .path()
+            .join(format!("{}1-cut{}", DIR_PREFIX, PARTIAL_SUFFIX));
+        fs::create_dir(&partial).unwrap();
+        // Not a dump: left alone
+        fs::create_dir(dir.path().join("notes")).unwrap();
+
+        // While serving, dumps being written are left alone
+        dumps(dir.path(), 10 << 20, None).evict(0, false).unwrap();
+        assert!(partial.exists());
+
+        // A restart sweeps them, and dumps past their retention
+        let config = ServerConfig {
+            debug_dump_dir: Some(dir.path().to_path_buf()),
+            debug_dump_token: Some("secret".to_string()),
+            ..ServerConfig::default()
+        };
+        DebugDumps::from_config(&config).unwrap();
+        assert_eq!(names(dir.path()), [name, "notes".to_string()]);
+
+        dumps(dir.path(), 10 << 20, Some(Duration::ZERO))
+            .evict(0, false)
+            .unwrap();
+        assert_eq!(names(dir.path()), ["notes"]);
+    }
+
+    #[test]
+    fn compares_secrets() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret!"));
+    }
+}
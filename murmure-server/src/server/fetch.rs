@@ -0,0 +1,416 @@
+// Server-side fetching of audio for TranscribeUrl
+//
+// Only URLs under a MURMURE_URL_ALLOWLIST prefix are fetched, and every
+// redirect must lead to one too, so the server can't be pointed at internal
+// services. A prefix matches URLs with the same scheme, host and port whose
+// path is the prefix's path or below it: `/audio` allows `/audio/a.wav` but
+// not `/audio-private/a.wav`. The query (a presigned S3 signature, say) is
+// not compared. URLs are logged and reported without their query,
+// which may hold credentials.
+//
+// The body is spooled like a TranscribeLargeFile upload, under the same
+// MURMURE_MAX_UPLOAD_BYTES limit. Its first bytes are sniffed to check that
+// the Content-Type the server sent matches what was actually received.
+
+use murmure_stt::config::ServerConfig;
+use murmure_stt::tempfiles;
+use reqwest::redirect::Policy;
+use reqwest::{StatusCode, Url};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::Status;
+
+use super::upload::{Spooled, UploadSpool};
+
+/// Bytes sniffed to tell the container apart
+const SNIFF_LEN: usize = 12;
+
+/// Content types that say nothing about the container
+const GENERIC_CONTENT_TYPES: [&str; 2] = ["application/octet-stream", "binary/octet-stream"];
+
+/// Why a URL could not be fetched.
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error("invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("{0} is not under an allowed URL prefix")]
+    NotAllowed(String),
+    #[error("fetching {url} timed out")]
+    Timeout { url: String },
+    #[error("fetching {url} failed: {reason}")]
+    Request { url: String, reason: String },
+    #[error("fetching {url} failed: {reason}")]
+    Redirect { url: String, reason: String },
+    #[error("fetching {url} failed: the server answered {status}")]
+    HttpStatus { url: String, status: StatusCode },
+    #[error("{url} is larger than the {limit} byte limit")]
+    TooLarge { url: String, limit: u64 },
+    #[error("{url} was served as '{content_type}' but the content is {actual}")]
+    ContentType {
+        url: String,
+        content_type: String,
+        actual: &'static str,
+    },
+    #[error("failed to spool {url}: {source}")]
+    Spool { url: String, source: io::Error },
+}
+
+impl FetchError {
+    /// Status of the failure, never `INVALID_ARGUMENT` once the URL is
+    /// accepted: that code is left to audio that fails to decode.
+    pub fn status(&self) -> Status {
+        let message = self.to_string();
+        match self {
+            Self::InvalidUrl(_) => Status::invalid_argument(message),
+            Self::NotAllowed(_) => Status::permission_denied(message),
+            Self::Timeout { .. } => Status::deadline_exceeded(message),
+            Self::HttpStatus { status, .. } if status.is_server_error() => {
+                Status::unavailable(message)
+            }
+            Self::Request { .. } => Status::unavailable(message),
+            Self::HttpStatus { .. }
+            | Self::Redirect { .. }
+            | Self::TooLarge { .. }
+            | Self::ContentType { .. } => Status::failed_precondition(message),
+            Self::Spool { source, .. } if tempfiles::is_storage_full(source) => {
+                Status::resource_exhausted(message)
+            }
+            Self::Spool { .. } => Status::internal(message),
+        }
+    }
+}
+
+/// Fetches allowed URLs for TranscribeUrl.
+pub struct UrlFetcher {
+    client: reqwest::Client,
+    allowlist: Arc<Vec<Url>>,
+    max_bytes: u64,
+    temp_dir: std::path::PathBuf,
+    min_free_bytes: u64,
+}
+
+impl UrlFetcher {
+    /// `None` when no URL prefix is allowed, which disables TranscribeUrl.
+    pub fn from_config(config: &ServerConfig) -> Option<Self> {
+        let allowlist: Arc<Vec<Url>> = Arc::new(
+            config
+                .url_allowlist
+                .iter()
+                .filter_map(|prefix| Url::parse(prefix).ok())
+                .collect(),
+        );
+        if allowlist.is_empty() {
+            return None;
+        }
+
+        let max_redirects = config.url_max_redirects;
+        let redirect_allowlist = Arc::clone(&allowlist);
+        let redirects = Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                attempt.error(format!("more than {} redirects", max_redirects))
+            } else if !is_allowed(&redirect_allowlist, attempt.url()) {
+                let target = redacted(attempt.url());
+                attempt.error(format!("redirected to {}, which is not allowed", target))
+            } else {
+                attempt.follow()
+            }
+        });
+        let client = reqwest::Client::builder()
+            .redirect(redirects)
+            .timeout(Duration::from_secs(config.url_fetch_timeout_secs))
+            .user_agent(concat!("murmure-server/", env!("CARGO_PKG_VERSION")))
+            .build();
+        match client {
+            Ok(client) => Some(Self {
+                client,
+                allowlist,
+                max_bytes: config.max_upload_bytes,
+                temp_dir: config.temp_dir.clone(),
+                min_free_bytes: config.min_free_disk_bytes(),
+            }),
+            Err(e) => {
+                tracing::error!("TranscribeUrl is disabled, the HTTP client failed: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Download `url` into memory, or a temp file when it is large.
+    pub async fn fetch(&self, url: &str) -> Result<Spooled, FetchError> {
+        let url = Url::parse(url).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+        let shown = redacted(&url);
+        if !is_allowed(&self.allowlist, &url) {
+            return Err(FetchError::NotAllowed(shown));
+        }
+        let request_error = |e: reqwest::Error| {
+            let url = shown.clone();
+            if e.is_timeout() {
+                FetchError::Timeout { url }
+            } else if e.is_redirect() {
+                let reason = describe(e);
+                FetchError::Redirect { url, reason }
+            } else {
+                let reason = describe(e);
+                FetchError::Request { url, reason }
+            }
+        };
+
+        let mut response = self.client.get(url).send().await.map_err(request_error)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(FetchError::HttpStatus { url: shown, status });
+        }
+        let too_large = |bytes: u64| self.max_bytes > 0 && bytes > self.max_bytes;
+        if response.content_length().is_some_and(too_large) {
+            return Err(FetchError::TooLarge {
+                url: shown,
+                limit: self.max_bytes,
+            });
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| {
+                value
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_ascii_lowercase()
+            });
+
+        let mut spool = UploadSpool::new(self.temp_dir.clone(), self.min_free_bytes);
+        if let Some(total_bytes) = response.content_length() {
+            spool
+                .check_space(total_bytes)
+                .map_err(|source| FetchError::Spool {
+                    url: shown.clone(),
+                    source,
+                })?;
+        }
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        while let Some(chunk) = response.chunk().await.map_err(request_error)? {
+            if too_large(spool.received_bytes() + chunk.len() as u64) {
+                return Err(FetchError::TooLarge {
+                    url: shown,
+                    limit: self.max_bytes,
+                });
+            }
+            if head.len() < SNIFF_LEN {
+                let take = (SNIFF_LEN - head.len()).min(chunk.len());
+                head.extend_from_slice(&chunk[..take]);
+            }
+            spool.write(&chunk).map_err(|source| FetchError::Spool {
+                url: shown.clone(),
+                source,
+            })?;
+        }
+
+        if let Some(content_type) = content_type {
+            check_content_type(&shown, &content_type, &head)?;
+        }
+        spool
+            .finish()
+            .map_err(|source| FetchError::Spool { url: shown, source })
+    }
+}
+
+/// `url` is under one of the allowed prefixes.
+fn is_allowed(allowlist: &[Url], url: &Url) -> bool {
+    allowlist.iter().any(|prefix| {
+        prefix.scheme() == url.scheme()
+            && prefix.host_str() == url.host_str()
+            && prefix.port_or_known_default() == url.port_or_known_default()
+            && path_under(url.path(), prefix.path())
+    })
+}
+
+/// `path` is `prefix` or a path below it, on a segment boundary.
+fn path_under(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || prefix.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// The error and its causes, without the URL reqwest includes with its query.
+fn describe(e: reqwest::Error) -> String {
+    let e = e.without_url();
+    let mut reason = e.to_string();
+    let mut source = std::error::Error::source(&e);
+    while let Some(cause) = source {
+        reason.push_str(": ");
+        reason.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    reason
+}
+
+/// `url` without its query and fragment.
+fn redacted(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_query(None);
+    url.set_fragment(None);
+    url.to_string()
+}
+
+/// Containers recognized from their first bytes, with the content types
+/// that may announce them.
+fn sniff_container(head: &[u8]) -> Option<(&'static str, &'static [&'static str])> {
+    match head {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some((
+            "WAV",
+            &["audio/wav", "audio/x-wav", "audio/wave", "audio/vnd.wave"],
+        )),
+        [b'I', b'D', b'3', ..] => Some(("MP3", &["audio/mpeg", "audio/mp3"])),
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some(("MP3", &["audio/mpeg", "audio/mp3"])),
+        [b'O', b'g', b'g', b'S', ..] => {
+            Some(("Ogg", &["audio/ogg", "application/ogg", "audio/opus"]))
+        }
+        [b'f', b'L', b'a', b'C', ..] => Some(("FLAC", &["audio/flac", "audio/x-flac"])),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some((
+            "MP4",
+            &["audio/mp4", "audio/m4a", "audio/x-m4a", "video/mp4"],
+        )),
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => Some(("WebM", &["audio/webm", "video/webm"])),
+        _ => None,
+    }
+}
+
+/// The Content-Type names the container that was received, or is generic.
+/// Whether that container can be decoded is left to the transcription.
+fn check_content_type(url: &str, content_type: &str, head: &[u8]) -> Result<(), FetchError> {
+    if GENERIC_CONTENT_TYPES.contains(&content_type) {
+        return Ok(());
+    }
+    let (actual, expected) = match sniff_container(head) {
+        Some((container, types)) => (container, types),
+        None => ("not a known audio container", &[][..]),
+    };
+    if expected.contains(&content_type) {
+        return Ok(());
+    }
+    Err(FetchError::ContentType {
+        url: url.to_string(),
+        content_type: content_type.to_string(),
+        actual,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(prefixes: &[&str], url: &str) -> bool {
+        let allowlist: Vec<Url> = prefixes.iter().map(|p| Url::parse(p).unwrap()).collect();
+        is_allowed(&allowlist, &Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn prefixes_match_on_segment_boundaries() {
+        let prefixes = ["https://bucket.example.com/audio"];
+        assert!(allowed(&prefixes, "https://bucket.example.com/audio"));
+        assert!(allowed(&prefixes, "https://bucket.example.com/audio/a.wav"));
+        assert!(allowed(
+            &prefixes,
+            "https://bucket.example.com/audio/a.wav?X-Amz-Signature=1"
+        ));
+        assert!(!allowed(
+            &prefixes,
+            "https://bucket.example.com/audio-private/a.wav"
+        ));
+        assert!(!allowed(
+            &prefixes,
+            "https://bucket.example.com/audiofile.wav"
+        ));
+        assert!(!allowed(
+            &prefixes,
+            "https://bucket.example.com/other/a.wav"
+        ));
+        // Dot segments are resolved before the comparison
+        assert!(!allowed(
+            &prefixes,
+            "https://bucket.example.com/audio/../secret.wav"
+        ));
+        assert!(!allowed(
+            &prefixes,
+            "https://bucket.example.com/audio/%2e%2e/secret.wav"
+        ));
+
+        let slash = ["https://bucket.example.com/audio/"];
+        assert!(allowed(&slash, "https://bucket.example.com/audio/a.wav"));
+        assert!(!allowed(&slash, "https://bucket.example.com/audio"));
+
+        let host = ["https://bucket.example.com"];
+        assert!(allowed(&host, "https://bucket.example.com/anything.wav"));
+    }
+
+    #[test]
+    fn prefixes_match_scheme_host_and_port() {
+        let prefixes = ["https://bucket.example.com/audio/"];
+        assert!(allowed(
+            &prefixes,
+            "https://bucket.example.com:443/audio/a.wav"
+        ));
+        assert!(!allowed(&prefixes, "http://bucket.example.com/audio/a.wav"));
+        assert!(!allowed(
+            &prefixes,
+            "https://bucket.example.com:8443/audio/a.wav"
+        ));
+        assert!(!allowed(
+            &prefixes,
+            "https://bucket.example.com.evil.net/audio/a.wav"
+        ));
+        assert!(!allowed(&prefixes, "https://other.example.com/audio/a.wav"));
+    }
+
+    #[test]
+    fn urls_are_shown_without_their_query() {
+        let url = Url::parse("https://host/a.wav?X-Amz-Signature=secret#frag").unwrap();
+        assert_eq!(redacted(&url), "https://host/a.wav");
+    }
+
+    #[test]
+    fn content_types_must_name_the_container() {
+        let wav = b"RIFF\0\0\0\0WAVEfmt ";
+        assert!(check_content_type("u", "audio/wav", wav).is_ok());
+        assert!(check_content_type("u", "application/octet-stream", b"anything").is_ok());
+        assert!(check_content_type("u", "audio/mpeg", wav).is_err());
+        assert!(check_content_type("u", "text/html", b"<html>").is_err());
+        assert!(check_content_type("u", "audio/flac", b"fLaC\0\0\0\0").is_ok());
+    }
+
+    #[test]
+    fn failures_map_to_statuses() {
+        let url = || "https://host/a.wav".to_string();
+        let code = |error: FetchError| error.status().code();
+        assert_eq!(
+            code(FetchError::InvalidUrl(url())),
+            tonic::Code::InvalidArgument
+        );
+        assert_eq!(
+            code(FetchError::NotAllowed(url())),
+            tonic::Code::PermissionDenied
+        );
+        assert_eq!(
+            code(FetchError::Timeout { url: url() }),
+            tonic::Code::DeadlineExceeded
+        );
+        let status = |status| FetchError::HttpStatus { url: url(), status };
+        assert_eq!(
+            code(status(StatusCode::BAD_GATEWAY)),
+            tonic::Code::Unavailable
+        );
+        assert_eq!(
+            code(status(StatusCode::NOT_FOUND)),
+            tonic::Code::FailedPrecondition
+        );
+        let too_large = FetchError::TooLarge {
+            url: url(),
+            limit: 1,
+        };
+        assert_eq!(code(too_large), tonic::Code::FailedPrecondition);
+    }
+}
@@ -1,27 +1,260 @@
-use murmure_stt::transcription::TranscriptionService;
+use murmure_stt::config::{is_language_code, IncompleteStream, ServerConfig};
+use murmure_stt::dictionary::{self, Dictionary};
+use murmure_stt::filter::ProfanityFilter;
+use murmure_stt::formats::{self, CueOptions, OutputFormat};
+use murmure_stt::model::Model;
+use murmure_stt::tempfiles;
+use murmure_stt::transcription::{
+    ChannelMode, ChannelTranscript, StageTimings, Transcript, TranscriptSegment,
+    TranscriptionOptions, TranscriptionService, TwoPassOutcome,
+};
+use murmure_stt::validation;
+use murmure_stt::DecodingOptions;
+use murmure_stt::SpeechStats;
+use murmure_stt::TranscriptionError;
+use murmure_stt::{DetectedCommand, DetectedLanguage, VoiceCommand};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::{Request, Response, Status};
+use tonic::metadata::AsciiMetadataValue;
+use tonic::{Code, Request, Response, Status};
 
-// Include the generated proto code
-pub mod murmure {
-    include!(concat!(env!("OUT_DIR"), "/murmure.rs"));
-}
+use super::debug_dump::{DebugDumps, DEBUG_DUMP_HEADER};
+use super::fetch::UrlFetcher;
+use super::history::{HistoryEntry, HistoryStore};
+use super::idempotency::IdempotencyCache;
+use super::jobs::JobQueue;
+use super::ratelimit::{client_label, ClientKey, RateLimiter, ADMIN_TOKEN_HEADER};
+use super::salvage::SalvageDir;
+use super::session::{new_session_id, MemorySessionStore, SessionState, SessionStore};
+use super::upload::{Spooled, UploadRegistry, UploadSpool};
+
+// Generated proto code, shared with the client crate
+pub use murmure_proto as murmure;
 
 use murmure::{
-    TranscribeFileRequest, TranscribeFileResponse, TranscribeStreamRequest,
-    TranscribeStreamResponse,
+    CancelJobRequest, ChannelResult, ClientUsage, CorrectTextRequest, CorrectTextResponse,
+    Correction, GetHistoryRequest, GetHistoryResponse, GetJobResultRequest, GetJobStatusRequest,
+    GetServerInfoRequest, GetServerInfoResponse, GetUploadProgressRequest,
+    GetUploadProgressResponse, GetUsageRequest, GetUsageResponse, InferenceOptions, JobStatus,
+    ListModelsRequest, ListModelsResponse, ModelInfo, Segment, StreamConfig,
+    SubmitTranscriptionJobRequest, SubmitTranscriptionJobResponse, TranscribeFileRequest,
+    TranscribeFileResponse, TranscribeLargeFileRequest, TranscribeStreamRequest,
+    TranscribeStreamResponse, TranscribeUrlRequest,
 };
 
 pub struct TranscriptionServiceImpl {
     service: Arc<TranscriptionService>,
+    uploads: UploadRegistry,
+    history: HistoryStore,
+    salvage: Option<Arc<SalvageDir>>,
+    /// Debug dumps of flagged requests (`None` without MURMURE_DEBUG_DUMP_DIR)
+    debug_dumps: Option<Arc<DebugDumps>>,
+    /// Stream sessions (`None` when MURMURE_SESSION_TTL_SECS is 0)
+    sessions: Option<Arc<dyn SessionStore>>,
+    /// TranscribeFile responses by idempotency key (`None` when
+    /// MURMURE_IDEMPOTENCY_TTL_SECS is 0)
+    idempotency: Option<IdempotencyCache>,
+    /// Background transcription jobs (`None` when MURMURE_JOB_WORKERS is 0)
+    jobs: Option<Arc<JobQueue>>,
+    /// Fetches TranscribeUrl audio (`None` when MURMURE_URL_ALLOWLIST is empty)
+    fetcher: Option<UrlFetcher>,
+    limiter: Arc<RateLimiter>,
+    /// Fixed part of the metadata attached to transcription responses
+    engine_metadata: Vec<(&'static str, AsciiMetadataValue)>,
+    /// Fixed metadata of each model, by model name
+    model_metadata: HashMap<String, Vec<(&'static str, AsciiMetadataValue)>>,
 }
 
 impl TranscriptionServiceImpl {
-    pub fn new(service: Arc<TranscriptionService>) -> Self {
-        Self { service }
+    pub fn new(service: Arc<TranscriptionService>, limiter: Arc<RateLimiter>) -> Self {
+        let salvage = SalvageDir::from_config(service.get_config()).map(Arc::new);
+        let debug_dumps = DebugDumps::from_config(service.get_config()).map(Arc::new);
+        let session_ttl = service.get_config().session_ttl_secs;
+        let sessions = (session_ttl > 0).then(|| {
+            Arc::new(MemorySessionStore::new(Duration::from_secs(session_ttl)))
+                as Arc<dyn SessionStore>
+        });
+        let config = service.get_config();
+        let idempotency = (config.idempotency_ttl_secs > 0).then(|| {
+            IdempotencyCache::new(
+                Duration::from_secs(config.idempotency_ttl_secs),
+                config.idempotency_max_entries,
+            )
+        });
+        let model_metadata = service
+            .get_models()
+            .iter()
+            .map(|model| (model.name(), model_metadata(model)))
+            .collect();
+        let jobs = JobQueue::start(Arc::clone(&service));
+        let fetcher = UrlFetcher::from_config(service.get_config());
+        Self {
+            service,
+            uploads: UploadRegistry::default(),
+            history: HistoryStore::default(),
+            salvage,
+            debug_dumps,
+            sessions,
+            idempotency,
+            jobs,
+            fetcher,
+            limiter,
+            engine_metadata: engine_metadata(),
+            model_metadata,
+        }
+    }
+
+    /// The job queue, or `FAILED_PRECONDITION` when jobs are disabled.
+    fn jobs(&self) -> Result<&JobQueue, Status> {
+        self.jobs.as_deref().ok_or_else(|| {
+            Status::failed_precondition("background jobs are disabled (MURMURE_JOB_WORKERS=0)")
+        })
+    }
+
+    /// Transcribe an upload or fetched file, counting its audio against the
+    /// client's budget.
+    async fn transcribe_spooled(
+        &self,
+        client: &ClientKey,
+        spooled: Spooled,
+        options: TranscriptionOptions,
+        model: &Model,
+    ) -> Result<Response<TranscribeFileResponse>, Status> {
+        let audio_secs = match &spooled {
+            Spooled::Memory(audio_data) => wav_secs(audio_data),
+            Spooled::Disk(file) => validation::validate_wav_file(file.path(), 0.0, None)
+                .map_or(0.0, |info| info.duration_secs),
+        };
+        let _permit = self.limiter.reserve_audio(client, audio_secs)?;
+        let include_timing = options.include_timing;
+        file_response(
+            match spooled {
+                Spooled::Memory(audio_data) => {
+                    self.service
+                        .transcribe_audio_bytes_async(audio_data, options)
+                        .await
+                }
+                Spooled::Disk(file) => {
+                    // `file` stays alive (and on disk) until transcription is done
+                    self.service
+                        .transcribe_file_async(file.path().to_path_buf(), options)
+                        .await
+                }
+            },
+            OutputFormat::Text,
+            include_timing,
+        )
+        .map(|response| self.with_engine_metadata(response, Some(model)))
+    }
+
+    /// Whether the request carries the debug dump secret.
+    fn debug_dump_requested<T>(&self, request: &Request<T>) -> Result<bool, Status> {
+        match &self.debug_dumps {
+            Some(dumps) => dumps.requested(request.metadata()),
+            None if request.metadata().contains_key(DEBUG_DUMP_HEADER) => {
+                Err(Status::failed_precondition(
+                    "debug dumps are disabled (MURMURE_DEBUG_DUMP_DIR is not set)",
+                ))
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Dump a flagged request's audio and engine output, returning the name
+    /// of the dump's directory. A failed dump is logged, not reported to the
+    /// client.
+    async fn debug_dump(
+        &self,
+        payload: Vec<u8>,
+        transcript: &Transcript,
+        model: &Model,
+    ) -> Option<String> {
+        let dumps = Arc::clone(self.debug_dumps.as_ref()?);
+        let transcript = transcript.clone();
+        let model = model.name();
+        match tokio::task::spawn_blocking(move || dumps.save(&payload, &transcript, &model)).await {
+            Ok(Ok(name)) => name,
+            Ok(Err(e)) => {
+                tracing::error!("Failed to write debug dump: {}", e);
+                None
+            }
+            Err(e) => {
+                tracing::error!("Debug dump task failed: {}", e);
+                None
+            }
+        }
+    }
+
+    fn client_key<T>(&self, request: &Request<T>) -> ClientKey {
+        self.limiter
+            .client_key(request.metadata(), request.remote_addr())
     }
+
+    /// Attach the server, engine and model identifiers to a response, so a
+    /// transcript can be traced back to what produced it.
+    fn with_engine_metadata<T>(
+        &self,
+        mut response: Response<T>,
+        model: Option<&Model>,
+    ) -> Response<T> {
+        let metadata = response.metadata_mut();
+        for (key, value) in &self.engine_metadata {
+            metadata.insert(*key, value.clone());
+        }
+        if let Some(model) = model {
+            for (key, value) in self.model_metadata.get(&model.name()).into_iter().flatten() {
+                metadata.insert(*key, value.clone());
+            }
+            if let Some(provider) = model.execution_provider() {
+                metadata.insert(
+                    "x-murmure-execution-provider",
+                    AsciiMetadataValue::from_static(provider.as_str()),
+                );
+            }
+        }
+        let revision = self
+            .service
+            .get_dictionary()
+            .map_or(0, |dictionary| dictionary.revision());
+        metadata.insert("x-murmure-dictionary-revision", revision.into());
+        response
+    }
+}
+
+/// Response metadata that doesn't change while the server runs.
+fn engine_metadata() -> Vec<(&'static str, AsciiMetadataValue)> {
+    [
+        ("x-murmure-server-version", env!("CARGO_PKG_VERSION")),
+        ("x-murmure-engine", "parakeet-tdt"),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| Some((key, value.parse().ok()?)))
+    .collect()
+}
+
+/// Response metadata identifying a model.
+fn model_metadata(model: &Model) -> Vec<(&'static str, AsciiMetadataValue)> {
+    let mut entries = vec![
+        ("x-murmure-model", model.name()),
+        (
+            "x-murmure-model-precision",
+            model.precision().as_str().to_string(),
+        ),
+    ];
+    if let Some(modified) = model
+        .modified()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+    {
+        entries.push(("x-murmure-model-mtime", modified.as_secs().to_string()));
+    }
+
+    entries
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value.parse().ok()?)))
+        .collect()
 }
 
 #[tonic::async_trait]
@@ -30,32 +263,93 @@ impl murmure::transcription_service_server::TranscriptionService for Transcripti
         &self,
         request: Request<TranscribeFileRequest>,
     ) -> Result<Response<TranscribeFileResponse>, Status> {
-        let req = request.into_inner();
-        let audio_data = req.audio_data;
+        let client = self.client_key(&request);
+        // Clients known by their address only may share it, so their keys
+        // could collide: only API key clients get replays
+        let idempotency_key = match &self.idempotency {
+            Some(_) if client.is_api_key() => IdempotencyCache::key_from(request.metadata())?,
+            _ => None,
+        };
+        let debug_dump = self.debug_dump_requested(&request)?;
+        let mut req = request.into_inner();
+        let channel_mode = to_channel_mode(req.channel_mode());
+        let format = to_output_format(req.output_format());
+        let model = requested_model(&self.service, &req.model)?;
+        check_inference(req.inference.as_ref())?;
+        let audio_data = std::mem::take(&mut req.audio_data);
+        let fingerprint = idempotency_key
+            .is_some()
+            .then(|| IdempotencyCache::fingerprint(&audio_data, &req));
+
+        let mut options = TranscriptionOptions::from_config(self.service.get_config());
+        options.use_dictionary = req.use_dictionary;
+        options.language = requested_language(&req.language)?;
+        options.segment_on_silence |= req.segment_on_silence;
+        apply_overrides(
+            &mut options,
+            req.filter_profanity,
+            req.redact,
+            req.punctuate,
+            req.itn,
+        );
+        apply_preprocess(&mut options, req.highpass_hz, req.denoise);
+        apply_inference(&mut options, req.inference.as_ref());
+        options.channel_mode = channel_mode;
+        options.include_stats = req.include_stats;
+        options.include_timing = req.include_timing;
+        options.model = Some(model.name());
+        options.capture_debug = debug_dump;
+        options.two_pass = req.two_pass;
+        // Subtitle cues are timed from the pause-delimited segments
+        options.segment_on_silence |= format.is_subtitle();
 
         tracing::debug!(
             "Received transcribe_file request: {} bytes",
             audio_data.len()
         );
+        let include_timing = options.include_timing;
+        let audio_secs = wav_secs(&audio_data);
+        let payload = debug_dump.then(|| audio_data.clone());
+        let mut dump_name = None;
+        let transcribe = async {
+            let _permit = self.limiter.reserve_audio(&client, audio_secs)?;
+            let result = self
+                .service
+                .transcribe_audio_bytes_async(audio_data, options)
+                .await;
+            if let (Some(payload), Ok(transcript)) = (payload, &result) {
+                dump_name = self.debug_dump(payload, transcript, &model).await;
+            }
+            file_response(result, format, include_timing).map(Response::into_inner)
+        };
 
-        match self.service.transcribe_audio_bytes(&audio_data) {
-            Ok(text) => {
-                tracing::info!("Transcription successful: {} chars", text.len());
-                Ok(Response::new(TranscribeFileResponse {
-                    text,
-                    success: true,
-                    error: String::new(),
-                }))
+        // A retry with the key of an earlier request gets that request's
+        // response, without running or being charged for the audio again
+        let (outcome, replayed) = match (&self.idempotency, idempotency_key, fingerprint) {
+            (Some(cache), Some(key), Some(fingerprint)) => {
+                cache
+                    .run(client.clone(), key, fingerprint, transcribe)
+                    .await
             }
-            Err(e) => {
-                tracing::error!("Transcription failed: {}", e);
-                Ok(Response::new(TranscribeFileResponse {
-                    text: String::new(),
-                    success: false,
-                    error: format!("Transcription failed: {}", e),
-                }))
+            _ => (transcribe.await, false),
+        };
+        if replayed {
+            tracing::debug!("Replayed the response to an idempotency key");
+            if self.service.get_config().usage_count_replays {
+                self.limiter.usage().record(&client, audio_secs);
             }
         }
+        let mut response = self.with_engine_metadata(Response::new(outcome?), Some(&model));
+        if replayed {
+            response.metadata_mut().insert(
+                "x-murmure-idempotent-replay",
+                AsciiMetadataValue::from_static("true"),
+            );
+        }
+        if let Some(name) = dump_name.and_then(|name| name.parse().ok()) {
+            response.metadata_mut().insert(DEBUG_DUMP_HEADER, name);
+        }
+        Ok(response)
     }
 
     type TranscribeStreamStream = ReceiverStream<Result<TranscribeStreamResponse, Status>>;
@@ -64,36 +358,259 @@ impl murmure::transcription_service_server::TranscriptionService for Transcripti
         &self,
         request: Request<tonic::Streaming<TranscribeStreamRequest>>,
     ) -> Result<Response<Self::TranscribeStreamStream>, Status> {
+        let client = self.client_key(&request);
         let mut stream = request.into_inner();
         let (tx, rx) = mpsc::channel(128);
 
         let service = Arc::clone(&self.service);
+        let limiter = Arc::clone(&self.limiter);
+        let history = self.history.clone();
+        let salvage = self.salvage.clone();
+        let session = self.sessions.clone().map(|store| (store, new_session_id()));
 
         tokio::spawn(async move {
+            use murmure::transcribe_stream_request::RequestType;
+
+            if let Some((_, session_id)) = &session {
+                let started = session_response(session_id, false, 0, 0);
+                if tx.send(Ok(started)).await.is_err() {
+                    return;
+                }
+            }
+
             let mut audio_buffer = Vec::new();
             let mut end_of_stream = false;
+            let mut utterance_id = 0;
+            let mut options = TranscriptionOptions::from_config(service.get_config());
+            let keep_incomplete =
+                service.get_config().incomplete_stream == IncompleteStream::Transcribe;
+            let heartbeat_secs = service.get_config().stream_heartbeat_secs;
+            let mut heartbeat =
+                Heartbeat::new((heartbeat_secs > 0).then(|| Duration::from_secs(heartbeat_secs)));
+            let mut bytes_received = 0;
+            let mut messages_received = 0;
+            // Between a pause and its resume, audio chunks are dropped
+            let mut paused = false;
+            let mut dropped_bytes = 0;
 
-            while let Some(result) = stream.message().await.transpose() {
+            loop {
+                let result = tokio::select! {
+                    message = stream.message() => match message.transpose() {
+                        Some(result) => result,
+                        None => break,
+                    },
+                    () = heartbeat.tick() => {
+                        let progress = stream_progress(bytes_received, &audio_buffer, paused);
+                        if tx.send(Ok(heartbeat_response(progress, utterance_id))).await.is_err() {
+                            // Client gone: keep what it sent, in its session
+                            // if it fits, salvaged otherwise
+                            let audio = save_session(
+                                &session,
+                                service.get_config(),
+                                &options,
+                                utterance_id,
+                                std::mem::take(&mut audio_buffer),
+                            );
+                            if let Some(salvage) =
+                                salvage.filter(|_| keep_incomplete && !audio.is_empty())
+                            {
+                                salvage_utterance(
+                                    Arc::clone(&service),
+                                    salvage,
+                                    history.clone(),
+                                    client.clone(),
+                                    audio,
+                                    options.clone(),
+                                );
+                            }
+                            return;
+                        }
+                        continue;
+                    }
+                };
+                if result.is_ok() {
+                    messages_received += 1;
+                }
                 match result {
-                    Ok(req) => {
-                        match req.request_type {
-                            Some(murmure::transcribe_stream_request::RequestType::AudioChunk(
-                                chunk,
-                            )) => {
-                                audio_buffer.extend_from_slice(&chunk);
+                    Ok(req) => match req.request_type {
+                        Some(RequestType::Config(config))
+                            if !config.resume_session_id.is_empty() =>
+                        {
+                            let sessions = session.as_ref().map(|(store, _)| store.as_ref());
+                            let state = match resume_session(
+                                sessions,
+                                &config.resume_session_id,
+                                messages_received == 1,
+                            ) {
+                                Ok(state) => state,
+                                Err(status) => {
+                                    let _ = tx.send(Err(status)).await;
+                                    return;
+                                }
+                            };
+                            tracing::debug!(
+                                "Resumed session at utterance {} with {} buffered bytes",
+                                state.next_utterance_id,
+                                state.pending_audio.len()
+                            );
+                            options = state.options;
+                            utterance_id = state.next_utterance_id;
+                            audio_buffer = state.pending_audio;
+                            if let Some((_, session_id)) = &session {
+                                let resumed = session_response(
+                                    session_id,
+                                    true,
+                                    utterance_id,
+                                    audio_buffer.len() as u64,
+                                );
+                                if tx.send(Ok(resumed)).await.is_err() {
+                                    return;
+                                }
+                            }
+                        }
+                        Some(RequestType::AudioChunk(chunk)) if paused => {
+                            // Chunks the client sent before its pause reached
+                            // us are dropped rather than failing the stream
+                            dropped_bytes += chunk.len() as u64;
+                        }
+                        Some(RequestType::AudioChunk(chunk)) => {
+                            bytes_received += chunk.len() as u64;
+                            // Hard cap, for buffers the duration check can't
+                            // measure: no header yet, or an inflated byte rate
+                            if let Some(limit) = stream_byte_limit(service.get_config(), &options)
+                                .filter(|&limit| (audio_buffer.len() + chunk.len()) as u64 > limit)
+                            {
+                                let _ = tx
+                                    .send(Err(Status::invalid_argument(format!(
+                                        "stream audio exceeds the {} byte limit",
+                                        limit
+                                    ))))
+                                    .await;
+                                return;
+                            }
+                            audio_buffer.extend_from_slice(&chunk);
+                            // Stop before the whole overlong utterance is buffered
+                            if let Some(max_secs) = options.max_audio_secs {
+                                if let Err(e) = validation::check_received(&audio_buffer, max_secs)
+                                {
+                                    let _ =
+                                        tx.send(Err(Status::invalid_argument(e.to_string()))).await;
+                                    return;
+                                }
                             }
-                            Some(murmure::transcribe_stream_request::RequestType::EndOfStream(
-                                _,
-                            )) => {
-                                end_of_stream = true;
-                                break;
+                        }
+                        Some(RequestType::Config(config)) => {
+                            if let Err(status) = requested_model(&service, &config.model) {
+                                let _ = tx.send(Err(status)).await;
+                                return;
                             }
-                            None => {
-                                // Empty request, ignore
+                            if let Err(status) = check_stream_config(&config) {
+                                let _ = tx.send(Err(status)).await;
+                                return;
                             }
+                            apply_stream_config(&mut options, &config);
                         }
-                    }
+                        Some(RequestType::UtteranceBoundary(_)) => {
+                            // Session: transcribe this utterance, keep the stream open
+                            let audio = std::mem::take(&mut audio_buffer);
+                            let permit = match limiter.reserve_audio(&client, wav_secs(&audio)) {
+                                Ok(permit) => permit,
+                                Err(status) => {
+                                    let _ = tx.send(Err(status)).await;
+                                    return;
+                                }
+                            };
+                            let progress = stream_progress(bytes_received, &audio, paused);
+                            let sent = heartbeat
+                                .during(
+                                    send_utterance(
+                                        &service,
+                                        audio,
+                                        &options,
+                                        utterance_id,
+                                        false,
+                                        &tx,
+                                    ),
+                                    &tx,
+                                    progress,
+                                    utterance_id,
+                                )
+                                .await;
+                            drop(permit);
+                            if !sent {
+                                save_session(
+                                    &session,
+                                    service.get_config(),
+                                    &options,
+                                    utterance_id + 1,
+                                    Vec::new(),
+                                );
+                                return;
+                            }
+                            utterance_id += 1;
+                        }
+                        Some(RequestType::Pause(_)) | Some(RequestType::Resume(_)) => {
+                            let pause = matches!(req.request_type, Some(RequestType::Pause(_)));
+                            if let Err(status) = check_pause_transition(paused, pause) {
+                                let _ = tx.send(Err(status)).await;
+                                return;
+                            }
+                            paused = pause;
+                            if !paused && dropped_bytes > 0 {
+                                tracing::debug!(
+                                    "Dropped {} bytes of audio sent while the stream was paused",
+                                    dropped_bytes
+                                );
+                                dropped_bytes = 0;
+                            }
+                            // Acknowledge right away rather than at the next heartbeat
+                            let progress = stream_progress(bytes_received, &audio_buffer, paused);
+                            if tx
+                                .send(Ok(heartbeat_response(progress, utterance_id)))
+                                .await
+                                .is_err()
+                            {
+                                let audio = std::mem::take(&mut audio_buffer);
+                                save_session(
+                                    &session,
+                                    service.get_config(),
+                                    &options,
+                                    utterance_id,
+                                    audio,
+                                );
+                                return;
+                            }
+                        }
+                        Some(RequestType::EndOfStream(_)) => {
+                            end_of_stream = true;
+                            break;
+                        }
+                        None => {
+                            // Empty request, ignore
+                        }
+                    },
                     Err(e) => {
+                        // Client dropped mid-utterance: keep what it sent, in
+                        // its session if it fits, salvaged otherwise
+                        let audio = save_session(
+                            &session,
+                            service.get_config(),
+                            &options,
+                            utterance_id,
+                            std::mem::take(&mut audio_buffer),
+                        );
+                        if let Some(salvage) =
+                            salvage.filter(|_| keep_incomplete && !audio.is_empty())
+                        {
+                            salvage_utterance(
+                                Arc::clone(&service),
+                                salvage,
+                                history.clone(),
+                                client.clone(),
+                                audio,
+                                options.clone(),
+                            );
+                        }
                         let _ = tx
                             .send(Ok(TranscribeStreamResponse {
                                 response_type: Some(
@@ -102,6 +619,7 @@ impl murmure::transcription_service_server::TranscriptionService for Transcripti
                                     ),
                                 ),
                                 is_final: false,
+                                ..Default::default()
                             }))
                             .await;
                         return;
@@ -109,37 +627,1283 @@ impl murmure::transcription_service_server::TranscriptionService for Transcripti
                 }
             }
 
-            // Process accumulated audio buffer
-            if !audio_buffer.is_empty() || end_of_stream {
-                match service.transcribe_audio_bytes(&audio_buffer) {
-                    Ok(text) => {
-                        let response = TranscribeStreamResponse {
-                            response_type: Some(
-                                murmure::transcribe_stream_response::ResponseType::FinalText(text),
-                            ),
-                            is_final: true,
-                        };
-                        let _ = tx.send(Ok(response)).await;
-                    }
-                    Err(e) => {
-                        let response = TranscribeStreamResponse {
-                            response_type: Some(
-                                murmure::transcribe_stream_response::ResponseType::Error(format!(
-                                    "Transcription failed: {}",
-                                    e
-                                )),
-                            ),
-                            is_final: true,
-                        };
-                        let _ = tx.send(Ok(response)).await;
+            // Audio after the last boundary, or the whole stream for
+            // single-utterance clients. EndOfStream always gets a final
+            // result, even without audio; a stream half-closed without it
+            // is transcribed or discarded as configured.
+            let transcribe_rest = if end_of_stream {
+                !audio_buffer.is_empty() || utterance_id == 0
+            } else {
+                keep_incomplete && !audio_buffer.is_empty()
+            };
+            if !end_of_stream && !audio_buffer.is_empty() && !keep_incomplete {
+                tracing::debug!(
+                    "Discarding {} bytes of a stream that ended without EndOfStream",
+                    audio_buffer.len()
+                );
+            }
+            if transcribe_rest {
+                let _permit = match limiter.reserve_audio(&client, wav_secs(&audio_buffer)) {
+                    Ok(permit) => permit,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        return;
                     }
-                }
+                };
+                let progress = stream_progress(bytes_received, &audio_buffer, paused);
+                heartbeat
+                    .during(
+                        send_utterance(&service, audio_buffer, &options, utterance_id, true, &tx),
+                        &tx,
+                        progress,
+                        utterance_id,
+                    )
+                    .await;
+                utterance_id += 1;
             }
+            save_session(
+                &session,
+                service.get_config(),
+                &options,
+                utterance_id,
+                Vec::new(),
+            );
 
             // Signal end of response stream
             drop(tx);
         });
 
-        Ok(Response::new(ReceiverStream::new(rx)))
+        // Headers go out before the StreamConfig that may pick another model,
+        // so they only name the model when there is no choice
+        let model = match self.service.get_models() {
+            [only] => Some(only.as_ref()),
+            _ => None,
+        };
+        Ok(self.with_engine_metadata(Response::new(ReceiverStream::new(rx)), model))
+    }
+
+    async fn transcribe_large_file(
+        &self,
+        request: Request<tonic::Streaming<TranscribeLargeFileRequest>>,
+    ) -> Result<Response<TranscribeFileResponse>, Status> {
+        use murmure::transcribe_large_file_request::RequestType;
+
+        let client = self.client_key(&request);
+        let mut stream = request.into_inner();
+        let max_upload_bytes = self.service.get_config().max_upload_bytes;
+        let exceeds_limit = |bytes: u64| max_upload_bytes > 0 && bytes > max_upload_bytes;
+
+        let start = match stream.message().await? {
+            Some(TranscribeLargeFileRequest {
+                request_type: Some(RequestType::Start(start)),
+            }) => start,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "the first message of an upload must be UploadStart",
+                ))
+            }
+        };
+        if exceeds_limit(start.total_bytes) {
+            return Err(Status::invalid_argument(format!(
+                "upload of {} bytes exceeds the {} byte limit",
+                start.total_bytes, max_upload_bytes
+            )));
+        }
+
+        let (options, model) = upload_options(&self.service, start.options.as_ref())?;
+
+        let progress = if start.upload_id.is_empty() {
+            None
+        } else {
+            let guard = self
+                .uploads
+                .register(start.upload_id.clone(), start.total_bytes)
+                .ok_or_else(|| {
+                    Status::already_exists(format!(
+                        "upload '{}' is already in progress",
+                        start.upload_id
+                    ))
+                })?;
+            Some(guard)
+        };
+
+        let settings = self.service.get_config();
+        let mut spool = UploadSpool::new(settings.temp_dir.clone(), settings.min_free_disk_bytes());
+        spool.check_space(start.total_bytes).map_err(spool_error)?;
+        let mut committed = false;
+        while let Some(req) = stream.message().await? {
+            match req.request_type {
+                Some(RequestType::Chunk(chunk)) => {
+                    if exceeds_limit(spool.received_bytes() + chunk.len() as u64) {
+                        return Err(Status::invalid_argument(format!(
+                            "upload exceeds the {} byte limit",
+                            max_upload_bytes
+                        )));
+                    }
+                    spool.write(&chunk).map_err(spool_error)?;
+                    if let Some(progress) = &progress {
+                        progress.update(|p| p.received_bytes = spool.received_bytes());
+                    }
+                }
+                Some(RequestType::Commit(_)) => {
+                    committed = true;
+                    break;
+                }
+                Some(RequestType::Start(_)) => {
+                    return Err(Status::invalid_argument(
+                        "UploadStart may only be sent once per upload",
+                    ))
+                }
+                None => {
+                    // Empty request, ignore
+                }
+            }
+        }
+        if !committed {
+            return Err(Status::invalid_argument(
+                "upload ended without a commit message",
+            ));
+        }
+
+        tracing::debug!(
+            "Received transcribe_large_file upload: {} bytes",
+            spool.received_bytes()
+        );
+        if let Some(progress) = &progress {
+            progress.update(|p| p.transcribing = true);
+        }
+
+        let spooled = spool
+            .finish()
+            .map_err(|e| Status::internal(format!("Failed to spool upload: {}", e)))?;
+        self.transcribe_spooled(&client, spooled, options, &model)
+            .await
+    }
+
+    async fn transcribe_url(
+        &self,
+        request: Request<TranscribeUrlRequest>,
+    ) -> Result<Response<TranscribeFileResponse>, Status> {
+        let fetcher = self.fetcher.as_ref().ok_or_else(|| {
+            Status::failed_precondition(
+                "TranscribeUrl is disabled (MURMURE_URL_ALLOWLIST is empty)",
+            )
+        })?;
+        let client = self.client_key(&request);
+        let req = request.into_inner();
+        let (options, model) = upload_options(&self.service, req.options.as_ref())?;
+
+        let spooled = fetcher.fetch(&req.url).await.map_err(|e| {
+            tracing::warn!("TranscribeUrl fetch failed: {}", e);
+            e.status()
+        })?;
+        self.transcribe_spooled(&client, spooled, options, &model)
+            .await
+    }
+
+    async fn get_upload_progress(
+        &self,
+        request: Request<GetUploadProgressRequest>,
+    ) -> Result<Response<GetUploadProgressResponse>, Status> {
+        let upload_id = request.into_inner().upload_id;
+        let progress = self
+            .uploads
+            .get(&upload_id)
+            .ok_or_else(|| Status::not_found(format!("no upload '{}' in progress", upload_id)))?;
+
+        Ok(Response::new(GetUploadProgressResponse {
+            received_bytes: progress.received_bytes,
+            total_bytes: progress.total_bytes,
+            transcribing: progress.transcribing,
+        }))
+    }
+
+    async fn get_history(
+        &self,
+        request: Request<GetHistoryRequest>,
+    ) -> Result<Response<GetHistoryResponse>, Status> {
+        let client = self.client_key(&request);
+        let req = request.into_inner();
+        let entries = self
+            .history
+            .recent(&client, req.limit as usize)
+            .iter()
+            .map(to_proto_history_entry)
+            .collect();
+
+        Ok(Response::new(GetHistoryResponse { entries }))
+    }
+
+    async fn get_server_info(
+        &self,
+        _request: Request<GetServerInfoRequest>,
+    ) -> Result<Response<GetServerInfoResponse>, Status> {
+        let requested = self.service.get_config().execution_provider;
+        let active = self
+            .service
+            .execution_provider()
+            .map(|provider| provider.to_string())
+            .unwrap_or_default();
+        let load_stats = self.service.get_model().load_stats();
+
+        Ok(Response::new(GetServerInfoResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            execution_provider: active,
+            requested_execution_provider: requested.to_string(),
+            engine_panics: self.service.engine_panics(),
+            idempotent_replays: self
+                .idempotency
+                .as_ref()
+                .map_or(0, IdempotencyCache::replays),
+            dictionary_status: self.service.dictionary_status().to_string(),
+            dictionary_languages: self
+                .service
+                .dictionary_languages()
+                .map(str::to_string)
+                .collect(),
+            model_load_ms: load_stats.map_or(0, |stats| stats.load_time.as_millis() as u64),
+            model_resident_delta_bytes: load_stats
+                .and_then(|stats| stats.resident_delta_bytes)
+                .unwrap_or(0),
+            model_status: self
+                .service
+                .get_models()
+                .iter()
+                .map(|model| format!("{}: {}", model.name(), model.availability().summary()))
+                .collect(),
+            inference_defaults: Some(to_proto_inference(
+                &self.service.get_config().decoding_options(),
+            )),
+        }))
+    }
+
+    async fn list_models(
+        &self,
+        _request: Request<ListModelsRequest>,
+    ) -> Result<Response<ListModelsResponse>, Status> {
+        let models = self
+            .service
+            .get_models()
+            .iter()
+            .enumerate()
+            .map(|(index, model)| ModelInfo {
+                name: model.name(),
+                path: model
+                    .get_model_path()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default(),
+                precision: model.precision().as_str().to_string(),
+                is_default: index == 0,
+                loaded: model.is_loaded(),
+            })
+            .collect();
+
+        Ok(Response::new(ListModelsResponse {
+            models,
+            max_loaded_models: self.service.get_config().max_loaded_models as u32,
+        }))
+    }
+
+    async fn correct_text(
+        &self,
+        request: Request<CorrectTextRequest>,
+    ) -> Result<Response<CorrectTextResponse>, Status> {
+        let req = request.into_inner();
+        if req.words.iter().any(|word| word.trim().is_empty()) {
+            return Err(Status::invalid_argument("words contains an empty entry"));
+        }
+
+        let language = requested_language(&req.language)?;
+        let config = self.service.get_config();
+        let cc_rules = match &language {
+            Some(language) => config.get_language_cc_rules_path(language),
+            None => config.get_cc_rules_path(),
+        }
+        .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        let dictionary = if req.words.is_empty() {
+            self.service
+                .dictionary_for(language.as_deref())
+                .map_or_else(|| Arc::new(Dictionary::new(Vec::new())), Arc::clone)
+        } else {
+            Arc::new(Dictionary::new(req.words))
+        };
+
+        // Building the phonetic rules reads them from disk
+        let text = req.text;
+        let corrected = tokio::task::spawn_blocking(move || dictionary.correct(&text, &cc_rules))
+            .await
+            .map_err(|e| Status::internal(format!("dictionary correction failed: {e}")))?
+            .map_err(|e| transcription_status(&e.into()))?;
+
+        Ok(Response::new(CorrectTextResponse {
+            corrections: corrected
+                .corrections
+                .iter()
+                .map(to_proto_correction)
+                .collect(),
+            text: corrected.text,
+        }))
+    }
+
+    async fn submit_transcription_job(
+        &self,
+        request: Request<SubmitTranscriptionJobRequest>,
+    ) -> Result<Response<SubmitTranscriptionJobResponse>, Status> {
+        let jobs = self.jobs()?;
+        let client = self.client_key(&request);
+        let req = request.into_inner();
+        // Jobs run after the request returns, so their audio only counts
+        // against the quota, not the in-flight budget
+        let audio_secs = wav_secs(&req.audio_data);
+        self.limiter.usage().check(&client, audio_secs)?;
+        let (job_id, queue_position) = jobs.submit(client.clone(), req)?;
+        self.limiter.usage().record(&client, audio_secs);
+        tracing::debug!("Queued background job {}", job_id);
+
+        Ok(Response::new(SubmitTranscriptionJobResponse {
+            job_id,
+            queue_position,
+        }))
+    }
+
+    async fn get_job_status(
+        &self,
+        request: Request<GetJobStatusRequest>,
+    ) -> Result<Response<JobStatus>, Status> {
+        let client = self.client_key(&request);
+        let job_id = request.into_inner().job_id;
+        let status = self.jobs()?.status(&client, &job_id).ok_or_else(|| {
+            Status::not_found(format!("no job '{}', it may have expired", job_id))
+        })?;
+
+        Ok(Response::new(status))
+    }
+
+    async fn get_job_result(
+        &self,
+        request: Request<GetJobResultRequest>,
+    ) -> Result<Response<TranscribeFileResponse>, Status> {
+        let client = self.client_key(&request);
+        let job_id = request.into_inner().job_id;
+        let response = self.jobs()?.result(&client, &job_id)?;
+
+        Ok(self.with_engine_metadata(Response::new(response), None))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<CancelJobRequest>,
+    ) -> Result<Response<JobStatus>, Status> {
+        let client = self.client_key(&request);
+        let job_id = request.into_inner().job_id;
+        let status = self.jobs()?.cancel(&client, &job_id)?;
+        tracing::debug!("Cancelled background job {}", job_id);
+
+        Ok(Response::new(status))
+    }
+
+    async fn get_usage(
+        &self,
+        request: Request<GetUsageRequest>,
+    ) -> Result<Response<GetUsageResponse>, Status> {
+        let caller = self.client_key(&request);
+        let admin = self.limiter.is_admin(request.metadata())?;
+        let req = request.into_inner();
+        if req.to_unix_ms != 0 && req.to_unix_ms < req.from_unix_ms {
+            return Err(Status::invalid_argument(
+                "to_unix_ms must not be before from_unix_ms",
+            ));
+        }
+        if !admin && !req.client.is_empty() && req.client != caller.label() {
+            return Err(Status::permission_denied(format!(
+                "Only the caller's own usage is reported without {}",
+                ADMIN_TOKEN_HEADER
+            )));
+        }
+        // Without the admin token the caller is matched on its raw identity,
+        // so a label that happens to collide can't reveal another client
+        let include = |name: &str| match (admin, req.client.is_empty()) {
+            (true, true) => true,
+            (true, false) => client_label(name) == req.client,
+            (false, _) => name == caller.0,
+        };
+        let clients = self
+            .limiter
+            .usage()
+            .report(include, req.from_unix_ms, req.to_unix_ms)
+            .into_iter()
+            .map(|report| ClientUsage {
+                client: report.client,
+                audio_secs: report.usage.audio_secs,
+                requests: report.usage.requests,
+                month_audio_secs: report.month_audio_secs,
+                monthly_quota_secs: report.monthly_quota_secs,
+            })
+            .collect();
+
+        Ok(Response::new(GetUsageResponse { clients }))
+    }
+}
+
+/// Most bytes a stream may buffer for one utterance: MURMURE_MAX_UPLOAD_BYTES,
+/// and the duration limit at the highest byte rate accepted. `None` when
+/// neither is set.
+fn stream_byte_limit(config: &ServerConfig, options: &TranscriptionOptions) -> Option<u64> {
+    let uploads = (config.max_upload_bytes > 0).then_some(config.max_upload_bytes);
+    let duration = options.max_audio_secs.map(validation::max_received_bytes);
+    match (uploads, duration) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Duration declared by a WAV payload, for the audio rate limit. Malformed
+/// payloads count as nothing; validation rejects them later.
+fn wav_secs(audio: &[u8]) -> f32 {
+    validation::validate_wav(audio, 0.0, None).map_or(0.0, |info| info.duration_secs)
+}
+
+/// Periodic heartbeat responses of a stream, so proxies with an idle timeout
+/// don't reset it while the client is still sending audio or the server is
+/// transcribing.
+struct Heartbeat {
+    interval: Option<tokio::time::Interval>,
+}
+
+impl Heartbeat {
+    /// `None` disables heartbeats.
+    fn new(period: Option<Duration>) -> Self {
+        let interval = period.map(|period| {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            interval
+        });
+        Self { interval }
+    }
+
+    /// Wait until the next heartbeat is due, forever when disabled.
+    async fn tick(&mut self) {
+        match &mut self.interval {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Run `work` to completion, sending heartbeats while it runs.
+    async fn during<T>(
+        &mut self,
+        work: impl std::future::Future<Output = T>,
+        tx: &mpsc::Sender<Result<TranscribeStreamResponse, Status>>,
+        progress: murmure::Heartbeat,
+        utterance_id: u32,
+    ) -> T {
+        tokio::pin!(work);
+        loop {
+            tokio::select! {
+                output = &mut work => return output,
+                () = self.tick() => {
+                    // A closed stream is noticed by `work` when it sends its result
+                    let _ = tx.send(Ok(heartbeat_response(progress, utterance_id))).await;
+                }
+            }
+        }
+    }
+}
+
+fn stream_progress(bytes_received: u64, audio_buffer: &[u8], paused: bool) -> murmure::Heartbeat {
+    murmure::Heartbeat {
+        bytes_received,
+        buffered_secs: validation::received_secs(audio_buffer),
+        paused,
+    }
+}
+
+/// A stream can only be paused while taking audio, and resumed while paused;
+/// anything else is a client bug and fails with `FAILED_PRECONDITION`.
+fn check_pause_transition(paused: bool, pause: bool) -> Result<(), Status> {
+    match (paused, pause) {
+        (true, true) => Err(Status::failed_precondition("stream is already paused")),
+        (false, false) => Err(Status::failed_precondition("stream is not paused")),
+        _ => Ok(()),
+    }
+}
+
+fn heartbeat_response(progress: murmure::Heartbeat, utterance_id: u32) -> TranscribeStreamResponse {
+    TranscribeStreamResponse {
+        response_type: Some(murmure::transcribe_stream_response::ResponseType::Heartbeat(progress)),
+        utterance_id,
+        ..Default::default()
+    }
+}
+
+/// Status for a failed spool write: `RESOURCE_EXHAUSTED` when the temp
+/// directory is short on space, `INTERNAL` otherwise.
+fn spool_error(e: std::io::Error) -> Status {
+    if tempfiles::is_storage_full(&e) {
+        Status::resource_exhausted(e.to_string())
+    } else {
+        Status::internal(format!("Failed to spool upload: {}", e))
+    }
+}
+
+/// The model a request asked for (empty = default). Unknown names are
+/// rejected with `INVALID_ARGUMENT` before any audio is processed.
+pub(super) fn requested_model(
+    service: &TranscriptionService,
+    name: &str,
+) -> Result<Arc<Model>, Status> {
+    let name = (!name.is_empty()).then_some(name);
+    service
+        .resolve_model(name)
+        .cloned()
+        .map_err(|e| Status::invalid_argument(e.to_string()))
+}
+
+/// Options and model for a large file or background job: the server
+/// defaults with the large-file duration limit, overridden by `config`.
+pub(super) fn upload_options(
+    service: &TranscriptionService,
+    config: Option<&StreamConfig>,
+) -> Result<(TranscriptionOptions, Arc<Model>), Status> {
+    let mut options = TranscriptionOptions::from_config(service.get_config());
+    options.max_audio_secs = service.get_config().large_file_max_audio_duration();
+    let mut model = Arc::clone(service.get_model());
+    if let Some(config) = config {
+        model = requested_model(service, &config.model)?;
+        check_stream_config(config)?;
+        apply_stream_config(&mut options, config);
+    }
+    Ok((options, model))
+}
+
+/// The language a request gave (empty = none). Anything that isn't a
+/// language code is rejected with `INVALID_ARGUMENT`; a language without a
+/// dictionary of its own is accepted and uses the default one.
+pub(super) fn requested_language(language: &str) -> Result<Option<String>, Status> {
+    if language.is_empty() {
+        return Ok(None);
+    }
+    if !is_language_code(language) {
+        return Err(Status::invalid_argument(format!(
+            "language '{}' is not a language code like 'en' or 'fr-CA'",
+            language
+        )));
+    }
+    Ok(Some(language.to_string()))
+}
+
+/// Reject per-stream options the server config would not accept: a
+/// language that isn't a language code, a voice command without a phrase
+/// or action, or a threshold outside (0, 1].
+fn check_stream_config(config: &StreamConfig) -> Result<(), Status> {
+    requested_language(&config.language)?;
+    if config
+        .commands
+        .iter()
+        .any(|command| command.phrase.trim().is_empty() || command.action.trim().is_empty())
+    {
+        return Err(Status::invalid_argument(
+            "voice commands need both a phrase and an action",
+        ));
+    }
+    if let Some(threshold) = config.command_threshold {
+        if !(threshold > 0.0 && threshold <= 1.0) {
+            return Err(Status::invalid_argument(format!(
+                "command_threshold must be above 0 and at most 1, got {}",
+                threshold
+            )));
+        }
+    }
+    check_inference(config.inference.as_ref())
+}
+
+/// Reject decoding settings outside the ranges MURMURE_DECODING_* accept.
+fn check_inference(inference: Option<&InferenceOptions>) -> Result<(), Status> {
+    let Some(inference) = inference else {
+        return Ok(());
+    };
+    let max_penalty = DecodingOptions::MAX_BLANK_PENALTY;
+    if let Some(penalty) = inference.blank_penalty {
+        if !(-max_penalty..=max_penalty).contains(&penalty) {
+            return Err(Status::invalid_argument(format!(
+                "blank_penalty must be between -{} and {}, got {}",
+                max_penalty, max_penalty, penalty
+            )));
+        }
+    }
+    if let Some(symbols) = inference.max_symbols_per_step {
+        if !(1..=DecodingOptions::MAX_SYMBOLS_PER_STEP).contains(&(symbols as usize)) {
+            return Err(Status::invalid_argument(format!(
+                "max_symbols_per_step must be between 1 and {}, got {}",
+                DecodingOptions::MAX_SYMBOLS_PER_STEP,
+                symbols
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Apply the decoding settings a request set, keeping the server's for the
+/// others; a max_tokens of 0 removes the limit.
+fn apply_inference(options: &mut TranscriptionOptions, inference: Option<&InferenceOptions>) {
+    let Some(inference) = inference else {
+        return;
+    };
+    if let Some(penalty) = inference.blank_penalty {
+        options.decoding.blank_penalty = penalty;
+    }
+    if let Some(symbols) = inference.max_symbols_per_step {
+        options.decoding.max_symbols_per_step = symbols as usize;
+    }
+    if let Some(tokens) = inference.max_tokens {
+        options.decoding.max_tokens = (tokens > 0).then_some(tokens as usize);
+    }
+}
+
+fn to_proto_inference(decoding: &DecodingOptions) -> InferenceOptions {
+    InferenceOptions {
+        blank_penalty: Some(decoding.blank_penalty),
+        max_symbols_per_step: Some(decoding.max_symbols_per_step as u32),
+        max_tokens: Some(
+            decoding
+                .max_tokens
+                .map_or(0, |tokens| u32::try_from(tokens).unwrap_or(u32::MAX)),
+        ),
+    }
+}
+
+fn session_response(
+    session_id: &str,
+    resumed: bool,
+    next_utterance_id: u32,
+    resumed_bytes: u64,
+) -> TranscribeStreamResponse {
+    TranscribeStreamResponse {
+        response_type: Some(murmure::transcribe_stream_response::ResponseType::Session(
+            murmure::StreamSession {
+                session_id: session_id.to_string(),
+                resumed,
+                next_utterance_id,
+                resumed_bytes,
+            },
+        )),
+        utterance_id: next_utterance_id,
+        ..Default::default()
+    }
+}
+
+/// Take the session a stream asked to resume. Only the first message of a
+/// stream may ask, so nothing it already set is overwritten.
+fn resume_session(
+    sessions: Option<&dyn SessionStore>,
+    session_id: &str,
+    first_message: bool,
+) -> Result<SessionState, Status> {
+    let sessions = sessions.ok_or_else(|| {
+        Status::failed_precondition("stream sessions are disabled (MURMURE_SESSION_TTL_SECS=0)")
+    })?;
+    if !first_message {
+        return Err(Status::invalid_argument(
+            "resume_session_id must be sent in the first message of a stream",
+        ));
+    }
+    sessions
+        .take(session_id)
+        .ok_or_else(|| Status::not_found(format!("session '{}' is unknown or expired", session_id)))
+}
+
+/// Keep a finished stream's state for a client that resumes it. The
+/// unfinished utterance's `audio` is kept too when it fits
+/// MURMURE_SESSION_RESUME_MAX_BYTES; otherwise it is handed back to be
+/// salvaged or dropped.
+fn save_session(
+    session: &Option<(Arc<dyn SessionStore>, String)>,
+    config: &ServerConfig,
+    options: &TranscriptionOptions,
+    next_utterance_id: u32,
+    audio: Vec<u8>,
+) -> Vec<u8> {
+    let Some((store, session_id)) = session else {
+        return audio;
+    };
+    let keep_audio = audio.len() as u64 <= config.session_resume_max_bytes;
+    let (pending_audio, rest) = if keep_audio {
+        (audio, Vec::new())
+    } else {
+        (Vec::new(), audio)
+    };
+    store.save(
+        session_id,
+        SessionState {
+            options: options.clone(),
+            next_utterance_id,
+            pending_audio,
+        },
+    );
+    rest
+}
+
+/// Save the audio buffered by a stream that dropped mid-utterance, then
+/// transcribe it in the background and record the result as salvaged.
+fn salvage_utterance(
+    service: Arc<TranscriptionService>,
+    salvage: Arc<SalvageDir>,
+    history: HistoryStore,
+    client: ClientKey,
+    audio: Vec<u8>,
+    options: TranscriptionOptions,
+) {
+    tokio::spawn(async move {
+        let path = match tokio::task::spawn_blocking(move || salvage.save(audio)).await {
+            Ok(Ok(Some(path))) => path,
+            Ok(Ok(None)) => return,
+            Ok(Err(e)) => {
+                tracing::error!("Failed to save audio of dropped stream: {}", e);
+                return;
+            }
+            Err(e) => {
+                tracing::error!("Salvage task failed: {}", e);
+                return;
+            }
+        };
+        tracing::info!("Saved audio of dropped stream to {}", path.display());
+
+        match service.transcribe_file_async(path.clone(), options).await {
+            Ok(transcript) => {
+                let id = history.record(client, transcript.text, transcript.audio_duration_secs);
+                tracing::info!("Salvaged transcript recorded as history entry {}", id);
+            }
+            Err(e) => {
+                tracing::error!("Failed to transcribe salvaged {}: {}", path.display(), e);
+            }
+        }
+    });
+}
+
+/// Turn a transcription result into a TranscribeFile response.
+///
+/// The text is rendered in `format`, with the stage timings when
+/// `include_timing` is set. Failures become the status of their kind, see
+/// [`transcription_status`].
+pub(super) fn file_response(
+    result: Result<Transcript, TranscriptionError>,
+    format: OutputFormat,
+    include_timing: bool,
+) -> Result<Response<TranscribeFileResponse>, Status> {
+    match result {
+        Ok(transcript) => {
+            log_transcript(&transcript);
+            to_file_response(transcript, format, include_timing).map(Response::new)
+        }
+        Err(e) => {
+            let status = transcription_status(&e);
+            if status.code() == Code::InvalidArgument {
+                tracing::warn!("Rejected transcribe_file payload: {}", e);
+            } else {
+                tracing::error!("Transcription failed: {}", e);
+            }
+            Err(status)
+        }
+    }
+}
+
+/// gRPC status for a failed transcription, carrying the error's message.
+///
+/// Deliberately without a catch-all arm, so a new kind of error has to be
+/// given a code here.
+pub(super) fn transcription_status(error: &TranscriptionError) -> Status {
+    let code = match error {
+        TranscriptionError::UnknownModel(_)
+        | TranscriptionError::InvalidAudio(_)
+        | TranscriptionError::UnsupportedFormat { .. }
+        | TranscriptionError::AudioDecode { .. } => Code::InvalidArgument,
+        TranscriptionError::ModelNotFound { .. }
+        | TranscriptionError::DictionaryRules(_)
+        | TranscriptionError::Config(_) => Code::FailedPrecondition,
+        TranscriptionError::EngineLoad(_) | TranscriptionError::EngineUnavailable(_) => {
+            Code::Unavailable
+        }
+        TranscriptionError::Io(e) if tempfiles::is_storage_full(e) => Code::ResourceExhausted,
+        TranscriptionError::EnginePanic(_)
+        | TranscriptionError::Inference(_)
+        | TranscriptionError::PostProcess { .. }
+        | TranscriptionError::Io(_) => Code::Internal,
+    };
+    Status::new(code, error.to_string())
+}
+
+/// Transcribe one utterance of a stream and send its segments and final
+/// text, tagged with `utterance_id`.
+///
+/// Rejected audio ends the whole stream with `INVALID_ARGUMENT` when
+/// `reject_invalid` is set; otherwise it is reported as an error response
+/// for that utterance and the session continues. Returns `false` once the
+/// stream should stop.
+pub(super) async fn send_utterance(
+    service: &TranscriptionService,
+    mut audio: Vec<u8>,
+    options: &TranscriptionOptions,
+    utterance_id: u32,
+    reject_invalid: bool,
+    tx: &mpsc::Sender<Result<TranscribeStreamResponse, Status>>,
+) -> bool {
+    // Nothing to transcribe: answer without touching the engine
+    if audio.is_empty() {
+        let response = TranscribeStreamResponse {
+            response_type: Some(
+                murmure::transcribe_stream_response::ResponseType::FinalText(String::new()),
+            ),
+            is_final: true,
+            utterance_id,
+            no_audio: true,
+            ..Default::default()
+        };
+        return tx.send(Ok(response)).await.is_ok();
+    }
+
+    // A stream stopped early declares more audio than it sent
+    if validation::clamp_wav_sizes(&mut audio) {
+        tracing::debug!(
+            "Utterance {} ended before the declared WAV length, transcribing {} bytes",
+            utterance_id,
+            audio.len()
+        );
+    }
+
+    let transcript = match service
+        .transcribe_audio_bytes_async(audio, options.clone())
+        .await
+    {
+        Ok(transcript) => transcript,
+        Err(e) => {
+            let status = transcription_status(&e);
+            if matches!(e, TranscriptionError::EnginePanic(_)) {
+                let _ = tx.send(Err(status)).await;
+                return false;
+            }
+            if status.code() == Code::InvalidArgument {
+                tracing::warn!("Rejected stream payload: {}", e);
+                if reject_invalid {
+                    let _ = tx.send(Err(status)).await;
+                    return false;
+                }
+            }
+            let response = TranscribeStreamResponse {
+                response_type: Some(murmure::transcribe_stream_response::ResponseType::Error(
+                    format!("Transcription failed: {}", e),
+                )),
+                is_final: true,
+                utterance_id,
+                ..Default::default()
+            };
+            return tx.send(Ok(response)).await.is_ok();
+        }
+    };
+
+    // Emit each pause-delimited segment before the final text
+    for segment in &transcript.segments {
+        let response = TranscribeStreamResponse {
+            response_type: Some(
+                murmure::transcribe_stream_response::ResponseType::PartialText(
+                    segment.text.clone(),
+                ),
+            ),
+            segment: Some(to_proto_segment(segment)),
+            utterance_id,
+            ..Default::default()
+        };
+        if tx.send(Ok(response)).await.is_err() {
+            return false;
+        }
+    }
+
+    log_transcript(&transcript);
+
+    let serialize_start = Instant::now();
+    let stage_timings = transcript.timing;
+    let mut response = TranscribeStreamResponse {
+        realtime_factor: transcript.realtime_factor(),
+        audio_duration_secs: transcript.audio_duration_secs,
+        processing_time_ms: transcript.processing_time_ms,
+        response_type: Some(
+            murmure::transcribe_stream_response::ResponseType::FinalText(transcript.text),
+        ),
+        is_final: true,
+        segment: None,
+        corrections: transcript
+            .corrections
+            .iter()
+            .map(to_proto_correction)
+            .collect(),
+        channels: transcript.channels.iter().map(to_proto_channel).collect(),
+        utterance_id,
+        no_audio: false,
+        timing: None,
+        input_sample_rate: transcript.input.sample_rate,
+        detected_command: transcript.command.as_ref().map(to_proto_command),
+        detected_language: transcript.detected_language.as_ref().map(to_proto_language),
+    };
+    let timing = finish_timing(stage_timings, serialize_start.elapsed());
+    response.timing = options.include_timing.then(|| to_proto_timing(&timing));
+    tx.send(Ok(response)).await.is_ok()
+}
+
+/// Apply the optional per-request post-processing overrides on top of the
+/// server defaults.
+fn apply_overrides(
+    options: &mut TranscriptionOptions,
+    filter_profanity: Option<bool>,
+    redact: Option<bool>,
+    punctuate: Option<bool>,
+    itn: Option<bool>,
+) {
+    if let Some(filter_profanity) = filter_profanity {
+        options.profanity_filter = match (filter_profanity, options.profanity_filter) {
+            (false, _) => ProfanityFilter::Off,
+            (true, ProfanityFilter::Off) => ProfanityFilter::Mask,
+            (true, configured) => configured,
+        };
+    }
+    if let Some(redact) = redact {
+        options.redact = redact;
+    }
+    if let Some(punctuate) = punctuate {
+        options.punctuate = punctuate;
+    }
+    if let Some(itn) = itn {
+        options.itn = itn;
+    }
+}
+
+/// Apply the options of a StreamConfig message.
+pub(super) fn apply_stream_config(options: &mut TranscriptionOptions, config: &StreamConfig) {
+    options.use_dictionary = config.use_dictionary.unwrap_or(true);
+    options.language = (!config.language.is_empty()).then(|| config.language.clone());
+    options.segment_on_silence |= config.segment_on_silence;
+    apply_overrides(
+        options,
+        config.filter_profanity,
+        config.redact,
+        config.punctuate,
+        config.itn,
+    );
+    apply_preprocess(options, config.highpass_hz, config.denoise);
+    options.channel_mode = to_channel_mode(config.channel_mode());
+    options.model = (!config.model.is_empty()).then(|| config.model.clone());
+    options.include_timing = config.include_timing;
+    if !config.commands.is_empty() {
+        options.commands = config
+            .commands
+            .iter()
+            .map(|command| VoiceCommand {
+                phrase: command.phrase.clone(),
+                action: command.action.clone(),
+            })
+            .collect();
+    }
+    if let Some(threshold) = config.command_threshold {
+        options.command_threshold = threshold;
+    }
+    apply_inference(options, config.inference.as_ref());
+}
+
+/// The TranscribeFile response message for a transcript, also printed by
+/// `murmure-server transcribe --json`.
+pub fn to_file_response(
+    transcript: Transcript,
+    format: OutputFormat,
+    include_timing: bool,
+) -> Result<TranscribeFileResponse, Status> {
+    let serialize_start = Instant::now();
+    let text = formats::render(&transcript, format, &CueOptions::default())
+        .map_err(|e| Status::internal(format!("Failed to format transcript: {}", e)))?;
+    let mut response = TranscribeFileResponse {
+        realtime_factor: transcript.realtime_factor(),
+        audio_duration_secs: transcript.audio_duration_secs,
+        processing_time_ms: transcript.processing_time_ms,
+        segments: transcript.segments.iter().map(to_proto_segment).collect(),
+        corrections: transcript
+            .corrections
+            .iter()
+            .map(to_proto_correction)
+            .collect(),
+        channels: transcript.channels.iter().map(to_proto_channel).collect(),
+        stats: transcript.stats.as_ref().map(to_proto_stats),
+        format: to_proto_output_format(format).into(),
+        text,
+        success: true,
+        error: String::new(),
+        timing: None,
+        input_sample_rate: transcript.input.sample_rate,
+        detected_command: transcript.command.as_ref().map(to_proto_command),
+        detected_language: transcript.detected_language.as_ref().map(to_proto_language),
+        two_pass: transcript.two_pass.as_ref().map(to_proto_two_pass),
+    };
+    let timing = finish_timing(transcript.timing, serialize_start.elapsed());
+    response.timing = include_timing.then(|| to_proto_timing(&timing));
+    Ok(response)
+}
+
+/// Log the properties of the transcribed audio, which usually explain an
+/// empty transcript (telephone-rate audio, a clip of a few milliseconds,
+/// near silence).
+fn log_transcript(transcript: &Transcript) {
+    if transcript.text.trim().is_empty() {
+        tracing::info!(
+            "Transcription found no speech in {:.2}s of audio ({})",
+            transcript.audio_duration_secs,
+            transcript.input
+        );
+    } else {
+        tracing::info!(
+            "Transcription successful: {} chars, {:.1}s audio in {}ms ({})",
+            transcript.text.len(),
+            transcript.audio_duration_secs,
+            transcript.processing_time_ms,
+            transcript.input
+        );
+    }
+}
+
+/// Apply the optional per-request preprocessing overrides; a cutoff of 0
+/// turns the high-pass filter off.
+fn apply_preprocess(
+    options: &mut TranscriptionOptions,
+    highpass_hz: Option<f32>,
+    denoise: Option<bool>,
+) {
+    if let Some(cutoff) = highpass_hz {
+        options.highpass_hz = (cutoff > 0.0).then_some(cutoff);
+    }
+    if let Some(denoise) = denoise {
+        options.denoise = denoise;
+    }
+}
+
+/// Add the time spent building the response to the pipeline timings and
+/// log the breakdown.
+fn finish_timing(mut timing: StageTimings, serialization: Duration) -> StageTimings {
+    timing.serialization = serialization;
+    timing.total += serialization;
+    tracing::debug!("Stage timings: {}", timing);
+    timing
+}
+
+fn to_channel_mode(mode: murmure::ChannelMode) -> ChannelMode {
+    match mode {
+        murmure::ChannelMode::Downmix => ChannelMode::Downmix,
+        murmure::ChannelMode::Separate => ChannelMode::Separate,
+        murmure::ChannelMode::LeftOnly => ChannelMode::LeftOnly,
+        murmure::ChannelMode::RightOnly => ChannelMode::RightOnly,
+    }
+}
+
+fn to_output_format(format: murmure::OutputFormat) -> OutputFormat {
+    match format {
+        murmure::OutputFormat::Text => OutputFormat::Text,
+        murmure::OutputFormat::Json => OutputFormat::Json,
+        murmure::OutputFormat::Srt => OutputFormat::Srt,
+        murmure::OutputFormat::Vtt => OutputFormat::Vtt,
+    }
+}
+
+fn to_proto_output_format(format: OutputFormat) -> murmure::OutputFormat {
+    match format {
+        OutputFormat::Text => murmure::OutputFormat::Text,
+        OutputFormat::Json => murmure::OutputFormat::Json,
+        OutputFormat::Srt => murmure::OutputFormat::Srt,
+        OutputFormat::Vtt => murmure::OutputFormat::Vtt,
+    }
+}
+
+fn to_proto_segment(segment: &TranscriptSegment) -> Segment {
+    Segment {
+        start: segment.start,
+        end: segment.end,
+        text: segment.text.clone(),
+        channel: segment.channel.map(|channel| channel as i32),
+    }
+}
+
+fn to_proto_channel(channel: &ChannelTranscript) -> ChannelResult {
+    ChannelResult {
+        channel: channel.channel as i32,
+        text: channel.text.clone(),
+        corrections: channel
+            .corrections
+            .iter()
+            .map(to_proto_correction)
+            .collect(),
+    }
+}
+
+fn to_proto_stats(stats: &SpeechStats) -> murmure::SpeechStats {
+    murmure::SpeechStats {
+        word_count: stats.word_count as u32,
+        words_per_minute: stats.words_per_minute,
+        voiced_secs: stats.voiced_secs,
+        mean_rms: stats.mean_rms,
+        max_rms: stats.max_rms,
+        silence_percent: stats.silence_percent,
+    }
+}
+
+fn to_proto_timing(timing: &StageTimings) -> murmure::StageTimings {
+    let ms = |elapsed: Duration| elapsed.as_secs_f32() * 1000.0;
+    murmure::StageTimings {
+        decode_ms: ms(timing.decode),
+        resample_ms: ms(timing.resample),
+        preprocess_ms: ms(timing.preprocess),
+        vad_ms: ms(timing.vad),
+        inference_ms: ms(timing.inference),
+        dictionary_ms: ms(timing.dictionary),
+        post_process_ms: ms(timing.post_process),
+        serialization_ms: ms(timing.serialization),
+        total_ms: ms(timing.total),
+    }
+}
+
+fn to_proto_correction(correction: &dictionary::Correction) -> Correction {
+    Correction {
+        original: correction.original.clone(),
+        replacement: correction.replacement.clone(),
+        offset: correction.offset as u32,
+    }
+}
+
+fn to_proto_command(command: &DetectedCommand) -> murmure::DetectedCommand {
+    murmure::DetectedCommand {
+        phrase: command.phrase.clone(),
+        action: command.action.clone(),
+        score: command.score,
+    }
+}
+
+fn to_proto_language(language: &DetectedLanguage) -> murmure::DetectedLanguage {
+    murmure::DetectedLanguage {
+        language: language.language.clone(),
+        confidence: language.confidence,
+    }
+}
+
+fn to_proto_two_pass(outcome: &TwoPassOutcome) -> murmure::TwoPassResult {
+    murmure::TwoPassResult {
+        first_confidence: outcome.first_confidence,
+        second_confidence: outcome.second_confidence,
+        second_pass_won: outcome.second_won,
+    }
+}
+
+fn to_proto_history_entry(entry: &HistoryEntry) -> murmure::HistoryEntry {
+    murmure::HistoryEntry {
+        id: entry.id,
+        created_at_unix_ms: entry
+            .created_at
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default(),
+        text: entry.text.clone(),
+        audio_duration_secs: entry.audio_duration_secs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use murmure_stt::validation::AudioValidationError;
+    use murmure_stt::{InternalEngineError, UnknownModelError};
+    use std::path::{Path, PathBuf};
+
+    fn io_error(kind: std::io::ErrorKind) -> TranscriptionError {
+        std::io::Error::new(kind, "disk trouble").into()
+    }
+
+    #[test]
+    fn every_error_has_its_status_code() {
+        let rules_error = Dictionary::new(vec!["Murmure".to_string()])
+            .correct("murmur", Path::new("/nonexistent/cc-rules"))
+            .unwrap_err();
+        let cases = [
+            (
+                TranscriptionError::ModelNotFound {
+                    path: PathBuf::from("/models"),
+                },
+                Code::FailedPrecondition,
+            ),
+            (
+                TranscriptionError::UnknownModel(UnknownModelError {
+                    name: "nope".to_string(),
+                    available: "default".to_string(),
+                }),
+                Code::InvalidArgument,
+            ),
+            (
+                TranscriptionError::InvalidAudio(AudioValidationError::Empty),
+                Code::InvalidArgument,
+            ),
+            (
+                TranscriptionError::UnsupportedFormat {
+                    found: "8-bit float".to_string(),
+                },
+                Code::InvalidArgument,
+            ),
+            (
+                TranscriptionError::AudioDecode {
+                    source: "bad sample".into(),
+                    detail: "bad sample".to_string(),
+                },
+                Code::InvalidArgument,
+            ),
+            (
+                TranscriptionError::EngineLoad("missing weights".into()),
+                Code::Unavailable,
+            ),
+            (
+                TranscriptionError::EngineUnavailable("still loading".to_string()),
+                Code::Unavailable,
+            ),
+            (
+                TranscriptionError::EnginePanic(InternalEngineError {
+                    model: "default".to_string(),
+                }),
+                Code::Internal,
+            ),
+            (
+                TranscriptionError::Inference("shape mismatch".into()),
+                Code::Internal,
+            ),
+            (
+                TranscriptionError::DictionaryRules(rules_error),
+                Code::FailedPrecondition,
+            ),
+            (
+                TranscriptionError::PostProcess {
+                    name: "scrubber".to_string(),
+                    source: "offline".into(),
+                },
+                Code::Internal,
+            ),
+            (
+                TranscriptionError::Config("unreadable word list".into()),
+                Code::FailedPrecondition,
+            ),
+            (
+                tempfiles::storage_full("disk trouble".into()).into(),
+                Code::ResourceExhausted,
+            ),
+            (
+                io_error(std::io::ErrorKind::PermissionDenied),
+                Code::Internal,
+            ),
+        ];
+
+        for (error, code) in cases {
+            let status = transcription_status(&error);
+            assert_eq!(status.code(), code, "{:?}", error);
+            assert_eq!(status.message(), error.to_string());
+        }
     }
 }
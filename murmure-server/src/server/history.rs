@@ -0,0 +1,107 @@
+// In-memory history of salvaged stream transcripts, served by GetHistory
+//
+// Only audio recovered from streams that dropped mid-utterance is recorded:
+// the client never got those transcripts, every other one it already has.
+// Entries belong to the client whose stream dropped, and GetHistory only
+// returns the caller's own.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use super::ratelimit::ClientKey;
+
+/// Number of transcripts kept across all clients; the oldest entry is
+/// dropped beyond this
+pub const HISTORY_CAPACITY: usize = 200;
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub client: ClientKey,
+    pub created_at: SystemTime,
+    pub text: String,
+    pub audio_duration_secs: f32,
+}
+
+#[derive(Default)]
+struct History {
+    entries: VecDeque<HistoryEntry>,
+    next_id: u64,
+}
+
+/// Shared, bounded list of the latest salvaged transcripts.
+#[derive(Clone, Default)]
+pub struct HistoryStore {
+    inner: Arc<Mutex<History>>,
+}
+
+impl HistoryStore {
+    /// Add a transcript salvaged from a stream of `client` and return its id.
+    pub fn record(&self, client: ClientKey, text: String, audio_duration_secs: f32) -> u64 {
+        let mut history = self.inner.lock().unwrap();
+        history.next_id += 1;
+        let id = history.next_id;
+
+        if history.entries.len() == HISTORY_CAPACITY {
+            history.entries.pop_front();
+        }
+        history.entries.push_back(HistoryEntry {
+            id,
+            client,
+            created_at: SystemTime::now(),
+            text,
+            audio_duration_secs,
+        });
+        id
+    }
+
+    /// Up to `limit` entries of `client`, newest first (`limit` 0 = all).
+    pub fn recent(&self, client: &ClientKey, limit: usize) -> Vec<HistoryEntry> {
+        let history = self.inner.lock().unwrap();
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        history
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| &entry.client == client)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(name: &str) -> ClientKey {
+        ClientKey(name.to_string())
+    }
+
+    #[test]
+    fn clients_only_see_their_own_entries() {
+        let history = HistoryStore::default();
+        history.record(client("10.0.0.1"), "first".to_string(), 1.0);
+        history.record(client("10.0.0.2"), "other".to_string(), 1.0);
+        history.record(client("10.0.0.1"), "second".to_string(), 2.0);
+
+        let own = history.recent(&client("10.0.0.1"), 0);
+        let texts: Vec<_> = own.iter().map(|entry| entry.text.as_str()).collect();
+        assert_eq!(texts, ["second", "first"]);
+        assert_eq!(history.recent(&client("10.0.0.1"), 1).len(), 1);
+        assert!(history.recent(&client("10.0.0.3"), 0).is_empty());
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped() {
+        let history = HistoryStore::default();
+        for i in 0..HISTORY_CAPACITY + 5 {
+            history.record(client("10.0.0.1"), i.to_string(), 1.0);
+        }
+        let entries = history.recent(&client("10.0.0.1"), 0);
+        assert_eq!(entries.len(), HISTORY_CAPACITY);
+        assert_eq!(entries.last().unwrap().text, "5");
+        assert_eq!(entries[0].id, (HISTORY_CAPACITY + 5) as u64);
+    }
+}
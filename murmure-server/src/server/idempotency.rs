@@ -0,0 +1,384 @@
+// Replay of TranscribeFile responses for retried requests
+//
+// A client that retries after a dropped connection sends the same
+// `idempotency-key` header. The first request with a key runs the
+// transcription; later ones with the same key, from the same client, get its
+// response instead of a second inference, including while the first is still
+// running. Failed transcriptions are forgotten so a retry runs again.
+//
+// Keys are only honoured for clients with an API key: clients known by their
+// address may share it behind a NAT. A key reused for a different payload or
+// different options is rejected rather than answered with the wrong response.
+
+use prost::Message;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tonic::metadata::MetadataMap;
+use tonic::Status;
+
+use super::grpc::murmure::{TranscribeFileRequest, TranscribeFileResponse};
+use super::ratelimit::ClientKey;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Longest key accepted, in bytes
+const MAX_KEY_LEN: usize = 256;
+
+type Outcome = Result<TranscribeFileResponse, Status>;
+
+/// A key's response, set once by the request that got the key first.
+type Slot = Arc<OnceCell<Outcome>>;
+
+/// When a key was first used, the fingerprint of that request and its slot
+struct Entry {
+    created: Instant,
+    fingerprint: u64,
+    slot: Slot,
+}
+
+/// Responses by client and idempotency key, kept for a TTL and up to a
+/// number of keys.
+pub struct IdempotencyCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<(ClientKey, String), Entry>>,
+    /// Requests answered from the cache since startup
+    replays: AtomicU64,
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            replays: AtomicU64::new(0),
+        }
+    }
+
+    /// The `idempotency-key` header, `None` when absent. Keys must be
+    /// printable ASCII of at most 256 bytes.
+    pub fn key_from(metadata: &MetadataMap) -> Result<Option<String>, Status> {
+        let Some(value) = metadata.get(IDEMPOTENCY_KEY_HEADER) else {
+            return Ok(None);
+        };
+        match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => Ok(Some(key.to_string())),
+            _ => Err(Status::invalid_argument(format!(
+                "{} must be 1 to {} printable ASCII characters",
+                IDEMPOTENCY_KEY_HEADER, MAX_KEY_LEN
+            ))),
+        }
+    }
+
+    /// Hash of everything that determines a TranscribeFile response: the
+    /// audio and the other fields of `request`, whose audio was taken out.
+    pub fn fingerprint(audio: &[u8], request: &TranscribeFileRequest) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        audio.hash(&mut hasher);
+        request.encode_to_vec().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Run `transcribe` for the first request with `key` from `client` and
+    /// hand its outcome to every request with that key and `fingerprint`
+    /// until it expires; a request with another fingerprint fails with
+    /// `FAILED_PRECONDITION`. The flag is `true` when the outcome was
+    /// replayed rather than computed for this request.
+    pub async fn run<F>(
+        &self,
+        client: ClientKey,
+        key: String,
+        fingerprint: u64,
+        transcribe: F,
+    ) -> (Outcome, bool)
+    where
+        F: std::future::Future<Output = Outcome>,
+    {
+        let id = (client, key);
+        let mut transcribe = Some(transcribe);
+        loop {
+            let Some(slot) = self.slot(&id, fingerprint) else {
+                let status = Status::failed_precondition(format!(
+                    "the {} was already used for a different request",
+                    IDEMPOTENCY_KEY_HEADER
+                ));
+                return (Err(status), false);
+            };
+            let mut computed = false;
+            let outcome = slot
+                .get_or_init(|| {
+                    computed = true;
+                    transcribe.take().expect("computed at most once")
+                })
+                .await
+                .clone();
+
+            if outcome.is_err() {
+                self.forget(&id, &slot);
+                if !computed {
+                    // The request that ran failed: run this one again
+                    // instead of replaying its error
+                    continue;
+                }
+            } else if !computed {
+                self.replays.fetch_add(1, Ordering::Relaxed);
+            }
+            return (outcome, !computed);
+        }
+    }
+
+    /// Requests answered from the cache since startup.
+    pub fn replays(&self) -> u64 {
+        self.replays.load(Ordering::Relaxed)
+    }
+
+    /// The slot of `id`, created if needed. `None` when `id` was used with
+    /// another fingerprint.
+    fn slot(&self, id: &(ClientKey, String), fingerprint: u64) -> Option<Slot> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        entries.retain(|_, entry| now.duration_since(entry.created) < self.ttl);
+        if let Some(entry) = entries.get(id) {
+            return (entry.fingerprint == fingerprint).then(|| Arc::clone(&entry.slot));
+        }
+
+        while entries.len() >= self.max_entries {
+            let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.created)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            entries.remove(&oldest);
+        }
+        let slot = Slot::default();
+        entries.insert(
+            id.clone(),
+            Entry {
+                created: now,
+                fingerprint,
+                slot: Arc::clone(&slot),
+            },
+        );
+        Some(slot)
+    }
+
+    /// Drop `id` if it still maps to `slot`, so the next retry runs again.
+    fn forget(&self, id: &(ClientKey, String), slot: &Slot) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries
+            .get(id)
+            .is_some_and(|entry| Arc::ptr_eq(&entry.slot, slot))
+        {
+            entries.remove(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn client() -> ClientKey {
+        ClientKey("team-key".to_string())
+    }
+
+    fn cache() -> IdempotencyCache {
+        IdempotencyCache::new(Duration::from_secs(60), 10)
+    }
+
+    fn response(text: &str) -> Outcome {
+        Ok(TranscribeFileResponse {
+            text: text.to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn failed() -> Outcome {
+        Err(Status::unavailable("engine busy"))
+    }
+
+    /// A transcription counting its runs, answering `outcome` after `delay`.
+    async fn counted(runs: &AtomicUsize, delay: u64, outcome: Outcome) -> Outcome {
+        runs.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        outcome
+    }
+
+    #[tokio::test]
+    async fn retries_get_the_first_response() {
+        let cache = cache();
+        let runs = AtomicUsize::new(0);
+        let key = || "retry-1".to_string();
+
+        let (first, replayed) = cache
+            .run(client(), key(), 1, counted(&runs, 0, response("first")))
+            .await;
+        assert_eq!(first.unwrap().text, "first");
+        assert!(!replayed);
+        let (second, replayed) = cache
+            .run(client(), key(), 1, counted(&runs, 0, response("second")))
+            .await;
+        assert_eq!(second.unwrap().text, "first");
+        assert!(replayed);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.replays(), 1);
+
+        // The same key from another client is another request
+        let other = ClientKey("other-key".to_string());
+        let (third, replayed) = cache
+            .run(other, key(), 1, counted(&runs, 0, response("third")))
+            .await;
+        assert_eq!(third.unwrap().text, "third");
+        assert!(!replayed);
+    }
+
+    #[tokio::test]
+    async fn a_key_reused_for_another_request_is_rejected() {
+        let cache = cache();
+        let runs = AtomicUsize::new(0);
+        cache
+            .run(
+                client(),
+                "k".to_string(),
+                1,
+                counted(&runs, 0, response("a")),
+            )
+            .await
+            .0
+            .unwrap();
+        let (outcome, replayed) = cache
+            .run(
+                client(),
+                "k".to_string(),
+                2,
+                counted(&runs, 0, response("b")),
+            )
+            .await;
+        assert_eq!(outcome.unwrap_err().code(), tonic::Code::FailedPrecondition);
+        assert!(!replayed);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.replays(), 0);
+    }
+
+    #[tokio::test]
+    async fn concurrent_duplicates_run_once() {
+        let cache = cache();
+        let runs = AtomicUsize::new(0);
+        let ((first, first_replayed), (second, second_replayed)) = tokio::join!(
+            cache.run(
+                client(),
+                "k".to_string(),
+                1,
+                counted(&runs, 50, response("once"))
+            ),
+            cache.run(
+                client(),
+                "k".to_string(),
+                1,
+                counted(&runs, 50, response("twice"))
+            ),
+        );
+        assert_eq!(first.unwrap().text, "once");
+        assert_eq!(second.unwrap().text, "once");
+        assert!(!first_replayed && second_replayed);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.replays(), 1);
+    }
+
+    #[tokio::test]
+    async fn failures_are_not_replayed() {
+        let cache = cache();
+        let runs = AtomicUsize::new(0);
+        // A duplicate waiting on a failed request runs itself
+        let ((first, first_replayed), (second, second_replayed)) = tokio::join!(
+            cache.run(client(), "k".to_string(), 1, counted(&runs, 50, failed())),
+            cache.run(
+                client(),
+                "k".to_string(),
+                1,
+                counted(&runs, 0, response("ok"))
+            ),
+        );
+        assert_eq!(first.unwrap_err().code(), tonic::Code::Unavailable);
+        assert_eq!(second.unwrap().text, "ok");
+        assert!(!first_replayed && !second_replayed);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.replays(), 0);
+
+        // A later retry gets the stored success
+        let (third, replayed) = cache
+            .run(client(), "k".to_string(), 1, counted(&runs, 0, failed()))
+            .await;
+        assert_eq!(third.unwrap().text, "ok");
+        assert!(replayed);
+    }
+
+    #[tokio::test]
+    async fn the_oldest_keys_are_forgotten() {
+        let cache = IdempotencyCache::new(Duration::from_secs(60), 2);
+        let runs = AtomicUsize::new(0);
+        for key in ["a", "b", "c"] {
+            cache
+                .run(
+                    client(),
+                    key.to_string(),
+                    1,
+                    counted(&runs, 0, response(key)),
+                )
+                .await
+                .0
+                .unwrap();
+        }
+        let (outcome, replayed) = cache
+            .run(
+                client(),
+                "a".to_string(),
+                1,
+                counted(&runs, 0, response("again")),
+            )
+            .await;
+        assert_eq!(outcome.unwrap().text, "again");
+        assert!(!replayed);
+    }
+
+    #[test]
+    fn fingerprints_cover_audio_and_options() {
+        let request = TranscribeFileRequest::default();
+        let with_dictionary = TranscribeFileRequest {
+            use_dictionary: true,
+            ..Default::default()
+        };
+        let base = IdempotencyCache::fingerprint(b"audio", &request);
+        assert_eq!(base, IdempotencyCache::fingerprint(b"audio", &request));
+        assert_ne!(base, IdempotencyCache::fingerprint(b"other", &request));
+        assert_ne!(
+            base,
+            IdempotencyCache::fingerprint(b"audio", &with_dictionary)
+        );
+    }
+
+    #[test]
+    fn keys_must_be_printable_and_short() {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(IdempotencyCache::key_from(&metadata).unwrap(), None);
+        metadata.insert(IDEMPOTENCY_KEY_HEADER, "retry-1".parse().unwrap());
+        assert_eq!(
+            IdempotencyCache::key_from(&metadata).unwrap().as_deref(),
+            Some("retry-1")
+        );
+        metadata.insert(
+            IDEMPOTENCY_KEY_HEADER,
+            "x".repeat(MAX_KEY_LEN + 1).parse().unwrap(),
+        );
+        assert!(IdempotencyCache::key_from(&metadata).is_err());
+    }
+}
@@ -0,0 +1,491 @@
+// Background transcription jobs, served by SubmitTranscriptionJob,
+// GetJobStatus, GetJobResult and CancelJob
+//
+// A submitted file is validated, queued and answered with a job id right
+// away. A fixed number of workers take jobs off the queue and transcribe them
+// with the same engines as the other RPCs. Finished jobs are kept for
+// MURMURE_JOB_RETENTION_SECS so clients can poll for their status and result.
+//
+// A job belongs to the client that submitted it: the other clients get
+// NOT_FOUND for its id, as for a job that doesn't exist.
+//
+// With MURMURE_JOB_DIR set, `<id>.job` holds a job's owner and request until
+// it finishes and `<id>.done` the owner and response of a job that
+// succeeded. On startup, done jobs are restored and jobs that were queued or
+// running are queued again. Failed and cancelled jobs are not kept across
+// restarts.
+//
+// The engine can't be interrupted mid-inference: cancelling a running job
+// discards its result once the inference ends.
+
+use murmure_stt::formats::OutputFormat;
+use murmure_stt::transcription::TranscriptionService;
+use murmure_stt::validation;
+use murmure_stt::TranscriptionError;
+use prost::Message;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tonic::{Response, Status};
+
+use super::grpc::murmure::{
+    JobState, JobStatus, SubmitTranscriptionJobRequest, TranscribeFileResponse,
+};
+use super::grpc::{file_response, transcription_status, upload_options};
+use super::ratelimit::ClientKey;
+use super::session::new_session_id;
+
+const REQUEST_EXT: &str = "job";
+const RESULT_EXT: &str = "done";
+
+/// Where a job is in its lifecycle.
+enum Phase {
+    Queued,
+    Running,
+    Done(TranscribeFileResponse),
+    Failed(Status),
+    Cancelled,
+}
+
+struct Job {
+    owner: ClientKey,
+    phase: Phase,
+    audio_duration_secs: f32,
+    /// When the job finished, `None` while queued or running
+    finished: Option<Instant>,
+}
+
+impl Job {
+    fn status(&self, job_id: &str) -> JobStatus {
+        let (state, error) = match &self.phase {
+            Phase::Queued => (JobState::Queued, String::new()),
+            Phase::Running => (JobState::Running, String::new()),
+            Phase::Done(_) => (JobState::Done, String::new()),
+            Phase::Failed(status) => (JobState::Failed, status.message().to_string()),
+            Phase::Cancelled => (JobState::Cancelled, String::new()),
+        };
+        JobStatus {
+            job_id: job_id.to_string(),
+            state: state.into(),
+            error,
+            audio_duration_secs: self.audio_duration_secs,
+        }
+    }
+}
+
+/// A queued job's request, waiting for a worker.
+struct Pending {
+    id: String,
+    request: SubmitTranscriptionJobRequest,
+}
+
+/// Contents of `<id>.job`
+#[derive(Clone, PartialEq, prost::Message)]
+struct StoredRequest {
+    #[prost(string, tag = "1")]
+    owner: String,
+    #[prost(message, optional, tag = "2")]
+    request: Option<SubmitTranscriptionJobRequest>,
+}
+
+/// Contents of `<id>.done`
+#[derive(Clone, PartialEq, prost::Message)]
+struct StoredResult {
+    #[prost(string, tag = "1")]
+    owner: String,
+    #[prost(message, optional, tag = "2")]
+    response: Option<TranscribeFileResponse>,
+}
+
+/// An unguessable job id, with a `job-` prefix so it can't be mistaken for
+/// a session or upload id.
+fn new_job_id() -> String {
+    format!("job-{}", new_session_id())
+}
+
+/// Jobs by id, and the queue the workers take them from.
+pub struct JobQueue {
+    service: Arc<TranscriptionService>,
+    jobs: Mutex<HashMap<String, Job>>,
+    queue: mpsc::UnboundedSender<Pending>,
+    /// Most jobs waiting for a worker
+    max_queued: usize,
+    retention: Duration,
+    dir: Option<PathBuf>,
+}
+
+impl JobQueue {
+    /// Start the configured number of workers and restore the jobs kept in
+    /// the job directory. `None` when MURMURE_JOB_WORKERS is 0.
+    pub fn start(service: Arc<TranscriptionService>) -> Option<Arc<Self>> {
+        let config = service.get_config();
+        if config.job_workers == 0 {
+            return None;
+        }
+        let workers = config.job_workers;
+        let (queue, receiver) = mpsc::unbounded_channel();
+        let jobs = Arc::new(Self {
+            max_queued: config.job_queue_depth,
+            retention: Duration::from_secs(config.job_retention_secs),
+            dir: config.job_dir.clone(),
+            service,
+            jobs: Mutex::new(HashMap::new()),
+            queue,
+        });
+
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        for _ in 0..workers {
+            let jobs = Arc::clone(&jobs);
+            let receiver = Arc::clone(&receiver);
+            tokio::spawn(async move {
+                loop {
+                    let next = receiver.lock().await.recv().await;
+                    match next {
+                        Some(pending) => jobs.run(pending).await,
+                        None => return,
+                    }
+                }
+            });
+        }
+
+        if let Err(e) = jobs.restore() {
+            tracing::warn!("Failed to restore background jobs: {}", e);
+        }
+        Some(jobs)
+    }
+
+    /// Validate `request` and queue it for `owner`. Returns the job id and
+    /// the number of jobs queued ahead of it; fails with
+    /// `RESOURCE_EXHAUSTED` when the queue is full.
+    pub fn submit(
+        &self,
+        owner: ClientKey,
+        request: SubmitTranscriptionJobRequest,
+    ) -> Result<(String, u32), Status> {
+        let (options, _) = upload_options(&self.service, request.options.as_ref())?;
+        let info = validation::validate_wav(
+            &request.audio_data,
+            self.service.get_config().min_audio_duration(),
+            options.max_audio_secs,
+        )
+        .map_err(|e| transcription_status(&TranscriptionError::from(e)))?;
+
+        let id = new_job_id();
+        let position = {
+            let mut jobs = self.jobs.lock().unwrap();
+            self.purge(&mut jobs);
+            let queued = jobs
+                .values()
+                .filter(|job| matches!(job.phase, Phase::Queued))
+                .count();
+            if queued >= self.max_queued {
+                return Err(Status::resource_exhausted(format!(
+                    "the job queue is full ({} jobs waiting), retry later",
+                    queued
+                )));
+            }
+            jobs.insert(
+                id.clone(),
+                Job {
+                    owner: owner.clone(),
+                    phase: Phase::Queued,
+                    audio_duration_secs: info.duration_secs,
+                    finished: None,
+                },
+            );
+            queued as u32
+        };
+
+        let stored = StoredRequest {
+            owner: owner.0,
+            request: Some(request),
+        };
+        if let Err(e) = self.write(&id, REQUEST_EXT, &stored.encode_to_vec()) {
+            tracing::warn!(
+                "Failed to persist job {}, it won't survive a restart: {}",
+                id,
+                e
+            );
+        }
+        let _ = self.queue.send(Pending {
+            id: id.clone(),
+            request: stored.request.unwrap_or_default(),
+        });
+        Ok((id, position))
+    }
+
+    /// State of the job `id` of `client`, `None` if it doesn't exist, has
+    /// expired or belongs to another client.
+    pub fn status(&self, client: &ClientKey, id: &str) -> Option<JobStatus> {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.purge(&mut jobs);
+        jobs.get(id)
+            .filter(|job| &job.owner == client)
+            .map(|job| job.status(id))
+    }
+
+    /// Transcript of the finished job `id` of `client`. A failed job returns
+    /// the error it failed with.
+    pub fn result(&self, client: &ClientKey, id: &str) -> Result<TranscribeFileResponse, Status> {
+        let mut jobs = self.jobs.lock().unwrap();
+        self.purge(&mut jobs);
+        let job = jobs
+            .get(id)
+            .filter(|job| &job.owner == client)
+            .ok_or_else(|| not_found(id))?;
+        match &job.phase {
+            Phase::Done(response) => Ok(response.clone()),
+            Phase::Failed(status) => Err(status.clone()),
+            Phase::Queued => Err(Status::failed_precondition("the job is still queued")),
+            Phase::Running => Err(Status::failed_precondition("the job is still running")),
+            Phase::Cancelled => Err(Status::failed_precondition("the job was cancelled")),
+        }
+    }
+
+    /// Cancel the job `id` of `client` if it hasn't finished yet.
+    pub fn cancel(&self, client: &ClientKey, id: &str) -> Result<JobStatus, Status> {
+        let status = {
+            let mut jobs = self.jobs.lock().unwrap();
+            self.purge(&mut jobs);
+            let job = jobs
+                .get_mut(id)
+                .filter(|job| &job.owner == client)
+                .ok_or_else(|| not_found(id))?;
+            if !matches!(job.phase, Phase::Queued | Phase::Running) {
+                return Err(Status::failed_precondition("the job has already finished"));
+            }
+            job.phase = Phase::Cancelled;
+            job.finished = Some(Instant::now());
+            job.status(id)
+        };
+        self.remove(id, REQUEST_EXT);
+        Ok(status)
+    }
+
+    /// Transcribe a queued job, unless it was cancelled while waiting.
+    async fn run(&self, pending: Pending) {
+        let Pending { id, request } = pending;
+        let owner = {
+            let mut jobs = self.jobs.lock().unwrap();
+            match jobs.get_mut(&id) {
+                Some(job) if matches!(job.phase, Phase::Queued) => {
+                    job.phase = Phase::Running;
+                    job.owner.clone()
+                }
+                _ => return,
+            }
+        };
+
+        let outcome = match upload_options(&self.service, request.options.as_ref()) {
+            Ok((options, _)) => {
+                let include_timing = options.include_timing;
+                file_response(
+                    self.service
+                        .transcribe_audio_bytes_async(request.audio_data, options)
+                        .await,
+                    OutputFormat::Text,
+                    include_timing,
+                )
+                .map(Response::into_inner)
+            }
+            Err(status) => Err(status),
+        };
+
+        // Written before the job is marked done, without holding the lock,
+        // so a restart never loses a result the client may have seen
+        if let Ok(response) = &outcome {
+            let stored = StoredResult {
+                owner: owner.0,
+                response: Some(response.clone()),
+            };
+            if let Err(e) = self.write(&id, RESULT_EXT, &stored.encode_to_vec()) {
+                tracing::warn!("Failed to persist the result of job {}: {}", id, e);
+            }
+        }
+
+        let finished = {
+            let mut jobs = self.jobs.lock().unwrap();
+            match jobs.get_mut(&id) {
+                // Not cancelled while running
+                Some(job) if matches!(job.phase, Phase::Running) => {
+                    job.finished = Some(Instant::now());
+                    job.phase = match outcome {
+                        Ok(response) => Phase::Done(response),
+                        Err(status) => Phase::Failed(status),
+                    };
+                    true
+                }
+                _ => false,
+            }
+        };
+        if !finished {
+            self.remove(&id, RESULT_EXT);
+        }
+        self.remove(&id, REQUEST_EXT);
+    }
+
+    /// Drop jobs finished longer ago than the retention period.
+    fn purge(&self, jobs: &mut HashMap<String, Job>) {
+        let expired: Vec<String> = jobs
+            .iter()
+            .filter(|(_, job)| {
+                job.finished
+                    .is_some_and(|at| at.elapsed() >= self.retention)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            jobs.remove(&id);
+            self.remove(&id, RESULT_EXT);
+        }
+    }
+
+    /// Load the jobs kept in the job directory: done jobs still within the
+    /// retention period, and jobs to run again.
+    fn restore(&self) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        if !dir.exists() {
+            return Ok(());
+        }
+
+        let mut requeued = Vec::new();
+        let mut jobs = self.jobs.lock().unwrap();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let (Some(id), Some(ext)) = (
+                path.file_stem().and_then(|stem| stem.to_str()),
+                path.extension().and_then(|ext| ext.to_str()),
+            ) else {
+                continue;
+            };
+            if ext != RESULT_EXT && ext != REQUEST_EXT {
+                continue;
+            }
+            // One unreadable file must not cost the others
+            let (bytes, age) = match read_job_file(&path) {
+                Ok(read) => read,
+                Err(e) => {
+                    tracing::warn!("Skipping unreadable job file {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let id = id.to_string();
+            match ext {
+                RESULT_EXT => {
+                    let (owner, response) = match StoredResult::decode(&*bytes) {
+                        Ok(StoredResult {
+                            owner,
+                            response: Some(response),
+                        }) if age < self.retention => (owner, response),
+                        _ => {
+                            let _ = fs::remove_file(&path);
+                            continue;
+                        }
+                    };
+                    jobs.insert(
+                        id,
+                        Job {
+                            owner: ClientKey(owner),
+                            audio_duration_secs: response.audio_duration_secs,
+                            phase: Phase::Done(response),
+                            finished: Some(
+                                Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+                            ),
+                        },
+                    );
+                }
+                REQUEST_EXT => {
+                    let Ok(StoredRequest {
+                        owner,
+                        request: Some(request),
+                    }) = StoredRequest::decode(&*bytes)
+                    else {
+                        tracing::warn!("Discarding unreadable job file {}", path.display());
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    };
+                    requeued.push((ClientKey(owner), Pending { id, request }));
+                }
+                _ => {}
+            }
+        }
+
+        // A job whose result was written but whose request wasn't removed
+        // yet is done, not queued
+        requeued.retain(|(_, pending)| {
+            let done = jobs.contains_key(&pending.id);
+            if done {
+                self.remove(&pending.id, REQUEST_EXT);
+            }
+            !done
+        });
+        let (done, queued) = (jobs.len(), requeued.len());
+        for (owner, pending) in requeued {
+            let audio_duration_secs =
+                validation::validate_wav(&pending.request.audio_data, 0.0, None)
+                    .map_or(0.0, |info| info.duration_secs);
+            jobs.insert(
+                pending.id.clone(),
+                Job {
+                    owner,
+                    phase: Phase::Queued,
+                    audio_duration_secs,
+                    finished: None,
+                },
+            );
+            let _ = self.queue.send(pending);
+        }
+        if done + queued > 0 {
+            tracing::info!(
+                "Restored {} finished and {} queued background jobs from {}",
+                done,
+                queued,
+                dir.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Write a job file, replacing it atomically.
+    fn write(&self, id: &str, ext: &str, bytes: &[u8]) -> io::Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.{}", id, ext));
+        let partial = dir.join(format!("{}.{}.partial", id, ext));
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, &path)
+    }
+
+    /// Delete a job file, if it exists.
+    fn remove(&self, id: &str, ext: &str) {
+        if let Some(dir) = &self.dir {
+            let path = dir.join(format!("{}.{}", id, ext));
+            if let Err(e) = fs::remove_file(&path) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    tracing::warn!("Failed to remove {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+fn not_found(id: &str) -> Status {
+    Status::not_found(format!("no job '{}', it may have expired", id))
+}
+
+/// Contents of a job file and how long ago it was last written.
+fn read_job_file(path: &Path) -> io::Result<(Vec<u8>, Duration)> {
+    let age = fs::metadata(path)?
+        .modified()?
+        .elapsed()
+        .unwrap_or_default();
+    Ok((fs::read(path)?, age))
+}
@@ -0,0 +1,178 @@
+// gRPC listeners: every configured address, each with its own TLS setting,
+// serving the same service instances
+//
+// All addresses are bound, and their certificates loaded, before any is
+// served, so a taken address fails startup by name instead of leaving the
+// server half reachable. The listeners stop on the same shutdown signal and
+// each drains its in-flight requests; one failing while serving stops the
+// others too.
+
+use murmure_stt::config::{GrpcListener, ServerConfig};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::task::{JoinError, JoinSet};
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+
+use super::GrpcService;
+
+/// Why the gRPC listeners could not start or stopped serving.
+#[derive(Debug, thiserror::Error)]
+pub enum ListenerError {
+    #[error("Failed to read {} for gRPC listener {addr}: {source}", path.display())]
+    ReadTls {
+        addr: SocketAddr,
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error("Invalid TLS certificate or key for gRPC listener {addr}: {source}")]
+    Tls {
+        addr: SocketAddr,
+        source: tonic::transport::Error,
+    },
+    #[error("Failed to bind gRPC listener {addr}: {source}")]
+    Bind { addr: SocketAddr, source: io::Error },
+    #[error("Server failed on {addr}: {source}")]
+    Serve {
+        addr: SocketAddr,
+        source: tonic::transport::Error,
+    },
+    #[error("Server failed: {0}")]
+    Task(JoinError),
+}
+
+/// Every gRPC listener of the configuration, bound and not serving yet.
+pub struct GrpcListeners {
+    bound: Vec<BoundListener>,
+}
+
+struct BoundListener {
+    addr: SocketAddr,
+    incoming: TcpIncoming,
+    server: Server,
+}
+
+impl GrpcListeners {
+    /// Bind every listener of [`ServerConfig::listeners`], with the HTTP/2
+    /// keepalive of the configuration. Fails on the first listener that
+    /// can't be bound or whose certificate can't be loaded.
+    pub async fn bind(config: &ServerConfig) -> Result<Self, ListenerError> {
+        let keepalive = (config.http2_keepalive_secs > 0)
+            .then(|| Duration::from_secs(config.http2_keepalive_secs));
+        let mut bound = Vec::new();
+        for listener in config.listeners() {
+            let mut server = Server::builder()
+                .http2_keepalive_interval(keepalive)
+                .http2_keepalive_timeout(
+                    keepalive.map(|_| Duration::from_secs(config.http2_keepalive_timeout_secs)),
+                );
+            let tls = server_tls(&listener)?;
+            let is_tls = tls.is_some();
+            if let Some(tls) = tls {
+                server = server
+                    .tls_config(tls)
+                    .map_err(|source| ListenerError::Tls {
+                        addr: listener.addr,
+                        source,
+                    })?;
+            }
+            let bind_error = |source| ListenerError::Bind {
+                addr: listener.addr,
+                source,
+            };
+            let tcp = tokio::net::TcpListener::bind(listener.addr)
+                .await
+                .map_err(bind_error)?;
+            let addr = tcp.local_addr().map_err(bind_error)?;
+            let incoming = TcpIncoming::from_listener(tcp, false, None)
+                .map_err(|e| bind_error(io::Error::other(e)))?;
+            tracing::info!(
+                "gRPC server listening on {} ({})",
+                addr,
+                if is_tls { "TLS" } else { "plaintext" }
+            );
+            bound.push(BoundListener {
+                addr,
+                incoming,
+                server,
+            });
+        }
+        Ok(Self { bound })
+    }
+
+    /// Bound addresses, in the order of the configuration; the actual port
+    /// of a listener configured with port 0.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.bound.iter().map(|listener| listener.addr).collect()
+    }
+
+    /// Serve `service` on every listener until `shutdown` completes or a
+    /// listener fails, then wait for all of them to drain. Returns the
+    /// first failure.
+    pub async fn serve(
+        self,
+        service: GrpcService,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ListenerError> {
+        let (stop, stopped) = tokio::sync::watch::channel(false);
+        let mut listeners = JoinSet::new();
+        for BoundListener {
+            addr,
+            incoming,
+            mut server,
+        } in self.bound
+        {
+            let router = server.add_service(service.clone());
+            let mut stopped = stopped.clone();
+            listeners.spawn(async move {
+                let served = router
+                    .serve_with_incoming_shutdown(incoming, async move {
+                        let _ = stopped.wait_for(|stop| *stop).await;
+                    })
+                    .await;
+                served.map_err(|source| ListenerError::Serve { addr, source })
+            });
+        }
+
+        let mut failure = None;
+        tokio::select! {
+            _ = shutdown => {}
+            Some(joined) = listeners.join_next() => failure = listener_failure(joined),
+        }
+        stop.send_replace(true);
+        while let Some(joined) = listeners.join_next().await {
+            failure = failure.or(listener_failure(joined));
+        }
+        failure.map_or(Ok(()), Err)
+    }
+}
+
+/// TLS settings of a gRPC listener, `None` when it serves plaintext.
+fn server_tls(listener: &GrpcListener) -> Result<Option<ServerTlsConfig>, ListenerError> {
+    let (Some(cert), Some(key)) = (&listener.tls_cert, &listener.tls_key) else {
+        return Ok(None);
+    };
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|source| ListenerError::ReadTls {
+            addr: listener.addr,
+            path: path.to_path_buf(),
+            source,
+        })
+    };
+    let identity = Identity::from_pem(read(cert)?, read(key)?);
+    Ok(Some(ServerTlsConfig::new().identity(identity)))
+}
+
+/// The error of a gRPC listener that stopped, `None` when it stopped cleanly.
+fn listener_failure(joined: Result<Result<(), ListenerError>, JoinError>) -> Option<ListenerError> {
+    let error = match joined {
+        Ok(Ok(())) => return None,
+        Ok(Err(e)) => e,
+        Err(e) => ListenerError::Task(e),
+    };
+    tracing::error!("gRPC listener stopped: {}", error);
+    Some(error)
+}
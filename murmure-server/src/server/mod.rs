@@ -1,3 +1,42 @@
+// Helpers shared with the gRPC handlers return `Result<_, tonic::Status>`
+// like the handlers themselves, so a failure reaches the client unchanged.
+// Status is large, which clippy flags on every such function.
+#![allow(clippy::result_large_err)]
+
+mod debug_dump;
+mod fetch;
 pub mod grpc;
+mod history;
+mod idempotency;
+mod jobs;
+mod listeners;
+mod ratelimit;
+mod salvage;
+mod session;
+mod upload;
+mod usage;
+mod websocket;
 
 pub use grpc::{murmure, TranscriptionServiceImpl};
+pub use listeners::{GrpcListeners, ListenerError};
+pub use ratelimit::{RateLimitInterceptor, RateLimiter};
+pub use websocket::WebSocketBridge;
+
+use std::sync::Arc;
+use tonic::codec::CompressionEncoding;
+use tonic::service::interceptor::InterceptedService;
+
+use murmure::transcription_service_server::TranscriptionServiceServer;
+
+/// The gRPC service as served on every listener
+pub type GrpcService =
+    InterceptedService<TranscriptionServiceServer<TranscriptionServiceImpl>, RateLimitInterceptor>;
+
+/// The gRPC service as served on every listener: rate limited, with gzip
+/// used when the client asks for it.
+pub fn grpc_service(service: TranscriptionServiceImpl, limiter: Arc<RateLimiter>) -> GrpcService {
+    let service = TranscriptionServiceServer::new(service)
+        .accept_compressed(CompressionEncoding::Gzip)
+        .send_compressed(CompressionEncoding::Gzip);
+    InterceptedService::new(service, RateLimitInterceptor::new(limiter))
+}
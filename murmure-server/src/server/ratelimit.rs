@@ -0,0 +1,574 @@
+// Per-client rate limiting: requests per minute and audio seconds in flight
+//
+// Clients are identified by peer IP. A request carrying an `x-api-key` listed
+// in the rate limit overrides is tracked under that key instead, so a team
+// behind a shared NAT can get its own budget. Keys that aren't configured are
+// ignored: the server has no authentication, and honoring arbitrary keys would
+// let a client dodge its limit by sending a new one with every request.
+//
+// Audio admitted here is also counted in the client's usage, and checked
+// against its monthly quota, see usage.rs.
+//
+// API keys are secrets: logs and responses show a client's label instead,
+// its IP or `key-` and a fingerprint of its key.
+
+use murmure_stt::config::ServerConfig;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use super::debug_dump::constant_time_eq;
+use super::usage::UsageLedger;
+
+/// Metadata key carrying the API key a client identifies with
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Metadata key of the retry hint on rejected requests, in milliseconds
+pub const RETRY_AFTER_HEADER: &str = "retry-after-ms";
+
+/// Retry hint when the audio budget is used up; it frees up as soon as one of
+/// the client's transcriptions finishes, which can't be predicted
+const AUDIO_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Idle clients are forgotten once this many are tracked
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Metadata key carrying the admin token
+pub const ADMIN_TOKEN_HEADER: &str = "x-murmure-admin-token";
+
+/// Identity of clients with neither an API key nor a known peer address
+const UNKNOWN_CLIENT: &str = "unknown";
+
+/// Identity a request is rate limited under: a configured API key, or the
+/// peer IP. Formats as its [label](ClientKey::label), never as the key.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ClientKey(pub String);
+
+impl ClientKey {
+    /// The IP, or `key-` and a fingerprint of the API key.
+    pub fn label(&self) -> String {
+        client_label(&self.0)
+    }
+
+    /// Whether the client sent one of the configured API keys, rather than
+    /// being known by its address only.
+    pub fn is_api_key(&self) -> bool {
+        is_api_key(&self.0)
+    }
+}
+
+impl fmt::Display for ClientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.label())
+    }
+}
+
+impl fmt::Debug for ClientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ClientKey({})", self.label())
+    }
+}
+
+/// Whether `client` is an API key rather than an address. A key sent in the
+/// header that looks like an address is ignored, so a client can't pass as the
+/// peer IP of an override.
+fn is_api_key(client: &str) -> bool {
+    !client.is_empty() && client != UNKNOWN_CLIENT && client.parse::<IpAddr>().is_err()
+}
+
+/// Label of the client identified as `client`, see [`ClientKey::label`].
+pub fn client_label(client: &str) -> String {
+    if !is_api_key(client) {
+        return client.to_string();
+    }
+    // FNV-1a, stable across builds so labels can be compared between runs
+    let hash = client.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("key-{:08x}", (hash >> 32) as u32 ^ hash as u32)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// 0 = no limit
+    requests_per_minute: u32,
+    /// 0.0 = no limit
+    audio_secs: f32,
+}
+
+/// Token bucket refilled continuously at `requests_per_minute / 60` tokens
+/// per second, holding at most one minute worth of requests.
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(requests_per_minute: u32, now: Instant) -> Self {
+        Self {
+            tokens: requests_per_minute as f64,
+            updated: now,
+        }
+    }
+
+    /// Take a token, or return how long until one is available.
+    fn try_take(&mut self, requests_per_minute: u32, now: Instant) -> Result<(), Duration> {
+        let capacity = requests_per_minute as f64;
+        let per_sec = capacity / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(capacity);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_sec))
+        }
+    }
+
+    fn is_full(&self, requests_per_minute: u32, now: Instant) -> bool {
+        let per_sec = requests_per_minute as f64 / 60.0;
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * per_sec >= requests_per_minute as f64
+    }
+}
+
+#[derive(Debug)]
+struct ClientState {
+    bucket: TokenBucket,
+    audio_in_flight: f32,
+}
+
+/// Shared rate limit state of all clients.
+pub struct RateLimiter {
+    defaults: Limits,
+    overrides: HashMap<String, Limits>,
+    clients: Mutex<HashMap<ClientKey, ClientState>>,
+    usage: Arc<UsageLedger>,
+    admin_token: Option<String>,
+}
+
+impl RateLimiter {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let defaults = Limits {
+            requests_per_minute: config.rate_limit_rpm,
+            audio_secs: config.rate_limit_audio_secs as f32,
+        };
+        let overrides = config
+            .rate_limit_overrides
+            .iter()
+            .map(|entry| {
+                let limits = Limits {
+                    requests_per_minute: entry
+                        .requests_per_minute
+                        .unwrap_or(defaults.requests_per_minute),
+                    audio_secs: entry
+                        .audio_secs
+                        .map_or(defaults.audio_secs, |secs| secs as f32),
+                };
+                (entry.key.clone(), limits)
+            })
+            .collect();
+        Self {
+            defaults,
+            overrides,
+            clients: Mutex::new(HashMap::new()),
+            usage: Arc::new(UsageLedger::from_config(config)),
+            admin_token: config.admin_token.clone(),
+        }
+    }
+
+    /// Whether the request carries the admin token. A header that doesn't
+    /// hold it is rejected rather than ignored, as is any header when no
+    /// token is configured.
+    pub fn is_admin(&self, metadata: &MetadataMap) -> Result<bool, Status> {
+        let Some(value) = metadata.get(ADMIN_TOKEN_HEADER) else {
+            return Ok(false);
+        };
+        match &self.admin_token {
+            Some(token) if constant_time_eq(value.as_bytes(), token.as_bytes()) => Ok(true),
+            _ => Err(Status::permission_denied(format!(
+                "{} does not match MURMURE_ADMIN_TOKEN",
+                ADMIN_TOKEN_HEADER
+            ))),
+        }
+    }
+
+    /// Usage of the clients this limiter admitted audio for.
+    pub fn usage(&self) -> &Arc<UsageLedger> {
+        &self.usage
+    }
+
+    /// Identity of the client sending `metadata` from `peer`: a configured
+    /// API key when one is sent, the peer IP otherwise.
+    pub fn client_key(
+        &self,
+        metadata: &MetadataMap,
+        peer: Option<std::net::SocketAddr>,
+    ) -> ClientKey {
+        let api_key = metadata
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|key| is_api_key(key) && self.overrides.contains_key(*key));
+        match (api_key, peer) {
+            (Some(key), _) => ClientKey(key.to_string()),
+            (None, Some(peer)) => ClientKey(peer.ip().to_string()),
+            (None, None) => ClientKey(UNKNOWN_CLIENT.to_string()),
+        }
+    }
+
+    fn limits(&self, key: &ClientKey) -> Limits {
+        self.overrides.get(&key.0).copied().unwrap_or(self.defaults)
+    }
+
+    /// Count one request against the client's per-minute budget.
+    pub fn check_request(&self, key: &ClientKey) -> Result<(), Status> {
+        let rpm = self.limits(key).requests_per_minute;
+        if rpm == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        self.forget_idle(&mut clients, now);
+        clients
+            .entry(key.clone())
+            .or_insert_with(|| ClientState {
+                bucket: TokenBucket::full(rpm, now),
+                audio_in_flight: 0.0,
+            })
+            .bucket
+            .try_take(rpm, now)
+            .map_err(|retry_after| {
+                exhausted(
+                    format!("rate limit of {} requests per minute exceeded", rpm),
+                    retry_after,
+                )
+            })
+    }
+
+    /// Reserve `secs` of audio against the client's in-flight budget until
+    /// the returned permit is dropped, and count it in the client's usage.
+    ///
+    /// A request longer than the whole budget is still let through when the
+    /// client has nothing else in flight, so it can't be locked out for good.
+    /// The monthly quota has no such exception.
+    pub fn reserve_audio(
+        self: &Arc<Self>,
+        key: &ClientKey,
+        secs: f32,
+    ) -> Result<AudioPermit, Status> {
+        let limit = self.limits(key).audio_secs;
+        if limit <= 0.0 {
+            self.usage.admit(key, secs)?;
+            return Ok(AudioPermit::none());
+        }
+
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        self.forget_idle(&mut clients, now);
+        let state = clients.entry(key.clone()).or_insert_with(|| ClientState {
+            bucket: TokenBucket::full(self.limits(key).requests_per_minute, now),
+            audio_in_flight: 0.0,
+        });
+        if state.audio_in_flight > 0.0 && state.audio_in_flight + secs > limit {
+            return Err(exhausted(
+                format!(
+                    "{:.0}s of audio already in transcription, the limit is {:.0}s",
+                    state.audio_in_flight, limit
+                ),
+                AUDIO_RETRY_AFTER,
+            ));
+        }
+        self.usage.admit(key, secs)?;
+        state.audio_in_flight += secs;
+
+        Ok(AudioPermit {
+            limiter: Some(Arc::clone(self)),
+            key: key.clone(),
+            secs,
+        })
+    }
+
+    fn release_audio(&self, key: &ClientKey, secs: f32) {
+        if let Some(state) = self.clients.lock().unwrap().get_mut(key) {
+            state.audio_in_flight = (state.audio_in_flight - secs).max(0.0);
+        }
+    }
+
+    /// Drop clients with a full bucket and no audio in flight once too many
+    /// are tracked; they would start from the same state anyway.
+    fn forget_idle(&self, clients: &mut HashMap<ClientKey, ClientState>, now: Instant) {
+        if clients.len() < MAX_TRACKED_CLIENTS {
+            return;
+        }
+        clients.retain(|key, state| {
+            state.audio_in_flight > 0.0
+                || !state
+                    .bucket
+                    .is_full(self.limits(key).requests_per_minute, now)
+        });
+    }
+}
+
+/// Audio seconds reserved by a request, given back when dropped.
+pub struct AudioPermit {
+    limiter: Option<Arc<RateLimiter>>,
+    key: ClientKey,
+    secs: f32,
+}
+
+impl AudioPermit {
+    fn none() -> Self {
+        Self {
+            limiter: None,
+            key: ClientKey(String::new()),
+            secs: 0.0,
+        }
+    }
+}
+
+impl Drop for AudioPermit {
+    fn drop(&mut self) {
+        if let Some(limiter) = &self.limiter {
+            limiter.release_audio(&self.key, self.secs);
+        }
+    }
+}
+
+/// Interceptor applying the per-minute limit to every RPC.
+#[derive(Clone)]
+pub struct RateLimitInterceptor {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimitInterceptor {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        Self { limiter }
+    }
+}
+
+impl Interceptor for RateLimitInterceptor {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let key = self
+            .limiter
+            .client_key(request.metadata(), request.remote_addr());
+        if let Err(status) = self.limiter.check_request(&key) {
+            tracing::warn!("Rate limited client {}: {}", key, status.message());
+            return Err(status);
+        }
+        Ok(request)
+    }
+}
+
+/// `RESOURCE_EXHAUSTED` carrying a retry hint in [`RETRY_AFTER_HEADER`].
+pub(super) fn exhausted(message: String, retry_after: Duration) -> Status {
+    let mut metadata = MetadataMap::new();
+    metadata.insert(
+        RETRY_AFTER_HEADER,
+        (retry_after.as_millis().max(1) as u64).into(),
+    );
+    Status::with_metadata(tonic::Code::ResourceExhausted, message, metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use murmure_stt::config::RateLimitOverride;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn bucket_refills_one_token_per_interval() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(60, start);
+        for _ in 0..60 {
+            bucket.try_take(60, start).unwrap();
+        }
+        // Empty: the next token is a second away at 60 per minute
+        let retry = bucket.try_take(60, start).unwrap_err();
+        assert!((retry.as_secs_f64() - 1.0).abs() < 1e-6, "{:?}", retry);
+
+        // Half a second later, half a token: the other half is still missing
+        let retry = bucket.try_take(60, start + secs(0.5)).unwrap_err();
+        assert!((retry.as_secs_f64() - 0.5).abs() < 1e-6, "{:?}", retry);
+        bucket.try_take(60, start + secs(1.0)).unwrap();
+    }
+
+    #[test]
+    fn retry_after_scales_with_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(6, start);
+        for _ in 0..6 {
+            bucket.try_take(6, start).unwrap();
+        }
+        // 6 per minute: one token every 10 seconds
+        let retry = bucket.try_take(6, start).unwrap_err();
+        assert!((retry.as_secs_f64() - 10.0).abs() < 1e-6, "{:?}", retry);
+    }
+
+    #[test]
+    fn bucket_never_holds_more_than_a_minute() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::full(2, start);
+        assert!(bucket.is_full(2, start));
+        bucket.try_take(2, start).unwrap();
+        assert!(!bucket.is_full(2, start));
+        assert!(bucket.is_full(2, start + secs(30.0)));
+
+        // An hour idle still only buys a minute worth of requests
+        let later = start + secs(3600.0);
+        bucket.try_take(2, later).unwrap();
+        bucket.try_take(2, later).unwrap();
+        assert!(bucket.try_take(2, later).is_err());
+    }
+
+    fn limiter(rpm: u32, audio_secs: u64) -> Arc<RateLimiter> {
+        let config = ServerConfig {
+            rate_limit_rpm: rpm,
+            rate_limit_audio_secs: audio_secs,
+            rate_limit_overrides: vec![RateLimitOverride {
+                key: "team-key".to_string(),
+                requests_per_minute: Some(0),
+                audio_secs: None,
+                monthly_audio_secs: None,
+            }],
+            ..ServerConfig::default()
+        };
+        Arc::new(RateLimiter::from_config(&config))
+    }
+
+    fn metadata(api_key: Option<&str>) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        if let Some(key) = api_key {
+            metadata.insert(API_KEY_HEADER, key.parse().unwrap());
+        }
+        metadata
+    }
+
+    #[test]
+    fn identifies_configured_keys_only() {
+        let limiter = limiter(1, 0);
+        let peer = Some("10.0.0.7:4000".parse().unwrap());
+        assert_eq!(limiter.client_key(&metadata(None), peer).0, "10.0.0.7");
+        assert_eq!(
+            limiter.client_key(&metadata(Some("team-key")), peer).0,
+            "team-key"
+        );
+        assert_eq!(
+            limiter.client_key(&metadata(Some("made-up")), peer).0,
+            "10.0.0.7"
+        );
+        assert_eq!(limiter.client_key(&metadata(None), None).0, "unknown");
+    }
+
+    #[test]
+    fn labels_hide_keys() {
+        let key = ClientKey("team-key".to_string());
+        let label = key.label();
+        assert!(label.starts_with("key-") && label.len() == 12, "{}", label);
+        assert!(!format!("{} {:?}", key, key).contains("team-key"));
+        assert_eq!(label, ClientKey("team-key".to_string()).label());
+        assert_ne!(label, ClientKey("other-key".to_string()).label());
+        assert_eq!(ClientKey("10.0.0.7".to_string()).label(), "10.0.0.7");
+        assert_eq!(ClientKey("::1".to_string()).label(), "::1");
+    }
+
+    #[test]
+    fn rejects_requests_over_the_rate_with_a_hint() {
+        let limiter = limiter(1, 0);
+        let client = ClientKey("10.0.0.7".to_string());
+        limiter.check_request(&client).unwrap();
+        let status = limiter.check_request(&client).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        let retry: u64 = status
+            .metadata()
+            .get(RETRY_AFTER_HEADER)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((59_000..=60_000).contains(&retry), "{}", retry);
+
+        // Other clients and the unlimited override are unaffected
+        limiter
+            .check_request(&ClientKey("10.0.0.8".to_string()))
+            .unwrap();
+        let team = ClientKey("team-key".to_string());
+        for _ in 0..10 {
+            limiter.check_request(&team).unwrap();
+        }
+    }
+
+    #[test]
+    fn audio_budget_is_given_back_with_the_permit() {
+        let limiter = limiter(0, 10);
+        let client = ClientKey("10.0.0.7".to_string());
+        // Longer than the budget, but nothing else in flight
+        let long = limiter.reserve_audio(&client, 30.0).unwrap();
+        let status = limiter.reserve_audio(&client, 1.0).err().unwrap();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(
+            status.metadata().get(RETRY_AFTER_HEADER).unwrap(),
+            &AUDIO_RETRY_AFTER.as_millis().to_string()
+        );
+        drop(long);
+
+        let first = limiter.reserve_audio(&client, 6.0).unwrap();
+        assert!(limiter.reserve_audio(&client, 6.0).is_err());
+        let _second = limiter.reserve_audio(&client, 4.0).unwrap();
+        drop(first);
+        limiter.reserve_audio(&client, 6.0).unwrap();
+    }
+
+    #[test]
+    fn audio_only_limits_forget_idle_clients() {
+        let limiter = limiter(0, 10);
+        for i in 0..MAX_TRACKED_CLIENTS + 100 {
+            let client = ClientKey(format!("10.{}.{}.{}", i >> 16, (i >> 8) & 0xff, i & 0xff));
+            drop(limiter.reserve_audio(&client, 1.0).unwrap());
+        }
+        assert!(limiter.clients.lock().unwrap().len() <= MAX_TRACKED_CLIENTS);
+
+        // Clients with audio in flight are kept
+        let busy = ClientKey("10.255.0.1".to_string());
+        let _permit = limiter.reserve_audio(&busy, 1.0).unwrap();
+        for i in 0..MAX_TRACKED_CLIENTS {
+            let client = ClientKey(format!("10.254.{}.{}", i >> 8, i & 0xff));
+            drop(limiter.reserve_audio(&client, 1.0).unwrap());
+        }
+        assert!(limiter.clients.lock().unwrap().contains_key(&busy));
+    }
+
+    #[test]
+    fn admin_token_is_checked() {
+        let config = ServerConfig {
+            admin_token: Some("0123456789abcdef".to_string()),
+            ..ServerConfig::default()
+        };
+        let limiter = RateLimiter::from_config(&config);
+        let mut headers = MetadataMap::new();
+        assert!(!limiter.is_admin(&headers).unwrap());
+        headers.insert(ADMIN_TOKEN_HEADER, "0123456789abcdef".parse().unwrap());
+        assert!(limiter.is_admin(&headers).unwrap());
+        headers.insert(ADMIN_TOKEN_HEADER, "0123456789abcdeX".parse().unwrap());
+        assert_eq!(
+            limiter.is_admin(&headers).unwrap_err().code(),
+            tonic::Code::PermissionDenied
+        );
+
+        // Without a configured token nobody is admin
+        let limiter = RateLimiter::from_config(&ServerConfig::default());
+        assert!(limiter.is_admin(&headers).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+// Saving the audio of streams that drop mid-utterance
+//
+// When a client disconnects (laptop sleep, network drop) the audio buffered
+// for the current utterance would otherwise be discarded. With a salvage
+// directory configured it is written to a timestamped WAV so it can be
+// transcribed in the background and found later through GetHistory.
+
+use murmure_stt::config::ServerConfig;
+use murmure_stt::validation;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FILE_PREFIX: &str = "salvage-";
+
+pub struct SalvageDir {
+    dir: PathBuf,
+    min_secs: f32,
+    /// 0 = no limit
+    max_bytes: u64,
+}
+
+impl SalvageDir {
+    /// `None` unless `MURMURE_SALVAGE_DIR` is set.
+    pub fn from_config(config: &ServerConfig) -> Option<Self> {
+        config.salvage_dir.as_ref().map(|dir| Self {
+            dir: dir.clone(),
+            min_secs: config.salvage_min_secs as f32,
+            max_bytes: config.salvage_max_bytes,
+        })
+    }
+
+    /// Write the buffered WAV bytes of a dropped stream to the salvage
+    /// directory. Returns `None` when the audio is unreadable or shorter than
+    /// the configured minimum.
+    pub fn save(&self, mut audio: Vec<u8>) -> io::Result<Option<PathBuf>> {
+        validation::clamp_wav_sizes(&mut audio);
+        let info = match validation::validate_wav(&audio, 0.0, None) {
+            Ok(info) => info,
+            Err(e) => {
+                tracing::debug!("Not salvaging stream audio: {}", e);
+                return Ok(None);
+            }
+        };
+        if info.duration_secs < self.min_secs {
+            return Ok(None);
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let (mut file, path) = tempfile::Builder::new()
+            .prefix(&format!("{}{}-", FILE_PREFIX, millis))
+            .suffix(".wav")
+            .tempfile_in(&self.dir)?
+            .keep()
+            .map_err(|e| e.error)?;
+        file.write_all(&audio)?;
+        file.sync_all()?;
+
+        if let Err(e) = self.evict(&path) {
+            tracing::warn!("Failed to trim salvage directory: {}", e);
+        }
+        Ok(Some(path))
+    }
+
+    /// Delete the oldest salvaged files until the directory fits in
+    /// `max_bytes`, never removing `keep`.
+    fn evict(&self, keep: &Path) -> io::Result<()> {
+        if self.max_bytes == 0 {
+            return Ok(());
+        }
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if !name.to_string_lossy().starts_with(FILE_PREFIX) {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            if metadata.is_file() {
+                let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+                files.push((modified, entry.path(), metadata.len()));
+            }
+        }
+        files.sort();
+
+        let mut total: u64 = files.iter().map(|(_, _, len)| len).sum();
+        for (_, path, len) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            fs::remove_file(&path)?;
+            tracing::info!("Evicted salvaged recording {}", path.display());
+            total -= len;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use murmure_stt::dsp::write_wav_bytes;
+
+    fn salvage(dir: &Path, max_bytes: u64) -> SalvageDir {
+        SalvageDir {
+            dir: dir.to_path_buf(),
+            min_secs: 1.0,
+            max_bytes,
+        }
+    }
+
+    fn wav(secs: f32) -> Vec<u8> {
+        write_wav_bytes(&vec![0.0; (secs * 16_000.0) as usize], 16_000).unwrap()
+    }
+
+    #[test]
+    fn skips_short_and_unreadable_audio() {
+        let dir = tempfile::tempdir().unwrap();
+        let salvage = salvage(dir.path(), 0);
+        assert!(salvage.save(wav(0.5)).unwrap().is_none());
+        assert!(salvage.save(b"not a wav".to_vec()).unwrap().is_none());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn saves_truncated_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut audio = wav(3.0);
+        audio.truncate(audio.len() / 2);
+        let path = salvage(dir.path(), 0).save(audio).unwrap().unwrap();
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with(FILE_PREFIX) && name.ends_with(".wav"));
+        assert!(validation::validate_wav_file(&path, 0.0, None).is_ok());
+    }
+
+    #[test]
+    fn evicts_the_oldest_files_but_keeps_the_new_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let audio = wav(2.0);
+        let salvage = salvage(dir.path(), audio.len() as u64 * 2);
+        let first = salvage.save(audio.clone()).unwrap().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let second = salvage.save(audio.clone()).unwrap().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        let third = salvage.save(audio).unwrap().unwrap();
+        assert!(!first.exists());
+        assert!(second.exists() && third.exists());
+    }
+}
@@ -0,0 +1,155 @@
+// Resumable TranscribeStream sessions
+//
+// Every stream gets an id, sent in its first response. When the stream ends,
+// however it ends, the options it set and its next utterance id are kept
+// under that id for MURMURE_SESSION_TTL_SECS, together with the audio of an
+// unfinished utterance when the connection broke and the audio is within
+// MURMURE_SESSION_RESUME_MAX_BYTES. A client that reconnects, possibly to
+// another replica behind the same store, presents the id in a StreamConfig
+// and continues where it left off. A session can be resumed once; the new
+// stream has its own id. The in-memory store keeps at most MAX_SESSIONS
+// sessions, dropping the oldest to make room.
+
+use murmure_stt::transcription::TranscriptionOptions;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Sessions kept in memory at most; the oldest is dropped beyond this
+const MAX_SESSIONS: usize = 10_000;
+
+/// What a stream leaves behind for the one that resumes it.
+#[derive(Clone)]
+pub struct SessionState {
+    /// Options as set by the stream's configs
+    pub options: TranscriptionOptions,
+    /// Id the next utterance will get
+    pub next_utterance_id: u32,
+    /// Audio of the utterance that was in progress (empty if none was kept)
+    pub pending_audio: Vec<u8>,
+}
+
+/// Where sessions are kept between streams. The in-memory store only serves
+/// reconnections to the same replica; a shared store (e.g. Redis) makes
+/// sessions follow clients across replicas.
+pub trait SessionStore: Send + Sync {
+    /// Keep `state` under `id` until it is taken or expires.
+    fn save(&self, id: &str, state: SessionState);
+
+    /// Remove and return the session, `None` if it is unknown or expired.
+    fn take(&self, id: &str) -> Option<SessionState>;
+}
+
+/// Sessions in a map, expired lazily when the map is next used.
+pub struct MemorySessionStore {
+    ttl: Duration,
+    max_sessions: usize,
+    sessions: Mutex<HashMap<String, (Instant, SessionState)>>,
+}
+
+impl MemorySessionStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self::with_max_sessions(ttl, MAX_SESSIONS)
+    }
+
+    fn with_max_sessions(ttl: Duration, max_sessions: usize) -> Self {
+        Self {
+            ttl,
+            max_sessions,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn prune(&self, sessions: &mut HashMap<String, (Instant, SessionState)>) {
+        sessions.retain(|_, (saved, _)| saved.elapsed() < self.ttl);
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn save(&self, id: &str, state: SessionState) {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.prune(&mut sessions);
+        while sessions.len() >= self.max_sessions && !sessions.contains_key(id) {
+            let Some(oldest) = sessions
+                .iter()
+                .min_by_key(|(_, (saved, _))| *saved)
+                .map(|(id, _)| id.clone())
+            else {
+                break;
+            };
+            sessions.remove(&oldest);
+        }
+        sessions.insert(id.to_string(), (Instant::now(), state));
+    }
+
+    fn take(&self, id: &str) -> Option<SessionState> {
+        let mut sessions = self.sessions.lock().unwrap();
+        self.prune(&mut sessions);
+        sessions.remove(id).map(|(_, state)| state)
+    }
+}
+
+/// A new unguessable session id: 128 random bits in hex. Knowing an id is
+/// enough to resume its session, so it must not be predictable.
+pub fn new_session_id() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).expect("OS random number generator unavailable");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use murmure_stt::config::ServerConfig;
+
+    fn state(next_utterance_id: u32) -> SessionState {
+        SessionState {
+            options: TranscriptionOptions::from_config(&ServerConfig::default()),
+            next_utterance_id,
+            pending_audio: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sessions_are_taken_once() {
+        let store = MemorySessionStore::new(Duration::from_secs(60));
+        store.save("a", state(3));
+        assert_eq!(store.take("a").unwrap().next_utterance_id, 3);
+        assert!(store.take("a").is_none());
+        assert!(store.take("unknown").is_none());
+    }
+
+    #[test]
+    fn sessions_expire() {
+        let store = MemorySessionStore::new(Duration::ZERO);
+        store.save("a", state(1));
+        assert!(store.take("a").is_none());
+    }
+
+    #[test]
+    fn the_oldest_sessions_make_room() {
+        let store = MemorySessionStore::with_max_sessions(Duration::from_secs(60), 2);
+        for id in ["a", "b", "c"] {
+            store.save(id, state(0));
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        assert!(store.take("a").is_none());
+        assert!(store.take("b").is_some());
+        assert!(store.take("c").is_some());
+
+        // Saving a session again doesn't evict another one
+        store.save("d", state(0));
+        store.save("e", state(0));
+        store.save("e", state(1));
+        assert!(store.take("d").is_some());
+        assert_eq!(store.take("e").unwrap().next_utterance_id, 1);
+    }
+
+    #[test]
+    fn session_ids_are_random_hex() {
+        let (a, b) = (new_session_id(), new_session_id());
+        assert_eq!(a.len(), 32);
+        assert!(a.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,223 @@
+// Spooling and progress tracking for TranscribeLargeFile uploads
+
+use murmure_stt::tempfiles;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tempfile::NamedTempFile;
+
+/// Uploads up to this size stay in memory; larger ones are moved to a temp file
+pub const SPOOL_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Where the bytes of an upload ended up.
+pub enum Spooled {
+    Memory(Vec<u8>),
+    Disk(NamedTempFile),
+}
+
+/// Upload buffer that keeps small uploads in memory and spills to disk once
+/// they exceed [`SPOOL_MEMORY_LIMIT`].
+pub struct UploadSpool {
+    inner: Spooled,
+    len: u64,
+    /// Where the temp file goes once the upload spills to disk
+    temp_dir: PathBuf,
+    /// Free space to leave in `temp_dir`, in bytes (0 = no check)
+    min_free_bytes: u64,
+}
+
+impl UploadSpool {
+    pub fn new(temp_dir: PathBuf, min_free_bytes: u64) -> Self {
+        Self {
+            inner: Spooled::Memory(Vec::new()),
+            len: 0,
+            temp_dir,
+            min_free_bytes,
+        }
+    }
+
+    /// Fail with a [`tempfiles::storage_full`] error when spooling `total_bytes`
+    /// to disk would leave less than the configured free space. Uploads that
+    /// fit in memory always pass.
+    pub fn check_space(&self, total_bytes: u64) -> io::Result<()> {
+        check_space(&self.temp_dir, self.min_free_bytes, total_bytes)
+    }
+
+    /// Number of bytes received so far.
+    pub fn received_bytes(&self) -> u64 {
+        self.len
+    }
+
+    pub fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if let Spooled::Memory(buffer) = &mut self.inner {
+            if buffer.len() + chunk.len() > SPOOL_MEMORY_LIMIT {
+                let total_bytes = (buffer.len() + chunk.len()) as u64;
+                check_space(&self.temp_dir, self.min_free_bytes, total_bytes)?;
+                let mut file = tempfiles::create(&self.temp_dir)?;
+                file.write_all(buffer)?;
+                self.inner = Spooled::Disk(file);
+            }
+        }
+
+        match &mut self.inner {
+            Spooled::Memory(buffer) => buffer.extend_from_slice(chunk),
+            Spooled::Disk(file) => file.write_all(chunk)?,
+        }
+        self.len += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Finish the upload and hand over its contents.
+    pub fn finish(self) -> io::Result<Spooled> {
+        let mut inner = self.inner;
+        if let Spooled::Disk(file) = &mut inner {
+            file.flush()?;
+        }
+        Ok(inner)
+    }
+}
+
+fn check_space(temp_dir: &Path, min_free_bytes: u64, total_bytes: u64) -> io::Result<()> {
+    if min_free_bytes == 0 || total_bytes <= SPOOL_MEMORY_LIMIT as u64 {
+        return Ok(());
+    }
+    let available = tempfiles::available_space(temp_dir)?;
+    if available.saturating_sub(total_bytes) < min_free_bytes {
+        return Err(tempfiles::storage_full(format!(
+            "not enough disk space to spool {} MB, {} MB free in {}",
+            total_bytes / (1024 * 1024),
+            available / (1024 * 1024),
+            temp_dir.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Progress of an upload, as reported by GetUploadProgress.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UploadProgress {
+    pub received_bytes: u64,
+    /// Size announced by the client (0 = unknown)
+    pub total_bytes: u64,
+    /// The upload is complete and being transcribed
+    pub transcribing: bool,
+}
+
+/// Progress of the uploads currently in flight, keyed by client-chosen id.
+#[derive(Clone, Default)]
+pub struct UploadRegistry {
+    uploads: Arc<Mutex<HashMap<String, UploadProgress>>>,
+}
+
+impl UploadRegistry {
+    pub fn get(&self, upload_id: &str) -> Option<UploadProgress> {
+        self.uploads.lock().unwrap().get(upload_id).copied()
+    }
+
+    /// Start tracking an upload; the entry is removed when the guard is dropped.
+    /// Returns `None` if an upload with the same id is already in flight.
+    pub fn register(&self, upload_id: String, total_bytes: u64) -> Option<UploadGuard> {
+        let mut uploads = self.uploads.lock().unwrap();
+        if uploads.contains_key(&upload_id) {
+            return None;
+        }
+        uploads.insert(
+            upload_id.clone(),
+            UploadProgress {
+                total_bytes,
+                ..Default::default()
+            },
+        );
+        Some(UploadGuard {
+            registry: self.clone(),
+            upload_id,
+        })
+    }
+}
+
+/// Handle used by an upload to publish its progress.
+pub struct UploadGuard {
+    registry: UploadRegistry,
+    upload_id: String,
+}
+
+impl UploadGuard {
+    pub fn update(&self, update: impl FnOnce(&mut UploadProgress)) {
+        if let Some(progress) = self
+            .registry
+            .uploads
+            .lock()
+            .unwrap()
+            .get_mut(&self.upload_id)
+        {
+            update(progress);
+        }
+    }
+}
+
+impl Drop for UploadGuard {
+    fn drop(&mut self) {
+        self.registry
+            .uploads
+            .lock()
+            .unwrap()
+            .remove(&self.upload_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_in_memory_skip_the_space_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = UploadSpool::new(dir.path().to_path_buf(), u64::MAX);
+        spool.check_space(SPOOL_MEMORY_LIMIT as u64).unwrap();
+        spool.write(&[0; 1024]).unwrap();
+        assert!(matches!(spool.finish().unwrap(), Spooled::Memory(bytes) if bytes.len() == 1024));
+    }
+
+    #[test]
+    fn refuses_uploads_that_would_fill_the_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = UploadSpool::new(dir.path().to_path_buf(), u64::MAX);
+        let error = spool
+            .check_space(SPOOL_MEMORY_LIMIT as u64 + 1)
+            .unwrap_err();
+        assert!(tempfiles::is_storage_full(&error));
+        assert!(
+            error
+                .to_string()
+                .contains(&dir.path().display().to_string()),
+            "{}",
+            error
+        );
+
+        // Also when the size wasn't announced and the upload spills over
+        let mut spool = UploadSpool::new(dir.path().to_path_buf(), u64::MAX);
+        spool.write(&vec![0; SPOOL_MEMORY_LIMIT]).unwrap();
+        let error = spool.write(&[0]).unwrap_err();
+        assert!(tempfiles::is_storage_full(&error));
+    }
+
+    #[test]
+    fn spills_large_uploads_to_the_temp_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut spool = UploadSpool::new(dir.path().to_path_buf(), 0);
+        spool.check_space(u64::MAX).unwrap();
+        spool.write(&vec![0; SPOOL_MEMORY_LIMIT]).unwrap();
+        spool.write(&[1; 10]).unwrap();
+        assert_eq!(spool.received_bytes(), SPOOL_MEMORY_LIMIT as u64 + 10);
+
+        let Spooled::Disk(file) = spool.finish().unwrap() else {
+            panic!("a spilled upload is on disk");
+        };
+        assert_eq!(file.path().parent(), Some(dir.path()));
+        assert_eq!(
+            file.as_file().metadata().unwrap().len(),
+            SPOOL_MEMORY_LIMIT as u64 + 10
+        );
+    }
+}
@@ -0,0 +1,503 @@
+// Audio usage per client, served by GetUsage, and the monthly quotas
+//
+// Every transcription admitted by the rate limiter is added to its client's
+// total for the current UTC day, under the same identity as the rate limits:
+// a configured API key, or the peer IP. Totals are kept by day, so GetUsage can
+// report any range of days and a quota can sum up the calendar month.
+//
+// Audio is counted when a request is admitted, from the duration its WAV
+// header declares, whether or not the transcription then succeeds.
+//
+// With MURMURE_USAGE_FILE set, the totals are written to that file every
+// minute and on shutdown, and read back on startup. A file that can't be read
+// is left alone: usage is then only kept in memory until the next restart.
+//
+// Totals are kept under the raw identity, so quotas can be looked up by key,
+// and only leave the ledger under the client's label.
+
+use murmure_stt::config::ServerConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tonic::Status;
+
+use super::ratelimit::{client_label, exhausted, ClientKey};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// How often changed totals are written to MURMURE_USAGE_FILE
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Days of usage kept, a bit over a year so last year's month can be compared
+const RETENTION_DAYS: i64 = 400;
+
+/// Usage of one client, on one day or summed over several.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub audio_secs: f64,
+    pub requests: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.audio_secs += other.audio_secs;
+        self.requests += other.requests;
+    }
+}
+
+/// A client's usage over the requested days, as reported by GetUsage.
+#[derive(Debug, Clone)]
+pub struct UsageReport {
+    /// Label of the client, see `ClientKey::label`
+    pub client: String,
+    pub usage: Usage,
+    /// Audio used in the current calendar month, for comparing with the quota
+    pub month_audio_secs: f64,
+    /// 0 = no quota
+    pub monthly_quota_secs: u64,
+}
+
+/// One entry of MURMURE_USAGE_FILE.
+#[derive(Serialize, Deserialize)]
+struct StoredDay {
+    client: String,
+    /// UTC date, `YYYY-MM-DD`
+    date: String,
+    audio_secs: f64,
+    requests: u64,
+}
+
+/// Per-client usage totals by UTC day, and the monthly quotas they are
+/// checked against.
+pub struct UsageLedger {
+    /// Totals by client and day (days since the Unix epoch)
+    days: Mutex<BTreeMap<(String, i64), Usage>>,
+    /// Monthly quota in seconds (0 = none)
+    default_quota: u64,
+    /// Quotas of the clients given their own in the rate limit overrides
+    quotas: HashMap<String, u64>,
+    /// `None` when usage isn't persisted, or the file couldn't be read
+    file: Option<PathBuf>,
+    /// Totals changed since they were last written to `file`
+    dirty: AtomicBool,
+    /// Day old totals were last dropped on
+    pruned_day: AtomicI64,
+}
+
+impl UsageLedger {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let quotas = config
+            .rate_limit_overrides
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .monthly_audio_secs
+                    .map(|secs| (entry.key.clone(), secs))
+            })
+            .collect();
+        let (days, file) = match &config.usage_file {
+            Some(path) => match load(path) {
+                Ok(days) => (days, Some(path.clone())),
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to read usage from {}, usage won't be saved until it is fixed: {}",
+                        path.display(),
+                        e
+                    );
+                    (BTreeMap::new(), None)
+                }
+            },
+            None => (BTreeMap::new(), None),
+        };
+        Self {
+            days: Mutex::new(days),
+            default_quota: config.usage_monthly_quota_secs,
+            quotas,
+            file,
+            dirty: AtomicBool::new(false),
+            pruned_day: AtomicI64::new(0),
+        }
+    }
+
+    /// Seconds of audio per calendar month `client` may use, 0 for no quota.
+    fn quota(&self, client: &str) -> u64 {
+        self.quotas
+            .get(client)
+            .copied()
+            .unwrap_or(self.default_quota)
+    }
+
+    /// Count a request with `secs` of audio against the client's monthly
+    /// quota, rejecting it when it would go over.
+    pub fn admit(&self, key: &ClientKey, secs: f32) -> Result<(), Status> {
+        let mut days = self.days.lock().unwrap();
+        self.check_quota(&days, key, secs)?;
+        self.add(&mut days, key, secs);
+        Ok(())
+    }
+
+    /// Reject `secs` more audio from the client when it would go over its
+    /// monthly quota, without counting it; [`Self::record`] counts it once
+    /// the request is accepted.
+    pub fn check(&self, key: &ClientKey, secs: f32) -> Result<(), Status> {
+        self.check_quota(&self.days.lock().unwrap(), key, secs)
+    }
+
+    fn check_quota(
+        &self,
+        days: &BTreeMap<(String, i64), Usage>,
+        key: &ClientKey,
+        secs: f32,
+    ) -> Result<(), Status> {
+        let quota = self.quota(&key.0);
+        if quota == 0 {
+            return Ok(());
+        }
+        let now = unix_secs();
+        let today = (now / SECS_PER_DAY) as i64;
+        let used = month_audio_secs(days, &key.0, today);
+        if used + secs as f64 <= quota as f64 {
+            return Ok(());
+        }
+        let next_month = next_month_start(today) as u64 * SECS_PER_DAY;
+        Err(exhausted(
+            format!(
+                "monthly quota of {}s of audio used up ({:.0}s used this month)",
+                quota, used
+            ),
+            Duration::from_secs(next_month.saturating_sub(now)),
+        ))
+    }
+
+    /// Count a request with `secs` of audio without checking the quota.
+    pub fn record(&self, key: &ClientKey, secs: f32) {
+        self.add(&mut self.days.lock().unwrap(), key, secs);
+    }
+
+    fn add(&self, days: &mut BTreeMap<(String, i64), Usage>, key: &ClientKey, secs: f32) {
+        let today = (unix_secs() / SECS_PER_DAY) as i64;
+        if self.pruned_day.swap(today, Ordering::Relaxed) != today {
+            days.retain(|(_, day), _| *day > today - RETENTION_DAYS);
+        }
+        let usage = days.entry((key.0.clone(), today)).or_default();
+        usage.audio_secs += secs as f64;
+        usage.requests += 1;
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Usage of the clients `include` accepts, given their raw identity, over
+    /// the days overlapping `from_unix_ms` to `to_unix_ms` (0 = up to now),
+    /// sorted by label.
+    pub fn report(
+        &self,
+        include: impl Fn(&str) -> bool,
+        from_unix_ms: u64,
+        to_unix_ms: u64,
+    ) -> Vec<UsageReport> {
+        const MS_PER_DAY: u64 = SECS_PER_DAY * 1000;
+        let today = (unix_secs() / SECS_PER_DAY) as i64;
+        let from_day = (from_unix_ms / MS_PER_DAY) as i64;
+        let to_day = match to_unix_ms {
+            0 => today + 1,
+            ms => ms.div_ceil(MS_PER_DAY) as i64,
+        };
+
+        let days = self.days.lock().unwrap();
+        let mut totals: BTreeMap<&str, Usage> = BTreeMap::new();
+        for ((name, day), usage) in days.iter() {
+            if (from_day..to_day).contains(day) && include(name) {
+                totals.entry(name).or_default().add(usage);
+            }
+        }
+        let mut reports: Vec<UsageReport> = totals
+            .into_iter()
+            .map(|(name, usage)| UsageReport {
+                client: client_label(name),
+                usage,
+                month_audio_secs: month_audio_secs(&days, name, today),
+                monthly_quota_secs: self.quota(name),
+            })
+            .collect();
+        reports.sort_by(|a, b| a.client.cmp(&b.client));
+        reports
+    }
+
+    /// Write the totals to MURMURE_USAGE_FILE if they changed since the last
+    /// write.
+    pub fn flush(&self) -> io::Result<()> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let stored: Vec<StoredDay> = self
+            .days
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((client, day), usage)| StoredDay {
+                client: client.clone(),
+                date: format_date(*day),
+                audio_secs: usage.audio_secs,
+                requests: usage.requests,
+            })
+            .collect();
+        let result = serde_json::to_vec_pretty(&stored)
+            .map_err(io::Error::from)
+            .and_then(|json| {
+                let partial = path.with_extension("partial");
+                fs::write(&partial, json)?;
+                fs::rename(&partial, path)
+            });
+        if result.is_err() {
+            // Try again on the next flush
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Write changed totals to MURMURE_USAGE_FILE every minute.
+    pub fn spawn_flusher(self: &Arc<Self>) {
+        if self.file.is_none() {
+            return;
+        }
+        let ledger = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let ledger = Arc::clone(&ledger);
+                match tokio::task::spawn_blocking(move || ledger.flush()).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => tracing::warn!("Failed to save usage: {}", e),
+                    Err(e) => tracing::warn!("Saving usage failed: {}", e),
+                }
+            }
+        });
+    }
+}
+
+/// Totals saved in `path`; a missing file is an empty ledger.
+fn load(path: &Path) -> io::Result<BTreeMap<(String, i64), Usage>> {
+    let json = match fs::read(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+    let stored: Vec<StoredDay> = serde_json::from_slice(&json)?;
+
+    let mut days = BTreeMap::new();
+    for entry in stored {
+        let day = parse_date(&entry.date).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid date '{}'", entry.date),
+            )
+        })?;
+        let usage: &mut Usage = days.entry((entry.client, day)).or_default();
+        usage.add(&Usage {
+            audio_secs: entry.audio_secs,
+            requests: entry.requests,
+        });
+    }
+    Ok(days)
+}
+
+/// Audio `client` used from the first of the month `today` is in.
+fn month_audio_secs(days: &BTreeMap<(String, i64), Usage>, client: &str, today: i64) -> f64 {
+    let (year, month, _) = civil_from_days(today);
+    let first = days_from_civil(year, month, 1);
+    days.range((client.to_string(), first)..=(client.to_string(), today))
+        .map(|(_, usage)| usage.audio_secs)
+        .sum()
+}
+
+/// First day of the month after the one `day` is in.
+fn next_month_start(day: i64) -> i64 {
+    match civil_from_days(day) {
+        (year, 12, _) => days_from_civil(year + 1, 1, 1),
+        (year, month, _) => days_from_civil(year, month + 1, 1),
+    }
+}
+
+fn unix_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn format_date(day: i64) -> String {
+    let (year, month, day) = civil_from_days(day);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts
+        .next()?
+        .parse()
+        .ok()
+        .filter(|m| (1..=12).contains(m))?;
+    let day = parts
+        .next()?
+        .parse()
+        .ok()
+        .filter(|d| (1..=31).contains(d))?;
+    Some(days_from_civil(year, month, day))
+}
+
+/// Gregorian year, month and day of a day counted from 1970-01-01.
+/// See http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Days since 1970-01-01 of a Gregorian date, the inverse of
+/// [`civil_from_days`].
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use murmure_stt::config::RateLimitOverride;
+
+    fn ledger(config: &ServerConfig) -> UsageLedger {
+        UsageLedger::from_config(config)
+    }
+
+    fn client(name: &str) -> ClientKey {
+        ClientKey(name.to_string())
+    }
+
+    #[test]
+    fn dates_round_trip() {
+        for (date, day) in [
+            ("1970-01-01", 0),
+            ("2000-02-29", 11_016),
+            ("2024-12-31", 20_088),
+        ] {
+            assert_eq!(parse_date(date), Some(day));
+            assert_eq!(format_date(day), date);
+        }
+        assert_eq!(parse_date("2024-13-01"), None);
+        assert_eq!(parse_date("2024-01"), None);
+        assert_eq!(parse_date("yesterday"), None);
+    }
+
+    #[test]
+    fn next_month_wraps_the_year() {
+        let mid_november = days_from_civil(2024, 11, 15);
+        assert_eq!(next_month_start(mid_november), days_from_civil(2024, 12, 1));
+        let new_year_eve = days_from_civil(2024, 12, 31);
+        assert_eq!(next_month_start(new_year_eve), days_from_civil(2025, 1, 1));
+    }
+
+    #[test]
+    fn quota_rejects_audio_over_the_month() {
+        let config = ServerConfig {
+            usage_monthly_quota_secs: 100,
+            rate_limit_overrides: vec![RateLimitOverride {
+                key: "batch-team".to_string(),
+                requests_per_minute: None,
+                audio_secs: None,
+                monthly_audio_secs: Some(0),
+            }],
+            ..ServerConfig::default()
+        };
+        let usage = ledger(&config);
+
+        usage.admit(&client("10.0.0.1"), 60.0).unwrap();
+        let status = usage.admit(&client("10.0.0.1"), 60.0).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(status.metadata().get("retry-after-ms").is_some());
+        // A rejected request isn't counted, a smaller one still fits
+        usage.admit(&client("10.0.0.1"), 40.0).unwrap();
+
+        // The override's 0 lifts the quota
+        usage.admit(&client("batch-team"), 1000.0).unwrap();
+    }
+
+    #[test]
+    fn report_labels_keys_and_filters_clients() {
+        let usage = ledger(&ServerConfig::default());
+        usage.record(&client("10.0.0.1"), 10.0);
+        usage.record(&client("10.0.0.1"), 5.0);
+        usage.record(&client("secret-team-key"), 30.0);
+
+        let all = usage.report(|_| true, 0, 0);
+        assert_eq!(all.len(), 2);
+        assert!(all.iter().all(|report| !report.client.contains("secret")));
+        let key = all
+            .iter()
+            .find(|report| report.client.starts_with("key-"))
+            .unwrap();
+        assert_eq!(key.client, client("secret-team-key").label());
+        assert_eq!(key.usage.requests, 1);
+
+        let own = usage.report(|name| name == "10.0.0.1", 0, 0);
+        assert_eq!(own.len(), 1);
+        assert_eq!(own[0].client, "10.0.0.1");
+        assert_eq!(own[0].usage.requests, 2);
+        assert_eq!(own[0].usage.audio_secs, 15.0);
+        assert_eq!(own[0].month_audio_secs, 15.0);
+
+        // A range in the past has nothing
+        assert!(usage.report(|_| true, 0, 1000).is_empty());
+    }
+
+    #[test]
+    fn totals_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            usage_file: Some(dir.path().join("usage.json")),
+            ..ServerConfig::default()
+        };
+        let usage = ledger(&config);
+        usage.record(&client("10.0.0.1"), 12.5);
+        usage.flush().unwrap();
+
+        let reloaded = ledger(&config).report(|_| true, 0, 0);
+        assert_eq!(reloaded.len(), 1);
+        assert_eq!(reloaded[0].usage.audio_secs, 12.5);
+        assert_eq!(reloaded[0].usage.requests, 1);
+    }
+
+    #[test]
+    fn files_written_with_synthesized_chars_still_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usage.json");
+        fs::write(
+            &path,
+            r#"[{"client": "10.0.0.1", "date": "2024-01-02", "audio_secs": 3.0,
+                "requests": 2, "synthesized_chars": 0}]"#,
+        )
+        .unwrap();
+        let days = load(&path).unwrap();
+        assert_eq!(days[&("10.0.0.1".to_string(), 19_724)].requests, 2);
+    }
+}
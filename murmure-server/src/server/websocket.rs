@@ -0,0 +1,763 @@
+// WebSocket bridge for browser clients
+//
+// Browsers can't open gRPC client streams, so live dictation from a web page
+// goes through `GET /v1/ws/transcribe` on its own port instead. The first
+// frame is a JSON config, binary frames carry raw PCM, and JSON control frames
+// end an utterance or the session. Utterances are transcribed by the same code
+// as TranscribeStream utterances and the responses are relayed as JSON.
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use murmure_stt::dsp;
+use murmure_stt::transcription::{TranscriptionOptions, TranscriptionService};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tonic::metadata::MetadataMap;
+
+use super::grpc::{
+    apply_stream_config, murmure, requested_language, requested_model, send_utterance,
+};
+use super::ratelimit::{ClientKey, RateLimiter};
+
+/// Path of the transcription endpoint
+pub const TRANSCRIBE_PATH: &str = "/v1/ws/transcribe";
+
+/// Interval between server pings
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+
+/// A session that sends nothing for this long, pongs included, is closed
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest frame accepted from a client
+const MAX_FRAME_BYTES: usize = 1024 * 1024;
+
+/// Close code for malformed config, control or audio frames
+const CLOSE_INVALID: u16 = 1007;
+
+/// Close code for sessions ended by a server limit
+const CLOSE_POLICY: u16 = 1008;
+
+/// Sample rates accepted in the session config
+const SAMPLE_RATES: std::ops::RangeInclusive<u32> = 8_000..=192_000;
+
+/// Most interleaved channels accepted in the session config
+const MAX_CHANNELS: u16 = 8;
+
+/// First frame of a session: layout of the PCM frames and transcription
+/// options, mirroring StreamConfig.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct SessionConfig {
+    sample_rate: u32,
+    channels: u16,
+    encoding: PcmEncoding,
+    /// Name of the model to transcribe with (empty = default model)
+    model: String,
+    use_dictionary: bool,
+    /// Language of the audio, selecting its dictionary (empty = default)
+    language: String,
+    segment_on_silence: bool,
+    punctuate: Option<bool>,
+    itn: Option<bool>,
+    /// High-pass cutoff in Hz (unset = server config, 0 = off)
+    highpass_hz: Option<f32>,
+    denoise: Option<bool>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 16000,
+            channels: 1,
+            encoding: PcmEncoding::default(),
+            model: String::new(),
+            use_dictionary: true,
+            language: String::new(),
+            segment_on_silence: false,
+            punctuate: None,
+            itn: None,
+            highpass_hz: None,
+            denoise: None,
+        }
+    }
+}
+
+/// Sample encoding of the binary frames; samples of several channels are
+/// interleaved.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum PcmEncoding {
+    /// Signed 16-bit little-endian (default)
+    #[default]
+    PcmS16le,
+    /// 32-bit float little-endian, as produced by the Web Audio API
+    PcmF32le,
+}
+
+impl PcmEncoding {
+    fn sample_bytes(self) -> usize {
+        match self {
+            Self::PcmS16le => 2,
+            Self::PcmF32le => 4,
+        }
+    }
+
+    fn decode(self, bytes: &[u8], out: &mut Vec<f32>) {
+        match self {
+            Self::PcmS16le => out.extend(
+                bytes
+                    .chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0),
+            ),
+            Self::PcmF32le => out.extend(
+                bytes
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PcmFormat {
+    sample_rate: u32,
+    channels: u16,
+    encoding: PcmEncoding,
+}
+
+/// Control frames a client sends after the config.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Control {
+    /// Transcribe the audio received since the last utterance, keep the session open
+    UtteranceEnd,
+    /// Transcribe the remaining audio and close the session
+    End,
+}
+
+/// Messages pushed to the client.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    /// Config accepted, audio may follow
+    Ready {
+        max_session_secs: u64,
+    },
+    /// One pause-delimited segment of an utterance
+    Partial {
+        utterance_id: u32,
+        text: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        start: Option<f32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        end: Option<f32>,
+    },
+    /// Complete transcript of an utterance
+    Final {
+        utterance_id: u32,
+        text: String,
+        audio_duration_secs: f32,
+        processing_time_ms: u64,
+        realtime_factor: f32,
+        /// Action of the voice command the text matched, if any
+        #[serde(skip_serializing_if = "Option::is_none")]
+        command: Option<String>,
+        /// Language the text was identified as, if detection is on
+        #[serde(skip_serializing_if = "Option::is_none")]
+        language: Option<String>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    fn from_stream_response(response: murmure::TranscribeStreamResponse) -> Option<Self> {
+        use murmure::transcribe_stream_response::ResponseType;
+
+        let utterance_id = response.utterance_id;
+        Some(match response.response_type? {
+            ResponseType::PartialText(text) => Self::Partial {
+                utterance_id,
+                text,
+                start: response.segment.as_ref().map(|segment| segment.start),
+                end: response.segment.as_ref().map(|segment| segment.end),
+            },
+            ResponseType::FinalText(text) => Self::Final {
+                utterance_id,
+                text,
+                audio_duration_secs: response.audio_duration_secs,
+                processing_time_ms: response.processing_time_ms,
+                realtime_factor: response.realtime_factor,
+                command: response.detected_command.map(|command| command.action),
+                language: response.detected_language.map(|language| language.language),
+            },
+            ResponseType::Error(message) => Self::Error { message },
+            ResponseType::Heartbeat(_) | ResponseType::Session(_) => return None,
+        })
+    }
+}
+
+/// Shared state of the WebSocket listener.
+pub struct WebSocketBridge {
+    service: Arc<TranscriptionService>,
+    limiter: Arc<RateLimiter>,
+    /// Origins of the pages allowed to open sessions, lowercased
+    allowed_origins: Vec<String>,
+}
+
+impl WebSocketBridge {
+    pub fn new(service: Arc<TranscriptionService>, limiter: Arc<RateLimiter>) -> Self {
+        let allowed_origins = service
+            .get_config()
+            .ws_allowed_origins
+            .iter()
+            .map(|origin| origin.trim_end_matches('/').to_ascii_lowercase())
+            .collect();
+        Self {
+            service,
+            limiter,
+            allowed_origins,
+        }
+    }
+
+    /// Whether a request with these headers may open a session. Browsers
+    /// always send the origin of the page, which has to be allowed; other
+    /// clients send none.
+    fn origin_allowed(&self, headers: &HeaderMap) -> bool {
+        let Some(origin) = headers.get(header::ORIGIN) else {
+            return true;
+        };
+        origin.to_str().is_ok_and(|origin| {
+            let origin = origin.to_ascii_lowercase();
+            self.allowed_origins.contains(&origin)
+        })
+    }
+
+    /// Accept sessions on `addr` until the process exits.
+    pub async fn serve(self, addr: SocketAddr) -> std::io::Result<()> {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        axum::serve(
+            listener,
+            self.router()
+                .into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    }
+
+    fn router(self) -> Router {
+        Router::new()
+            .route(TRANSCRIBE_PATH, get(upgrade))
+            .with_state(Arc::new(self))
+    }
+}
+
+/// Opening a session counts as one request against the client's rate limit.
+/// Pages from origins that aren't allowed are refused before that.
+async fn upgrade(
+    State(bridge): State<Arc<WebSocketBridge>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Response {
+    if !bridge.origin_allowed(&headers) {
+        tracing::warn!(
+            "Refused WebSocket session from {} for origin {:?}",
+            peer.ip(),
+            headers.get(header::ORIGIN)
+        );
+        return (StatusCode::FORBIDDEN, "origin not allowed").into_response();
+    }
+    let client = bridge
+        .limiter
+        .client_key(&MetadataMap::from_headers(headers), Some(peer));
+    if let Err(status) = bridge.limiter.check_request(&client) {
+        tracing::warn!(
+            "Rate limited WebSocket client {}: {}",
+            client,
+            status.message()
+        );
+        return (StatusCode::TOO_MANY_REQUESTS, status.message().to_string()).into_response();
+    }
+
+    ws.max_message_size(MAX_FRAME_BYTES)
+        .max_frame_size(MAX_FRAME_BYTES)
+        .on_upgrade(move |socket| {
+            let options = TranscriptionOptions::from_config(bridge.service.get_config());
+            Session {
+                bridge,
+                socket,
+                client,
+                options,
+                format: None,
+                samples: Vec::new(),
+                pending: Vec::new(),
+                utterance_id: 0,
+            }
+            .run()
+        })
+}
+
+struct Session {
+    bridge: Arc<WebSocketBridge>,
+    socket: WebSocket,
+    client: ClientKey,
+    options: TranscriptionOptions,
+    /// Set by the config frame
+    format: Option<PcmFormat>,
+    /// Interleaved samples of the current utterance
+    samples: Vec<f32>,
+    /// Trailing bytes of a sample split across frames
+    pending: Vec<u8>,
+    utterance_id: u32,
+}
+
+impl Session {
+    async fn run(mut self) {
+        let max_session_secs = self.bridge.service.get_config().ws_max_session_secs;
+        let deadline = (max_session_secs > 0)
+            .then(|| tokio::time::Instant::now() + Duration::from_secs(max_session_secs));
+        let mut ping = tokio::time::interval(PING_INTERVAL);
+        ping.tick().await;
+        let mut last_seen = Instant::now();
+
+        loop {
+            let frame = tokio::select! {
+                frame = self.socket.recv() => frame,
+                _ = ping.tick() => {
+                    if last_seen.elapsed() > IDLE_TIMEOUT {
+                        self.close(CLOSE_POLICY, "idle timeout").await;
+                        return;
+                    }
+                    if self.socket.send(Message::Ping(Vec::new())).await.is_err() {
+                        return;
+                    }
+                    continue;
+                }
+                _ = expire(deadline) => {
+                    // Keep what was said before the limit
+                    if self.transcribe_utterance().await {
+                        self.close(CLOSE_POLICY, "maximum session duration reached")
+                            .await;
+                    }
+                    return;
+                }
+            };
+
+            let keep_going = match frame {
+                Some(Ok(Message::Binary(data))) => self.push_audio(&data).await,
+                Some(Ok(Message::Text(text))) => self.handle_text(&text).await,
+                // Pings are answered by the socket itself
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => true,
+                Some(Ok(Message::Close(_))) | None => false,
+                Some(Err(e)) => {
+                    tracing::debug!("WebSocket session of {} failed: {}", self.client, e);
+                    false
+                }
+            };
+            if !keep_going {
+                return;
+            }
+            // Also covers the time spent transcribing, when no frame is read
+            last_seen = Instant::now();
+        }
+    }
+
+    /// Handle the config frame or a control frame. Returns `false` once the
+    /// session is over.
+    async fn handle_text(&mut self, text: &str) -> bool {
+        if self.format.is_none() {
+            return self.configure(text).await;
+        }
+        match serde_json::from_str::<Control>(text) {
+            Ok(Control::UtteranceEnd) => self.transcribe_utterance().await,
+            Ok(Control::End) => {
+                if self.transcribe_utterance().await {
+                    self.close(1000, "").await;
+                }
+                false
+            }
+            Err(e) => {
+                self.fail(CLOSE_INVALID, format!("Invalid control message: {}", e))
+                    .await
+            }
+        }
+    }
+
+    async fn configure(&mut self, text: &str) -> bool {
+        let config: SessionConfig = match serde_json::from_str(text) {
+            Ok(config) => config,
+            Err(e) => {
+                return self
+                    .fail(CLOSE_INVALID, format!("Invalid session config: {}", e))
+                    .await;
+            }
+        };
+        if !SAMPLE_RATES.contains(&config.sample_rate) {
+            return self
+                .fail(
+                    CLOSE_INVALID,
+                    format!(
+                        "sample_rate must be between {} and {} Hz, got {}",
+                        SAMPLE_RATES.start(),
+                        SAMPLE_RATES.end(),
+                        config.sample_rate
+                    ),
+                )
+                .await;
+        }
+        if !(1..=MAX_CHANNELS).contains(&config.channels) {
+            return self
+                .fail(
+                    CLOSE_INVALID,
+                    format!(
+                        "channels must be between 1 and {}, got {}",
+                        MAX_CHANNELS, config.channels
+                    ),
+                )
+                .await;
+        }
+        if let Err(status) = requested_model(&self.bridge.service, &config.model) {
+            return self.fail(CLOSE_INVALID, status.message().to_string()).await;
+        }
+        if let Err(status) = requested_language(&config.language) {
+            return self.fail(CLOSE_INVALID, status.message().to_string()).await;
+        }
+
+        apply_stream_config(
+            &mut self.options,
+            &murmure::StreamConfig {
+                use_dictionary: Some(config.use_dictionary),
+                segment_on_silence: config.segment_on_silence,
+                punctuate: config.punctuate,
+                itn: config.itn,
+                highpass_hz: config.highpass_hz,
+                denoise: config.denoise,
+                model: config.model,
+                language: config.language,
+                ..Default::default()
+            },
+        );
+        self.format = Some(PcmFormat {
+            sample_rate: config.sample_rate,
+            channels: config.channels,
+            encoding: config.encoding,
+        });
+        self.send(&ServerMessage::Ready {
+            max_session_secs: self.bridge.service.get_config().ws_max_session_secs,
+        })
+        .await
+    }
+
+    async fn push_audio(&mut self, data: &[u8]) -> bool {
+        let Some(format) = self.format else {
+            return self
+                .fail(
+                    CLOSE_INVALID,
+                    "Send the JSON session config before audio".to_string(),
+                )
+                .await;
+        };
+
+        self.pending.extend_from_slice(data);
+        let sample_bytes = format.encoding.sample_bytes();
+        let whole = self.pending.len() / sample_bytes * sample_bytes;
+        format
+            .encoding
+            .decode(&self.pending[..whole], &mut self.samples);
+        self.pending.drain(..whole);
+
+        let secs = self.samples.len() as f64 / (format.sample_rate as f64 * format.channels as f64);
+        if let Some(max) = self
+            .bridge
+            .service
+            .get_config()
+            .max_audio_duration()
+            .filter(|&max| secs > max as f64)
+        {
+            return self
+                .fail(
+                    CLOSE_POLICY,
+                    format!("Utterance longer than {:.0}s, end it sooner", max),
+                )
+                .await;
+        }
+        true
+    }
+
+    /// Transcribe the buffered audio as one utterance and relay its
+    /// responses. Returns `false` once the session is over.
+    async fn transcribe_utterance(&mut self) -> bool {
+        let Some(format) = self.format else {
+            return true;
+        };
+        self.pending.clear();
+        let samples = std::mem::take(&mut self.samples);
+        if samples.is_empty() {
+            return true;
+        }
+
+        let mono = dsp::downmix_to_mono(&samples, format.channels as usize);
+        let secs = mono.len() as f32 / format.sample_rate as f32;
+        let wav = match dsp::write_wav_bytes(&mono, format.sample_rate) {
+            Ok(wav) => wav,
+            Err(e) => {
+                return self
+                    .fail(CLOSE_INVALID, format!("Failed to encode audio: {}", e))
+                    .await;
+            }
+        };
+        let _permit = match self.bridge.limiter.reserve_audio(&self.client, secs) {
+            Ok(permit) => permit,
+            Err(status) => {
+                return self.fail(CLOSE_POLICY, status.message().to_string()).await;
+            }
+        };
+
+        let (tx, mut rx) = mpsc::channel(32);
+        let bridge = &self.bridge;
+        let options = &self.options;
+        let socket = &mut self.socket;
+        let utterance_id = self.utterance_id;
+
+        let transcribe = async move {
+            let sent =
+                send_utterance(&bridge.service, wav, options, utterance_id, false, &tx).await;
+            drop(tx);
+            sent
+        };
+        let relay = async move {
+            let mut connected = true;
+            // Keep draining after a failed send so the transcription can finish
+            while let Some(response) = rx.recv().await {
+                let message = match response {
+                    Ok(response) => ServerMessage::from_stream_response(response),
+                    Err(status) => Some(ServerMessage::Error {
+                        message: status.message().to_string(),
+                    }),
+                };
+                if let Some(message) = message.filter(|_| connected) {
+                    connected = send_json(socket, &message).await;
+                }
+            }
+            connected
+        };
+
+        let (sent, connected) = tokio::join!(transcribe, relay);
+        self.utterance_id += 1;
+        sent && connected
+    }
+
+    async fn send(&mut self, message: &ServerMessage) -> bool {
+        send_json(&mut self.socket, message).await
+    }
+
+    /// Report an error to the client and close the session. Always `false`.
+    async fn fail(&mut self, code: u16, message: String) -> bool {
+        tracing::debug!("Closing WebSocket session of {}: {}", self.client, message);
+        if self.send(&ServerMessage::Error { message }).await {
+            self.close(code, "").await;
+        }
+        false
+    }
+
+    async fn close(&mut self, code: u16, reason: &'static str) {
+        let _ = self
+            .socket
+            .send(Message::Close(Some(CloseFrame {
+                code,
+                reason: reason.into(),
+            })))
+            .await;
+    }
+}
+
+async fn send_json(socket: &mut WebSocket, message: &ServerMessage) -> bool {
+    match serde_json::to_string(message) {
+        Ok(json) => socket.send(Message::Text(json)).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Completes at `deadline`, never when there is none.
+async fn expire(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use murmure_stt::config::ServerConfig;
+    use murmure_stt::mock::MockEngine;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::{self, Message as WsMessage};
+
+    const ALLOWED: &str = "https://dictation.example.com";
+
+    fn bridge() -> WebSocketBridge {
+        let config = ServerConfig {
+            ws_allowed_origins: vec![format!("{}/", ALLOWED.to_uppercase())],
+            ..ServerConfig::default()
+        };
+        let engine = MockEngine::new("hello world");
+        let service = TranscriptionService::builder()
+            .config(config.clone())
+            .engine(move || engine.clone())
+            .build()
+            .unwrap();
+        WebSocketBridge::new(
+            Arc::new(service),
+            Arc::new(RateLimiter::from_config(&config)),
+        )
+    }
+
+    /// Serve a bridge on a free local port and return its URL.
+    async fn start() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = bridge()
+            .router()
+            .into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("ws://{}{}", addr, TRANSCRIBE_PATH)
+    }
+
+    fn headers(origin: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, origin.parse().unwrap());
+        }
+        headers
+    }
+
+    #[tokio::test]
+    async fn only_allowed_origins_open_sessions() {
+        let bridge = bridge();
+        assert!(bridge.origin_allowed(&headers(None)));
+        assert!(bridge.origin_allowed(&headers(Some(ALLOWED))));
+        assert!(bridge.origin_allowed(&headers(Some("HTTPS://Dictation.Example.com"))));
+        assert!(!bridge.origin_allowed(&headers(Some("https://evil.example.com"))));
+        assert!(!bridge.origin_allowed(&headers(Some("https://dictation.example.com.evil"))));
+        assert!(!bridge.origin_allowed(&headers(Some("null"))));
+    }
+
+    #[test]
+    fn decodes_both_encodings() {
+        let mut samples = Vec::new();
+        PcmEncoding::PcmS16le.decode(&[0x00, 0x40, 0x00, 0xc0, 0xff], &mut samples);
+        assert_eq!(samples, [0.5, -0.5]);
+
+        samples.clear();
+        let bytes: Vec<u8> = [0.25f32, -1.0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        PcmEncoding::PcmF32le.decode(&bytes, &mut samples);
+        assert_eq!(samples, [0.25, -1.0]);
+    }
+
+    #[tokio::test]
+    async fn foreign_origin_is_refused() {
+        let url = start().await;
+        let mut request = url.into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("origin", "https://evil.example.com".parse().unwrap());
+        match tokio_tungstenite::connect_async(request).await {
+            Err(tungstenite::Error::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::FORBIDDEN)
+            }
+            other => panic!("expected 403, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn transcribes_an_utterance() {
+        let url = start().await;
+        let mut request = url.into_client_request().unwrap();
+        request
+            .headers_mut()
+            .insert("origin", ALLOWED.parse().unwrap());
+        let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+
+        socket
+            .send(WsMessage::Text(r#"{"sample_rate": 16000}"#.into()))
+            .await
+            .unwrap();
+        let ready = next_json(&mut socket).await;
+        assert_eq!(ready["type"], "ready");
+
+        // One second of a 440 Hz tone
+        let pcm: Vec<u8> = (0..16_000)
+            .map(|i| ((i as f32 * 440.0 * std::f32::consts::TAU / 16_000.0).sin() * 8000.0) as i16)
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        socket.send(WsMessage::Binary(pcm)).await.unwrap();
+        socket
+            .send(WsMessage::Text(r#"{"type": "end"}"#.into()))
+            .await
+            .unwrap();
+
+        let last = loop {
+            let message = next_json(&mut socket).await;
+            if message["type"] != "partial" {
+                break message;
+            }
+        };
+        assert_eq!(last["type"], "final", "{}", last);
+        assert_eq!(last["text"], "hello world");
+        assert_eq!(last["utterance_id"], 0);
+    }
+
+    async fn next_json<S>(socket: &mut S) -> serde_json::Value
+    where
+        S: StreamExt<Item = Result<WsMessage, tungstenite::Error>> + Unpin,
+    {
+        loop {
+            match socket.next().await.unwrap().unwrap() {
+                WsMessage::Text(text) => return serde_json::from_str(&text).unwrap(),
+                WsMessage::Ping(_) | WsMessage::Pong(_) => continue,
+                other => panic!("unexpected frame {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_formats() {
+        for config in [
+            r#"{"sample_rate": 4000000000, "channels": 2}"#,
+            r#"{"sample_rate": 0}"#,
+            r#"{"sample_rate": 16000, "channels": 65535}"#,
+            r#"{"sample_rate": 16000, "channels": 0}"#,
+        ] {
+            let mut request = start().await.into_client_request().unwrap();
+            request
+                .headers_mut()
+                .insert("origin", ALLOWED.parse().unwrap());
+            let (mut socket, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+            socket.send(WsMessage::Text(config.into())).await.unwrap();
+
+            let close = loop {
+                match socket.next().await.unwrap().unwrap() {
+                    WsMessage::Close(frame) => break frame.unwrap(),
+                    WsMessage::Text(text) => {
+                        assert!(!text.contains("ready"), "{}: {}", config, text)
+                    }
+                    _ => continue,
+                }
+            };
+            assert_eq!(u16::from(close.code), CLOSE_INVALID, "{}", config);
+        }
+    }
+}
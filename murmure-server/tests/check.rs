@@ -0,0 +1,136 @@
+// `murmure-server --check --json` against temp environments: the exit code
+// and the components reported as failing
+
+use std::collections::BTreeMap;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Command;
+
+use serde_json::Value;
+
+/// Run the check in `dir` with only `env` set, so no resources next to the
+/// working directory are found; its exit code and JSON report.
+fn check(dir: &Path, env: &[(&str, &Path)]) -> (Option<i32>, Value) {
+    let output = Command::new(env!("CARGO_BIN_EXE_murmure-server"))
+        .args(["--check", "--json"])
+        .current_dir(dir)
+        .env_clear()
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    let report = serde_json::from_slice(&output.stdout)
+        .unwrap_or_else(|e| panic!("{}: {}", e, String::from_utf8_lossy(&output.stdout)));
+    (output.status.code(), report)
+}
+
+/// Status of each component in a report.
+fn statuses(report: &Value) -> BTreeMap<String, String> {
+    report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|check| {
+            (
+                check["component"].as_str().unwrap().to_string(),
+                check["status"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+fn free_port() -> String {
+    let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+    listener.local_addr().unwrap().port().to_string()
+}
+
+#[test]
+fn passes_a_complete_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let model = dir.path().join("model");
+    std::fs::create_dir(&model).unwrap();
+    for name in [
+        "encoder-model.int8.onnx",
+        "decoder_joint-model.int8.onnx",
+        "nemo128.onnx",
+        "vocab.txt",
+    ] {
+        std::fs::write(model.join(name), b"").unwrap();
+    }
+    let temp = dir.path().join("tmp");
+    let port = free_port();
+
+    let (code, report) = check(
+        dir.path(),
+        &[
+            ("MURMURE_MODEL_PATH", &model),
+            ("MURMURE_TEMP_DIR", &temp),
+            ("MURMURE_GRPC_PORT", Path::new(&port)),
+        ],
+    );
+    assert_eq!(code, Some(0), "{:#}", report);
+    assert_eq!(report["ok"], true);
+    let statuses = statuses(&report);
+    assert_eq!(statuses["model"], "ok");
+    assert_eq!(statuses["temp_dir"], "ok");
+    assert_eq!(statuses["grpc_port"], "ok");
+    // Not needed without a dictionary
+    assert_eq!(statuses["cc_rules"], "skip");
+    assert_eq!(statuses["tts"], "skip");
+}
+
+#[test]
+fn reports_each_broken_component() {
+    let dir = tempfile::tempdir().unwrap();
+    // A regular file where the temp dir should be
+    let temp = dir.path().join("tmp");
+    std::fs::write(&temp, b"").unwrap();
+    // And a port already taken
+    let taken = TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = taken.local_addr().unwrap().port().to_string();
+
+    let (code, report) = check(
+        dir.path(),
+        &[
+            ("MURMURE_MODEL_PATH", &dir.path().join("missing-model")),
+            ("MURMURE_CC_RULES_PATH", &dir.path().join("missing-rules")),
+            ("MURMURE_DICTIONARY", Path::new(r#"["Murmure"]"#)),
+            ("MURMURE_TEMP_DIR", &temp),
+            ("MURMURE_GRPC_PORT", Path::new(&port)),
+        ],
+    );
+    assert_eq!(code, Some(1), "{:#}", report);
+    assert_eq!(report["ok"], false);
+    let statuses = statuses(&report);
+    for component in ["config", "model", "cc_rules", "temp_dir", "grpc_port"] {
+        assert_eq!(statuses[component], "fail", "{}: {:#}", component, report);
+    }
+    assert_eq!(statuses["tts"], "skip");
+}
+
+#[test]
+fn reports_an_unreadable_configuration_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let (code, report) = check(dir.path(), &[("MURMURE_DICTIONARY", Path::new("not json"))]);
+    assert_eq!(code, Some(1));
+    assert_eq!(report["checks"].as_array().unwrap().len(), 1);
+    assert_eq!(statuses(&report)["config"], "fail");
+    assert!(report["checks"][0]["detail"]
+        .as_str()
+        .unwrap()
+        .contains("MURMURE_DICTIONARY"));
+}
+
+#[test]
+fn reports_a_tampered_settings_bundle() {
+    let dir = tempfile::tempdir().unwrap();
+    let bundle = dir.path().join("settings.json");
+    std::fs::write(&bundle, r#"{"format": "murmure-settings", "version": 99}"#).unwrap();
+
+    let (code, report) = check(dir.path(), &[("MURMURE_SETTINGS_BUNDLE", &bundle)]);
+    assert_eq!(code, Some(1));
+    assert_eq!(statuses(&report)["config"], "fail");
+    assert!(report["checks"][0]["detail"]
+        .as_str()
+        .unwrap()
+        .contains("version 99 is newer"));
+}
@@ -0,0 +1,282 @@
+// The client crate against the real service: helpers, connection options,
+// failover between the server and the local engine, and the proto
+// compatibility it promises
+
+mod common;
+
+use common::{murmure, tone_wav, TestServer};
+use murmure_client::{
+    BackendKind, BackendPolicy, ClientError, ClientOptions, Murmure, MurmureClient,
+};
+use murmure_stt::config::{RateLimitOverride, ServerConfig};
+use murmure_stt::mock::MockEngine;
+use murmure_stt::TranscriptionService;
+use prost::Message;
+use tokio_stream::StreamExt;
+use tonic::Code;
+
+fn options(server: &TestServer) -> ClientOptions {
+    ClientOptions::new(format!("http://{}", server.addr))
+}
+
+/// Options of a port nothing listens on anymore.
+async fn closed_port() -> ClientOptions {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    ClientOptions::new(format!("http://{}", addr))
+}
+
+/// The in-process backend, on `engine`.
+fn local(engine: MockEngine) -> TranscriptionService {
+    TranscriptionService::builder()
+        .engine(move || engine.clone())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn transcribes_wav_bytes() {
+    let server = TestServer::start(MockEngine::new("hello world")).await;
+    let mut client = MurmureClient::connect(options(&server)).await.unwrap();
+
+    let result = client.transcribe_wav_bytes(&tone_wav(1.0)).await.unwrap();
+    assert_eq!(result.text, "hello world");
+    assert!((result.audio_duration_secs - 1.0).abs() < 0.01);
+    assert!(result
+        .metadata
+        .get("x-murmure-dictionary-revision")
+        .is_some());
+}
+
+#[tokio::test]
+async fn streams_wav_chunks() {
+    let server = TestServer::start(MockEngine::new("streamed text")).await;
+    let options = ClientOptions {
+        chunk_size: 1000,
+        ..options(&server)
+    };
+    let mut client = MurmureClient::connect(options).await.unwrap();
+
+    let wav = tone_wav(1.0);
+    let chunks: Vec<_> = client.wav_chunks(wav.clone().into()).collect().await;
+    assert_eq!(chunks.len(), wav.len().div_ceil(1000));
+    assert!(chunks[..chunks.len() - 1].iter().all(|c| c.len() == 1000));
+    assert_eq!(chunks.concat(), wav);
+
+    let result = client
+        .transcribe_stream(client.wav_chunks(wav.into()))
+        .await
+        .unwrap();
+    assert_eq!(result.text, "streamed text");
+    assert!((result.audio_duration_secs - 1.0).abs() < 0.01);
+}
+
+#[tokio::test]
+async fn compresses_requests() {
+    let server = TestServer::start(MockEngine::new("compressed")).await;
+    let options = ClientOptions {
+        compression: true,
+        ..options(&server)
+    };
+    let mut client = MurmureClient::connect(options).await.unwrap();
+
+    let result = client.transcribe_wav_bytes(&tone_wav(1.0)).await.unwrap();
+    assert_eq!(result.text, "compressed");
+}
+
+#[tokio::test]
+async fn server_errors_keep_their_status() {
+    let server = TestServer::start(MockEngine::new("unused")).await;
+    let mut client = MurmureClient::connect(options(&server)).await.unwrap();
+
+    let error = client
+        .transcribe_wav_bytes(b"definitely not a wav file")
+        .await
+        .unwrap_err();
+    let ClientError::Status(status) = &error else {
+        panic!("expected a status, got {:?}", error);
+    };
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(!error.is_unavailable());
+}
+
+#[tokio::test]
+async fn sends_the_api_key() {
+    // One request a minute, except for the key
+    let config = ServerConfig {
+        rate_limit_rpm: 1,
+        rate_limit_overrides: vec![RateLimitOverride {
+            key: "team-key".to_string(),
+            requests_per_minute: Some(0),
+            audio_secs: None,
+            monthly_audio_secs: None,
+        }],
+        ..ServerConfig::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("ok")).await;
+
+    let keyed = ClientOptions {
+        api_key: Some("team-key".to_string()),
+        ..options(&server)
+    };
+    let mut client = MurmureClient::connect(keyed).await.unwrap();
+    for _ in 0..3 {
+        client.transcribe_wav_bytes(&tone_wav(0.5)).await.unwrap();
+    }
+
+    let mut anonymous = MurmureClient::connect(options(&server)).await.unwrap();
+    anonymous
+        .transcribe_wav_bytes(&tone_wav(0.5))
+        .await
+        .unwrap();
+    let error = anonymous
+        .transcribe_wav_bytes(&tone_wav(0.5))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, ClientError::Status(status) if status.code() == Code::ResourceExhausted),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn rejects_keys_not_valid_as_metadata() {
+    let options = ClientOptions {
+        api_key: Some("line\nbreak".to_string()),
+        ..ClientOptions::default()
+    };
+    let error = MurmureClient::connect(options).await.err().unwrap();
+    assert!(matches!(error, ClientError::InvalidApiKey), "{:?}", error);
+}
+
+#[tokio::test]
+async fn unreachable_servers_are_unavailable() {
+    let error = MurmureClient::connect(closed_port().await)
+        .await
+        .err()
+        .unwrap();
+    assert!(error.is_unavailable(), "{:?}", error);
+}
+
+#[tokio::test]
+async fn fails_over_to_the_local_engine() {
+    let wav = tone_wav(1.0);
+    let mut murmure = Murmure::new(BackendPolicy::PreferRemote)
+        .with_remote(closed_port().await)
+        .with_local(local(MockEngine::new("local text")));
+    let result = murmure.transcribe_wav_bytes(&wav).await.unwrap();
+    assert_eq!(result.text, "local text");
+    assert_eq!(murmure.last_backend(), Some(BackendKind::Local));
+
+    let mut remote_only = Murmure::new(BackendPolicy::RemoteOnly)
+        .with_remote(closed_port().await)
+        .with_local(local(MockEngine::new("local text")));
+    let error = remote_only.transcribe_wav_bytes(&wav).await.unwrap_err();
+    assert!(error.is_unavailable(), "{:?}", error);
+    assert_eq!(remote_only.last_backend(), None);
+
+    let mut local_only = Murmure::new(BackendPolicy::LocalOnly).with_remote(closed_port().await);
+    let error = local_only.transcribe_wav_bytes(&wav).await.unwrap_err();
+    assert!(matches!(error, ClientError::NoBackend), "{:?}", error);
+}
+
+#[tokio::test]
+async fn follows_the_order_of_the_policy() {
+    let server = TestServer::start(MockEngine::new("remote text")).await;
+    let wav = tone_wav(1.0);
+    for (policy, text, kind) in [
+        (BackendPolicy::PreferLocal, "local text", BackendKind::Local),
+        (
+            BackendPolicy::PreferRemote,
+            "remote text",
+            BackendKind::Remote,
+        ),
+        (BackendPolicy::LocalOnly, "local text", BackendKind::Local),
+        (
+            BackendPolicy::RemoteOnly,
+            "remote text",
+            BackendKind::Remote,
+        ),
+    ] {
+        let mut murmure = Murmure::new(policy)
+            .with_remote(options(&server))
+            .with_local(local(MockEngine::new("local text")));
+        let result = murmure.transcribe_wav_bytes(&wav).await.unwrap();
+        assert_eq!(result.text, text, "{:?}", policy);
+        assert_eq!(murmure.last_backend(), Some(kind), "{:?}", policy);
+    }
+}
+
+#[tokio::test]
+async fn backends_return_the_same_result() {
+    let server = TestServer::start(MockEngine::new("hello world")).await;
+    let wav = tone_wav(1.5);
+    let mut remote = Murmure::new(BackendPolicy::RemoteOnly).with_remote(options(&server));
+    let mut in_process =
+        Murmure::new(BackendPolicy::LocalOnly).with_local(local(MockEngine::new("hello world")));
+
+    let remote = remote.transcribe_wav_bytes(&wav).await.unwrap();
+    let in_process = in_process.transcribe_wav_bytes(&wav).await.unwrap();
+    assert_eq!(in_process.text, remote.text);
+    assert_eq!(in_process.segments, remote.segments);
+    assert_eq!(in_process.corrections, remote.corrections);
+    assert!((in_process.audio_duration_secs - remote.audio_duration_secs).abs() < 0.001);
+}
+
+#[tokio::test]
+async fn request_errors_do_not_fail_over() {
+    let server = TestServer::start(MockEngine::new("remote text")).await;
+    let mut murmure = Murmure::new(BackendPolicy::PreferRemote)
+        .with_remote(options(&server))
+        .with_local(local(MockEngine::new("local text")));
+
+    let error = murmure
+        .transcribe_wav_bytes(b"not a wav file")
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, ClientError::Status(status) if status.code() == Code::InvalidArgument),
+        "{:?}",
+        error
+    );
+    assert_eq!(murmure.last_backend(), None);
+}
+
+#[test]
+fn ignores_fields_it_does_not_know() {
+    // What a newer server of the same major version may send: a field
+    // number this client wasn't built with
+    let response = murmure::TranscribeFileResponse {
+        success: true,
+        text: "hello".to_string(),
+        ..Default::default()
+    };
+    let mut bytes = response.encode_to_vec();
+    // Field 999, varint 1
+    bytes.extend([0xb8, 0x3e, 0x01]);
+
+    let decoded = murmure::TranscribeFileResponse::decode(bytes.as_slice()).unwrap();
+    assert_eq!(decoded, response);
+
+    // And what an older one may leave out reads as the default
+    let decoded = murmure::TranscribeFileResponse::decode(&[][..]).unwrap();
+    assert!(!decoded.success);
+    assert!(decoded.segments.is_empty());
+    assert_eq!(decoded.timing, None);
+}
+
+#[test]
+fn server_and_client_share_the_generated_code() {
+    // One generated type, not two copies that happen to match
+    let request: murmure_server::server::murmure::TranscribeFileRequest =
+        murmure_client::proto::TranscribeFileRequest {
+            use_dictionary: true,
+            ..Default::default()
+        };
+    let response: murmure_client::proto::TranscribeFileResponse =
+        murmure_server::server::murmure::TranscribeFileResponse::default();
+    assert!(request.use_dictionary);
+    assert!(!response.success);
+}
@@ -0,0 +1,96 @@
+// Test harness: the real gRPC service on an ephemeral port, transcribing
+// with a mock engine so no model files are needed
+//
+// Each test file includes this module and uses what it needs of it.
+#![allow(dead_code)]
+
+use murmure_server::server::{self, RateLimiter, TranscriptionServiceImpl};
+use murmure_stt::config::ServerConfig;
+use murmure_stt::mock::MockEngine;
+use murmure_stt::TranscriptionService;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Channel, Server};
+
+use murmure::transcription_service_client::TranscriptionServiceClient;
+pub use murmure_proto as murmure;
+
+/// Sample rate of the generated fixtures, the one the engine runs at
+pub const SAMPLE_RATE: u32 = 16_000;
+
+/// A server running until dropped.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    pub service: Arc<TranscriptionService>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl TestServer {
+    /// Serve `engine` with the defaults of the server's configuration.
+    pub async fn start(engine: MockEngine) -> Self {
+        Self::with_config(ServerConfig::default(), engine).await
+    }
+
+    /// Serve `engine` with `config`, wired the way the binary wires it:
+    /// rate limiting interceptor, gzip and all.
+    pub async fn with_config(config: ServerConfig, engine: MockEngine) -> Self {
+        let service = Arc::new(
+            TranscriptionService::builder()
+                .config(config.clone())
+                .engine(move || engine.clone())
+                .build()
+                .expect("mock service builds without model files"),
+        );
+        let limiter = Arc::new(RateLimiter::from_config(&config));
+        let grpc = TranscriptionServiceImpl::new(Arc::clone(&service), Arc::clone(&limiter));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let (shutdown, stopped) = oneshot::channel();
+        tokio::spawn(
+            Server::builder()
+                .add_service(server::grpc_service(grpc, limiter))
+                .serve_with_incoming_shutdown(incoming, async {
+                    let _ = stopped.await;
+                }),
+        );
+        Self {
+            addr,
+            service,
+            shutdown: Some(shutdown),
+        }
+    }
+
+    pub async fn client(&self) -> TranscriptionServiceClient<Channel> {
+        TranscriptionServiceClient::connect(format!("http://{}", self.addr))
+            .await
+            .expect("test server accepts connections")
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+/// A 440 Hz tone lasting `secs`, as a 16 kHz mono 16-bit WAV.
+pub fn tone_wav(secs: f32) -> Vec<u8> {
+    let samples: Vec<f32> = (0..(secs * SAMPLE_RATE as f32) as usize)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / SAMPLE_RATE as f32).sin() * 0.3)
+        .collect();
+    murmure_stt::dsp::write_wav_bytes(&samples, SAMPLE_RATE).unwrap()
+}
+
+/// A TranscribeFile request for `audio` with every option left to the server.
+pub fn file_request(audio: Vec<u8>) -> murmure::TranscribeFileRequest {
+    murmure::TranscribeFileRequest {
+        audio_data: audio,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,89 @@
+// Debug dumps: only requests carrying the secret are dumped, and their dump
+// holds what the engine was given
+
+mod common;
+
+use std::path::Path;
+
+use common::{file_request, murmure, tone_wav, TestServer};
+use murmure_stt::config::ServerConfig;
+use murmure_stt::mock::MockEngine;
+use tonic::{Code, Request};
+
+const HEADER: &str = "x-murmure-debug-dump";
+
+fn config(dir: &Path) -> ServerConfig {
+    ServerConfig {
+        debug_dump_dir: Some(dir.to_path_buf()),
+        debug_dump_token: Some("secret".to_string()),
+        ..ServerConfig::default()
+    }
+}
+
+fn flagged(audio: Vec<u8>, token: &str) -> Request<murmure::TranscribeFileRequest> {
+    let mut request = Request::new(file_request(audio));
+    request
+        .metadata_mut()
+        .insert(HEADER, token.parse().unwrap());
+    request
+}
+
+fn entries(dir: &Path) -> usize {
+    std::fs::read_dir(dir).map_or(0, |entries| entries.count())
+}
+
+#[tokio::test]
+async fn dumps_flagged_requests_only() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::with_config(config(dir.path()), MockEngine::new("hello world")).await;
+    let mut client = server.client().await;
+    let audio = tone_wav(1.0);
+
+    let plain = client
+        .transcribe_file(file_request(audio.clone()))
+        .await
+        .unwrap();
+    assert!(plain.metadata().get(HEADER).is_none());
+    assert_eq!(entries(dir.path()), 0);
+
+    let response = client
+        .transcribe_file(flagged(audio.clone(), "secret"))
+        .await
+        .unwrap();
+    let name = response.metadata().get(HEADER).unwrap().to_str().unwrap();
+    let dump = dir.path().join(name);
+    assert_eq!(std::fs::read(dump.join("payload.wav")).unwrap(), audio);
+    assert!(dump.join("resampled.wav").is_file());
+    assert!(dump.join("speech.wav").is_file());
+    let pipeline: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(dump.join("pipeline.json")).unwrap()).unwrap();
+    assert_eq!(pipeline["text"], "hello world");
+    assert_eq!(pipeline["raw_text"][0][0], "hello world");
+    assert_eq!(entries(dir.path()), 1);
+}
+
+#[tokio::test]
+async fn rejects_a_wrong_secret() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = TestServer::with_config(config(dir.path()), MockEngine::new("hello world")).await;
+    let mut client = server.client().await;
+
+    let status = client
+        .transcribe_file(flagged(tone_wav(1.0), "guess"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+    assert_eq!(entries(dir.path()), 0);
+}
+
+#[tokio::test]
+async fn flagged_requests_fail_when_dumps_are_disabled() {
+    let server = TestServer::start(MockEngine::new("hello world")).await;
+    let mut client = server.client().await;
+
+    let status = client
+        .transcribe_file(flagged(tone_wav(1.0), "secret"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+}
@@ -0,0 +1,226 @@
+// Per-request dictionary correction: use_dictionary on files, StreamConfig
+// on streams, and CorrectText on text transcribed elsewhere
+
+mod common;
+
+use std::path::Path;
+
+use common::{file_request, murmure, tone_wav, TestServer};
+use murmure::transcribe_stream_request::RequestType;
+use murmure::transcribe_stream_response::ResponseType;
+use murmure::{StreamConfig, TranscribeStreamRequest};
+use murmure_stt::config::ServerConfig;
+use murmure_stt::mock::MockEngine;
+
+const RAW: &str = "murmur runs locally";
+const CORRECTED: &str = "Murmure runs locally";
+
+async fn server() -> TestServer {
+    let config = ServerConfig {
+        cc_rules_path: Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("../resources/cc-rules")),
+        dictionary: vec!["Murmure".to_string()],
+        ..ServerConfig::default()
+    };
+    TestServer::with_config(config, MockEngine::new(RAW)).await
+}
+
+#[tokio::test]
+async fn files_are_corrected_only_on_request() {
+    let server = server().await;
+    let mut client = server.client().await;
+
+    let raw = client
+        .transcribe_file(file_request(tone_wav(1.0)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(raw.text, RAW);
+    assert!(raw.corrections.is_empty());
+
+    let corrected = client
+        .transcribe_file(murmure::TranscribeFileRequest {
+            use_dictionary: true,
+            ..file_request(tone_wav(1.0))
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(corrected.text, CORRECTED);
+    assert_eq!(corrected.corrections.len(), 1);
+    assert_eq!(corrected.corrections[0].original, "murmur");
+    assert_eq!(corrected.corrections[0].replacement, "Murmure");
+}
+
+async fn stream_text(server: &TestServer, config: Option<StreamConfig>) -> String {
+    let mut client = server.client().await;
+    let requests: Vec<_> = config
+        .map(RequestType::Config)
+        .into_iter()
+        .chain([
+            RequestType::AudioChunk(tone_wav(1.0)),
+            RequestType::EndOfStream(true),
+        ])
+        .map(|request_type| TranscribeStreamRequest {
+            request_type: Some(request_type),
+        })
+        .collect();
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::FinalText(text)) = response.response_type {
+            return text;
+        }
+    }
+    panic!("the stream ended without a final text");
+}
+
+#[tokio::test]
+async fn streams_are_corrected_unless_disabled() {
+    let server = server().await;
+
+    assert_eq!(stream_text(&server, None).await, CORRECTED);
+    let disabled = StreamConfig {
+        use_dictionary: Some(false),
+        ..Default::default()
+    };
+    assert_eq!(stream_text(&server, Some(disabled)).await, RAW);
+}
+
+fn correct_request(text: &str, words: &[&str]) -> murmure::CorrectTextRequest {
+    murmure::CorrectTextRequest {
+        text: text.to_string(),
+        words: words.iter().map(|word| word.to_string()).collect(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn corrects_text_from_elsewhere() {
+    let server = server().await;
+    let mut client = server.client().await;
+
+    // The server's dictionary
+    let response = client
+        .correct_text(correct_request(RAW, &[]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.text, CORRECTED);
+    assert_eq!(response.corrections.len(), 1);
+    assert_eq!(response.corrections[0].original, "murmur");
+
+    // A word list of the request's own replaces it
+    let response = client
+        .correct_text(correct_request("murmur runs parakit", &["Parakeet"]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.text, "murmur runs Parakeet");
+
+    // Corrected text stays as it is
+    let again = client
+        .correct_text(correct_request(CORRECTED, &[]))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(again.text, CORRECTED);
+    assert!(again.corrections.is_empty());
+}
+
+#[tokio::test]
+async fn correct_text_rejects_empty_words() {
+    let server = server().await;
+    let mut client = server.client().await;
+
+    let status = client
+        .correct_text(correct_request(RAW, &["Murmure", " "]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn server_info_reports_the_dictionary_status() {
+    let status = |server: TestServer| async move {
+        let mut client = server.client().await;
+        client
+            .get_server_info(murmure::GetServerInfoRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .dictionary_status
+    };
+    assert_eq!(status(server().await).await, "active");
+    assert_eq!(
+        status(TestServer::start(MockEngine::new(RAW)).await).await,
+        "off"
+    );
+
+    let degraded = ServerConfig {
+        cc_rules_path: Some("/nonexistent/cc-rules".into()),
+        dictionary: vec!["Murmure".to_string()],
+        allow_missing_cc_rules: true,
+        ..ServerConfig::default()
+    };
+    let server = TestServer::with_config(degraded, MockEngine::new(RAW)).await;
+    assert_eq!(status(server).await, "degraded");
+}
+
+#[tokio::test]
+async fn requests_select_the_dictionary_of_their_language() {
+    let config = ServerConfig {
+        cc_rules_path: Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("../resources/cc-rules")),
+        dictionary: vec!["Murmure".to_string()],
+        dictionaries: [("fr".to_string(), vec!["Kubernetes".to_string()])].into(),
+        ..ServerConfig::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new(RAW)).await;
+    let mut client = server.client().await;
+
+    let info = client
+        .get_server_info(murmure::GetServerInfoRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(info.dictionary_languages, ["fr"]);
+
+    for (language, expected) in [("", CORRECTED), ("fr-CA", RAW), ("de", CORRECTED)] {
+        let file = client
+            .transcribe_file(murmure::TranscribeFileRequest {
+                use_dictionary: true,
+                language: language.to_string(),
+                ..file_request(tone_wav(1.0))
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(file.text, expected, "{:?}", language);
+
+        let text = client
+            .correct_text(murmure::CorrectTextRequest {
+                language: language.to_string(),
+                ..correct_request(RAW, &[])
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(text.text, expected, "{:?}", language);
+    }
+
+    let status = client
+        .correct_text(murmure::CorrectTextRequest {
+            language: "not a language".to_string(),
+            ..correct_request(RAW, &[])
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    assert!(
+        status.message().contains("'not a language'"),
+        "{}",
+        status.message()
+    );
+}
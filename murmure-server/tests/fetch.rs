@@ -0,0 +1,155 @@
+// TranscribeUrl against a local HTTP server: allowed prefixes, redirects
+// and the size cap
+
+mod common;
+
+use std::net::SocketAddr;
+
+use axum::body::Body;
+use axum::http::header;
+use axum::response::{IntoResponse, Redirect};
+use axum::routing::get;
+use axum::Router;
+use common::{murmure, tone_wav, TestServer};
+use murmure_stt::config::ServerConfig;
+use murmure_stt::mock::MockEngine;
+use tonic::Code;
+
+/// Size cap of the test server, between the clip and the large file
+const MAX_UPLOAD_BYTES: u64 = 100_000;
+
+fn wav(bytes: Vec<u8>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "audio/wav")], bytes)
+}
+
+/// An HTTP server with audio under /audio and elsewhere.
+async fn audio_host() -> SocketAddr {
+    let clip = tone_wav(1.0);
+    let large = tone_wav(5.0);
+    assert!(clip.len() as u64 <= MAX_UPLOAD_BYTES && large.len() as u64 > MAX_UPLOAD_BYTES);
+    let app = Router::new()
+        .route(
+            "/audio/clip.wav",
+            get({
+                let clip = clip.clone();
+                || async move { wav(clip) }
+            }),
+        )
+        .route(
+            "/audio-private/clip.wav",
+            get({
+                let clip = clip.clone();
+                || async move { wav(clip) }
+            }),
+        )
+        .route("/private/clip.wav", get(|| async move { wav(clip) }))
+        .route(
+            "/audio/large.wav",
+            get({
+                let large = large.clone();
+                || async move { wav(large) }
+            }),
+        )
+        // Without a Content-Length, so the cap applies while reading
+        .route(
+            "/audio/streamed.wav",
+            get(|| async move {
+                let chunks: Vec<Result<Vec<u8>, std::io::Error>> =
+                    large.chunks(8192).map(|chunk| Ok(chunk.to_vec())).collect();
+                Body::from_stream(futures_util::stream::iter(chunks))
+            }),
+        )
+        .route(
+            "/audio/moved.wav",
+            get(|| async { Redirect::temporary("/audio/clip.wav") }),
+        )
+        .route(
+            "/audio/escape.wav",
+            get(|| async { Redirect::temporary("/private/clip.wav") }),
+        )
+        .route(
+            "/audio/loop.wav",
+            get(|| async { Redirect::temporary("/audio/loop.wav") }),
+        );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    addr
+}
+
+async fn fetch(server: &TestServer, url: String) -> Result<String, tonic::Status> {
+    let mut client = server.client().await;
+    let request = murmure::TranscribeUrlRequest { url, options: None };
+    Ok(client.transcribe_url(request).await?.into_inner().text)
+}
+
+async fn setup() -> (TestServer, SocketAddr) {
+    let host = audio_host().await;
+    let config = ServerConfig {
+        url_allowlist: vec![format!("http://{}/audio", host)],
+        max_upload_bytes: MAX_UPLOAD_BYTES,
+        url_max_redirects: 3,
+        ..ServerConfig::default()
+    };
+    (
+        TestServer::with_config(config, MockEngine::new("fetched")).await,
+        host,
+    )
+}
+
+#[tokio::test]
+async fn fetches_urls_under_a_prefix() {
+    let (server, host) = setup().await;
+    let text = fetch(
+        &server,
+        format!("http://{}/audio/clip.wav?sig=secret", host),
+    )
+    .await;
+    assert_eq!(text.unwrap(), "fetched");
+
+    for path in ["audio-private/clip.wav", "private/clip.wav"] {
+        let status = fetch(&server, format!("http://{}/{}", host, path))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied, "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn redirects_must_stay_under_a_prefix() {
+    let (server, host) = setup().await;
+    let text = fetch(&server, format!("http://{}/audio/moved.wav", host)).await;
+    assert_eq!(text.unwrap(), "fetched");
+
+    let status = fetch(&server, format!("http://{}/audio/escape.wav", host))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+    assert!(
+        status.message().contains("not allowed"),
+        "{}",
+        status.message()
+    );
+
+    let status = fetch(&server, format!("http://{}/audio/loop.wav", host))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn bodies_over_the_cap_are_refused() {
+    let (server, host) = setup().await;
+    for path in ["large.wav", "streamed.wav"] {
+        let status = fetch(&server, format!("http://{}/audio/{}", host, path))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::FailedPrecondition, "{}", path);
+        assert!(
+            status.message().contains("byte limit"),
+            "{}",
+            status.message()
+        );
+    }
+}
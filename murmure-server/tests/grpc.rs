@@ -0,0 +1,735 @@
+// The gRPC service end to end, on the mock engine
+
+mod common;
+
+use common::{file_request, murmure, tone_wav, TestServer};
+use murmure::transcribe_stream_request::RequestType;
+use murmure::transcribe_stream_response::ResponseType;
+use murmure::TranscribeStreamRequest;
+use murmure_stt::mock::MockEngine;
+use tonic::Code;
+
+#[tokio::test]
+async fn transcribes_a_file() {
+    let server = TestServer::start(MockEngine::new("hello world")).await;
+    let mut client = server.client().await;
+
+    let response = client
+        .transcribe_file(file_request(tone_wav(1.0)))
+        .await
+        .unwrap();
+    assert!(response
+        .metadata()
+        .get("x-murmure-dictionary-revision")
+        .is_some());
+    let response = response.into_inner();
+    assert!(response.success);
+    assert_eq!(response.text, "hello world");
+    assert!((response.audio_duration_secs - 1.0).abs() < 0.01);
+    assert_eq!(response.input_sample_rate, common::SAMPLE_RATE);
+}
+
+#[tokio::test]
+async fn canned_text_follows_the_audio() {
+    let engine = MockEngine::new("anything else").on_duration(1500, "one and a half seconds");
+    let server = TestServer::start(engine).await;
+    let mut client = server.client().await;
+
+    let long = client
+        .transcribe_file(file_request(tone_wav(1.5)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(long.text, "one and a half seconds");
+    let short = client
+        .transcribe_file(file_request(tone_wav(0.5)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(short.text, "anything else");
+}
+
+#[tokio::test]
+async fn rejects_audio_that_is_not_wav() {
+    let server = TestServer::start(MockEngine::new("unused")).await;
+    let mut client = server.client().await;
+
+    let status = client
+        .transcribe_file(file_request(b"definitely not a wav file".to_vec()))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn streams_an_utterance_in_chunks() {
+    let server = TestServer::start(MockEngine::new("streamed text")).await;
+    let mut client = server.client().await;
+
+    let wav = tone_wav(1.0);
+    let mut requests: Vec<_> = wav
+        .chunks(4096)
+        .map(|chunk| TranscribeStreamRequest {
+            request_type: Some(RequestType::AudioChunk(chunk.to_vec())),
+        })
+        .collect();
+    requests.push(TranscribeStreamRequest {
+        request_type: Some(RequestType::EndOfStream(true)),
+    });
+
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut finals = Vec::new();
+    while let Some(response) = responses.message().await.unwrap() {
+        match response.response_type {
+            Some(ResponseType::FinalText(text)) => finals.push(text),
+            Some(ResponseType::Error(message)) => panic!("stream failed: {}", message),
+            _ => {}
+        }
+    }
+    assert_eq!(finals, ["streamed text"]);
+}
+
+#[tokio::test]
+async fn streams_several_utterances() {
+    let server = TestServer::start(MockEngine::new("utterance")).await;
+    let mut client = server.client().await;
+
+    let requests = vec![
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::AudioChunk(tone_wav(1.0))),
+        },
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::UtteranceBoundary(Default::default())),
+        },
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::AudioChunk(tone_wav(1.0))),
+        },
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::EndOfStream(true)),
+        },
+    ];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut finals = Vec::new();
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::FinalText(_)) = response.response_type {
+            finals.push(response.utterance_id);
+        }
+    }
+    assert_eq!(finals, [0, 1]);
+}
+
+#[tokio::test]
+async fn reports_duration_and_processing_time() {
+    let engine = MockEngine::new("timed").with_latency(std::time::Duration::from_millis(100));
+    let server = TestServer::start(engine).await;
+    let mut client = server.client().await;
+
+    let response = client
+        .transcribe_file(file_request(tone_wav(2.0)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!((response.audio_duration_secs - 2.0).abs() < 0.01);
+    assert!(
+        response.processing_time_ms >= 100,
+        "{}",
+        response.processing_time_ms
+    );
+    let expected = response.audio_duration_secs * 1000.0 / response.processing_time_ms as f32;
+    assert!((response.realtime_factor - expected).abs() / expected < 0.1);
+
+    let requests = vec![
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::AudioChunk(tone_wav(1.0))),
+        },
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::EndOfStream(true)),
+        },
+    ];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut finals = 0;
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::FinalText(_)) = response.response_type {
+            finals += 1;
+            assert!((response.audio_duration_secs - 1.0).abs() < 0.01);
+            assert!(response.processing_time_ms >= 100);
+            assert!(response.realtime_factor > 0.0);
+        }
+    }
+    assert_eq!(finals, 1);
+}
+
+#[tokio::test]
+async fn segments_at_long_pauses() {
+    let engine = MockEngine::new("unused")
+        .on_duration(1500, "first part")
+        .on_duration(1000, "second part");
+    let server = TestServer::start(engine).await;
+    let mut client = server.client().await;
+
+    // One second of speech, one of silence, half a second of speech: the
+    // cut falls in the middle of the pause
+    let rate = common::SAMPLE_RATE as usize;
+    let mut samples: Vec<f32> = (0..rate).map(|i| (i as f32 * 0.1).sin() * 0.3).collect();
+    samples.extend(std::iter::repeat(0.0).take(rate));
+    samples.extend((0..rate / 2).map(|i| (i as f32 * 0.1).sin() * 0.3));
+    let audio = murmure_stt::dsp::write_wav_bytes(&samples, common::SAMPLE_RATE).unwrap();
+
+    let response = client
+        .transcribe_file(murmure::TranscribeFileRequest {
+            segment_on_silence: true,
+            ..file_request(audio.clone())
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let segments: Vec<_> = response
+        .segments
+        .iter()
+        .map(|s| (s.start, s.end, s.text.as_str()))
+        .collect();
+    assert_eq!(
+        segments,
+        [(0.0, 1.5, "first part"), (1.5, 2.5, "second part")]
+    );
+    assert!(response.text.contains("first part"));
+    assert!(response.text.contains("second part"));
+
+    let unsegmented = client
+        .transcribe_file(file_request(audio))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(unsegmented.segments.is_empty());
+    assert_eq!(unsegmented.text, "unused");
+}
+
+#[tokio::test]
+async fn speech_stats_on_request() {
+    let server = TestServer::start(MockEngine::new("four words per second")).await;
+    let mut client = server.client().await;
+
+    let plain = client
+        .transcribe_file(file_request(tone_wav(1.0)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(plain.stats.is_none());
+
+    let response = client
+        .transcribe_file(murmure::TranscribeFileRequest {
+            include_stats: true,
+            ..file_request(tone_wav(1.0))
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let stats = response.stats.expect("stats were requested");
+    assert_eq!(stats.word_count, 4);
+    assert!((stats.voiced_secs - 1.0).abs() < 0.01, "{:?}", stats);
+    assert!((stats.words_per_minute - 240.0).abs() < 1.0, "{:?}", stats);
+    assert!(stats.silence_percent < 1.0);
+}
+
+#[tokio::test]
+async fn selects_models_by_name() {
+    let spec = |name: &str| murmure_stt::ModelSpec {
+        name: name.to_string(),
+        path: format!("/nonexistent/models/{}", name).into(),
+        precision: Default::default(),
+    };
+    let config = murmure_stt::config::ServerConfig {
+        models: vec![spec("fast"), spec("accurate")],
+        ..Default::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("from fast")).await;
+    let mut client = server.client().await;
+
+    let response = client
+        .transcribe_file(murmure::TranscribeFileRequest {
+            model: "fast".to_string(),
+            ..file_request(tone_wav(1.0))
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.text, "from fast");
+
+    let status = client
+        .transcribe_file(murmure::TranscribeFileRequest {
+            model: "nope".to_string(),
+            ..file_request(tone_wav(1.0))
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(
+        status.message().contains("fast, accurate"),
+        "{}",
+        status.message()
+    );
+
+    let models = client
+        .list_models(murmure::ListModelsRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .models;
+    let listed: Vec<_> = models
+        .iter()
+        .map(|model| (model.name.as_str(), model.is_default, model.loaded))
+        .collect();
+    assert_eq!(listed, [("fast", true, true), ("accurate", false, false)]);
+}
+
+#[tokio::test]
+async fn stage_timings_on_request() {
+    let engine = MockEngine::new("timed").with_latency(std::time::Duration::from_millis(50));
+    let server = TestServer::start(engine).await;
+    let mut client = server.client().await;
+
+    let plain = client
+        .transcribe_file(file_request(tone_wav(1.0)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(plain.timing.is_none());
+
+    let response = client
+        .transcribe_file(murmure::TranscribeFileRequest {
+            include_timing: true,
+            ..file_request(tone_wav(1.0))
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let timing = response.timing.expect("timing was requested");
+    assert!(timing.inference_ms >= 50.0, "{:?}", timing);
+    assert!(timing.serialization_ms > 0.0, "{:?}", timing);
+    let sum = timing.decode_ms
+        + timing.resample_ms
+        + timing.preprocess_ms
+        + timing.vad_ms
+        + timing.inference_ms
+        + timing.dictionary_ms
+        + timing.post_process_ms
+        + timing.serialization_ms;
+    assert!(sum <= timing.total_ms + 0.01, "{:?}", timing);
+    assert!(timing.total_ms - sum < 25.0, "{:?}", timing);
+}
+
+#[tokio::test]
+async fn refuses_uploads_the_disk_cannot_hold() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = murmure_stt::config::ServerConfig {
+        temp_dir: dir.path().to_path_buf(),
+        min_free_disk_mb: 1,
+        max_upload_bytes: 0,
+        ..Default::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("unused")).await;
+    let mut client = server.client().await;
+
+    let start = murmure::TranscribeLargeFileRequest {
+        request_type: Some(murmure::transcribe_large_file_request::RequestType::Start(
+            murmure::UploadStart {
+                total_bytes: u64::MAX / 2,
+                ..Default::default()
+            },
+        )),
+    };
+    let status = client
+        .transcribe_large_file(tokio_stream::iter(vec![start]))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert!(
+        status.message().contains("not enough disk space"),
+        "{:?}",
+        status
+    );
+}
+
+#[tokio::test]
+async fn enforces_the_duration_limit_on_files() {
+    let config = murmure_stt::config::ServerConfig {
+        max_audio_secs: 2,
+        ..Default::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("unused")).await;
+    let mut client = server.client().await;
+
+    let status = client
+        .transcribe_file(file_request(tone_wav(3.0)))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(
+        status.message().contains("exceeding the 2 s limit"),
+        "{:?}",
+        status
+    );
+}
+
+#[tokio::test]
+async fn caps_streams_the_duration_limit_cannot_measure() {
+    let config = murmure_stt::config::ServerConfig {
+        max_upload_bytes: 100_000,
+        ..Default::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("unused")).await;
+    let mut client = server.client().await;
+
+    // No WAV header ever arrives, so no duration can be measured
+    let (requests, outgoing) = tokio::sync::mpsc::channel(64);
+    let mut responses = client
+        .transcribe_stream(tokio_stream::wrappers::ReceiverStream::new(outgoing))
+        .await
+        .unwrap()
+        .into_inner();
+    for _ in 0..20 {
+        let request = TranscribeStreamRequest {
+            request_type: Some(RequestType::AudioChunk(vec![0; 10_000])),
+        };
+        if requests.send(request).await.is_err() {
+            break;
+        }
+    }
+
+    let status = loop {
+        match responses.message().await {
+            Ok(Some(_)) => continue,
+            Ok(None) => panic!("stream ended without an error"),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(
+        status.message().contains("exceeds the 100000 byte limit"),
+        "{:?}",
+        status
+    );
+    drop(requests);
+}
+
+#[tokio::test]
+async fn enforces_the_duration_limit_while_streaming() {
+    let config = murmure_stt::config::ServerConfig {
+        max_audio_secs: 2,
+        ..Default::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("unused")).await;
+    let mut client = server.client().await;
+
+    // Ten seconds of audio, without EndOfStream: the server has to stop
+    // the stream on its own once the limit is passed
+    let (requests, outgoing) = tokio::sync::mpsc::channel(64);
+    let mut responses = client
+        .transcribe_stream(tokio_stream::wrappers::ReceiverStream::new(outgoing))
+        .await
+        .unwrap()
+        .into_inner();
+    for chunk in tone_wav(10.0).chunks(16_000) {
+        let request = TranscribeStreamRequest {
+            request_type: Some(RequestType::AudioChunk(chunk.to_vec())),
+        };
+        if requests.send(request).await.is_err() {
+            break;
+        }
+    }
+
+    let status = loop {
+        match responses.message().await {
+            Ok(Some(_)) => continue,
+            Ok(None) => panic!("stream ended without an error"),
+            Err(status) => break status,
+        }
+    };
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(
+        status.message().contains("exceeding the 2 s limit"),
+        "{:?}",
+        status
+    );
+    drop(requests);
+}
+
+#[tokio::test]
+async fn reports_voice_commands() {
+    let config = murmure_stt::config::ServerConfig {
+        commands: vec![murmure_stt::VoiceCommand {
+            phrase: "new line".to_string(),
+            action: "newline".to_string(),
+        }],
+        ..Default::default()
+    };
+    let engine = MockEngine::new("add a new line after the title").on_duration(500, "New line.");
+    let server = TestServer::with_config(config, engine).await;
+    let mut client = server.client().await;
+
+    let response = client
+        .transcribe_file(file_request(tone_wav(0.5)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.text, "New line.");
+    let command = response.detected_command.expect("the text is a command");
+    assert_eq!(command.action, "newline");
+    assert_eq!(command.phrase, "new line");
+
+    let dictation = client
+        .transcribe_file(file_request(tone_wav(1.0)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(dictation.detected_command.is_none());
+
+    // Commands of a stream replace the configured ones
+    let requests = vec![
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::Config(murmure::StreamConfig {
+                commands: vec![murmure::VoiceCommand {
+                    phrase: "new line".to_string(),
+                    action: "line_break".to_string(),
+                }],
+                ..Default::default()
+            })),
+        },
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::AudioChunk(tone_wav(0.5))),
+        },
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::EndOfStream(true)),
+        },
+    ];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut actions = Vec::new();
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::FinalText(_)) = response.response_type {
+            actions.extend(response.detected_command.map(|command| command.action));
+        }
+    }
+    assert_eq!(actions, ["line_break"]);
+}
+
+#[tokio::test]
+async fn server_info_summarizes_model_availability() {
+    let dir = tempfile::tempdir().unwrap();
+    let model_dir = dir.path().join("half-copied");
+    std::fs::create_dir(&model_dir).unwrap();
+    std::fs::write(model_dir.join("vocab.txt"), b"").unwrap();
+    let config = murmure_stt::config::ServerConfig {
+        model_path: Some(model_dir.clone()),
+        ..Default::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("ok")).await;
+    let mut client = server.client().await;
+
+    let info = client
+        .get_server_info(murmure::GetServerInfoRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(info.model_status.len(), 1);
+    let status = &info.model_status[0];
+    assert!(
+        status.starts_with(&format!("half-copied: {} is missing", model_dir.display())),
+        "{}",
+        status
+    );
+    assert!(status.contains("nemo128.onnx"), "{}", status);
+    assert!(!status.contains("vocab.txt"), "{}", status);
+}
+
+#[tokio::test]
+async fn applies_inference_options() {
+    let config = murmure_stt::config::ServerConfig {
+        decoding_blank_penalty: 0.5,
+        decoding_max_tokens: 3,
+        ..Default::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("one two three four")).await;
+    let mut client = server.client().await;
+
+    let info = client
+        .get_server_info(murmure::GetServerInfoRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        info.inference_defaults,
+        Some(murmure::InferenceOptions {
+            blank_penalty: Some(0.5),
+            max_symbols_per_step: Some(10),
+            max_tokens: Some(3),
+        })
+    );
+
+    let transcribe = |inference: Option<murmure::InferenceOptions>| {
+        let mut client = client.clone();
+        async move {
+            client
+                .transcribe_file(murmure::TranscribeFileRequest {
+                    inference,
+                    ..file_request(tone_wav(1.0))
+                })
+                .await
+        }
+    };
+    // Omitted: the server's limit
+    assert_eq!(
+        transcribe(None).await.unwrap().into_inner().text,
+        "one two three"
+    );
+    let limited = murmure::InferenceOptions {
+        max_tokens: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(
+        transcribe(Some(limited)).await.unwrap().into_inner().text,
+        "one two"
+    );
+    // 0 lifts the limit
+    let unlimited = murmure::InferenceOptions {
+        max_tokens: Some(0),
+        ..Default::default()
+    };
+    assert_eq!(
+        transcribe(Some(unlimited)).await.unwrap().into_inner().text,
+        "one two three four"
+    );
+
+    for (inference, message) in [
+        (
+            murmure::InferenceOptions {
+                blank_penalty: Some(11.0),
+                ..Default::default()
+            },
+            "blank_penalty must be between -10 and 10, got 11",
+        ),
+        (
+            murmure::InferenceOptions {
+                max_symbols_per_step: Some(0),
+                ..Default::default()
+            },
+            "max_symbols_per_step must be between 1 and 32, got 0",
+        ),
+    ] {
+        let status = transcribe(Some(inference)).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), message);
+    }
+
+    // And on streams, through their config
+    let requests = vec![
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::Config(murmure::StreamConfig {
+                inference: Some(limited),
+                ..Default::default()
+            })),
+        },
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::AudioChunk(tone_wav(1.0))),
+        },
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::EndOfStream(true)),
+        },
+    ];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut finals = Vec::new();
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::FinalText(text)) = response.response_type {
+            finals.push(text);
+        }
+    }
+    assert_eq!(finals, ["one two"]);
+}
+
+#[tokio::test]
+async fn reports_the_detected_language() {
+    let config = murmure_stt::config::ServerConfig {
+        detect_languages: vec!["en".to_string(), "fr-CA".to_string()],
+        ..Default::default()
+    };
+    let server = TestServer::with_config(
+        config,
+        MockEngine::new("nous avons mis les fichiers dans le dossier"),
+    )
+    .await;
+    let mut client = server.client().await;
+
+    let response = client
+        .transcribe_file(file_request(tone_wav(1.0)))
+        .await
+        .unwrap()
+        .into_inner();
+    let detected = response.detected_language.unwrap();
+    assert_eq!(detected.language, "fr-CA");
+    assert!(detected.confidence > 0.5 && detected.confidence <= 1.0);
+
+    let requests = vec![
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::AudioChunk(tone_wav(1.0))),
+        },
+        TranscribeStreamRequest {
+            request_type: Some(RequestType::EndOfStream(true)),
+        },
+    ];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut languages = Vec::new();
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::FinalText(_)) = response.response_type {
+            languages.extend(response.detected_language.map(|detected| detected.language));
+        }
+    }
+    assert_eq!(languages, ["fr-CA"]);
+}
+
+#[tokio::test]
+async fn reports_both_passes_of_two_pass_requests() {
+    let server = TestServer::start(MockEngine::new("hello world").with_confidence(0.4)).await;
+    let mut client = server.client().await;
+
+    let single = client
+        .transcribe_file(file_request(tone_wav(1.0)))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(single.two_pass, None);
+
+    let request = murmure::TranscribeFileRequest {
+        two_pass: true,
+        ..file_request(tone_wav(1.0))
+    };
+    let response = client.transcribe_file(request).await.unwrap().into_inner();
+    assert_eq!(response.text, "hello world");
+    let two_pass = response.two_pass.unwrap();
+    // Equally unsure: the first pass is kept
+    assert!((two_pass.first_confidence.unwrap() - 0.4).abs() < 1e-4);
+    assert!((two_pass.second_confidence.unwrap() - 0.4).abs() < 1e-4);
+    assert!(!two_pass.second_pass_won);
+}
@@ -0,0 +1,74 @@
+// Idempotency keys on TranscribeFile: replays for API key clients, keys
+// ignored for clients known by their address
+
+mod common;
+
+use common::{file_request, tone_wav, TestServer};
+use murmure_stt::config::{RateLimitOverride, ServerConfig};
+use murmure_stt::mock::MockEngine;
+use tonic::{Code, Request};
+
+const REPLAY_HEADER: &str = "x-murmure-idempotent-replay";
+
+fn config() -> ServerConfig {
+    ServerConfig {
+        rate_limit_overrides: vec![RateLimitOverride {
+            key: "team-key".to_string(),
+            requests_per_minute: None,
+            audio_secs: None,
+            monthly_audio_secs: None,
+        }],
+        ..ServerConfig::default()
+    }
+}
+
+fn keyed<T>(message: T, api_key: Option<&str>, idempotency_key: &str) -> Request<T> {
+    let mut request = Request::new(message);
+    let metadata = request.metadata_mut();
+    if let Some(api_key) = api_key {
+        metadata.insert("x-api-key", api_key.parse().unwrap());
+    }
+    metadata.insert("idempotency-key", idempotency_key.parse().unwrap());
+    request
+}
+
+#[tokio::test]
+async fn api_key_clients_get_replays() {
+    let server = TestServer::with_config(config(), MockEngine::new("once")).await;
+    let mut client = server.client().await;
+    let audio = tone_wav(1.0);
+
+    let first = client
+        .transcribe_file(keyed(file_request(audio.clone()), Some("team-key"), "k1"))
+        .await
+        .unwrap();
+    assert!(first.metadata().get(REPLAY_HEADER).is_none());
+    let retry = client
+        .transcribe_file(keyed(file_request(audio.clone()), Some("team-key"), "k1"))
+        .await
+        .unwrap();
+    assert_eq!(retry.metadata().get(REPLAY_HEADER).unwrap(), "true");
+    assert_eq!(retry.into_inner().text, "once");
+
+    // Same key, different options
+    let mut request = file_request(audio);
+    request.use_dictionary = true;
+    let status = client
+        .transcribe_file(keyed(request, Some("team-key"), "k1"))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn address_only_clients_are_never_replayed() {
+    let server = TestServer::with_config(config(), MockEngine::new("each time")).await;
+    let mut client = server.client().await;
+    for audio in [tone_wav(1.0), tone_wav(2.0)] {
+        let response = client
+            .transcribe_file(keyed(file_request(audio), None, "shared"))
+            .await
+            .unwrap();
+        assert!(response.metadata().get(REPLAY_HEADER).is_none());
+    }
+}
@@ -0,0 +1,253 @@
+// Background jobs as clients see them: polling, ownership, cancellation,
+// the queue limit and restarts
+
+mod common;
+
+use std::time::Duration;
+
+use common::{murmure, tone_wav, TestServer};
+use murmure::transcription_service_client::TranscriptionServiceClient;
+use murmure::JobState;
+use murmure_stt::config::{RateLimitOverride, ServerConfig};
+use murmure_stt::mock::MockEngine;
+use tonic::transport::Channel;
+use tonic::{Code, Request};
+
+const OWNER_KEY: &str = "owner-key";
+const OTHER_KEY: &str = "other-key";
+
+fn config() -> ServerConfig {
+    let key = |key: &str| RateLimitOverride {
+        key: key.to_string(),
+        requests_per_minute: None,
+        audio_secs: None,
+        monthly_audio_secs: None,
+    };
+    ServerConfig {
+        job_workers: 1,
+        rate_limit_overrides: vec![key(OWNER_KEY), key(OTHER_KEY)],
+        ..ServerConfig::default()
+    }
+}
+
+/// `message` sent with the `x-api-key` of `key`.
+fn as_client<T>(key: &str, message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    request
+        .metadata_mut()
+        .insert("x-api-key", key.parse().unwrap());
+    request
+}
+
+async fn submit(client: &mut TranscriptionServiceClient<Channel>) -> Result<String, tonic::Status> {
+    let request = murmure::SubmitTranscriptionJobRequest {
+        audio_data: tone_wav(1.0),
+        options: None,
+    };
+    Ok(client
+        .submit_transcription_job(as_client(OWNER_KEY, request))
+        .await?
+        .into_inner()
+        .job_id)
+}
+
+async fn state(client: &mut TranscriptionServiceClient<Channel>, job_id: &str) -> JobState {
+    let request = murmure::GetJobStatusRequest {
+        job_id: job_id.to_string(),
+    };
+    client
+        .get_job_status(as_client(OWNER_KEY, request))
+        .await
+        .unwrap()
+        .into_inner()
+        .state()
+}
+
+/// Poll until the job leaves `from`, for up to five seconds.
+async fn wait_past(
+    client: &mut TranscriptionServiceClient<Channel>,
+    job_id: &str,
+    from: &[JobState],
+) -> JobState {
+    for _ in 0..500 {
+        let state = state(client, job_id).await;
+        if !from.contains(&state) {
+            return state;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("job {} stayed in {:?}", job_id, from);
+}
+
+async fn result(
+    client: &mut TranscriptionServiceClient<Channel>,
+    key: &str,
+    job_id: &str,
+) -> Result<String, tonic::Status> {
+    let request = murmure::GetJobResultRequest {
+        job_id: job_id.to_string(),
+    };
+    Ok(client
+        .get_job_result(as_client(key, request))
+        .await?
+        .into_inner()
+        .text)
+}
+
+#[tokio::test]
+async fn submitted_jobs_are_polled_to_their_transcript() {
+    let server = TestServer::with_config(config(), MockEngine::new("in the background")).await;
+    let mut client = server.client().await;
+
+    let job_id = submit(&mut client).await.unwrap();
+    assert!(job_id.starts_with("job-"), "{}", job_id);
+    let done = wait_past(&mut client, &job_id, &[JobState::Queued, JobState::Running]).await;
+    assert_eq!(done, JobState::Done);
+    assert_eq!(
+        result(&mut client, OWNER_KEY, &job_id).await.unwrap(),
+        "in the background"
+    );
+}
+
+#[tokio::test]
+async fn jobs_are_hidden_from_other_clients() {
+    let server = TestServer::with_config(config(), MockEngine::new("private")).await;
+    let mut client = server.client().await;
+    let job_id = submit(&mut client).await.unwrap();
+    wait_past(&mut client, &job_id, &[JobState::Queued, JobState::Running]).await;
+
+    let status = client
+        .get_job_status(as_client(
+            OTHER_KEY,
+            murmure::GetJobStatusRequest {
+                job_id: job_id.clone(),
+            },
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    let status = result(&mut client, OTHER_KEY, &job_id).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    // Without a key the client is its address, another identity again
+    let status = client
+        .get_job_result(murmure::GetJobResultRequest {
+            job_id: job_id.clone(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    let status = client
+        .cancel_job(as_client(
+            OTHER_KEY,
+            murmure::CancelJobRequest {
+                job_id: job_id.clone(),
+            },
+        ))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    assert_eq!(
+        result(&mut client, OWNER_KEY, &job_id).await.unwrap(),
+        "private"
+    );
+}
+
+#[tokio::test]
+async fn cancelling_a_running_job_discards_its_result() {
+    let engine = MockEngine::new("discarded").with_latency(Duration::from_millis(300));
+    let server = TestServer::with_config(config(), engine).await;
+    let mut client = server.client().await;
+
+    let job_id = submit(&mut client).await.unwrap();
+    assert_eq!(
+        wait_past(&mut client, &job_id, &[JobState::Queued]).await,
+        JobState::Running
+    );
+    let cancelled = client
+        .cancel_job(as_client(
+            OWNER_KEY,
+            murmure::CancelJobRequest {
+                job_id: job_id.clone(),
+            },
+        ))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(cancelled.state(), JobState::Cancelled);
+
+    // Still cancelled once the engine is done with it
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(state(&mut client, &job_id).await, JobState::Cancelled);
+    let status = result(&mut client, OWNER_KEY, &job_id).await.unwrap_err();
+    assert_eq!(status.code(), Code::FailedPrecondition);
+}
+
+#[tokio::test]
+async fn a_full_queue_rejects_submissions() {
+    let config = ServerConfig {
+        job_queue_depth: 1,
+        ..config()
+    };
+    let engine = MockEngine::new("slow").with_latency(Duration::from_millis(300));
+    let server = TestServer::with_config(config, engine).await;
+    let mut client = server.client().await;
+
+    let running = submit(&mut client).await.unwrap();
+    wait_past(&mut client, &running, &[JobState::Queued]).await;
+    submit(&mut client).await.unwrap();
+    let status = submit(&mut client).await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+}
+
+#[tokio::test]
+async fn done_jobs_survive_a_restart_with_their_owner() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = ServerConfig {
+        job_dir: Some(dir.path().to_path_buf()),
+        ..config()
+    };
+
+    let job_id = {
+        let server = TestServer::with_config(config.clone(), MockEngine::new("kept")).await;
+        let mut client = server.client().await;
+        let job_id = submit(&mut client).await.unwrap();
+        wait_past(&mut client, &job_id, &[JobState::Queued, JobState::Running]).await;
+        job_id
+    };
+
+    let server = TestServer::with_config(config, MockEngine::new("unused")).await;
+    let mut client = server.client().await;
+    assert_eq!(
+        result(&mut client, OWNER_KEY, &job_id).await.unwrap(),
+        "kept"
+    );
+    let status = result(&mut client, OTHER_KEY, &job_id).await.unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn unreadable_job_files_do_not_stop_the_restore() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = ServerConfig {
+        job_dir: Some(dir.path().to_path_buf()),
+        ..config()
+    };
+
+    let job_id = {
+        let server = TestServer::with_config(config.clone(), MockEngine::new("kept")).await;
+        let mut client = server.client().await;
+        let job_id = submit(&mut client).await.unwrap();
+        wait_past(&mut client, &job_id, &[JobState::Queued, JobState::Running]).await;
+        job_id
+    };
+    // Can't be read as a file
+    std::fs::create_dir(dir.path().join("job-broken.done")).unwrap();
+
+    let server = TestServer::with_config(config, MockEngine::new("unused")).await;
+    let mut client = server.client().await;
+    assert_eq!(
+        result(&mut client, OWNER_KEY, &job_id).await.unwrap(),
+        "kept"
+    );
+}
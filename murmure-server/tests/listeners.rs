@@ -0,0 +1,141 @@
+// Several gRPC listeners serving one service: plaintext and TLS side by
+// side, a listener that can't start failing startup by name, and one
+// shutdown for all
+//
+// The certificates in fixtures/ are a test CA and a `localhost` certificate
+// it signed, valid for a century.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use common::tone_wav;
+use murmure_client::{ClientOptions, MurmureClient};
+use murmure_server::server::{
+    self, GrpcListeners, ListenerError, RateLimiter, TranscriptionServiceImpl,
+};
+use murmure_stt::config::{GrpcListener, ServerConfig};
+use murmure_stt::mock::MockEngine;
+use murmure_stt::TranscriptionService;
+use tokio::sync::oneshot;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn plaintext(addr: SocketAddr) -> GrpcListener {
+    GrpcListener {
+        addr,
+        tls_cert: None,
+        tls_key: None,
+    }
+}
+
+fn tls(addr: SocketAddr) -> GrpcListener {
+    GrpcListener {
+        addr,
+        tls_cert: Some(fixture("server.pem")),
+        tls_key: Some(fixture("server.key")),
+    }
+}
+
+fn any_port() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+fn config(listeners: Vec<GrpcListener>) -> ServerConfig {
+    ServerConfig {
+        grpc_listeners: listeners,
+        ..ServerConfig::default()
+    }
+}
+
+fn grpc_service(config: &ServerConfig) -> server::GrpcService {
+    let service = Arc::new(
+        TranscriptionService::builder()
+            .config(config.clone())
+            .engine(|| MockEngine::new("hello world"))
+            .build()
+            .unwrap(),
+    );
+    let limiter = Arc::new(RateLimiter::from_config(config));
+    server::grpc_service(
+        TranscriptionServiceImpl::new(service, Arc::clone(&limiter)),
+        limiter,
+    )
+}
+
+#[tokio::test]
+async fn serves_every_listener_until_shutdown() {
+    let config = config(vec![plaintext(any_port()), tls(any_port())]);
+    let listeners = GrpcListeners::bind(&config).await.unwrap();
+    let [plain_addr, tls_addr] = listeners.local_addrs()[..] else {
+        panic!("two listeners were configured");
+    };
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    let serving = tokio::spawn(listeners.serve(grpc_service(&config), async {
+        let _ = stopped.await;
+    }));
+
+    let plain = ClientOptions::new(format!("http://{}", plain_addr));
+    let secure = ClientOptions {
+        ca_certificate: Some(std::fs::read(fixture("ca.pem")).unwrap()),
+        tls_domain: Some("localhost".to_string()),
+        ..ClientOptions::new(format!("https://{}", tls_addr))
+    };
+    for options in [plain, secure] {
+        let mut client = MurmureClient::connect(options.clone()).await.unwrap();
+        let result = client.transcribe_wav_bytes(&tone_wav(1.0)).await.unwrap();
+        assert_eq!(result.text, "hello world", "{}", options.endpoint);
+    }
+
+    shutdown.send(()).unwrap();
+    serving.await.unwrap().unwrap();
+    for addr in [plain_addr, tls_addr] {
+        assert!(
+            tokio::net::TcpStream::connect(addr).await.is_err(),
+            "{}",
+            addr
+        );
+    }
+}
+
+#[tokio::test]
+async fn names_the_listener_that_cannot_bind() {
+    let held = tokio::net::TcpListener::bind(any_port()).await.unwrap();
+    let taken = held.local_addr().unwrap();
+
+    let error = GrpcListeners::bind(&config(vec![plaintext(any_port()), plaintext(taken)]))
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(error, ListenerError::Bind { addr, .. } if addr == taken),
+        "{:?}",
+        error
+    );
+    assert!(error
+        .to_string()
+        .starts_with(&format!("Failed to bind gRPC listener {}: ", taken)));
+}
+
+#[tokio::test]
+async fn names_the_listener_whose_certificate_is_missing() {
+    let missing = GrpcListener {
+        tls_cert: Some(fixture("missing.pem")),
+        ..tls(any_port())
+    };
+    let error = GrpcListeners::bind(&config(vec![plaintext(any_port()), missing]))
+        .await
+        .err()
+        .unwrap();
+    assert!(
+        matches!(&error, ListenerError::ReadTls { path, .. } if path.ends_with("missing.pem")),
+        "{:?}",
+        error
+    );
+}
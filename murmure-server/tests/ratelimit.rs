@@ -0,0 +1,84 @@
+// Rate limits and quotas as clients see them: RESOURCE_EXHAUSTED with a
+// `retry-after-ms` hint
+
+mod common;
+
+use common::{file_request, tone_wav, TestServer};
+use murmure_stt::config::{RateLimitOverride, ServerConfig};
+use murmure_stt::mock::MockEngine;
+use tonic::{Code, Request, Status};
+
+fn retry_after_ms(status: &Status) -> u64 {
+    status
+        .metadata()
+        .get("retry-after-ms")
+        .expect("rejections carry retry-after-ms")
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn requests_over_the_rate_are_exhausted() {
+    let config = ServerConfig {
+        rate_limit_rpm: 2,
+        rate_limit_overrides: vec![RateLimitOverride {
+            key: "team-key".to_string(),
+            requests_per_minute: Some(0),
+            audio_secs: None,
+            monthly_audio_secs: None,
+        }],
+        ..ServerConfig::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("ok")).await;
+    let mut client = server.client().await;
+
+    for _ in 0..2 {
+        client
+            .transcribe_file(file_request(tone_wav(0.5)))
+            .await
+            .unwrap();
+    }
+    let status = client
+        .transcribe_file(file_request(tone_wav(0.5)))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    // 2 per minute: the next request is allowed within 30 seconds
+    let retry = retry_after_ms(&status);
+    assert!((1..=30_000).contains(&retry), "{}", retry);
+
+    // A client with its own key isn't held back by the address it shares
+    let mut team = server.client().await;
+    for _ in 0..3 {
+        let mut request = Request::new(file_request(tone_wav(0.5)));
+        request
+            .metadata_mut()
+            .insert("x-api-key", "team-key".parse().unwrap());
+        team.transcribe_file(request).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn audio_over_the_monthly_quota_is_exhausted() {
+    let config = ServerConfig {
+        usage_monthly_quota_secs: 1,
+        ..ServerConfig::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("ok")).await;
+    let mut client = server.client().await;
+
+    client
+        .transcribe_file(file_request(tone_wav(0.8)))
+        .await
+        .unwrap();
+    let status = client
+        .transcribe_file(file_request(tone_wav(0.8)))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    // Points at the start of next month, at most 31 days away
+    let retry = retry_after_ms(&status);
+    assert!(retry > 0 && retry <= 31 * 24 * 3600 * 1000, "{}", retry);
+}
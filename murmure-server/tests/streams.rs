@@ -0,0 +1,327 @@
+// Long-lived TranscribeStream behaviour: heartbeats while the client is
+// silent, resuming a session on a new stream, pausing and resuming a stream,
+// and how streams that carry no audio or never send EndOfStream end
+
+mod common;
+
+use std::time::Duration;
+
+use common::{murmure, tone_wav, TestServer};
+use murmure::transcribe_stream_request::RequestType;
+use murmure::transcribe_stream_response::ResponseType;
+use murmure::{StreamConfig, TranscribeStreamRequest};
+use murmure_stt::config::{IncompleteStream, ServerConfig};
+use murmure_stt::mock::MockEngine;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+fn message(request_type: RequestType) -> TranscribeStreamRequest {
+    TranscribeStreamRequest {
+        request_type: Some(request_type),
+    }
+}
+
+#[tokio::test]
+async fn silent_streams_get_heartbeats() {
+    let config = ServerConfig {
+        stream_heartbeat_secs: 1,
+        ..ServerConfig::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("after a pause")).await;
+    let mut client = server.client().await;
+
+    let (requests, outgoing) = mpsc::channel(4);
+    let mut responses = client
+        .transcribe_stream(ReceiverStream::new(outgoing))
+        .await
+        .unwrap()
+        .into_inner();
+    let audio = tone_wav(1.0);
+    requests
+        .send(message(RequestType::AudioChunk(audio.clone())))
+        .await
+        .unwrap();
+
+    // Nothing is sent for longer than the heartbeat interval
+    let heartbeat = loop {
+        let response = tokio::time::timeout(Duration::from_secs(3), responses.message())
+            .await
+            .expect("a heartbeat within the interval")
+            .unwrap()
+            .unwrap();
+        if let Some(ResponseType::Heartbeat(heartbeat)) = response.response_type {
+            break heartbeat;
+        }
+    };
+    assert_eq!(heartbeat.bytes_received, audio.len() as u64);
+
+    requests
+        .send(message(RequestType::EndOfStream(true)))
+        .await
+        .unwrap();
+    let mut finals = Vec::new();
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::FinalText(text)) = response.response_type {
+            finals.push(text);
+        }
+    }
+    assert_eq!(finals, ["after a pause"]);
+}
+
+#[tokio::test]
+async fn sessions_resume_on_a_new_stream() {
+    let server = TestServer::start(MockEngine::new("resumed")).await;
+    let mut client = server.client().await;
+
+    let first = vec![
+        message(RequestType::AudioChunk(tone_wav(1.0))),
+        message(RequestType::UtteranceBoundary(Default::default())),
+        message(RequestType::AudioChunk(tone_wav(1.0))),
+        message(RequestType::EndOfStream(true)),
+    ];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(first))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut session_id = None;
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::Session(session)) = response.response_type {
+            assert!(!session.resumed);
+            session_id.get_or_insert(session.session_id);
+        }
+    }
+    let session_id = session_id.expect("streams start with their session");
+
+    let second = vec![
+        message(RequestType::Config(StreamConfig {
+            resume_session_id: session_id.clone(),
+            ..Default::default()
+        })),
+        message(RequestType::AudioChunk(tone_wav(1.0))),
+        message(RequestType::EndOfStream(true)),
+    ];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(second))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut resumed = None;
+    let mut finals = Vec::new();
+    while let Some(response) = responses.message().await.unwrap() {
+        match response.response_type {
+            Some(ResponseType::Session(session)) if session.resumed => resumed = Some(session),
+            Some(ResponseType::FinalText(text)) => finals.push((response.utterance_id, text)),
+            Some(ResponseType::Error(message)) => panic!("stream failed: {}", message),
+            _ => {}
+        }
+    }
+    let resumed = resumed.expect("the resume is acknowledged");
+    // A session resumes once; the new stream has an id of its own
+    assert_ne!(resumed.session_id, session_id);
+    assert_eq!(resumed.next_utterance_id, 2);
+    assert_eq!(resumed.resumed_bytes, 0);
+    // Utterance ids carry on from the first stream
+    assert_eq!(finals, [(2, "resumed".to_string())]);
+}
+
+#[tokio::test]
+async fn unknown_sessions_fail_the_resume() {
+    let server = TestServer::start(MockEngine::new("unused")).await;
+    let mut client = server.client().await;
+    let requests = vec![message(RequestType::Config(StreamConfig {
+        resume_session_id: "0123456789abcdef".to_string(),
+        ..Default::default()
+    }))];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut failed = false;
+    loop {
+        match responses.message().await {
+            Ok(Some(response)) => {
+                failed |= matches!(response.response_type, Some(ResponseType::Error(_)))
+            }
+            Ok(None) => break,
+            Err(_) => {
+                failed = true;
+                break;
+            }
+        }
+    }
+    assert!(failed);
+}
+
+#[tokio::test]
+async fn end_of_stream_without_audio_skips_the_engine() {
+    let server = TestServer::start(MockEngine::new("never heard")).await;
+    let mut client = server.client().await;
+
+    let requests = vec![message(RequestType::EndOfStream(true))];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut finals = Vec::new();
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::FinalText(text)) = response.response_type {
+            assert!(response.is_final);
+            assert!(response.no_audio);
+            finals.push(text);
+        }
+    }
+    assert_eq!(finals, [""]);
+}
+
+/// Final texts of a stream that sends one utterance and half-closes
+/// without EndOfStream.
+async fn finals_without_end_of_stream(incomplete_stream: IncompleteStream) -> Vec<String> {
+    let config = ServerConfig {
+        incomplete_stream,
+        ..ServerConfig::default()
+    };
+    let server = TestServer::with_config(config, MockEngine::new("cut short")).await;
+    let mut client = server.client().await;
+
+    let requests = vec![message(RequestType::AudioChunk(tone_wav(1.0)))];
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut finals = Vec::new();
+    while let Some(response) = responses.message().await.unwrap() {
+        if let Some(ResponseType::FinalText(text)) = response.response_type {
+            assert!(!response.no_audio);
+            finals.push(text);
+        }
+    }
+    finals
+}
+
+#[tokio::test]
+async fn streams_without_end_of_stream_are_transcribed_by_default() {
+    assert_eq!(
+        finals_without_end_of_stream(IncompleteStream::default()).await,
+        ["cut short"]
+    );
+}
+
+#[tokio::test]
+async fn streams_without_end_of_stream_can_be_discarded() {
+    assert!(finals_without_end_of_stream(IncompleteStream::Discard)
+        .await
+        .is_empty());
+}
+
+/// Heartbeats and final texts of a stream of `requests`, and the status it
+/// failed with.
+async fn run_stream(
+    server: &TestServer,
+    requests: Vec<RequestType>,
+) -> (Vec<murmure::Heartbeat>, Vec<String>, Option<tonic::Status>) {
+    let mut client = server.client().await;
+    let mut responses = client
+        .transcribe_stream(tokio_stream::iter(requests.into_iter().map(message)))
+        .await
+        .unwrap()
+        .into_inner();
+    let (mut heartbeats, mut finals) = (Vec::new(), Vec::new());
+    loop {
+        match responses.message().await {
+            Ok(Some(response)) => match response.response_type {
+                Some(ResponseType::Heartbeat(heartbeat)) => heartbeats.push(heartbeat),
+                Some(ResponseType::FinalText(text)) => finals.push(text),
+                _ => {}
+            },
+            Ok(None) => return (heartbeats, finals, None),
+            Err(status) => return (heartbeats, finals, Some(status)),
+        }
+    }
+}
+
+#[tokio::test]
+async fn pauses_drop_audio_and_keep_the_buffer() {
+    let server = TestServer::start(MockEngine::new("other").on_duration(1000, "one second")).await;
+    let wav = tone_wav(1.0);
+    let (first, second) = wav.split_at(wav.len() / 2);
+
+    let (_, unpaused, status) = run_stream(
+        &server,
+        vec![
+            RequestType::AudioChunk(first.to_vec()),
+            RequestType::AudioChunk(second.to_vec()),
+            RequestType::EndOfStream(true),
+        ],
+    )
+    .await;
+    assert!(status.is_none(), "{:?}", status);
+
+    let (heartbeats, paused, status) = run_stream(
+        &server,
+        vec![
+            RequestType::AudioChunk(first.to_vec()),
+            RequestType::Pause(murmure::StreamPause {}),
+            // In flight when the client paused: dropped
+            RequestType::AudioChunk(tone_wav(0.5)),
+            RequestType::Resume(murmure::StreamResume {}),
+            RequestType::AudioChunk(second.to_vec()),
+            RequestType::EndOfStream(true),
+        ],
+    )
+    .await;
+    assert!(status.is_none(), "{:?}", status);
+    assert_eq!(paused, unpaused);
+    assert_eq!(paused, ["one second"]);
+
+    // Each transition is acknowledged, the dropped chunk not counted
+    let acks: Vec<_> = heartbeats
+        .iter()
+        .map(|heartbeat| (heartbeat.paused, heartbeat.bytes_received))
+        .collect();
+    assert_eq!(
+        acks,
+        [(true, first.len() as u64), (false, first.len() as u64)]
+    );
+}
+
+#[tokio::test]
+async fn end_of_stream_while_paused_transcribes_the_buffer() {
+    let server = TestServer::start(MockEngine::new("kept")).await;
+    let (_, finals, status) = run_stream(
+        &server,
+        vec![
+            RequestType::AudioChunk(tone_wav(1.0)),
+            RequestType::Pause(murmure::StreamPause {}),
+            RequestType::EndOfStream(true),
+        ],
+    )
+    .await;
+    assert!(status.is_none(), "{:?}", status);
+    assert_eq!(finals, ["kept"]);
+}
+
+#[tokio::test]
+async fn invalid_pause_transitions_fail_the_stream() {
+    let server = TestServer::start(MockEngine::new("unused")).await;
+    let pause = || RequestType::Pause(murmure::StreamPause {});
+    let resume = || RequestType::Resume(murmure::StreamResume {});
+
+    for (requests, message) in [
+        (vec![resume()], "stream is not paused"),
+        (vec![pause(), resume(), resume()], "stream is not paused"),
+        (vec![pause(), pause()], "stream is already paused"),
+    ] {
+        let mut requests = requests;
+        requests.push(RequestType::AudioChunk(tone_wav(0.5)));
+        requests.push(RequestType::EndOfStream(true));
+        let (_, finals, status) = run_stream(&server, requests).await;
+        let status = status.expect("the stream fails");
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(status.message(), message);
+        assert!(finals.is_empty());
+    }
+}
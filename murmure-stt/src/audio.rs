@@ -1,141 +1,970 @@
 use crate::config::ServerConfig;
-use crate::dictionary::{fix_transcription_with_dictionary, get_cc_rules_path, Dictionary};
+use crate::dictionary::{CorrectedText, Correction};
+use crate::dsp::{self, MonoAudio, ResamplerQuality};
 use crate::engine::{
     parakeet::{ParakeetEngine, ParakeetModelParams},
-    transcription_engine::TranscriptionEngine,
+    transcription_engine::{OutputToken, SpeechEngine, TranscriptionEngine, TranscriptionOutput},
 };
-use crate::model::Model;
+use crate::error::TranscriptionError;
+use crate::langid::detect_language;
+use crate::model::{ExecutionProvider, Model, ModelPrecision};
+use crate::postprocess::DictionaryCorrector;
+use crate::stats::SpeechStats;
+use crate::transcription::{
+    ChannelMode, ChannelTranscript, DebugCapture, InputAudio, StageTimings, Transcript,
+    TranscriptSegment, TranscriptWord, TranscriptionOptions,
+};
+use crate::vad::split_on_silence;
+use crate::validation::AudioValidationError;
 use anyhow::Result;
 use once_cell::sync::Lazy;
+use parking_lot::{Condvar, MappedMutexGuard, Mutex, MutexGuard};
+use std::any::Any;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Sample rate expected by the Parakeet engine
+pub const TARGET_SAMPLE_RATE: usize = 16000;
+
+/// Peak level audio is normalized to, in dBFS
+const NORMALIZE_PEAK_DB: f32 = -1.0;
+
+/// The engine panicked while loading or transcribing. The panic was
+/// contained and only the request failed; the panic message is logged, not
+/// returned, as it may contain paths or model internals.
+#[derive(Debug, thiserror::Error)]
+#[error("internal engine error in model '{model}', the request was not transcribed")]
+pub struct InternalEngineError {
+    pub model: String,
+}
+
+/// Engine panics since startup, all models
+static ENGINE_PANICS: AtomicU64 = AtomicU64::new(0);
+
+/// Lifecycle of a model's engine.
+enum EngineSlot {
+    Unloaded,
+    /// A caller is loading the model outside the lock
+    Loading,
+    Loaded(Box<dyn SpeechEngine>),
+}
+
+struct SharedEngine {
+    slot: Mutex<EngineSlot>,
+    /// Notified whenever a load finishes (successfully or not)
+    load_finished: Condvar,
+    /// Provider the loaded engine runs on, readable without waiting for the engine
+    execution_provider: Mutex<Option<ExecutionProvider>>,
+    /// Whether the slot holds a loaded engine, readable without waiting for it
+    loaded: AtomicBool,
+    /// Last time a request locked the engine, for least-recently-used unloading
+    last_used: Mutex<Instant>,
+    /// Panics of the engine since it was last loaded
+    panics: AtomicU32,
+    /// Cost of the last successful load
+    load_stats: Mutex<Option<LoadStats>>,
+}
 
-static ENGINE: Lazy<parking_lot::Mutex<Option<ParakeetEngine>>> =
-    Lazy::new(|| parking_lot::Mutex::new(None));
+/// What loading a model's engine took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadStats {
+    /// Wall-clock time to load the engine
+    pub load_time: Duration,
+    /// Change in the process's resident memory across the load, in bytes.
+    /// `None` where resident memory can't be read (anything but Linux).
+    /// Other requests running meanwhile are counted too.
+    pub resident_delta_bytes: Option<i64>,
+}
+
+impl SharedEngine {
+    fn new() -> Self {
+        Self {
+            slot: Mutex::new(EngineSlot::Unloaded),
+            load_finished: Condvar::new(),
+            execution_provider: Mutex::new(None),
+            loaded: AtomicBool::new(false),
+            last_used: Mutex::new(Instant::now()),
+            panics: AtomicU32::new(0),
+            load_stats: Mutex::new(None),
+        }
+    }
+}
+
+/// Engines by [`Model::engine_key`], shared by every service and model
+/// entry loading the same weights the same way. Entries are only ever
+/// unloaded, never removed, so they are leaked to hand out `'static` engine
+/// guards.
+static ENGINES: Lazy<Mutex<HashMap<String, &'static SharedEngine>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn shared_engine(model: &Model) -> &'static SharedEngine {
+    ENGINES
+        .lock()
+        .entry(model.engine_key())
+        .or_insert_with(|| Box::leak(Box::new(SharedEngine::new())))
+}
 
 pub fn read_wav_samples(wav_path: &std::path::Path) -> Result<Vec<f32>> {
-    let mut reader = hound::WavReader::open(wav_path)?;
-    let spec = reader.spec();
+    read_wav_samples_with_quality(wav_path, ResamplerQuality::default())
+}
+
+/// Decode a WAV file to 16 kHz mono using the given resampler.
+pub fn read_wav_samples_with_quality(
+    wav_path: &std::path::Path,
+    quality: ResamplerQuality,
+) -> Result<Vec<f32>> {
+    let file = std::io::BufReader::new(std::fs::File::open(wav_path)?);
+    let audio = dsp::read_wav(file)?;
+    Ok(to_engine_rate(audio, quality))
+}
 
-    if spec.bits_per_sample != 16 {
-        return Err(anyhow::anyhow!(
-            "Expected 16 bits per sample, found {}",
-            spec.bits_per_sample
-        ));
+/// Resample decoded mono audio to the engine's 16 kHz rate.
+fn to_engine_rate(audio: MonoAudio, quality: ResamplerQuality) -> Vec<f32> {
+    if audio.sample_rate as usize != TARGET_SAMPLE_RATE {
+        dsp::resample(
+            &audio.samples,
+            audio.sample_rate as usize,
+            TARGET_SAMPLE_RATE,
+            quality,
+        )
+    } else {
+        audio.samples
     }
+}
 
-    if spec.sample_format != hound::SampleFormat::Int {
-        return Err(anyhow::anyhow!(
-            "Expected Int sample format, found {:?}",
-            spec.sample_format
-        ));
+pub fn preload_engine(model: &Model) -> Result<(), TranscriptionError> {
+    let name = model.name();
+    let key = model.engine_key();
+    let shared = shared_engine(model);
+    {
+        let mut slot = shared.slot.lock();
+        loop {
+            if matches!(*slot, EngineSlot::Loaded(_)) {
+                return Ok(());
+            }
+            if matches!(*slot, EngineSlot::Unloaded) {
+                *slot = EngineSlot::Loading;
+                break;
+            }
+            // Another caller is loading: wait for it instead of loading twice
+            shared.load_finished.wait(&mut slot);
+        }
     }
 
-    let raw_i16: Result<Vec<i16>, _> = reader.samples::<i16>().collect();
-    let mut raw_i16 = raw_i16?;
+    unload_least_recently_used(&key, model.get_config().max_loaded_models);
+
+    // The expensive load runs without holding the lock so concurrent
+    // transcriptions can observe the Loading state and time out promptly.
+    // A panic must not leave the slot Loading, or every later request would
+    // wait for a load that never finishes.
+    let resident_before = resident_bytes();
+    let started = Instant::now();
+    let loaded = panic::catch_unwind(AssertUnwindSafe(|| load_engine(model)))
+        .unwrap_or_else(|payload| Err(record_panic(model, "loading", payload).into()));
+    let stats = LoadStats {
+        load_time: started.elapsed(),
+        resident_delta_bytes: resident_before
+            .zip(resident_bytes())
+            .map(|(before, after)| after as i64 - before as i64),
+    };
 
-    if spec.channels > 1 {
-        let ch = spec.channels as usize;
-        let mut mono: Vec<i16> = Vec::with_capacity(raw_i16.len() / ch);
-        for frame in raw_i16.chunks_exact(ch) {
-            let sum: i32 = frame.iter().map(|&s| s as i32).sum();
-            let avg = (sum / ch as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
-            mono.push(avg);
+    let mut slot = shared.slot.lock();
+    let result = match loaded {
+        Ok(engine) => {
+            *shared.execution_provider.lock() = engine.execution_provider();
+            *shared.last_used.lock() = Instant::now();
+            shared.panics.store(0, Ordering::Relaxed);
+            *slot = EngineSlot::Loaded(engine);
+            shared.loaded.store(true, Ordering::Relaxed);
+            *shared.load_stats.lock() = Some(stats);
+            log::info!(
+                "Model '{}' loaded and cached in memory in {} ms ({})",
+                name,
+                stats.load_time.as_millis(),
+                stats.resident_delta_bytes.map_or_else(
+                    || "resident memory unknown".to_string(),
+                    |delta| format!("{:+} MiB resident", delta / (1024 * 1024))
+                )
+            );
+            Ok(())
         }
-        raw_i16 = mono;
-    }
+        Err(e) => {
+            *slot = EngineSlot::Unloaded;
+            Err(e)
+        }
+    };
+    drop(slot);
+    shared.load_finished.notify_all();
 
-    let samples_f32: Vec<f32> = raw_i16
-        .into_iter()
-        .map(|s| s as f32 / i16::MAX as f32)
+    result
+}
+
+/// Unload the least recently used engines other than the one keyed `keep`
+/// until loading it stays within `max_loaded` resident engines.
+///
+/// Engines in use by a request are skipped, so the limit can be exceeded
+/// for as long as they stay busy.
+fn unload_least_recently_used(keep: &str, max_loaded: usize) {
+    let engines = ENGINES.lock();
+    let mut resident: Vec<(Instant, &str, &'static SharedEngine)> = engines
+        .iter()
+        .filter(|(key, engine)| key.as_str() != keep && engine.loaded.load(Ordering::Relaxed))
+        .map(|(key, engine)| (*engine.last_used.lock(), key.as_str(), *engine))
         .collect();
+    resident.sort_by_key(|(last_used, _, _)| *last_used);
 
-    let out = if spec.sample_rate != 16000 {
-        resample_linear(&samples_f32, spec.sample_rate as usize, 16000)
-    } else {
-        samples_f32
+    let mut count = resident.len();
+    for (_, key, engine) in resident {
+        if count < max_loaded.max(1) {
+            break;
+        }
+        let Some(mut slot) = engine.slot.try_lock() else {
+            continue;
+        };
+        if unload(engine, &mut slot) {
+            count -= 1;
+            log::info!("Engine {} unloaded to make room", key);
+        }
+    }
+}
+
+/// Drop the engine held in `slot`. Returns `false` if none was loaded.
+fn unload(shared: &SharedEngine, slot: &mut EngineSlot) -> bool {
+    if !matches!(slot, EngineSlot::Loaded(_)) {
+        return false;
+    }
+    *slot = EngineSlot::Unloaded;
+    *shared.execution_provider.lock() = None;
+    shared.loaded.store(false, Ordering::Relaxed);
+    true
+}
+
+/// Run `f` on the locked engine, turning a panic into [`InternalEngineError`].
+///
+/// The guard is released before a panic is handled, so an engine that
+/// reached `engine_panic_threshold` panics can be unloaded and reloaded.
+fn run_engine<T>(
+    model: &Model,
+    mut engine: MappedMutexGuard<'static, dyn SpeechEngine>,
+    f: impl FnOnce(&mut dyn SpeechEngine) -> Result<T, TranscriptionError>,
+) -> Result<T, TranscriptionError> {
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| f(&mut *engine)));
+    drop(engine);
+    outcome.unwrap_or_else(|payload| {
+        let error = record_panic(model, "transcribing", payload);
+        reload_if_unhealthy(model);
+        Err(error.into())
+    })
+}
+
+/// Count and log an engine panic.
+fn record_panic(model: &Model, during: &str, payload: Box<dyn Any + Send>) -> InternalEngineError {
+    let name = model.name();
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string());
+    ENGINE_PANICS.fetch_add(1, Ordering::Relaxed);
+    shared_engine(model).panics.fetch_add(1, Ordering::Relaxed);
+    log::error!(
+        "Engine of model '{}' panicked while {}: {}",
+        name,
+        during,
+        message
+    );
+    InternalEngineError { model: name }
+}
+
+/// Once the loaded engine has panicked `engine_panic_threshold` times, treat
+/// it as corrupted: unload it and load it again in the background. Requests
+/// arriving meanwhile wait for the reload like for a first load.
+fn reload_if_unhealthy(model: &Model) {
+    let threshold = model.get_config().engine_panic_threshold;
+    let shared = shared_engine(model);
+    if threshold == 0 || shared.panics.load(Ordering::Relaxed) < threshold {
+        return;
+    }
+    if !unload(shared, &mut shared.slot.lock()) {
+        // Already unloaded by another request
+        return;
+    }
+
+    log::warn!(
+        "Engine of model '{}' panicked {} times, reloading it",
+        model.name(),
+        threshold
+    );
+    let model = model.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = preload_engine(&model) {
+            log::error!("Failed to reload model '{}': {}", model.name(), e);
+        }
+    });
+}
+
+/// Engine panics since startup, across all models.
+pub fn engine_panic_count() -> u64 {
+    ENGINE_PANICS.load(Ordering::Relaxed)
+}
+
+/// The model's engine: made by its injected factory, or Parakeet loaded
+/// from the model directory.
+fn load_engine(model: &Model) -> Result<Box<dyn SpeechEngine>, TranscriptionError> {
+    if let Some(factory) = model.engine_factory() {
+        return Ok(factory());
+    }
+    let model_path = model.get_model_path()?;
+
+    let mut engine = ParakeetEngine::new();
+    engine
+        .load_model_with_params(&model_path, model_params(model))
+        .map_err(|e| {
+            TranscriptionError::EngineLoad(format!("Failed to load model: {}", e).into())
+        })?;
+
+    Ok(Box::new(engine))
+}
+
+/// Engine load parameters derived from the model precision and configured threads.
+fn model_params(model: &Model) -> ParakeetModelParams {
+    let config = model.get_config();
+    let params = match model.precision() {
+        ModelPrecision::Int8 => ParakeetModelParams::int8(),
+        ModelPrecision::Fp16 => ParakeetModelParams::fp16(),
+        ModelPrecision::Fp32 => ParakeetModelParams::fp32(),
     };
+    ParakeetModelParams {
+        intra_op_threads: config.intra_op_threads,
+        inter_op_threads: config.inter_op_threads,
+        execution_provider: config.execution_provider,
+        mmap: config.mmap_model,
+        ..params
+    }
+}
+
+/// Execution provider the model's engine actually runs on (`None` while it
+/// isn't loaded).
+pub fn active_execution_provider(model: &Model) -> Option<ExecutionProvider> {
+    *shared_engine(model).execution_provider.lock()
+}
+
+/// Cost of the model's last engine load, `None` if it never loaded.
+pub fn engine_load_stats(model: &Model) -> Option<LoadStats> {
+    *shared_engine(model).load_stats.lock()
+}
+
+/// Resident set size of this process, from `/proc/self/status`.
+fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Whether the model's engine is resident in memory.
+pub fn is_engine_loaded(model: &Model) -> bool {
+    ENGINES
+        .lock()
+        .get(&model.engine_key())
+        .is_some_and(|engine| engine.loaded.load(Ordering::Relaxed))
+}
 
-    Ok(out)
+/// Lock the model's engine, loading it first when needed.
+///
+/// An engine unloaded to make room for another model between loading and
+/// locking is loaded again, a few times at most.
+fn acquire_engine(
+    model: &Model,
+    wait: Option<Duration>,
+) -> Result<MappedMutexGuard<'static, dyn SpeechEngine>, TranscriptionError> {
+    const ATTEMPTS: usize = 3;
+
+    let shared = shared_engine(model);
+    for _ in 0..ATTEMPTS {
+        if let Some(engine) = lock_engine(shared, wait)? {
+            return Ok(engine);
+        }
+        preload_engine(model)?;
+    }
+    Err(TranscriptionError::EngineUnavailable(format!(
+        "Model '{}' was unloaded before it could be used, too many models are in use for MURMURE_MAX_LOADED_MODELS",
+        model.name()
+    )))
 }
 
-pub fn preload_engine(model: &Model) -> Result<()> {
-    let mut engine = ENGINE.lock();
+/// Lock a loaded engine, giving up after `wait` (`None` waits indefinitely).
+/// Returns `None` when the engine isn't loaded.
+///
+/// Fails with a "model loading" error if the model is still being loaded when
+/// the wait expires, and with an "engine busy" error if another request holds
+/// the engine for longer than that.
+fn lock_engine(
+    shared: &'static SharedEngine,
+    wait: Option<Duration>,
+) -> Result<Option<MappedMutexGuard<'static, dyn SpeechEngine>>, TranscriptionError> {
+    let deadline = wait.map(|w| Instant::now() + w);
 
-    if engine.is_none() {
-        let model_path = model
-            .get_model_path()
-            .map_err(|e| anyhow::anyhow!("Failed to get model path: {}", e))?;
+    let mut slot = match deadline {
+        Some(deadline) => shared.slot.try_lock_until(deadline).ok_or_else(|| {
+            TranscriptionError::EngineUnavailable(format!(
+                "Engine busy: timed out after {}ms waiting for the transcription engine",
+                wait.unwrap_or_default().as_millis()
+            ))
+        })?,
+        None => shared.slot.lock(),
+    };
 
-        let mut new_engine = ParakeetEngine::new();
-        new_engine
-            .load_model_with_params(&model_path, ParakeetModelParams::int8())
-            .map_err(|e| anyhow::anyhow!("Failed to load model: {}", e))?;
+    while matches!(*slot, EngineSlot::Loading) {
+        match deadline {
+            Some(deadline) => {
+                if shared
+                    .load_finished
+                    .wait_until(&mut slot, deadline)
+                    .timed_out()
+                    && matches!(*slot, EngineSlot::Loading)
+                {
+                    return Err(TranscriptionError::EngineUnavailable(format!(
+                        "Model is still loading, retry later (waited {}ms)",
+                        wait.unwrap_or_default().as_millis()
+                    )));
+                }
+            }
+            None => shared.load_finished.wait(&mut slot),
+        }
+    }
 
-        *engine = Some(new_engine);
-        println!("Model loaded and cached in memory");
+    if matches!(*slot, EngineSlot::Loaded(_)) {
+        *shared.last_used.lock() = Instant::now();
     }
+    Ok(MutexGuard::try_map(slot, |slot| match slot {
+        EngineSlot::Loaded(engine) => Some(engine.as_mut()),
+        _ => None,
+    })
+    .ok())
+}
+
+/// Run the model's engine once on a short stretch of silence so the first
+/// real request doesn't pay for lazy ONNX initialization.
+pub fn warm_up_engine(model: &Model) -> Result<(), TranscriptionError> {
+    let engine = acquire_engine(model, None)?;
+
+    run_engine(model, engine, |engine| {
+        engine
+            .transcribe(vec![0.0; TARGET_SAMPLE_RATE / 2])
+            .map_err(|e| inference_error("Engine warm-up failed", e))
+    })?;
 
     Ok(())
 }
 
-pub fn transcribe_audio(
+/// Transcribe the file at `audio_path`. `dictionary` picks the corrector to
+/// apply given the language the transcript was identified as, if any.
+pub fn transcribe_audio<'a>(
     audio_path: &std::path::Path,
-    _model: &Model,
-    dictionary: Option<&Dictionary>,
+    model: &Model,
+    dictionary: impl FnOnce(Option<&str>) -> Option<&'a DictionaryCorrector>,
     config: &ServerConfig,
-) -> Result<String> {
-    let samples = read_wav_samples(audio_path)?;
-
-    let mut engine = ENGINE.lock();
-    let engine = engine
-        .as_mut()
-        .ok_or_else(|| anyhow::anyhow!("Engine not loaded"))?;
-
-    let result = engine
-        .transcribe_samples(samples, None)
-        .map_err(|e| anyhow::anyhow!("Transcription failed: {}", e))?;
-
-    let raw_text = result.text;
-
-    // Apply dictionary corrections if available
-    let text = if let Some(dict) = dictionary {
-        match get_cc_rules_path(config) {
-            Ok(cc_rules_path) => {
-                let dict_words = dict.get();
-                fix_transcription_with_dictionary(raw_text, dict_words, cc_rules_path)
-            }
-            Err(_) => {
-                eprintln!("Warning: CC rules not found, skipping dictionary correction");
-                raw_text
-            }
+    options: &TranscriptionOptions,
+) -> Result<Transcript, TranscriptionError> {
+    let mut timing = StageTimings::default();
+    let (mut channels, input) = read_channels(
+        audio_path,
+        options.channel_mode,
+        config.resampler_quality,
+        &mut timing,
+    )?;
+    // The header was checked upfront; this catches data past the declared
+    // size and formats whose header can't be trusted
+    if let Some(max_secs) = options
+        .max_audio_secs
+        .filter(|&max| input.duration_secs > max)
+    {
+        return Err(AudioValidationError::TooLong {
+            duration_secs: input.duration_secs,
+            max_secs,
+            sample_rate: input.sample_rate,
         }
+        .into());
+    }
+    let resampled = options.capture_debug.then(|| {
+        channels
+            .iter()
+            .map(|(_, samples)| samples.clone())
+            .collect()
+    });
+    let preprocess_start = Instant::now();
+    for (_, samples) in &mut channels {
+        preprocess(samples, options);
+    }
+    timing.preprocess = preprocess_start.elapsed();
+    let separate = options.channel_mode == ChannelMode::Separate && channels.len() > 1;
+    let audio_duration_secs = channels.first().map_or(0.0, |(_, samples)| {
+        samples.len() as f32 / TARGET_SAMPLE_RATE as f32
+    });
+
+    let vad_start = Instant::now();
+    let ranges: Vec<Vec<Range<usize>>> = channels
+        .iter()
+        .map(|(_, samples)| {
+            if options.segment_on_silence {
+                split_on_silence(samples, TARGET_SAMPLE_RATE, config.segment_min_silence_ms)
+            } else {
+                let whole = 0..samples.len();
+                vec![whole]
+            }
+        })
+        .collect();
+    timing.vad = vad_start.elapsed();
+
+    let inference_start = Instant::now();
+    let outputs = {
+        let wait =
+            (config.engine_wait_ms > 0).then(|| Duration::from_millis(config.engine_wait_ms));
+        let engine = acquire_engine(model, wait)?;
+
+        run_engine(model, engine, |engine| {
+            channels
+                .iter()
+                .zip(&ranges)
+                .map(|((_, samples), ranges)| {
+                    ranges
+                        .iter()
+                        .map(|range| {
+                            engine
+                                .transcribe_with(samples[range.clone()].to_vec(), &options.decoding)
+                                .map_err(|e| inference_error("Transcription failed", e))
+                        })
+                        .collect::<Result<Vec<TranscriptionOutput>, _>>()
+                })
+                .collect::<Result<Vec<Vec<TranscriptionOutput>>, _>>()
+        })?
+    };
+    timing.inference = inference_start.elapsed();
+
+    let debug = resampled.map(|resampled| DebugCapture {
+        sample_rate: TARGET_SAMPLE_RATE as u32,
+        resampled,
+        speech: channels
+            .iter()
+            .zip(&ranges)
+            .map(|((_, samples), ranges)| {
+                ranges
+                    .iter()
+                    .flat_map(|range| &samples[range.clone()])
+                    .copied()
+                    .collect()
+            })
+            .collect(),
+        raw_text: outputs
+            .iter()
+            .map(|outputs| outputs.iter().map(|output| output.text.clone()).collect())
+            .collect(),
+    });
+
+    // Word timings are taken from the tokens before the text is corrected,
+    // then the corrections are carried over to the words
+    let dictionary_start = Instant::now();
+    let detected_language = if config.detect_languages.is_empty() {
+        None
     } else {
-        raw_text
+        let raw_text: Vec<&str> = outputs
+            .iter()
+            .flatten()
+            .map(|output| output.text.as_str())
+            .collect();
+        detect_language(&raw_text.join(" "), &config.detect_languages)
+    };
+    let confidence = confidence(&outputs);
+    let dictionary = dictionary(detected_language.as_ref().map(|d| d.language.as_str()));
+    let corrected = outputs
+        .into_iter()
+        .zip(&ranges)
+        .map(|(outputs, ranges)| {
+            outputs
+                .into_iter()
+                .zip(ranges)
+                .map(|(output, range)| {
+                    let offset = range.start as f32 / TARGET_SAMPLE_RATE as f32;
+                    let mut words = words_from_tokens(&output.tokens, offset);
+                    let raw_text = output.into_text();
+                    let corrected = match dictionary {
+                        Some(dictionary) => dictionary.correct(raw_text)?,
+                        None => CorrectedText {
+                            text: raw_text,
+                            corrections: Vec::new(),
+                        },
+                    };
+                    apply_corrections(&mut words, &corrected.corrections);
+                    Ok((corrected, words))
+                })
+                .collect::<Result<Vec<_>, TranscriptionError>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    timing.dictionary = dictionary_start.elapsed();
+
+    let mut per_channel: Vec<ChannelTranscript> = channels
+        .iter()
+        .zip(ranges)
+        .zip(corrected)
+        .map(|(((channel, _), ranges), corrected)| {
+            assemble_channel(
+                ranges,
+                corrected,
+                options.segment_on_silence,
+                separate.then_some(*channel),
+            )
+        })
+        .collect();
+
+    let mut transcript = Transcript {
+        audio_duration_secs,
+        processing_time_ms: timing.inference.as_millis() as u64,
+        dictionary_time_ms: timing.dictionary.as_millis() as u64,
+        timing,
+        input,
+        detected_language,
+        debug,
+        confidence,
+        ..Default::default()
+    };
+    if separate {
+        transcript.channels = per_channel;
+        transcript.merge_channels();
+    } else if let Some(single) = per_channel.pop() {
+        transcript.text = single.text;
+        transcript.segments = single.segments;
+        transcript.corrections = single.corrections;
+        transcript.words = single.words;
+    }
+
+    if options.include_stats {
+        let samples: Vec<&[f32]> = channels.iter().map(|(_, s)| s.as_slice()).collect();
+        let mut stats = SpeechStats::from_audio(&samples, TARGET_SAMPLE_RATE);
+        stats.count_words(&transcript.text);
+        transcript.stats = Some(stats);
+    }
+
+    Ok(transcript)
+}
+
+/// An engine error, which isn't `Send`, as a [`TranscriptionError`] with
+/// the message it had.
+fn inference_error(during: &str, error: Box<dyn std::error::Error>) -> TranscriptionError {
+    TranscriptionError::Inference(format!("{}: {}", during, error).into())
+}
+
+/// Samples of one channel, with its index in the source file
+type IndexedChannel = (usize, Vec<f32>);
+
+/// Decode the channels to transcribe, each resampled to 16 kHz and paired
+/// with its index in the source file, along with the properties of the
+/// audio as received.
+fn read_channels(
+    audio_path: &std::path::Path,
+    mode: ChannelMode,
+    quality: ResamplerQuality,
+    timing: &mut StageTimings,
+) -> Result<(Vec<IndexedChannel>, InputAudio), TranscriptionError> {
+    let decode_start = Instant::now();
+    let file = std::io::BufReader::new(std::fs::File::open(audio_path)?);
+    if mode == ChannelMode::Downmix {
+        let audio = dsp::read_wav(file).map_err(TranscriptionError::decode)?;
+        let input = InputAudio {
+            sample_rate: audio.sample_rate,
+            channels: audio.source_channels,
+            bits_per_sample: audio.bits_per_sample,
+            duration_secs: audio.samples.len() as f32 / audio.sample_rate as f32,
+            peak: dsp::peak(&audio.samples),
+        };
+        timing.decode = decode_start.elapsed();
+        let resample_start = Instant::now();
+        let samples = to_engine_rate(audio, quality);
+        timing.resample = resample_start.elapsed();
+        return Ok((vec![(0, samples)], input));
+    }
+
+    let audio = dsp::read_wav_channels(file).map_err(TranscriptionError::decode)?;
+    let sample_rate = audio.sample_rate;
+    let input = InputAudio {
+        sample_rate,
+        channels: audio.channels.len() as u16,
+        bits_per_sample: audio.bits_per_sample,
+        duration_secs: audio
+            .channels
+            .first()
+            .map_or(0.0, |samples| samples.len() as f32 / sample_rate as f32),
+        peak: audio
+            .channels
+            .iter()
+            .map(|samples| dsp::peak(samples))
+            .fold(0.0, f32::max),
     };
+    let mut channels: Vec<IndexedChannel> = audio.channels.into_iter().enumerate().collect();
 
-    Ok(text)
+    // Mono input has nothing to pick or split
+    if channels.len() > 1 {
+        match mode {
+            ChannelMode::LeftOnly => channels.truncate(1),
+            ChannelMode::RightOnly => {
+                channels.swap(0, 1);
+                channels.truncate(1);
+            }
+            ChannelMode::Downmix | ChannelMode::Separate => {}
+        }
+    }
+    timing.decode = decode_start.elapsed();
+
+    let resample_start = Instant::now();
+    let channels = channels
+        .into_iter()
+        .map(|(index, samples)| {
+            let mono = MonoAudio {
+                samples,
+                sample_rate,
+                source_channels: 1,
+                bits_per_sample: input.bits_per_sample,
+            };
+            (index, to_engine_rate(mono, quality))
+        })
+        .collect();
+    timing.resample = resample_start.elapsed();
+    Ok((channels, input))
 }
 
-fn resample_linear(input: &[f32], src_hz: usize, dst_hz: usize) -> Vec<f32> {
-    if input.is_empty() || src_hz == 0 || dst_hz == 0 {
-        return Vec::new();
+/// Optional clean-up of 16 kHz samples before segmentation and inference.
+/// The high-pass runs first so rumble doesn't skew the noise profile.
+fn preprocess(samples: &mut Vec<f32>, options: &TranscriptionOptions) {
+    if let Some(cutoff) = options.highpass_hz {
+        dsp::highpass(samples, cutoff, TARGET_SAMPLE_RATE);
     }
-    if src_hz == dst_hz {
-        return input.to_vec();
+    if options.denoise {
+        *samples = dsp::spectral_gate(samples, TARGET_SAMPLE_RATE);
     }
-    let ratio = dst_hz as f64 / src_hz as f64;
-    let out_len = ((input.len() as f64) * ratio).ceil() as usize;
-    if out_len == 0 {
-        return Vec::new();
+    if options.normalize {
+        let peak = dsp::peak(samples);
+        if peak > 0.0 {
+            dsp::apply_gain(samples, dsp::db_to_gain(NORMALIZE_PEAK_DB) / peak);
+        }
     }
-    let mut out = Vec::with_capacity(out_len);
-    let last_idx = input.len().saturating_sub(1);
-    for i in 0..out_len {
-        let t = (i as f64) / ratio;
-        let idx = t.floor() as usize;
-        let frac = (t - idx as f64) as f32;
-        let a = input[idx];
-        let b = input[std::cmp::min(idx + 1, last_idx)];
-        out.push(a + (b - a) * frac);
+}
+
+/// Geometric mean of the probabilities of all decoded tokens, `None` when
+/// there are none.
+fn confidence(outputs: &[Vec<TranscriptionOutput>]) -> Option<f32> {
+    let logprobs: Vec<f32> = outputs
+        .iter()
+        .flatten()
+        .flat_map(|output| &output.tokens)
+        .map(|token| token.logprob)
+        .collect();
+    if logprobs.is_empty() {
+        return None;
+    }
+    let mean = logprobs.iter().sum::<f32>() / logprobs.len() as f32;
+    Some(mean.exp())
+}
+
+/// Group tokens into words at the tokens that start with a space, with
+/// times shifted by `offset` seconds.
+fn words_from_tokens(tokens: &[OutputToken], offset: f32) -> Vec<TranscriptWord> {
+    let mut words: Vec<TranscriptWord> = Vec::new();
+    for token in tokens {
+        let continues_word = !token.text.starts_with(' ');
+        match words.last_mut() {
+            Some(word) if continues_word => {
+                word.text.push_str(&token.text);
+                word.end = offset + token.end;
+                word.logprob += token.logprob;
+            }
+            _ => words.push(TranscriptWord {
+                text: token.text.trim_start().to_string(),
+                start: offset + token.start,
+                end: offset + token.end,
+                logprob: token.logprob,
+                channel: None,
+            }),
+        }
+    }
+    words.retain(|word| !word.text.is_empty());
+    words
+}
+
+/// Replace the corrected words in `words`. Corrections are in text order,
+/// so each one applies to the next word containing its original spelling.
+fn apply_corrections(words: &mut [TranscriptWord], corrections: &[Correction]) {
+    let mut remaining = words.iter_mut();
+    for correction in corrections {
+        if let Some(word) = remaining.find(|word| word.text.contains(&correction.original)) {
+            word.text = word
+                .text
+                .replacen(&correction.original, &correction.replacement, 1);
+        }
+    }
+}
+
+/// Join the corrected text of each range into a channel transcript, with
+/// segments when segmentation was requested.
+fn assemble_channel(
+    ranges: Vec<Range<usize>>,
+    corrected: Vec<(CorrectedText, Vec<TranscriptWord>)>,
+    segment_on_silence: bool,
+    channel: Option<usize>,
+) -> ChannelTranscript {
+    let (corrected, words): (Vec<CorrectedText>, Vec<Vec<TranscriptWord>>) =
+        corrected.into_iter().unzip();
+    let words = words
+        .into_iter()
+        .flatten()
+        .map(|word| TranscriptWord { channel, ..word })
+        .collect();
+
+    if !segment_on_silence {
+        let single = corrected.into_iter().next().unwrap_or_default();
+        return ChannelTranscript {
+            channel: channel.unwrap_or_default(),
+            text: single.text,
+            segments: Vec::new(),
+            corrections: single.corrections,
+            words,
+        };
+    }
+
+    let mut text = String::new();
+    let mut corrections = Vec::new();
+    let mut segments = Vec::with_capacity(ranges.len());
+
+    for (range, segment) in ranges.into_iter().zip(corrected) {
+        let trimmed = segment.text.trim();
+        if !trimmed.is_empty() {
+            if !text.is_empty() {
+                text.push(' ');
+            }
+            // Shift offsets from the segment's raw text into the joined text
+            let leading = segment
+                .text
+                .chars()
+                .take_while(|c| c.is_whitespace())
+                .count();
+            let base = text.chars().count();
+            corrections.extend(segment.corrections.into_iter().map(|c| Correction {
+                offset: base + c.offset - leading,
+                ..c
+            }));
+            text.push_str(trimmed);
+        }
+        segments.push(TranscriptSegment {
+            start: range.start as f32 / TARGET_SAMPLE_RATE as f32,
+            end: range.end as f32 / TARGET_SAMPLE_RATE as f32,
+            text: trimmed.to_string(),
+            channel,
+        });
+    }
+
+    ChannelTranscript {
+        channel: channel.unwrap_or_default(),
+        text,
+        segments,
+        corrections,
+        words,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::parakeet::QuantizationType;
+
+    #[test]
+    fn model_params_follow_the_config() {
+        let config = ServerConfig {
+            model_precision: ModelPrecision::Fp16,
+            intra_op_threads: Some(4),
+            inter_op_threads: Some(2),
+            ..ServerConfig::default()
+        };
+        let params = model_params(&Model::new(config));
+        assert_eq!(params.quantization, QuantizationType::FP16);
+        assert_eq!(params.intra_op_threads, Some(4));
+        assert_eq!(params.inter_op_threads, Some(2));
+        assert_eq!(params.execution_provider, ExecutionProvider::Cpu);
+
+        let params = model_params(&Model::new(ServerConfig::default()));
+        assert_eq!(params.quantization, QuantizationType::Int8);
+        assert_eq!(params.intra_op_threads, None);
+        assert!(params.mmap);
+
+        let config = ServerConfig {
+            mmap_model: false,
+            ..ServerConfig::default()
+        };
+        assert!(!model_params(&Model::new(config)).mmap);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_the_resident_memory() {
+        let resident = resident_bytes().unwrap();
+        assert!(resident > 0 && resident % 1024 == 0, "{}", resident);
+    }
+
+    fn token(text: &str, start: f32, end: f32, logprob: f32) -> OutputToken {
+        OutputToken {
+            text: text.to_string(),
+            start_frame: (start / 0.08) as usize,
+            end_frame: (end / 0.08) as usize,
+            start,
+            end,
+            logprob,
+        }
+    }
+
+    #[test]
+    fn groups_tokens_into_words() {
+        let tokens = [
+            token(" hel", 0.0, 0.08, -0.5),
+            token("lo", 0.08, 0.16, -0.25),
+            token(" ", 0.16, 0.24, 0.0),
+            token(" wor", 0.24, 0.32, -1.0),
+            token("ld.", 0.32, 0.4, 0.0),
+        ];
+        let words = words_from_tokens(&tokens, 1.5);
+        let expected = [("hello", 1.5, 1.66, -0.75), ("world.", 1.74, 1.9, -1.0)];
+        assert_eq!(words.len(), expected.len());
+        for (word, (text, start, end, logprob)) in words.iter().zip(expected) {
+            assert_eq!(word.text, text);
+            assert!((word.start - start).abs() < 1e-5, "{:?}", word);
+            assert!((word.end - end).abs() < 1e-5, "{:?}", word);
+            assert_eq!(word.logprob, logprob);
+            assert_eq!(word.channel, None);
+        }
+        assert!(words_from_tokens(&[], 0.0).is_empty());
+    }
+
+    #[test]
+    fn carries_corrections_over_to_words() {
+        let mut words: Vec<TranscriptWord> = ["murmur", "runs", "murmur,"]
+            .iter()
+            .map(|text| TranscriptWord {
+                text: text.to_string(),
+                ..TranscriptWord::default()
+            })
+            .collect();
+        let correction = |original: &str, replacement: &str| Correction {
+            original: original.to_string(),
+            replacement: replacement.to_string(),
+            offset: 0,
+        };
+        // Only as many words change as there were corrections, in order
+        apply_corrections(&mut words, &[correction("murmur", "Murmure")]);
+        assert_eq!(words[0].text, "Murmure");
+        assert_eq!(words[2].text, "murmur,");
+
+        apply_corrections(&mut words[1..], &[correction("murmur", "Murmure")]);
+        assert_eq!(words[2].text, "Murmure,");
+        assert_eq!(words[1].text, "runs");
     }
-    out
 }
@@ -0,0 +1,234 @@
+// Settings bundles exported by the desktop app
+//
+// A bundle is one JSON file carrying a user's settings, dictionary and
+// shortcuts, and optionally their history settings, so they can be moved
+// to another machine. The server reads the same file as a config source
+// (`MURMURE_SETTINGS_BUNDLE`) to deploy a dictionary tuned on the desktop;
+// it only uses the dictionaries and ignores the rest.
+//
+//     {
+//       "format": "murmure-settings",
+//       "version": 1,
+//       "settings": { "language": "fr" },
+//       "dictionary": ["Kubernetes", "Murmure"],
+//       "dictionaries": { "fr": ["Toulouse"] },
+//       "shortcuts": { "record": "Ctrl+Space" }
+//     }
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ServerConfig;
+
+/// Value of the `format` field, telling a bundle apart from other JSON
+pub const BUNDLE_FORMAT: &str = "murmure-settings";
+
+/// Version of the bundles this build writes, and the newest it reads
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// A settings bundle as exported by the desktop app.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SettingsBundle {
+    /// Always [`BUNDLE_FORMAT`]
+    pub format: String,
+    /// Schema version, at most [`BUNDLE_VERSION`]
+    pub version: u32,
+    /// Desktop app settings (device, language, output mode, ...)
+    #[serde(default)]
+    pub settings: Map<String, Value>,
+    /// Default dictionary words
+    #[serde(default)]
+    pub dictionary: Vec<String>,
+    /// Dictionary words by language code
+    #[serde(default)]
+    pub dictionaries: BTreeMap<String, Vec<String>>,
+    /// Keyboard shortcuts by action
+    #[serde(default)]
+    pub shortcuts: Map<String, Value>,
+    /// History settings, absent when exported without them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<Value>,
+    /// Fields of a later minor revision, kept so they survive a re-export
+    #[serde(flatten)]
+    pub unknown: Map<String, Value>,
+}
+
+impl SettingsBundle {
+    /// Read and check the bundle at `path`. A file that isn't a bundle, or
+    /// comes from a newer version, is rejected; unknown fields are reported
+    /// and kept.
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read(path)
+            .with_context(|| format!("Failed to read settings bundle {}", path.display()))?;
+        let bundle: Self = serde_json::from_slice(&json)
+            .with_context(|| format!("{} is not a valid settings bundle", path.display()))?;
+        bundle.check()?;
+        for key in bundle.unknown.keys() {
+            eprintln!(
+                "Warning: Unknown field '{}' in settings bundle {}, kept as is",
+                key,
+                path.display()
+            );
+        }
+        Ok(bundle)
+    }
+
+    /// The format and version are ones this build understands.
+    pub fn check(&self) -> Result<()> {
+        if self.format != BUNDLE_FORMAT {
+            bail!(
+                "not a settings bundle: format is '{}', expected '{}'",
+                self.format,
+                BUNDLE_FORMAT
+            );
+        }
+        match self.version {
+            0 => bail!("settings bundle has no version"),
+            version if version > BUNDLE_VERSION => bail!(
+                "settings bundle version {} is newer than this build reads ({})",
+                version,
+                BUNDLE_VERSION
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Add the bundle's dictionary words to `config`, after the words it
+    /// already has. Words it already has are not added again.
+    pub fn apply_to(&self, config: &mut ServerConfig) {
+        merge_words(&mut config.dictionary, &self.dictionary);
+        for (language, words) in &self.dictionaries {
+            merge_words(
+                config.dictionaries.entry(language.clone()).or_default(),
+                words,
+            );
+        }
+    }
+}
+
+fn merge_words(into: &mut Vec<String>, words: &[String]) {
+    for word in words {
+        if !into.contains(word) {
+            into.push(word.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn write(dir: &tempfile::TempDir, content: &str) -> std::path::PathBuf {
+        let path = dir.path().join("settings.json");
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn bundle() -> SettingsBundle {
+        SettingsBundle {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            settings: json!({"language": "fr", "device": "USB mic"})
+                .as_object()
+                .unwrap()
+                .clone(),
+            dictionary: vec!["Kubernetes".to_string(), "Murmure".to_string()],
+            dictionaries: [("fr".to_string(), vec!["Toulouse".to_string()])].into(),
+            shortcuts: json!({"record": "Ctrl+Space"}).as_object().unwrap().clone(),
+            history: Some(json!({"keep_days": 30})),
+            unknown: Map::new(),
+        }
+    }
+
+    #[test]
+    fn round_trips_a_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let exported = bundle();
+        let path = write(&dir, &serde_json::to_string_pretty(&exported).unwrap());
+
+        let imported = SettingsBundle::load(&path).unwrap();
+        assert_eq!(
+            serde_json::to_value(&imported).unwrap(),
+            serde_json::to_value(&exported).unwrap()
+        );
+
+        // Exported without history, the field is left out
+        let without_history = SettingsBundle {
+            history: None,
+            ..exported
+        };
+        let json = serde_json::to_value(&without_history).unwrap();
+        assert!(json.get("history").is_none());
+        let path = write(&dir, &json.to_string());
+        assert!(SettingsBundle::load(&path).unwrap().history.is_none());
+    }
+
+    #[test]
+    fn keeps_unknown_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write(
+            &dir,
+            r#"{"format": "murmure-settings", "version": 1, "theme": "dark"}"#,
+        );
+        let bundle = SettingsBundle::load(&path).unwrap();
+        assert_eq!(bundle.unknown["theme"], "dark");
+        assert!(bundle.dictionary.is_empty());
+        assert_eq!(serde_json::to_value(&bundle).unwrap()["theme"], "dark");
+    }
+
+    #[test]
+    fn rejects_invalid_bundles() {
+        let dir = tempfile::tempdir().unwrap();
+        for (content, message) in [
+            (
+                r#"{"format": "murmure-settings", "version": 1, "dictionary": ["Murm"#,
+                "is not a valid settings bundle",
+            ),
+            (
+                r#"{"format": "murmure-settings", "version": 1, "dictionary": "Murmure"}"#,
+                "is not a valid settings bundle",
+            ),
+            (
+                r#"{"format": "vscode-settings", "version": 1}"#,
+                "format is 'vscode-settings', expected 'murmure-settings'",
+            ),
+            (
+                r#"{"format": "murmure-settings", "version": 0}"#,
+                "settings bundle has no version",
+            ),
+            (
+                r#"{"format": "murmure-settings", "version": 2}"#,
+                "settings bundle version 2 is newer than this build reads (1)",
+            ),
+        ] {
+            let path = write(&dir, content);
+            let error = format!("{:#}", SettingsBundle::load(&path).unwrap_err());
+            assert!(error.contains(message), "{}: {}", content, error);
+        }
+
+        let error = SettingsBundle::load(&dir.path().join("missing.json")).unwrap_err();
+        assert!(error.to_string().contains("Failed to read settings bundle"));
+    }
+
+    #[test]
+    fn adds_its_words_after_the_configured_ones() {
+        let mut config = ServerConfig {
+            dictionary: vec!["Murmure".to_string(), "Parakeet".to_string()],
+            dictionaries: [("fr".to_string(), vec!["Lyon".to_string()])].into(),
+            ..ServerConfig::default()
+        };
+        bundle().apply_to(&mut config);
+        assert_eq!(config.dictionary, ["Murmure", "Parakeet", "Kubernetes"]);
+        assert_eq!(config.dictionaries["fr"], ["Lyon", "Toulouse"]);
+
+        // Applying it again changes nothing
+        bundle().apply_to(&mut config);
+        assert_eq!(config.dictionary.len(), 3);
+        assert_eq!(config.dictionaries["fr"].len(), 2);
+    }
+}
@@ -0,0 +1,177 @@
+// Voice command spotting
+//
+// A final transcript that, as a whole, sounds like one of the configured
+// phrases ("new line", "stop listening") is reported as that command along
+// with its text. Case and punctuation are ignored and small differences are
+// tolerated through a normalized edit distance, so "New lines." still
+// matches "new line". A phrase inside longer dictation is never a command:
+// "add a new line after the title" stays plain text.
+
+use serde::{Deserialize, Serialize};
+
+/// A spoken phrase and the action it stands for.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct VoiceCommand {
+    /// What the user says, e.g. "delete that"
+    pub phrase: String,
+    /// Identifier passed back to the client, e.g. "delete_last"
+    pub action: String,
+}
+
+/// A command recognized in a transcript.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedCommand {
+    /// Configured phrase that matched
+    pub phrase: String,
+    pub action: String,
+    /// Similarity between the transcript and the phrase, 0 to 1
+    pub score: f32,
+}
+
+/// The command whose phrase is closest to the whole of `text`, if it scores
+/// at least `threshold`. Ties go to the command configured first.
+pub fn detect_command(
+    text: &str,
+    commands: &[VoiceCommand],
+    threshold: f32,
+) -> Option<DetectedCommand> {
+    let spoken = normalize(text);
+    if spoken.is_empty() {
+        return None;
+    }
+
+    let mut best: Option<(&VoiceCommand, f32)> = None;
+    for command in commands {
+        let score = similarity(&spoken, &normalize(&command.phrase));
+        if score >= threshold && best.map_or(true, |(_, best_score)| score > best_score) {
+            best = Some((command, score));
+        }
+    }
+    best.map(|(command, score)| DetectedCommand {
+        phrase: command.phrase.clone(),
+        action: command.action.clone(),
+        score,
+    })
+}
+
+/// Lowercase words separated by single spaces, punctuation removed.
+fn normalize(text: &str) -> Vec<char> {
+    let lowered = text.to_lowercase();
+    let words: Vec<&str> = lowered
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| !word.is_empty())
+        .collect();
+    words.join(" ").chars().collect()
+}
+
+/// 1 minus the edit distance relative to the longer string.
+fn similarity(a: &[char], b: &[char]) -> f32 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(a, b) as f32 / longest as f32
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> Vec<VoiceCommand> {
+        [
+            ("new line", "newline"),
+            ("delete that", "delete_last"),
+            ("stop listening", "stop"),
+        ]
+        .iter()
+        .map(|(phrase, action)| VoiceCommand {
+            phrase: phrase.to_string(),
+            action: action.to_string(),
+        })
+        .collect()
+    }
+
+    fn action(text: &str) -> Option<String> {
+        detect_command(text, &commands(), 0.8).map(|command| command.action)
+    }
+
+    #[test]
+    fn matches_exact_phrases() {
+        let command = detect_command("delete that", &commands(), 0.8).unwrap();
+        assert_eq!(command.phrase, "delete that");
+        assert_eq!(command.action, "delete_last");
+        assert_eq!(command.score, 1.0);
+    }
+
+    #[test]
+    fn ignores_case_and_punctuation() {
+        assert_eq!(action("Stop listening!").as_deref(), Some("stop"));
+        assert_eq!(action("  New, line. ").as_deref(), Some("newline"));
+    }
+
+    #[test]
+    fn tolerates_small_differences() {
+        let command = detect_command("New lines.", &commands(), 0.8).unwrap();
+        assert_eq!(command.action, "newline");
+        assert!(command.score < 1.0 && command.score >= 0.8);
+        assert_eq!(action("delete dat").as_deref(), Some("delete_last"));
+    }
+
+    #[test]
+    fn near_misses_are_dictation() {
+        assert_eq!(action("new list"), None);
+        assert_eq!(action("stop it"), None);
+        assert_eq!(action(""), None);
+        assert_eq!(action("..."), None);
+    }
+
+    #[test]
+    fn phrases_inside_dictation_are_not_commands() {
+        assert_eq!(action("add a new line after the title"), None);
+        assert_eq!(action("please delete that paragraph"), None);
+    }
+
+    #[test]
+    fn the_closest_phrase_wins() {
+        let commands = [
+            VoiceCommand {
+                phrase: "new line".to_string(),
+                action: "newline".to_string(),
+            },
+            VoiceCommand {
+                phrase: "new lines".to_string(),
+                action: "newlines".to_string(),
+            },
+            VoiceCommand {
+                phrase: "new line".to_string(),
+                action: "duplicate".to_string(),
+            },
+        ];
+        let command = detect_command("new lines", &commands, 0.5).unwrap();
+        assert_eq!(command.action, "newlines");
+        // Ties go to the command configured first
+        let command = detect_command("new line", &commands, 0.5).unwrap();
+        assert_eq!(command.action, "newline");
+    }
+
+    #[test]
+    fn the_threshold_decides() {
+        assert!(detect_command("new lime", &commands(), 0.9).is_none());
+        assert!(detect_command("new lime", &commands(), 0.8).is_some());
+    }
+}
@@ -1,6 +1,16 @@
+use crate::bundle::SettingsBundle;
+use crate::commands::VoiceCommand;
+use crate::dsp::ResamplerQuality;
+use crate::engine::transcription_engine::DecodingOptions;
+use crate::filter::ProfanityFilter;
+use crate::model::{ExecutionProvider, ModelPrecision, ModelSpec};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::{env, fs, path::PathBuf};
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, fmt, fs};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(default)]
@@ -8,8 +18,239 @@ pub struct ServerConfig {
     pub model_path: Option<PathBuf>,
     pub cc_rules_path: Option<PathBuf>,
     pub dictionary: Vec<String>,
+    /// Word lists by language code (`en`, `fr`, ...), used instead of
+    /// `dictionary` for requests in that language
+    pub dictionaries: BTreeMap<String, Vec<String>>,
+    /// Start with a dictionary even when the cc-rules directory can't be
+    /// found, returning transcripts uncorrected (otherwise startup fails)
+    pub allow_missing_cc_rules: bool,
     pub grpc_port: u16,
+    /// Addresses the gRPC server listens on, each with its own TLS settings
+    /// (empty = plaintext on all interfaces at `grpc_port`)
+    pub grpc_listeners: Vec<GrpcListener>,
     pub log_level: String,
+    /// Seconds to wait for the model path to appear at startup (0 = fail immediately)
+    pub model_wait_secs: u64,
+    /// Marker file written once the engine is loaded and warmed, removed on shutdown
+    pub ready_file: Option<PathBuf>,
+    /// Split transcriptions into segments at long pauses by default
+    pub segment_on_silence: bool,
+    /// Minimum pause length (ms) that starts a new segment
+    pub segment_min_silence_ms: u64,
+    /// Resampling algorithm for input that isn't already 16 kHz
+    pub resampler_quality: ResamplerQuality,
+    /// Cutoff of the high-pass filter removing low-frequency rumble, in Hz (None = off)
+    pub highpass_hz: Option<f32>,
+    /// Reduce steady background noise before transcription by default
+    pub denoise: bool,
+    /// Maximum time (ms) a request waits for the engine before failing (0 = no limit)
+    pub engine_wait_ms: u64,
+    /// Weight precision variant to load from the model directory
+    pub model_precision: ModelPrecision,
+    /// Panics after which a loaded engine is reloaded (0 = never reload)
+    pub engine_panic_threshold: u32,
+    /// ONNX Runtime intra-op threads (None = runtime default)
+    pub intra_op_threads: Option<usize>,
+    /// ONNX Runtime inter-op threads (None = sequential execution)
+    pub inter_op_threads: Option<usize>,
+    /// ONNX Runtime execution provider for the STT engine
+    pub execution_provider: ExecutionProvider,
+    /// Memory-map ORT-format model files (`.ort`) instead of reading them
+    pub mmap_model: bool,
+    /// Default profanity handling for transcripts
+    pub profanity_filter: ProfanityFilter,
+    /// Extra profanity words, one per line (added to the built-in list)
+    pub profanity_words_path: Option<PathBuf>,
+    /// Regexes whose matches are replaced with "[REDACTED]"
+    pub redact_patterns: Vec<String>,
+    /// Add sentence punctuation and capitalization to transcripts by default
+    pub punctuate: bool,
+    /// Rewrite spoken numbers, times, dates and amounts in written form by default
+    pub itn: bool,
+    /// Language used for inverse text normalization
+    pub itn_language: String,
+    /// Shortest audio accepted per request, in milliseconds (0 = no minimum)
+    pub min_audio_ms: u64,
+    /// Longest audio accepted per request, in seconds (0 = no limit)
+    pub max_audio_secs: u64,
+    /// Longest audio accepted by TranscribeLargeFile, in seconds (None =
+    /// same as `max_audio_secs`, 0 = no limit)
+    pub large_file_max_audio_secs: Option<u64>,
+    /// Largest upload accepted by TranscribeLargeFile, in bytes (0 = no limit)
+    pub max_upload_bytes: u64,
+    /// Directory where audio of dropped streams is saved and transcribed (None = disabled)
+    pub salvage_dir: Option<PathBuf>,
+    /// Minimum buffered audio, in seconds, worth salvaging from a dropped stream
+    pub salvage_min_secs: u64,
+    /// Size cap for the salvage directory, in bytes; oldest files are evicted first (0 = no limit)
+    pub salvage_max_bytes: u64,
+    /// Directory where the audio and engine output of flagged requests are
+    /// dumped for debugging (None = disabled)
+    pub debug_dump_dir: Option<PathBuf>,
+    /// Secret a request sends in `x-murmure-debug-dump` to be dumped
+    pub debug_dump_token: Option<String>,
+    /// Size cap for the debug dump directory, in bytes; oldest dumps are
+    /// evicted first
+    pub debug_dump_max_bytes: u64,
+    /// Days a dump is kept before it is deleted (0 = until evicted)
+    pub debug_dump_retention_days: u64,
+    /// Directory for temporary audio files, swept of stale files by the server
+    pub temp_dir: PathBuf,
+    /// Free space, in megabytes, to keep in `temp_dir`; uploads that would
+    /// go below it are refused (0 = no check)
+    pub min_free_disk_mb: u64,
+    /// Named models selectable per request, the first being the default
+    /// (empty = the single model at `model_path`)
+    pub models: Vec<ModelSpec>,
+    /// Most models kept loaded at once; the least recently used is unloaded first
+    pub max_loaded_models: usize,
+    /// Requests per minute allowed per client (0 = no limit)
+    pub rate_limit_rpm: u32,
+    /// Seconds of audio a client may have in transcription at once (0 = no limit)
+    pub rate_limit_audio_secs: u64,
+    /// Per-client limits replacing the defaults above, by API key or peer IP
+    pub rate_limit_overrides: Vec<RateLimitOverride>,
+    /// What to do with audio of a stream that ends without EndOfStream
+    pub incomplete_stream: IncompleteStream,
+    /// Port of the WebSocket bridge for browser clients (None = disabled)
+    pub ws_port: Option<u16>,
+    /// Longest a WebSocket session may stay open, in seconds (0 = no limit)
+    pub ws_max_session_secs: u64,
+    /// Origins of the web pages allowed to open WebSocket sessions, e.g.
+    /// `https://dictation.example.com` (empty = no browser page)
+    pub ws_allowed_origins: Vec<String>,
+    /// Seconds between heartbeat responses on TranscribeStream (0 = none)
+    pub stream_heartbeat_secs: u64,
+    /// Seconds between HTTP/2 keepalive pings on gRPC connections (0 = none)
+    pub http2_keepalive_secs: u64,
+    /// Seconds to wait for a keepalive ping to be acknowledged before the
+    /// connection is closed
+    pub http2_keepalive_timeout_secs: u64,
+    /// Phrases reported as voice commands when an utterance consists of one
+    pub commands: Vec<VoiceCommand>,
+    /// Similarity (0 to 1) an utterance needs with a phrase to count as that
+    /// command
+    pub command_threshold: f32,
+    /// Languages transcripts are identified as, reported with each result and
+    /// used to pick a dictionary when the request names no language (empty =
+    /// no detection)
+    pub detect_languages: Vec<String>,
+    /// Confidence (0 to 1) below which a two-pass request is transcribed a
+    /// second time with heavier preprocessing
+    pub two_pass_confidence: f32,
+    /// Model of the second pass, one of `models` (None = the request's model)
+    pub two_pass_model: Option<String>,
+    /// How long a finished stream's session can be resumed, in seconds
+    /// (0 = streams get no session id)
+    pub session_ttl_secs: u64,
+    /// Largest unfinished utterance kept for a resumed session, in bytes;
+    /// larger ones are salvaged or discarded as usual (0 = keep no audio)
+    pub session_resume_max_bytes: u64,
+    /// How long the response to a TranscribeFile request sent with an
+    /// idempotency key is replayed to retries, in seconds (0 = keys ignored)
+    pub idempotency_ttl_secs: u64,
+    /// Most idempotency keys remembered at once; the oldest are forgotten
+    /// first
+    pub idempotency_max_entries: usize,
+    /// Background transcription jobs run at once (0 = job RPCs disabled)
+    pub job_workers: usize,
+    /// Jobs waiting for a worker before submissions are rejected
+    pub job_queue_depth: usize,
+    /// How long a finished job's status and result are kept, in seconds
+    pub job_retention_secs: u64,
+    /// Directory where queued and finished jobs are kept across restarts
+    /// (unset = jobs are lost on restart)
+    pub job_dir: Option<PathBuf>,
+    /// Seconds of audio a client may have transcribed per calendar month, UTC
+    /// (0 = no quota)
+    pub usage_monthly_quota_secs: u64,
+    /// Whether responses replayed for an idempotency key count as usage again
+    pub usage_count_replays: bool,
+    /// JSON file where usage totals are kept across restarts
+    /// (unset = usage is lost on restart)
+    pub usage_file: Option<PathBuf>,
+    /// Secret a request sends in `x-murmure-admin-token` to see the usage of
+    /// every client (None = each client only sees its own)
+    pub admin_token: Option<String>,
+    /// URL prefixes TranscribeUrl may fetch from (empty = TranscribeUrl
+    /// disabled)
+    pub url_allowlist: Vec<String>,
+    /// Longest a TranscribeUrl fetch may take, body included, in seconds
+    pub url_fetch_timeout_secs: u64,
+    /// Redirects followed by a TranscribeUrl fetch, each to an allowed URL
+    pub url_max_redirects: usize,
+    /// Subtracted from the blank logit while decoding: positive values keep
+    /// more of quiet or fast speech, negative ones drop more noise
+    pub decoding_blank_penalty: f32,
+    /// Tokens the decoder may emit on one encoder frame
+    pub decoding_max_symbols_per_step: usize,
+    /// Tokens decoded per segment before decoding stops (0 = no limit)
+    pub decoding_max_tokens: usize,
+    /// Settings bundle exported by the desktop app whose dictionaries are
+    /// added to `dictionary` and `dictionaries`
+    pub settings_bundle: Option<PathBuf>,
+}
+
+/// Handling of audio buffered by a stream that ends without EndOfStream.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IncompleteStream {
+    /// Transcribe it: reply when the client only half-closed, salvage it
+    /// when the connection broke (default)
+    #[default]
+    Transcribe,
+    /// Drop it without transcribing
+    Discard,
+}
+
+impl FromStr for IncompleteStream {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "transcribe" => Ok(Self::Transcribe),
+            "discard" => Ok(Self::Discard),
+            other => Err(anyhow::anyhow!(
+                "Unknown incomplete stream handling '{}', expected 'transcribe' or 'discard'",
+                other
+            )),
+        }
+    }
+}
+
+/// An address the gRPC server listens on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GrpcListener {
+    /// Address and port to bind, e.g. `127.0.0.1:50051` or `[::]:50052`
+    pub addr: SocketAddr,
+    /// PEM certificate chain; with `tls_key`, the listener serves TLS
+    #[serde(default)]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of `tls_cert`
+    #[serde(default)]
+    pub tls_key: Option<PathBuf>,
+}
+
+impl GrpcListener {
+    pub fn is_tls(&self) -> bool {
+        self.tls_cert.is_some() && self.tls_key.is_some()
+    }
+}
+
+/// Rate limits for one client, identified by API key or peer IP.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RateLimitOverride {
+    /// `x-api-key` value or peer IP address
+    pub key: String,
+    /// Requests per minute (unset = server default, 0 = no limit)
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// Seconds of audio in transcription at once (unset = server default, 0 = no limit)
+    #[serde(default)]
+    pub audio_secs: Option<u64>,
+    /// Seconds of audio per calendar month (unset = server default, 0 = no quota)
+    #[serde(default)]
+    pub monthly_audio_secs: Option<u64>,
 }
 
 impl Default for ServerConfig {
@@ -18,8 +259,80 @@ impl Default for ServerConfig {
             model_path: None,
             cc_rules_path: None,
             dictionary: Vec::new(),
+            dictionaries: BTreeMap::new(),
+            allow_missing_cc_rules: false,
             grpc_port: 50051,
+            grpc_listeners: Vec::new(),
             log_level: "info".to_string(),
+            model_wait_secs: 0,
+            ready_file: None,
+            segment_on_silence: false,
+            segment_min_silence_ms: 700,
+            resampler_quality: ResamplerQuality::default(),
+            highpass_hz: None,
+            denoise: false,
+            engine_wait_ms: 30_000,
+            model_precision: ModelPrecision::default(),
+            engine_panic_threshold: 3,
+            intra_op_threads: None,
+            inter_op_threads: None,
+            execution_provider: ExecutionProvider::default(),
+            mmap_model: true,
+            profanity_filter: ProfanityFilter::default(),
+            profanity_words_path: None,
+            redact_patterns: Vec::new(),
+            punctuate: false,
+            itn: false,
+            itn_language: "en".to_string(),
+            min_audio_ms: 100,
+            max_audio_secs: 1800,
+            large_file_max_audio_secs: None,
+            max_upload_bytes: 1024 * 1024 * 1024,
+            salvage_dir: None,
+            salvage_min_secs: 2,
+            salvage_max_bytes: 512 * 1024 * 1024,
+            debug_dump_dir: None,
+            debug_dump_token: None,
+            debug_dump_max_bytes: 256 * 1024 * 1024,
+            debug_dump_retention_days: 7,
+            temp_dir: env::temp_dir().join("murmure"),
+            min_free_disk_mb: 512,
+            models: Vec::new(),
+            max_loaded_models: 2,
+            rate_limit_rpm: 0,
+            rate_limit_audio_secs: 0,
+            rate_limit_overrides: Vec::new(),
+            incomplete_stream: IncompleteStream::default(),
+            ws_port: None,
+            ws_max_session_secs: 600,
+            ws_allowed_origins: Vec::new(),
+            stream_heartbeat_secs: 15,
+            http2_keepalive_secs: 0,
+            http2_keepalive_timeout_secs: 20,
+            commands: Vec::new(),
+            command_threshold: 0.8,
+            detect_languages: Vec::new(),
+            two_pass_confidence: 0.7,
+            two_pass_model: None,
+            session_ttl_secs: 300,
+            session_resume_max_bytes: 0,
+            idempotency_ttl_secs: 600,
+            idempotency_max_entries: 1000,
+            job_workers: 1,
+            job_queue_depth: 32,
+            job_retention_secs: 3600,
+            job_dir: None,
+            usage_monthly_quota_secs: 0,
+            usage_count_replays: false,
+            usage_file: None,
+            admin_token: None,
+            url_allowlist: Vec::new(),
+            url_fetch_timeout_secs: 60,
+            url_max_redirects: 3,
+            decoding_blank_penalty: 0.0,
+            decoding_max_symbols_per_step: DecodingOptions::default().max_symbols_per_step,
+            decoding_max_tokens: 0,
+            settings_bundle: None,
         }
     }
 }
@@ -42,16 +355,426 @@ impl ServerConfig {
                 .context("Failed to parse MURMURE_DICTIONARY as JSON array")?;
         }
 
+        if let Ok(dicts_json) = env::var("MURMURE_DICTIONARIES") {
+            config.dictionaries = serde_json::from_str(&dicts_json).context(
+                "Failed to parse MURMURE_DICTIONARIES as a JSON object of language to word array",
+            )?;
+        }
+
+        if let Ok(allow_str) = env::var("MURMURE_ALLOW_MISSING_CC_RULES") {
+            config.allow_missing_cc_rules = allow_str
+                .parse()
+                .context("MURMURE_ALLOW_MISSING_CC_RULES must be true or false")?;
+        }
+
         if let Ok(port_str) = env::var("MURMURE_GRPC_PORT") {
             config.grpc_port = port_str
                 .parse()
                 .context("MURMURE_GRPC_PORT must be a valid port number")?;
         }
 
+        if let Ok(listeners_json) = env::var("MURMURE_GRPC_LISTENERS") {
+            config.grpc_listeners = serde_json::from_str(&listeners_json)
+                .context("Failed to parse MURMURE_GRPC_LISTENERS as JSON array")?;
+        }
+
         if let Ok(log_level) = env::var("MURMURE_LOG_LEVEL") {
             config.log_level = log_level;
         }
 
+        if let Ok(wait_str) = env::var("MURMURE_MODEL_WAIT_SECS") {
+            config.model_wait_secs = wait_str
+                .parse()
+                .context("MURMURE_MODEL_WAIT_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(ready_file) = env::var("MURMURE_READY_FILE") {
+            config.ready_file = Some(PathBuf::from(ready_file));
+        }
+
+        if let Ok(segment_str) = env::var("MURMURE_SEGMENT_ON_SILENCE") {
+            config.segment_on_silence = segment_str
+                .parse()
+                .context("MURMURE_SEGMENT_ON_SILENCE must be true or false")?;
+        }
+
+        if let Ok(silence_str) = env::var("MURMURE_SEGMENT_MIN_SILENCE_MS") {
+            config.segment_min_silence_ms = silence_str
+                .parse()
+                .context("MURMURE_SEGMENT_MIN_SILENCE_MS must be a number of milliseconds")?;
+        }
+
+        if let Ok(quality_str) = env::var("MURMURE_RESAMPLER_QUALITY") {
+            config.resampler_quality = quality_str
+                .parse()
+                .context("MURMURE_RESAMPLER_QUALITY must be 'linear' or 'high'")?;
+        }
+
+        if let Ok(cutoff_str) = env::var("MURMURE_HIGHPASS_HZ") {
+            config.highpass_hz = Some(
+                cutoff_str
+                    .parse()
+                    .context("MURMURE_HIGHPASS_HZ must be a frequency in Hz")?,
+            );
+        }
+
+        if let Ok(denoise_str) = env::var("MURMURE_DENOISE") {
+            config.denoise = denoise_str
+                .parse()
+                .context("MURMURE_DENOISE must be true or false")?;
+        }
+
+        if let Ok(wait_str) = env::var("MURMURE_ENGINE_WAIT_MS") {
+            config.engine_wait_ms = wait_str
+                .parse()
+                .context("MURMURE_ENGINE_WAIT_MS must be a number of milliseconds")?;
+        }
+
+        if let Ok(precision_str) = env::var("MURMURE_MODEL_PRECISION") {
+            config.model_precision = precision_str
+                .parse()
+                .context("MURMURE_MODEL_PRECISION must be 'int8', 'fp16' or 'fp32'")?;
+        }
+
+        if let Ok(threshold_str) = env::var("MURMURE_ENGINE_PANIC_THRESHOLD") {
+            config.engine_panic_threshold = threshold_str
+                .parse()
+                .context("MURMURE_ENGINE_PANIC_THRESHOLD must be a number of panics")?;
+        }
+
+        if let Ok(threads_str) = env::var("MURMURE_ONNX_THREADS") {
+            config.intra_op_threads = Some(
+                threads_str
+                    .parse()
+                    .context("MURMURE_ONNX_THREADS must be a number of threads")?,
+            );
+        }
+
+        if let Ok(threads_str) = env::var("MURMURE_ONNX_INTER_THREADS") {
+            config.inter_op_threads = Some(
+                threads_str
+                    .parse()
+                    .context("MURMURE_ONNX_INTER_THREADS must be a number of threads")?,
+            );
+        }
+
+        if let Ok(provider_str) = env::var("MURMURE_EXECUTION_PROVIDER") {
+            config.execution_provider = provider_str.parse().context(
+                "MURMURE_EXECUTION_PROVIDER must be 'cpu', 'cuda', 'coreml' or 'directml'",
+            )?;
+        }
+
+        if let Ok(mmap_str) = env::var("MURMURE_MMAP_MODEL") {
+            config.mmap_model = mmap_str
+                .parse()
+                .context("MURMURE_MMAP_MODEL must be true or false")?;
+        }
+
+        if let Ok(filter_str) = env::var("MURMURE_PROFANITY_FILTER") {
+            config.profanity_filter = filter_str
+                .parse()
+                .context("MURMURE_PROFANITY_FILTER must be 'off', 'mask' or 'remove'")?;
+        }
+
+        if let Ok(words_path) = env::var("MURMURE_PROFANITY_WORDS_FILE") {
+            config.profanity_words_path = Some(PathBuf::from(words_path));
+        }
+
+        if let Ok(patterns_json) = env::var("MURMURE_REDACT_PATTERNS") {
+            config.redact_patterns = serde_json::from_str(&patterns_json)
+                .context("Failed to parse MURMURE_REDACT_PATTERNS as JSON array")?;
+        }
+
+        if let Ok(punctuate_str) = env::var("MURMURE_PUNCTUATE") {
+            config.punctuate = punctuate_str
+                .parse()
+                .context("MURMURE_PUNCTUATE must be true or false")?;
+        }
+
+        if let Ok(itn_str) = env::var("MURMURE_ITN") {
+            config.itn = itn_str
+                .parse()
+                .context("MURMURE_ITN must be true or false")?;
+        }
+
+        if let Ok(language) = env::var("MURMURE_ITN_LANGUAGE") {
+            config.itn_language = language;
+        }
+
+        if let Ok(min_str) = env::var("MURMURE_MIN_AUDIO_MS") {
+            config.min_audio_ms = min_str
+                .parse()
+                .context("MURMURE_MIN_AUDIO_MS must be a number of milliseconds")?;
+        }
+
+        if let Ok(max_str) = env::var("MURMURE_MAX_AUDIO_SECS") {
+            config.max_audio_secs = max_str
+                .parse()
+                .context("MURMURE_MAX_AUDIO_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(max_str) = env::var("MURMURE_LARGE_FILE_MAX_AUDIO_SECS") {
+            config.large_file_max_audio_secs = Some(
+                max_str
+                    .parse()
+                    .context("MURMURE_LARGE_FILE_MAX_AUDIO_SECS must be a number of seconds")?,
+            );
+        }
+
+        if let Ok(max_str) = env::var("MURMURE_MAX_UPLOAD_BYTES") {
+            config.max_upload_bytes = max_str
+                .parse()
+                .context("MURMURE_MAX_UPLOAD_BYTES must be a number of bytes")?;
+        }
+
+        if let Ok(salvage_dir) = env::var("MURMURE_SALVAGE_DIR") {
+            config.salvage_dir = Some(PathBuf::from(salvage_dir));
+        }
+
+        if let Ok(min_str) = env::var("MURMURE_SALVAGE_MIN_SECS") {
+            config.salvage_min_secs = min_str
+                .parse()
+                .context("MURMURE_SALVAGE_MIN_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(max_str) = env::var("MURMURE_SALVAGE_MAX_BYTES") {
+            config.salvage_max_bytes = max_str
+                .parse()
+                .context("MURMURE_SALVAGE_MAX_BYTES must be a number of bytes")?;
+        }
+
+        if let Ok(dump_dir) = env::var("MURMURE_DEBUG_DUMP_DIR") {
+            config.debug_dump_dir = Some(PathBuf::from(dump_dir));
+        }
+
+        if let Ok(token) = env::var("MURMURE_DEBUG_DUMP_TOKEN") {
+            config.debug_dump_token = Some(token);
+        }
+
+        if let Ok(max_str) = env::var("MURMURE_DEBUG_DUMP_MAX_BYTES") {
+            config.debug_dump_max_bytes = max_str
+                .parse()
+                .context("MURMURE_DEBUG_DUMP_MAX_BYTES must be a number of bytes")?;
+        }
+
+        if let Ok(days_str) = env::var("MURMURE_DEBUG_DUMP_RETENTION_DAYS") {
+            config.debug_dump_retention_days = days_str
+                .parse()
+                .context("MURMURE_DEBUG_DUMP_RETENTION_DAYS must be a number of days")?;
+        }
+
+        if let Ok(temp_dir) = env::var("MURMURE_TEMP_DIR") {
+            config.temp_dir = PathBuf::from(temp_dir);
+        }
+
+        if let Ok(min_str) = env::var("MURMURE_MIN_FREE_DISK_MB") {
+            config.min_free_disk_mb = min_str
+                .parse()
+                .context("MURMURE_MIN_FREE_DISK_MB must be a number of megabytes")?;
+        }
+
+        if let Ok(models_json) = env::var("MURMURE_MODELS") {
+            config.models = serde_json::from_str(&models_json)
+                .context("Failed to parse MURMURE_MODELS as JSON array")?;
+        }
+
+        if let Ok(max_str) = env::var("MURMURE_MAX_LOADED_MODELS") {
+            config.max_loaded_models = max_str
+                .parse()
+                .context("MURMURE_MAX_LOADED_MODELS must be a number of models")?;
+        }
+
+        if let Ok(rpm_str) = env::var("MURMURE_RATE_LIMIT_RPM") {
+            config.rate_limit_rpm = rpm_str
+                .parse()
+                .context("MURMURE_RATE_LIMIT_RPM must be a number of requests per minute")?;
+        }
+
+        if let Ok(secs_str) = env::var("MURMURE_RATE_LIMIT_AUDIO_SECS") {
+            config.rate_limit_audio_secs = secs_str
+                .parse()
+                .context("MURMURE_RATE_LIMIT_AUDIO_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(overrides_json) = env::var("MURMURE_RATE_LIMIT_OVERRIDES") {
+            config.rate_limit_overrides = serde_json::from_str(&overrides_json)
+                .context("Failed to parse MURMURE_RATE_LIMIT_OVERRIDES as JSON array")?;
+        }
+
+        if let Ok(handling_str) = env::var("MURMURE_INCOMPLETE_STREAM") {
+            config.incomplete_stream = handling_str
+                .parse()
+                .context("MURMURE_INCOMPLETE_STREAM must be 'transcribe' or 'discard'")?;
+        }
+
+        if let Ok(port_str) = env::var("MURMURE_WS_PORT") {
+            config.ws_port = Some(
+                port_str
+                    .parse()
+                    .context("MURMURE_WS_PORT must be a valid port number")?,
+            );
+        }
+
+        if let Ok(secs_str) = env::var("MURMURE_WS_MAX_SESSION_SECS") {
+            config.ws_max_session_secs = secs_str
+                .parse()
+                .context("MURMURE_WS_MAX_SESSION_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(origins_json) = env::var("MURMURE_WS_ALLOWED_ORIGINS") {
+            config.ws_allowed_origins = serde_json::from_str(&origins_json)
+                .context("Failed to parse MURMURE_WS_ALLOWED_ORIGINS as JSON array")?;
+        }
+
+        if let Ok(secs_str) = env::var("MURMURE_STREAM_HEARTBEAT_SECS") {
+            config.stream_heartbeat_secs = secs_str
+                .parse()
+                .context("MURMURE_STREAM_HEARTBEAT_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(secs_str) = env::var("MURMURE_HTTP2_KEEPALIVE_SECS") {
+            config.http2_keepalive_secs = secs_str
+                .parse()
+                .context("MURMURE_HTTP2_KEEPALIVE_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(secs_str) = env::var("MURMURE_HTTP2_KEEPALIVE_TIMEOUT_SECS") {
+            config.http2_keepalive_timeout_secs = secs_str
+                .parse()
+                .context("MURMURE_HTTP2_KEEPALIVE_TIMEOUT_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(commands_json) = env::var("MURMURE_COMMANDS") {
+            config.commands = serde_json::from_str(&commands_json)
+                .context("Failed to parse MURMURE_COMMANDS as JSON array")?;
+        }
+
+        if let Ok(threshold_str) = env::var("MURMURE_COMMAND_THRESHOLD") {
+            config.command_threshold = threshold_str
+                .parse()
+                .context("MURMURE_COMMAND_THRESHOLD must be a number between 0 and 1")?;
+        }
+
+        if let Ok(languages_json) = env::var("MURMURE_DETECT_LANGUAGES") {
+            config.detect_languages = serde_json::from_str(&languages_json)
+                .context("Failed to parse MURMURE_DETECT_LANGUAGES as JSON array")?;
+        }
+
+        if let Ok(confidence_str) = env::var("MURMURE_TWO_PASS_CONFIDENCE") {
+            config.two_pass_confidence = confidence_str
+                .parse()
+                .context("MURMURE_TWO_PASS_CONFIDENCE must be a number between 0 and 1")?;
+        }
+
+        if let Ok(model) = env::var("MURMURE_TWO_PASS_MODEL") {
+            config.two_pass_model = Some(model);
+        }
+
+        if let Ok(ttl_str) = env::var("MURMURE_SESSION_TTL_SECS") {
+            config.session_ttl_secs = ttl_str
+                .parse()
+                .context("MURMURE_SESSION_TTL_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(bytes_str) = env::var("MURMURE_SESSION_RESUME_MAX_BYTES") {
+            config.session_resume_max_bytes = bytes_str
+                .parse()
+                .context("MURMURE_SESSION_RESUME_MAX_BYTES must be a number of bytes")?;
+        }
+
+        if let Ok(ttl_str) = env::var("MURMURE_IDEMPOTENCY_TTL_SECS") {
+            config.idempotency_ttl_secs = ttl_str
+                .parse()
+                .context("MURMURE_IDEMPOTENCY_TTL_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(max_str) = env::var("MURMURE_IDEMPOTENCY_MAX_ENTRIES") {
+            config.idempotency_max_entries = max_str
+                .parse()
+                .context("MURMURE_IDEMPOTENCY_MAX_ENTRIES must be a positive integer")?;
+        }
+
+        if let Ok(workers_str) = env::var("MURMURE_JOB_WORKERS") {
+            config.job_workers = workers_str
+                .parse()
+                .context("MURMURE_JOB_WORKERS must be a number of workers")?;
+        }
+
+        if let Ok(depth_str) = env::var("MURMURE_JOB_QUEUE_DEPTH") {
+            config.job_queue_depth = depth_str
+                .parse()
+                .context("MURMURE_JOB_QUEUE_DEPTH must be a positive integer")?;
+        }
+
+        if let Ok(retention_str) = env::var("MURMURE_JOB_RETENTION_SECS") {
+            config.job_retention_secs = retention_str
+                .parse()
+                .context("MURMURE_JOB_RETENTION_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(job_dir) = env::var("MURMURE_JOB_DIR") {
+            config.job_dir = Some(PathBuf::from(job_dir));
+        }
+
+        if let Ok(quota_str) = env::var("MURMURE_USAGE_MONTHLY_QUOTA_SECS") {
+            config.usage_monthly_quota_secs = quota_str
+                .parse()
+                .context("MURMURE_USAGE_MONTHLY_QUOTA_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(replays_str) = env::var("MURMURE_USAGE_COUNT_REPLAYS") {
+            config.usage_count_replays = replays_str
+                .parse()
+                .context("MURMURE_USAGE_COUNT_REPLAYS must be true or false")?;
+        }
+
+        if let Ok(usage_file) = env::var("MURMURE_USAGE_FILE") {
+            config.usage_file = Some(PathBuf::from(usage_file));
+        }
+
+        if let Ok(token) = env::var("MURMURE_ADMIN_TOKEN") {
+            config.admin_token = Some(token);
+        }
+
+        if let Ok(allowlist_json) = env::var("MURMURE_URL_ALLOWLIST") {
+            config.url_allowlist = serde_json::from_str(&allowlist_json)
+                .context("Failed to parse MURMURE_URL_ALLOWLIST as JSON array")?;
+        }
+
+        if let Ok(timeout_str) = env::var("MURMURE_URL_FETCH_TIMEOUT_SECS") {
+            config.url_fetch_timeout_secs = timeout_str
+                .parse()
+                .context("MURMURE_URL_FETCH_TIMEOUT_SECS must be a number of seconds")?;
+        }
+
+        if let Ok(redirects_str) = env::var("MURMURE_URL_MAX_REDIRECTS") {
+            config.url_max_redirects = redirects_str
+                .parse()
+                .context("MURMURE_URL_MAX_REDIRECTS must be a number of redirects")?;
+        }
+
+        if let Ok(penalty_str) = env::var("MURMURE_DECODING_BLANK_PENALTY") {
+            config.decoding_blank_penalty = penalty_str
+                .parse()
+                .context("MURMURE_DECODING_BLANK_PENALTY must be a number")?;
+        }
+
+        if let Ok(symbols_str) = env::var("MURMURE_DECODING_MAX_SYMBOLS_PER_STEP") {
+            config.decoding_max_symbols_per_step = symbols_str
+                .parse()
+                .context("MURMURE_DECODING_MAX_SYMBOLS_PER_STEP must be a number of tokens")?;
+        }
+
+        if let Ok(tokens_str) = env::var("MURMURE_DECODING_MAX_TOKENS") {
+            config.decoding_max_tokens = tokens_str
+                .parse()
+                .context("MURMURE_DECODING_MAX_TOKENS must be a number of tokens")?;
+        }
+
+        if let Ok(bundle) = env::var("MURMURE_SETTINGS_BUNDLE") {
+            config.settings_bundle = Some(PathBuf::from(bundle));
+        }
+
         // Try to load from config file (optional)
         if let Some(file_config) =
             Self::load_from_file("config.json").or_else(|| Self::load_from_file("config.toml"))
@@ -60,27 +783,55 @@ impl ServerConfig {
             config = file_config.merge_with_env(config);
         }
 
+        // Added last, so the bundle's words extend whichever dictionary the
+        // environment or config file set
+        if let Some(path) = config.settings_bundle.clone() {
+            SettingsBundle::load(&path)?.apply_to(&mut config);
+        }
+
         Ok(config)
     }
 
+    /// Read `path` if it exists. The settings are either at the top level or
+    /// in a `server` section, so the file can also hold sections for other
+    /// components. Unknown keys are reported, as they are usually typos; a
+    /// file that doesn't parse is reported and ignored.
     fn load_from_file(path: &str) -> Option<Self> {
-        if let Ok(content) = fs::read_to_string(path) {
-            if path.ends_with(".json") {
-                serde_json::from_str(&content).ok()
-            } else if path.ends_with(".toml") {
-                // Try to parse as TOML, but don't fail if it doesn't work
-                match toml::from_str(&content) {
-                    Ok(config) => Some(config),
-                    Err(e) => {
-                        eprintln!("Warning: Failed to parse TOML config file {}: {}", path, e);
-                        None
-                    }
-                }
-            } else {
+        let content = fs::read_to_string(path).ok()?;
+        let parsed: Result<serde_json::Value> = if path.ends_with(".json") {
+            serde_json::from_str(&content).map_err(Into::into)
+        } else if path.ends_with(".toml") {
+            toml::from_str(&content).map_err(Into::into)
+        } else {
+            return None;
+        };
+        let mut value = match parsed {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("Warning: Failed to parse config file {}: {}", path, e);
+                return None;
+            }
+        };
+
+        if let Some(section) = value
+            .get_mut("server")
+            .filter(|section| section.is_object())
+        {
+            value = section.take();
+        }
+        let known = serde_json::to_value(Self::default()).ok()?;
+        if let (Some(keys), Some(known)) = (value.as_object(), known.as_object()) {
+            for key in keys.keys().filter(|key| !known.contains_key(*key)) {
+                eprintln!("Warning: Unknown setting '{}' in {}, ignored", key, path);
+            }
+        }
+
+        match serde_json::from_value(value) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                eprintln!("Warning: Invalid settings in config file {}: {}", path, e);
                 None
             }
-        } else {
-            None
         }
     }
 
@@ -93,91 +844,1749 @@ impl ServerConfig {
             } else {
                 env_config.dictionary
             },
+            dictionaries: if env_config.dictionaries.is_empty() {
+                self.dictionaries
+            } else {
+                env_config.dictionaries
+            },
+            allow_missing_cc_rules: env_config.allow_missing_cc_rules
+                || self.allow_missing_cc_rules,
             grpc_port: env_config.grpc_port,
+            grpc_listeners: if env_config.grpc_listeners.is_empty() {
+                self.grpc_listeners
+            } else {
+                env_config.grpc_listeners
+            },
             log_level: env_config.log_level,
-        }
-    }
-
-    pub fn get_model_path(&self) -> Result<PathBuf> {
-        if let Some(ref path) = self.model_path {
-            if path.exists() {
-                return Ok(path.clone());
-            }
-        }
-
-        // Try default locations
-        const MODEL_FILENAME: &str = "parakeet-tdt-0.6b-v3-int8";
-
-        let possible_paths = vec![
-            PathBuf::from(format!("resources/{}", MODEL_FILENAME)),
-            PathBuf::from(format!("../resources/{}", MODEL_FILENAME)),
-            PathBuf::from(format!("_up_/resources/{}", MODEL_FILENAME)),
-        ];
-
-        for path in possible_paths {
-            if path.exists() {
-                println!("Model found at: {}", path.display());
-                return Ok(path);
-            }
-        }
-
-        // Try relative to executable
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let dev_path = exe_dir.join("_up_").join("resources").join(MODEL_FILENAME);
-                if dev_path.exists() {
-                    println!("Model found at dev location: {}", dev_path.display());
-                    return Ok(dev_path);
-                }
-
-                let resource_path = exe_dir.join("resources").join(MODEL_FILENAME);
-                if resource_path.exists() {
-                    println!("Model found at: {}", resource_path.display());
-                    return Ok(resource_path);
-                }
-            }
-        }
-
-        anyhow::bail!(
-            "Model '{}' not found. Set MURMURE_MODEL_PATH environment variable or place model in resources/ directory.",
-            MODEL_FILENAME
-        )
-    }
-
-    pub fn get_cc_rules_path(&self) -> Result<PathBuf> {
-        if let Some(ref path) = self.cc_rules_path {
-            if path.exists() {
-                return Ok(path.clone());
-            }
-        }
-
-        // Try default locations
-        let possible_paths = vec![
-            PathBuf::from("resources/cc-rules"),
-            PathBuf::from("../resources/cc-rules"),
-            PathBuf::from("_up_/resources/cc-rules"),
-        ];
-
-        for path in possible_paths {
-            if path.exists() {
-                println!("CC rules found at: {}", path.display());
-                return Ok(path);
-            }
-        }
-
-        // Try relative to executable
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let resource_path = exe_dir.join("resources").join("cc-rules");
-                if resource_path.exists() {
-                    println!("CC rules found at: {}", resource_path.display());
-                    return Ok(resource_path);
-                }
-            }
-        }
+            model_wait_secs: if env_config.model_wait_secs != 0 {
+                env_config.model_wait_secs
+            } else {
+                self.model_wait_secs
+            },
+            ready_file: env_config.ready_file.or(self.ready_file),
+            segment_on_silence: env_config.segment_on_silence || self.segment_on_silence,
+            segment_min_silence_ms: if env_config.segment_min_silence_ms
+                != Self::default().segment_min_silence_ms
+            {
+                env_config.segment_min_silence_ms
+            } else {
+                self.segment_min_silence_ms
+            },
+            resampler_quality: if env_config.resampler_quality != ResamplerQuality::default() {
+                env_config.resampler_quality
+            } else {
+                self.resampler_quality
+            },
+            highpass_hz: env_config.highpass_hz.or(self.highpass_hz),
+            denoise: env_config.denoise || self.denoise,
+            engine_wait_ms: if env_config.engine_wait_ms != Self::default().engine_wait_ms {
+                env_config.engine_wait_ms
+            } else {
+                self.engine_wait_ms
+            },
+            model_precision: if env_config.model_precision != ModelPrecision::default() {
+                env_config.model_precision
+            } else {
+                self.model_precision
+            },
+            engine_panic_threshold: if env_config.engine_panic_threshold
+                != Self::default().engine_panic_threshold
+            {
+                env_config.engine_panic_threshold
+            } else {
+                self.engine_panic_threshold
+            },
+            intra_op_threads: env_config.intra_op_threads.or(self.intra_op_threads),
+            inter_op_threads: env_config.inter_op_threads.or(self.inter_op_threads),
+            execution_provider: if env_config.execution_provider != ExecutionProvider::default() {
+                env_config.execution_provider
+            } else {
+                self.execution_provider
+            },
+            mmap_model: if env_config.mmap_model != Self::default().mmap_model {
+                env_config.mmap_model
+            } else {
+                self.mmap_model
+            },
+            profanity_filter: if env_config.profanity_filter != ProfanityFilter::default() {
+                env_config.profanity_filter
+            } else {
+                self.profanity_filter
+            },
+            profanity_words_path: env_config
+                .profanity_words_path
+                .or(self.profanity_words_path),
+            redact_patterns: if env_config.redact_patterns.is_empty() {
+                self.redact_patterns
+            } else {
+                env_config.redact_patterns
+            },
+            punctuate: env_config.punctuate || self.punctuate,
+            itn: env_config.itn || self.itn,
+            itn_language: if env_config.itn_language != Self::default().itn_language {
+                env_config.itn_language
+            } else {
+                self.itn_language
+            },
+            min_audio_ms: if env_config.min_audio_ms != Self::default().min_audio_ms {
+                env_config.min_audio_ms
+            } else {
+                self.min_audio_ms
+            },
+            max_audio_secs: if env_config.max_audio_secs != Self::default().max_audio_secs {
+                env_config.max_audio_secs
+            } else {
+                self.max_audio_secs
+            },
+            large_file_max_audio_secs: env_config
+                .large_file_max_audio_secs
+                .or(self.large_file_max_audio_secs),
+            max_upload_bytes: if env_config.max_upload_bytes != Self::default().max_upload_bytes {
+                env_config.max_upload_bytes
+            } else {
+                self.max_upload_bytes
+            },
+            salvage_dir: env_config.salvage_dir.or(self.salvage_dir),
+            salvage_min_secs: if env_config.salvage_min_secs != Self::default().salvage_min_secs {
+                env_config.salvage_min_secs
+            } else {
+                self.salvage_min_secs
+            },
+            salvage_max_bytes: if env_config.salvage_max_bytes != Self::default().salvage_max_bytes
+            {
+                env_config.salvage_max_bytes
+            } else {
+                self.salvage_max_bytes
+            },
+            debug_dump_dir: env_config.debug_dump_dir.or(self.debug_dump_dir),
+            debug_dump_token: env_config.debug_dump_token.or(self.debug_dump_token),
+            debug_dump_max_bytes: if env_config.debug_dump_max_bytes
+                != Self::default().debug_dump_max_bytes
+            {
+                env_config.debug_dump_max_bytes
+            } else {
+                self.debug_dump_max_bytes
+            },
+            debug_dump_retention_days: if env_config.debug_dump_retention_days
+                != Self::default().debug_dump_retention_days
+            {
+                env_config.debug_dump_retention_days
+            } else {
+                self.debug_dump_retention_days
+            },
+            temp_dir: if env_config.temp_dir != Self::default().temp_dir {
+                env_config.temp_dir
+            } else {
+                self.temp_dir
+            },
+            min_free_disk_mb: if env_config.min_free_disk_mb != Self::default().min_free_disk_mb {
+                env_config.min_free_disk_mb
+            } else {
+                self.min_free_disk_mb
+            },
+            models: if env_config.models.is_empty() {
+                self.models
+            } else {
+                env_config.models
+            },
+            max_loaded_models: if env_config.max_loaded_models != Self::default().max_loaded_models
+            {
+                env_config.max_loaded_models
+            } else {
+                self.max_loaded_models
+            },
+            rate_limit_rpm: if env_config.rate_limit_rpm != 0 {
+                env_config.rate_limit_rpm
+            } else {
+                self.rate_limit_rpm
+            },
+            rate_limit_audio_secs: if env_config.rate_limit_audio_secs != 0 {
+                env_config.rate_limit_audio_secs
+            } else {
+                self.rate_limit_audio_secs
+            },
+            rate_limit_overrides: if env_config.rate_limit_overrides.is_empty() {
+                self.rate_limit_overrides
+            } else {
+                env_config.rate_limit_overrides
+            },
+            incomplete_stream: if env_config.incomplete_stream != IncompleteStream::default() {
+                env_config.incomplete_stream
+            } else {
+                self.incomplete_stream
+            },
+            ws_port: env_config.ws_port.or(self.ws_port),
+            ws_max_session_secs: if env_config.ws_max_session_secs
+                != Self::default().ws_max_session_secs
+            {
+                env_config.ws_max_session_secs
+            } else {
+                self.ws_max_session_secs
+            },
+            ws_allowed_origins: if env_config.ws_allowed_origins.is_empty() {
+                self.ws_allowed_origins
+            } else {
+                env_config.ws_allowed_origins
+            },
+            stream_heartbeat_secs: if env_config.stream_heartbeat_secs
+                != Self::default().stream_heartbeat_secs
+            {
+                env_config.stream_heartbeat_secs
+            } else {
+                self.stream_heartbeat_secs
+            },
+            http2_keepalive_secs: if env_config.http2_keepalive_secs
+                != Self::default().http2_keepalive_secs
+            {
+                env_config.http2_keepalive_secs
+            } else {
+                self.http2_keepalive_secs
+            },
+            http2_keepalive_timeout_secs: if env_config.http2_keepalive_timeout_secs
+                != Self::default().http2_keepalive_timeout_secs
+            {
+                env_config.http2_keepalive_timeout_secs
+            } else {
+                self.http2_keepalive_timeout_secs
+            },
+            commands: if env_config.commands.is_empty() {
+                self.commands
+            } else {
+                env_config.commands
+            },
+            command_threshold: if env_config.command_threshold != Self::default().command_threshold
+            {
+                env_config.command_threshold
+            } else {
+                self.command_threshold
+            },
+            detect_languages: if env_config.detect_languages.is_empty() {
+                self.detect_languages
+            } else {
+                env_config.detect_languages
+            },
+            two_pass_confidence: if env_config.two_pass_confidence
+                != Self::default().two_pass_confidence
+            {
+                env_config.two_pass_confidence
+            } else {
+                self.two_pass_confidence
+            },
+            two_pass_model: env_config.two_pass_model.or(self.two_pass_model),
+            session_ttl_secs: if env_config.session_ttl_secs != Self::default().session_ttl_secs {
+                env_config.session_ttl_secs
+            } else {
+                self.session_ttl_secs
+            },
+            session_resume_max_bytes: if env_config.session_resume_max_bytes
+                != Self::default().session_resume_max_bytes
+            {
+                env_config.session_resume_max_bytes
+            } else {
+                self.session_resume_max_bytes
+            },
+            idempotency_ttl_secs: if env_config.idempotency_ttl_secs
+                != Self::default().idempotency_ttl_secs
+            {
+                env_config.idempotency_ttl_secs
+            } else {
+                self.idempotency_ttl_secs
+            },
+            idempotency_max_entries: if env_config.idempotency_max_entries
+                != Self::default().idempotency_max_entries
+            {
+                env_config.idempotency_max_entries
+            } else {
+                self.idempotency_max_entries
+            },
+            job_workers: if env_config.job_workers != Self::default().job_workers {
+                env_config.job_workers
+            } else {
+                self.job_workers
+            },
+            job_queue_depth: if env_config.job_queue_depth != Self::default().job_queue_depth {
+                env_config.job_queue_depth
+            } else {
+                self.job_queue_depth
+            },
+            job_retention_secs: if env_config.job_retention_secs
+                != Self::default().job_retention_secs
+            {
+                env_config.job_retention_secs
+            } else {
+                self.job_retention_secs
+            },
+            job_dir: env_config.job_dir.or(self.job_dir),
+            usage_monthly_quota_secs: if env_config.usage_monthly_quota_secs != 0 {
+                env_config.usage_monthly_quota_secs
+            } else {
+                self.usage_monthly_quota_secs
+            },
+            usage_count_replays: env_config.usage_count_replays || self.usage_count_replays,
+            usage_file: env_config.usage_file.or(self.usage_file),
+            admin_token: env_config.admin_token.or(self.admin_token),
+            url_allowlist: if env_config.url_allowlist.is_empty() {
+                self.url_allowlist
+            } else {
+                env_config.url_allowlist
+            },
+            url_fetch_timeout_secs: if env_config.url_fetch_timeout_secs
+                != Self::default().url_fetch_timeout_secs
+            {
+                env_config.url_fetch_timeout_secs
+            } else {
+                self.url_fetch_timeout_secs
+            },
+            url_max_redirects: if env_config.url_max_redirects != Self::default().url_max_redirects
+            {
+                env_config.url_max_redirects
+            } else {
+                self.url_max_redirects
+            },
+            decoding_blank_penalty: if env_config.decoding_blank_penalty
+                != Self::default().decoding_blank_penalty
+            {
+                env_config.decoding_blank_penalty
+            } else {
+                self.decoding_blank_penalty
+            },
+            decoding_max_symbols_per_step: if env_config.decoding_max_symbols_per_step
+                != Self::default().decoding_max_symbols_per_step
+            {
+                env_config.decoding_max_symbols_per_step
+            } else {
+                self.decoding_max_symbols_per_step
+            },
+            decoding_max_tokens: if env_config.decoding_max_tokens
+                != Self::default().decoding_max_tokens
+            {
+                env_config.decoding_max_tokens
+            } else {
+                self.decoding_max_tokens
+            },
+            settings_bundle: env_config.settings_bundle.or(self.settings_bundle),
+        }
+    }
+
+    /// Check the whole configuration and collect every problem found.
+    ///
+    /// Errors are settings the server can't run with; warnings are settings
+    /// that work but probably don't do what was intended.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        let missing_models: Vec<String> = if self.models.is_empty() {
+            self.get_model_path()
+                .err()
+                .map(|e| e.to_string())
+                .into_iter()
+                .collect()
+        } else {
+            self.models
+                .iter()
+                .filter(|spec| !spec.path.exists())
+                .map(|spec| format!("Model '{}' not found at {}", spec.name, spec.path.display()))
+                .collect()
+        };
+        for missing in missing_models {
+            if self.model_wait_secs > 0 {
+                report.warning(format!(
+                    "{} (will wait up to {}s for it to appear)",
+                    missing, self.model_wait_secs
+                ));
+            } else {
+                report.error(missing);
+            }
+        }
+
+        for (index, spec) in self.models.iter().enumerate() {
+            if spec.name.trim().is_empty() {
+                report.error("MURMURE_MODELS contains a model without a name".to_string());
+            } else if self.models[..index]
+                .iter()
+                .any(|other| other.name == spec.name)
+            {
+                report.error(format!(
+                    "MURMURE_MODELS names '{}' more than once",
+                    spec.name
+                ));
+            }
+        }
+        if !self.models.is_empty() && self.model_path.is_some() {
+            report.warning("MURMURE_MODEL_PATH is ignored when MURMURE_MODELS is set".to_string());
+        }
+        if self.max_loaded_models == 0 {
+            report.error("MURMURE_MAX_LOADED_MODELS must be at least 1".to_string());
+        }
+
+        for (index, entry) in self.rate_limit_overrides.iter().enumerate() {
+            if entry.key.trim().is_empty() {
+                report.error(
+                    "MURMURE_RATE_LIMIT_OVERRIDES contains an entry without a key".to_string(),
+                );
+            } else if self.rate_limit_overrides[..index]
+                .iter()
+                .any(|other| other.key == entry.key)
+            {
+                report.error(format!(
+                    "MURMURE_RATE_LIMIT_OVERRIDES lists key '{}' more than once",
+                    entry.key
+                ));
+            }
+        }
+        if self.rate_limit_audio_secs > 0
+            && self.max_audio_secs > 0
+            && self.rate_limit_audio_secs < self.max_audio_secs
+        {
+            report.warning(format!(
+                "MURMURE_RATE_LIMIT_AUDIO_SECS ({}s) is below MURMURE_MAX_AUDIO_SECS ({}s), \
+                 longer files are only accepted when the client has nothing else in flight",
+                self.rate_limit_audio_secs, self.max_audio_secs
+            ));
+        }
+
+        if !self.dictionary.is_empty() || !self.dictionaries.is_empty() {
+            if let Err(e) = self.get_cc_rules_path() {
+                if self.allow_missing_cc_rules {
+                    report.warning(format!(
+                        "A dictionary is configured but the cc-rules directory was not found, \
+                         dictionary corrections will be skipped: {}",
+                        e
+                    ));
+                } else {
+                    report.error(format!(
+                        "A dictionary is configured but the cc-rules directory was not found: {}. \
+                         Set MURMURE_CC_RULES_PATH, or MURMURE_ALLOW_MISSING_CC_RULES=true to run \
+                         without dictionary correction.",
+                        e
+                    ));
+                }
+            }
+        }
+        if self.dictionary.iter().any(|word| word.trim().is_empty()) {
+            report.error("MURMURE_DICTIONARY contains an empty entry".to_string());
+        }
+        for (language, words) in &self.dictionaries {
+            if !is_language_code(language) {
+                report.error(format!(
+                    "MURMURE_DICTIONARIES key '{}' is not a language code like 'en' or 'fr-CA'",
+                    language
+                ));
+            }
+            if words.iter().any(|word| word.trim().is_empty()) {
+                report.error(format!(
+                    "MURMURE_DICTIONARIES '{}' contains an empty entry",
+                    language
+                ));
+            }
+        }
+
+        if self.max_audio_secs > 0 && self.min_audio_ms >= self.max_audio_secs * 1000 {
+            report.error(format!(
+                "MURMURE_MIN_AUDIO_MS ({} ms) must be below MURMURE_MAX_AUDIO_SECS ({} s)",
+                self.min_audio_ms, self.max_audio_secs
+            ));
+        }
+        if let Some(large_max) = self.large_file_max_audio_duration() {
+            if self
+                .max_audio_duration()
+                .map_or(true, |max| large_max < max)
+            {
+                report.warning(format!(
+                    "MURMURE_LARGE_FILE_MAX_AUDIO_SECS ({large_max} s) is stricter than \
+                     MURMURE_MAX_AUDIO_SECS, TranscribeLargeFile accepts less audio than the other RPCs"
+                ));
+            }
+        }
+
+        if self.grpc_port == 0 {
+            report.error("MURMURE_GRPC_PORT must be between 1 and 65535".to_string());
+        }
+        if !self.grpc_listeners.is_empty() && self.grpc_port != Self::default().grpc_port {
+            report.warning(
+                "MURMURE_GRPC_PORT is ignored when MURMURE_GRPC_LISTENERS is set".to_string(),
+            );
+        }
+        for (index, listener) in self.grpc_listeners.iter().enumerate() {
+            let addr = listener.addr;
+            if addr.port() == 0 {
+                report.error(format!(
+                    "MURMURE_GRPC_LISTENERS entry {} needs a port between 1 and 65535",
+                    addr
+                ));
+            } else if self.grpc_listeners[..index]
+                .iter()
+                .any(|other| other.addr == addr)
+            {
+                report.error(format!(
+                    "MURMURE_GRPC_LISTENERS lists {} more than once",
+                    addr
+                ));
+            }
+            match (&listener.tls_cert, &listener.tls_key) {
+                (Some(cert), Some(key)) => {
+                    for (name, path) in [("tls_cert", cert), ("tls_key", key)] {
+                        if !path.is_file() {
+                            report.error(format!(
+                                "MURMURE_GRPC_LISTENERS entry {}: {} {} not found",
+                                addr,
+                                name,
+                                path.display()
+                            ));
+                        }
+                    }
+                }
+                (None, None) => {}
+                _ => report.error(format!(
+                    "MURMURE_GRPC_LISTENERS entry {} needs both tls_cert and tls_key for TLS",
+                    addr
+                )),
+            }
+        }
+        if self.ws_port == Some(0) {
+            report.error("MURMURE_WS_PORT must be between 1 and 65535".to_string());
+        } else if let Some(ws_port) = self.ws_port {
+            if self
+                .listeners()
+                .iter()
+                .any(|listener| listener.addr.port() == ws_port)
+            {
+                report.error("MURMURE_WS_PORT must differ from the gRPC ports".to_string());
+            }
+            if self.ws_allowed_origins.is_empty() {
+                report.warning(
+                    "MURMURE_WS_ALLOWED_ORIGINS is empty, browsers can't open WebSocket sessions"
+                        .to_string(),
+                );
+            }
+        }
+        for origin in &self.ws_allowed_origins {
+            let host = origin
+                .strip_prefix("https://")
+                .or_else(|| origin.strip_prefix("http://"));
+            let valid = host.is_some_and(|host| !host.is_empty() && !host.contains('/'));
+            // Pages opened from disk or sandboxed send `null`
+            if !valid && origin != "null" {
+                report.error(format!(
+                    "MURMURE_WS_ALLOWED_ORIGINS entry '{}' must be an origin: scheme, host and optional port, without a path",
+                    origin
+                ));
+            }
+        }
+        if self.http2_keepalive_secs > 0 && self.http2_keepalive_timeout_secs == 0 {
+            report.error(
+                "MURMURE_HTTP2_KEEPALIVE_TIMEOUT_SECS must be greater than 0 when keepalive is enabled"
+                    .to_string(),
+            );
+        }
+
+        for (index, command) in self.commands.iter().enumerate() {
+            if command.phrase.trim().is_empty() || command.action.trim().is_empty() {
+                report
+                    .error("MURMURE_COMMANDS entries need both a phrase and an action".to_string());
+            } else if self.commands[..index]
+                .iter()
+                .any(|other| other.phrase.eq_ignore_ascii_case(&command.phrase))
+            {
+                report.error(format!(
+                    "MURMURE_COMMANDS lists the phrase '{}' more than once",
+                    command.phrase
+                ));
+            }
+        }
+        if !(self.command_threshold > 0.0 && self.command_threshold <= 1.0) {
+            report.error(format!(
+                "MURMURE_COMMAND_THRESHOLD must be above 0 and at most 1, got {}",
+                self.command_threshold
+            ));
+        } else if !self.commands.is_empty() && self.command_threshold < 0.5 {
+            report.warning(format!(
+                "MURMURE_COMMAND_THRESHOLD is {}, short dictation will often be taken for a command",
+                self.command_threshold
+            ));
+        }
+        for (index, language) in self.detect_languages.iter().enumerate() {
+            if !is_language_code(language) {
+                report.error(format!(
+                    "MURMURE_DETECT_LANGUAGES entry '{}' is not a language code like 'en' or 'fr-CA'",
+                    language
+                ));
+            } else if !crate::langid::is_supported(language) {
+                report.error(format!(
+                    "MURMURE_DETECT_LANGUAGES entry '{}' can't be detected, supported languages are {}",
+                    language,
+                    crate::langid::SUPPORTED_LANGUAGES.join(", ")
+                ));
+            } else if self.detect_languages[..index]
+                .iter()
+                .any(|other| other.eq_ignore_ascii_case(language))
+            {
+                report.error(format!(
+                    "MURMURE_DETECT_LANGUAGES lists '{}' more than once",
+                    language
+                ));
+            }
+        }
+        if !(self.two_pass_confidence > 0.0 && self.two_pass_confidence <= 1.0) {
+            report.error(format!(
+                "MURMURE_TWO_PASS_CONFIDENCE must be above 0 and at most 1, got {}",
+                self.two_pass_confidence
+            ));
+        }
+        if let Some(model) = &self.two_pass_model {
+            if !self.models.iter().any(|spec| &spec.name == model) {
+                report.error(format!(
+                    "MURMURE_TWO_PASS_MODEL '{}' is not one of the models in MURMURE_MODELS",
+                    model
+                ));
+            }
+        }
+        if self.session_ttl_secs == 0 && self.session_resume_max_bytes > 0 {
+            report.warning(
+                "MURMURE_SESSION_RESUME_MAX_BYTES has no effect with MURMURE_SESSION_TTL_SECS=0"
+                    .to_string(),
+            );
+        }
+        if self.idempotency_ttl_secs > 0 && self.idempotency_max_entries == 0 {
+            report.error(
+                "MURMURE_IDEMPOTENCY_MAX_ENTRIES must be greater than 0 (set MURMURE_IDEMPOTENCY_TTL_SECS=0 to ignore idempotency keys)"
+                    .to_string(),
+            );
+        }
+        if self.job_workers > 0 && self.job_queue_depth == 0 {
+            report.error(
+                "MURMURE_JOB_QUEUE_DEPTH must be greater than 0 (set MURMURE_JOB_WORKERS=0 to disable background jobs)"
+                    .to_string(),
+            );
+        }
+        if self.job_workers > 0 && self.job_retention_secs == 0 {
+            report.warning(
+                "MURMURE_JOB_RETENTION_SECS is 0, job results are dropped as soon as they are ready"
+                    .to_string(),
+            );
+        }
+        if let Some(dir) = &self.job_dir {
+            if dir.exists() && !dir.is_dir() {
+                report.error(format!(
+                    "MURMURE_JOB_DIR {} is not a directory",
+                    dir.display()
+                ));
+            }
+        }
+        if let Some(file) = &self.usage_file {
+            if file.is_dir() {
+                report.error(format!(
+                    "MURMURE_USAGE_FILE {} is a directory",
+                    file.display()
+                ));
+            }
+        }
+        if let Some(token) = &self.admin_token {
+            if token.len() < 16 || !token.bytes().all(|b| b.is_ascii_graphic()) {
+                report.error(
+                    "MURMURE_ADMIN_TOKEN must be at least 16 printable ASCII characters"
+                        .to_string(),
+                );
+            }
+        }
+        for prefix in &self.url_allowlist {
+            let host = prefix
+                .strip_prefix("https://")
+                .or_else(|| prefix.strip_prefix("http://"))
+                .and_then(|rest| rest.split('/').next());
+            match host {
+                Some(host) if !host.is_empty() => {
+                    if prefix.starts_with("http://") {
+                        report.warning(format!(
+                            "MURMURE_URL_ALLOWLIST prefix '{}' is plain http, fetched audio can be tampered with in transit",
+                            prefix
+                        ));
+                    }
+                }
+                _ => report.error(format!(
+                    "MURMURE_URL_ALLOWLIST prefix '{}' must be an http(s) URL with a host",
+                    prefix
+                )),
+            }
+        }
+        if !self.url_allowlist.is_empty() && self.url_fetch_timeout_secs == 0 {
+            report.error("MURMURE_URL_FETCH_TIMEOUT_SECS must be greater than 0".to_string());
+        }
+        let max_penalty = DecodingOptions::MAX_BLANK_PENALTY;
+        if !(-max_penalty..=max_penalty).contains(&self.decoding_blank_penalty) {
+            report.error(format!(
+                "MURMURE_DECODING_BLANK_PENALTY must be between -{} and {}, got {}",
+                max_penalty, max_penalty, self.decoding_blank_penalty
+            ));
+        }
+        if !(1..=DecodingOptions::MAX_SYMBOLS_PER_STEP)
+            .contains(&self.decoding_max_symbols_per_step)
+        {
+            report.error(format!(
+                "MURMURE_DECODING_MAX_SYMBOLS_PER_STEP must be between 1 and {}, got {}",
+                DecodingOptions::MAX_SYMBOLS_PER_STEP,
+                self.decoding_max_symbols_per_step
+            ));
+        }
+        if self.usage_count_replays && self.idempotency_ttl_secs == 0 {
+            report.warning(
+                "MURMURE_USAGE_COUNT_REPLAYS has no effect when MURMURE_IDEMPOTENCY_TTL_SECS is 0"
+                    .to_string(),
+            );
+        }
+
+        if self.segment_on_silence && self.segment_min_silence_ms == 0 {
+            report.error(
+                "MURMURE_SEGMENT_MIN_SILENCE_MS must be greater than 0 when segmentation is enabled"
+                    .to_string(),
+            );
+        }
+
+        if let Some(cutoff) = self.highpass_hz {
+            if !(cutoff > 0.0 && cutoff < 8000.0) {
+                report.error(
+                    "MURMURE_HIGHPASS_HZ must be between 0 and 8000 Hz (exclusive)".to_string(),
+                );
+            } else if cutoff > 300.0 {
+                report.warning(format!(
+                    "MURMURE_HIGHPASS_HZ is {} Hz, cutoffs above 300 Hz remove part of the voice",
+                    cutoff
+                ));
+            }
+        }
+
+        if self.intra_op_threads == Some(0) || self.inter_op_threads == Some(0) {
+            report.error(
+                "MURMURE_ONNX_THREADS and MURMURE_ONNX_INTER_THREADS must be at least 1"
+                    .to_string(),
+            );
+        }
+
+        if let Some(path) = &self.profanity_words_path {
+            if !path.is_file() {
+                report.error(format!(
+                    "MURMURE_PROFANITY_WORDS_FILE {} does not exist",
+                    path.display()
+                ));
+            } else if self.profanity_filter == ProfanityFilter::Off {
+                report.warning(
+                    "MURMURE_PROFANITY_WORDS_FILE is set but MURMURE_PROFANITY_FILTER is off"
+                        .to_string(),
+                );
+            }
+        }
+
+        for pattern in &self.redact_patterns {
+            if let Err(e) = regex::Regex::new(pattern) {
+                report.error(format!("Invalid redaction pattern '{}': {}", pattern, e));
+            }
+        }
+
+        if self.itn && !crate::itn::is_supported(&self.itn_language) {
+            report.warning(format!(
+                "MURMURE_ITN is enabled but language '{}' has no normalization rules, text will be left as is",
+                self.itn_language
+            ));
+        }
+
+        if self.salvage_dir.is_some() && self.incomplete_stream == IncompleteStream::Discard {
+            report.warning(
+                "MURMURE_SALVAGE_DIR is set but MURMURE_INCOMPLETE_STREAM is 'discard', \
+                 audio of dropped streams will not be salvaged"
+                    .to_string(),
+            );
+        }
+        if let Some(dir) = &self.salvage_dir {
+            if dir.exists() && !dir.is_dir() {
+                report.error(format!(
+                    "MURMURE_SALVAGE_DIR {} is not a directory",
+                    dir.display()
+                ));
+            }
+        }
+        match (&self.debug_dump_dir, &self.debug_dump_token) {
+            (Some(dir), _) if dir.exists() && !dir.is_dir() => {
+                report.error(format!(
+                    "MURMURE_DEBUG_DUMP_DIR {} is not a directory",
+                    dir.display()
+                ));
+            }
+            (Some(_), None) => report.error(
+                "MURMURE_DEBUG_DUMP_DIR needs MURMURE_DEBUG_DUMP_TOKEN, the secret requests send to be dumped"
+                    .to_string(),
+            ),
+            (None, Some(_)) => report.warning(
+                "MURMURE_DEBUG_DUMP_TOKEN has no effect without MURMURE_DEBUG_DUMP_DIR".to_string(),
+            ),
+            _ => {}
+        }
+        if let Some(token) = &self.debug_dump_token {
+            if token.len() < 16 || !token.bytes().all(|b| b.is_ascii_graphic()) {
+                report.error(
+                    "MURMURE_DEBUG_DUMP_TOKEN must be at least 16 printable ASCII characters"
+                        .to_string(),
+                );
+            }
+        }
+        if self.debug_dump_dir.is_some() && self.debug_dump_max_bytes == 0 {
+            report.error(
+                "MURMURE_DEBUG_DUMP_MAX_BYTES must be greater than 0, dumps hold raw audio"
+                    .to_string(),
+            );
+        }
+
+        if self.temp_dir.exists() && !self.temp_dir.is_dir() {
+            report.error(format!(
+                "MURMURE_TEMP_DIR {} is not a directory",
+                self.temp_dir.display()
+            ));
+        } else if let Err(e) = crate::tempfiles::check_writable(&self.temp_dir) {
+            report.error(format!("MURMURE_TEMP_DIR is not writable: {}", e));
+        }
+
+        if let Some(path) = &self.ready_file {
+            let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+            if parent.is_some_and(|dir| !dir.is_dir()) {
+                report.error(format!(
+                    "Directory for MURMURE_READY_FILE {} does not exist",
+                    path.display()
+                ));
+            }
+        }
+
+        report
+    }
+
+    /// Decoding settings of requests that don't choose their own.
+    pub fn decoding_options(&self) -> DecodingOptions {
+        DecodingOptions {
+            blank_penalty: self.decoding_blank_penalty,
+            max_symbols_per_step: self.decoding_max_symbols_per_step,
+            max_tokens: (self.decoding_max_tokens > 0).then_some(self.decoding_max_tokens),
+        }
+    }
+
+    /// Free space to keep in `temp_dir`, in bytes.
+    pub fn min_free_disk_bytes(&self) -> u64 {
+        self.min_free_disk_mb * 1024 * 1024
+    }
+
+    /// Lower bound on audio duration per request, in seconds.
+    pub fn min_audio_duration(&self) -> f32 {
+        self.min_audio_ms as f32 / 1000.0
+    }
+
+    /// The gRPC listeners to start: `grpc_listeners`, or a single plaintext
+    /// one at `grpc_port` when there are none.
+    pub fn listeners(&self) -> Vec<GrpcListener> {
+        if !self.grpc_listeners.is_empty() {
+            return self.grpc_listeners.clone();
+        }
+        vec![GrpcListener {
+            addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, self.grpc_port)),
+            tls_cert: None,
+            tls_key: None,
+        }]
+    }
+
+    /// Address of the WebSocket bridge: `ws_port` on the address of the
+    /// first gRPC listener. `None` when the bridge is disabled.
+    pub fn ws_addr(&self) -> Option<SocketAddr> {
+        let ip = self
+            .listeners()
+            .first()
+            .map(|listener| listener.addr.ip())?;
+        self.ws_port.map(|port| SocketAddr::new(ip, port))
+    }
+
+    /// Upper bound on audio duration per request, `None` when unlimited.
+    pub fn max_audio_duration(&self) -> Option<f32> {
+        (self.max_audio_secs > 0).then_some(self.max_audio_secs as f32)
+    }
+
+    /// Upper bound on audio duration for TranscribeLargeFile, `None` when
+    /// unlimited. Falls back to [`Self::max_audio_duration`] when unset.
+    pub fn large_file_max_audio_duration(&self) -> Option<f32> {
+        match self.large_file_max_audio_secs {
+            Some(secs) => (secs > 0).then_some(secs as f32),
+            None => self.max_audio_duration(),
+        }
+    }
+
+    /// The model directory: `model_path` when set, otherwise the first
+    /// [`resource_search_dirs`] entry that has the default model.
+    pub fn get_model_path(&self) -> Result<PathBuf> {
+        if let Some(ref path) = self.model_path {
+            if !path.exists() {
+                anyhow::bail!("Model not found at {}", path.display());
+            }
+            return Ok(path.clone());
+        }
+
+        if let Some(path) = find_resource(DEFAULT_MODEL_DIR) {
+            log::info!("Model found at: {}", path.display());
+            return Ok(path);
+        }
+
+        anyhow::bail!(
+            "Model '{}' not found in {}. Set MURMURE_MODEL_PATH environment variable or place model in one of these directories.",
+            DEFAULT_MODEL_DIR,
+            searched_dirs()
+        )
+    }
+
+    /// The cc-rules directory: `cc_rules_path` when set, otherwise the first
+    /// [`resource_search_dirs`] entry that has one.
+    pub fn get_cc_rules_path(&self) -> Result<PathBuf> {
+        if let Some(ref path) = self.cc_rules_path {
+            if !path.exists() {
+                anyhow::bail!("CC rules directory not found at {}", path.display());
+            }
+            return Ok(path.clone());
+        }
+
+        if let Some(path) = find_resource(CC_RULES_DIR) {
+            log::info!("CC rules found at: {}", path.display());
+            return Ok(path);
+        }
 
         anyhow::bail!(
-            "CC rules directory not found. Set MURMURE_CC_RULES_PATH environment variable or place cc-rules in resources/ directory."
+            "CC rules directory not found in {}. Set MURMURE_CC_RULES_PATH environment variable or place cc-rules in one of these directories.",
+            searched_dirs()
         )
     }
+
+    /// The cc-rules for `language`: its subdirectory of the cc-rules
+    /// directory (`cc-rules/fr`) when there is one, the directory itself
+    /// otherwise.
+    pub fn get_language_cc_rules_path(&self, language: &str) -> Result<PathBuf> {
+        let base = self.get_cc_rules_path()?;
+        Ok(language_fallbacks(language)
+            .map(|code| base.join(code))
+            .find(|scoped| scoped.is_dir())
+            .unwrap_or(base))
+    }
+}
+
+/// Codes to look a language up by, most specific first: `fr-CA` gives
+/// `fr-ca`, then `fr`.
+pub fn language_fallbacks(language: &str) -> impl Iterator<Item = String> {
+    let full = language.trim().to_ascii_lowercase().replace('_', "-");
+    let primary = full.split('-').next().unwrap_or_default().to_string();
+    let primary = (primary != full).then_some(primary);
+    std::iter::once(full).chain(primary)
+}
+
+/// Whether `code` looks like a language code: a primary subtag, optionally
+/// followed by a region (`en`, `fr-CA`).
+pub fn is_language_code(code: &str) -> bool {
+    code.split(['-', '_']).all(|part| {
+        (2..=8).contains(&part.len()) && part.bytes().all(|b| b.is_ascii_alphanumeric())
+    })
+}
+
+/// Directory name of the model used when none is configured
+pub(crate) const DEFAULT_MODEL_DIR: &str = "parakeet-tdt-0.6b-v3-int8";
+
+/// Directory name of the Beider-Morse rules
+const CC_RULES_DIR: &str = "cc-rules";
+
+/// Directories searched, in order, for the model and cc-rules when their
+/// paths aren't configured.
+///
+/// `resources/` relative to the working directory and to the executable
+/// everywhere (`_up_/` is where Tauri dev builds put it). On Windows the
+/// executable's own directory, `%PROGRAMDATA%\Murmure\models` and
+/// `%LOCALAPPDATA%\Murmure` come after, for installs that keep the model
+/// outside the program directory.
+pub fn resource_search_dirs() -> Vec<PathBuf> {
+    let resources = Path::new("resources");
+    let mut dirs = vec![
+        resources.to_path_buf(),
+        Path::new("..").join(resources),
+        Path::new("_up_").join(resources),
+    ];
+
+    let exe_dir = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf));
+    if let Some(exe_dir) = &exe_dir {
+        dirs.push(exe_dir.join("_up_").join(resources));
+        dirs.push(exe_dir.join(resources));
+    }
+
+    if cfg!(windows) {
+        if let Some(exe_dir) = exe_dir {
+            dirs.push(exe_dir);
+        }
+        if let Some(program_data) = env::var_os("PROGRAMDATA") {
+            dirs.push(PathBuf::from(program_data).join("Murmure").join("models"));
+        }
+        if let Some(local_app_data) = env::var_os("LOCALAPPDATA") {
+            dirs.push(PathBuf::from(local_app_data).join("Murmure"));
+        }
+    }
+    dirs
+}
+
+/// First `dir/name` that exists among [`resource_search_dirs`].
+fn find_resource(name: &str) -> Option<PathBuf> {
+    resource_search_dirs()
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.exists())
+}
+
+/// The search directories, for error messages.
+fn searched_dirs() -> String {
+    resource_search_dirs()
+        .iter()
+        .map(|dir| dir.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Problems found by [`ServerConfig::validate`].
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// Settings the server can't start with
+    pub errors: Vec<String>,
+    /// Settings that work but are likely mistakes
+    pub warnings: Vec<String>,
+}
+
+impl ValidationReport {
+    /// No hard errors were found (warnings may still be present).
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    fn warning(&mut self, message: String) {
+        self.warnings.push(message);
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Configuration check: {} error(s), {} warning(s)",
+            self.errors.len(),
+            self.warnings.len()
+        )?;
+        for error in &self.errors {
+            writeln!(f, "  [ERROR] {}", error)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "  [WARN]  {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A configuration that passes validation: every path it names exists.
+    fn valid() -> ServerConfig {
+        let crate_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        ServerConfig {
+            model_path: Some(crate_dir.to_path_buf()),
+            cc_rules_path: Some(crate_dir.join("../resources/cc-rules")),
+            ..ServerConfig::default()
+        }
+    }
+
+    fn errors(config: ServerConfig) -> Vec<String> {
+        config.validate().errors
+    }
+
+    fn warnings(config: ServerConfig) -> Vec<String> {
+        config.validate().warnings
+    }
+
+    fn mentions(messages: &[String], needle: &str) -> bool {
+        messages.iter().any(|message| message.contains(needle))
+    }
+
+    #[test]
+    fn valid_configuration_passes() {
+        let report = valid().validate();
+        assert!(report.is_ok(), "{}", report);
+        assert!(report.warnings.is_empty(), "{}", report);
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let report = ServerConfig {
+            grpc_port: 0,
+            dictionary: vec![" ".to_string()],
+            redact_patterns: vec!["[".to_string()],
+            ..valid()
+        }
+        .validate();
+        assert!(!report.is_ok());
+        assert_eq!(report.errors.len(), 3, "{}", report);
+        let printed = report.to_string();
+        assert!(printed.starts_with("Configuration check: 3 error(s), 0 warning(s)"));
+        assert_eq!(printed.matches("[ERROR]").count(), 3);
+    }
+
+    #[test]
+    fn model_path_must_exist() {
+        let missing = || ServerConfig {
+            model_path: Some(PathBuf::from("/nonexistent/murmure-model")),
+            ..valid()
+        };
+        assert!(mentions(
+            &errors(missing()),
+            "Model not found at /nonexistent"
+        ));
+
+        // Waiting for the model turns the error into a warning
+        let waiting = ServerConfig {
+            model_wait_secs: 30,
+            ..missing()
+        };
+        assert!(errors(waiting.clone()).is_empty());
+        assert!(mentions(&warnings(waiting), "will wait up to 30s"));
+    }
+
+    #[test]
+    fn dictionaries_need_cc_rules() {
+        let dictionary = || ServerConfig {
+            dictionary: vec!["Murmure".to_string()],
+            ..valid()
+        };
+        assert!(errors(dictionary()).is_empty());
+
+        let without_rules = || ServerConfig {
+            cc_rules_path: Some(PathBuf::from("/nonexistent/cc-rules")),
+            ..dictionary()
+        };
+        assert!(mentions(
+            &errors(without_rules()),
+            "cc-rules directory was not found"
+        ));
+
+        let allowed = ServerConfig {
+            allow_missing_cc_rules: true,
+            ..without_rules()
+        };
+        assert!(errors(allowed.clone()).is_empty());
+        assert!(mentions(&warnings(allowed), "corrections will be skipped"));
+
+        // Without a dictionary the rules aren't needed
+        let no_dictionary = ServerConfig {
+            cc_rules_path: Some(PathBuf::from("/nonexistent/cc-rules")),
+            ..valid()
+        };
+        assert!(errors(no_dictionary).is_empty());
+    }
+
+    #[test]
+    fn dictionary_entries_must_not_be_empty() {
+        let config = ServerConfig {
+            dictionary: vec!["Murmure".to_string(), "".to_string()],
+            ..valid()
+        };
+        assert!(mentions(
+            &errors(config),
+            "MURMURE_DICTIONARY contains an empty entry"
+        ));
+    }
+
+    #[test]
+    fn port_must_be_set() {
+        let config = ServerConfig {
+            grpc_port: 0,
+            ..valid()
+        };
+        assert!(mentions(&errors(config), "MURMURE_GRPC_PORT"));
+        assert!(errors(ServerConfig {
+            grpc_port: 65535,
+            ..valid()
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn segmentation_needs_a_pause_length() {
+        let config = |on, ms| ServerConfig {
+            segment_on_silence: on,
+            segment_min_silence_ms: ms,
+            ..valid()
+        };
+        assert!(mentions(
+            &errors(config(true, 0)),
+            "MURMURE_SEGMENT_MIN_SILENCE_MS"
+        ));
+        assert!(errors(config(false, 0)).is_empty());
+        assert!(errors(config(true, 300)).is_empty());
+    }
+
+    #[test]
+    fn thread_counts_must_be_positive() {
+        let config = |intra, inter| ServerConfig {
+            intra_op_threads: intra,
+            inter_op_threads: inter,
+            ..valid()
+        };
+        assert!(errors(config(Some(4), Some(1))).is_empty());
+        assert!(mentions(
+            &errors(config(Some(0), None)),
+            "MURMURE_ONNX_THREADS"
+        ));
+        assert!(mentions(
+            &errors(config(None, Some(0))),
+            "MURMURE_ONNX_THREADS"
+        ));
+    }
+
+    #[test]
+    fn profanity_word_list_must_exist_and_be_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let words = dir.path().join("words.txt");
+        std::fs::write(&words, "blimey\n").unwrap();
+        let config = |path: PathBuf, filter| ServerConfig {
+            profanity_words_path: Some(path),
+            profanity_filter: filter,
+            ..valid()
+        };
+
+        let missing = config(dir.path().join("missing.txt"), ProfanityFilter::Mask);
+        assert!(mentions(&errors(missing), "MURMURE_PROFANITY_WORDS_FILE"));
+        let unused = config(words.clone(), ProfanityFilter::Off);
+        assert!(mentions(
+            &warnings(unused),
+            "MURMURE_PROFANITY_FILTER is off"
+        ));
+        assert!(config(words, ProfanityFilter::Mask)
+            .validate()
+            .warnings
+            .is_empty());
+    }
+
+    #[test]
+    fn redaction_patterns_must_compile() {
+        let config = |pattern: &str| ServerConfig {
+            redact_patterns: vec![pattern.to_string()],
+            ..valid()
+        };
+        assert!(mentions(
+            &errors(config("(unclosed")),
+            "Invalid redaction pattern '(unclosed'"
+        ));
+        assert!(errors(config(r"\d{4}")).is_empty());
+    }
+
+    #[test]
+    fn itn_warns_about_unsupported_languages() {
+        let config = |language: &str| ServerConfig {
+            itn: true,
+            itn_language: language.to_string(),
+            ..valid()
+        };
+        assert!(mentions(&warnings(config("xx")), "no normalization rules"));
+        assert!(warnings(config("en")).is_empty());
+    }
+
+    #[test]
+    fn ready_file_directory_must_exist() {
+        let config = |path: &str| ServerConfig {
+            ready_file: Some(PathBuf::from(path)),
+            ..valid()
+        };
+        assert!(mentions(
+            &errors(config("/nonexistent/dir/ready")),
+            "MURMURE_READY_FILE"
+        ));
+        let dir = tempfile::tempdir().unwrap();
+        let ready = dir.path().join("ready");
+        assert!(errors(config(ready.to_str().unwrap())).is_empty());
+    }
+
+    #[test]
+    fn parses_incomplete_stream_handling() {
+        assert_eq!(
+            "Transcribe".parse::<IncompleteStream>().unwrap(),
+            IncompleteStream::Transcribe
+        );
+        assert_eq!(
+            "discard".parse::<IncompleteStream>().unwrap(),
+            IncompleteStream::Discard
+        );
+        assert!("keep".parse::<IncompleteStream>().is_err());
+    }
+
+    #[test]
+    fn discarding_incomplete_streams_leaves_nothing_to_salvage() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = |incomplete_stream| ServerConfig {
+            salvage_dir: Some(dir.path().to_path_buf()),
+            incomplete_stream,
+            ..valid()
+        };
+        assert!(mentions(
+            &warnings(config(IncompleteStream::Discard)),
+            "will not be salvaged"
+        ));
+        assert!(warnings(config(IncompleteStream::Transcribe)).is_empty());
+    }
+
+    #[test]
+    fn highpass_cutoff_must_be_in_the_band() {
+        let config = |cutoff: f32| ServerConfig {
+            highpass_hz: Some(cutoff),
+            ..valid()
+        };
+        assert!(mentions(&errors(config(0.0)), "MURMURE_HIGHPASS_HZ"));
+        assert!(mentions(&errors(config(8000.0)), "MURMURE_HIGHPASS_HZ"));
+        assert!(mentions(&warnings(config(400.0)), "part of the voice"));
+        let report = config(80.0).validate();
+        assert!(report.errors.is_empty() && report.warnings.is_empty());
+    }
+
+    /// Write `content` to `name` in `dir` and load it as a config file.
+    fn load(dir: &tempfile::TempDir, name: &str, content: &str) -> Option<ServerConfig> {
+        let path = dir.path().join(name);
+        fs::write(&path, content).unwrap();
+        ServerConfig::load_from_file(path.to_str().unwrap())
+    }
+
+    #[test]
+    fn loads_top_level_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = load(
+            &dir,
+            "config.json",
+            r#"{"grpc_port": 6000, "punctuate": true}"#,
+        )
+        .expect("JSON config loads");
+        assert_eq!(json.grpc_port, 6000);
+        assert!(json.punctuate);
+        // Everything else keeps its default
+        assert_eq!(json.itn_language, ServerConfig::default().itn_language);
+
+        let toml = load(&dir, "config.toml", "grpc_port = 6001\npunctuate = true\n")
+            .expect("TOML config loads");
+        assert_eq!(toml.grpc_port, 6001);
+        assert!(toml.punctuate);
+    }
+
+    #[test]
+    fn loads_the_server_section_of_a_shared_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let toml = load(
+            &dir,
+            "config.toml",
+            "[server]\ngrpc_port = 6002\n\n[tts]\nvoice = \"alto\"\n",
+        )
+        .expect("TOML config loads");
+        assert_eq!(toml.grpc_port, 6002);
+
+        let json = load(
+            &dir,
+            "config.json",
+            r#"{"server": {"grpc_port": 6003}, "tts": {"voice": "alto"}}"#,
+        )
+        .expect("JSON config loads");
+        assert_eq!(json.grpc_port, 6003);
+    }
+
+    #[test]
+    fn unknown_keys_do_not_stop_loading() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = load(&dir, "config.toml", "grpc_port = 6004\ngprc_port = 1\n")
+            .expect("config with a typo loads");
+        assert_eq!(config.grpc_port, 6004);
+    }
+
+    #[test]
+    fn unreadable_files_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(load(&dir, "config.json", "{ not json").is_none());
+        assert!(load(&dir, "config.toml", "grpc_port = ").is_none());
+        assert!(load(&dir, "config.toml", "grpc_port = \"fast\"\n").is_none());
+        assert!(load(&dir, "config.yaml", "grpc_port: 6005\n").is_none());
+        assert!(ServerConfig::load_from_file("/nonexistent/config.toml").is_none());
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = load(
+            &dir,
+            "config.toml",
+            "[server]\nsegment_min_silence_ms = 900\nhighpass_hz = 80.0\n",
+        )
+        .unwrap();
+
+        // Variables that aren't set leave the file's settings alone
+        let merged = file.clone().merge_with_env(ServerConfig::default());
+        assert_eq!(merged.segment_min_silence_ms, 900);
+        assert_eq!(merged.highpass_hz, Some(80.0));
+
+        let env = ServerConfig {
+            highpass_hz: Some(120.0),
+            ..ServerConfig::default()
+        };
+        let merged = file.merge_with_env(env);
+        assert_eq!(merged.segment_min_silence_ms, 900);
+        assert_eq!(merged.highpass_hz, Some(120.0));
+    }
+
+    #[test]
+    fn memory_maps_models_unless_turned_off() {
+        assert!(ServerConfig::default().mmap_model);
+        let dir = tempfile::tempdir().unwrap();
+        let file = load(&dir, "config.toml", "[server]\nmmap_model = false\n").unwrap();
+        assert!(!file.mmap_model);
+        assert!(!file.merge_with_env(ServerConfig::default()).mmap_model);
+    }
+
+    #[test]
+    fn minimum_audio_length_must_be_below_the_maximum() {
+        let config = |min_audio_ms: u64, max_audio_secs: u64| ServerConfig {
+            min_audio_ms,
+            max_audio_secs,
+            ..valid()
+        };
+        assert!(mentions(
+            &errors(config(2000, 2)),
+            "MURMURE_MIN_AUDIO_MS (2000 ms) must be below MURMURE_MAX_AUDIO_SECS (2 s)"
+        ));
+        assert!(errors(config(1999, 2)).is_empty());
+        // No maximum
+        assert!(errors(config(5000, 0)).is_empty());
+        assert_eq!(config(250, 0).min_audio_duration(), 0.25);
+    }
+
+    #[test]
+    fn temp_dir_must_be_writable() {
+        let root = tempfile::tempdir().unwrap();
+        let blocker = root.path().join("file");
+        fs::write(&blocker, b"").unwrap();
+
+        let config = |temp_dir: PathBuf| ServerConfig {
+            temp_dir,
+            ..valid()
+        };
+        let unwritable = blocker.join("spool");
+        assert!(mentions(
+            &errors(config(unwritable.clone())),
+            &format!(
+                "MURMURE_TEMP_DIR is not writable: can't create a temp file in {}",
+                unwritable.display()
+            )
+        ));
+        assert!(mentions(&errors(config(blocker)), "is not a directory"));
+        assert!(errors(config(root.path().join("spool"))).is_empty());
+    }
+
+    #[test]
+    fn large_files_default_to_the_general_limit() {
+        let config = |max_audio_secs: u64, large_file_max_audio_secs: Option<u64>| ServerConfig {
+            max_audio_secs,
+            large_file_max_audio_secs,
+            ..valid()
+        };
+        assert_eq!(
+            config(1800, None).large_file_max_audio_duration(),
+            Some(1800.0)
+        );
+        assert_eq!(
+            config(1800, Some(14_400)).large_file_max_audio_duration(),
+            Some(14_400.0)
+        );
+        assert_eq!(config(1800, Some(0)).large_file_max_audio_duration(), None);
+        assert_eq!(config(0, None).large_file_max_audio_duration(), None);
+
+        assert!(warnings(config(1800, Some(14_400))).is_empty());
+        assert!(mentions(
+            &warnings(config(1800, Some(600))),
+            "MURMURE_LARGE_FILE_MAX_AUDIO_SECS (600 s) is stricter"
+        ));
+        assert!(mentions(
+            &warnings(config(0, Some(600))),
+            "is stricter than"
+        ));
+    }
+
+    #[test]
+    fn searches_resources_next_to_the_working_directory_first() {
+        let dirs = resource_search_dirs();
+        let resources = Path::new("resources");
+        assert_eq!(
+            dirs[..3],
+            [
+                resources.to_path_buf(),
+                Path::new("..").join(resources),
+                Path::new("_up_").join(resources),
+            ]
+        );
+        let exe_dir = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+        assert_eq!(
+            dirs[3..5],
+            [
+                exe_dir.join("_up_").join(resources),
+                exe_dir.join(resources)
+            ]
+        );
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn searches_only_resources_dirs_outside_windows() {
+        assert_eq!(resource_search_dirs().len(), 5);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn searches_windows_install_locations_last() {
+        let dirs = resource_search_dirs();
+        let exe_dir = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+        let mut expected = vec![exe_dir];
+        if let Some(program_data) = env::var_os("PROGRAMDATA") {
+            expected.push(PathBuf::from(program_data).join("Murmure").join("models"));
+        }
+        if let Some(local_app_data) = env::var_os("LOCALAPPDATA") {
+            expected.push(PathBuf::from(local_app_data).join("Murmure"));
+        }
+        assert_eq!(dirs[5..], expected[..]);
+    }
+
+    #[test]
+    fn finds_resources_in_the_first_directory_that_has_them() {
+        // Tests run in the crate directory, the repository's resources/ is
+        // one level up
+        assert_eq!(
+            find_resource(CC_RULES_DIR),
+            Some(Path::new("..").join("resources").join(CC_RULES_DIR))
+        );
+        assert_eq!(find_resource("no-such-resource"), None);
+        assert!(searched_dirs().starts_with(&format!(
+            "resources, {}",
+            Path::new("..").join("resources").display()
+        )));
+    }
+
+    #[test]
+    fn commands_need_a_phrase_an_action_and_a_threshold() {
+        let command = |phrase: &str, action: &str| VoiceCommand {
+            phrase: phrase.to_string(),
+            action: action.to_string(),
+        };
+        let config = |commands: Vec<VoiceCommand>, command_threshold: f32| ServerConfig {
+            commands,
+            command_threshold,
+            ..valid()
+        };
+        assert!(mentions(
+            &errors(config(vec![command("new line", " ")], 0.8)),
+            "need both a phrase and an action"
+        ));
+        assert!(mentions(
+            &errors(config(
+                vec![command("new line", "a"), command("New Line", "b")],
+                0.8
+            )),
+            "lists the phrase 'New Line' more than once"
+        ));
+        assert!(mentions(
+            &errors(config(Vec::new(), 0.0)),
+            "MURMURE_COMMAND_THRESHOLD"
+        ));
+        assert!(mentions(
+            &errors(config(Vec::new(), 1.5)),
+            "MURMURE_COMMAND_THRESHOLD"
+        ));
+        assert!(mentions(
+            &warnings(config(vec![command("new line", "a")], 0.3)),
+            "taken for a command"
+        ));
+        let report = config(vec![command("new line", "newline")], 0.8).validate();
+        assert!(report.errors.is_empty() && report.warnings.is_empty());
+    }
+
+    #[test]
+    fn looks_languages_up_from_the_most_specific_code() {
+        let codes = |language| language_fallbacks(language).collect::<Vec<_>>();
+        assert_eq!(codes("fr-CA"), ["fr-ca", "fr"]);
+        assert_eq!(codes(" FR_ca "), ["fr-ca", "fr"]);
+        assert_eq!(codes("en"), ["en"]);
+
+        for code in ["en", "fr-CA", "pt_BR", "zh-Hant"] {
+            assert!(is_language_code(code), "{}", code);
+        }
+        for code in ["", "e", "french language", "fr-", "../fr"] {
+            assert!(!is_language_code(code), "{}", code);
+        }
+    }
+
+    #[test]
+    fn languages_use_their_own_cc_rules_when_there_are_some() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("fr")).unwrap();
+        let config = ServerConfig {
+            cc_rules_path: Some(dir.path().to_path_buf()),
+            ..ServerConfig::default()
+        };
+        let rules = |language| config.get_language_cc_rules_path(language).unwrap();
+        assert_eq!(rules("fr"), dir.path().join("fr"));
+        assert_eq!(rules("fr-CA"), dir.path().join("fr"));
+        assert_eq!(rules("en"), dir.path());
+    }
+
+    #[test]
+    fn checks_language_dictionaries() {
+        let config = |language: &str, words: &[&str]| ServerConfig {
+            dictionaries: [(
+                language.to_string(),
+                words.iter().map(|word| word.to_string()).collect(),
+            )]
+            .into(),
+            ..valid()
+        };
+        assert!(errors(config("fr", &["Murmure"])).is_empty());
+        assert!(mentions(
+            &errors(config("fr ca", &["Murmure"])),
+            "MURMURE_DICTIONARIES key 'fr ca' is not a language code"
+        ));
+        assert!(mentions(
+            &errors(config("fr", &["Murmure", " "])),
+            "MURMURE_DICTIONARIES 'fr' contains an empty entry"
+        ));
+
+        // They need cc-rules as much as the default dictionary does
+        let without_rules = ServerConfig {
+            cc_rules_path: Some(PathBuf::from("/nonexistent/cc-rules")),
+            ..config("fr", &["Murmure"])
+        };
+        assert!(mentions(
+            &errors(without_rules),
+            "cc-rules directory was not found"
+        ));
+    }
+
+    #[test]
+    fn loads_language_dictionaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = load(
+            &dir,
+            "config.toml",
+            "[server.dictionaries]\nen = [\"Murmure\"]\nfr = [\"Murmure\", \"Parakeet\"]\n",
+        )
+        .unwrap();
+        assert_eq!(file.dictionaries["en"], ["Murmure"]);
+        assert_eq!(file.dictionaries["fr"], ["Murmure", "Parakeet"]);
+
+        // The environment's replace the file's as a whole
+        let env = ServerConfig {
+            dictionaries: [("de".to_string(), vec!["Murmure".to_string()])].into(),
+            ..ServerConfig::default()
+        };
+        let merged = file.clone().merge_with_env(env);
+        assert_eq!(merged.dictionaries.keys().collect::<Vec<_>>(), ["de"]);
+        let merged = file.merge_with_env(ServerConfig::default());
+        assert_eq!(merged.dictionaries.len(), 2);
+    }
+
+    #[test]
+    fn decoding_settings_are_checked_and_applied() {
+        let config = |blank_penalty, max_symbols_per_step| ServerConfig {
+            decoding_blank_penalty: blank_penalty,
+            decoding_max_symbols_per_step: max_symbols_per_step,
+            ..valid()
+        };
+        assert!(errors(config(10.0, 32)).is_empty());
+        assert!(errors(config(-10.0, 1)).is_empty());
+        assert!(mentions(
+            &errors(config(10.5, 10)),
+            "MURMURE_DECODING_BLANK_PENALTY must be between -10 and 10, got 10.5"
+        ));
+        assert!(mentions(
+            &errors(config(0.0, 0)),
+            "MURMURE_DECODING_MAX_SYMBOLS_PER_STEP must be between 1 and 32, got 0"
+        ));
+        assert!(mentions(
+            &errors(config(0.0, 33)),
+            "MURMURE_DECODING_MAX_SYMBOLS_PER_STEP must be between 1 and 32"
+        ));
+
+        assert_eq!(
+            ServerConfig::default().decoding_options(),
+            DecodingOptions::default()
+        );
+        let limited = ServerConfig {
+            decoding_max_tokens: 50,
+            ..config(-1.0, 3)
+        };
+        assert_eq!(
+            limited.decoding_options(),
+            DecodingOptions {
+                blank_penalty: -1.0,
+                max_symbols_per_step: 3,
+                max_tokens: Some(50),
+            }
+        );
+    }
+
+    #[test]
+    fn detected_languages_must_be_supported_once() {
+        let config = |languages: &[&str]| ServerConfig {
+            detect_languages: languages.iter().map(|l| l.to_string()).collect(),
+            ..valid()
+        };
+        assert!(errors(config(&["en", "fr-CA"])).is_empty());
+        assert!(mentions(
+            &errors(config(&["en us"])),
+            "MURMURE_DETECT_LANGUAGES entry 'en us' is not a language code"
+        ));
+        assert!(mentions(
+            &errors(config(&["ja"])),
+            "MURMURE_DETECT_LANGUAGES entry 'ja' can't be detected, supported languages are en, fr"
+        ));
+        assert!(mentions(
+            &errors(config(&["en", "EN"])),
+            "MURMURE_DETECT_LANGUAGES lists 'EN' more than once"
+        ));
+    }
+
+    #[test]
+    fn two_pass_settings_are_checked() {
+        let config = |confidence, model: Option<&str>| ServerConfig {
+            two_pass_confidence: confidence,
+            two_pass_model: model.map(str::to_string),
+            models: vec![ModelSpec {
+                name: "accurate".to_string(),
+                path: PathBuf::from("/models/accurate"),
+                precision: Default::default(),
+            }],
+            ..valid()
+        };
+        let two_pass_errors = |config| {
+            errors(config)
+                .into_iter()
+                .filter(|e| e.contains("TWO_PASS"))
+                .collect::<Vec<_>>()
+        };
+        assert!(two_pass_errors(config(1.0, Some("accurate"))).is_empty());
+        assert!(two_pass_errors(config(0.5, None)).is_empty());
+        for confidence in [0.0, 1.5, f32::NAN] {
+            assert!(mentions(
+                &two_pass_errors(config(confidence, None)),
+                "MURMURE_TWO_PASS_CONFIDENCE must be above 0 and at most 1"
+            ));
+        }
+        assert!(mentions(
+            &two_pass_errors(config(0.7, Some("large"))),
+            "MURMURE_TWO_PASS_MODEL 'large' is not one of the models in MURMURE_MODELS"
+        ));
+    }
 }
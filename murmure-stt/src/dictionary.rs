@@ -1,21 +1,67 @@
-use rphonetic::{BeiderMorseBuilder, ConfigFiles, LanguageSet};
+use rphonetic::{BeiderMorseBuilder, ConfigFiles, LanguageSet, PhoneticError};
 use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
 };
 
-pub struct Dictionary(pub Arc<Mutex<Vec<String>>>);
+pub struct Dictionary {
+    words: Arc<Mutex<Vec<String>>>,
+    /// Bumped on every change, so responses can say which word list they used
+    revision: AtomicU64,
+}
 
 impl Dictionary {
     pub fn new(dictionary: Vec<String>) -> Self {
-        Self(Arc::new(Mutex::new(dictionary)))
+        Self {
+            words: Arc::new(Mutex::new(dictionary)),
+            revision: AtomicU64::new(0),
+        }
     }
     pub fn get(&self) -> Vec<String> {
-        self.0.lock().unwrap().clone()
+        self.words.lock().unwrap().clone()
     }
     pub fn set(&self, dictionary: Vec<String>) {
-        *self.0.lock().unwrap() = dictionary;
+        *self.words.lock().unwrap() = dictionary;
+        self.revision.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Number of changes since the dictionary was loaded (0 = as loaded).
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Relaxed)
     }
+    /// Correct `text` against the current word list. Needs no engine or
+    /// model, so it works on transcripts from any source.
+    pub fn correct(&self, text: &str, cc_rules: &Path) -> Result<CorrectedText, RulesError> {
+        correct_transcription_with_dictionary(text.to_string(), &self.get(), cc_rules.to_path_buf())
+    }
+}
+
+/// A word of the transcription replaced by a dictionary entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Correction {
+    /// Word as transcribed
+    pub original: String,
+    /// Dictionary entry it was replaced with
+    pub replacement: String,
+    /// Character offset of the replacement in the corrected text
+    pub offset: usize,
+}
+
+/// Dictionary-corrected text and the list of replacements applied to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorrectedText {
+    pub text: String,
+    pub corrections: Vec<Correction>,
+}
+
+/// The cc-rules directory exists but its rule files can't be loaded.
+#[derive(Debug, thiserror::Error)]
+#[error("Failed to load the cc-rules in {}: {source}", path.display())]
+pub struct RulesError {
+    pub path: PathBuf,
+    pub source: PhoneticError,
 }
 
 /**
@@ -25,12 +71,30 @@ pub fn fix_transcription_with_dictionary(
     transcription: String,
     dictionary: Vec<String>,
     cc_rules_path: PathBuf,
-) -> String {
+) -> Result<String, RulesError> {
+    correct_transcription_with_dictionary(transcription, &dictionary, cc_rules_path)
+        .map(|corrected| corrected.text)
+}
+
+/**
+ * Use phonetic algorithm to fix the transcription, reporting each replacement
+ */
+pub fn correct_transcription_with_dictionary(
+    transcription: String,
+    dictionary: &[String],
+    cc_rules_path: PathBuf,
+) -> Result<CorrectedText, RulesError> {
     if dictionary.is_empty() {
-        return transcription;
+        return Ok(CorrectedText {
+            text: transcription,
+            corrections: Vec::new(),
+        });
     }
 
-    let config_files = ConfigFiles::new(&cc_rules_path).unwrap();
+    let config_files = ConfigFiles::new(&cc_rules_path).map_err(|source| RulesError {
+        path: cc_rules_path.clone(),
+        source,
+    })?;
     let builder = BeiderMorseBuilder::new(&config_files);
     let beider_morse = builder.build();
 
@@ -39,34 +103,163 @@ pub fn fix_transcription_with_dictionary(
 
     // Prepare dictionary words to be encoded phonetically
     let mut encoded_dict = Vec::new();
-    for word in &dictionary {
+    for word in dictionary {
         let code = beider_morse.encode_with_languages(word, &langs);
         encoded_dict.push((word, code));
     }
 
-    // Split transcription into words
-    let mut corrected_transcription = transcription.clone();
-    let words: Vec<&str> = transcription.split_whitespace().collect();
+    // Rebuild the transcription word by word so each replacement's position
+    // in the corrected text is known
+    let mut corrected = CorrectedText::default();
+    let mut corrected_chars = 0;
+    let mut rest = transcription.as_str();
+
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+        let end = rest[start..]
+            .find(char::is_whitespace)
+            .map_or(rest.len(), |len| start + len);
+        let token = &rest[start..end];
+
+        // Surrounding punctuation stays in place, only the word is compared
+        let word = token.trim_matches(|c: char| !c.is_alphanumeric());
+        if word.is_empty() {
+            corrected.text.push_str(&rest[..end]);
+            corrected_chars += rest[..end].chars().count();
+            rest = &rest[end..];
+            continue;
+        }
+        let word_start = start + token.find(word).unwrap_or(0);
+        let word_end = word_start + word.len();
 
-    for word in words {
         let candidate = beider_morse.encode_with_languages(word, &langs);
         let candidate_codes: Vec<&str> = candidate.split('|').collect();
-        for (dict_word, dict_code) in &encoded_dict {
+        let replacement = encoded_dict.iter().find_map(|(dict_word, dict_code)| {
             let dict_codes: Vec<&str> = dict_code.split('|').collect();
-            println!(
-                "Dict word: {:?}, Dict code: {:?}, Candidate: {:?}",
-                dict_word, dict_code, candidate
-            );
-            if dict_codes.iter().any(|dc| candidate_codes.contains(dc)) {
-                corrected_transcription = corrected_transcription.replace(word, dict_word);
+            dict_codes
+                .iter()
+                .any(|dc| candidate_codes.contains(dc))
+                .then_some(dict_word.as_str())
+        });
+
+        let before = &rest[..word_start];
+        corrected.text.push_str(before);
+        corrected_chars += before.chars().count();
+
+        match replacement {
+            Some(dict_word) if dict_word != word => {
+                corrected.corrections.push(Correction {
+                    original: word.to_string(),
+                    replacement: dict_word.to_string(),
+                    offset: corrected_chars,
+                });
+                corrected.text.push_str(dict_word);
+                corrected_chars += dict_word.chars().count();
+            }
+            _ => {
+                corrected.text.push_str(word);
+                corrected_chars += word.chars().count();
             }
         }
+
+        rest = &rest[word_end..];
     }
+    corrected.text.push_str(rest);
 
-    corrected_transcription
+    Ok(corrected)
 }
 
 // Downloaded from https://github.com/apache/commons-codec/tree/rel/commons-codec-1.15/src/main/resources/org/apache/commons/codec/language/bm
 pub fn get_cc_rules_path(config: &crate::config::ServerConfig) -> anyhow::Result<PathBuf> {
     config.get_cc_rules_path()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cc_rules() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../resources/cc-rules")
+    }
+
+    fn words(list: &[&str]) -> Vec<String> {
+        list.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn empty_dictionary_leaves_the_text_alone() {
+        // No rules are loaded, so a missing directory is fine
+        let corrected = correct_transcription_with_dictionary(
+            "hello  there".to_string(),
+            &[],
+            "missing".into(),
+        )
+        .unwrap();
+        assert_eq!(corrected.text, "hello  there");
+        assert!(corrected.corrections.is_empty());
+    }
+
+    #[test]
+    fn missing_rules_are_an_error() {
+        let err = correct_transcription_with_dictionary(
+            "hello".to_string(),
+            &words(&["Murmure"]),
+            "/nonexistent/cc-rules".into(),
+        )
+        .unwrap_err();
+        assert_eq!(err.path, PathBuf::from("/nonexistent/cc-rules"));
+    }
+
+    #[test]
+    fn sound_alike_words_are_replaced_in_place() {
+        let corrected = correct_transcription_with_dictionary(
+            "i use  murmur, daily".to_string(),
+            &words(&["Murmure"]),
+            cc_rules(),
+        )
+        .unwrap();
+        assert_eq!(corrected.text, "i use  Murmure, daily");
+        assert_eq!(
+            corrected.corrections,
+            [Correction {
+                original: "murmur".to_string(),
+                replacement: "Murmure".to_string(),
+                offset: 7,
+            }]
+        );
+    }
+
+    #[test]
+    fn exact_matches_are_not_reported() {
+        let corrected = correct_transcription_with_dictionary(
+            "Murmure works".to_string(),
+            &words(&["Murmure"]),
+            cc_rules(),
+        )
+        .unwrap();
+        assert_eq!(corrected.text, "Murmure works");
+        assert!(corrected.corrections.is_empty());
+    }
+
+    #[test]
+    fn set_bumps_the_revision() {
+        let dictionary = Dictionary::new(words(&["one"]));
+        assert_eq!(dictionary.revision(), 0);
+        dictionary.set(words(&["one", "two"]));
+        assert_eq!(dictionary.revision(), 1);
+        assert_eq!(dictionary.get(), words(&["one", "two"]));
+    }
+
+    #[test]
+    fn correcting_twice_equals_once() {
+        let dictionary = Dictionary::new(words(&["Murmure", "Parakeet"]));
+        let once = dictionary
+            .correct("murmur runs parakit models, murmur is local", &cc_rules())
+            .unwrap();
+        assert_eq!(once.text, "Murmure runs Parakeet models, Murmure is local");
+        assert_eq!(once.corrections.len(), 3);
+
+        let twice = dictionary.correct(&once.text, &cc_rules()).unwrap();
+        assert_eq!(twice.text, once.text);
+        assert!(twice.corrections.is_empty(), "{:?}", twice.corrections);
+    }
+}
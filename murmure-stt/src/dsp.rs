@@ -0,0 +1,760 @@
+// Audio DSP primitives shared by the transcription pipeline
+//
+// Everything here works on f32 samples in [-1.0, 1.0] and has no engine or
+// model dependency, so it can be reused by any audio consumer (STT today,
+// synthesis output later).
+
+use anyhow::Result;
+use realfft::num_complex::Complex;
+use realfft::RealFftPlanner;
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read, Seek, Write};
+use std::str::FromStr;
+
+use crate::wav::{self, WavFormat};
+
+/// Half-length of the windowed-sinc kernel, in samples at the lower of the two rates
+const SINC_HALF_TAPS: usize = 16;
+
+/// Largest upsampling factor (after ratio reduction) handled by the polyphase
+/// resampler; beyond this the phase table gets too large and linear is used.
+const MAX_POLYPHASE_PHASES: usize = 1024;
+
+/// Order of the Butterworth high-pass (36 dB per octave), so a 50 Hz hum is
+/// attenuated by more than 20 dB with an 80 Hz cutoff
+const HIGHPASS_ORDER: usize = 6;
+
+/// Length of a noise reduction analysis frame, in milliseconds
+const GATE_FRAME_MS: usize = 32;
+
+/// Share of the quietest frames the noise profile is estimated from
+const GATE_NOISE_FRACTION: f32 = 0.1;
+
+/// A bin passes the gate once it is this many times above the noise floor (6 dB)
+const GATE_THRESHOLD: f32 = 2.0;
+
+/// Gain applied to bins below the threshold (-20 dB); gating to zero would
+/// leave audible holes and musical noise
+const GATE_REDUCTION: f32 = 0.1;
+
+/// Resampling algorithm used when input audio isn't already at the target rate.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResamplerQuality {
+    /// Linear interpolation (cheap, audible aliasing on 44.1/48 kHz input)
+    Linear,
+    /// Polyphase windowed-sinc low-pass resampling (default)
+    #[default]
+    High,
+}
+
+impl FromStr for ResamplerQuality {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(Self::Linear),
+            "high" => Ok(Self::High),
+            other => Err(anyhow::anyhow!(
+                "Unknown resampler quality '{}', expected 'linear' or 'high'",
+                other
+            )),
+        }
+    }
+}
+
+/// Decoded WAV audio, downmixed to mono.
+#[derive(Debug, Clone)]
+pub struct MonoAudio {
+    /// Mono samples in [-1.0, 1.0]
+    pub samples: Vec<f32>,
+    /// Sample rate of `samples` in Hz
+    pub sample_rate: u32,
+    /// Channel count of the source before downmixing
+    pub source_channels: u16,
+    /// Significant bits of a source sample
+    pub bits_per_sample: u16,
+}
+
+/// Decoded WAV audio with its channels kept apart.
+#[derive(Debug, Clone)]
+pub struct MultiChannelAudio {
+    /// One sample buffer per channel, in [-1.0, 1.0]
+    pub channels: Vec<Vec<f32>>,
+    /// Sample rate of every channel in Hz
+    pub sample_rate: u32,
+    /// Significant bits of a source sample
+    pub bits_per_sample: u16,
+}
+
+/// Read WAV data (integer PCM or float, see wav.rs) from any reader and
+/// downmix it to mono.
+pub fn read_wav<R: Read>(reader: R) -> Result<MonoAudio> {
+    let (interleaved, format) = decode_wav(reader)?;
+
+    Ok(MonoAudio {
+        samples: downmix_to_mono(&interleaved, format.channels as usize),
+        sample_rate: format.sample_rate,
+        source_channels: format.channels,
+        bits_per_sample: format.bits_per_sample,
+    })
+}
+
+/// Read WAV data from any reader, keeping each channel separate.
+pub fn read_wav_channels<R: Read>(reader: R) -> Result<MultiChannelAudio> {
+    let (interleaved, format) = decode_wav(reader)?;
+
+    Ok(MultiChannelAudio {
+        channels: deinterleave(&interleaved, format.channels as usize),
+        sample_rate: format.sample_rate,
+        bits_per_sample: format.bits_per_sample,
+    })
+}
+
+/// Decode the audio of a WAV file to interleaved f32 samples.
+fn decode_wav<R: Read>(mut reader: R) -> Result<(Vec<f32>, WavFormat)> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let layout = wav::read_layout(&mut Cursor::new(&bytes), bytes.len() as u64)?;
+    let start = layout.data_start as usize;
+    let data = &bytes[start..start + layout.data_len as usize];
+
+    Ok((
+        wav::decode_samples(data, layout.format.encoding),
+        layout.format,
+    ))
+}
+
+/// Read WAV data from an in-memory buffer and downmix it to mono.
+pub fn read_wav_bytes(bytes: &[u8]) -> Result<MonoAudio> {
+    read_wav(Cursor::new(bytes))
+}
+
+/// Write mono samples as a 16-bit PCM WAV stream.
+pub fn write_wav<W: Write + Seek>(writer: W, samples: &[f32], sample_rate: u32) -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut writer = hound::WavWriter::new(writer, spec)?;
+    for &sample in samples {
+        writer.write_sample(f32_to_i16(sample))?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+/// Encode mono samples as an in-memory 16-bit PCM WAV file.
+pub fn write_wav_bytes(samples: &[f32], sample_rate: u32) -> Result<Vec<u8>> {
+    let mut cursor = Cursor::new(Vec::with_capacity(44 + samples.len() * 2));
+    write_wav(&mut cursor, samples, sample_rate)?;
+    Ok(cursor.into_inner())
+}
+
+/// Average interleaved frames into a single channel.
+///
+/// Mono input is returned unchanged; a trailing partial frame is dropped.
+pub fn downmix_to_mono(interleaved: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return interleaved.to_vec();
+    }
+    interleaved
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// Split interleaved frames into one buffer per channel.
+///
+/// A trailing partial frame is dropped.
+pub fn deinterleave(interleaved: &[f32], channels: usize) -> Vec<Vec<f32>> {
+    if channels <= 1 {
+        return vec![interleaved.to_vec()];
+    }
+    let mut split = vec![Vec::with_capacity(interleaved.len() / channels); channels];
+    for frame in interleaved.chunks_exact(channels) {
+        for (channel, &sample) in split.iter_mut().zip(frame) {
+            channel.push(sample);
+        }
+    }
+    split
+}
+
+/// Resample `input` from `src_hz` to `dst_hz` with the requested quality.
+pub fn resample(
+    input: &[f32],
+    src_hz: usize,
+    dst_hz: usize,
+    quality: ResamplerQuality,
+) -> Vec<f32> {
+    match quality {
+        ResamplerQuality::Linear => resample_linear(input, src_hz, dst_hz),
+        ResamplerQuality::High => resample_sinc(input, src_hz, dst_hz),
+    }
+}
+
+/// Resample with linear interpolation between neighbouring samples.
+///
+/// Output length is `ceil(len * dst_hz / src_hz)`; empty input or a zero rate
+/// yields an empty buffer.
+pub fn resample_linear(input: &[f32], src_hz: usize, dst_hz: usize) -> Vec<f32> {
+    if input.is_empty() || src_hz == 0 || dst_hz == 0 {
+        return Vec::new();
+    }
+    if src_hz == dst_hz {
+        return input.to_vec();
+    }
+    let ratio = dst_hz as f64 / src_hz as f64;
+    let out_len = ((input.len() as f64) * ratio).ceil() as usize;
+    if out_len == 0 {
+        return Vec::new();
+    }
+    let mut out = Vec::with_capacity(out_len);
+    let last_idx = input.len().saturating_sub(1);
+    for i in 0..out_len {
+        let t = (i as f64) / ratio;
+        let idx = t.floor() as usize;
+        let frac = (t - idx as f64) as f32;
+        let a = input[idx];
+        let b = input[std::cmp::min(idx + 1, last_idx)];
+        out.push(a + (b - a) * frac);
+    }
+    out
+}
+
+/// Resample with a polyphase Blackman-windowed sinc filter.
+///
+/// The kernel is symmetric around each output instant, so the output is time
+/// aligned with the input (no group delay) and has the same length as
+/// [`resample_linear`]. The cutoff follows the lower of the two Nyquist
+/// frequencies to suppress aliasing when downsampling, and every phase is
+/// normalized to unity DC gain so silence and constant offsets are preserved.
+/// Ratios that don't reduce to at most `MAX_POLYPHASE_PHASES` phases fall back
+/// to linear interpolation.
+pub fn resample_sinc(input: &[f32], src_hz: usize, dst_hz: usize) -> Vec<f32> {
+    if input.is_empty() || src_hz == 0 || dst_hz == 0 {
+        return Vec::new();
+    }
+    if src_hz == dst_hz {
+        return input.to_vec();
+    }
+
+    let divisor = gcd(src_hz, dst_hz);
+    let up = dst_hz / divisor;
+    let down = src_hz / divisor;
+    if up > MAX_POLYPHASE_PHASES {
+        return resample_linear(input, src_hz, dst_hz);
+    }
+
+    // Cutoff relative to the input Nyquist frequency
+    let cutoff = (dst_hz as f64 / src_hz as f64).min(1.0);
+    let half_width = (SINC_HALF_TAPS as f64 / cutoff).ceil() as isize;
+    let taps = (2 * half_width) as usize;
+
+    // table[phase * taps + j] weights input sample (idx + j - half_width + 1)
+    let mut table = vec![0.0f32; up * taps];
+    for phase in 0..up {
+        let frac = phase as f64 / up as f64;
+        let weights = &mut table[phase * taps..(phase + 1) * taps];
+        let mut sum = 0.0f64;
+        let mut raw = Vec::with_capacity(taps);
+        for j in 0..taps {
+            let x = (j as isize - half_width + 1) as f64 - frac;
+            let w = cutoff * sinc(cutoff * x) * blackman(x, half_width as f64);
+            sum += w;
+            raw.push(w);
+        }
+        for (weight, w) in weights.iter_mut().zip(raw) {
+            *weight = (w / sum) as f32;
+        }
+    }
+
+    let out_len = (input.len() * up).div_ceil(down);
+    let last_idx = input.len() as isize - 1;
+    let mut out = Vec::with_capacity(out_len);
+    for i in 0..out_len {
+        let pos = i * down;
+        let idx = (pos / up) as isize;
+        let phase = pos % up;
+        let weights = &table[phase * taps..(phase + 1) * taps];
+
+        let mut acc = 0.0f32;
+        for (j, &weight) in weights.iter().enumerate() {
+            let sample_idx = (idx + j as isize - half_width + 1).clamp(0, last_idx);
+            acc += input[sample_idx as usize] * weight;
+        }
+        out.push(acc);
+    }
+    out
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+/// Blackman window centered at 0, zero outside `[-half_width, half_width]`.
+fn blackman(x: f64, half_width: f64) -> f64 {
+    if x.abs() >= half_width {
+        return 0.0;
+    }
+    let t = std::f64::consts::PI * x / half_width;
+    0.42 + 0.5 * t.cos() + 0.08 * (2.0 * t).cos()
+}
+
+fn gcd(mut a: usize, mut b: usize) -> usize {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Root-mean-square level of a block of samples.
+pub fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum_sq: f32 = samples.iter().map(|s| s * s).sum();
+    (sum_sq / samples.len() as f32).sqrt()
+}
+
+/// Largest absolute sample value.
+pub fn peak(samples: &[f32]) -> f32 {
+    samples.iter().fold(0.0f32, |max, s| max.max(s.abs()))
+}
+
+/// Convert a level in dBFS to a linear amplitude factor.
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+/// Convert a linear amplitude to dBFS (silence maps to negative infinity).
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.log10()
+}
+
+/// Multiply samples by `gain` in place, clamping the result to [-1.0, 1.0].
+pub fn apply_gain(samples: &mut [f32], gain: f32) {
+    for sample in samples.iter_mut() {
+        *sample = (*sample * gain).clamp(-1.0, 1.0);
+    }
+}
+
+/// Butterworth high-pass filter, applied in place.
+///
+/// Built as a cascade of RBJ biquad sections, so it has the flat passband of
+/// a Butterworth filter and rolls off at 36 dB per octave below `cutoff_hz`.
+/// A cutoff that isn't between 0 and the Nyquist frequency leaves the
+/// samples unchanged.
+pub fn highpass(samples: &mut [f32], cutoff_hz: f32, sample_rate: usize) {
+    let nyquist = sample_rate as f64 / 2.0;
+    let cutoff = cutoff_hz as f64;
+    if !(cutoff > 0.0 && cutoff < nyquist) {
+        return;
+    }
+
+    let w0 = std::f64::consts::PI * cutoff / nyquist;
+    let (sin_w0, cos_w0) = w0.sin_cos();
+    for section in 0..HIGHPASS_ORDER / 2 {
+        // Pole pair `section` of the Butterworth prototype
+        let angle = std::f64::consts::PI * (2 * section + 1) as f64 / (2 * HIGHPASS_ORDER) as f64;
+        let q = 1.0 / (2.0 * angle.cos());
+        let alpha = sin_w0 / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        let b0 = (1.0 + cos_w0) / 2.0 / a0;
+        let b1 = -(1.0 + cos_w0) / a0;
+        let b2 = b0;
+        let a1 = -2.0 * cos_w0 / a0;
+        let a2 = (1.0 - alpha) / a0;
+
+        // Transposed direct form II
+        let (mut z1, mut z2) = (0.0f64, 0.0f64);
+        for sample in samples.iter_mut() {
+            let x = *sample as f64;
+            let y = b0 * x + z1;
+            z1 = b1 * x - a1 * y + z2;
+            z2 = b2 * x - a2 * y;
+            *sample = y as f32;
+        }
+    }
+}
+
+/// Spectral-gate noise reduction.
+///
+/// The signal is cut into half-overlapping frames of about 32 ms. The noise
+/// floor of each frequency bin is its mean magnitude over the quietest 10% of
+/// frames, so it assumes the recording has some pauses. In every frame, bins
+/// that don't rise 6 dB above the floor are turned down by 20 dB. Steady
+/// noise such as hiss or fan noise drops while speech passes mostly
+/// unchanged. Input shorter than two frames is returned as is.
+pub fn spectral_gate(samples: &[f32], sample_rate: usize) -> Vec<f32> {
+    let frame_len = (sample_rate * GATE_FRAME_MS / 1000).next_power_of_two();
+    let hop = frame_len / 2;
+    if samples.len() < 2 * frame_len {
+        return samples.to_vec();
+    }
+
+    // Pad so every sample is covered by two frames
+    let mut padded = vec![0.0f32; hop];
+    padded.extend_from_slice(samples);
+    padded.resize(padded.len().div_ceil(hop) * hop + hop, 0.0);
+    let frame_count = (padded.len() - frame_len) / hop + 1;
+
+    // Square-root periodic Hann on analysis and synthesis: the squared
+    // windows of half-overlapping frames sum to one
+    let window: Vec<f32> = (0..frame_len)
+        .map(|i| {
+            let phase = 2.0 * std::f32::consts::PI * i as f32 / frame_len as f32;
+            (0.5 - 0.5 * phase.cos()).sqrt()
+        })
+        .collect();
+
+    let mut planner = RealFftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(frame_len);
+    let inverse = planner.plan_fft_inverse(frame_len);
+    let mut frame = forward.make_input_vec();
+    let mut spectrum = forward.make_output_vec();
+    let mut scratch = forward.make_scratch_vec();
+
+    let mut analyze = |index: usize, frame: &mut Vec<f32>, spectrum: &mut Vec<Complex<f32>>| {
+        let start = index * hop;
+        for ((out, sample), w) in frame
+            .iter_mut()
+            .zip(&padded[start..start + frame_len])
+            .zip(&window)
+        {
+            *out = sample * w;
+        }
+        // Buffers come from the plan, so the lengths always match
+        let _ = forward.process_with_scratch(frame, spectrum, &mut scratch);
+    };
+
+    // Noise profile from the quietest frames
+    let mut energies: Vec<(usize, f32)> = (0..frame_count)
+        .map(|index| {
+            let start = index * hop;
+            let energy = padded[start..start + frame_len]
+                .iter()
+                .zip(&window)
+                .map(|(s, w)| (s * w) * (s * w))
+                .sum();
+            (index, energy)
+        })
+        .collect();
+    energies.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let quiet = ((frame_count as f32 * GATE_NOISE_FRACTION) as usize).max(1);
+    let mut noise = vec![0.0f32; spectrum.len()];
+    for &(index, _) in &energies[..quiet] {
+        analyze(index, &mut frame, &mut spectrum);
+        for (floor, bin) in noise.iter_mut().zip(&spectrum) {
+            *floor += bin.norm() / quiet as f32;
+        }
+    }
+
+    // Gate every frame and overlap-add the result
+    let mut output = vec![0.0f32; padded.len()];
+    let mut inverse_scratch = inverse.make_scratch_vec();
+    for index in 0..frame_count {
+        analyze(index, &mut frame, &mut spectrum);
+        for (bin, floor) in spectrum.iter_mut().zip(&noise) {
+            if bin.norm() < floor * GATE_THRESHOLD {
+                *bin *= GATE_REDUCTION;
+            }
+        }
+        // The DC and Nyquist bins of a real signal's spectrum are real
+        spectrum[0].im = 0.0;
+        if let Some(last) = spectrum.last_mut() {
+            last.im = 0.0;
+        }
+        let _ = inverse.process_with_scratch(&mut spectrum, &mut frame, &mut inverse_scratch);
+
+        let start = index * hop;
+        for ((out, sample), w) in output[start..start + frame_len]
+            .iter_mut()
+            .zip(&frame)
+            .zip(&window)
+        {
+            // The inverse transform is unnormalized
+            *out += sample * w / frame_len as f32;
+        }
+    }
+
+    output.drain(..hop);
+    output.truncate(samples.len());
+    output
+}
+
+pub(crate) fn i16_to_f32(sample: i16) -> f32 {
+    sample as f32 / i16::MAX as f32
+}
+
+fn f32_to_i16(sample: f32) -> i16 {
+    (sample * i16::MAX as f32).clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rate pairs seen in practice: telephony, CD, video and high-res input
+    const RATE_PAIRS: [(usize, usize); 6] = [
+        (8000, 16000),
+        (16000, 8000),
+        (22050, 16000),
+        (44100, 16000),
+        (48000, 16000),
+        (16000, 48000),
+    ];
+
+    fn sine(freq: f32, rate: usize, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (i as f32 * freq * std::f32::consts::TAU / rate as f32).sin() * 0.5)
+            .collect()
+    }
+
+    #[test]
+    fn downmixes_by_averaging_frames() {
+        assert_eq!(downmix_to_mono(&[0.5, -0.5, 1.0, 0.0, 0.3], 2), [0.0, 0.5]);
+        assert_eq!(downmix_to_mono(&[0.1, 0.2], 1), [0.1, 0.2]);
+    }
+
+    #[test]
+    fn deinterleaves_channels() {
+        assert_eq!(
+            deinterleave(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0], 3),
+            [vec![1.0, 4.0], vec![2.0, 5.0], vec![3.0, 6.0]]
+        );
+        assert_eq!(deinterleave(&[1.0, 2.0], 1), [vec![1.0, 2.0]]);
+    }
+
+    #[test]
+    fn meters_levels() {
+        assert_eq!(rms(&[]), 0.0);
+        assert!((rms(&[0.5, -0.5, 0.5, -0.5]) - 0.5).abs() < 1e-6);
+        assert_eq!(peak(&[0.2, -0.7, 0.4]), 0.7);
+        assert!((db_to_gain(-6.0) - 0.501).abs() < 1e-3);
+        assert!((gain_to_db(0.5) + 6.02).abs() < 1e-2);
+        assert_eq!(gain_to_db(0.0), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn gain_clamps_to_full_scale() {
+        let mut samples = [0.25, -0.25, 0.75];
+        apply_gain(&mut samples, 2.0);
+        assert_eq!(samples, [0.5, -0.5, 1.0]);
+    }
+
+    #[test]
+    fn wav_round_trip() {
+        let samples = sine(440.0, 16000, 1600);
+        let bytes = write_wav_bytes(&samples, 16000).unwrap();
+        assert_eq!(bytes.len(), 44 + samples.len() * 2);
+
+        let audio = read_wav_bytes(&bytes).unwrap();
+        assert_eq!(audio.sample_rate, 16000);
+        assert_eq!(audio.source_channels, 1);
+        assert_eq!(audio.bits_per_sample, 16);
+        assert_eq!(audio.samples.len(), samples.len());
+        for (decoded, original) in audio.samples.iter().zip(&samples) {
+            assert!((decoded - original).abs() < 1e-4);
+        }
+
+        let channels = read_wav_channels(Cursor::new(&bytes)).unwrap();
+        assert_eq!(channels.channels.len(), 1);
+        assert_eq!(channels.channels[0], audio.samples);
+    }
+
+    #[test]
+    fn out_of_range_samples_are_clipped_on_write() {
+        let bytes = write_wav_bytes(&[2.0, -2.0], 16000).unwrap();
+        let audio = read_wav_bytes(&bytes).unwrap();
+        assert_eq!(audio.samples, [1.0, -1.0]);
+    }
+
+    #[test]
+    fn resampled_length_follows_the_ratio() {
+        for quality in [ResamplerQuality::Linear, ResamplerQuality::High] {
+            for (src, dst) in RATE_PAIRS {
+                for len in [1, 7, 1000, src] {
+                    let out = resample(&vec![0.1; len], src, dst, quality);
+                    let expected = (len * dst).div_ceil(src);
+                    assert_eq!(out.len(), expected, "{quality:?} {src}->{dst} len {len}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resampling_preserves_silence_and_offsets() {
+        for quality in [ResamplerQuality::Linear, ResamplerQuality::High] {
+            for (src, dst) in RATE_PAIRS {
+                let silent = resample(&vec![0.0; src / 10], src, dst, quality);
+                assert!(silent.iter().all(|&s| s == 0.0), "{quality:?} {src}->{dst}");
+
+                let offset = resample(&vec![0.25; src / 10], src, dst, quality);
+                assert!(
+                    offset.iter().all(|&s| (s - 0.25).abs() < 1e-4),
+                    "{quality:?} {src}->{dst}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn resampling_degenerate_input() {
+        for quality in [ResamplerQuality::Linear, ResamplerQuality::High] {
+            assert!(resample(&[], 48000, 16000, quality).is_empty());
+            assert!(resample(&[0.5], 0, 16000, quality).is_empty());
+            assert!(resample(&[0.5], 16000, 0, quality).is_empty());
+            assert_eq!(resample(&[0.1, 0.2], 16000, 16000, quality), [0.1, 0.2]);
+        }
+    }
+
+    #[test]
+    fn linear_interpolates_between_samples() {
+        assert_eq!(
+            resample_linear(&[0.0, 1.0, 0.0], 1, 2),
+            [0.0, 0.5, 1.0, 0.5, 0.0, 0.0]
+        );
+        assert_eq!(resample_linear(&[0.0, 0.5, 1.0, 0.5], 2, 1), [0.0, 1.0]);
+    }
+
+    #[test]
+    fn sinc_output_is_time_aligned() {
+        let input = sine(440.0, 48000, 48000);
+        let expected = sine(440.0, 16000, 16000);
+        let out = resample_sinc(&input, 48000, 16000);
+        // Away from the clamped edges the output follows the ideal tone
+        let error = out[100..15900]
+            .iter()
+            .zip(&expected[100..15900])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0f32, f32::max);
+        assert!(error < 0.01, "{error}");
+    }
+
+    #[test]
+    fn sinc_suppresses_aliasing() {
+        // 12 kHz is above the 8 kHz Nyquist frequency of the output
+        let input = sine(12000.0, 48000, 48000);
+        let linear = rms(&resample_linear(&input, 48000, 16000)[100..15900]);
+        let sinc = rms(&resample_sinc(&input, 48000, 16000)[100..15900]);
+        assert!(linear > 0.3, "{linear}");
+        assert!(sinc < 0.01, "{sinc}");
+    }
+
+    #[test]
+    fn sinc_falls_back_to_linear_on_odd_ratios() {
+        let input = sine(440.0, 16001, 1600);
+        assert_eq!(
+            resample_sinc(&input, 16001, 16000),
+            resample_linear(&input, 16001, 16000)
+        );
+    }
+
+    #[test]
+    fn parses_resampler_quality() {
+        assert_eq!(ResamplerQuality::default(), ResamplerQuality::High);
+        assert_eq!(
+            "linear".parse::<ResamplerQuality>().unwrap(),
+            ResamplerQuality::Linear
+        );
+        assert_eq!(
+            "HIGH".parse::<ResamplerQuality>().unwrap(),
+            ResamplerQuality::High
+        );
+        assert!("cubic".parse::<ResamplerQuality>().is_err());
+    }
+
+    /// Cost of a 60-second 48 kHz clip with each resampler:
+    /// `cargo test -p murmure-stt --release resampler_cost -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn resampler_cost() {
+        let input = sine(440.0, 48000, 48000 * 60);
+        for quality in [ResamplerQuality::Linear, ResamplerQuality::High] {
+            let start = std::time::Instant::now();
+            let out = resample(&input, 48000, 16000, quality);
+            println!(
+                "{quality:?}: {:?} for {} samples",
+                start.elapsed(),
+                out.len()
+            );
+        }
+    }
+
+    /// Deterministic white noise of the given peak amplitude.
+    fn white_noise(amplitude: f32, len: usize) -> Vec<f32> {
+        let mut state = 0x2545_f491_u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state as f32 / u32::MAX as f32 * 2.0 - 1.0) * amplitude
+            })
+            .collect()
+    }
+
+    /// Level of `filtered` relative to `original` in dB, past the first
+    /// 0.25 s where the filters settle.
+    fn attenuation_db(original: &[f32], filtered: &[f32]) -> f32 {
+        let settled = 4000;
+        gain_to_db(rms(&filtered[settled..]) / rms(&original[settled..]))
+    }
+
+    #[test]
+    fn highpass_removes_rumble_and_keeps_the_voice_band() {
+        let rumble = sine(50.0, 16000, 16000);
+        let mut filtered = rumble.clone();
+        highpass(&mut filtered, 80.0, 16000);
+        assert!(attenuation_db(&rumble, &filtered) < -20.0);
+
+        let voice = sine(1000.0, 16000, 16000);
+        let mut filtered = voice.clone();
+        highpass(&mut filtered, 80.0, 16000);
+        assert!(attenuation_db(&voice, &filtered).abs() < 0.5);
+    }
+
+    #[test]
+    fn highpass_ignores_cutoffs_outside_the_band() {
+        let input = sine(50.0, 16000, 1600);
+        for cutoff in [0.0, -10.0, 8000.0, f32::NAN] {
+            let mut filtered = input.clone();
+            highpass(&mut filtered, cutoff, 16000);
+            assert_eq!(filtered, input, "cutoff {}", cutoff);
+        }
+    }
+
+    #[test]
+    fn spectral_gate_lowers_the_noise_floor() {
+        // Two seconds of hiss with a tone in the second half
+        let mut input = white_noise(0.05, 32000);
+        for (sample, tone) in input[16000..].iter_mut().zip(sine(1000.0, 16000, 16000)) {
+            *sample += tone;
+        }
+        let output = spectral_gate(&input, 16000);
+        assert_eq!(output.len(), input.len());
+
+        let noise_only = 2000..14000;
+        let reduction = gain_to_db(rms(&output[noise_only.clone()]) / rms(&input[noise_only]));
+        assert!(reduction < -6.0, "noise reduced by {} dB", reduction);
+        let with_tone = 18000..30000;
+        let change = gain_to_db(rms(&output[with_tone.clone()]) / rms(&input[with_tone]));
+        assert!(change.abs() < 1.0, "tone changed by {} dB", change);
+    }
+
+    #[test]
+    fn spectral_gate_leaves_short_input_alone() {
+        let input = white_noise(0.05, 800);
+        assert_eq!(spectral_gate(&input, 16000), input);
+        assert!(spectral_gate(&[], 16000).is_empty());
+    }
+}
@@ -1,29 +1,43 @@
 use ndarray::{Array, Array1, Array2, Array3, ArrayD, ArrayViewD, IxDyn};
 use once_cell::sync::Lazy;
-use ort::execution_providers::CPUExecutionProvider;
+use ort::execution_providers::{
+    CPUExecutionProvider, CUDAExecutionProvider, CoreMLExecutionProvider,
+    DirectMLExecutionProvider, ExecutionProviderDispatch,
+};
 use ort::inputs;
 use ort::session::builder::GraphOptimizationLevel;
 use ort::session::Session;
 use ort::value::TensorRef;
 use regex::Regex;
 
+use memmap2::Mmap;
 use std::fs;
 use std::path::Path;
 
+use super::parakeet::ParakeetModelParams;
+use super::transcription_engine::DecodingOptions;
+use crate::model::ExecutionProvider;
+
 pub type DecoderState = (Array3<f32>, Array3<f32>);
 
 const SUBSAMPLING_FACTOR: usize = 8;
 const WINDOW_SIZE: f32 = 0.01;
-const MAX_TOKENS_PER_STEP: usize = 10;
 
 static DECODE_SPACE_RE: Lazy<Result<Regex, regex::Error>> =
     Lazy::new(|| Regex::new(r"\A\s|\s\B|(\s)\b"));
 
+/// Token ids, the encoder frame each was emitted on and its log-probability
+type DecodedSequence = (Vec<i32>, Vec<usize>, Vec<f32>);
+
 #[derive(Debug, Clone)]
 pub struct TimestampedResult {
     pub text: String,
     pub timestamps: Vec<f32>,
     pub tokens: Vec<String>,
+    /// Encoder frame each token was emitted on
+    pub frames: Vec<usize>,
+    /// Log-probability of each token at the step it was emitted
+    pub logprobs: Vec<f32>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -46,9 +60,14 @@ pub struct ParakeetModel {
     encoder: Session,
     decoder_joint: Session,
     preprocessor: Session,
+    /// Memory-mapped ORT-format files the sessions read their weights from;
+    /// declared after the sessions so it is dropped after them
+    #[allow(dead_code)] // only held so the mappings outlive the sessions
+    mapped: Vec<Mmap>,
     vocab: Vec<String>,
     blank_idx: i32,
     vocab_size: usize,
+    execution_provider: ExecutionProvider,
 }
 
 impl Drop for ParakeetModel {
@@ -60,11 +79,83 @@ impl Drop for ParakeetModel {
     }
 }
 
+/// Provider list for a session: the requested accelerator (failing loudly
+/// if it can't be registered) followed by the CPU for unsupported operators.
+fn execution_providers(provider: ExecutionProvider) -> Vec<ExecutionProviderDispatch> {
+    let accelerator = match provider {
+        ExecutionProvider::Cpu => None,
+        ExecutionProvider::Cuda => Some(CUDAExecutionProvider::default().build()),
+        ExecutionProvider::CoreMl => Some(CoreMLExecutionProvider::default().build()),
+        ExecutionProvider::DirectMl => Some(DirectMLExecutionProvider::default().build()),
+    };
+
+    accelerator
+        .map(|ep| ep.error_on_failure())
+        .into_iter()
+        .chain(std::iter::once(CPUExecutionProvider::default().build()))
+        .collect()
+}
+
 impl ParakeetModel {
-    pub fn new<P: AsRef<Path>>(model_dir: P, quantized: bool) -> Result<Self, ParakeetError> {
-        let encoder = Self::init_session(&model_dir, "encoder-model", None, quantized)?;
-        let decoder_joint = Self::init_session(&model_dir, "decoder_joint-model", None, quantized)?;
-        let preprocessor = Self::init_session(&model_dir, "nemo128", None, false)?;
+    pub fn new<P: AsRef<Path>>(
+        model_dir: P,
+        params: &ParakeetModelParams,
+    ) -> Result<Self, ParakeetError> {
+        let variant = params.quantization.file_suffix();
+
+        // The encoder is the first session created: if the requested provider
+        // can't be registered there, use the CPU for every session.
+        let mut provider = params.execution_provider;
+        let mut mapped = Vec::new();
+        let encoder = match Self::init_session(
+            &model_dir,
+            "encoder-model",
+            params,
+            variant,
+            provider,
+            &mut mapped,
+        ) {
+            Ok(session) => session,
+            Err(e) if provider != ExecutionProvider::Cpu => {
+                log::warn!(
+                        "Failed to initialize the '{}' execution provider ({}); FALLING BACK TO CPU. \
+                         Check that murmure-stt was built with the '{}' feature and the runtime libraries are installed.",
+                        provider,
+                        e,
+                        provider
+                    );
+                provider = ExecutionProvider::Cpu;
+                mapped.clear();
+                Self::init_session(
+                    &model_dir,
+                    "encoder-model",
+                    params,
+                    variant,
+                    provider,
+                    &mut mapped,
+                )?
+            }
+            Err(e) => return Err(e),
+        };
+        let decoder_joint = Self::init_session(
+            &model_dir,
+            "decoder_joint-model",
+            params,
+            variant,
+            provider,
+            &mut mapped,
+        )?;
+        let preprocessor =
+            Self::init_session(&model_dir, "nemo128", params, None, provider, &mut mapped)?;
+        if !mapped.is_empty() {
+            log::info!(
+                "{} model file(s) memory-mapped ({} MiB shared with other processes)",
+                mapped.len(),
+                mapped.iter().map(|map| map.len()).sum::<usize>() / (1024 * 1024)
+            );
+        } else if params.mmap {
+            log::info!("No ORT-format model files found; ONNX weights are read into memory");
+        }
 
         let (vocab, blank_idx) = Self::load_vocab(&model_dir)?;
         let vocab_size = vocab.len();
@@ -79,54 +170,79 @@ impl ParakeetModel {
             encoder,
             decoder_joint,
             preprocessor,
+            mapped,
             vocab,
             blank_idx,
             vocab_size,
+            execution_provider: provider,
         })
     }
 
+    /// Execution provider the sessions were actually created with.
+    pub fn execution_provider(&self) -> ExecutionProvider {
+        self.execution_provider
+    }
+
+    /// Seconds of audio covered by one encoder frame.
+    pub fn frame_shift_secs() -> f32 {
+        WINDOW_SIZE * SUBSAMPLING_FACTOR as f32
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.vocab_size
+    }
+
+    /// Create the session of `model_name`. With `params.mmap`, an
+    /// ORT-format file (`.ort`) is preferred and memory-mapped; its mapping
+    /// is pushed to `mapped` and must outlive the session.
     fn init_session<P: AsRef<Path>>(
         model_dir: P,
         model_name: &str,
-        intra_threads: Option<usize>,
-        try_quantized: bool,
+        params: &ParakeetModelParams,
+        variant: Option<&str>,
+        provider: ExecutionProvider,
+        mapped: &mut Vec<Mmap>,
     ) -> Result<Session, ParakeetError> {
-        let providers = vec![CPUExecutionProvider::default().build()];
-
-        // Try quantized version first if requested, fallback to regular version
-        let model_filename = if try_quantized {
-            let quantized_name = format!("{}.int8.onnx", model_name);
-            let quantized_path = model_dir.as_ref().join(&quantized_name);
-            if quantized_path.exists() {
-                log::info!("Loading quantized model from {}...", quantized_name);
-                quantized_name
-            } else {
-                let regular_name = format!("{}.onnx", model_name);
-                log::info!(
-                    "Quantized model not found, loading regular model from {}...",
-                    regular_name
-                );
-                regular_name
-            }
-        } else {
-            let regular_name = format!("{}.onnx", model_name);
-            log::info!("Loading model from {}...", regular_name);
-            regular_name
-        };
+        let providers = execution_providers(provider);
+
+        let ort_file = params
+            .mmap
+            .then(|| ort_file(model_dir.as_ref(), model_name, variant))
+            .flatten();
+
+        let model_filename = weights_file(model_dir.as_ref(), model_name, variant);
 
+        // Inter-op threads only matter when independent nodes may run in parallel
         let mut builder = Session::builder()?
             .with_optimization_level(GraphOptimizationLevel::Level3)?
             .with_execution_providers(providers)?
             .with_memory_pattern(false)?
-            .with_parallel_execution(false)?;
+            .with_parallel_execution(params.inter_op_threads.is_some())?;
 
-        if let Some(threads) = intra_threads {
-            builder = builder
-                .with_intra_threads(threads)?
-                .with_inter_threads(threads)?;
+        if let Some(threads) = params.intra_op_threads {
+            builder = builder.with_intra_threads(threads)?;
+        }
+        if let Some(threads) = params.inter_op_threads {
+            builder = builder.with_inter_threads(threads)?;
         }
 
-        let session = builder.commit_from_file(model_dir.as_ref().join(&model_filename))?;
+        let session = match ort_file {
+            Some(ort_file) => {
+                log::info!("Memory-mapping ORT-format model {}", ort_file);
+                let file = fs::File::open(model_dir.as_ref().join(&ort_file))?;
+                // SAFETY: model files are not modified while the server runs;
+                // the mapping is kept in `mapped` for as long as the session
+                // reads its initializers from it.
+                let map = unsafe { Mmap::map(&file)? };
+                let session = builder
+                    .with_config_entry("session.use_ort_model_bytes_directly", "1")?
+                    .with_config_entry("session.use_ort_model_bytes_for_initializers", "1")?
+                    .commit_from_memory(&map)?;
+                mapped.push(map);
+                session
+            }
+            None => builder.commit_from_file(model_dir.as_ref().join(&model_filename))?,
+        };
 
         for input in &session.inputs {
             log::info!(
@@ -326,6 +442,7 @@ impl ParakeetModel {
         &mut self,
         waveforms: &ArrayViewD<f32>,
         waveforms_len: &ArrayViewD<i64>,
+        decoding: &DecodingOptions,
     ) -> Result<Vec<TimestampedResult>, ParakeetError> {
         // Preprocess and encode
         let (features, features_lens) = self.preprocess(waveforms, waveforms_len)?;
@@ -335,9 +452,9 @@ impl ParakeetModel {
         // Decode for each batch item
         let mut results = Vec::new();
         for (encodings, &encodings_len) in encoder_out.outer_iter().zip(encoder_out_lens.iter()) {
-            let (tokens, timestamps) =
-                self.decode_sequence(&encodings.view(), encodings_len as usize)?;
-            let result = self.decode_tokens(tokens, timestamps);
+            let (tokens, timestamps, logprobs) =
+                self.decode_sequence(&encodings.view(), encodings_len as usize, decoding)?;
+            let result = self.decode_tokens(tokens, timestamps, logprobs);
             results.push(result);
         }
 
@@ -348,15 +465,20 @@ impl ParakeetModel {
         &mut self,
         encodings: &ArrayViewD<f32>, // [time_steps, 1024]
         encodings_len: usize,
-    ) -> Result<(Vec<i32>, Vec<usize>), ParakeetError> {
+        decoding: &DecodingOptions,
+    ) -> Result<DecodedSequence, ParakeetError> {
         let mut prev_state = self.create_decoder_state()?;
         let mut tokens = Vec::new();
         let mut timestamps = Vec::new();
+        let mut logprobs = Vec::new();
 
         let mut t = 0;
         let mut emitted_tokens = 0;
 
         while t < encodings_len {
+            if decoding.max_tokens.is_some_and(|max| tokens.len() >= max) {
+                break;
+            }
             let encoder_step = encodings.slice(ndarray::s![t, ..]);
             // Convert to dynamic dimension to match decode_step parameter type
             let encoder_step_dyn = encoder_step.to_owned().into_dyn();
@@ -385,10 +507,19 @@ impl ParakeetModel {
                 vocab_logits_slice
             };
 
-            // Get argmax token from vocabulary logits only
+            // Get argmax token from vocabulary logits only, the blank
+            // penalized; log-probabilities are of the logits as they were
+            let blank_idx = self.blank_idx as usize;
             let token = vocab_logits
                 .iter()
                 .enumerate()
+                .map(|(idx, &logit)| {
+                    if idx == blank_idx {
+                        (idx, logit - decoding.blank_penalty)
+                    } else {
+                        (idx, logit)
+                    }
+                })
                 .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
                 .map(|(idx, _)| idx as i32)
                 .unwrap_or(self.blank_idx);
@@ -397,31 +528,38 @@ impl ParakeetModel {
                 prev_state = new_state;
                 tokens.push(token);
                 timestamps.push(t);
+                logprobs.push(log_softmax_at(vocab_logits, token as usize));
                 emitted_tokens += 1;
             }
 
             // Step logic from Python - simplified since step is always -1
-            if token == self.blank_idx || emitted_tokens == MAX_TOKENS_PER_STEP {
+            if token == self.blank_idx || emitted_tokens >= decoding.max_symbols_per_step {
                 t += 1;
                 emitted_tokens = 0;
             }
         }
 
-        Ok((tokens, timestamps))
+        Ok((tokens, timestamps, logprobs))
     }
 
-    fn decode_tokens(&self, ids: Vec<i32>, timestamps: Vec<usize>) -> TimestampedResult {
-        let tokens: Vec<String> = ids
-            .iter()
-            .filter_map(|&id| {
-                let idx = id as usize;
-                if idx < self.vocab.len() {
-                    Some(self.vocab[idx].clone())
-                } else {
-                    None
-                }
-            })
-            .collect();
+    fn decode_tokens(
+        &self,
+        ids: Vec<i32>,
+        timestamps: Vec<usize>,
+        logprobs: Vec<f32>,
+    ) -> TimestampedResult {
+        // Ids outside the vocabulary are dropped along with their frame and
+        // log-probability, so the three lists stay aligned
+        let mut tokens = Vec::with_capacity(ids.len());
+        let mut frames = Vec::with_capacity(ids.len());
+        let mut token_logprobs = Vec::with_capacity(ids.len());
+        for ((id, frame), logprob) in ids.into_iter().zip(timestamps).zip(logprobs) {
+            if let Some(token) = self.vocab.get(id as usize) {
+                tokens.push(token.clone());
+                frames.push(frame);
+                token_logprobs.push(logprob);
+            }
+        }
 
         let text = match &*DECODE_SPACE_RE {
             Ok(regex) => regex
@@ -436,21 +574,24 @@ impl ParakeetModel {
             Err(_) => tokens.join(""), // Fallback if regex failed to compile
         };
 
-        let float_timestamps: Vec<f32> = timestamps
+        let float_timestamps: Vec<f32> = frames
             .iter()
-            .map(|&t| WINDOW_SIZE * SUBSAMPLING_FACTOR as f32 * t as f32)
+            .map(|&t| Self::frame_shift_secs() * t as f32)
             .collect();
 
         TimestampedResult {
             text,
             timestamps: float_timestamps,
             tokens,
+            frames,
+            logprobs: token_logprobs,
         }
     }
 
     pub fn transcribe_samples(
         &mut self,
         samples: Vec<f32>,
+        decoding: &DecodingOptions,
     ) -> Result<TimestampedResult, ParakeetError> {
         let batch_size = 1;
         let samples_len = samples.len();
@@ -462,7 +603,7 @@ impl ParakeetModel {
         let waveforms_lens = Array1::from_vec(vec![samples_len as i64]).into_dyn();
 
         // Run recognition to get detailed results
-        let results = self.recognize_batch(&waveforms.view(), &waveforms_lens.view())?;
+        let results = self.recognize_batch(&waveforms.view(), &waveforms_lens.view(), decoding)?;
 
         // Extract the first (and only) result
         let timestamped_result = results.into_iter().next().ok_or_else(|| {
@@ -475,3 +616,115 @@ impl ParakeetModel {
         Ok(timestamped_result)
     }
 }
+
+/// ORT-format file to memory-map for `model_name`: the precision variant
+/// (`encoder-model.int8.ort`), else the plain `.ort` file, if either exists.
+fn ort_file(model_dir: &Path, model_name: &str, variant: Option<&str>) -> Option<String> {
+    variant
+        .map(|variant| format!("{}.{}.ort", model_name, variant))
+        .into_iter()
+        .chain(std::iter::once(format!("{}.ort", model_name)))
+        .find(|name| model_dir.join(name).is_file())
+}
+
+/// Weight file to load for `model_name`: the requested precision variant
+/// (`encoder-model.int8.onnx`) when present, the plain file otherwise.
+fn weights_file(model_dir: &Path, model_name: &str, variant: Option<&str>) -> String {
+    let regular_name = format!("{}.onnx", model_name);
+    match variant {
+        Some(variant) => {
+            let variant_name = format!("{}.{}.onnx", model_name, variant);
+            if model_dir.join(&variant_name).exists() {
+                log::info!("Loading {} model from {}...", variant, variant_name);
+                variant_name
+            } else {
+                log::info!(
+                    "{} model not found, loading regular model from {}...",
+                    variant,
+                    regular_name
+                );
+                regular_name
+            }
+        }
+        None => {
+            log::info!("Loading model from {}...", regular_name);
+            regular_name
+        }
+    }
+}
+
+/// Log-softmax of `logits` at `index`, computed stably around the maximum.
+fn log_softmax_at(logits: &[f32], index: usize) -> f32 {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let sum: f32 = logits.iter().map(|&l| (l - max).exp()).sum();
+    logits[index] - max - sum.ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accelerators_keep_the_cpu_as_fallback() {
+        assert_eq!(execution_providers(ExecutionProvider::Cpu).len(), 1);
+        for provider in [
+            ExecutionProvider::Cuda,
+            ExecutionProvider::CoreMl,
+            ExecutionProvider::DirectMl,
+        ] {
+            assert_eq!(execution_providers(provider).len(), 2);
+        }
+    }
+
+    #[test]
+    fn loads_the_requested_precision_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["encoder-model.onnx", "encoder-model.int8.onnx"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let file = |variant| weights_file(dir.path(), "encoder-model", variant);
+        assert_eq!(file(Some("int8")), "encoder-model.int8.onnx");
+        assert_eq!(file(None), "encoder-model.onnx");
+        // No fp16 variant in the directory: full precision instead
+        assert_eq!(file(Some("fp16")), "encoder-model.onnx");
+    }
+
+    #[test]
+    fn maps_ort_files_of_the_variant_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |variant| ort_file(dir.path(), "encoder-model", variant);
+        fs::write(dir.path().join("encoder-model.int8.onnx"), b"").unwrap();
+        assert_eq!(file(Some("int8")), None);
+
+        fs::write(dir.path().join("encoder-model.ort"), b"").unwrap();
+        assert_eq!(file(Some("int8")).as_deref(), Some("encoder-model.ort"));
+        fs::write(dir.path().join("encoder-model.int8.ort"), b"").unwrap();
+        assert_eq!(
+            file(Some("int8")).as_deref(),
+            Some("encoder-model.int8.ort")
+        );
+        assert_eq!(file(Some("fp16")).as_deref(), Some("encoder-model.ort"));
+        assert_eq!(file(None).as_deref(), Some("encoder-model.ort"));
+    }
+
+    #[test]
+    fn log_softmax_is_a_log_probability() {
+        let logits = [2.0, 1.0, 0.1, -3.0];
+        let logprobs: Vec<f32> = (0..logits.len())
+            .map(|i| log_softmax_at(&logits, i))
+            .collect();
+        assert!(logprobs.iter().all(|&logprob| logprob <= 0.0));
+        let total: f32 = logprobs.iter().map(|logprob| logprob.exp()).sum();
+        assert!((total - 1.0).abs() < 1e-5, "{}", total);
+        assert!(logprobs.windows(2).all(|pair| pair[0] > pair[1]));
+
+        // Stable far from zero, where a naive exp would overflow
+        let large = [1000.0, 1000.0];
+        assert!((log_softmax_at(&large, 0) - 0.5f32.ln()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn frames_are_eighty_milliseconds() {
+        assert!((ParakeetModel::frame_shift_secs() - 0.08).abs() < 1e-6);
+    }
+}
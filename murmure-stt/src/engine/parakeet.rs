@@ -3,8 +3,12 @@
 use super::{
     model::ParakeetModel,
     timestamp::convert_timestamps,
-    transcription_engine::{TranscriptionEngine, TranscriptionResult},
+    transcription_engine::{
+        DecodingOptions, ModelMetadata, OutputToken, SpeechEngine, TranscriptionEngine,
+        TranscriptionOutput,
+    },
 };
+use crate::model::ExecutionProvider;
 use std::path::{Path, PathBuf};
 
 /// Granularity level for timestamp generation.
@@ -32,27 +36,61 @@ pub enum QuantizationType {
     /// Full precision (32-bit floating point, default)
     #[default]
     FP32,
+    /// Half precision (16-bit floating point, mainly useful on GPUs)
+    FP16,
     /// 8-bit integer quantization (faster, slightly lower accuracy)
     Int8,
 }
 
+impl QuantizationType {
+    /// Suffix inserted before `.onnx` in the weight file name, e.g.
+    /// `encoder-model.int8.onnx`. Full precision uses the plain file name.
+    pub fn file_suffix(&self) -> Option<&'static str> {
+        match self {
+            QuantizationType::FP32 => None,
+            QuantizationType::FP16 => Some("fp16"),
+            QuantizationType::Int8 => Some("int8"),
+        }
+    }
+}
+
 /// Parameters for configuring Parakeet model loading.
 ///
-/// Controls model quantization settings for balancing performance vs accuracy.
+/// Controls model quantization, ONNX Runtime threading and the execution provider.
 #[derive(Debug, Clone, Default)]
 pub struct ParakeetModelParams {
     /// The quantization type to use for the model
     pub quantization: QuantizationType,
+    /// Threads used within a single operator (`None` = ONNX Runtime default)
+    pub intra_op_threads: Option<usize>,
+    /// Threads used to run independent operators in parallel (`None` = sequential execution)
+    pub inter_op_threads: Option<usize>,
+    /// Execution provider to request (falls back to CPU if unavailable)
+    pub execution_provider: ExecutionProvider,
+    /// Memory-map ORT-format model files (`.ort`) when present, so processes
+    /// loading the same files share their weights
+    pub mmap: bool,
 }
 
 impl ParakeetModelParams {
     /// Create parameters for full precision (FP32) model loading.
     ///
     /// Provides the highest accuracy but slower inference speed.
-    #[allow(dead_code)] // Will be useful for high-precision mode
     pub fn fp32() -> Self {
         Self {
             quantization: QuantizationType::FP32,
+            ..Default::default()
+        }
+    }
+
+    /// Create parameters for half precision (FP16) model loading.
+    ///
+    /// Halves memory use compared to FP32; fastest on hardware with native
+    /// FP16 support.
+    pub fn fp16() -> Self {
+        Self {
+            quantization: QuantizationType::FP16,
+            ..Default::default()
         }
     }
 
@@ -62,14 +100,17 @@ impl ParakeetModelParams {
     ///
     /// # Examples
     ///
-    /// ```rust
-    /// use transcribe_rs::engines::parakeet::ParakeetModelParams;
+    /// The engine module is internal to the crate, hence `ignore`:
+    ///
+    /// ```rust,ignore
+    /// use crate::engine::parakeet::ParakeetModelParams;
     ///
     /// let params = ParakeetModelParams::int8();
     /// ```
     pub fn int8() -> Self {
         Self {
             quantization: QuantizationType::Int8,
+            ..Default::default()
         }
     }
 }
@@ -82,12 +123,15 @@ impl ParakeetModelParams {
 pub struct ParakeetInferenceParams {
     /// The granularity level for timestamp generation
     pub timestamp_granularity: TimestampGranularity,
+    /// Settings of the greedy decoding search
+    pub decoding: DecodingOptions,
 }
 
 impl Default for ParakeetInferenceParams {
     fn default() -> Self {
         Self {
             timestamp_granularity: TimestampGranularity::Token,
+            decoding: DecodingOptions::default(),
         }
     }
 }
@@ -101,12 +145,14 @@ impl Default for ParakeetInferenceParams {
 ///
 /// - **Format**: Directory containing model files
 /// - **Structure**: Must contain tokenizer, config, and weight files
-/// - **Quantization**: Supports both FP32 and Int8 quantized models
+/// - **Quantization**: Supports FP32, FP16 and Int8 model variants
 ///
 /// # Examples
 ///
-/// ```rust,no_run
-/// use transcribe_rs::engines::parakeet::ParakeetEngine;
+/// The engine module is internal to the crate, hence `ignore`:
+///
+/// ```rust,ignore
+/// use crate::engine::parakeet::ParakeetEngine;
 ///
 /// let mut engine = ParakeetEngine::new();
 /// // Engine is ready to load a model directory
@@ -130,8 +176,10 @@ impl ParakeetEngine {
     ///
     /// # Examples
     ///
-    /// ```rust
-    /// use transcribe_rs::engines::parakeet::ParakeetEngine;
+    /// The engine module is internal to the crate, hence `ignore`:
+    ///
+    /// ```rust,ignore
+    /// use crate::engine::parakeet::ParakeetEngine;
     ///
     /// let engine = ParakeetEngine::new();
     /// // Engine is ready to load a model directory
@@ -142,6 +190,36 @@ impl ParakeetEngine {
             model: None,
         }
     }
+
+    /// Execution provider the loaded model runs on, if a model is loaded.
+    pub fn execution_provider(&self) -> Option<ExecutionProvider> {
+        self.model.as_ref().map(|model| model.execution_provider())
+    }
+}
+
+impl SpeechEngine for ParakeetEngine {
+    fn transcribe(
+        &mut self,
+        samples: Vec<f32>,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        self.transcribe_samples(samples, None)
+    }
+
+    fn transcribe_with(
+        &mut self,
+        samples: Vec<f32>,
+        decoding: &DecodingOptions,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        let params = ParakeetInferenceParams {
+            decoding: *decoding,
+            ..Default::default()
+        };
+        self.transcribe_samples(samples, Some(params))
+    }
+
+    fn execution_provider(&self) -> Option<ExecutionProvider> {
+        ParakeetEngine::execution_provider(self)
+    }
 }
 
 impl Drop for ParakeetEngine {
@@ -159,11 +237,7 @@ impl TranscriptionEngine for ParakeetEngine {
         model_path: &Path,
         params: Self::ModelParams,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let quantized = match params.quantization {
-            QuantizationType::FP32 => false,
-            QuantizationType::Int8 => true,
-        };
-        let model = ParakeetModel::new(model_path, quantized)?;
+        let model = ParakeetModel::new(model_path, &params)?;
 
         self.model = Some(model);
         self.loaded_model_path = Some(model_path.to_path_buf());
@@ -179,7 +253,7 @@ impl TranscriptionEngine for ParakeetEngine {
         &mut self,
         samples: Vec<f32>,
         params: Option<Self::InferenceParams>,
-    ) -> Result<TranscriptionResult, Box<dyn std::error::Error>> {
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
         let model: &mut ParakeetModel = self
             .model
             .as_mut()
@@ -188,15 +262,37 @@ impl TranscriptionEngine for ParakeetEngine {
         let parakeet_params = params.unwrap_or_default();
 
         // Get the timestamped result from the model
-        let timestamped_result = model.transcribe_samples(samples)?;
+        let timestamped_result = model.transcribe_samples(samples, &parakeet_params.decoding)?;
 
         // Convert timestamps based on requested granularity
         let segments =
             convert_timestamps(&timestamped_result, parakeet_params.timestamp_granularity);
 
-        Ok(TranscriptionResult {
+        let frame_shift_secs = ParakeetModel::frame_shift_secs();
+        let tokens = timestamped_result
+            .tokens
+            .iter()
+            .zip(&timestamped_result.frames)
+            .zip(&timestamped_result.logprobs)
+            .map(|((text, &frame), &logprob)| OutputToken {
+                text: text.clone(),
+                start_frame: frame,
+                end_frame: frame + 1,
+                start: frame as f32 * frame_shift_secs,
+                end: (frame + 1) as f32 * frame_shift_secs,
+                logprob,
+            })
+            .collect();
+
+        Ok(TranscriptionOutput {
             text: timestamped_result.text,
             segments,
+            tokens,
+            model: ModelMetadata {
+                engine: "parakeet",
+                frame_shift_secs,
+                vocab_size: model.vocab_size(),
+            },
         })
     }
 }
@@ -1,19 +1,95 @@
 // Minimal transcription API types
 
 use crate::audio::read_wav_samples;
+use crate::model::ExecutionProvider;
 use std::path::Path;
 
 /// The result of a transcription operation.
 ///
-/// Contains both the full transcribed text and detailed timing information
-/// for individual segments within the audio.
+/// Contains the full transcribed text, the tokens it was decoded from with
+/// their timing and log-probability, and segments grouped at the requested
+/// timestamp granularity.
 #[derive(Debug)]
-#[allow(dead_code)] // segments will be useful for UI timestamps
-pub struct TranscriptionResult {
+#[allow(dead_code)] // segments and model metadata will be useful for UI timestamps
+pub struct TranscriptionOutput {
     /// The complete transcribed text from the audio
     pub text: String,
     /// Individual segments with timing information
     pub segments: Vec<TranscriptionSegment>,
+    /// Decoded tokens in order, blanks excluded
+    pub tokens: Vec<OutputToken>,
+    /// The model that produced this output
+    pub model: ModelMetadata,
+}
+
+impl TranscriptionOutput {
+    /// Only the text, for callers that don't need tokens or timing.
+    pub fn into_text(self) -> String {
+        self.text
+    }
+}
+
+/// A token emitted by the decoder.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)] // frame indices will be useful for alignment
+pub struct OutputToken {
+    /// Token text; a leading space marks the start of a word
+    pub text: String,
+    /// Encoder frame the token was emitted on
+    pub start_frame: usize,
+    /// Frame after the last one covered by the token
+    pub end_frame: usize,
+    /// Start of the token in seconds (`start_frame` times the frame shift)
+    pub start: f32,
+    /// End of the token in seconds
+    pub end: f32,
+    /// Natural log of the token's probability at the step it was emitted
+    pub logprob: f32,
+}
+
+/// Description of the model behind a [`TranscriptionOutput`].
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)] // will be useful for UI timestamps
+pub struct ModelMetadata {
+    /// Engine name, e.g. `parakeet`
+    pub engine: &'static str,
+    /// Seconds of audio per encoder frame
+    pub frame_shift_secs: f32,
+    /// Number of vocabulary entries, blank included
+    pub vocab_size: usize,
+}
+
+/// Settings of the decoding search a request can tune.
+///
+/// Engines decode greedily, so these are the knobs of that search; engines
+/// without an equivalent ignore them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodingOptions {
+    /// Subtracted from the blank logit before a token is chosen: positive
+    /// values make the decoder emit more tokens, negative ones fewer
+    pub blank_penalty: f32,
+    /// Tokens emitted on one encoder frame before moving to the next
+    pub max_symbols_per_step: usize,
+    /// Stop decoding after this many tokens (None = no limit)
+    pub max_tokens: Option<usize>,
+}
+
+impl DecodingOptions {
+    /// Largest blank penalty accepted, either way; beyond it the decoder
+    /// emits nothing or a token on every frame
+    pub const MAX_BLANK_PENALTY: f32 = 10.0;
+    /// Largest `max_symbols_per_step` accepted
+    pub const MAX_SYMBOLS_PER_STEP: usize = 32;
+}
+
+impl Default for DecodingOptions {
+    fn default() -> Self {
+        Self {
+            blank_penalty: 0.0,
+            max_symbols_per_step: 10,
+            max_tokens: None,
+        }
+    }
 }
 
 /// A single transcribed segment with timing information.
@@ -38,24 +114,12 @@ pub struct TranscriptionSegment {
 ///
 /// # Examples
 ///
-/// ## Using Whisper Engine
+/// The engine module is internal to the crate, hence `ignore`; from outside,
+/// go through [`crate::TranscriptionService`].
 ///
-/// ```rust,no_run
-/// use transcribe_rs::{TranscriptionEngine, engines::whisper::WhisperEngine};
-/// use std::path::PathBuf;
-///
-/// let mut engine = WhisperEngine::new();
-/// engine.load_model(&PathBuf::from("models/whisper-medium-q4_1.bin"))?;
-///
-/// let result = engine.transcribe_file(&PathBuf::from("audio.wav"), None)?;
-/// println!("Transcription: {}", result.text);
-/// # Ok::<(), Box<dyn std::error::Error>>(())
-/// ```
-///
-/// ## Using Parakeet Engine
-///
-/// ```rust,no_run
-/// use transcribe_rs::{TranscriptionEngine, engines::parakeet::{ParakeetEngine, ParakeetModelParams}};
+/// ```rust,ignore
+/// use crate::engine::parakeet::{ParakeetEngine, ParakeetModelParams};
+/// use crate::engine::transcription_engine::TranscriptionEngine;
 /// use std::path::PathBuf;
 ///
 /// let mut engine = ParakeetEngine::new();
@@ -102,12 +166,12 @@ pub trait TranscriptionEngine {
     ///
     /// # Returns
     ///
-    /// Returns transcription result with text and timing information.
+    /// Returns transcription result with text, tokens and timing information.
     fn transcribe_samples(
         &mut self,
         samples: Vec<f32>,
         params: Option<Self::InferenceParams>,
-    ) -> Result<TranscriptionResult, Box<dyn std::error::Error>>;
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>>;
 
     /// Transcribe audio from a WAV file.
     ///
@@ -130,8 +194,37 @@ pub trait TranscriptionEngine {
         &mut self,
         wav_path: &Path,
         params: Option<Self::InferenceParams>,
-    ) -> Result<TranscriptionResult, Box<dyn std::error::Error>> {
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
         let samples = read_wav_samples(wav_path)?;
         self.transcribe_samples(samples, params)
     }
 }
+
+/// A loaded engine as the transcription service drives it.
+///
+/// Parakeet implements it; so does [`crate::mock::MockEngine`], which a
+/// [`crate::TranscriptionServiceBuilder::engine`] factory can hand out to run
+/// the service without model files.
+pub trait SpeechEngine: Send {
+    /// Transcribe 16 kHz mono samples.
+    fn transcribe(
+        &mut self,
+        samples: Vec<f32>,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>>;
+
+    /// Transcribe 16 kHz mono samples with the given decoding settings.
+    /// Engines that can't tune their decoding fall back to
+    /// [`Self::transcribe`].
+    fn transcribe_with(
+        &mut self,
+        samples: Vec<f32>,
+        _decoding: &DecodingOptions,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        self.transcribe(samples)
+    }
+
+    /// Execution provider the engine runs on, `None` if not applicable.
+    fn execution_provider(&self) -> Option<ExecutionProvider> {
+        None
+    }
+}
@@ -0,0 +1,145 @@
+// Errors of the public transcription API
+//
+// One variant per kind of failure, so callers can tell a missing model from
+// a bad payload or an engine failure without matching on messages. Internal
+// code still uses anyhow; errors are classified where they leave the engine
+// or the pipeline, and keep the message they had as their Display output.
+
+use crate::audio::InternalEngineError;
+use crate::dictionary::RulesError;
+use crate::model::UnknownModelError;
+use crate::validation::AudioValidationError;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Underlying error of a variant that wraps one
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Why a transcription, or setting up the service for one, failed.
+#[derive(Debug, Error)]
+pub enum TranscriptionError {
+    /// The model directory doesn't exist
+    #[error("Model not found at {}", path.display())]
+    ModelNotFound { path: PathBuf },
+    /// A request named a model that isn't configured
+    #[error(transparent)]
+    UnknownModel(#[from] UnknownModelError),
+    /// The payload was rejected before decoding
+    #[error(transparent)]
+    InvalidAudio(AudioValidationError),
+    /// The payload is a WAV file in an encoding the engine can't take
+    #[error("unsupported WAV format: {found}")]
+    UnsupportedFormat { found: String },
+    /// The payload passed validation but its samples couldn't be decoded
+    #[error("{detail}")]
+    AudioDecode { source: BoxError, detail: String },
+    /// The engine couldn't be loaded
+    #[error(transparent)]
+    EngineLoad(BoxError),
+    /// The engine is busy or still loading and the wait ran out
+    #[error("{0}")]
+    EngineUnavailable(String),
+    /// The engine panicked; only this request failed
+    #[error(transparent)]
+    EnginePanic(#[from] InternalEngineError),
+    /// The engine failed on this audio
+    #[error(transparent)]
+    Inference(BoxError),
+    /// The cc-rules used for dictionary correction couldn't be loaded
+    #[error(transparent)]
+    DictionaryRules(#[from] RulesError),
+    /// A custom post-processor failed
+    #[error("Post-processor '{name}' failed")]
+    PostProcess { name: String, source: BoxError },
+    /// The configuration can't be used, e.g. an unreadable word list
+    #[error(transparent)]
+    Config(BoxError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl From<AudioValidationError> for TranscriptionError {
+    fn from(error: AudioValidationError) -> Self {
+        match error {
+            AudioValidationError::UnsupportedFormat(found) => Self::UnsupportedFormat { found },
+            error => Self::InvalidAudio(error),
+        }
+    }
+}
+
+impl TranscriptionError {
+    /// Wrap a decoder error, keeping its message.
+    pub(crate) fn decode(source: impl Into<BoxError>) -> Self {
+        let source = source.into();
+        Self::AudioDecode {
+            detail: source.to_string(),
+            source,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_the_ones_callers_saw_before() {
+        let error = TranscriptionError::ModelNotFound {
+            path: PathBuf::from("/models/parakeet"),
+        };
+        assert_eq!(error.to_string(), "Model not found at /models/parakeet");
+
+        let error = TranscriptionError::from(UnknownModelError {
+            name: "nope".to_string(),
+            available: "fast, accurate".to_string(),
+        });
+        assert_eq!(
+            error.to_string(),
+            "unknown model 'nope', configured models: fast, accurate"
+        );
+
+        let error = TranscriptionError::decode("bad sample at 12");
+        assert_eq!(error.to_string(), "bad sample at 12");
+        let error = TranscriptionError::PostProcess {
+            name: "scrubber".to_string(),
+            source: "offline".into(),
+        };
+        assert_eq!(error.to_string(), "Post-processor 'scrubber' failed");
+    }
+
+    #[test]
+    fn unsupported_encodings_get_their_own_variant() {
+        let error = TranscriptionError::from(AudioValidationError::UnsupportedFormat(
+            "8-bit float".to_string(),
+        ));
+        assert!(
+            matches!(&error, TranscriptionError::UnsupportedFormat { found } if found == "8-bit float")
+        );
+        assert_eq!(error.to_string(), "unsupported WAV format: 8-bit float");
+
+        let error = TranscriptionError::from(AudioValidationError::Empty);
+        assert!(matches!(
+            error,
+            TranscriptionError::InvalidAudio(AudioValidationError::Empty)
+        ));
+    }
+
+    #[test]
+    fn wrapped_errors_stay_reachable() {
+        use std::error::Error as _;
+
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "gone");
+        let error = TranscriptionError::from(io);
+        assert_eq!(error.to_string(), "gone");
+        let TranscriptionError::Io(io) = &error else {
+            panic!("expected an I/O error, got {:?}", error);
+        };
+        assert_eq!(io.kind(), std::io::ErrorKind::NotFound);
+
+        let error = TranscriptionError::PostProcess {
+            name: "scrubber".to_string(),
+            source: "offline".into(),
+        };
+        assert!(error.source().is_some());
+    }
+}
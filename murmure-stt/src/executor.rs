@@ -0,0 +1,69 @@
+// Dedicated worker threads for the async API
+//
+// Transcription is CPU-bound and blocking. Instead of relying on the caller's
+// runtime (tokio's spawn_blocking, async-std, ...), async methods hand their
+// work to a small pool owned by the library and await the result over a
+// oneshot channel, which works with any executor.
+
+use crate::error::TranscriptionError;
+use once_cell::sync::Lazy;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Job queue shared by the worker threads, started on first use
+static POOL: Lazy<Mutex<Sender<Job>>> = Lazy::new(|| {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    let workers = thread::available_parallelism().map_or(2, |n| n.get().max(2));
+    for index in 0..workers {
+        let receiver = Arc::clone(&receiver);
+        thread::Builder::new()
+            .name(format!("murmure-worker-{}", index))
+            .spawn(move || loop {
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                job();
+            })
+            .expect("Failed to spawn murmure worker thread");
+    }
+
+    Mutex::new(sender)
+});
+
+/// Run `job` on the worker pool and wait for its result without blocking the
+/// calling task.
+pub(crate) async fn run_blocking<T, F>(job: F) -> Result<T, TranscriptionError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, TranscriptionError> + Send + 'static,
+{
+    let (result_tx, result_rx) = oneshot::channel();
+    let wrapped: Job = Box::new(move || {
+        // Keep the worker alive if the job panics; the caller gets an error
+        let result = panic::catch_unwind(AssertUnwindSafe(job))
+            .unwrap_or_else(|_| Err(worker_error("Transcription worker panicked")));
+        let _ = result_tx.send(result);
+    });
+
+    POOL.lock()
+        .unwrap()
+        .send(wrapped)
+        .map_err(|_| worker_error("Transcription worker pool has shut down"))?;
+
+    result_rx
+        .await
+        .map_err(|_| worker_error("Transcription worker dropped the request"))?
+}
+
+/// The job never ran to completion, through no fault of the audio
+fn worker_error(message: &str) -> TranscriptionError {
+    TranscriptionError::Inference(message.into())
+}
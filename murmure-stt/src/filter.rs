@@ -0,0 +1,319 @@
+// Transcript post-processing: profanity filtering and pattern redaction
+
+use crate::config::ServerConfig;
+use crate::transcription::Transcript;
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Replacement text for matches of a redaction pattern
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Words filtered out of the box; extend with `profanity_words_path`.
+const BUILTIN_PROFANITY: &[&str] = &[
+    "arse",
+    "arsehole",
+    "asshole",
+    "bastard",
+    "bitch",
+    "bollocks",
+    "bullshit",
+    "cock",
+    "crap",
+    "cunt",
+    "damn",
+    "dick",
+    "dickhead",
+    "fuck",
+    "fucked",
+    "fucker",
+    "fucking",
+    "motherfucker",
+    "piss",
+    "pissed",
+    "prick",
+    "shit",
+    "shitty",
+    "slut",
+    "twat",
+    "wanker",
+    "whore",
+];
+
+/// How profanity in transcripts is handled.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProfanityFilter {
+    /// Leave transcripts untouched (default)
+    #[default]
+    Off,
+    /// Replace each letter of a matched word with `*`
+    Mask,
+    /// Drop matched words entirely
+    Remove,
+}
+
+impl FromStr for ProfanityFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "mask" => Ok(Self::Mask),
+            "remove" => Ok(Self::Remove),
+            other => Err(anyhow::anyhow!(
+                "Unknown profanity filter '{}', expected 'off', 'mask' or 'remove'",
+                other
+            )),
+        }
+    }
+}
+
+/// Compiled profanity list and redaction patterns, applied after dictionary
+/// correction.
+///
+/// Filtering only rewrites text: segment boundaries and timings are kept as
+/// they are, so time spans still line up with the audio.
+#[derive(Debug, Clone)]
+pub struct TranscriptFilter {
+    profanity: Regex,
+    redactions: Vec<Regex>,
+}
+
+impl TranscriptFilter {
+    /// Build the filter from the built-in word list, the optional word list
+    /// file and the redaction patterns in the configuration.
+    pub fn from_config(config: &ServerConfig) -> Result<Self> {
+        let mut words: Vec<String> = BUILTIN_PROFANITY.iter().map(|w| w.to_string()).collect();
+        if let Some(path) = config.profanity_words_path.as_deref() {
+            words.extend(load_word_list(path)?);
+        }
+
+        let redactions = config
+            .redact_patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .with_context(|| format!("Invalid redaction pattern '{}'", pattern))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            profanity: word_regex(&words)?,
+            redactions,
+        })
+    }
+
+    /// Apply the filter to a single piece of text.
+    pub fn filter_text(&self, text: &str, profanity: ProfanityFilter, redact: bool) -> String {
+        let mut filtered = text.to_string();
+
+        if redact {
+            for pattern in &self.redactions {
+                filtered = pattern.replace_all(&filtered, REDACTED).into_owned();
+            }
+        }
+
+        match profanity {
+            ProfanityFilter::Off => filtered,
+            ProfanityFilter::Mask => self
+                .profanity
+                .replace_all(&filtered, |caps: &regex::Captures| {
+                    "*".repeat(caps[0].chars().count())
+                })
+                .into_owned(),
+            ProfanityFilter::Remove => {
+                let removed = self.profanity.replace_all(&filtered, "");
+                removed.split_whitespace().collect::<Vec<_>>().join(" ")
+            }
+        }
+    }
+
+    /// Apply the filter to the transcript text and each of its segments.
+    pub fn apply(&self, transcript: &mut Transcript, profanity: ProfanityFilter, redact: bool) {
+        if profanity == ProfanityFilter::Off && !redact {
+            return;
+        }
+        // Filtering works on whole texts, a pattern can span several words
+        transcript.words.clear();
+        for channel in &mut transcript.channels {
+            channel.words.clear();
+        }
+
+        if !transcript.channels.is_empty() {
+            for channel in &mut transcript.channels {
+                channel.text = self.filter_text(&channel.text, profanity, redact);
+                for segment in &mut channel.segments {
+                    segment.text = self.filter_text(&segment.text, profanity, redact);
+                }
+            }
+            transcript.merge_channels();
+            return;
+        }
+
+        transcript.text = self.filter_text(&transcript.text, profanity, redact);
+        for segment in &mut transcript.segments {
+            segment.text = self.filter_text(&segment.text, profanity, redact);
+        }
+    }
+}
+
+/// Read a word list file: one word per line, blank lines and `#` comments ignored.
+fn load_word_list(path: &Path) -> Result<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read profanity word list {}", path.display()))?;
+
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Case-insensitive whole-word alternation of `words`.
+fn word_regex(words: &[String]) -> Result<Regex> {
+    let alternation = words
+        .iter()
+        .map(|w| regex::escape(w))
+        .collect::<Vec<_>>()
+        .join("|");
+    Regex::new(&format!(r"(?i)\b(?:{})\b", alternation))
+        .context("Failed to compile profanity word list")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcription::{TranscriptSegment, TranscriptWord};
+
+    fn filter(redact_patterns: &[&str]) -> TranscriptFilter {
+        TranscriptFilter::from_config(&ServerConfig {
+            redact_patterns: redact_patterns.iter().map(|p| p.to_string()).collect(),
+            ..ServerConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn masks_or_removes_whole_words() {
+        let filter = filter(&[]);
+        let text = "Well SHIT, the class assessment in Scunthorpe is crap";
+        assert_eq!(filter.filter_text(text, ProfanityFilter::Off, false), text);
+        assert_eq!(
+            filter.filter_text(text, ProfanityFilter::Mask, false),
+            "Well ****, the class assessment in Scunthorpe is ****"
+        );
+        assert_eq!(
+            filter.filter_text(
+                "the shit class is crap today",
+                ProfanityFilter::Remove,
+                false
+            ),
+            "the class is today"
+        );
+    }
+
+    #[test]
+    fn redacts_patterns_when_asked() {
+        let filter = filter(&[r"\b\d{4}-\d{4}\b", r"(?i)card number"]);
+        let text = "my Card Number is 1234-5678";
+        assert_eq!(filter.filter_text(text, ProfanityFilter::Off, false), text);
+        assert_eq!(
+            filter.filter_text(text, ProfanityFilter::Off, true),
+            "my [REDACTED] is [REDACTED]"
+        );
+    }
+
+    #[test]
+    fn invalid_patterns_are_errors() {
+        let error = TranscriptFilter::from_config(&ServerConfig {
+            redact_patterns: vec!["(unclosed".to_string()],
+            ..ServerConfig::default()
+        })
+        .unwrap_err();
+        assert!(error.to_string().contains("(unclosed"), "{error}");
+    }
+
+    #[test]
+    fn extends_the_list_from_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("words.txt");
+        fs::write(&path, "# house words\n\n  blimey \nflipping\n").unwrap();
+        let filter = TranscriptFilter::from_config(&ServerConfig {
+            profanity_words_path: Some(path),
+            ..ServerConfig::default()
+        })
+        .unwrap();
+        assert_eq!(
+            filter.filter_text("blimey, flipping damn", ProfanityFilter::Mask, false),
+            "******, ******** ****"
+        );
+
+        let missing = ServerConfig {
+            profanity_words_path: Some(dir.path().join("missing.txt")),
+            ..ServerConfig::default()
+        };
+        assert!(TranscriptFilter::from_config(&missing).is_err());
+    }
+
+    #[test]
+    fn filters_segments_and_keeps_their_timings() {
+        let mut transcript = Transcript {
+            text: "oh damn it".to_string(),
+            segments: vec![
+                TranscriptSegment {
+                    start: 0.0,
+                    end: 0.4,
+                    text: "oh damn".to_string(),
+                    channel: None,
+                },
+                TranscriptSegment {
+                    start: 1.2,
+                    end: 1.5,
+                    text: "it".to_string(),
+                    channel: None,
+                },
+            ],
+            words: vec![TranscriptWord {
+                text: "damn".to_string(),
+                ..TranscriptWord::default()
+            }],
+            ..Transcript::default()
+        };
+
+        let filter = filter(&[]);
+        filter.apply(&mut transcript, ProfanityFilter::Off, false);
+        assert_eq!(transcript.words.len(), 1);
+
+        filter.apply(&mut transcript, ProfanityFilter::Mask, false);
+        assert_eq!(transcript.text, "oh **** it");
+        assert_eq!(transcript.segments[0].text, "oh ****");
+        assert_eq!(
+            (transcript.segments[1].start, transcript.segments[1].end),
+            (1.2, 1.5)
+        );
+        // Word timings can't follow a rewrite, so they are dropped
+        assert!(transcript.words.is_empty());
+    }
+
+    #[test]
+    fn parses_filter_modes() {
+        assert_eq!(
+            "MASK".parse::<ProfanityFilter>().unwrap(),
+            ProfanityFilter::Mask
+        );
+        assert_eq!(
+            "remove".parse::<ProfanityFilter>().unwrap(),
+            ProfanityFilter::Remove
+        );
+        assert_eq!(
+            "off".parse::<ProfanityFilter>().unwrap(),
+            ProfanityFilter::Off
+        );
+        assert!("bleep".parse::<ProfanityFilter>().is_err());
+    }
+}
@@ -0,0 +1,283 @@
+// Transcript export formats: plain text, JSON, SRT and WebVTT subtitles
+//
+// Subtitles are built from the pause-delimited segments. There are no word
+// timestamps, so words inside a segment get a share of its time proportional
+// to their length, and cues are cut at sentence ends, at the line budget or at
+// the maximum cue duration.
+
+use crate::transcription::Transcript;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Shape of the transcript returned to the caller.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// The transcript text as is (default)
+    #[default]
+    Text,
+    /// Text, duration and segments as a JSON document
+    Json,
+    /// SubRip subtitles
+    Srt,
+    /// WebVTT subtitles
+    Vtt,
+}
+
+impl OutputFormat {
+    /// Subtitle formats need segment timestamps to be useful.
+    pub fn is_subtitle(&self) -> bool {
+        matches!(self, Self::Srt | Self::Vtt)
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "srt" => Ok(Self::Srt),
+            "vtt" => Ok(Self::Vtt),
+            other => Err(anyhow::anyhow!(
+                "Unknown output format '{}', expected 'text', 'json', 'srt' or 'vtt'",
+                other
+            )),
+        }
+    }
+}
+
+/// Limits applied when grouping words into caption cues.
+#[derive(Debug, Clone, Copy)]
+pub struct CueOptions {
+    /// Characters per caption line
+    pub max_chars_per_line: usize,
+    /// Lines per cue
+    pub max_lines: usize,
+    /// Longest time a cue stays on screen, in seconds
+    pub max_cue_secs: f32,
+}
+
+impl Default for CueOptions {
+    fn default() -> Self {
+        Self {
+            max_chars_per_line: 42,
+            max_lines: 2,
+            max_cue_secs: 6.0,
+        }
+    }
+}
+
+/// One caption: a time range and up to `max_lines` lines of text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub start: f32,
+    pub end: f32,
+    pub lines: Vec<String>,
+}
+
+/// Render the transcript in `format`.
+pub fn render(
+    transcript: &Transcript,
+    format: OutputFormat,
+    options: &CueOptions,
+) -> Result<String> {
+    Ok(match format {
+        OutputFormat::Text => transcript.text.clone(),
+        OutputFormat::Json => json(transcript)?,
+        OutputFormat::Srt => srt(&cues(transcript, options)),
+        OutputFormat::Vtt => vtt(&cues(transcript, options)),
+    })
+}
+
+/// Group the transcript's words into caption cues, in time order.
+pub fn cues(transcript: &Transcript, options: &CueOptions) -> Vec<Cue> {
+    let spans: Vec<(f32, f32, &str)> = if transcript.segments.is_empty() {
+        vec![(
+            0.0,
+            transcript.audio_duration_secs,
+            transcript.text.as_str(),
+        )]
+    } else {
+        transcript
+            .segments
+            .iter()
+            .map(|segment| (segment.start, segment.end, segment.text.as_str()))
+            .collect()
+    };
+
+    let mut cues = Vec::new();
+    for (start, end, text) in spans {
+        let words = timed_words(start, end, text);
+        let mut builder = CueBuilder::default();
+
+        for (word, word_start, word_end) in words {
+            if !builder.fits(word, word_end, options) {
+                cues.extend(builder.finish());
+            }
+            builder.push(word, word_start, word_end, options);
+            if ends_sentence(word) {
+                cues.extend(builder.finish());
+            }
+        }
+        cues.extend(builder.finish());
+    }
+    cues
+}
+
+/// SubRip: numbered cues with `HH:MM:SS,mmm` timestamps.
+pub fn srt(cues: &[Cue]) -> String {
+    let mut out = String::new();
+    for (index, cue) in cues.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "{}\n{} --> {}\n{}\n",
+            index + 1,
+            timestamp(cue.start, ','),
+            timestamp(cue.end, ','),
+            cue.lines.join("\n")
+        );
+    }
+    out
+}
+
+/// WebVTT: a `WEBVTT` header and cues with `HH:MM:SS.mmm` timestamps.
+pub fn vtt(cues: &[Cue]) -> String {
+    let mut out = String::from("WEBVTT\n\n");
+    for cue in cues {
+        let _ = writeln!(
+            out,
+            "{} --> {}\n{}\n",
+            timestamp(cue.start, '.'),
+            timestamp(cue.end, '.'),
+            cue.lines.join("\n")
+        );
+    }
+    out
+}
+
+/// Text, duration and segments as pretty-printed JSON.
+pub fn json(transcript: &Transcript) -> Result<String> {
+    #[derive(Serialize)]
+    struct JsonSegment<'a> {
+        start: f32,
+        end: f32,
+        text: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel: Option<usize>,
+    }
+
+    #[derive(Serialize)]
+    struct JsonTranscript<'a> {
+        text: &'a str,
+        audio_duration_secs: f32,
+        segments: Vec<JsonSegment<'a>>,
+    }
+
+    let document = JsonTranscript {
+        text: &transcript.text,
+        audio_duration_secs: transcript.audio_duration_secs,
+        segments: transcript
+            .segments
+            .iter()
+            .map(|segment| JsonSegment {
+                start: segment.start,
+                end: segment.end,
+                text: &segment.text,
+                channel: segment.channel,
+            })
+            .collect(),
+    };
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+/// Cue being filled word by word, wrapped into lines as it grows.
+#[derive(Default)]
+struct CueBuilder {
+    start: f32,
+    end: f32,
+    lines: Vec<String>,
+}
+
+impl CueBuilder {
+    /// Whether `word` still fits in this cue without breaking the limits.
+    fn fits(&self, word: &str, word_end: f32, options: &CueOptions) -> bool {
+        let Some(line) = self.lines.last() else {
+            return true;
+        };
+        if word_end - self.start > options.max_cue_secs {
+            return false;
+        }
+        let fits_line =
+            line.chars().count() + 1 + word.chars().count() <= options.max_chars_per_line;
+        fits_line || self.lines.len() < options.max_lines
+    }
+
+    fn push(&mut self, word: &str, word_start: f32, word_end: f32, options: &CueOptions) {
+        match self.lines.last_mut() {
+            None => {
+                self.start = word_start;
+                self.lines.push(word.to_string());
+            }
+            Some(line)
+                if line.chars().count() + 1 + word.chars().count()
+                    <= options.max_chars_per_line =>
+            {
+                line.push(' ');
+                line.push_str(word);
+            }
+            Some(_) => self.lines.push(word.to_string()),
+        }
+        self.end = word_end;
+    }
+
+    fn finish(&mut self) -> Option<Cue> {
+        if self.lines.is_empty() {
+            return None;
+        }
+        Some(Cue {
+            start: self.start,
+            end: self.end.max(self.start),
+            lines: std::mem::take(&mut self.lines),
+        })
+    }
+}
+
+/// Words of `text` with start and end times spread over `start..end` in
+/// proportion to their length.
+fn timed_words(start: f32, end: f32, text: &str) -> Vec<(&str, f32, f32)> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let total_chars: usize = words.iter().map(|w| w.chars().count() + 1).sum();
+    let duration = (end - start).max(0.0);
+
+    let mut elapsed = 0;
+    words
+        .into_iter()
+        .map(|word| {
+            let word_start = start + duration * elapsed as f32 / total_chars as f32;
+            elapsed += word.chars().count() + 1;
+            let word_end = start + duration * elapsed as f32 / total_chars as f32;
+            (word, word_start, word_end)
+        })
+        .collect()
+}
+
+fn ends_sentence(word: &str) -> bool {
+    word.ends_with(['.', '!', '?'])
+}
+
+/// `HH:MM:SS<sep>mmm`, the millisecond separator being `,` (SRT) or `.` (VTT).
+fn timestamp(secs: f32, separator: char) -> String {
+    let millis = (secs.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        millis % 1000
+    )
+}
@@ -0,0 +1,570 @@
+// Inverse text normalization: spoken numbers and entities to written form
+//
+// Rule based and deliberately conservative: a phrase is only rewritten when
+// its reading is unambiguous (a time needs "am"/"pm", a date needs an ordinal
+// day, a lone "one" or "five" is left alone, ...). Anything that doesn't
+// match a rule is copied through untouched.
+
+use crate::transcription::Transcript;
+
+const UNITS: [&str; 10] = [
+    "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+];
+
+const TEENS: [&str; 10] = [
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+/// twenty (20) through ninety (90)
+const TENS: [&str; 8] = [
+    "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const ORDINAL_UNITS: [(&str, u64); 19] = [
+    ("first", 1),
+    ("second", 2),
+    ("third", 3),
+    ("fourth", 4),
+    ("fifth", 5),
+    ("sixth", 6),
+    ("seventh", 7),
+    ("eighth", 8),
+    ("ninth", 9),
+    ("tenth", 10),
+    ("eleventh", 11),
+    ("twelfth", 12),
+    ("thirteenth", 13),
+    ("fourteenth", 14),
+    ("fifteenth", 15),
+    ("sixteenth", 16),
+    ("seventeenth", 17),
+    ("eighteenth", 18),
+    ("nineteenth", 19),
+];
+
+const ORDINAL_TENS: [(&str, u64); 2] = [("twentieth", 20), ("thirtieth", 30)];
+
+const SCALES: [(&str, u64); 3] = [
+    ("thousand", 1_000),
+    ("million", 1_000_000),
+    ("billion", 1_000_000_000),
+];
+
+/// Months spelled like everyday words ("you may", "they march")
+const WORD_MONTHS: [&str; 2] = ["March", "May"];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Whether `language` (e.g. "en", "en-US", "english") has normalization rules.
+pub fn is_supported(language: &str) -> bool {
+    let language = language.to_ascii_lowercase();
+    language == "en"
+        || language == "english"
+        || language.starts_with("en-")
+        || language.starts_with("en_")
+}
+
+/// Rewrite spelled-out numbers, times, dates, currencies and digit sequences
+/// in `text` into written form.
+///
+/// Unsupported languages are returned unchanged.
+pub fn normalize(text: &str, language: &str) -> String {
+    if !is_supported(language) {
+        return text.to_string();
+    }
+
+    let tokens = tokenize(text);
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let window = phrase(&tokens[i..]);
+        let (consumed, written) = match match_rules(&window, &tokens[i].original) {
+            Some(Rule::Replace(consumed, written)) => (consumed, Some(written)),
+            Some(Rule::Keep(consumed)) => (consumed, None),
+            None => (1, None),
+        };
+
+        let taken = &tokens[i..i + consumed];
+        match written {
+            Some(written) => {
+                let last = &taken[consumed - 1];
+                out.push_str(&written);
+                out.push_str(&last.trail);
+                out.push_str(last.sep_after);
+            }
+            None => {
+                for token in taken {
+                    out.push_str(&token.original);
+                    out.push_str(token.sep_after);
+                }
+            }
+        }
+        i += consumed;
+    }
+
+    out.trim_end().to_string()
+}
+
+/// Normalize the transcript text and each of its segments in place.
+pub fn normalize_transcript(transcript: &mut Transcript, language: &str) {
+    transcript.rewrite_text(|text| normalize(text, language));
+}
+
+struct Token {
+    /// Text as it appeared in the input
+    original: String,
+    /// Lowercased word without trailing punctuation
+    core: String,
+    /// Trailing punctuation stripped from `core`
+    trail: String,
+    /// Separator to the next token ("-" inside hyphenated numbers)
+    sep_after: &'static str,
+}
+
+enum Rule {
+    /// Replace this many tokens with the written form
+    Replace(usize, String),
+    /// Copy this many tokens through unchanged
+    Keep(usize),
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for word in text.split_whitespace() {
+        // Keep the period of "a.m." / "p.m." with the word, not the trail
+        let stripped = match word.get(..4) {
+            Some(head)
+                if ["a.m.", "p.m."]
+                    .iter()
+                    .any(|a| head.eq_ignore_ascii_case(a)) =>
+            {
+                head
+            }
+            _ => word.trim_end_matches([',', '.', ';', ':', '!', '?']),
+        };
+        let trail = &word[stripped.len()..];
+        let lower = stripped.to_lowercase().replace('.', "");
+
+        // Split hyphenated numbers ("twenty-three") so each part is a word
+        let parts: Vec<&str> = lower.split('-').collect();
+        if parts.len() > 1 && parts.iter().all(|p| is_number_word(p)) {
+            let originals: Vec<&str> = stripped.split('-').collect();
+            for (idx, (part, original)) in parts.iter().zip(&originals).enumerate() {
+                let last = idx == parts.len() - 1;
+                tokens.push(Token {
+                    original: if last {
+                        format!("{}{}", original, trail)
+                    } else {
+                        original.to_string()
+                    },
+                    core: part.to_string(),
+                    trail: if last {
+                        trail.to_string()
+                    } else {
+                        String::new()
+                    },
+                    sep_after: if last { " " } else { "-" },
+                });
+            }
+            continue;
+        }
+
+        tokens.push(Token {
+            original: word.to_string(),
+            core: lower,
+            trail: trail.to_string(),
+            sep_after: " ",
+        });
+    }
+    tokens
+}
+
+/// Words available to a rule: a match never spans trailing punctuation, so
+/// the window ends at the first token that carries some.
+fn phrase(tokens: &[Token]) -> Vec<&str> {
+    let mut window = Vec::new();
+    for token in tokens {
+        window.push(token.core.as_str());
+        if !token.trail.is_empty() {
+            break;
+        }
+    }
+    window
+}
+
+/// `first` is the first word as it was written, for rules that depend on
+/// its case.
+fn match_rules(w: &[&str], first: &str) -> Option<Rule> {
+    time(w)
+        .or_else(|| date(w, first))
+        .or_else(|| quantity(w))
+        .or_else(|| digit_sequence(w))
+        .or_else(|| compound_ordinal(w))
+        .or_else(|| cardinal(w))
+}
+
+/// "three thirty pm" → "3:30 PM", "ten oh five am" → "10:05 AM",
+/// "seven o'clock" → "7:00"
+fn time(w: &[&str]) -> Option<Rule> {
+    let hour = w.first().and_then(|word| small_number(word))?;
+    if !(1..=12).contains(&hour) {
+        return None;
+    }
+
+    let (minutes, used) = minutes(&w[1..]).unwrap_or((0, 0));
+    let idx = 1 + used;
+    let meridiem = match w.get(idx).copied() {
+        Some("am") => Some("AM"),
+        Some("pm") => Some("PM"),
+        Some("o'clock") if used == 0 => None,
+        _ => return None,
+    };
+
+    let written = match meridiem {
+        Some(meridiem) if used == 0 => format!("{} {}", hour, meridiem),
+        Some(meridiem) => format!("{}:{:02} {}", hour, minutes, meridiem),
+        None => format!("{}:00", hour),
+    };
+    Some(Rule::Replace(idx + 1, written))
+}
+
+fn minutes(w: &[&str]) -> Option<(u64, usize)> {
+    let first = *w.first()?;
+    if first == "oh" {
+        let unit = w.get(1).and_then(|word| unit_value(word))?;
+        return (unit > 0).then_some((unit, 2));
+    }
+    if let Some(teen) = teen_value(first) {
+        return Some((teen, 1));
+    }
+    let tens = tens_value(first).filter(|t| *t < 60)?;
+    match w.get(1).and_then(|word| unit_value(word)) {
+        Some(unit) if unit > 0 => Some((tens + unit, 2)),
+        _ => Some((tens, 1)),
+    }
+}
+
+/// "january fifth" → "January 5th" (the day must be an ordinal). Months
+/// that are also common words only count when capitalized: "March second"
+/// is a date, "we march second" and "you may first check" are not.
+fn date(w: &[&str], written: &str) -> Option<Rule> {
+    let first = *w.first()?;
+    let month = MONTHS
+        .iter()
+        .find(|month| month.eq_ignore_ascii_case(first))?;
+    if WORD_MONTHS.contains(month) && !written.starts_with(char::is_uppercase) {
+        return None;
+    }
+    let (day, used) = ordinal(&w[1..])?;
+    if !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(Rule::Replace(
+        1 + used,
+        format!("{} {}{}", month, day, ordinal_suffix(day)),
+    ))
+}
+
+/// Amounts followed by a unit: "five dollars and ten cents" → "$5.10",
+/// "twenty euros" → "€20", "fifty percent" → "50%"
+fn quantity(w: &[&str]) -> Option<Rule> {
+    let (value, used) = parse_cardinal(w)?;
+    let symbol = match w.get(used).copied() {
+        Some("dollar" | "dollars") => "$",
+        Some("euro" | "euros") => "€",
+        Some("percent") => return Some(Rule::Replace(used + 1, format!("{}%", value))),
+        _ => return None,
+    };
+
+    let after_unit = used + 1;
+    if w.get(after_unit) == Some(&"and") {
+        if let Some((cents, cents_used)) = parse_cardinal(&w[after_unit + 1..]) {
+            let unit = w.get(after_unit + 1 + cents_used).copied();
+            if cents < 100 && matches!(unit, Some("cent" | "cents")) {
+                return Some(Rule::Replace(
+                    after_unit + cents_used + 2,
+                    format!("{}{}.{:02}", symbol, value, cents),
+                ));
+            }
+        }
+    }
+    Some(Rule::Replace(after_unit, format!("{}{}", symbol, value)))
+}
+
+/// Three or more single digits read one by one: "five five five one two three
+/// four" → "555-1234". Seven and ten digit runs get phone number grouping.
+fn digit_sequence(w: &[&str]) -> Option<Rule> {
+    let mut digits = String::new();
+    for (idx, word) in w.iter().enumerate() {
+        match unit_value(word) {
+            Some(d) => digits.push(char::from(b'0' + d as u8)),
+            // "oh" reads as zero only inside a digit run
+            None if *word == "oh" && idx > 0 => digits.push('0'),
+            None => break,
+        }
+    }
+    if digits.len() < 3 {
+        return None;
+    }
+
+    let used = digits.len();
+    let written = match used {
+        7 => format!("{}-{}", &digits[..3], &digits[3..]),
+        10 => format!("{}-{}-{}", &digits[..3], &digits[3..6], &digits[6..]),
+        _ => digits,
+    };
+    Some(Rule::Replace(used, written))
+}
+
+/// "twenty first" → "21st". A lone "first" or "second" stays a word.
+fn compound_ordinal(w: &[&str]) -> Option<Rule> {
+    tens_value(w.first()?)?;
+    let (value, used) = ordinal(w)?;
+    (used == 2).then(|| Rule::Replace(used, format!("{}{}", value, ordinal_suffix(value))))
+}
+
+/// Spelled-out cardinals of two or more words, or of ten and above:
+/// "twenty three" → "23", "one hundred and five" → "105", "twelve" → "12".
+///
+/// Runs that keep going with number words the grammar can't attach ("nineteen
+/// ninety nine") are ambiguous and kept verbatim.
+fn cardinal(w: &[&str]) -> Option<Rule> {
+    let (value, used) = parse_cardinal(w)?;
+
+    if w.get(used).is_some_and(|word| is_number_word(word)) {
+        let run = w.iter().take_while(|word| is_number_word(word)).count();
+        return Some(Rule::Keep(run));
+    }
+
+    (used >= 2 || value >= 10).then(|| Rule::Replace(used, value.to_string()))
+}
+
+/// Parse the longest well-formed cardinal at the start of `w`.
+fn parse_cardinal(w: &[&str]) -> Option<(u64, usize)> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Last {
+        Start,
+        Unit,
+        Teen,
+        Tens,
+        Hundred,
+        Scale,
+    }
+
+    let mut total = 0u64;
+    let mut current = 0u64;
+    let mut last = Last::Start;
+    let mut last_scale = u64::MAX;
+    let mut used = 0;
+    let mut idx = 0;
+
+    while let Some(&word) = w.get(idx) {
+        if word == "zero" {
+            if last == Last::Start {
+                return Some((0, 1));
+            }
+            break;
+        }
+
+        if let Some(unit) = unit_value(word) {
+            if !matches!(last, Last::Start | Last::Tens | Last::Hundred | Last::Scale) {
+                break;
+            }
+            current += unit;
+            last = Last::Unit;
+        } else if let Some(teen) = teen_value(word) {
+            if !matches!(last, Last::Start | Last::Hundred | Last::Scale) {
+                break;
+            }
+            current += teen;
+            last = Last::Teen;
+        } else if let Some(tens) = tens_value(word) {
+            if !matches!(last, Last::Start | Last::Hundred | Last::Scale) {
+                break;
+            }
+            current += tens;
+            last = Last::Tens;
+        } else if word == "hundred" {
+            if !matches!(last, Last::Unit | Last::Teen) || current >= 100 {
+                break;
+            }
+            current *= 100;
+            last = Last::Hundred;
+        } else if let Some(scale) = scale_value(word) {
+            if last == Last::Start || last == Last::Scale || scale >= last_scale {
+                break;
+            }
+            total += current * scale;
+            current = 0;
+            last_scale = scale;
+            last = Last::Scale;
+        } else if word == "and" && matches!(last, Last::Hundred | Last::Scale) {
+            // Only part of the number if a number word follows
+            if !w.get(idx + 1).is_some_and(|next| is_number_word(next)) {
+                break;
+            }
+            idx += 1;
+            continue;
+        } else {
+            break;
+        }
+
+        idx += 1;
+        used = idx;
+    }
+
+    (used > 0).then_some((total + current, used))
+}
+
+/// Ordinal at the start of `w`: "fifth", "twentieth", "twenty first".
+fn ordinal(w: &[&str]) -> Option<(u64, usize)> {
+    let first = *w.first()?;
+    if let Some(value) = ordinal_value(first) {
+        return Some((value, 1));
+    }
+    let tens = tens_value(first)?;
+    let unit = w
+        .get(1)
+        .and_then(|word| ordinal_value(word))
+        .filter(|unit| *unit < 10)?;
+    Some((tens + unit, 2))
+}
+
+fn ordinal_value(word: &str) -> Option<u64> {
+    ORDINAL_UNITS
+        .iter()
+        .chain(ORDINAL_TENS.iter())
+        .find(|(name, _)| *name == word)
+        .map(|(_, value)| *value)
+}
+
+fn ordinal_suffix(n: u64) -> &'static str {
+    match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    }
+}
+
+fn unit_value(word: &str) -> Option<u64> {
+    UNITS.iter().position(|u| *u == word).map(|v| v as u64)
+}
+
+fn teen_value(word: &str) -> Option<u64> {
+    TEENS.iter().position(|t| *t == word).map(|v| v as u64 + 10)
+}
+
+fn tens_value(word: &str) -> Option<u64> {
+    TENS.iter()
+        .position(|t| *t == word)
+        .map(|v| (v as u64 + 2) * 10)
+}
+
+fn scale_value(word: &str) -> Option<u64> {
+    SCALES
+        .iter()
+        .find(|(name, _)| *name == word)
+        .map(|(_, value)| *value)
+}
+
+/// Number from one to nineteen (hours are read this way)
+fn small_number(word: &str) -> Option<u64> {
+    unit_value(word).or_else(|| teen_value(word))
+}
+
+fn is_number_word(word: &str) -> bool {
+    unit_value(word).is_some()
+        || teen_value(word).is_some()
+        || tens_value(word).is_some()
+        || scale_value(word).is_some()
+        || word == "hundred"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn english_rules() {
+        let cases = [
+            // Times
+            ("meet at three thirty pm", "meet at 3:30 PM"),
+            ("ten oh five am works", "10:05 AM works"),
+            ("seven o'clock", "7:00"),
+            // Dates
+            ("on january fifth", "on January 5th"),
+            ("by October twenty first.", "by October 21st."),
+            ("March second", "March 2nd"),
+            ("since May first", "since May 1st"),
+            // Quantities
+            ("five dollars and ten cents", "$5.10"),
+            ("twenty euros", "€20"),
+            ("fifty percent", "50%"),
+            // Digit runs
+            ("call five five five one two three four", "call 555-1234"),
+            ("code one oh two", "code 102"),
+            // Ordinals and cardinals
+            ("the twenty first time", "the 21st time"),
+            ("twenty-three people", "23 people"),
+            ("one hundred and five", "105"),
+            ("twelve, then two thousand", "12, then 2000"),
+        ];
+        for (spoken, written) in cases {
+            assert_eq!(normalize(spoken, "en"), written, "{:?}", spoken);
+        }
+    }
+
+    #[test]
+    fn ambiguous_phrases_are_left_alone() {
+        let cases = [
+            // Months that are also words
+            "you may first check the logs",
+            "we march second in line",
+            // A date needs an ordinal day
+            "june five",
+            // A time needs a meridiem or o'clock
+            "three thirty",
+            // Lone small numbers and ordinals stay words
+            "one of five",
+            "first and second",
+            "nineteen ninety nine",
+            "and then",
+        ];
+        for text in cases {
+            assert_eq!(normalize(text, "en"), text);
+        }
+    }
+
+    #[test]
+    fn other_languages_are_unchanged() {
+        assert!(is_supported("en-US") && is_supported("English"));
+        assert!(!is_supported("fr"));
+        assert_eq!(normalize("vingt trois", "fr"), "vingt trois");
+        assert_eq!(normalize("twenty three", "fr"), "twenty three");
+    }
+}
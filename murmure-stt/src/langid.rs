@@ -0,0 +1,176 @@
+// Spoken language identification
+//
+// The model transcribes every language it knows without being told which one
+// it hears, so the language is identified from the decoded text: each
+// candidate scores the words of the transcript found in its list of common
+// function words. Short utterances ("ok", a name) carry few of those words,
+// which lowers the confidence rather than producing a guess.
+
+use crate::config::language_fallbacks;
+
+/// Languages that can be identified, by primary subtag
+pub const SUPPORTED_LANGUAGES: [&str; 7] = ["en", "fr", "de", "es", "it", "pt", "nl"];
+
+/// Function words found in a transcript before the confidence stops growing
+/// with its length
+const CONFIDENT_HITS: usize = 5;
+
+/// The language identified for a transcript.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedLanguage {
+    /// BCP-47 code, as configured (`fr-CA` when `fr-CA` was allowed)
+    pub language: String,
+    /// How sure the detection is, 0 to 1
+    pub confidence: f32,
+}
+
+/// Whether `language` (or its primary subtag) can be identified.
+pub fn is_supported(language: &str) -> bool {
+    language_fallbacks(language).any(|code| stopwords(&code).is_some())
+}
+
+/// The allowed language whose common words make up most of `text`, if any
+/// are found. Ties go to the language allowed first.
+pub fn detect_language(text: &str, allowed: &[String]) -> Option<DetectedLanguage> {
+    let lowered = text.to_lowercase();
+    let words: Vec<&str> = lowered
+        .split(|c: char| !c.is_alphabetic() && c != '\'')
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    let scores: Vec<(&String, usize)> = allowed
+        .iter()
+        .filter_map(|language| {
+            let stopwords = language_fallbacks(language).find_map(|code| stopwords(&code))?;
+            let hits = words.iter().filter(|word| stopwords.contains(word)).count();
+            Some((language, hits))
+        })
+        .collect();
+    let total: usize = scores.iter().map(|(_, hits)| hits).sum();
+    let mut best: Option<(&String, usize)> = None;
+    for (language, hits) in scores {
+        if hits > 0 && best.map_or(true, |(_, best_hits)| hits > best_hits) {
+            best = Some((language, hits));
+        }
+    }
+    best.map(|(language, hits)| {
+        let share = hits as f32 / total as f32;
+        let evidence = (hits as f32 / CONFIDENT_HITS as f32).min(1.0);
+        DetectedLanguage {
+            language: language.clone(),
+            confidence: share * evidence,
+        }
+    })
+}
+
+/// Common words of a language given by its lowercase primary subtag.
+fn stopwords(code: &str) -> Option<&'static [&'static str]> {
+    let words: &[&str] = match code {
+        "en" => &[
+            "the", "and", "is", "are", "was", "of", "to", "in", "that", "it", "you", "we", "this",
+            "with", "for", "have", "not", "be", "they", "what", "i'm", "don't", "there", "would",
+        ],
+        "fr" => &[
+            "le", "la", "les", "et", "est", "un", "une", "des", "du", "que", "qui", "pas", "je",
+            "nous", "vous", "il", "elle", "ce", "c'est", "dans", "pour", "avec", "sur", "mais",
+        ],
+        "de" => &[
+            "der", "die", "das", "und", "ist", "nicht", "ich", "wir", "sie", "ein", "eine", "mit",
+            "den", "dem", "zu", "auf", "für", "auch", "es", "sind", "war", "haben", "aber", "wie",
+        ],
+        "es" => &[
+            "el", "los", "las", "y", "es", "un", "una", "que", "de", "en", "por", "para", "con",
+            "no", "lo", "se", "del", "como", "pero", "yo", "está", "son", "muy", "también",
+        ],
+        "it" => &[
+            "il", "lo", "gli", "e", "è", "di", "che", "non", "un", "una", "per", "con", "sono",
+            "della", "del", "ma", "anche", "io", "noi", "questo", "come", "più", "nel", "perché",
+        ],
+        "pt" => &[
+            "o", "os", "as", "e", "é", "um", "uma", "que", "não", "com", "para", "do", "da", "em",
+            "no", "na", "eu", "nós", "você", "isso", "mas", "também", "está", "são",
+        ],
+        "nl" => &[
+            "de", "het", "een", "en", "is", "niet", "ik", "wij", "we", "dat", "die", "van", "met",
+            "op", "voor", "zijn", "maar", "ook", "er", "aan", "wat", "hij", "zij", "naar",
+        ],
+        _ => return None,
+    };
+    Some(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENGLISH: &str = "What would you like to do with the files that we have in this folder?";
+    const FRENCH: &str =
+        "Je pense que nous avons mis les fichiers dans le dossier pour la réunion.";
+
+    fn allowed(languages: &[&str]) -> Vec<String> {
+        languages
+            .iter()
+            .map(|language| language.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn tells_english_from_french() {
+        let languages = allowed(&["en", "fr"]);
+        let english = detect_language(ENGLISH, &languages).unwrap();
+        assert_eq!(english.language, "en");
+        assert!(english.confidence > 0.8, "{:?}", english);
+        let french = detect_language(FRENCH, &languages).unwrap();
+        assert_eq!(french.language, "fr");
+        assert!(french.confidence > 0.8, "{:?}", french);
+    }
+
+    #[test]
+    fn reports_languages_as_configured() {
+        let french = detect_language(FRENCH, &allowed(&["en", "fr-CA"])).unwrap();
+        assert_eq!(french.language, "fr-CA");
+    }
+
+    #[test]
+    fn only_detects_allowed_languages() {
+        assert_eq!(detect_language(FRENCH, &allowed(&["en"])), None);
+        assert_eq!(detect_language(FRENCH, &[]), None);
+        // Unsupported entries are skipped
+        assert_eq!(detect_language(FRENCH, &allowed(&["ja"])), None);
+    }
+
+    #[test]
+    fn short_utterances_are_not_confident() {
+        let languages = allowed(&["en", "fr"]);
+        assert_eq!(detect_language("Kubernetes", &languages), None);
+        let short = detect_language("the end", &languages).unwrap();
+        assert_eq!(short.language, "en");
+        assert!(short.confidence <= 0.2, "{:?}", short);
+    }
+
+    #[test]
+    fn ties_go_to_the_language_allowed_first() {
+        // "de" and "en" are common words of both Dutch and Spanish
+        let text = "de en";
+        assert_eq!(
+            detect_language(text, &allowed(&["nl", "es"]))
+                .unwrap()
+                .language,
+            "nl"
+        );
+        assert_eq!(
+            detect_language(text, &allowed(&["es", "nl"]))
+                .unwrap()
+                .language,
+            "es"
+        );
+    }
+
+    #[test]
+    fn supports_regions_of_its_languages() {
+        assert!(is_supported("en"));
+        assert!(is_supported("pt-BR"));
+        assert!(!is_supported("ja"));
+    }
+}
@@ -1,12 +1,47 @@
 mod audio;
+pub mod bundle;
+pub mod commands;
 pub mod config;
 pub mod dictionary;
+pub mod dsp;
 mod engine;
+pub mod error;
+#[cfg(feature = "async")]
+mod executor;
+pub mod filter;
+pub mod formats;
+pub mod itn;
+pub mod langid;
+pub mod mock;
 pub mod model;
+pub mod postprocess;
+pub mod punctuation;
+pub mod stats;
+pub mod tempfiles;
 pub mod transcription;
+mod vad;
+pub mod validation;
+mod wav;
 
 // Re-export public types for library usage
+pub use audio::{InternalEngineError, LoadStats};
+pub use commands::{DetectedCommand, VoiceCommand};
 pub use config::ServerConfig;
 pub use dictionary::Dictionary;
-pub use model::Model;
-pub use transcription::TranscriptionService;
+pub use engine::transcription_engine::{
+    DecodingOptions, ModelMetadata, OutputToken, SpeechEngine, TranscriptionOutput,
+    TranscriptionSegment,
+};
+pub use error::TranscriptionError;
+pub use langid::DetectedLanguage;
+pub use model::{Model, ModelSpec, ModelStatus, UnknownModelError};
+pub use postprocess::{
+    DictionaryCorrector, DictionaryStatus, TranscriptPostProcessor, TranscriptionContext,
+};
+pub use stats::SpeechStats;
+pub use transcription::{
+    ChannelMode, ChannelTranscript, DebugCapture, InputAudio, StageTimings, Transcript,
+    TranscriptSegment, TranscriptWord, TranscriptionOptions, TranscriptionService,
+    TranscriptionServiceBuilder, TwoPassOutcome,
+};
+pub use validation::AudioValidationError;
@@ -0,0 +1,165 @@
+//! A stand-in for the Parakeet engine that returns canned text
+//!
+//! Runs the whole service (decoding, resampling, segmentation, dictionary,
+//! post-processing) without model files, e.g. to exercise a server in CI:
+//!
+//! ```rust,no_run
+//! use murmure_stt::mock::MockEngine;
+//! use murmure_stt::TranscriptionService;
+//!
+//! let engine = MockEngine::new("hello world").on_duration(1500, "one and a half seconds");
+//! let service = TranscriptionService::builder()
+//!     .engine(move || engine.clone())
+//!     .build()?;
+//! # Ok::<(), murmure_stt::TranscriptionError>(())
+//! ```
+//!
+//! The engine sees what the service hands it: 16 kHz mono samples, one call
+//! per segment when segmentation is on, so durations and fingerprints are
+//! those of the resampled segment rather than of the uploaded file.
+//!
+//! Of the decoding options, `max_tokens` is honoured, counting a word as a
+//! token, so a caller can see its options reach the engine; the others have
+//! no meaning for canned text and are ignored.
+//!
+//! Every token is decoded with the probability set by
+//! [`MockEngine::with_confidence`] (1 by default), which becomes the
+//! transcript's confidence. [`MockEngine::with_latency`] makes each call
+//! take a while, to observe requests and jobs while they run.
+
+use crate::audio::TARGET_SAMPLE_RATE;
+use crate::engine::transcription_engine::{
+    DecodingOptions, ModelMetadata, OutputToken, SpeechEngine, TranscriptionOutput,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Seconds per reported frame, the same as Parakeet's encoder
+const FRAME_SHIFT_SECS: f32 = 0.08;
+
+/// Canned transcripts keyed by the audio they are returned for.
+#[derive(Clone, Debug, Default)]
+pub struct MockEngine {
+    /// Text for audio of a given duration, in milliseconds
+    by_duration: HashMap<u64, String>,
+    /// Text for given samples, see [`MockEngine::fingerprint`]
+    by_fingerprint: HashMap<u64, String>,
+    /// Text for any other audio
+    default_text: String,
+    /// Confidence of every transcript, `None` = certain
+    confidence: Option<f32>,
+    /// How long each call blocks before returning
+    latency: Duration,
+}
+
+impl MockEngine {
+    /// An engine transcribing everything as `default_text`.
+    pub fn new(default_text: impl Into<String>) -> Self {
+        Self {
+            default_text: default_text.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Return `text` for audio lasting `millis` milliseconds.
+    pub fn on_duration(mut self, millis: u64, text: impl Into<String>) -> Self {
+        self.by_duration.insert(millis, text.into());
+        self
+    }
+
+    /// Return `text` for the samples with this [`MockEngine::fingerprint`].
+    /// Takes precedence over a duration match.
+    pub fn on_fingerprint(mut self, fingerprint: u64, text: impl Into<String>) -> Self {
+        self.by_fingerprint.insert(fingerprint, text.into());
+        self
+    }
+
+    /// Decode every token with probability `confidence` (0 to 1).
+    pub fn with_confidence(mut self, confidence: f32) -> Self {
+        self.confidence = Some(confidence);
+        self
+    }
+
+    /// Block for `latency` in every call, like a real inference would.
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// FNV-1a hash of the samples' bits, to key a transcript on exact audio.
+    pub fn fingerprint(samples: &[f32]) -> u64 {
+        samples
+            .iter()
+            .flat_map(|sample| sample.to_bits().to_le_bytes())
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+
+    fn reply(&self, samples: &[f32]) -> &str {
+        let millis = samples.len() as u64 * 1000 / TARGET_SAMPLE_RATE as u64;
+        self.by_fingerprint
+            .get(&Self::fingerprint(samples))
+            .or_else(|| self.by_duration.get(&millis))
+            .unwrap_or(&self.default_text)
+    }
+}
+
+impl SpeechEngine for MockEngine {
+    fn transcribe(
+        &mut self,
+        samples: Vec<f32>,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        self.transcribe_with(samples, &DecodingOptions::default())
+    }
+
+    /// The canned text, cut to `max_tokens` words, its words spread evenly
+    /// over the audio so word timings and segments look like a real
+    /// transcript's.
+    fn transcribe_with(
+        &mut self,
+        samples: Vec<f32>,
+        decoding: &DecodingOptions,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        std::thread::sleep(self.latency);
+        let reply = self.reply(&samples);
+        let frames = (samples.len() as f32 / TARGET_SAMPLE_RATE as f32 / FRAME_SHIFT_SECS) as usize;
+        let words: Vec<&str> = reply
+            .split_whitespace()
+            .take(decoding.max_tokens.unwrap_or(usize::MAX))
+            .collect();
+        let text = match decoding.max_tokens {
+            Some(_) => words.join(" "),
+            None => reply.to_string(),
+        };
+        let per_word = frames / words.len().max(1);
+        let logprob = self.confidence.map_or(0.0, f32::ln);
+        let tokens = words
+            .iter()
+            .enumerate()
+            .map(|(i, word)| {
+                let start_frame = i * per_word;
+                let end_frame = start_frame + per_word.max(1);
+                OutputToken {
+                    text: format!(" {}", word),
+                    start_frame,
+                    end_frame,
+                    start: start_frame as f32 * FRAME_SHIFT_SECS,
+                    end: end_frame as f32 * FRAME_SHIFT_SECS,
+                    logprob,
+                }
+            })
+            .collect();
+
+        Ok(TranscriptionOutput {
+            text,
+            segments: Vec::new(),
+            tokens,
+            model: ModelMetadata {
+                engine: "mock",
+                frame_shift_secs: FRAME_SHIFT_SECS,
+                vocab_size: 0,
+            },
+        })
+    }
+}
@@ -1,21 +1,695 @@
-use crate::config::ServerConfig;
+use crate::config::{self, ServerConfig};
+use crate::engine::transcription_engine::SpeechEngine;
+use crate::error::TranscriptionError;
 use anyhow::Result;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
 
+/// Weight precision to load from the model directory.
+///
+/// Each precision maps to a file variant (`encoder-model.int8.onnx`,
+/// `encoder-model.fp16.onnx`, `encoder-model.onnx`); when the requested variant
+/// is missing the full-precision file is used instead.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelPrecision {
+    /// 8-bit quantized weights (default, fastest on CPU)
+    #[default]
+    Int8,
+    /// Half-precision weights
+    Fp16,
+    /// Full-precision weights
+    Fp32,
+}
+
+impl ModelPrecision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Int8 => "int8",
+            Self::Fp16 => "fp16",
+            Self::Fp32 => "fp32",
+        }
+    }
+}
+
+impl ModelPrecision {
+    /// Suffix of this precision's weight files, e.g. `encoder-model.int8.onnx`
+    /// (`None` for full precision, which uses the plain file name).
+    fn file_suffix(&self) -> Option<&'static str> {
+        match self {
+            Self::Int8 => Some("int8"),
+            Self::Fp16 => Some("fp16"),
+            Self::Fp32 => None,
+        }
+    }
+}
+
+impl FromStr for ModelPrecision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "int8" => Ok(Self::Int8),
+            "fp16" => Ok(Self::Fp16),
+            "fp32" => Ok(Self::Fp32),
+            other => Err(anyhow::anyhow!(
+                "Unknown model precision '{}', expected 'int8', 'fp16' or 'fp32'",
+                other
+            )),
+        }
+    }
+}
+
+/// ONNX Runtime execution provider used to run the model.
+///
+/// GPU providers require the matching cargo feature (`cuda`, `coreml`,
+/// `directml`); if the requested provider can't be initialized the engine
+/// falls back to the CPU and logs a warning.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionProvider {
+    /// Default CPU provider (always available)
+    #[default]
+    Cpu,
+    /// NVIDIA CUDA
+    Cuda,
+    /// Apple CoreML
+    CoreMl,
+    /// DirectML on Windows
+    DirectMl,
+}
+
+impl ExecutionProvider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Cpu => "cpu",
+            Self::Cuda => "cuda",
+            Self::CoreMl => "coreml",
+            Self::DirectMl => "directml",
+        }
+    }
+}
+
+impl std::fmt::Display for ExecutionProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ExecutionProvider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "cpu" => Ok(Self::Cpu),
+            "cuda" => Ok(Self::Cuda),
+            "coreml" => Ok(Self::CoreMl),
+            "directml" => Ok(Self::DirectMl),
+            other => Err(anyhow::anyhow!(
+                "Unknown execution provider '{}', expected 'cpu', 'cuda', 'coreml' or 'directml'",
+                other
+            )),
+        }
+    }
+}
+
+/// A model the server can serve, selected by name in requests.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ModelSpec {
+    /// Name used to select the model
+    pub name: String,
+    /// Model directory
+    pub path: PathBuf,
+    /// Weight precision variant to load
+    #[serde(default)]
+    pub precision: ModelPrecision,
+}
+
+/// A request named a model that isn't configured.
+#[derive(Debug, thiserror::Error)]
+#[error("unknown model '{name}', configured models: {available}")]
+pub struct UnknownModelError {
+    pub name: String,
+    pub available: String,
+}
+
+/// Weight files loaded with the precision variant (falling back to the
+/// plain file), then the files loaded as they are.
+const WEIGHT_FILES: [&str; 2] = ["encoder-model", "decoder_joint-model"];
+const PLAIN_FILES: [&str; 2] = ["nemo128.onnx", "vocab.txt"];
+
+/// What was checked to decide whether a model can be loaded, from
+/// [`Model::availability`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelStatus {
+    /// Directory set by `MURMURE_MODEL_PATH` or the model's `path` (`None`
+    /// when it is searched for)
+    pub configured_path: Option<PathBuf>,
+    /// Directories the model was looked for in, in order
+    pub candidates: Vec<ProbedPath>,
+    /// First candidate that exists
+    pub model_dir: Option<PathBuf>,
+    /// Files the engine loads from `model_dir` (empty without one)
+    pub files: Vec<ModelFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbedPath {
+    pub path: PathBuf,
+    pub exists: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelFile {
+    /// File name, or the alternatives when either would do
+    /// (`encoder-model.int8.onnx or encoder-model.onnx`)
+    pub name: String,
+    pub present: bool,
+}
+
+impl ModelStatus {
+    /// The model directory was found and holds every file the engine loads.
+    pub fn is_available(&self) -> bool {
+        self.model_dir.is_some() && self.files.iter().all(|file| file.present)
+    }
+
+    pub fn missing_files(&self) -> impl Iterator<Item = &str> {
+        self.files
+            .iter()
+            .filter(|file| !file.present)
+            .map(|file| file.name.as_str())
+    }
+
+    /// One line: `available at <dir>`, the missing files, or where the
+    /// directory was looked for.
+    pub fn summary(&self) -> String {
+        match &self.model_dir {
+            Some(dir) if self.is_available() => format!("available at {}", dir.display()),
+            Some(dir) => format!(
+                "{} is missing {}",
+                dir.display(),
+                self.missing_files().collect::<Vec<_>>().join(", ")
+            ),
+            None if self.configured_path.is_some() => format!(
+                "configured path {} does not exist",
+                self.candidates[0].path.display()
+            ),
+            None => format!(
+                "not found in {} (set MURMURE_MODEL_PATH)",
+                self.candidates
+                    .iter()
+                    .map(|candidate| candidate.path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+/// The full report, one line per path and file checked.
+impl fmt::Display for ModelStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.configured_path {
+            Some(path) => writeln!(f, "Configured model path: {}", path.display())?,
+            None => writeln!(
+                f,
+                "MURMURE_MODEL_PATH is not set, searching for {}",
+                config::DEFAULT_MODEL_DIR
+            )?,
+        }
+        for candidate in &self.candidates {
+            let found = if candidate.exists { "found" } else { "missing" };
+            writeln!(f, "  [{}] {}", found, candidate.path.display())?;
+        }
+        if let Some(dir) = &self.model_dir {
+            writeln!(f, "Files in {}:", dir.display())?;
+            for file in &self.files {
+                let found = if file.present { "found" } else { "missing" };
+                writeln!(f, "  [{}] {}", found, file.name)?;
+            }
+        }
+        write!(f, "Model {}", self.summary())
+    }
+}
+
+/// Check the files [`Model`] loads from `dir` with the given precision.
+fn probe_files(dir: &Path, precision: ModelPrecision) -> Vec<ModelFile> {
+    let weights = WEIGHT_FILES.iter().map(|name| {
+        let plain = format!("{}.onnx", name);
+        match precision.file_suffix() {
+            Some(suffix) => {
+                let variant = format!("{}.{}.onnx", name, suffix);
+                ModelFile {
+                    present: dir.join(&variant).is_file() || dir.join(&plain).is_file(),
+                    name: format!("{} or {}", variant, plain),
+                }
+            }
+            None => ModelFile {
+                present: dir.join(&plain).is_file(),
+                name: plain,
+            },
+        }
+    });
+    let plain = PLAIN_FILES.iter().map(|name| ModelFile {
+        name: name.to_string(),
+        present: dir.join(name).is_file(),
+    });
+    weights.chain(plain).collect()
+}
+
+/// Makes the engine of a model that isn't loaded from its directory.
+pub(crate) type EngineFactory = Arc<dyn Fn() -> Box<dyn SpeechEngine> + Send + Sync>;
+
+/// Id of the next injected engine factory, see [`Model::engine_key`]
+static NEXT_FACTORY_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone)]
 pub struct Model {
     config: ServerConfig,
+    /// `None` for the single model found through `model_path`
+    spec: Option<ModelSpec>,
+    /// Replaces the Parakeet engine, see [`Self::with_engine`], with the id
+    /// its engine is keyed by
+    engine: Option<(u64, EngineFactory)>,
 }
 
 impl Model {
+    /// The default model: the first entry of `models`, or the model at
+    /// `model_path` when no named models are configured.
     pub fn new(config: ServerConfig) -> Self {
-        Self { config }
+        let spec = config.models.first().cloned();
+        Self {
+            config,
+            spec,
+            engine: None,
+        }
+    }
+
+    /// One of the named models of `config`.
+    pub fn from_spec(config: ServerConfig, spec: ModelSpec) -> Self {
+        Self {
+            config,
+            spec: Some(spec),
+            engine: None,
+        }
     }
 
-    pub fn get_model_path(&self) -> Result<PathBuf> {
-        self.config.get_model_path()
+    /// Run this model on engines made by `factory` instead of loading
+    /// Parakeet from the model directory, which then needn't exist. The
+    /// factory is called whenever the engine is (re)loaded.
+    pub fn with_engine<E: SpeechEngine + 'static>(
+        self,
+        factory: impl Fn() -> E + Send + Sync + 'static,
+    ) -> Self {
+        self.with_engine_factory(Arc::new(move || Box::new(factory())))
     }
 
+    pub(crate) fn with_engine_factory(mut self, factory: EngineFactory) -> Self {
+        let id = NEXT_FACTORY_ID.fetch_add(1, Ordering::Relaxed);
+        self.engine = Some((id, factory));
+        self
+    }
+
+    pub(crate) fn engine_factory(&self) -> Option<&EngineFactory> {
+        self.engine.as_ref().map(|(_, factory)| factory)
+    }
+
+    pub fn get_config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn get_model_path(&self) -> std::result::Result<PathBuf, TranscriptionError> {
+        let configured = match &self.spec {
+            Some(spec) => Some(&spec.path),
+            None => self.config.model_path.as_ref(),
+        };
+        match configured {
+            Some(path) if path.exists() => Ok(path.clone()),
+            Some(path) => Err(TranscriptionError::ModelNotFound { path: path.clone() }),
+            // Not found in any of the default locations
+            None => self
+                .config
+                .get_model_path()
+                .map_err(|e| TranscriptionError::EngineLoad(e.into())),
+        }
+    }
+
+    /// Whether the model directory is found and holds every file the engine
+    /// loads. [`Self::availability`] tells what is missing.
     pub fn is_available(&self) -> bool {
-        self.get_model_path().is_ok()
+        self.engine.is_some() || self.availability().is_available()
+    }
+
+    /// Where the model was looked for and which of its files are present.
+    pub fn availability(&self) -> ModelStatus {
+        let configured_path = match &self.spec {
+            Some(spec) => Some(spec.path.clone()),
+            None => self.config.model_path.clone(),
+        };
+        let candidates: Vec<ProbedPath> = match &configured_path {
+            Some(path) => vec![path.clone()],
+            None => config::resource_search_dirs()
+                .into_iter()
+                .map(|dir| dir.join(config::DEFAULT_MODEL_DIR))
+                .collect(),
+        }
+        .into_iter()
+        .map(|path| ProbedPath {
+            exists: path.exists(),
+            path,
+        })
+        .collect();
+        let model_dir = candidates
+            .iter()
+            .find(|candidate| candidate.exists)
+            .map(|candidate| candidate.path.clone());
+        let files = model_dir
+            .as_deref()
+            .map(|dir| probe_files(dir, self.precision()))
+            .unwrap_or_default();
+        ModelStatus {
+            configured_path,
+            candidates,
+            model_dir,
+            files,
+        }
+    }
+
+    /// Name requests select the model by: the configured name, or the model
+    /// directory name (e.g. `parakeet-tdt-0.6b-v3-int8`) for the single
+    /// `model_path` model.
+    pub fn name(&self) -> String {
+        if let Some(spec) = &self.spec {
+            return spec.name.clone();
+        }
+        self.get_model_path()
+            .ok()
+            .and_then(|path| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| "default".to_string())
+    }
+
+    /// Weight precision to load for this model.
+    pub fn precision(&self) -> ModelPrecision {
+        self.spec
+            .as_ref()
+            .map_or(self.config.model_precision, |spec| spec.precision)
+    }
+
+    /// Execution provider this model's engine runs on (`None` while unloaded).
+    pub fn execution_provider(&self) -> Option<ExecutionProvider> {
+        crate::audio::active_execution_provider(self)
+    }
+
+    /// Time and memory the last load of this model's engine took (`None`
+    /// if it was never loaded).
+    pub fn load_stats(&self) -> Option<crate::audio::LoadStats> {
+        crate::audio::engine_load_stats(self)
+    }
+
+    /// Whether the engine for this model is currently resident in memory.
+    pub fn is_loaded(&self) -> bool {
+        crate::audio::is_engine_loaded(self)
+    }
+
+    /// Identifies the engine this model loads. Models with the same weights,
+    /// precision, execution provider and `mmap_model` share one engine, whatever they are
+    /// named and whichever service they belong to; any other model gets its
+    /// own, even under the same name. Each injected engine factory gets an
+    /// id of its own, so it never shares an engine with another one, even
+    /// one allocated where a dropped factory was.
+    pub(crate) fn engine_key(&self) -> String {
+        if let Some((id, _)) = &self.engine {
+            return format!("injected#{}", id);
+        }
+        let configured = match &self.spec {
+            Some(spec) => Some(&spec.path),
+            None => self.config.model_path.as_ref(),
+        };
+        // Unset: found by searching, the same directory for every service
+        let path = configured.map_or_else(
+            || "<default>".to_string(),
+            |path| {
+                std::fs::canonicalize(path)
+                    .unwrap_or_else(|_| path.clone())
+                    .display()
+                    .to_string()
+            },
+        );
+        // Mapped and read weights are loaded differently, so don't share
+        format!(
+            "{}#{}#{}{}",
+            path,
+            self.precision().as_str(),
+            self.config.execution_provider.as_str(),
+            if self.config.mmap_model { "" } else { "#read" }
+        )
+    }
+
+    /// Latest modification time among the model files, to tell apart two
+    /// deployments of the same model name.
+    pub fn modified(&self) -> Option<SystemTime> {
+        let path = self.get_model_path().ok()?;
+        std::fs::read_dir(path)
+            .ok()?
+            .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_precisions() {
+        assert_eq!(
+            "INT8".parse::<ModelPrecision>().unwrap(),
+            ModelPrecision::Int8
+        );
+        assert_eq!(
+            "fp16".parse::<ModelPrecision>().unwrap(),
+            ModelPrecision::Fp16
+        );
+        assert_eq!(
+            "fp32".parse::<ModelPrecision>().unwrap(),
+            ModelPrecision::Fp32
+        );
+        assert!("int4".parse::<ModelPrecision>().is_err());
+        assert_eq!(ModelPrecision::default(), ModelPrecision::Int8);
+    }
+
+    #[test]
+    fn parses_execution_providers() {
+        for provider in [
+            ExecutionProvider::Cpu,
+            ExecutionProvider::Cuda,
+            ExecutionProvider::CoreMl,
+            ExecutionProvider::DirectMl,
+        ] {
+            assert_eq!(
+                provider.to_string().parse::<ExecutionProvider>().unwrap(),
+                provider
+            );
+        }
+        assert_eq!(
+            "CUDA".parse::<ExecutionProvider>().unwrap(),
+            ExecutionProvider::Cuda
+        );
+        assert!("tensorrt".parse::<ExecutionProvider>().is_err());
+        assert_eq!(ExecutionProvider::default(), ExecutionProvider::Cpu);
+    }
+
+    #[test]
+    fn named_models_override_the_configured_precision() {
+        let config = ServerConfig {
+            model_precision: ModelPrecision::Fp16,
+            ..ServerConfig::default()
+        };
+        assert_eq!(Model::new(config.clone()).precision(), ModelPrecision::Fp16);
+
+        let spec = ModelSpec {
+            name: "accurate".to_string(),
+            path: PathBuf::from("/models/accurate"),
+            precision: ModelPrecision::Fp32,
+        };
+        let model = Model::from_spec(config, spec);
+        assert_eq!(model.precision(), ModelPrecision::Fp32);
+        assert!(model.engine_key().ends_with("#fp32#cpu"));
+    }
+
+    #[test]
+    fn mapped_and_read_models_load_their_own_engine() {
+        let config = |mmap_model| ServerConfig {
+            model_path: Some(PathBuf::from("/models/parakeet")),
+            mmap_model,
+            ..ServerConfig::default()
+        };
+        let mapped = Model::new(config(true)).engine_key();
+        assert_eq!(mapped, Model::new(config(true)).engine_key());
+        assert_ne!(mapped, Model::new(config(false)).engine_key());
+    }
+
+    #[test]
+    fn probes_the_files_of_the_precision() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in [
+            "encoder-model.int8.onnx",
+            "decoder_joint-model.int8.onnx",
+            "nemo128.onnx",
+            "vocab.txt",
+        ] {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let status = |precision| {
+            let config = ServerConfig {
+                model_path: Some(dir.path().to_path_buf()),
+                model_precision: precision,
+                ..ServerConfig::default()
+            };
+            Model::new(config).availability()
+        };
+
+        assert!(status(ModelPrecision::Int8).is_available());
+        let fp32 = status(ModelPrecision::Fp32);
+        assert!(!fp32.is_available());
+        assert_eq!(
+            fp32.missing_files().collect::<Vec<_>>(),
+            ["encoder-model.onnx", "decoder_joint-model.onnx"]
+        );
+        // fp16 would fall back to the plain files, which aren't there either
+        assert_eq!(
+            status(ModelPrecision::Fp16).missing_files().next(),
+            Some("encoder-model.fp16.onnx or encoder-model.onnx")
+        );
+    }
+
+    fn status_of(dir: &Path) -> ModelStatus {
+        let config = ServerConfig {
+            model_path: Some(dir.to_path_buf()),
+            ..ServerConfig::default()
+        };
+        Model::new(config).availability()
+    }
+
+    fn touch(dir: &Path, names: &[&str]) {
+        for name in names {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+    }
+
+    #[test]
+    fn reports_a_configured_path_that_does_not_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("parakeet");
+        let status = status_of(&path);
+
+        assert!(!status.is_available());
+        assert_eq!(status.configured_path.as_ref(), Some(&path));
+        assert_eq!(
+            status.candidates,
+            [ProbedPath {
+                path: path.clone(),
+                exists: false
+            }]
+        );
+        assert_eq!(status.model_dir, None);
+        assert!(status.files.is_empty());
+        assert_eq!(
+            status.summary(),
+            format!("configured path {} does not exist", path.display())
+        );
+        let report = status.to_string();
+        assert!(report.contains(&format!("Configured model path: {}", path.display())));
+        assert!(report.contains(&format!("[missing] {}", path.display())));
+    }
+
+    #[test]
+    fn reports_every_file_of_an_empty_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let status = status_of(dir.path());
+
+        assert!(!status.is_available());
+        assert_eq!(status.model_dir.as_deref(), Some(dir.path()));
+        assert_eq!(
+            status.missing_files().collect::<Vec<_>>(),
+            [
+                "encoder-model.int8.onnx or encoder-model.onnx",
+                "decoder_joint-model.int8.onnx or decoder_joint-model.onnx",
+                "nemo128.onnx",
+                "vocab.txt",
+            ]
+        );
+        assert!(status.summary().starts_with(&format!(
+            "{} is missing encoder-model",
+            dir.path().display()
+        )));
+    }
+
+    #[test]
+    fn reports_the_one_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(
+            dir.path(),
+            &[
+                "encoder-model.onnx",
+                "decoder_joint-model.int8.onnx",
+                "nemo128.onnx",
+            ],
+        );
+        let status = status_of(dir.path());
+
+        assert!(!status.is_available());
+        assert_eq!(status.missing_files().collect::<Vec<_>>(), ["vocab.txt"]);
+        assert_eq!(
+            status.summary(),
+            format!("{} is missing vocab.txt", dir.path().display())
+        );
+        let report = status.to_string();
+        assert!(report.contains("[found] nemo128.onnx"), "{}", report);
+        assert!(report.contains("[missing] vocab.txt"), "{}", report);
+
+        touch(dir.path(), &["vocab.txt"]);
+        let status = status_of(dir.path());
+        assert!(status.is_available());
+        assert_eq!(
+            status.summary(),
+            format!("available at {}", dir.path().display())
+        );
+    }
+
+    #[test]
+    fn searches_the_resource_directories_when_unset() {
+        let status = Model::new(ServerConfig::default()).availability();
+
+        assert_eq!(status.configured_path, None);
+        let probed: Vec<_> = status.candidates.iter().map(|c| c.path.clone()).collect();
+        let expected: Vec<_> = config::resource_search_dirs()
+            .into_iter()
+            .map(|dir| dir.join(config::DEFAULT_MODEL_DIR))
+            .collect();
+        assert_eq!(probed, expected);
+        assert!(status
+            .to_string()
+            .starts_with("MURMURE_MODEL_PATH is not set"));
+        if status.model_dir.is_none() {
+            assert!(status.summary().ends_with("(set MURMURE_MODEL_PATH)"));
+        }
+    }
+
+    #[test]
+    fn injected_engines_need_no_model_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ServerConfig {
+            model_path: Some(dir.path().join("missing")),
+            ..ServerConfig::default()
+        };
+        let model = Model::new(config).with_engine(|| crate::mock::MockEngine::new("text"));
+        assert!(model.is_available());
+        assert!(!model.availability().is_available());
     }
 }
@@ -0,0 +1,162 @@
+// Pluggable text post-processing
+//
+// Order in which a transcript's text is rewritten:
+//
+// 1. Dictionary correction ([`DictionaryCorrector`]), on each raw segment
+// 2. The custom processors given to `TranscriptionService::new`, in order,
+//    each receiving the output of the previous one
+// 3. Number normalization, punctuation, profanity filtering and redaction,
+//    as enabled for the request
+//
+// Custom processors run on every segment when segmentation is on (on the
+// whole text otherwise) and on every channel in separate channel mode, so
+// segment texts and time ranges stay in sync. Built-in filtering comes last,
+// so configured redaction also applies to what a processor adds. A processor
+// error fails the request.
+
+use crate::config::ServerConfig;
+use crate::dictionary::{
+    correct_transcription_with_dictionary, get_cc_rules_path, CorrectedText, Dictionary, RulesError,
+};
+use crate::transcription::TranscriptionOptions;
+use anyhow::Result;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// What a post-processor is told about the text it rewrites.
+#[derive(Debug, Clone, Copy)]
+pub struct TranscriptionContext<'a> {
+    /// Options of the request being transcribed
+    pub options: &'a TranscriptionOptions,
+    /// Name of the model that produced the text
+    pub model: &'a str,
+    /// Source channel, in separate channel mode only
+    pub channel: Option<usize>,
+}
+
+/// A text rewriting step run on every transcript, such as a custom
+/// punctuation model or PII scrubbing.
+pub trait TranscriptPostProcessor: Send + Sync {
+    /// Short name used in errors and logs
+    fn name(&self) -> &str;
+
+    /// Rewrite one segment, or the whole text when segmentation is off.
+    fn process(&self, text: String, ctx: &TranscriptionContext) -> Result<String>;
+}
+
+/// Whether transcripts get dictionary corrections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DictionaryStatus {
+    /// A dictionary is configured and its cc-rules were found
+    Active,
+    /// A dictionary is configured but the cc-rules weren't found, so
+    /// transcripts are returned uncorrected (`allow_missing_cc_rules`)
+    Degraded,
+    /// No dictionary is configured
+    Off,
+}
+
+impl DictionaryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Degraded => "degraded",
+            Self::Off => "off",
+        }
+    }
+}
+
+impl fmt::Display for DictionaryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Built-in dictionary correction.
+///
+/// The pipeline runs it first and keeps the corrections it reports; as a
+/// [`TranscriptPostProcessor`] it only returns the corrected text.
+#[derive(Clone)]
+pub struct DictionaryCorrector {
+    dictionary: Arc<Dictionary>,
+    /// Language the dictionary is scoped to, `None` for the default one
+    language: Option<String>,
+    /// Resolved once at construction, `None` when it couldn't be found
+    cc_rules: Option<PathBuf>,
+    /// Whether the missing cc-rules were reported already
+    warned: Arc<AtomicBool>,
+}
+
+impl DictionaryCorrector {
+    pub fn new(dictionary: Arc<Dictionary>, config: Arc<ServerConfig>) -> Self {
+        Self {
+            dictionary,
+            language: None,
+            cc_rules: get_cc_rules_path(&config).ok(),
+            warned: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Correction with the dictionary of `language`, using the language's
+    /// cc-rules subdirectory when there is one.
+    pub fn for_language(
+        dictionary: Arc<Dictionary>,
+        config: &ServerConfig,
+        language: &str,
+    ) -> Self {
+        Self {
+            dictionary,
+            language: Some(language.to_string()),
+            cc_rules: config.get_language_cc_rules_path(language).ok(),
+            warned: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// The word list corrections are made against.
+    pub fn dictionary(&self) -> &Arc<Dictionary> {
+        &self.dictionary
+    }
+
+    /// Language the dictionary is scoped to, `None` for the default one.
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    /// The cc-rules directory corrections use, `None` when it wasn't found.
+    pub fn cc_rules_path(&self) -> Option<&Path> {
+        self.cc_rules.as_deref()
+    }
+
+    /// Correct `text`, reporting each replaced word. The text is returned
+    /// unchanged when the cc-rules directory wasn't found (logged once), and
+    /// an error when it was found but can't be loaded.
+    pub fn correct(&self, text: String) -> std::result::Result<CorrectedText, RulesError> {
+        let Some(cc_rules) = &self.cc_rules else {
+            if !self.warned.swap(true, Ordering::Relaxed) {
+                log::warn!(
+                    "cc-rules directory not found, transcripts{} are returned without dictionary correction",
+                    self.language
+                        .as_ref()
+                        .map_or_else(String::new, |language| format!(" in '{}'", language))
+                );
+            }
+            return Ok(CorrectedText {
+                text,
+                corrections: Vec::new(),
+            });
+        };
+        correct_transcription_with_dictionary(text, &self.dictionary.get(), cc_rules.clone())
+    }
+}
+
+impl TranscriptPostProcessor for DictionaryCorrector {
+    fn name(&self) -> &str {
+        "dictionary"
+    }
+
+    fn process(&self, text: String, _ctx: &TranscriptionContext) -> Result<String> {
+        Ok(self.correct(text)?.text)
+    }
+}
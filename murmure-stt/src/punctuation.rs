@@ -0,0 +1,138 @@
+// Rule-based punctuation and truecasing for raw transcripts
+
+use crate::transcription::Transcript;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Standalone first-person "i" (also matches "i'm", "i've", "i'll", "i'd")
+static LOWERCASE_I: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bi\b").unwrap());
+
+/// Punctuate and capitalize a single utterance.
+///
+/// Dictionary words are restored to their dictionary casing, a lone "i"
+/// becomes "I", the first letter of every sentence is capitalized and a final
+/// period is added when the text doesn't already end with punctuation.
+pub fn punctuate_text(text: &str, proper_nouns: &[String]) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return String::new();
+    }
+
+    let mut result = trimmed.to_string();
+    for noun in proper_nouns {
+        let pattern = format!(r"(?i)\b{}\b", regex::escape(noun));
+        if let Ok(re) = Regex::new(&pattern) {
+            result = re.replace_all(&result, noun.as_str()).into_owned();
+        }
+    }
+    result = LOWERCASE_I.replace_all(&result, "I").into_owned();
+
+    let mut result = capitalize_sentences(&result);
+    if result.chars().last().is_some_and(char::is_alphanumeric) {
+        result.push('.');
+    }
+    result
+}
+
+/// Punctuate a transcript in place.
+///
+/// Each pause-delimited segment is treated as its own sentence and the full
+/// text is rebuilt from the segments; without segments the whole text is a
+/// single utterance. Segment timings are left untouched.
+pub fn punctuate_transcript(transcript: &mut Transcript, proper_nouns: &[String]) {
+    transcript.rewrite_text(|text| punctuate_text(text, proper_nouns));
+}
+
+/// Uppercase the first letter of the text and of every sentence following
+/// `.`, `?` or `!`.
+fn capitalize_sentences(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut capitalize_next = true;
+
+    for c in text.chars() {
+        if capitalize_next && c.is_alphabetic() {
+            result.extend(c.to_uppercase());
+            capitalize_next = false;
+            continue;
+        }
+        if c.is_alphanumeric() {
+            capitalize_next = false;
+        } else if matches!(c, '.' | '?' | '!') {
+            capitalize_next = true;
+        }
+        result.push(c);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcription::TranscriptSegment;
+
+    fn nouns(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn capitalizes_and_ends_sentences() {
+        assert_eq!(punctuate_text("  hello world ", &[]), "Hello world.");
+        assert_eq!(
+            punctuate_text("i think i'm late. are you there? yes", &[]),
+            "I think I'm late. Are you there? Yes."
+        );
+        assert_eq!(punctuate_text("is it ready?", &[]), "Is it ready?");
+        assert_eq!(punctuate_text("", &[]), "");
+        assert_eq!(punctuate_text("   ", &[]), "");
+    }
+
+    #[test]
+    fn decimals_do_not_start_sentences() {
+        assert_eq!(
+            punctuate_text("version 3.5 ships friday", &[]),
+            "Version 3.5 ships friday."
+        );
+    }
+
+    #[test]
+    fn restores_dictionary_casing() {
+        assert_eq!(
+            punctuate_text(
+                "deploy MURMURE with kubernetes on iphone",
+                &nouns(&["Murmure", "iPhone"])
+            ),
+            "Deploy Murmure with kubernetes on iPhone."
+        );
+        // Whole words only
+        assert_eq!(
+            punctuate_text("murmured softly", &nouns(&["Murmure"])),
+            "Murmured softly."
+        );
+    }
+
+    #[test]
+    fn punctuates_each_segment() {
+        let segment = |text: &str, start| TranscriptSegment {
+            start,
+            end: start + 1.0,
+            text: text.to_string(),
+            channel: None,
+        };
+        let mut transcript = Transcript {
+            text: "hello there how are you".to_string(),
+            segments: vec![segment("hello there", 0.0), segment("how are you", 2.0)],
+            ..Transcript::default()
+        };
+        punctuate_transcript(&mut transcript, &[]);
+        assert_eq!(transcript.text, "Hello there. How are you.");
+        assert_eq!(transcript.segments[1].text, "How are you.");
+        assert_eq!(transcript.segments[1].start, 2.0);
+
+        let mut unsegmented = Transcript {
+            text: "hello there how are you".to_string(),
+            ..Transcript::default()
+        };
+        punctuate_transcript(&mut unsegmented, &[]);
+        assert_eq!(unsegmented.text, "Hello there how are you.");
+    }
+}
@@ -0,0 +1,131 @@
+// Speaking rate and level statistics for user feedback
+
+use crate::vad::{frame_energies, SILENCE_RMS_THRESHOLD};
+use serde::{Deserialize, Serialize};
+
+/// How fast and how loud the speaker talked.
+///
+/// Voiced time comes from the same energy frames as silence segmentation, so
+/// long pauses don't drag the speaking rate down.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct SpeechStats {
+    /// Words in the transcript
+    pub word_count: usize,
+    /// Words per minute of voiced audio (0 when nothing was voiced)
+    pub words_per_minute: f32,
+    /// Seconds of audio above the silence threshold
+    pub voiced_secs: f32,
+    /// Mean RMS level over all frames (0.0 - 1.0 of full scale)
+    pub mean_rms: f32,
+    /// Loudest frame RMS level (0.0 - 1.0 of full scale)
+    pub max_rms: f32,
+    /// Share of the audio below the silence threshold, in percent
+    pub silence_percent: f32,
+}
+
+impl SpeechStats {
+    /// Measure voiced time and levels of the transcribed channels. A frame
+    /// counts as voiced when any channel is above the silence threshold.
+    pub fn from_audio(channels: &[&[f32]], sample_rate: usize) -> Self {
+        let energies: Vec<Vec<f32>> = channels
+            .iter()
+            .map(|samples| frame_energies(samples, sample_rate))
+            .collect();
+        let frames = energies.iter().map(Vec::len).max().unwrap_or(0);
+        if frames == 0 {
+            return Self::default();
+        }
+
+        let voiced_frames = (0..frames)
+            .filter(|&frame| {
+                energies.iter().any(|channel| {
+                    channel
+                        .get(frame)
+                        .is_some_and(|&e| e >= SILENCE_RMS_THRESHOLD)
+                })
+            })
+            .count();
+        let all: Vec<f32> = energies.iter().flatten().copied().collect();
+
+        let total_secs =
+            channels.iter().map(|c| c.len()).max().unwrap_or(0) as f32 / sample_rate as f32;
+        let voiced_share = voiced_frames as f32 / frames as f32;
+
+        Self {
+            word_count: 0,
+            words_per_minute: 0.0,
+            voiced_secs: total_secs * voiced_share,
+            mean_rms: all.iter().sum::<f32>() / all.len() as f32,
+            max_rms: all.iter().copied().fold(0.0, f32::max),
+            silence_percent: (1.0 - voiced_share) * 100.0,
+        }
+    }
+
+    /// Fill in the word count and speaking rate from the transcript text.
+    pub fn count_words(&mut self, text: &str) {
+        self.word_count = text.split_whitespace().count();
+        self.words_per_minute = if self.voiced_secs > 0.0 {
+            self.word_count as f32 * 60.0 / self.voiced_secs
+        } else {
+            0.0
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: usize = 16000;
+
+    fn level(value: f32, secs: f32) -> Vec<f32> {
+        vec![value; (secs * RATE as f32) as usize]
+    }
+
+    fn close(actual: f32, expected: f32) -> bool {
+        (actual - expected).abs() < 1e-3
+    }
+
+    #[test]
+    fn measures_speech_between_pauses() {
+        // 3 s of speech at 0.5 and 1 s of silence
+        let samples = [level(0.5, 2.0), level(0.0, 1.0), level(0.5, 1.0)].concat();
+        let mut stats = SpeechStats::from_audio(&[&samples], RATE);
+        assert!(close(stats.voiced_secs, 3.0), "{:?}", stats);
+        assert!(close(stats.silence_percent, 25.0), "{:?}", stats);
+        assert!(close(stats.max_rms, 0.5));
+        assert!(close(stats.mean_rms, 0.375));
+
+        // 9 words in 3 voiced seconds: the pause doesn't count
+        stats.count_words("one two three four five six seven eight nine");
+        assert_eq!(stats.word_count, 9);
+        assert!(close(stats.words_per_minute, 180.0), "{:?}", stats);
+    }
+
+    #[test]
+    fn any_voiced_channel_counts() {
+        let left = [level(0.2, 1.0), level(0.0, 1.0)].concat();
+        let right = [level(0.0, 1.0), level(0.4, 1.0)].concat();
+        let stats = SpeechStats::from_audio(&[&left, &right], RATE);
+        assert!(close(stats.voiced_secs, 2.0), "{:?}", stats);
+        assert!(close(stats.silence_percent, 0.0));
+        assert!(close(stats.max_rms, 0.4));
+    }
+
+    #[test]
+    fn silence_has_no_rate() {
+        let silence = level(0.0, 1.0);
+        let mut stats = SpeechStats::from_audio(&[&silence], RATE);
+        stats.count_words("phantom words");
+        assert_eq!(stats.voiced_secs, 0.0);
+        assert_eq!(stats.silence_percent, 100.0);
+        assert_eq!(stats.words_per_minute, 0.0);
+        assert_eq!(stats.word_count, 2);
+
+        assert_eq!(SpeechStats::from_audio(&[], RATE), SpeechStats::default());
+        assert_eq!(
+            SpeechStats::from_audio(&[&[]], RATE),
+            SpeechStats::default()
+        );
+    }
+}
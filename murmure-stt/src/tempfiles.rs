@@ -0,0 +1,257 @@
+// Temporary audio files in a dedicated directory
+//
+// Files are named `murmure-<pid>-<random>.wav` inside `ServerConfig::temp_dir`.
+// A process killed mid-request can't delete its files, so the server sweeps
+// the directory at startup and periodically, removing stale files left by
+// other processes.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tempfile::NamedTempFile;
+
+const FILE_PREFIX: &str = "murmure-";
+
+/// Files of other processes older than this are considered abandoned
+pub const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// How often the server sweeps the temp directory
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Create a temp file for this process in `dir`, creating the directory if
+/// needed. The file is deleted when the handle is dropped.
+///
+/// Errors name `dir`, as the bare OS error ("No such file or directory",
+/// "Read-only file system") doesn't say a temp file was involved.
+pub fn create(dir: &Path) -> io::Result<NamedTempFile> {
+    fs::create_dir_all(dir)
+        .and_then(|()| {
+            tempfile::Builder::new()
+                .prefix(&format!("{}{}-", FILE_PREFIX, std::process::id()))
+                .suffix(".wav")
+                .tempfile_in(dir)
+        })
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("can't create a temp file in {}: {}", dir.display(), e),
+            )
+        })
+}
+
+/// Check that temp files can be created in `dir` by creating and deleting one.
+pub fn check_writable(dir: &Path) -> io::Result<()> {
+    create(dir)?.close()
+}
+
+/// Bytes available to this process on the filesystem holding `dir`.
+pub fn available_space(dir: &Path) -> io::Result<u64> {
+    fs::create_dir_all(dir)?;
+    fs4::available_space(dir)
+}
+
+/// Payload of the errors built by [`storage_full`].
+#[derive(Debug)]
+struct StorageFull(String);
+
+impl std::fmt::Display for StorageFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for StorageFull {}
+
+/// An error for a write refused because the disk is (nearly) full. Detect it
+/// with [`is_storage_full`]; `io::ErrorKind::StorageFull` needs Rust 1.83.
+pub fn storage_full(message: String) -> io::Error {
+    io::Error::other(StorageFull(message))
+}
+
+/// Whether `e` means the disk is full: built by [`storage_full`], or the
+/// OS reporting no space left on the device.
+pub fn is_storage_full(e: &io::Error) -> bool {
+    if e.get_ref().is_some_and(|inner| inner.is::<StorageFull>()) {
+        return true;
+    }
+    // ENOSPC, and ERROR_HANDLE_DISK_FULL / ERROR_DISK_FULL on Windows
+    let codes: &[i32] = if cfg!(windows) { &[39, 112] } else { &[28] };
+    e.raw_os_error().is_some_and(|code| codes.contains(&code))
+}
+
+/// Remove stale temp files from `dir` and return how many were deleted.
+///
+/// Files of other processes are removed once older than `max_age`. Files of
+/// this process are only removed when `include_own` is set, which is safe at
+/// startup (no request is running yet, the PID was reused from a dead process).
+pub fn sweep(dir: &Path, max_age: Duration, include_own: bool) -> io::Result<usize> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let own_pid = std::process::id();
+    let now = SystemTime::now();
+    let mut removed = 0;
+
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = owner_pid(&name.to_string_lossy()) else {
+            continue;
+        };
+        if pid == own_pid {
+            if !include_own {
+                continue;
+            }
+        } else {
+            let age = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok());
+            if age.map_or(true, |age| age < max_age) {
+                continue;
+            }
+        }
+
+        match fs::remove_file(entry.path()) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(removed)
+}
+
+/// PID embedded in a temp file name, `None` for files we didn't create.
+fn owner_pid(file_name: &str) -> Option<u32> {
+    let rest = file_name.strip_prefix(FILE_PREFIX)?;
+    let (pid, _) = rest.split_once('-')?;
+    pid.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create `name` in `dir`, last modified `age` ago.
+    fn file(dir: &Path, name: &str, age: Duration) {
+        let file = fs::File::create(dir.join(name)).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn creates_named_files_in_the_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("murmure");
+        let temp = create(&dir).unwrap();
+        let name = temp
+            .path()
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        assert!(name.ends_with(".wav"));
+        assert_eq!(owner_pid(&name), Some(std::process::id()));
+
+        let path = temp.path().to_path_buf();
+        drop(temp);
+        assert!(!path.exists());
+        assert!(names(&dir).is_empty());
+    }
+
+    #[test]
+    fn sweeps_only_stale_files_of_other_processes() {
+        let dir = tempfile::tempdir().unwrap();
+        let own = std::process::id();
+        let hour = Duration::from_secs(60 * 60);
+        file(dir.path(), "murmure-1-old.wav", 2 * hour);
+        file(dir.path(), "murmure-1-fresh.wav", Duration::ZERO);
+        file(dir.path(), &format!("murmure-{}-mine.wav", own), 2 * hour);
+        file(dir.path(), "unrelated-old.wav", 2 * hour);
+        file(dir.path(), "murmure-notapid.wav", 2 * hour);
+
+        assert_eq!(sweep(dir.path(), STALE_AFTER, false).unwrap(), 1);
+        assert_eq!(
+            names(dir.path()),
+            [
+                "murmure-1-fresh.wav".to_string(),
+                format!("murmure-{}-mine.wav", own),
+                "murmure-notapid.wav".to_string(),
+                "unrelated-old.wav".to_string(),
+            ]
+        );
+
+        // At startup the files of this PID are leftovers too
+        assert_eq!(sweep(dir.path(), STALE_AFTER, true).unwrap(), 1);
+        assert_eq!(names(dir.path()).len(), 3);
+    }
+
+    #[test]
+    fn sweeping_a_missing_directory_is_a_no_op() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            sweep(&dir.path().join("missing"), STALE_AFTER, true).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn reads_owner_pids() {
+        assert_eq!(owner_pid("murmure-4242-abc123.wav"), Some(4242));
+        assert_eq!(owner_pid("murmure-x-abc.wav"), None);
+        assert_eq!(owner_pid("other-4242-abc.wav"), None);
+    }
+
+    #[test]
+    fn errors_name_the_directory() {
+        // Not a directory for anyone, root included
+        let root = tempfile::tempdir().unwrap();
+        let blocker = root.path().join("file");
+        fs::write(&blocker, b"").unwrap();
+        let dir = blocker.join("spool");
+
+        let error = create(&dir).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with(&format!("can't create a temp file in {}: ", dir.display())),
+            "{}",
+            error
+        );
+        assert!(check_writable(&dir).is_err());
+    }
+
+    #[test]
+    fn writability_probes_leave_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        check_writable(dir.path()).unwrap();
+        assert!(names(dir.path()).is_empty());
+        assert!(available_space(dir.path()).unwrap() > 0);
+    }
+
+    #[test]
+    fn recognizes_full_disks() {
+        assert!(is_storage_full(&storage_full("no room".into())));
+        assert_eq!(storage_full("no room".into()).to_string(), "no room");
+        if cfg!(unix) {
+            assert!(is_storage_full(&io::Error::from_raw_os_error(28)));
+        }
+        assert!(!is_storage_full(&io::Error::other("no room")));
+        assert!(!is_storage_full(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+    }
+}
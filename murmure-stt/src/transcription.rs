@@ -1,96 +1,1095 @@
-use crate::audio::{preload_engine, transcribe_audio};
-use crate::config::ServerConfig;
-use crate::dictionary::Dictionary;
-use crate::model::Model;
+use crate::audio::{engine_panic_count, preload_engine, transcribe_audio, warm_up_engine};
+use crate::commands::{detect_command, DetectedCommand, VoiceCommand};
+use crate::config::{language_fallbacks, ServerConfig};
+use crate::dictionary::{Correction, Dictionary};
+use crate::engine::transcription_engine::{DecodingOptions, SpeechEngine};
+use crate::error::TranscriptionError;
+use crate::filter::{ProfanityFilter, TranscriptFilter};
+use crate::itn::normalize_transcript;
+use crate::langid::DetectedLanguage;
+use crate::model::{EngineFactory, ExecutionProvider, Model, UnknownModelError};
+use crate::postprocess::{
+    DictionaryCorrector, DictionaryStatus, TranscriptPostProcessor, TranscriptionContext,
+};
+use crate::punctuation::punctuate_transcript;
+use crate::stats::SpeechStats;
+use crate::tempfiles;
+use crate::validation::{validate_wav, validate_wav_file};
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io::Write;
+use std::ops::AddAssign;
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
-use tempfile::NamedTempFile;
+use std::time::{Duration, Instant};
 
+/// High-pass cutoff of the second pass when the request had none, in Hz
+const SECOND_PASS_HIGHPASS_HZ: f32 = 80.0;
+
+/// How multi-channel audio is turned into transcripts.
+///
+/// Mono input is transcribed as is whatever the mode.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelMode {
+    /// Average all channels into one (default)
+    #[default]
+    Downmix,
+    /// Transcribe every channel on its own
+    Separate,
+    /// Transcribe only the first channel
+    LeftOnly,
+    /// Transcribe only the second channel
+    RightOnly,
+}
+
+impl FromStr for ChannelMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().replace('-', "_").as_str() {
+            "downmix" => Ok(Self::Downmix),
+            "separate" => Ok(Self::Separate),
+            "left_only" => Ok(Self::LeftOnly),
+            "right_only" => Ok(Self::RightOnly),
+            other => Err(anyhow::anyhow!(
+                "Unknown channel mode '{}', expected 'downmix', 'separate', 'left_only' or 'right_only'",
+                other
+            )),
+        }
+    }
+}
+
+/// Per-request transcription options.
+#[derive(Debug, Clone)]
+pub struct TranscriptionOptions {
+    /// Apply the custom dictionary (when one is configured)
+    pub use_dictionary: bool,
+    /// Language of the audio as a code like `fr` or `fr-CA`. Selects that
+    /// language's dictionary; `None`, or a language without one, uses the
+    /// default dictionary.
+    pub language: Option<String>,
+    /// Split the audio at long pauses and report each utterance separately
+    pub segment_on_silence: bool,
+    /// Profanity handling applied after dictionary correction
+    pub profanity_filter: ProfanityFilter,
+    /// Replace matches of the configured redaction patterns
+    pub redact: bool,
+    /// Add sentence punctuation and capitalization (after dictionary correction)
+    pub punctuate: bool,
+    /// Rewrite spoken numbers, times, dates and amounts in written form
+    pub itn: bool,
+    /// How stereo and multi-channel audio is handled
+    pub channel_mode: ChannelMode,
+    /// High-pass cutoff in Hz applied before transcription (None = off)
+    pub highpass_hz: Option<f32>,
+    /// Spectral-gate noise reduction before transcription
+    pub denoise: bool,
+    /// Scale the audio so its peak reaches -1 dBFS, after the other
+    /// preprocessing
+    pub normalize: bool,
+    /// Measure speaking rate and levels ([`Transcript::stats`])
+    pub include_stats: bool,
+    /// Return the per-stage timings to the client. [`Transcript::timing`] is
+    /// always measured; this only tells front ends to pass it on.
+    pub include_timing: bool,
+    /// Name of the model to transcribe with (`None` = default model)
+    pub model: Option<String>,
+    /// Longest audio accepted, in seconds (`None` = no limit)
+    pub max_audio_secs: Option<f32>,
+    /// Phrases reported as [`Transcript::command`] (empty = no spotting)
+    pub commands: Vec<VoiceCommand>,
+    /// Similarity a transcript needs with a phrase to count as its command
+    pub command_threshold: f32,
+    /// Settings of the engine's decoding search
+    pub decoding: DecodingOptions,
+    /// Keep the audio the engine was given and its raw output in
+    /// [`Transcript::debug`]. Costs a copy of the samples.
+    pub capture_debug: bool,
+    /// Transcribe a second time with heavier preprocessing (and
+    /// [`ServerConfig::two_pass_model`], when set) when the first pass's
+    /// confidence is below [`ServerConfig::two_pass_confidence`], and keep
+    /// the transcript with the higher confidence
+    pub two_pass: bool,
+}
+
+impl Default for TranscriptionOptions {
+    fn default() -> Self {
+        Self {
+            use_dictionary: true,
+            language: None,
+            segment_on_silence: false,
+            profanity_filter: ProfanityFilter::default(),
+            redact: false,
+            punctuate: false,
+            itn: false,
+            channel_mode: ChannelMode::default(),
+            highpass_hz: None,
+            denoise: false,
+            normalize: false,
+            include_stats: false,
+            include_timing: false,
+            model: None,
+            max_audio_secs: ServerConfig::default().max_audio_duration(),
+            commands: Vec::new(),
+            command_threshold: ServerConfig::default().command_threshold,
+            decoding: DecodingOptions::default(),
+            capture_debug: false,
+            two_pass: false,
+        }
+    }
+}
+
+impl TranscriptionOptions {
+    /// Options matching the server-wide defaults from the configuration.
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            use_dictionary: true,
+            language: None,
+            segment_on_silence: config.segment_on_silence,
+            profanity_filter: config.profanity_filter,
+            redact: !config.redact_patterns.is_empty(),
+            punctuate: config.punctuate,
+            itn: config.itn,
+            channel_mode: ChannelMode::default(),
+            highpass_hz: config.highpass_hz,
+            denoise: config.denoise,
+            normalize: false,
+            include_stats: false,
+            include_timing: false,
+            model: None,
+            max_audio_secs: config.max_audio_duration(),
+            commands: config.commands.clone(),
+            command_threshold: config.command_threshold,
+            decoding: config.decoding_options(),
+            capture_debug: false,
+            two_pass: false,
+        }
+    }
+}
+
+/// A portion of the transcript bounded by pauses in the audio.
+#[derive(Debug, Clone, Default)]
+pub struct TranscriptSegment {
+    /// Start time of the segment in seconds
+    pub start: f32,
+    /// End time of the segment in seconds
+    pub end: f32,
+    /// Transcribed text for this segment
+    pub text: String,
+    /// Source channel (only set in [`ChannelMode::Separate`])
+    pub channel: Option<usize>,
+}
+
+/// Transcript of a single channel in [`ChannelMode::Separate`].
+#[derive(Debug, Clone, Default)]
+pub struct ChannelTranscript {
+    /// Zero-based channel index (0 = left, 1 = right)
+    pub channel: usize,
+    /// Transcribed text for this channel
+    pub text: String,
+    /// Pause-delimited segments of this channel (segmentation only)
+    pub segments: Vec<TranscriptSegment>,
+    /// Dictionary corrections, with offsets into this channel's text
+    pub corrections: Vec<Correction>,
+    /// Recognized words of this channel, see [`Transcript::words`]
+    pub words: Vec<TranscriptWord>,
+}
+
+/// A recognized word with its timing and confidence, built from the tokens
+/// the engine decoded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranscriptWord {
+    /// Word as recognized, punctuation attached, after dictionary correction
+    pub text: String,
+    /// Start time in seconds
+    pub start: f32,
+    /// End time in seconds
+    pub end: f32,
+    /// Sum of the log-probabilities of the word's tokens (0 = certain)
+    pub logprob: f32,
+    /// Source channel (only set in [`ChannelMode::Separate`])
+    pub channel: Option<usize>,
+}
+
+/// Transcribed text together with audio and timing metadata.
+#[derive(Debug, Clone, Default)]
+pub struct Transcript {
+    /// Transcribed text (after dictionary corrections, if any)
+    pub text: String,
+    /// Pause-delimited segments (empty unless segmentation was requested)
+    pub segments: Vec<TranscriptSegment>,
+    /// Words replaced by the dictionary; offsets point into the text as it was
+    /// right after dictionary correction (later number normalization or
+    /// profanity removal can shift them)
+    pub corrections: Vec<Correction>,
+    /// Per-channel results in [`ChannelMode::Separate`]; `text` and `segments`
+    /// then hold a merged view (chronological when segmented, one line per
+    /// channel otherwise) and `corrections` is empty
+    pub channels: Vec<ChannelTranscript>,
+    /// Duration of the decoded audio in seconds (16 kHz samples / 16000)
+    pub audio_duration_secs: f32,
+    /// Time spent in the transcription engine, in milliseconds
+    pub processing_time_ms: u64,
+    /// Time spent in dictionary post-processing, in milliseconds
+    pub dictionary_time_ms: u64,
+    /// Speaking rate and levels (only when requested); words are counted
+    /// after dictionary correction, before number normalization
+    pub stats: Option<SpeechStats>,
+    /// Time spent in each stage of the pipeline
+    pub timing: StageTimings,
+    /// The audio as received, before resampling
+    pub input: InputAudio,
+    /// Recognized words in time order. They follow dictionary correction but
+    /// not the later rewriting (custom post-processors, numbers,
+    /// punctuation), and are left empty when profanity filtering or redaction
+    /// applies, so no filtered word shows up here
+    pub words: Vec<TranscriptWord>,
+    /// Voice command the whole text matched, spotted before the text is
+    /// rewritten by post-processing. The text is returned either way.
+    pub command: Option<DetectedCommand>,
+    /// Language the transcript was identified as, among
+    /// [`ServerConfig::detect_languages`]. Reported even when the request
+    /// named a language, which then takes precedence for the dictionary.
+    pub detected_language: Option<DetectedLanguage>,
+    /// Intermediate audio and raw engine output, only with
+    /// [`TranscriptionOptions::capture_debug`]
+    pub debug: Option<DebugCapture>,
+    /// Geometric mean of the probabilities of the tokens the engine decoded,
+    /// 0 to 1 (`None` when it decoded none)
+    pub confidence: Option<f32>,
+    /// Both passes of a [`TranscriptionOptions::two_pass`] request
+    pub two_pass: Option<TwoPassOutcome>,
+}
+
+/// How a two-pass request went. `timing` and `processing_time_ms` of the
+/// transcript cover both passes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TwoPassOutcome {
+    /// Confidence of the first pass
+    pub first_confidence: Option<f32>,
+    /// Confidence of the second pass; `None` when there was none because the
+    /// first reached [`ServerConfig::two_pass_confidence`]
+    pub second_confidence: Option<f32>,
+    /// Whether the returned transcript is the second pass's
+    pub second_won: bool,
+}
+
+/// What the engine was given and what it returned, for investigating a bad
+/// transcription. Channels are in the order they were transcribed.
+#[derive(Debug, Clone, Default)]
+pub struct DebugCapture {
+    /// Rate of the samples below, the engine's
+    pub sample_rate: u32,
+    /// Decoded samples of each channel, before preprocessing
+    pub resampled: Vec<Vec<f32>>,
+    /// The samples passed to the engine: preprocessed, with the silences
+    /// between segments left out when segmenting
+    pub speech: Vec<Vec<f32>>,
+    /// Text the engine returned for each segment of each channel, before
+    /// dictionary correction and post-processing
+    pub raw_text: Vec<Vec<String>>,
+}
+
+/// Properties of the decoded audio as received, before resampling.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InputAudio {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    /// Duration in seconds
+    pub duration_secs: f32,
+    /// Largest absolute sample value, 0.0 - 1.0 of full scale (after
+    /// downmixing, when the channels are mixed)
+    pub peak: f32,
+}
+
+impl fmt::Display for InputAudio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} Hz, {} channel(s), {}-bit, {:.0} ms, peak {:.1} dBFS",
+            self.sample_rate,
+            self.channels,
+            self.bits_per_sample,
+            self.duration_secs * 1000.0,
+            crate::dsp::gain_to_db(self.peak)
+        )
+    }
+}
+
+/// Wall-clock time spent in each stage of a transcription.
+///
+/// The stages run one after the other, so they add up to about `total`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StageTimings {
+    /// Payload validation and WAV decoding
+    pub decode: Duration,
+    /// Resampling to 16 kHz
+    pub resample: Duration,
+    /// High-pass filtering and noise reduction (when enabled)
+    pub preprocess: Duration,
+    /// Silence detection (segmentation only)
+    pub vad: Duration,
+    /// Engine inference, including the wait for a free engine
+    pub inference: Duration,
+    /// Dictionary correction
+    pub dictionary: Duration,
+    /// Custom post-processors, number normalization, punctuation and filtering
+    pub post_process: Duration,
+    /// Rendering the response; left at zero here and filled in by the
+    /// front end that renders it
+    pub serialization: Duration,
+    /// The whole request
+    pub total: Duration,
+}
+
+impl StageTimings {
+    /// Each stage with its name, in pipeline order (`total` excluded).
+    pub fn stages(&self) -> [(&'static str, Duration); 8] {
+        [
+            ("decode", self.decode),
+            ("resample", self.resample),
+            ("preprocess", self.preprocess),
+            ("vad", self.vad),
+            ("inference", self.inference),
+            ("dictionary", self.dictionary),
+            ("post_process", self.post_process),
+            ("serialization", self.serialization),
+        ]
+    }
+}
+
+impl AddAssign for StageTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.decode += other.decode;
+        self.resample += other.resample;
+        self.preprocess += other.preprocess;
+        self.vad += other.vad;
+        self.inference += other.inference;
+        self.dictionary += other.dictionary;
+        self.post_process += other.post_process;
+        self.serialization += other.serialization;
+        self.total += other.total;
+    }
+}
+
+impl fmt::Display for StageTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, elapsed) in self.stages() {
+            write!(f, "{} {:.1}ms, ", name, elapsed.as_secs_f64() * 1000.0)?;
+        }
+        write!(f, "total {:.1}ms", self.total.as_secs_f64() * 1000.0)
+    }
+}
+
+impl Transcript {
+    /// Audio duration divided by engine processing time (e.g. 5.0 = five times faster than realtime).
+    ///
+    /// Returns 0.0 when no processing time was recorded.
+    pub fn realtime_factor(&self) -> f32 {
+        if self.processing_time_ms == 0 {
+            return 0.0;
+        }
+        self.audio_duration_secs / (self.processing_time_ms as f32 / 1000.0)
+    }
+
+    /// Rewrite each segment's text with `rewrite` and rebuild the full text
+    /// from the segments; without segments the full text is rewritten as is.
+    /// Per-channel transcripts are rewritten the same way and merged again.
+    pub(crate) fn rewrite_text(&mut self, rewrite: impl Fn(&str) -> String) {
+        // Can't fail, the closure always returns Ok
+        let _ = self.try_rewrite_text(|text, _| Ok(rewrite(text)));
+    }
+
+    /// Fallible [`Self::rewrite_text`]; `rewrite` also gets the channel of
+    /// the text in separate channel mode. The first error is returned and
+    /// leaves the transcript partly rewritten.
+    pub(crate) fn try_rewrite_text(
+        &mut self,
+        rewrite: impl Fn(&str, Option<usize>) -> Result<String>,
+    ) -> Result<()> {
+        if !self.channels.is_empty() {
+            for channel in &mut self.channels {
+                let index = channel.channel;
+                rewrite_parts(&mut channel.text, &mut channel.segments, &|text| {
+                    rewrite(text, Some(index))
+                })?;
+            }
+            self.merge_channels();
+            return Ok(());
+        }
+        rewrite_parts(&mut self.text, &mut self.segments, &|text| {
+            rewrite(text, None)
+        })
+    }
+
+    /// Rebuild `text` and `segments` from the per-channel transcripts:
+    /// segments from all channels in start-time order when available,
+    /// otherwise one line of text per channel.
+    pub(crate) fn merge_channels(&mut self) {
+        let mut segments: Vec<TranscriptSegment> = self
+            .channels
+            .iter()
+            .flat_map(|channel| channel.segments.iter().cloned())
+            .collect();
+        segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+        self.words = self
+            .channels
+            .iter()
+            .flat_map(|channel| channel.words.iter().cloned())
+            .collect();
+        self.words.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+        self.text = if segments.is_empty() {
+            self.channels
+                .iter()
+                .map(|channel| channel.text.as_str())
+                .collect::<Vec<_>>()
+                .join("\n")
+        } else {
+            join_segments(&segments)
+        };
+        self.segments = segments;
+    }
+}
+
+fn rewrite_parts(
+    text: &mut String,
+    segments: &mut [TranscriptSegment],
+    rewrite: &impl Fn(&str) -> Result<String>,
+) -> Result<()> {
+    if segments.is_empty() {
+        *text = rewrite(text)?;
+        return Ok(());
+    }
+
+    for segment in segments.iter_mut() {
+        segment.text = rewrite(&segment.text)?;
+    }
+    *text = join_segments(segments);
+    Ok(())
+}
+
+/// Space-separated text of the non-empty segments.
+fn join_segments(segments: &[TranscriptSegment]) -> String {
+    segments
+        .iter()
+        .map(|segment| segment.text.as_str())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Clone)]
 pub struct TranscriptionService {
-    model: Arc<Model>,
+    /// Configured models, the default one first
+    models: Arc<Vec<Arc<Model>>>,
     dictionary: Option<Arc<Dictionary>>,
+    corrector: Option<DictionaryCorrector>,
+    /// Correctors of the language-scoped dictionaries, by lowercase language code
+    language_correctors: Arc<BTreeMap<String, DictionaryCorrector>>,
+    /// Custom text post-processors, run in order after dictionary correction
+    post_processors: Arc<Vec<Box<dyn TranscriptPostProcessor>>>,
     config: Arc<ServerConfig>,
-    engine_loaded: Arc<std::sync::atomic::AtomicBool>,
+    filter: TranscriptFilter,
 }
 
 impl TranscriptionService {
+    /// `model` is the default model; the other named models of `config` are
+    /// loaded on first use. `dictionary` is used for requests without a
+    /// language-scoped one from `config.dictionaries`. `post_processors`
+    /// rewrite every transcript in order, see [`crate::postprocess`] for
+    /// where they run.
     pub fn new(
         model: Arc<Model>,
         dictionary: Option<Arc<Dictionary>>,
         config: Arc<ServerConfig>,
-    ) -> Result<Self> {
+        post_processors: Vec<Box<dyn TranscriptPostProcessor>>,
+    ) -> Result<Self, TranscriptionError> {
+        Self::assemble(model, dictionary, config, post_processors, true)
+    }
+
+    /// Build a service from explicit settings, without going through
+    /// environment variables, see [`TranscriptionServiceBuilder`].
+    pub fn builder() -> TranscriptionServiceBuilder {
+        TranscriptionServiceBuilder::default()
+    }
+
+    fn assemble(
+        model: Arc<Model>,
+        dictionary: Option<Arc<Dictionary>>,
+        config: Arc<ServerConfig>,
+        post_processors: Vec<Box<dyn TranscriptPostProcessor>>,
+        preload: bool,
+    ) -> Result<Self, TranscriptionError> {
+        let filter = TranscriptFilter::from_config(&config)
+            .map_err(|e| TranscriptionError::Config(e.into()))?;
+        let others = config
+            .models
+            .iter()
+            .filter(|spec| spec.name != model.name())
+            .map(|spec| Arc::new(Model::from_spec((*config).clone(), spec.clone())));
+        let models = std::iter::once(Arc::clone(&model)).chain(others).collect();
+        let corrector = dictionary.as_ref().map(|dictionary| {
+            DictionaryCorrector::new(Arc::clone(dictionary), Arc::clone(&config))
+        });
+        let language_correctors: BTreeMap<_, _> = config
+            .dictionaries
+            .iter()
+            .filter_map(|(language, words)| {
+                let language = language_fallbacks(language).next()?;
+                let dictionary = Arc::new(Dictionary::new(words.clone()));
+                let corrector = DictionaryCorrector::for_language(dictionary, &config, &language);
+                Some((language, corrector))
+            })
+            .collect();
+        if corrector
+            .iter()
+            .chain(language_correctors.values())
+            .any(|corrector| corrector.cc_rules_path().is_none())
+            && !config.allow_missing_cc_rules
+        {
+            let reason = config
+                .get_cc_rules_path()
+                .err()
+                .map_or_else(String::new, |e| e.to_string());
+            return Err(TranscriptionError::Config(
+                format!(
+                    "A dictionary is configured but the cc-rules directory can't be used: {}. \
+                     Allow running without dictionary correction with allow_missing_cc_rules.",
+                    reason
+                )
+                .into(),
+            ));
+        }
         let service = Self {
-            model,
+            models: Arc::new(models),
             dictionary,
+            corrector,
+            language_correctors: Arc::new(language_correctors),
+            post_processors: Arc::new(post_processors),
             config,
-            engine_loaded: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            filter,
         };
 
-        // Preload engine on initialization
-        service.ensure_engine_loaded()?;
+        // Preload the default engine on initialization
+        if preload {
+            preload_engine(&model)?;
+        }
 
         Ok(service)
     }
 
-    fn ensure_engine_loaded(&self) -> Result<()> {
-        if !self
-            .engine_loaded
-            .load(std::sync::atomic::Ordering::Relaxed)
-        {
-            preload_engine(&self.model)?;
-            self.engine_loaded
-                .store(true, std::sync::atomic::Ordering::Relaxed);
-        }
-        Ok(())
+    /// Run a short silent inference so the first real request is not slowed
+    /// down by lazy engine initialization.
+    pub fn warm_up(&self) -> Result<(), TranscriptionError> {
+        warm_up_engine(self.get_model())
     }
 
-    pub fn transcribe_audio_bytes(&self, audio_data: &[u8]) -> Result<String> {
-        // Ensure engine is loaded
-        self.ensure_engine_loaded()?;
+    /// Execution provider the default engine ended up on, which may be the
+    /// CPU even if a GPU provider was configured.
+    pub fn execution_provider(&self) -> Option<ExecutionProvider> {
+        self.get_model().execution_provider()
+    }
+
+    /// Engine panics caught since startup, across all models.
+    pub fn engine_panics(&self) -> u64 {
+        engine_panic_count()
+    }
+
+    /// The model named `name`, or the default model for `None`.
+    pub fn resolve_model(&self, name: Option<&str>) -> Result<&Arc<Model>, UnknownModelError> {
+        let Some(name) = name else {
+            return Ok(self.get_model());
+        };
+        self.models
+            .iter()
+            .find(|model| model.name() == name)
+            .ok_or_else(|| UnknownModelError {
+                name: name.to_string(),
+                available: self
+                    .models
+                    .iter()
+                    .map(|model| model.name())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })
+    }
+
+    pub fn transcribe_audio_bytes(&self, audio_data: &[u8]) -> Result<String, TranscriptionError> {
+        self.transcribe_audio_bytes_detailed(audio_data)
+            .map(|transcript| transcript.text)
+    }
+
+    /// Transcribe WAV bytes and return the text with duration and timing metadata.
+    pub fn transcribe_audio_bytes_detailed(
+        &self,
+        audio_data: &[u8],
+    ) -> Result<Transcript, TranscriptionError> {
+        self.transcribe_audio_bytes_with_options(
+            audio_data,
+            &TranscriptionOptions::from_config(&self.config),
+        )
+    }
+
+    /// Transcribe WAV bytes using explicit per-request options.
+    pub fn transcribe_audio_bytes_with_options(
+        &self,
+        audio_data: &[u8],
+        options: &TranscriptionOptions,
+    ) -> Result<Transcript, TranscriptionError> {
+        let started = Instant::now();
+        let model = self.resolve_model(options.model.as_deref())?;
+
+        // Reject malformed payloads before touching the engine or the disk
+        validate_wav(
+            audio_data,
+            self.config.min_audio_duration(),
+            options.max_audio_secs,
+        )?;
 
         // Write audio data to temporary file
-        let mut temp_file = NamedTempFile::new()?;
+        let mut temp_file = tempfiles::create(&self.config.temp_dir)?;
         temp_file.write_all(audio_data)?;
         temp_file.flush()?;
         let temp_path = temp_file.path();
+        let prepared = started.elapsed();
 
         // Transcribe
-        let result = transcribe_audio(
-            temp_path,
-            &self.model,
-            self.dictionary.as_deref(),
-            &self.config,
-        )?;
+        let (mut result, model) = self.transcribe_passes(temp_path, model, options)?;
+        result.timing.decode += prepared;
+        self.post_process(&mut result, model, options)?;
+        result.timing.total = started.elapsed();
 
         Ok(result)
     }
 
-    pub fn transcribe_audio_file(&self, audio_path: &Path) -> Result<String> {
-        // Ensure engine is loaded
-        self.ensure_engine_loaded()?;
+    pub fn transcribe_audio_file(&self, audio_path: &Path) -> Result<String, TranscriptionError> {
+        self.transcribe_audio_file_detailed(audio_path)
+            .map(|transcript| transcript.text)
+    }
+
+    /// Transcribe a WAV file and return the text with duration and timing metadata.
+    pub fn transcribe_audio_file_detailed(
+        &self,
+        audio_path: &Path,
+    ) -> Result<Transcript, TranscriptionError> {
+        self.transcribe_audio_file_with_options(
+            audio_path,
+            &TranscriptionOptions::from_config(&self.config),
+        )
+    }
+
+    /// Transcribe a WAV file using explicit per-request options.
+    pub fn transcribe_audio_file_with_options(
+        &self,
+        audio_path: &Path,
+        options: &TranscriptionOptions,
+    ) -> Result<Transcript, TranscriptionError> {
+        let started = Instant::now();
+        let model = self.resolve_model(options.model.as_deref())?;
+        validate_wav_file(
+            audio_path,
+            self.config.min_audio_duration(),
+            options.max_audio_secs,
+        )?;
+        let prepared = started.elapsed();
 
         // Transcribe
-        let result = transcribe_audio(
+        let (mut result, model) = self.transcribe_passes(audio_path, model, options)?;
+        result.timing.decode += prepared;
+        self.post_process(&mut result, model, options)?;
+        result.timing.total = started.elapsed();
+
+        Ok(result)
+    }
+
+    /// Transcribe the file, and again with [`second_pass_options`] when a
+    /// two-pass request's first pass is below the confidence threshold.
+    /// Returns the transcript with the higher confidence and the model that
+    /// produced it. There is never more than one retry.
+    fn transcribe_passes<'s>(
+        &'s self,
+        audio_path: &Path,
+        model: &'s Arc<Model>,
+        options: &TranscriptionOptions,
+    ) -> Result<(Transcript, &'s Arc<Model>), TranscriptionError> {
+        let first = transcribe_audio(
+            audio_path,
+            model,
+            |detected| self.request_corrector(options, detected),
+            &self.config,
+            options,
+        )?;
+        if !options.two_pass {
+            return Ok((first, model));
+        }
+        let mut outcome = TwoPassOutcome {
+            first_confidence: first.confidence,
+            ..Default::default()
+        };
+        // Nothing decoded leaves nothing to compare; a confident first pass
+        // isn't worth the cost of another
+        let retry = first
+            .confidence
+            .is_some_and(|confidence| confidence < self.config.two_pass_confidence);
+        let second_options = match second_pass_options(options, &self.config) {
+            Some(second_options) if retry => second_options,
+            _ => {
+                let first = Transcript {
+                    two_pass: Some(outcome),
+                    ..first
+                };
+                return Ok((first, model));
+            }
+        };
+        let second_model = self.resolve_model(second_options.model.as_deref())?;
+        let second = transcribe_audio(
             audio_path,
-            &self.model,
-            self.dictionary.as_deref(),
+            second_model,
+            |detected| self.request_corrector(&second_options, detected),
             &self.config,
+            &second_options,
         )?;
+        outcome.second_confidence = second.confidence;
+        outcome.second_won = second.confidence > first.confidence;
+        log::debug!(
+            "Second pass confidence {:?} against {:?}",
+            outcome.second_confidence,
+            outcome.first_confidence
+        );
+        let (mut winner, loser, model) = if outcome.second_won {
+            (second, first, second_model)
+        } else {
+            (first, second, model)
+        };
+        winner.timing += loser.timing;
+        winner.processing_time_ms += loser.processing_time_ms;
+        winner.dictionary_time_ms += loser.dictionary_time_ms;
+        winner.two_pass = Some(outcome);
+        Ok((winner, model))
+    }
 
-        Ok(result)
+    /// The dictionary to apply for a request, if any.
+    fn request_dictionary(
+        &self,
+        options: &TranscriptionOptions,
+        detected: Option<&str>,
+    ) -> Option<&Dictionary> {
+        self.request_corrector(options, detected)
+            .map(|corrector| corrector.dictionary().as_ref())
+    }
+
+    /// The dictionary corrector to apply for a request, if any: the one of
+    /// the language the request names, or else of the `detected` one.
+    fn request_corrector(
+        &self,
+        options: &TranscriptionOptions,
+        detected: Option<&str>,
+    ) -> Option<&DictionaryCorrector> {
+        if !options.use_dictionary {
+            return None;
+        }
+        self.corrector_for(options.language.as_deref().or(detected))
+    }
+
+    /// The corrector of `language`'s dictionary, or of the default one when
+    /// the language has none (`fr-CA` falls back to `fr` first).
+    pub fn corrector_for(&self, language: Option<&str>) -> Option<&DictionaryCorrector> {
+        language
+            .into_iter()
+            .flat_map(language_fallbacks)
+            .find_map(|code| self.language_correctors.get(&code))
+            .or(self.corrector.as_ref())
+    }
+
+    /// The dictionary applied to requests in `language`, see [`Self::corrector_for`].
+    pub fn dictionary_for(&self, language: Option<&str>) -> Option<&Arc<Dictionary>> {
+        self.corrector_for(language)
+            .map(DictionaryCorrector::dictionary)
+    }
+
+    /// Languages with a dictionary of their own.
+    pub fn dictionary_languages(&self) -> impl Iterator<Item = &str> {
+        self.language_correctors.keys().map(String::as_str)
+    }
+
+    /// Post-processing that runs after dictionary correction: the custom
+    /// post-processors, number normalization, then punctuation so corrected
+    /// proper nouns get capitalized, then filtering.
+    fn post_process(
+        &self,
+        transcript: &mut Transcript,
+        model: &Model,
+        options: &TranscriptionOptions,
+    ) -> Result<(), TranscriptionError> {
+        let started = Instant::now();
+        transcript.command = detect_command(
+            &transcript.text,
+            &options.commands,
+            options.command_threshold,
+        );
+        let model_name = model.name();
+        for processor in self.post_processors.iter() {
+            transcript
+                .try_rewrite_text(|text, channel| {
+                    let ctx = TranscriptionContext {
+                        options,
+                        model: &model_name,
+                        channel,
+                    };
+                    processor.process(text.to_string(), &ctx)
+                })
+                .map_err(|e| TranscriptionError::PostProcess {
+                    name: processor.name().to_string(),
+                    source: e.into(),
+                })?;
+        }
+        if options.itn {
+            normalize_transcript(transcript, &self.config.itn_language);
+        }
+        if options.punctuate {
+            let detected = transcript
+                .detected_language
+                .as_ref()
+                .map(|detected| detected.language.as_str());
+            let proper_nouns = self
+                .request_dictionary(options, detected)
+                .map(|dictionary| dictionary.get())
+                .unwrap_or_default();
+            punctuate_transcript(transcript, &proper_nouns);
+        }
+        self.filter
+            .apply(transcript, options.profanity_filter, options.redact);
+        transcript.timing.post_process = started.elapsed();
+        Ok(())
     }
 
+    /// The default model.
     pub fn get_model(&self) -> &Arc<Model> {
-        &self.model
+        &self.models[0]
+    }
+
+    /// Every configured model, the default one first.
+    pub fn get_models(&self) -> &[Arc<Model>] {
+        &self.models
     }
 
     pub fn get_dictionary(&self) -> Option<&Arc<Dictionary>> {
         self.dictionary.as_ref()
     }
 
+    /// Whether transcripts get dictionary corrections: off without a
+    /// dictionary, degraded when the cc-rules weren't found.
+    pub fn dictionary_status(&self) -> DictionaryStatus {
+        let mut correctors = self
+            .corrector
+            .iter()
+            .chain(self.language_correctors.values())
+            .peekable();
+        if correctors.peek().is_none() {
+            DictionaryStatus::Off
+        } else if correctors.any(|corrector| corrector.cc_rules_path().is_none()) {
+            DictionaryStatus::Degraded
+        } else {
+            DictionaryStatus::Active
+        }
+    }
+
     pub fn get_config(&self) -> &Arc<ServerConfig> {
         &self.config
     }
 }
+
+/// Builds a [`TranscriptionService`] for embedding in another application.
+///
+/// Only what is set on the builder is used: no `MURMURE_*` variable or
+/// config file is read, and an explicit model or cc-rules path is never
+/// replaced by a search of the `resources/` directories. Settings without
+/// a dedicated method come from [`Self::config`].
+///
+/// ```rust,no_run
+/// use murmure_stt::TranscriptionService;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let service = TranscriptionService::builder()
+///     .model_path("/opt/models/parakeet-tdt-0.6b-v3-int8")
+///     .cc_rules_path("/opt/models/cc-rules")
+///     .dictionary(vec!["Kubernetes".to_string(), "Murmure".to_string()])
+///     .preload(true)
+///     .build()?;
+/// let text = service.transcribe_audio_file(std::path::Path::new("meeting.wav"))?;
+/// println!("{text}");
+/// # Ok(())
+/// # }
+/// ```
+pub struct TranscriptionServiceBuilder {
+    config: ServerConfig,
+    post_processors: Vec<Box<dyn TranscriptPostProcessor>>,
+    preload: bool,
+    engine: Option<EngineFactory>,
+}
+
+impl Default for TranscriptionServiceBuilder {
+    fn default() -> Self {
+        Self {
+            config: ServerConfig::default(),
+            post_processors: Vec::new(),
+            preload: true,
+            engine: None,
+        }
+    }
+}
+
+impl TranscriptionServiceBuilder {
+    /// Base settings (segmentation, filtering, limits, ...). This replaces
+    /// everything set so far, so call it before the other methods.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Directory of the default model.
+    pub fn model_path(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.config.model_path = Some(path.into());
+        self
+    }
+
+    /// Directory of the phonetic rules used by dictionary correction.
+    pub fn cc_rules_path(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.config.cc_rules_path = Some(path.into());
+        self
+    }
+
+    /// Words transcripts are corrected towards. Needs the cc-rules directory.
+    pub fn dictionary(mut self, words: Vec<String>) -> Self {
+        self.config.dictionary = words;
+        self
+    }
+
+    /// Words for requests in `language` (`fr`, `en`, ...), used instead of
+    /// [`Self::dictionary`] for them. A `<cc-rules>/<language>` directory is
+    /// used for their rules when it exists.
+    pub fn language_dictionary(mut self, language: impl Into<String>, words: Vec<String>) -> Self {
+        self.config.dictionaries.insert(language.into(), words);
+        self
+    }
+
+    /// Build the service even when a dictionary is set but the cc-rules
+    /// directory can't be found; transcripts are then left uncorrected.
+    pub fn allow_missing_cc_rules(mut self, allow: bool) -> Self {
+        self.config.allow_missing_cc_rules = allow;
+        self
+    }
+
+    /// Load the default engine in [`Self::build`] (the default) rather than
+    /// on the first request.
+    pub fn preload(mut self, preload: bool) -> Self {
+        self.preload = preload;
+        self
+    }
+
+    /// Run the default model on engines made by `factory` instead of
+    /// loading Parakeet, e.g. a [`crate::mock::MockEngine`]; no model
+    /// directory is needed then. The other named models still load from disk.
+    pub fn engine<E: SpeechEngine + 'static>(
+        mut self,
+        factory: impl Fn() -> E + Send + Sync + 'static,
+    ) -> Self {
+        self.engine = Some(Arc::new(move || Box::new(factory())));
+        self
+    }
+
+    /// Add a text post-processor, run after the ones added before it.
+    pub fn post_processor(mut self, processor: impl TranscriptPostProcessor + 'static) -> Self {
+        self.post_processors.push(Box::new(processor));
+        self
+    }
+
+    /// Create the service. Fails when the model directory doesn't exist,
+    /// with the path that was given, even if the engine isn't preloaded.
+    pub fn build(self) -> Result<TranscriptionService, TranscriptionError> {
+        let config = Arc::new(self.config);
+        let model = Model::new((*config).clone());
+        let model = Arc::new(match self.engine {
+            Some(factory) => model.with_engine_factory(factory),
+            None => {
+                model.get_model_path()?;
+                model
+            }
+        });
+        let dictionary = (!config.dictionary.is_empty())
+            .then(|| Arc::new(Dictionary::new(config.dictionary.clone())));
+        TranscriptionService::assemble(
+            model,
+            dictionary,
+            config,
+            self.post_processors,
+            self.preload,
+        )
+    }
+}
+
+/// Options of a two-pass request's second pass: high-pass filtered,
+/// denoised and normalized, with the configured second-pass model. `None`
+/// when that is what the first pass already did.
+fn second_pass_options(
+    options: &TranscriptionOptions,
+    config: &ServerConfig,
+) -> Option<TranscriptionOptions> {
+    let model = config.two_pass_model.clone().or(options.model.clone());
+    if options.denoise
+        && options.normalize
+        && options.highpass_hz.is_some()
+        && model == options.model
+    {
+        return None;
+    }
+    Some(TranscriptionOptions {
+        highpass_hz: options.highpass_hz.or(Some(SECOND_PASS_HIGHPASS_HZ)),
+        denoise: true,
+        normalize: true,
+        model,
+        two_pass: false,
+        ..options.clone()
+    })
+}
+
+/// Async counterparts of the blocking methods.
+///
+/// The work runs on worker threads owned by the library, so these can be
+/// awaited from any executor without stalling it; the service is cloned
+/// (cheap, everything is shared) to move it onto the worker.
+#[cfg(feature = "async")]
+impl TranscriptionService {
+    /// Async version of [`Self::transcribe_audio_bytes_with_options`].
+    pub async fn transcribe_audio_bytes_async(
+        &self,
+        audio_data: Vec<u8>,
+        options: TranscriptionOptions,
+    ) -> Result<Transcript, TranscriptionError> {
+        let service = self.clone();
+        crate::executor::run_blocking(move || {
+            service.transcribe_audio_bytes_with_options(&audio_data, &options)
+        })
+        .await
+    }
+
+    /// Async version of [`Self::transcribe_audio_file_with_options`].
+    pub async fn transcribe_file_async(
+        &self,
+        audio_path: std::path::PathBuf,
+        options: TranscriptionOptions,
+    ) -> Result<Transcript, TranscriptionError> {
+        let service = self.clone();
+        crate::executor::run_blocking(move || {
+            service.transcribe_audio_file_with_options(&audio_path, &options)
+        })
+        .await
+    }
+}
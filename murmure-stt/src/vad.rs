@@ -0,0 +1,140 @@
+// Energy-based voice activity framing
+
+use crate::dsp::rms;
+use std::ops::Range;
+
+/// Frame length used for energy analysis, in milliseconds
+pub const FRAME_MS: usize = 20;
+
+/// Frames with an RMS below this level (~-40 dBFS) are considered silent
+pub const SILENCE_RMS_THRESHOLD: f32 = 0.01;
+
+/// RMS energy of consecutive `FRAME_MS` frames (the last frame may be shorter).
+pub fn frame_energies(samples: &[f32], sample_rate: usize) -> Vec<f32> {
+    let frame_len = frame_len(sample_rate);
+    samples.chunks(frame_len).map(rms).collect()
+}
+
+/// Split audio into contiguous ranges at silences lasting at least `min_silence_ms`.
+///
+/// Each cut is placed in the middle of a qualifying silence, so the returned
+/// ranges are ordered, never overlap, and together cover every sample of the
+/// input. Audio without a long enough pause yields a single range.
+pub fn split_on_silence(
+    samples: &[f32],
+    sample_rate: usize,
+    min_silence_ms: u64,
+) -> Vec<Range<usize>> {
+    let frame_len = frame_len(sample_rate);
+    let min_silent_frames = ((min_silence_ms as usize).div_ceil(FRAME_MS)).max(1);
+
+    let mut cuts = Vec::new();
+    let mut silence_start: Option<usize> = None;
+    let energies = frame_energies(samples, sample_rate);
+
+    for (idx, &energy) in energies.iter().enumerate() {
+        if energy < SILENCE_RMS_THRESHOLD {
+            silence_start.get_or_insert(idx);
+            continue;
+        }
+
+        if let Some(start) = silence_start.take() {
+            // Leading silence is kept with the first segment
+            if start > 0 && idx - start >= min_silent_frames {
+                cuts.push(((start + idx) / 2) * frame_len);
+            }
+        }
+    }
+
+    let mut ranges = Vec::with_capacity(cuts.len() + 1);
+    let mut begin = 0;
+    for cut in cuts {
+        ranges.push(begin..cut);
+        begin = cut;
+    }
+    ranges.push(begin..samples.len());
+    ranges
+}
+
+fn frame_len(sample_rate: usize) -> usize {
+    (sample_rate * FRAME_MS / 1000).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: usize = 16000;
+
+    fn tone(ms: usize) -> Vec<f32> {
+        vec![0.5; RATE * ms / 1000]
+    }
+
+    fn silence(ms: usize) -> Vec<f32> {
+        vec![0.0; RATE * ms / 1000]
+    }
+
+    fn audio(parts: &[Vec<f32>]) -> Vec<f32> {
+        parts.concat()
+    }
+
+    #[test]
+    fn frames_cover_the_input() {
+        let energies = frame_energies(&audio(&[tone(40), silence(30)]), RATE);
+        assert_eq!(energies.len(), 4);
+        assert!((energies[0] - 0.5).abs() < 1e-6);
+        assert_eq!(energies[3], 0.0);
+        assert!(frame_energies(&[], RATE).is_empty());
+    }
+
+    #[test]
+    fn cuts_in_the_middle_of_long_pauses() {
+        let samples = audio(&[tone(200), silence(400), tone(200)]);
+        let ranges = split_on_silence(&samples, RATE, 300);
+        // Silence spans frames 10..30, cut at frame 20
+        assert_eq!(ranges, vec![0..20 * 320, 20 * 320..samples.len()]);
+    }
+
+    #[test]
+    fn short_pauses_do_not_split() {
+        let samples = audio(&[tone(200), silence(200), tone(200)]);
+        assert_eq!(
+            split_on_silence(&samples, RATE, 300),
+            vec![0..samples.len()]
+        );
+    }
+
+    #[test]
+    fn edge_silences_stay_with_their_segment() {
+        let samples = audio(&[silence(500), tone(200), silence(500)]);
+        assert_eq!(
+            split_on_silence(&samples, RATE, 300),
+            vec![0..samples.len()]
+        );
+    }
+
+    #[test]
+    fn ranges_are_contiguous() {
+        let samples = audio(&[
+            tone(100),
+            silence(400),
+            tone(100),
+            silence(100),
+            tone(100),
+            silence(600),
+            tone(100),
+        ]);
+        let ranges = split_on_silence(&samples, RATE, 300);
+        assert_eq!(ranges.len(), 3);
+        assert_eq!(ranges[0].start, 0);
+        assert_eq!(ranges.last().unwrap().end, samples.len());
+        for pair in ranges.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+    }
+
+    #[test]
+    fn empty_audio_is_one_empty_range() {
+        assert_eq!(split_on_silence(&[], RATE, 300), vec![0..0]);
+    }
+}
@@ -0,0 +1,388 @@
+// Upfront validation of audio payloads
+//
+// Walks the RIFF chunks of a WAV payload (see wav.rs) before anything is
+// written to disk or decoded, so malformed uploads are rejected with a message
+// naming exactly what is wrong instead of a generic decoder error.
+
+use crate::error::TranscriptionError;
+use crate::wav::{self, read_u16, read_u32, WavError, WavLayout};
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::path::Path;
+use thiserror::Error;
+
+/// Why an audio payload was rejected.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum AudioValidationError {
+    #[error("payload is empty")]
+    Empty,
+    #[error("payload is not a RIFF/WAV file; first bytes were '{preview}'")]
+    NotWav { preview: String },
+    #[error("WAV header is truncated: {0}")]
+    TruncatedHeader(&'static str),
+    #[error("WAV file has no '{0}' chunk")]
+    MissingChunk(&'static str),
+    #[error("WAV '{chunk}' chunk is malformed: {reason}")]
+    MalformedChunk { chunk: String, reason: String },
+    #[error("WAV '{chunk}' chunk declares {declared} bytes but only {available} are present")]
+    TruncatedChunk {
+        chunk: String,
+        declared: usize,
+        available: usize,
+    },
+    #[error(
+        "WAV declares {declared_secs:.1} s but only {actual_secs:.1} s of data present \
+         ({sample_rate} Hz)"
+    )]
+    TruncatedData {
+        declared_secs: f32,
+        actual_secs: f32,
+        sample_rate: u32,
+    },
+    #[error("unsupported WAV format: {0}")]
+    UnsupportedFormat(String),
+    #[error("audio too short ({duration_ms} ms at {sample_rate} Hz), the minimum is {min_ms} ms")]
+    TooShort {
+        duration_ms: u64,
+        min_ms: u64,
+        sample_rate: u32,
+    },
+    #[error(
+        "audio is {duration_secs:.1} s long at {sample_rate} Hz, exceeding the {max_secs} s limit"
+    )]
+    TooLong {
+        duration_secs: f32,
+        max_secs: f32,
+        sample_rate: u32,
+    },
+}
+
+/// Format details read from a validated WAV header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WavInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Significant bits of a sample
+    pub bits_per_sample: u16,
+    /// Duration of the data chunk in seconds
+    pub duration_secs: f32,
+}
+
+/// Check that `bytes` is a complete WAV file in a supported encoding whose
+/// duration lies between `min_audio_secs` and `max_audio_secs` (`None` = no
+/// upper limit).
+pub fn validate_wav(
+    bytes: &[u8],
+    min_audio_secs: f32,
+    max_audio_secs: Option<f32>,
+) -> Result<WavInfo, AudioValidationError> {
+    let layout = match wav::read_layout(&mut Cursor::new(bytes), bytes.len() as u64) {
+        Ok(layout) => layout,
+        Err(WavError::Invalid(e)) => return Err(e),
+        // Only the bytes present are read, so this is a walker bug rather
+        // than a bad payload; report it the closest way there is
+        Err(WavError::Io(_)) => return Err(AudioValidationError::TruncatedHeader("chunk header")),
+    };
+    check_duration(&layout, min_audio_secs, max_audio_secs)
+}
+
+/// Same checks as [`validate_wav`] for a file on disk, reading only its
+/// chunk headers instead of the whole recording.
+pub fn validate_wav_file(
+    path: &Path,
+    min_audio_secs: f32,
+    max_audio_secs: Option<f32>,
+) -> Result<WavInfo, TranscriptionError> {
+    let file_error = |action: &str, e: io::Error| {
+        io::Error::new(
+            e.kind(),
+            format!("Failed to {} audio file {}: {}", action, path.display(), e),
+        )
+    };
+    let file = File::open(path).map_err(|e| file_error("open", e))?;
+    let total_len = file.metadata().map_err(|e| file_error("read", e))?.len();
+    let layout = match wav::read_layout(&mut BufReader::new(file), total_len) {
+        Ok(layout) => layout,
+        Err(WavError::Invalid(e)) => return Err(e.into()),
+        Err(WavError::Io(e)) => return Err(file_error("read", e).into()),
+    };
+    Ok(check_duration(&layout, min_audio_secs, max_audio_secs)?)
+}
+
+/// The format of a file whose audio was found, if its duration is within
+/// the limits.
+fn check_duration(
+    layout: &WavLayout,
+    min_audio_secs: f32,
+    max_audio_secs: Option<f32>,
+) -> Result<WavInfo, AudioValidationError> {
+    let format = layout.format;
+    let duration_secs = layout.duration_secs();
+    if duration_secs < min_audio_secs {
+        return Err(AudioValidationError::TooShort {
+            duration_ms: (duration_secs * 1000.0).round() as u64,
+            min_ms: (min_audio_secs * 1000.0).round() as u64,
+            sample_rate: format.sample_rate,
+        });
+    }
+    if let Some(max_secs) = max_audio_secs {
+        if duration_secs > max_secs {
+            return Err(AudioValidationError::TooLong {
+                duration_secs,
+                max_secs,
+                sample_rate: format.sample_rate,
+            });
+        }
+    }
+
+    Ok(WavInfo {
+        channels: format.channels,
+        sample_rate: format.sample_rate,
+        bits_per_sample: format.bits_per_sample,
+        duration_secs,
+    })
+}
+
+/// Shrink the RIFF and `data` chunk sizes of a WAV payload to the bytes
+/// actually present, dropping a trailing partial frame.
+///
+/// Streaming clients write the header before they know how much audio will
+/// follow; when such a stream is cut short the declared sizes overshoot.
+/// Returns `true` if the header was changed. Payloads that aren't WAV are
+/// left untouched for [`validate_wav`] to report.
+pub fn clamp_wav_sizes(bytes: &mut [u8]) -> bool {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return false;
+    }
+
+    let mut block_align = 1;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let size = read_u32(bytes, pos + 4) as usize;
+        let body = pos + 8;
+
+        if &bytes[pos..pos + 4] == b"data" {
+            let available = bytes.len() - body;
+            if size <= available {
+                return false;
+            }
+            let clamped = available - available % block_align.max(1);
+            bytes[pos + 4..pos + 8].copy_from_slice(&(clamped as u32).to_le_bytes());
+            let riff_size = (body + clamped - 8) as u32;
+            bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+            return true;
+        }
+        if &bytes[pos..pos + 4] == b"fmt " && body + 14 <= bytes.len() {
+            block_align = read_u16(bytes, body + 12) as usize;
+        }
+        pos = body + size + (size & 1);
+    }
+    false
+}
+
+/// Seconds of audio present in a WAV payload that may still be arriving,
+/// whatever sizes its header declares. 0 until the `data` chunk starts.
+pub fn received_secs(bytes: &[u8]) -> f32 {
+    partial_format(bytes).map_or(0.0, |format| format.received_secs(bytes))
+}
+
+/// Highest byte rate a stream is given credit for: 8 channels of 32-bit
+/// samples at 192 kHz. Header byte rates above it don't buy a longer buffer.
+pub const MAX_STREAM_BYTE_RATE: u64 = 192_000 * 8 * 4;
+
+/// Room for the header and other chunks ahead of the audio
+const HEADER_ALLOWANCE: u64 = 64 * 1024;
+
+/// Most bytes a WAV stream limited to `max_secs` may buffer, whether or not
+/// its header has arrived or tells the truth.
+pub fn max_received_bytes(max_secs: f32) -> u64 {
+    (max_secs as f64 * MAX_STREAM_BYTE_RATE as f64) as u64 + HEADER_ALLOWANCE
+}
+
+/// Fail with [`AudioValidationError::TooLong`] once a WAV payload that is
+/// still arriving holds more than `max_secs` of audio, so a stream can be
+/// stopped before all of it is buffered.
+pub fn check_received(bytes: &[u8], max_secs: f32) -> Result<(), AudioValidationError> {
+    let Some(format) = partial_format(bytes) else {
+        return Ok(());
+    };
+    let duration_secs = format.received_secs(bytes);
+    if duration_secs > max_secs {
+        return Err(AudioValidationError::TooLong {
+            duration_secs,
+            max_secs,
+            sample_rate: format.sample_rate,
+        });
+    }
+    Ok(())
+}
+
+/// What is known of a WAV payload once its `data` chunk has started.
+struct PartialFormat {
+    byte_rate: u32,
+    sample_rate: u32,
+    /// Offset of the first audio byte
+    data_start: usize,
+}
+
+impl PartialFormat {
+    fn received_secs(&self, bytes: &[u8]) -> f32 {
+        (bytes.len() - self.data_start) as f32 / self.byte_rate as f32
+    }
+}
+
+/// Walk the chunks of a payload that may still be arriving, `None` until
+/// both `fmt ` and the start of `data` are in.
+fn partial_format(bytes: &[u8]) -> Option<PartialFormat> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return None;
+    }
+
+    let mut rates = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let size = read_u32(bytes, pos + 4) as usize;
+        let body = pos + 8;
+
+        if &bytes[pos..pos + 4] == b"data" {
+            let (sample_rate, byte_rate) = rates.filter(|&(_, byte_rate)| byte_rate > 0)?;
+            return Some(PartialFormat {
+                byte_rate,
+                sample_rate,
+                data_start: body,
+            });
+        }
+        if &bytes[pos..pos + 4] == b"fmt " && body + 12 <= bytes.len() {
+            rates = Some((read_u32(bytes, body + 4), read_u32(bytes, body + 8)));
+        }
+        pos = body + size + (size & 1);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::write_wav_bytes;
+
+    fn wav(secs: f32) -> Vec<u8> {
+        write_wav_bytes(&vec![0.1; (secs * 16000.0) as usize], 16000).unwrap()
+    }
+
+    #[test]
+    fn accepts_audio_within_the_limits() {
+        let info = validate_wav(&wav(1.0), 0.1, Some(2.0)).unwrap();
+        assert_eq!(info.channels, 1);
+        assert_eq!(info.sample_rate, 16000);
+        assert_eq!(info.bits_per_sample, 16);
+        assert!((info.duration_secs - 1.0).abs() < 1e-3);
+        assert!(validate_wav(&wav(30.0), 0.1, None).is_ok());
+    }
+
+    #[test]
+    fn names_what_the_payload_is() {
+        assert_eq!(
+            validate_wav(b"", 0.1, None),
+            Err(AudioValidationError::Empty)
+        );
+        let error = validate_wav(br#"{"audio": "base64..."}"#, 0.1, None).unwrap_err();
+        assert!(matches!(error, AudioValidationError::NotWav { .. }));
+        assert!(
+            error.to_string().contains("first bytes were '{\"audio\""),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn reports_missing_data() {
+        let mut bytes = wav(3.0);
+        bytes.truncate(44 + 16000 * 2);
+        let error = validate_wav(&bytes, 0.1, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "WAV declares 3.0 s but only 1.0 s of data present (16000 Hz)"
+        );
+    }
+
+    #[test]
+    fn enforces_duration_bounds() {
+        let error = validate_wav(&wav(0.05), 0.1, None).unwrap_err();
+        assert_eq!(
+            error,
+            AudioValidationError::TooShort {
+                duration_ms: 50,
+                min_ms: 100,
+                sample_rate: 16000
+            }
+        );
+        let error = validate_wav(&wav(3.0), 0.1, Some(2.0)).unwrap_err();
+        assert!(matches!(error, AudioValidationError::TooLong { .. }));
+        assert!(
+            error.to_string().contains("exceeding the 2 s limit"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn validates_files_from_their_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audio.wav");
+        std::fs::write(&path, wav(1.0)).unwrap();
+        let info = validate_wav_file(&path, 0.1, Some(2.0)).unwrap();
+        assert!((info.duration_secs - 1.0).abs() < 1e-3);
+
+        std::fs::write(&path, b"not audio at all").unwrap();
+        assert!(matches!(
+            validate_wav_file(&path, 0.1, None),
+            Err(TranscriptionError::InvalidAudio(
+                AudioValidationError::NotWav { .. }
+            ))
+        ));
+        assert!(validate_wav_file(&dir.path().join("missing.wav"), 0.1, None).is_err());
+    }
+
+    #[test]
+    fn too_short_names_the_length_and_rate() {
+        // 50 ms of telephone audio
+        let clip = write_wav_bytes(&[0.1; 400], 8000).unwrap();
+        let error = validate_wav(&clip, 0.1, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "audio too short (50 ms at 8000 Hz), the minimum is 100 ms"
+        );
+        // No minimum
+        assert!(validate_wav(&clip, 0.0, None).is_ok());
+    }
+
+    #[test]
+    fn measures_audio_still_arriving() {
+        let bytes = wav(3.0);
+        // Header only, then 1.5 s of the 3 s announced
+        assert_eq!(received_secs(&bytes[..44]), 0.0);
+        assert_eq!(received_secs(&bytes[..20]), 0.0);
+        let partial = &bytes[..44 + 24000 * 2];
+        assert!((received_secs(partial) - 1.5).abs() < 1e-3);
+
+        assert!(check_received(partial, 2.0).is_ok());
+        assert!(check_received(b"not a wav", 2.0).is_ok());
+        assert_eq!(
+            check_received(partial, 1.0),
+            Err(AudioValidationError::TooLong {
+                duration_secs: 1.5,
+                max_secs: 1.0,
+                sample_rate: 16000
+            })
+        );
+    }
+
+    #[test]
+    fn caps_what_a_stream_may_buffer() {
+        // Whatever the header declares, 2 s never buffer more than 2 s at
+        // the highest byte rate
+        assert_eq!(
+            max_received_bytes(2.0),
+            2 * MAX_STREAM_BYTE_RATE + HEADER_ALLOWANCE
+        );
+        assert_eq!(max_received_bytes(0.0), HEADER_ALLOWANCE);
+    }
+}
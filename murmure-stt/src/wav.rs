@@ -0,0 +1,671 @@
+// RIFF/WAVE parsing shared by payload validation and decoding
+//
+// Files from browsers, DAWs and streamed captures don't all have the plain
+// 44-byte header:
+//
+// - LIST/INFO, JUNK, bext, fact and other chunks can come before or after
+//   the audio. They are skipped.
+// - A capture written before its length was known declares a data size of 0
+//   or 0xFFFFFFFF. The real size is then taken from what the file holds.
+// - WAVE_FORMAT_EXTENSIBLE carries the actual format in a sub-format GUID.
+//   PCM and IEEE float are decoded, like their plain format tags.
+// - Some writers leave out the pad byte after an odd-sized chunk. The walker
+//   looks one byte earlier when a chunk id doesn't look like one.
+//
+// Anything else is rejected with an error naming the chunk at fault.
+
+use crate::validation::AudioValidationError;
+use std::io::{self, Read, Seek, SeekFrom};
+use thiserror::Error;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Bytes 2 to 16 of the KSDATAFORMAT_SUBTYPE GUIDs, whose first two bytes
+/// are the format tag
+const SUBFORMAT_GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+];
+
+/// Data sizes written by capture tools that didn't know the length yet
+const UNKNOWN_DATA_SIZES: [u32; 2] = [0, u32::MAX];
+
+/// Bytes of a `fmt ` chunk that are read; WAVE_FORMAT_EXTENSIBLE needs 40
+const FMT_READ_LEN: u32 = 40;
+
+/// Number of leading bytes shown when a payload isn't a WAV file
+const PREVIEW_LEN: usize = 16;
+
+/// How samples are stored in the `data` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SampleEncoding {
+    U8,
+    I16,
+    I24,
+    I32,
+    F32,
+    F64,
+}
+
+impl SampleEncoding {
+    /// Bytes of one sample
+    fn width(self) -> usize {
+        match self {
+            Self::U8 => 1,
+            Self::I16 => 2,
+            Self::I24 => 3,
+            Self::I32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+}
+
+/// What the `fmt ` chunk says about the audio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct WavFormat {
+    pub encoding: SampleEncoding,
+    pub channels: u16,
+    pub sample_rate: u32,
+    /// Significant bits of a sample, at most the width it is stored in
+    pub bits_per_sample: u16,
+}
+
+impl WavFormat {
+    /// Bytes of one frame, a sample of every channel
+    pub fn block_align(&self) -> usize {
+        self.encoding.width() * self.channels as usize
+    }
+
+    pub fn byte_rate(&self) -> f32 {
+        self.block_align() as f32 * self.sample_rate as f32
+    }
+}
+
+/// Where the audio of a WAV file is, and how to read it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct WavLayout {
+    pub format: WavFormat,
+    /// Offset of the first audio byte
+    pub data_start: u64,
+    /// Bytes of audio, whole frames only
+    pub data_len: u64,
+}
+
+impl WavLayout {
+    pub fn duration_secs(&self) -> f32 {
+        self.data_len as f32 / self.format.byte_rate()
+    }
+}
+
+/// Why a WAV file's layout couldn't be read.
+#[derive(Debug, Error)]
+pub(crate) enum WavError {
+    #[error(transparent)]
+    Invalid(#[from] AudioValidationError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Walk the chunks of the `total_len` bytes WAV file read by `reader`,
+/// reading only the chunk headers and the `fmt ` chunk.
+pub(crate) fn read_layout<R: Read + Seek>(
+    reader: &mut R,
+    total_len: u64,
+) -> Result<WavLayout, WavError> {
+    if total_len == 0 {
+        return Err(AudioValidationError::Empty.into());
+    }
+    let mut head = [0u8; PREVIEW_LEN];
+    let head_len = read_at(reader, 0, &mut head, total_len)?;
+    let head = &head[..head_len];
+    if head.len() < 12 || &head[0..4] != b"RIFF" || &head[8..12] != b"WAVE" {
+        if head.len() < 12 && head.starts_with(b"RIFF") {
+            return Err(AudioValidationError::TruncatedHeader("RIFF header").into());
+        }
+        return Err(AudioValidationError::NotWav {
+            preview: preview(head),
+        }
+        .into());
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut pos = 12;
+    let mut after_odd_chunk = false;
+    while (format.is_none() || data.is_none()) && pos + 8 <= total_len {
+        let (id, size) = match read_chunk_header(reader, pos)? {
+            (id, size) if is_chunk_id(&id) => (id, size),
+            (id, _) if !after_odd_chunk => return Err(not_a_chunk(&id, pos).into()),
+            // The writer may have left out the pad byte of the previous chunk
+            (id, _) => match read_chunk_header(reader, pos - 1)? {
+                (unpadded, size) if is_chunk_id(&unpadded) => {
+                    pos -= 1;
+                    (unpadded, size)
+                }
+                _ => return Err(not_a_chunk(&id, pos).into()),
+            },
+        };
+        let body = pos + 8;
+        let available = total_len - body;
+
+        if &id == b"data" {
+            if UNKNOWN_DATA_SIZES.contains(&size) {
+                // Runs to the end of the file, nothing can follow it
+                data = Some((body, available, None));
+                break;
+            }
+            data = Some((body, size as u64, Some(available)));
+        } else if size as u64 > available {
+            return Err(AudioValidationError::TruncatedChunk {
+                chunk: chunk_name(&id),
+                declared: size as usize,
+                available: available as usize,
+            }
+            .into());
+        } else if &id == b"fmt " {
+            format = Some(read_format(reader, body, size)?);
+        }
+        pos = body + size as u64 + (size & 1) as u64;
+        after_odd_chunk = size & 1 == 1;
+    }
+
+    let format = format.ok_or(AudioValidationError::MissingChunk("fmt "))?;
+    let (data_start, declared_len, available) =
+        data.ok_or(AudioValidationError::MissingChunk("data"))?;
+    if let Some(available) = available.filter(|&available| declared_len > available) {
+        return Err(AudioValidationError::TruncatedData {
+            declared_secs: declared_len as f32 / format.byte_rate(),
+            actual_secs: available as f32 / format.byte_rate(),
+            sample_rate: format.sample_rate,
+        }
+        .into());
+    }
+    let block_align = format.block_align() as u64;
+    Ok(WavLayout {
+        format,
+        data_start,
+        data_len: declared_len - declared_len % block_align,
+    })
+}
+
+/// Interleaved samples in [-1.0, 1.0] from the audio bytes of a `data`
+/// chunk. A trailing partial sample is dropped.
+pub(crate) fn decode_samples(data: &[u8], encoding: SampleEncoding) -> Vec<f32> {
+    let samples = data.chunks_exact(encoding.width());
+    match encoding {
+        SampleEncoding::U8 => samples.map(|s| (s[0] as f32 - 128.0) / 128.0).collect(),
+        SampleEncoding::I16 => samples
+            .map(|s| crate::dsp::i16_to_f32(i16::from_le_bytes([s[0], s[1]])))
+            .map(|sample| sample.max(-1.0))
+            .collect(),
+        SampleEncoding::I24 => samples
+            .map(|s| (i32::from_le_bytes([0, s[0], s[1], s[2]]) >> 8) as f32 / 8_388_607.0)
+            .map(|sample| sample.max(-1.0))
+            .collect(),
+        SampleEncoding::I32 => samples
+            .map(|s| i32::from_le_bytes([s[0], s[1], s[2], s[3]]) as f32 / i32::MAX as f32)
+            .map(|sample| sample.max(-1.0))
+            .collect(),
+        SampleEncoding::F32 => samples
+            .map(|s| finite(f32::from_le_bytes([s[0], s[1], s[2], s[3]])))
+            .collect(),
+        SampleEncoding::F64 => samples
+            .map(|s| finite(f64::from_le_bytes(s.try_into().unwrap_or_default()) as f32))
+            .collect(),
+    }
+}
+
+/// Read the `fmt ` chunk whose body starts at `body`.
+fn read_format<R: Read + Seek>(
+    reader: &mut R,
+    body: u64,
+    size: u32,
+) -> Result<WavFormat, WavError> {
+    if size < 16 {
+        return Err(AudioValidationError::TruncatedHeader("'fmt ' chunk").into());
+    }
+    let mut fmt = vec![0u8; size.min(FMT_READ_LEN) as usize];
+    reader.seek(SeekFrom::Start(body))?;
+    reader.read_exact(&mut fmt)?;
+
+    let mut tag = read_u16(&fmt, 0);
+    let channels = read_u16(&fmt, 2);
+    let sample_rate = read_u32(&fmt, 4);
+    let block_align = read_u16(&fmt, 12);
+    let container_bits = read_u16(&fmt, 14);
+    let mut bits_per_sample = container_bits;
+    if tag == WAVE_FORMAT_EXTENSIBLE {
+        if fmt.len() < 40 {
+            return Err(malformed_fmt(format!(
+                "WAVE_FORMAT_EXTENSIBLE needs 40 bytes, the chunk has {}",
+                size
+            )));
+        }
+        let guid = &fmt[24..40];
+        if guid[2..] != SUBFORMAT_GUID_TAIL {
+            return Err(AudioValidationError::UnsupportedFormat(format!(
+                "extensible sub-format {}",
+                format_guid(guid)
+            ))
+            .into());
+        }
+        tag = read_u16(guid, 0);
+        let valid_bits = read_u16(&fmt, 18);
+        if valid_bits > 0 {
+            bits_per_sample = valid_bits;
+        }
+    }
+
+    let encoding = match (tag, container_bits) {
+        (WAVE_FORMAT_PCM, 8) => SampleEncoding::U8,
+        (WAVE_FORMAT_PCM, 16) => SampleEncoding::I16,
+        (WAVE_FORMAT_PCM, 24) => SampleEncoding::I24,
+        (WAVE_FORMAT_PCM, 32) => SampleEncoding::I32,
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => SampleEncoding::F32,
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => SampleEncoding::F64,
+        (WAVE_FORMAT_PCM | WAVE_FORMAT_IEEE_FLOAT, bits) => {
+            let kind = if tag == WAVE_FORMAT_PCM {
+                "integer"
+            } else {
+                "float"
+            };
+            return Err(AudioValidationError::UnsupportedFormat(format!(
+                "{}-bit {} samples (expected 8, 16, 24 or 32-bit integer, or 32 or 64-bit float)",
+                bits, kind
+            ))
+            .into());
+        }
+        (tag, _) => {
+            return Err(AudioValidationError::UnsupportedFormat(format!(
+                "format tag 0x{:04X} (expected PCM or IEEE float)",
+                tag
+            ))
+            .into())
+        }
+    };
+    if channels == 0 || sample_rate == 0 {
+        return Err(AudioValidationError::UnsupportedFormat(format!(
+            "{} channel(s) at {} Hz",
+            channels, sample_rate
+        ))
+        .into());
+    }
+    let format = WavFormat {
+        encoding,
+        channels,
+        sample_rate,
+        bits_per_sample: bits_per_sample.min(container_bits),
+    };
+    if block_align as usize != format.block_align() {
+        return Err(malformed_fmt(format!(
+            "block align is {} bytes, {} channel(s) of {}-bit samples take {}",
+            block_align,
+            channels,
+            container_bits,
+            format.block_align()
+        )));
+    }
+    Ok(format)
+}
+
+/// Id and declared size of the chunk starting at `pos`.
+fn read_chunk_header<R: Read + Seek>(reader: &mut R, pos: u64) -> io::Result<([u8; 4], u32)> {
+    let mut header = [0u8; 8];
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_exact(&mut header)?;
+    Ok((
+        [header[0], header[1], header[2], header[3]],
+        read_u32(&header, 4),
+    ))
+}
+
+/// Fill as much of `buf` as the file holds from `pos` and return how much
+/// that was.
+fn read_at<R: Read + Seek>(
+    reader: &mut R,
+    pos: u64,
+    buf: &mut [u8],
+    total_len: u64,
+) -> io::Result<usize> {
+    let len = buf.len().min(total_len.saturating_sub(pos) as usize);
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_exact(&mut buf[..len])?;
+    Ok(len)
+}
+
+/// Chunk ids are four printable ASCII characters, space-padded.
+fn is_chunk_id(id: &[u8; 4]) -> bool {
+    id.iter().all(|&b| b.is_ascii_graphic() || b == b' ') && id[0] != b' '
+}
+
+fn not_a_chunk(id: &[u8; 4], pos: u64) -> AudioValidationError {
+    AudioValidationError::MalformedChunk {
+        chunk: preview(id),
+        reason: format!("found at byte {} where a chunk id was expected", pos),
+    }
+}
+
+fn malformed_fmt(reason: String) -> WavError {
+    AudioValidationError::MalformedChunk {
+        chunk: "fmt ".to_string(),
+        reason,
+    }
+    .into()
+}
+
+fn chunk_name(id: &[u8; 4]) -> String {
+    String::from_utf8_lossy(id).into_owned()
+}
+
+/// A GUID as usually written, e.g. `00000001-0000-0010-8000-00AA00389B71`.
+fn format_guid(guid: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{}-{}",
+        read_u32(guid, 0),
+        read_u16(guid, 4),
+        read_u16(guid, 6),
+        hex(&guid[8..10]),
+        hex(&guid[10..16])
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// NaN and infinite samples become silence, others are clipped to full scale.
+fn finite(sample: f32) -> f32 {
+    if sample.is_finite() {
+        sample.clamp(-1.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+/// Printable rendering of the first bytes of a payload.
+fn preview(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .take(PREVIEW_LEN)
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                (b as char).to_string()
+            } else {
+                format!("\\x{:02x}", b)
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn read_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+pub(crate) fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A chunk with its header, padded to an even size.
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    /// A plain `fmt ` body.
+    fn fmt(tag: u16, channels: u16, sample_rate: u32, bits: u16) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let mut body = Vec::new();
+        body.extend_from_slice(&tag.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&sample_rate.to_le_bytes());
+        body.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        body.extend_from_slice(&block_align.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body
+    }
+
+    /// A WAVE_FORMAT_EXTENSIBLE `fmt ` body for `sub_tag`.
+    fn extensible(sub_tag: u16, bits: u16, valid_bits: u16) -> Vec<u8> {
+        let mut body = fmt(WAVE_FORMAT_EXTENSIBLE, 1, 16_000, bits);
+        body.extend_from_slice(&22u16.to_le_bytes());
+        body.extend_from_slice(&valid_bits.to_le_bytes());
+        body.extend_from_slice(&4u32.to_le_bytes());
+        body.extend_from_slice(&sub_tag.to_le_bytes());
+        body.extend_from_slice(&SUBFORMAT_GUID_TAIL);
+        body
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    fn layout(bytes: &[u8]) -> Result<WavLayout, AudioValidationError> {
+        match read_layout(&mut Cursor::new(bytes), bytes.len() as u64) {
+            Ok(layout) => Ok(layout),
+            Err(WavError::Invalid(e)) => Err(e),
+            Err(WavError::Io(e)) => panic!("I/O error on an in-memory file: {}", e),
+        }
+    }
+
+    #[test]
+    fn reads_the_plain_header() {
+        let bytes = riff(&[
+            chunk(b"fmt ", &fmt(1, 1, 16_000, 16)),
+            chunk(b"data", &[0; 3200]),
+        ]);
+        let layout = layout(&bytes).unwrap();
+        assert_eq!(layout.data_start, 44);
+        assert_eq!(layout.data_len, 3200);
+        assert_eq!(layout.format.encoding, SampleEncoding::I16);
+        assert!((layout.duration_secs() - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn skips_other_chunks() {
+        let bytes = riff(&[
+            chunk(b"LIST", b"INFOISFT\x05\0\0\0test\0"),
+            chunk(b"fmt ", &fmt(1, 2, 48_000, 16)),
+            chunk(b"JUNK", &[0; 3]),
+            chunk(b"bext", &[1; 10]),
+            chunk(b"data", &[0; 400]),
+            chunk(b"id3 ", &[0; 7]),
+        ]);
+        let layout = layout(&bytes).unwrap();
+        assert_eq!(layout.format.channels, 2);
+        assert_eq!(layout.format.sample_rate, 48_000);
+        assert_eq!(layout.data_len, 400);
+        assert_eq!(&bytes[layout.data_start as usize - 8..][..4], b"data");
+    }
+
+    #[test]
+    fn tolerates_a_missing_pad_byte() {
+        // Odd-sized chunk written without its pad byte
+        let mut junk = chunk(b"JUNK", &[0; 3]);
+        junk.pop();
+        let bytes = riff(&[
+            chunk(b"fmt ", &fmt(1, 1, 16_000, 16)),
+            junk,
+            chunk(b"data", &[0; 200]),
+        ]);
+        assert_eq!(layout(&bytes).unwrap().data_len, 200);
+    }
+
+    #[test]
+    fn unknown_data_sizes_run_to_the_end() {
+        for declared in UNKNOWN_DATA_SIZES {
+            let mut bytes = riff(&[
+                chunk(b"fmt ", &fmt(1, 1, 16_000, 16)),
+                chunk(b"data", &[0; 101]),
+            ]);
+            // The pad byte becomes audio, the odd byte a partial frame
+            bytes.truncate(bytes.len() - 1);
+            bytes[40..44].copy_from_slice(&declared.to_le_bytes());
+            assert_eq!(layout(&bytes).unwrap().data_len, 100, "{:#x}", declared);
+        }
+    }
+
+    #[test]
+    fn reports_truncation() {
+        let mut bytes = riff(&[
+            chunk(b"fmt ", &fmt(1, 1, 16_000, 16)),
+            chunk(b"data", &[0; 32_000]),
+        ]);
+        bytes.truncate(44 + 16_000);
+        assert!(matches!(
+            layout(&bytes),
+            Err(AudioValidationError::TruncatedData {
+                sample_rate: 16_000,
+                ..
+            })
+        ));
+
+        let mut bytes = riff(&[
+            chunk(b"LIST", &[0; 20]),
+            chunk(b"fmt ", &fmt(1, 1, 16_000, 16)),
+        ]);
+        bytes.truncate(30);
+        assert_eq!(
+            layout(&bytes),
+            Err(AudioValidationError::TruncatedChunk {
+                chunk: "LIST".to_string(),
+                declared: 20,
+                available: 10,
+            })
+        );
+        assert_eq!(
+            layout(b"RIFF\0\0"),
+            Err(AudioValidationError::TruncatedHeader("RIFF header"))
+        );
+    }
+
+    #[test]
+    fn reports_what_is_missing_or_wrong() {
+        assert_eq!(layout(b""), Err(AudioValidationError::Empty));
+        assert_eq!(
+            layout(b"ID3\x04\0\0\0\0\0\0mp3 data here"),
+            Err(AudioValidationError::NotWav {
+                preview: "ID3\\x04\\x00\\x00\\x00\\x00\\x00\\x00mp3 da".to_string()
+            })
+        );
+        let bytes = riff(&[chunk(b"fmt ", &fmt(1, 1, 16_000, 16))]);
+        assert_eq!(
+            layout(&bytes),
+            Err(AudioValidationError::MissingChunk("data"))
+        );
+        let bytes = riff(&[chunk(b"data", &[0; 10])]);
+        assert_eq!(
+            layout(&bytes),
+            Err(AudioValidationError::MissingChunk("fmt "))
+        );
+
+        let mut bytes = riff(&[
+            chunk(b"fmt ", &fmt(1, 1, 16_000, 16)),
+            chunk(b"data", &[0; 10]),
+        ]);
+        bytes[36..40].copy_from_slice(b"\x01\x02\x03\x04");
+        assert!(matches!(
+            layout(&bytes),
+            Err(AudioValidationError::MalformedChunk { reason, .. }) if reason.contains("byte 36")
+        ));
+
+        let mut body = fmt(1, 1, 16_000, 16);
+        body[12] = 4;
+        let bytes = riff(&[chunk(b"fmt ", &body), chunk(b"data", &[0; 10])]);
+        assert!(matches!(
+            layout(&bytes),
+            Err(AudioValidationError::MalformedChunk { chunk, .. }) if chunk == "fmt "
+        ));
+    }
+
+    #[test]
+    fn reads_supported_encodings() {
+        let cases = [
+            (fmt(1, 1, 8_000, 8), SampleEncoding::U8),
+            (fmt(1, 1, 8_000, 24), SampleEncoding::I24),
+            (fmt(1, 1, 8_000, 32), SampleEncoding::I32),
+            (fmt(3, 1, 8_000, 32), SampleEncoding::F32),
+            (fmt(3, 1, 8_000, 64), SampleEncoding::F64),
+        ];
+        for (body, encoding) in cases {
+            let bytes = riff(&[chunk(b"fmt ", &body), chunk(b"data", &[0; 48])]);
+            assert_eq!(layout(&bytes).unwrap().format.encoding, encoding);
+        }
+
+        for body in [
+            fmt(1, 1, 8_000, 12),
+            fmt(3, 1, 8_000, 16),
+            fmt(0x55, 1, 8_000, 16),
+        ] {
+            let bytes = riff(&[chunk(b"fmt ", &body), chunk(b"data", &[0; 48])]);
+            assert!(matches!(
+                layout(&bytes),
+                Err(AudioValidationError::UnsupportedFormat(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn reads_extensible_formats() {
+        let bytes = riff(&[
+            chunk(b"fmt ", &extensible(1, 24, 20)),
+            chunk(b"data", &[0; 30]),
+        ]);
+        let format = layout(&bytes).unwrap().format;
+        assert_eq!(format.encoding, SampleEncoding::I24);
+        assert_eq!(format.bits_per_sample, 20);
+
+        let bytes = riff(&[
+            chunk(b"fmt ", &extensible(3, 32, 0)),
+            chunk(b"data", &[0; 32]),
+        ]);
+        assert_eq!(layout(&bytes).unwrap().format.encoding, SampleEncoding::F32);
+
+        let mut body = extensible(1, 16, 16);
+        body[30] = 0xFF;
+        let bytes = riff(&[chunk(b"fmt ", &body), chunk(b"data", &[0; 32])]);
+        assert!(matches!(
+            layout(&bytes),
+            Err(AudioValidationError::UnsupportedFormat(message)) if message.contains("sub-format")
+        ));
+    }
+
+    #[test]
+    fn decodes_samples_to_unit_range() {
+        assert_eq!(
+            decode_samples(&[0, 128, 255], SampleEncoding::U8),
+            [-1.0, 0.0, 127.0 / 128.0]
+        );
+        let i16 = [0x00, 0x80, 0xFF, 0x7F, 0x00];
+        let decoded = decode_samples(&i16, SampleEncoding::I16);
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0], -1.0);
+        assert!((decoded[1] - 1.0).abs() < 1e-4);
+        let i24 = [0x00, 0x00, 0x80, 0xFF, 0xFF, 0x7F];
+        assert_eq!(decode_samples(&i24, SampleEncoding::I24), [-1.0, 1.0]);
+
+        let floats: Vec<u8> = [0.5f32, 2.0, f32::NAN, -f32::INFINITY]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        assert_eq!(
+            decode_samples(&floats, SampleEncoding::F32),
+            [0.5, 1.0, 0.0, 0.0]
+        );
+    }
+}
@@ -0,0 +1,87 @@
+// The async API runs on the library's workers, not the caller's runtime
+#![cfg(feature = "async")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use murmure_stt::dsp::write_wav_bytes;
+use murmure_stt::mock::MockEngine;
+use murmure_stt::{TranscriptionOptions, TranscriptionService};
+
+fn wav() -> Vec<u8> {
+    let samples: Vec<f32> = (0..16_000)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 16_000.0).sin() * 0.3)
+        .collect();
+    write_wav_bytes(&samples, 16_000).unwrap()
+}
+
+// A single-threaded runtime: a blocking call would stall the ticker
+#[tokio::test(flavor = "current_thread")]
+async fn concurrent_calls_leave_the_runtime_free() {
+    let engine = MockEngine::new("async").with_latency(Duration::from_millis(200));
+    let service = TranscriptionService::builder()
+        .engine(move || engine.clone())
+        .build()
+        .unwrap();
+
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let ticker = tokio::spawn({
+        let ticks = ticks.clone();
+        async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                ticks.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    });
+
+    let calls: Vec<_> = (0..4)
+        .map(|_| {
+            let service = service.clone();
+            tokio::spawn(async move {
+                service
+                    .transcribe_audio_bytes_async(wav(), TranscriptionOptions::default())
+                    .await
+            })
+        })
+        .collect();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        for call in calls {
+            assert_eq!(call.await.unwrap().unwrap().text, "async");
+        }
+    })
+    .await
+    .expect("the calls finish within the budget");
+    ticker.abort();
+
+    // The engine runs one request at a time, 800 ms in all; the ticker kept
+    // running throughout
+    assert!(ticks.load(Ordering::SeqCst) >= 40, "{:?}", ticks);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn files_and_errors_come_back_the_same() {
+    let service = TranscriptionService::builder()
+        .engine(|| MockEngine::new("from a file"))
+        .build()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audio.wav");
+    std::fs::write(&path, wav()).unwrap();
+
+    let transcript = service
+        .transcribe_file_async(path, TranscriptionOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(transcript.text, "from a file");
+
+    let sync_error = service
+        .transcribe_audio_bytes_with_options(b"not audio", &TranscriptionOptions::default())
+        .unwrap_err();
+    let async_error = service
+        .transcribe_audio_bytes_async(b"not audio".to_vec(), TranscriptionOptions::default())
+        .await
+        .unwrap_err();
+    assert_eq!(async_error.to_string(), sync_error.to_string());
+}
@@ -0,0 +1,1006 @@
+// The transcription pipeline on the mock engine, without model files
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use murmure_stt::dsp::{read_wav_bytes, read_wav_channels, write_wav_bytes};
+use murmure_stt::mock::MockEngine;
+use murmure_stt::model::ExecutionProvider;
+use murmure_stt::{
+    ChannelMode, DecodingOptions, DictionaryStatus, ServerConfig, SpeechEngine,
+    TranscriptPostProcessor, TranscriptionContext, TranscriptionError, TranscriptionOptions,
+    TranscriptionOutput, TranscriptionService,
+};
+
+fn service(engine: MockEngine) -> TranscriptionService {
+    TranscriptionService::builder()
+        .engine(move || engine.clone())
+        .build()
+        .unwrap()
+}
+
+/// A 440 Hz tone lasting `secs`, at 16 kHz.
+fn tone(secs: f32) -> Vec<f32> {
+    (0..(secs * 16_000.0) as usize)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 16_000.0).sin() * 0.3)
+        .collect()
+}
+
+#[test]
+fn builds_without_a_model_directory() {
+    let service = service(MockEngine::new("hello world"));
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    let transcript = service.transcribe_audio_bytes_detailed(&wav).unwrap();
+    assert_eq!(transcript.text, "hello world");
+    assert!((transcript.audio_duration_secs - 1.0).abs() < 0.01);
+    assert_eq!(transcript.confidence, Some(1.0));
+}
+
+#[test]
+fn resamples_before_the_engine() {
+    // 1.5 s at 48 kHz reaches the engine as 1.5 s at 16 kHz
+    let samples: Vec<f32> = (0..72_000)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 48_000.0).sin() * 0.3)
+        .collect();
+    let wav = write_wav_bytes(&samples, 48_000).unwrap();
+    let service = service(MockEngine::new("other").on_duration(1500, "resampled"));
+    let transcript = service.transcribe_audio_bytes_detailed(&wav).unwrap();
+    assert_eq!(transcript.text, "resampled");
+    assert_eq!(transcript.input.sample_rate, 48_000);
+}
+
+#[test]
+fn fingerprints_take_precedence_over_durations() {
+    let samples = tone(1.0);
+    let wav = write_wav_bytes(&samples, 16_000).unwrap();
+    // The engine sees the samples as quantized to 16 bits by the WAV
+    let decoded = read_wav_bytes(&wav).unwrap().samples;
+    let engine = MockEngine::new("default")
+        .on_duration(1000, "by duration")
+        .on_fingerprint(MockEngine::fingerprint(&decoded), "by fingerprint");
+    let service = service(engine);
+
+    assert_eq!(
+        service.transcribe_audio_bytes(&wav).unwrap(),
+        "by fingerprint"
+    );
+    let quieter: Vec<f32> = samples.iter().map(|sample| sample * 0.5).collect();
+    let wav = write_wav_bytes(&quieter, 16_000).unwrap();
+    assert_eq!(service.transcribe_audio_bytes(&wav).unwrap(), "by duration");
+}
+
+#[test]
+fn honours_max_tokens_and_reports_confidence() {
+    let service = service(MockEngine::new("one two three four").with_confidence(0.5));
+    let mut options = TranscriptionOptions::from_config(service.get_config());
+    options.decoding.max_tokens = Some(2);
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    let transcript = service
+        .transcribe_audio_bytes_with_options(&wav, &options)
+        .unwrap();
+    assert_eq!(transcript.text, "one two");
+    assert!((transcript.confidence.unwrap() - 0.5).abs() < 1e-4);
+}
+
+#[test]
+fn rejects_invalid_audio_before_the_engine() {
+    let service = service(MockEngine::new("unused"));
+    let error = service.transcribe_audio_bytes(b"RIFF").unwrap_err();
+    assert!(
+        matches!(error, TranscriptionError::InvalidAudio(_)),
+        "{:?}",
+        error
+    );
+}
+
+#[test]
+fn services_keep_their_own_engines() {
+    let first = service(MockEngine::new("first"));
+    let second = service(MockEngine::new("second"));
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    assert_eq!(first.transcribe_audio_bytes(&wav).unwrap(), "first");
+    assert_eq!(second.transcribe_audio_bytes(&wav).unwrap(), "second");
+    drop(first);
+    let third = service(MockEngine::new("third"));
+    assert_eq!(third.transcribe_audio_bytes(&wav).unwrap(), "third");
+}
+
+/// The mock engine, panicking while `panicking` is set.
+struct PanickingEngine {
+    inner: MockEngine,
+    panicking: Arc<AtomicBool>,
+}
+
+impl SpeechEngine for PanickingEngine {
+    fn transcribe(
+        &mut self,
+        samples: Vec<f32>,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        if self.panicking.load(Ordering::SeqCst) {
+            panic!("malformed model");
+        }
+        self.inner.transcribe(samples)
+    }
+}
+
+#[test]
+fn engine_panics_are_errors_and_reload_the_engine() {
+    let panicking = Arc::new(AtomicBool::new(false));
+    let builds = Arc::new(AtomicUsize::new(0));
+    let service = TranscriptionService::builder()
+        .config(ServerConfig {
+            engine_panic_threshold: 2,
+            ..ServerConfig::default()
+        })
+        .engine({
+            let panicking = panicking.clone();
+            let builds = builds.clone();
+            move || {
+                builds.fetch_add(1, Ordering::SeqCst);
+                PanickingEngine {
+                    inner: MockEngine::new("recovered"),
+                    panicking: panicking.clone(),
+                }
+            }
+        })
+        .build()
+        .unwrap();
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    assert_eq!(service.transcribe_audio_bytes(&wav).unwrap(), "recovered");
+    assert_eq!(builds.load(Ordering::SeqCst), 1);
+
+    let panics_before = service.engine_panics();
+    panicking.store(true, Ordering::SeqCst);
+    for _ in 0..2 {
+        let error = service.transcribe_audio_bytes(&wav).unwrap_err();
+        assert!(
+            matches!(error, TranscriptionError::EnginePanic(_)),
+            "{:?}",
+            error
+        );
+    }
+    assert!(service.engine_panics() >= panics_before + 2);
+
+    // The second panic reached the threshold, so a new engine is built
+    panicking.store(false, Ordering::SeqCst);
+    assert_eq!(service.transcribe_audio_bytes(&wav).unwrap(), "recovered");
+    assert_eq!(builds.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn requests_fail_fast_while_the_engine_loads() {
+    let builds = Arc::new(AtomicUsize::new(0));
+    let service = Arc::new(
+        TranscriptionService::builder()
+            .config(ServerConfig {
+                engine_wait_ms: 100,
+                ..ServerConfig::default()
+            })
+            .engine({
+                let builds = builds.clone();
+                move || {
+                    builds.fetch_add(1, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(800));
+                    MockEngine::new("loaded")
+                }
+            })
+            .preload(false)
+            .build()
+            .unwrap(),
+    );
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+
+    let loaders: Vec<_> = (0..2)
+        .map(|_| {
+            let service = service.clone();
+            std::thread::spawn(move || service.warm_up())
+        })
+        .collect();
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    // The load runs outside the lock, so this gives up after its own wait
+    let started = std::time::Instant::now();
+    let error = service.transcribe_audio_bytes(&wav).unwrap_err();
+    assert!(
+        matches!(&error, TranscriptionError::EngineUnavailable(message) if message.contains("still loading")),
+        "{:?}",
+        error
+    );
+    assert!(started.elapsed() < std::time::Duration::from_millis(500));
+
+    // The second loader waited for the first instead of loading again
+    for loader in loaders {
+        loader.join().unwrap().expect("warm-up");
+    }
+    assert_eq!(builds.load(Ordering::SeqCst), 1);
+    assert_eq!(service.transcribe_audio_bytes(&wav).unwrap(), "loaded");
+}
+
+/// The mock engine, having fallen back to the CPU whatever was requested.
+struct CpuFallbackEngine(MockEngine);
+
+impl SpeechEngine for CpuFallbackEngine {
+    fn transcribe(
+        &mut self,
+        samples: Vec<f32>,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        self.0.transcribe(samples)
+    }
+
+    fn execution_provider(&self) -> Option<ExecutionProvider> {
+        Some(ExecutionProvider::Cpu)
+    }
+}
+
+#[test]
+fn reports_the_provider_the_engine_ended_up_on() {
+    let service = TranscriptionService::builder()
+        .config(ServerConfig {
+            execution_provider: ExecutionProvider::Cuda,
+            ..ServerConfig::default()
+        })
+        .engine(|| CpuFallbackEngine(MockEngine::new("on the cpu")))
+        .preload(false)
+        .build()
+        .unwrap();
+    assert_eq!(service.execution_provider(), None);
+
+    service.warm_up().unwrap();
+    assert_eq!(service.execution_provider(), Some(ExecutionProvider::Cpu));
+    assert_eq!(
+        service.get_config().execution_provider,
+        ExecutionProvider::Cuda
+    );
+}
+
+/// A stereo 16-bit WAV of `left` and `right`, decoded back to what the
+/// engine will see of each channel.
+fn stereo_wav(left: &[f32], right: &[f32]) -> (Vec<u8>, Vec<Vec<f32>>) {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 16_000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut cursor = std::io::Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut cursor, spec).unwrap();
+    for (l, r) in left.iter().zip(right) {
+        writer.write_sample((l * i16::MAX as f32) as i16).unwrap();
+        writer.write_sample((r * i16::MAX as f32) as i16).unwrap();
+    }
+    writer.finalize().unwrap();
+    let bytes = cursor.into_inner();
+    let channels = read_wav_channels(bytes.as_slice()).unwrap().channels;
+    (bytes, channels)
+}
+
+#[test]
+fn transcribes_stereo_channels_apart() {
+    let left = tone(1.0);
+    let right: Vec<f32> = left.iter().map(|sample| sample * 0.5).collect();
+    let (wav, channels) = stereo_wav(&left, &right);
+    let service = service(
+        MockEngine::new("both speakers")
+            .on_fingerprint(MockEngine::fingerprint(&channels[0]), "agent")
+            .on_fingerprint(MockEngine::fingerprint(&channels[1]), "customer"),
+    );
+    let transcribe = |channel_mode| {
+        let options = TranscriptionOptions {
+            channel_mode,
+            ..TranscriptionOptions::default()
+        };
+        service
+            .transcribe_audio_bytes_with_options(&wav, &options)
+            .unwrap()
+    };
+
+    let separate = transcribe(ChannelMode::Separate);
+    let per_channel: Vec<_> = separate
+        .channels
+        .iter()
+        .map(|channel| (channel.channel, channel.text.as_str()))
+        .collect();
+    assert_eq!(per_channel, [(0, "agent"), (1, "customer")]);
+    assert!(separate.text.contains("agent") && separate.text.contains("customer"));
+    assert_eq!(separate.input.channels, 2);
+
+    assert_eq!(transcribe(ChannelMode::LeftOnly).text, "agent");
+    assert_eq!(transcribe(ChannelMode::RightOnly).text, "customer");
+    let downmixed = transcribe(ChannelMode::Downmix);
+    assert_eq!(downmixed.text, "both speakers");
+    assert!(downmixed.channels.is_empty());
+}
+
+/// Appends a tag, recording the text it was given and the model it came from.
+struct Tagger {
+    tag: &'static str,
+    seen: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl TranscriptPostProcessor for Tagger {
+    fn name(&self) -> &str {
+        self.tag
+    }
+
+    fn process(&self, text: String, ctx: &TranscriptionContext) -> anyhow::Result<String> {
+        self.seen
+            .lock()
+            .unwrap()
+            .push((text.clone(), ctx.model.to_string()));
+        Ok(format!("{} {}", text, self.tag))
+    }
+}
+
+struct Failing;
+
+impl TranscriptPostProcessor for Failing {
+    fn name(&self) -> &str {
+        "failing"
+    }
+
+    fn process(&self, _text: String, _ctx: &TranscriptionContext) -> anyhow::Result<String> {
+        anyhow::bail!("scrubber offline")
+    }
+}
+
+#[test]
+fn post_processors_run_in_order_after_the_dictionary() {
+    let config = ServerConfig {
+        cc_rules_path: Some(concat!(env!("CARGO_MANIFEST_DIR"), "/../resources/cc-rules").into()),
+        dictionary: vec!["Murmure".to_string()],
+        ..ServerConfig::default()
+    };
+    let engine = MockEngine::new("murmur runs locally");
+    let first = Arc::new(Mutex::new(Vec::new()));
+    let second = Arc::new(Mutex::new(Vec::new()));
+    let service = TranscriptionService::builder()
+        .config(config)
+        .engine(move || engine.clone())
+        .post_processor(Tagger {
+            tag: "[first]",
+            seen: Arc::clone(&first),
+        })
+        .post_processor(Tagger {
+            tag: "[second]",
+            seen: Arc::clone(&second),
+        })
+        .build()
+        .unwrap();
+
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    let options = TranscriptionOptions {
+        use_dictionary: true,
+        ..TranscriptionOptions::default()
+    };
+    let transcript = service
+        .transcribe_audio_bytes_with_options(&wav, &options)
+        .unwrap();
+    assert_eq!(transcript.text, "Murmure runs locally [first] [second]");
+
+    let first = first.lock().unwrap();
+    assert_eq!(first.len(), 1);
+    assert_eq!(first[0].0, "Murmure runs locally");
+    assert_eq!(first[0].1, service.get_model().name());
+    assert_eq!(second.lock().unwrap()[0].0, "Murmure runs locally [first]");
+}
+
+#[test]
+fn post_processor_errors_fail_the_request() {
+    let engine = MockEngine::new("hello");
+    let service = TranscriptionService::builder()
+        .engine(move || engine.clone())
+        .post_processor(Failing)
+        .build()
+        .unwrap();
+
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    let error = service.transcribe_audio_bytes_detailed(&wav).unwrap_err();
+    let TranscriptionError::PostProcess { name, source } = &error else {
+        panic!("expected a post-processor error, got {:?}", error);
+    };
+    assert_eq!(name, "failing");
+    assert_eq!(source.to_string(), "scrubber offline");
+}
+
+#[test]
+fn stage_timings_add_up_to_the_total() {
+    // 48 kHz with a high-pass and segmentation, so every audio stage runs
+    let samples: Vec<f32> = (0..48_000)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 48_000.0).sin() * 0.3)
+        .collect();
+    let wav = write_wav_bytes(&samples, 48_000).unwrap();
+    let service = service(MockEngine::new("timed").with_latency(Duration::from_millis(50)));
+    let options = TranscriptionOptions {
+        segment_on_silence: true,
+        highpass_hz: Some(80.0),
+        include_timing: true,
+        ..TranscriptionOptions::default()
+    };
+    let timing = service
+        .transcribe_audio_bytes_with_options(&wav, &options)
+        .unwrap()
+        .timing;
+
+    let stages: Vec<_> = timing.stages().iter().map(|(name, _)| *name).collect();
+    assert_eq!(
+        stages,
+        [
+            "decode",
+            "resample",
+            "preprocess",
+            "vad",
+            "inference",
+            "dictionary",
+            "post_process",
+            "serialization"
+        ]
+    );
+    for stage in [
+        timing.decode,
+        timing.resample,
+        timing.preprocess,
+        timing.vad,
+    ] {
+        assert!(stage > Duration::ZERO, "{:?}", timing);
+    }
+    assert!(
+        timing.inference >= Duration::from_millis(50),
+        "{:?}",
+        timing
+    );
+
+    // Only the glue between stages goes unaccounted for
+    let sum: Duration = timing.stages().iter().map(|(_, elapsed)| *elapsed).sum();
+    assert!(sum <= timing.total, "{:?}", timing);
+    assert!(
+        timing.total - sum < Duration::from_millis(10),
+        "{:?}",
+        timing
+    );
+}
+
+#[test]
+fn reports_the_input_audio() {
+    // One second of telephone audio peaking at half scale
+    let samples: Vec<f32> = (0..8000)
+        .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 8000.0).sin() * 0.5)
+        .collect();
+    let wav = write_wav_bytes(&samples, 8000).unwrap();
+    let input = service(MockEngine::new("hello"))
+        .transcribe_audio_bytes_detailed(&wav)
+        .unwrap()
+        .input;
+    assert_eq!(
+        (input.sample_rate, input.channels, input.bits_per_sample),
+        (8000, 1, 16)
+    );
+    assert!((input.duration_secs - 1.0).abs() < 1e-3);
+    assert!((input.peak - 0.5).abs() < 1e-3, "{:?}", input);
+    assert_eq!(
+        input.to_string(),
+        "8000 Hz, 1 channel(s), 16-bit, 1000 ms, peak -6.0 dBFS"
+    );
+}
+
+#[test]
+fn minimum_audio_length_is_configurable() {
+    let service_with_minimum = |min_audio_ms| {
+        let engine = MockEngine::new("short");
+        TranscriptionService::builder()
+            .config(ServerConfig {
+                min_audio_ms,
+                ..ServerConfig::default()
+            })
+            .engine(move || engine.clone())
+            .build()
+            .unwrap()
+    };
+
+    let wav = write_wav_bytes(&tone(0.3), 16_000).unwrap();
+    let error = service_with_minimum(500)
+        .transcribe_audio_bytes_detailed(&wav)
+        .unwrap_err();
+    assert!(matches!(error, TranscriptionError::InvalidAudio(_)));
+    assert_eq!(
+        error.to_string(),
+        "audio too short (300 ms at 16000 Hz), the minimum is 500 ms"
+    );
+
+    let clip = write_wav_bytes(&tone(0.02), 16_000).unwrap();
+    let transcript = service_with_minimum(0)
+        .transcribe_audio_bytes_detailed(&clip)
+        .unwrap();
+    assert_eq!(transcript.text, "short");
+}
+
+#[test]
+fn words_carry_timings_and_corrections() {
+    let config = ServerConfig {
+        cc_rules_path: Some(concat!(env!("CARGO_MANIFEST_DIR"), "/../resources/cc-rules").into()),
+        dictionary: vec!["Murmure".to_string()],
+        segment_min_silence_ms: 700,
+        ..ServerConfig::default()
+    };
+    let engine = MockEngine::new("unused")
+        .on_duration(1500, "murmur runs")
+        .on_duration(1000, "locally today");
+    let service = TranscriptionService::builder()
+        .config(config)
+        .engine(move || engine.clone())
+        .build()
+        .unwrap();
+
+    // Speech, a second of silence, speech: two segments
+    let mut samples = tone(1.0);
+    samples.extend(std::iter::repeat(0.0).take(16_000));
+    samples.extend(tone(0.5));
+    let wav = write_wav_bytes(&samples, 16_000).unwrap();
+    let options = TranscriptionOptions {
+        use_dictionary: true,
+        segment_on_silence: true,
+        ..TranscriptionOptions::default()
+    };
+    let transcript = service
+        .transcribe_audio_bytes_with_options(&wav, &options)
+        .unwrap();
+
+    let words: Vec<_> = transcript.words.iter().map(|w| w.text.as_str()).collect();
+    assert_eq!(words, ["Murmure", "runs", "locally", "today"]);
+    for word in &transcript.words {
+        assert!(word.start < word.end, "{:?}", word);
+        assert!(
+            word.end <= transcript.audio_duration_secs + 1e-3,
+            "{:?}",
+            word
+        );
+    }
+    assert!(transcript
+        .words
+        .windows(2)
+        .all(|pair| pair[0].end <= pair[1].start));
+    // The second segment's words are placed after the cut
+    assert!(transcript.words[2].start >= 1.5 - 1e-3);
+
+    // Redaction rewrites the text, so no word list goes out
+    let service = TranscriptionService::builder()
+        .config(ServerConfig {
+            redact_patterns: vec!["today".to_string()],
+            ..ServerConfig::default()
+        })
+        .engine(|| MockEngine::new("see you today"))
+        .build()
+        .unwrap();
+    let options = TranscriptionOptions {
+        redact: true,
+        ..TranscriptionOptions::default()
+    };
+    let transcript = service
+        .transcribe_audio_bytes_with_options(
+            &write_wav_bytes(&tone(1.0), 16_000).unwrap(),
+            &options,
+        )
+        .unwrap();
+    assert_eq!(transcript.text, "see you [REDACTED]");
+    assert!(transcript.words.is_empty());
+}
+
+#[test]
+fn rejects_audio_over_the_duration_limit() {
+    let service = service(MockEngine::new("too long"));
+    let wav = write_wav_bytes(&tone(3.0), 16_000).unwrap();
+    let options = TranscriptionOptions {
+        max_audio_secs: Some(2.0),
+        ..TranscriptionOptions::default()
+    };
+    let error = service
+        .transcribe_audio_bytes_with_options(&wav, &options)
+        .unwrap_err();
+    assert!(matches!(error, TranscriptionError::InvalidAudio(_)));
+    assert_eq!(
+        error.to_string(),
+        "audio is 3.0 s long at 16000 Hz, exceeding the 2 s limit"
+    );
+
+    let options = TranscriptionOptions {
+        max_audio_secs: None,
+        ..TranscriptionOptions::default()
+    };
+    assert!(service
+        .transcribe_audio_bytes_with_options(&wav, &options)
+        .is_ok());
+}
+
+#[test]
+fn dictionary_status_follows_the_configuration() {
+    let cc_rules = concat!(env!("CARGO_MANIFEST_DIR"), "/../resources/cc-rules");
+    let build = |dictionary: &[&str], cc_rules: &str, allow_missing: bool| {
+        let engine = MockEngine::new("murmur runs locally");
+        TranscriptionService::builder()
+            .engine(move || engine.clone())
+            .cc_rules_path(cc_rules)
+            .dictionary(dictionary.iter().map(|word| word.to_string()).collect())
+            .allow_missing_cc_rules(allow_missing)
+            .build()
+    };
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    let options = TranscriptionOptions {
+        use_dictionary: true,
+        ..TranscriptionOptions::default()
+    };
+
+    // No dictionary: the cc-rules don't matter
+    for path in [cc_rules, "/nonexistent/cc-rules"] {
+        let service = build(&[], path, false).unwrap();
+        assert_eq!(service.dictionary_status(), DictionaryStatus::Off);
+    }
+
+    let service = build(&["Murmure"], cc_rules, false).unwrap();
+    assert_eq!(service.dictionary_status(), DictionaryStatus::Active);
+    let transcript = service
+        .transcribe_audio_bytes_with_options(&wav, &options)
+        .unwrap();
+    assert_eq!(transcript.text, "Murmure runs locally");
+
+    let error = build(&["Murmure"], "/nonexistent/cc-rules", false)
+        .err()
+        .unwrap();
+    assert!(
+        matches!(error, TranscriptionError::Config(_)),
+        "{:?}",
+        error
+    );
+    assert!(
+        error.to_string().contains("allow_missing_cc_rules"),
+        "{}",
+        error
+    );
+
+    // Allowed to start, returning every transcript uncorrected
+    let service = build(&["Murmure"], "/nonexistent/cc-rules", true).unwrap();
+    assert_eq!(service.dictionary_status(), DictionaryStatus::Degraded);
+    assert_eq!(service.dictionary_status().to_string(), "degraded");
+    for _ in 0..2 {
+        let transcript = service
+            .transcribe_audio_bytes_with_options(&wav, &options)
+            .unwrap();
+        assert_eq!(transcript.text, "murmur runs locally");
+        assert!(transcript.corrections.is_empty());
+    }
+}
+
+#[test]
+fn records_what_loading_the_engine_took() {
+    let engine = MockEngine::new("hello");
+    let service = TranscriptionService::builder()
+        .engine(move || engine.clone())
+        .preload(false)
+        .build()
+        .unwrap();
+    assert_eq!(service.get_model().load_stats(), None);
+
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    service.transcribe_audio_bytes_detailed(&wav).unwrap();
+    let stats = service.get_model().load_stats().unwrap();
+    assert!(stats.load_time < Duration::from_secs(5), "{:?}", stats);
+    assert_eq!(
+        stats.resident_delta_bytes.is_some(),
+        cfg!(target_os = "linux")
+    );
+}
+
+#[test]
+fn requests_use_the_dictionary_of_their_language() {
+    let engine = MockEngine::new("murmur runs locally");
+    let service = TranscriptionService::builder()
+        .engine(move || engine.clone())
+        .cc_rules_path(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../resources/cc-rules"
+        ))
+        .dictionary(vec!["Murmure".to_string()])
+        .language_dictionary("fr", vec!["Kubernetes".to_string()])
+        .build()
+        .unwrap();
+    assert_eq!(service.dictionary_languages().collect::<Vec<_>>(), ["fr"]);
+
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    let text = |language: Option<&str>| {
+        let options = TranscriptionOptions {
+            use_dictionary: true,
+            language: language.map(str::to_string),
+            ..TranscriptionOptions::default()
+        };
+        service
+            .transcribe_audio_bytes_with_options(&wav, &options)
+            .unwrap()
+            .text
+    };
+    assert_eq!(text(None), "Murmure runs locally");
+    // The French list has nothing close to "murmur"
+    assert_eq!(text(Some("fr")), "murmur runs locally");
+    assert_eq!(text(Some("fr-CA")), "murmur runs locally");
+    // No list of its own: the default one
+    assert_eq!(text(Some("de")), "Murmure runs locally");
+
+    assert_eq!(
+        service.dictionary_for(Some("FR")).unwrap().get(),
+        ["Kubernetes"]
+    );
+    assert_eq!(
+        service.dictionary_for(Some("en")).unwrap().get(),
+        ["Murmure"]
+    );
+}
+
+/// The mock engine, recording the decoding options of each call.
+struct RecordingEngine {
+    inner: MockEngine,
+    seen: Arc<Mutex<Vec<DecodingOptions>>>,
+}
+
+impl SpeechEngine for RecordingEngine {
+    fn transcribe(
+        &mut self,
+        samples: Vec<f32>,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        self.inner.transcribe(samples)
+    }
+
+    fn transcribe_with(
+        &mut self,
+        samples: Vec<f32>,
+        decoding: &DecodingOptions,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        self.seen.lock().unwrap().push(*decoding);
+        self.inner.transcribe_with(samples, decoding)
+    }
+}
+
+#[test]
+fn decoding_options_reach_the_engine_unchanged() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let service = TranscriptionService::builder()
+        .config(ServerConfig {
+            decoding_blank_penalty: 1.5,
+            decoding_max_symbols_per_step: 4,
+            ..ServerConfig::default()
+        })
+        .engine({
+            let seen = Arc::clone(&seen);
+            move || RecordingEngine {
+                inner: MockEngine::new("one two three"),
+                seen: Arc::clone(&seen),
+            }
+        })
+        .build()
+        .unwrap();
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+
+    // Omitted: the server's settings
+    let defaults = TranscriptionOptions::from_config(service.get_config());
+    let transcript = service
+        .transcribe_audio_bytes_with_options(&wav, &defaults)
+        .unwrap();
+    assert_eq!(transcript.text, "one two three");
+
+    let custom = DecodingOptions {
+        blank_penalty: -2.0,
+        max_symbols_per_step: 1,
+        max_tokens: Some(2),
+    };
+    let options = TranscriptionOptions {
+        decoding: custom,
+        ..defaults
+    };
+    let transcript = service
+        .transcribe_audio_bytes_with_options(&wav, &options)
+        .unwrap();
+    assert_eq!(transcript.text, "one two");
+
+    let expected_defaults = DecodingOptions {
+        blank_penalty: 1.5,
+        max_symbols_per_step: 4,
+        max_tokens: None,
+    };
+    assert_eq!(*seen.lock().unwrap(), [expected_defaults, custom]);
+}
+
+#[test]
+fn detects_the_language_among_the_allowed_ones() {
+    let french = "je pense que nous avons mis murmur dans le dossier";
+    let build = |languages: &[&str]| {
+        let engine = MockEngine::new(french);
+        TranscriptionService::builder()
+            .config(ServerConfig {
+                detect_languages: languages.iter().map(|l| l.to_string()).collect(),
+                ..ServerConfig::default()
+            })
+            .engine(move || engine.clone())
+            .cc_rules_path(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../resources/cc-rules"
+            ))
+            .dictionary(vec!["Kubernetes".to_string()])
+            .language_dictionary("fr", vec!["Murmure".to_string()])
+            .build()
+            .unwrap()
+    };
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    let transcribe = |service: &TranscriptionService, language: Option<&str>| {
+        let options = TranscriptionOptions {
+            language: language.map(str::to_string),
+            ..TranscriptionOptions::default()
+        };
+        service
+            .transcribe_audio_bytes_with_options(&wav, &options)
+            .unwrap()
+    };
+
+    // Off unless languages are listed
+    let transcript = transcribe(&build(&[]), None);
+    assert_eq!(transcript.detected_language, None);
+    assert!(transcript.text.contains(" murmur "));
+
+    // The detected language picks the dictionary
+    let service = build(&["en", "fr"]);
+    let transcript = transcribe(&service, None);
+    let detected = transcript.detected_language.unwrap();
+    assert_eq!(detected.language, "fr");
+    assert!(detected.confidence > 0.5, "{:?}", detected);
+    assert!(transcript.text.contains(" Murmure "), "{}", transcript.text);
+
+    // A request's language takes precedence, and detection is still reported
+    let transcript = transcribe(&service, Some("en"));
+    assert_eq!(transcript.detected_language.unwrap().language, "fr");
+    assert!(transcript.text.contains(" murmur "), "{}", transcript.text);
+
+    // French isn't allowed: nothing is detected
+    let transcript = transcribe(&build(&["en", "de"]), None);
+    assert_eq!(transcript.detected_language, None);
+}
+
+/// Answers each pass with the next engine of `passes` (the last one from
+/// then on), and records the peak of the audio each pass was given.
+struct PassEngine {
+    passes: Vec<MockEngine>,
+    peaks: Arc<Mutex<Vec<f32>>>,
+}
+
+impl SpeechEngine for PassEngine {
+    fn transcribe(
+        &mut self,
+        samples: Vec<f32>,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        self.transcribe_with(samples, &DecodingOptions::default())
+    }
+
+    fn transcribe_with(
+        &mut self,
+        samples: Vec<f32>,
+        decoding: &DecodingOptions,
+    ) -> Result<TranscriptionOutput, Box<dyn std::error::Error>> {
+        let mut peaks = self.peaks.lock().unwrap();
+        let pass = peaks.len().min(self.passes.len() - 1);
+        peaks.push(murmure_stt::dsp::peak(&samples));
+        self.passes[pass].transcribe_with(samples, decoding)
+    }
+}
+
+/// A service whose passes are transcribed by `passes` in turn, and the
+/// peaks they were given.
+fn two_pass_service(passes: Vec<MockEngine>) -> (TranscriptionService, Arc<Mutex<Vec<f32>>>) {
+    let peaks = Arc::new(Mutex::new(Vec::new()));
+    let service = TranscriptionService::builder()
+        .config(ServerConfig {
+            two_pass_confidence: 0.7,
+            ..ServerConfig::default()
+        })
+        .engine({
+            let peaks = Arc::clone(&peaks);
+            move || PassEngine {
+                passes: passes.clone(),
+                peaks: Arc::clone(&peaks),
+            }
+        })
+        .build()
+        .unwrap();
+    (service, peaks)
+}
+
+fn two_pass() -> TranscriptionOptions {
+    TranscriptionOptions {
+        two_pass: true,
+        ..TranscriptionOptions::default()
+    }
+}
+
+#[test]
+fn low_confidence_first_passes_are_retried_once() {
+    let (service, peaks) = two_pass_service(vec![
+        MockEngine::new("first pass").with_confidence(0.4),
+        MockEngine::new("second pass").with_confidence(0.9),
+    ]);
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+
+    let transcript = service
+        .transcribe_audio_bytes_with_options(&wav, &two_pass())
+        .unwrap();
+    assert_eq!(transcript.text, "second pass");
+    let outcome = transcript.two_pass.unwrap();
+    assert!(outcome.second_won);
+    assert!((outcome.first_confidence.unwrap() - 0.4).abs() < 1e-4);
+    assert!((outcome.second_confidence.unwrap() - 0.9).abs() < 1e-4);
+    assert_eq!(transcript.confidence, outcome.second_confidence);
+
+    // One retry, on audio normalized to -1 dBFS
+    let peaks = peaks.lock().unwrap();
+    assert_eq!(peaks.len(), 2);
+    assert!((peaks[0] - 0.3).abs() < 0.01, "{:?}", peaks);
+    assert!((peaks[1] - 0.891).abs() < 0.01, "{:?}", peaks);
+}
+
+#[test]
+fn the_first_pass_wins_unless_the_second_is_more_confident() {
+    for second in [0.3, 0.5] {
+        let (service, peaks) = two_pass_service(vec![
+            MockEngine::new("first pass").with_confidence(0.5),
+            MockEngine::new("second pass").with_confidence(second),
+        ]);
+        let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+
+        let transcript = service
+            .transcribe_audio_bytes_with_options(&wav, &two_pass())
+            .unwrap();
+        assert_eq!(transcript.text, "first pass", "{}", second);
+        let outcome = transcript.two_pass.unwrap();
+        assert!(!outcome.second_won);
+        assert!(outcome.second_confidence.is_some());
+        assert_eq!(peaks.lock().unwrap().len(), 2);
+    }
+}
+
+#[test]
+fn second_passes_are_skipped_when_not_needed() {
+    let wav = write_wav_bytes(&tone(1.0), 16_000).unwrap();
+    let retried = |passes: Vec<MockEngine>, options: &TranscriptionOptions| {
+        let (service, peaks) = two_pass_service(passes);
+        let transcript = service
+            .transcribe_audio_bytes_with_options(&wav, options)
+            .unwrap();
+        let passes = peaks.lock().unwrap().len();
+        (transcript, passes)
+    };
+    let low = || MockEngine::new("first pass").with_confidence(0.4);
+
+    // Confident enough
+    let (transcript, passes) = retried(
+        vec![MockEngine::new("first pass").with_confidence(0.8)],
+        &two_pass(),
+    );
+    assert_eq!(passes, 1);
+    let outcome = transcript.two_pass.unwrap();
+    assert!(outcome.first_confidence.is_some());
+    assert_eq!(outcome.second_confidence, None);
+
+    // Nothing decoded, nothing to compare
+    let (transcript, passes) = retried(vec![MockEngine::new("")], &two_pass());
+    assert_eq!(passes, 1);
+    assert_eq!(transcript.two_pass.unwrap().first_confidence, None);
+
+    // Not asked for
+    let (transcript, passes) = retried(vec![low()], &TranscriptionOptions::default());
+    assert_eq!(passes, 1);
+    assert_eq!(transcript.two_pass, None);
+
+    // The first pass already had the second's preprocessing
+    let preprocessed = TranscriptionOptions {
+        highpass_hz: Some(100.0),
+        denoise: true,
+        normalize: true,
+        ..two_pass()
+    };
+    let (transcript, passes) = retried(vec![low()], &preprocessed);
+    assert_eq!(passes, 1);
+    assert_eq!(transcript.two_pass.unwrap().second_confidence, None);
+}
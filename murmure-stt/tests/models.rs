@@ -0,0 +1,113 @@
+// Named models: per-request selection and the resident engine limit
+//
+// A file of its own, as the engine cache is shared by the whole process and
+// a limit of one resident engine would unload the other tests' engines.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use murmure_stt::dsp::write_wav_bytes;
+use murmure_stt::mock::MockEngine;
+use murmure_stt::{
+    ModelSpec, ServerConfig, TranscriptionError, TranscriptionOptions, TranscriptionService,
+};
+
+fn wav() -> Vec<u8> {
+    write_wav_bytes(&vec![0.1; 16_000], 16_000).unwrap()
+}
+
+fn spec(name: &str) -> ModelSpec {
+    ModelSpec {
+        name: name.to_string(),
+        path: PathBuf::from(format!("/nonexistent/models/{}", name)),
+        precision: Default::default(),
+    }
+}
+
+fn with_model(model: &str) -> TranscriptionOptions {
+    TranscriptionOptions {
+        model: Some(model.to_string()),
+        ..TranscriptionOptions::default()
+    }
+}
+
+#[test]
+fn requests_select_models_by_name() {
+    let service = TranscriptionService::builder()
+        .config(ServerConfig {
+            models: vec![spec("fast"), spec("accurate")],
+            ..ServerConfig::default()
+        })
+        .engine(|| MockEngine::new("from fast"))
+        .build()
+        .unwrap();
+
+    assert_eq!(service.resolve_model(None).unwrap().name(), "fast");
+    assert_eq!(
+        service.resolve_model(Some("accurate")).unwrap().name(),
+        "accurate"
+    );
+    let Err(unknown) = service.resolve_model(Some("nope")) else {
+        panic!("'nope' isn't configured");
+    };
+    assert_eq!(
+        unknown.to_string(),
+        "unknown model 'nope', configured models: fast, accurate"
+    );
+
+    let transcript = service
+        .transcribe_audio_bytes_with_options(&wav(), &with_model("fast"))
+        .unwrap();
+    assert_eq!(transcript.text, "from fast");
+    // The other model loads from its own directory, which isn't there
+    let error = service
+        .transcribe_audio_bytes_with_options(&wav(), &with_model("accurate"))
+        .unwrap_err();
+    assert!(
+        matches!(error, TranscriptionError::ModelNotFound { .. }),
+        "{:?}",
+        error
+    );
+    assert!(matches!(
+        service.transcribe_audio_bytes_with_options(&wav(), &with_model("nope")),
+        Err(TranscriptionError::UnknownModel(_))
+    ));
+}
+
+/// A service whose engine counts how often it was built.
+fn counted(text: &'static str, builds: &Arc<AtomicUsize>) -> TranscriptionService {
+    let builds = builds.clone();
+    TranscriptionService::builder()
+        .config(ServerConfig {
+            max_loaded_models: 1,
+            ..ServerConfig::default()
+        })
+        .engine(move || {
+            builds.fetch_add(1, Ordering::SeqCst);
+            MockEngine::new(text)
+        })
+        .preload(false)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn least_recently_used_engines_are_unloaded() {
+    let (first_builds, second_builds) =
+        (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let first = counted("first", &first_builds);
+    let second = counted("second", &second_builds);
+
+    assert_eq!(first.transcribe_audio_bytes(&wav()).unwrap(), "first");
+    assert!(first.get_model().is_loaded());
+
+    assert_eq!(second.transcribe_audio_bytes(&wav()).unwrap(), "second");
+    assert!(second.get_model().is_loaded());
+    assert!(!first.get_model().is_loaded());
+
+    assert_eq!(first.transcribe_audio_bytes(&wav()).unwrap(), "first");
+    assert!(!second.get_model().is_loaded());
+    assert_eq!(first_builds.load(Ordering::SeqCst), 2);
+    assert_eq!(second_builds.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,66 @@
+// The pipeline on the real Parakeet model, skipped by default as it needs
+// the model files:
+// `MURMURE_MODEL_PATH=/path/to/model cargo test -p murmure-stt --test real_model -- --ignored`
+
+use murmure_stt::{ServerConfig, TranscriptionService};
+
+#[test]
+#[ignore]
+fn word_timestamps_are_monotonic() {
+    let model_path =
+        std::env::var("MURMURE_MODEL_PATH").expect("MURMURE_MODEL_PATH names the model directory");
+    let service = TranscriptionService::builder()
+        .model_path(model_path)
+        .build()
+        .unwrap();
+    let wav = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../tests/voices/test_en.wav"
+    ))
+    .unwrap();
+
+    let transcript = service.transcribe_audio_bytes_detailed(&wav).unwrap();
+    assert!(!transcript.words.is_empty(), "{:?}", transcript.text);
+    for word in &transcript.words {
+        assert!(word.start <= word.end, "{:?}", word);
+        assert!(
+            word.end <= transcript.audio_duration_secs + 0.1,
+            "{:?}",
+            word
+        );
+        assert!(word.logprob <= 0.0, "{:?}", word);
+    }
+    assert!(transcript
+        .words
+        .windows(2)
+        .all(|pair| pair[0].start <= pair[1].start));
+}
+
+#[test]
+#[ignore]
+fn memory_mapped_loads_transcribe_the_same() {
+    let model_path =
+        std::env::var("MURMURE_MODEL_PATH").expect("MURMURE_MODEL_PATH names the model directory");
+    let wav = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/../tests/voices/test_en.wav"
+    ))
+    .unwrap();
+
+    // Without `.ort` files in the directory both loads read the `.onnx` files
+    let transcribe = |mmap_model: bool| {
+        let config = ServerConfig {
+            model_path: Some(model_path.clone().into()),
+            mmap_model,
+            ..ServerConfig::default()
+        };
+        let service = TranscriptionService::builder()
+            .config(config)
+            .build()
+            .unwrap();
+        let stats = service.get_model().load_stats().unwrap();
+        println!("mmap {}: {:?}", mmap_model, stats);
+        service.transcribe_audio_bytes(&wav).unwrap()
+    };
+    assert_eq!(transcribe(true), transcribe(false));
+}